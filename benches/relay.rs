@@ -0,0 +1,221 @@
+//! Signaling hot-path benchmarks
+//!
+//! Measures `relay_message` throughput and join/leave latency as the number
+//! of active rooms and concurrent senders grows, so locking changes (one
+//! global room map vs. per-room locks) can be compared with numbers, and
+//! compares the parsed and pass-through paths for ICE candidates.
+//!
+//! Run with `cargo bench`; pass a filter such as `cargo bench relay` to run
+//! one group.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use axum::extract::ws::Utf8Bytes;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use tokio::runtime::Runtime;
+
+use axi_vid::handlers::handle_text_message;
+use axi_vid::lanes::{PeerReceiver, peer_channel};
+use axi_vid::models::{LeaveReason, WsMessage};
+use axi_vid::state::AppState;
+
+/// Room counts to measure against
+const ROOM_COUNTS: [usize; 4] = [1, 10, 100, 1000];
+
+/// Concurrent sending tasks for the contention benchmark
+const SENDERS: [usize; 4] = [1, 4, 16, 64];
+
+/// Messages each sender relays per contention iteration
+const MESSAGES_PER_SENDER: usize = 100;
+
+/// A two-peer room whose receivers are kept alive
+struct PairedRoom {
+    room_id: String,
+    caller: String,
+    callee_rx: PeerReceiver,
+}
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to build runtime")
+}
+
+/// Fill `state` with `count` interactive rooms of two peers each
+async fn populate(state: &AppState, count: usize) -> Vec<PairedRoom> {
+    let mut rooms = Vec::with_capacity(count);
+    for i in 0..count {
+        let room_id = format!("bench-room-{}", i);
+        let caller = format!("{}-caller", room_id);
+        let (caller_tx, _caller_rx) = peer_channel();
+        let (callee_tx, mut callee_rx) = peer_channel();
+        state
+            .join_room(&room_id, caller.clone(), caller_tx, None)
+            .await
+            .expect("join caller");
+        state
+            .join_room(&room_id, format!("{}-callee", room_id), callee_tx, None)
+            .await
+            .expect("join callee");
+        while callee_rx.try_recv().is_some() {}
+        rooms.push(PairedRoom {
+            room_id,
+            caller,
+            callee_rx,
+        });
+    }
+    rooms
+}
+
+fn offer() -> WsMessage {
+    WsMessage::Offer {
+        sdp: "v=0\r\no=- 1 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n".to_string(),
+        peer_id: None,
+    }
+}
+
+/// One relay plus delivery, cycling through every room
+fn relay_throughput(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("relay_message");
+    group.throughput(Throughput::Elements(1));
+
+    for count in ROOM_COUNTS {
+        let state = AppState::default();
+        let mut rooms = rt.block_on(populate(&state, count));
+        let mut next = 0;
+
+        group.bench_with_input(BenchmarkId::new("rooms", count), &count, |b, _| {
+            b.iter(|| {
+                let room = &mut rooms[next % count];
+                next += 1;
+                rt.block_on(state.relay_message(&room.room_id, &room.caller, offer()))
+                    .expect("relay");
+                black_box(room.callee_rx.try_recv().expect("delivered"));
+            });
+        });
+    }
+    group.finish();
+}
+
+/// A candidate from receipt to the text written to the other peer's socket,
+/// re-serialized by the full relay and passed through by the handler
+fn ice_relay(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("ice_relay");
+    group.throughput(Throughput::Elements(1));
+    let text = Utf8Bytes::from_static(
+        r#"{"type":"ice","candidate":"candidate:1 1 udp 2122260223 192.0.2.1 54400 typ host","sdpMLineIndex":0,"sdpMid":"0"}"#,
+    );
+
+    let state = AppState::default();
+    let mut rooms = rt.block_on(populate(&state, 1));
+    let room = &mut rooms[0];
+    group.bench_function("parsed", |b| {
+        b.iter(|| {
+            let msg = serde_json::from_str::<WsMessage>(&text).expect("candidate");
+            rt.block_on(state.relay_message(&room.room_id, &room.caller, msg))
+                .expect("relayed");
+            let frame = room.callee_rx.try_recv_frame().expect("delivered");
+            black_box(frame.into_text().expect("serialize"));
+        });
+    });
+    group.bench_function("raw", |b| {
+        b.iter(|| {
+            rt.block_on(handle_text_message(
+                &text,
+                &room.room_id,
+                &room.caller,
+                &state,
+            ));
+            let frame = room.callee_rx.try_recv_frame().expect("delivered");
+            black_box(frame.into_text().expect("serialize"));
+        });
+    });
+    group.finish();
+}
+
+/// Many tasks relaying in separate rooms at once
+fn relay_contention(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("relay_contention");
+
+    for senders in SENDERS {
+        let state = AppState::default();
+        let mut rooms = rt.block_on(populate(&state, senders));
+        let targets: Vec<(String, String)> = rooms
+            .iter()
+            .map(|r| (r.room_id.clone(), r.caller.clone()))
+            .collect();
+
+        group.throughput(Throughput::Elements((senders * MESSAGES_PER_SENDER) as u64));
+        group.bench_with_input(BenchmarkId::new("senders", senders), &senders, |b, _| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::ZERO;
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    rt.block_on(async {
+                        let tasks: Vec<_> = targets
+                            .iter()
+                            .cloned()
+                            .map(|(room_id, caller)| {
+                                let state = state.clone();
+                                tokio::spawn(async move {
+                                    for _ in 0..MESSAGES_PER_SENDER {
+                                        let _ =
+                                            state.relay_message(&room_id, &caller, offer()).await;
+                                    }
+                                })
+                            })
+                            .collect();
+                        for task in tasks {
+                            task.await.expect("sender task");
+                        }
+                    });
+                    total += start.elapsed();
+                    for room in &mut rooms {
+                        while room.callee_rx.try_recv().is_some() {}
+                    }
+                }
+                total
+            });
+        });
+    }
+    group.finish();
+}
+
+/// A peer joining and leaving a room while other rooms are active
+fn join_leave(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("join_leave");
+
+    for count in ROOM_COUNTS {
+        let state = AppState::default();
+        let _rooms = rt.block_on(populate(&state, count));
+
+        group.bench_with_input(BenchmarkId::new("rooms", count), &count, |b, _| {
+            b.to_async(&rt).iter(|| async {
+                let (tx, _rx) = peer_channel();
+                let joined = state
+                    .join_room("bench-join", "bench-peer".to_string(), tx, None)
+                    .await
+                    .expect("join");
+                state
+                    .leave_room("bench-join", &joined.peer_id, LeaveReason::UserHangup)
+                    .await;
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    relay_throughput,
+    relay_contention,
+    ice_relay,
+    join_leave
+);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+//! Arbitrary frames into `handle_text_message` for a peer in a full room
+
+#![no_main]
+
+use std::sync::OnceLock;
+
+use axi_vid::handlers::handle_text_message;
+use axi_vid::lanes::peer_channel;
+use axi_vid::state::AppState;
+use libfuzzer_sys::fuzz_target;
+use tokio::runtime::Runtime;
+
+const ROOM_ID: &str = "00000000-0000-4000-8000-000000000000";
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to build runtime")
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    runtime().block_on(async {
+        // A fresh room each run, since a fuzzed `Leave` or `End` changes it
+        let state = AppState::default();
+        let (caller_tx, _caller_rx) = peer_channel();
+        let (callee_tx, _callee_rx) = peer_channel();
+        let caller = state
+            .join_room(ROOM_ID, "caller".to_string(), caller_tx, None)
+            .await
+            .expect("join caller");
+        state
+            .join_room(ROOM_ID, "callee".to_string(), callee_tx, None)
+            .await
+            .expect("join callee");
+
+        // The socket's path, pass-through relay included
+        let text = text.to_string().into();
+        handle_text_message(&text, ROOM_ID, &caller.peer_id, &state).await;
+    });
+});
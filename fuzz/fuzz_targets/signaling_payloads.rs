@@ -0,0 +1,87 @@
+//! Structured garbage for the SDP and ICE candidate validators
+//!
+//! Raw bytes rarely get past the JSON parser, so this builds messages with
+//! a valid `type` tag and fuzzer-chosen payloads, and also calls the
+//! validators directly with SDP- and candidate-shaped strings.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use axi_vid::models::WsMessage;
+use libfuzzer_sys::fuzz_target;
+use serde_json::json;
+
+#[derive(Debug, Arbitrary)]
+enum Input {
+    /// A loose string for either validator
+    Raw(String),
+    /// `v=0` followed by fuzzed `<letter>=<value>` lines
+    Sdp(Vec<(u8, String)>),
+    /// `candidate:` followed by fuzzed fields
+    Candidate {
+        fields: Vec<String>,
+        attribute: bool,
+    },
+    /// A tagged message with fuzzed field values
+    Message {
+        kind: Kind,
+        payload: String,
+        index: i64,
+        mid: Option<String>,
+    },
+}
+
+#[derive(Debug, Arbitrary)]
+enum Kind {
+    Offer,
+    Answer,
+    IceCandidate,
+}
+
+fuzz_target!(|input: Input| {
+    match input {
+        Input::Raw(s) => {
+            let _ = WsMessage::valid_sdp(&s);
+            let _ = WsMessage::valid_candidate(&s);
+        }
+        Input::Sdp(lines) => {
+            let mut sdp = String::from("v=0\r\n");
+            for (key, value) in lines {
+                sdp.push(char::from(b'a' + key % 26));
+                sdp.push('=');
+                sdp.push_str(&value);
+                sdp.push_str("\r\n");
+            }
+            let _ = WsMessage::valid_sdp(&sdp);
+        }
+        Input::Candidate { fields, attribute } => {
+            let prefix = if attribute {
+                "a=candidate:"
+            } else {
+                "candidate:"
+            };
+            let candidate = format!("{}{}", prefix, fields.join(" "));
+            let _ = WsMessage::valid_candidate(&candidate);
+        }
+        Input::Message {
+            kind,
+            payload,
+            index,
+            mid,
+        } => {
+            let value = match kind {
+                Kind::Offer => json!({"type": "offer", "sdp": payload}),
+                Kind::Answer => json!({"type": "answer", "sdp": payload}),
+                Kind::IceCandidate => json!({
+                    "type": "ice",
+                    "candidate": payload,
+                    "sdpMLineIndex": index,
+                    "sdpMid": mid,
+                }),
+            };
+            if let Ok(msg) = serde_json::from_value::<WsMessage>(value) {
+                let _ = msg.check_payload();
+            }
+        }
+    }
+});
@@ -0,0 +1,23 @@
+//! Arbitrary bytes into `WsMessage` deserialization
+//!
+//! Anything that parses must serialize and parse back to the same JSON.
+
+#![no_main]
+
+use axi_vid::models::WsMessage;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(msg) = serde_json::from_str::<WsMessage>(text) else {
+        return;
+    };
+    let _ = msg.check_payload();
+
+    let json = serde_json::to_value(&msg).expect("parsed message must serialize");
+    let reparsed: WsMessage =
+        serde_json::from_value(json.clone()).expect("serialized message must parse");
+    assert_eq!(json, serde_json::to_value(&reparsed).unwrap());
+});
@@ -0,0 +1,12 @@
+-- What a room leaves behind, one row per room
+CREATE TABLE room_records (
+    room_id     text PRIMARY KEY,
+    started_at  bigint,
+    ended_at    bigint,
+    call        jsonb,
+    transcript  jsonb,
+    timeline    jsonb,
+    saved_at    bigint NOT NULL
+);
+
+CREATE INDEX room_records_saved_at ON room_records (saved_at);
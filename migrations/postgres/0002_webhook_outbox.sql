@@ -0,0 +1,12 @@
+-- Room events queued for, or delivered to, the webhook
+CREATE TABLE webhook_deliveries (
+    id text PRIMARY KEY,
+    -- Queue order, kept when a delivery is updated
+    seq bigserial NOT NULL,
+    room_id text NOT NULL,
+    status text NOT NULL,
+    created_at bigint NOT NULL,
+    delivery jsonb NOT NULL
+);
+
+CREATE INDEX webhook_deliveries_created_at ON webhook_deliveries (created_at);
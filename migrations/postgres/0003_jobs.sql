@@ -0,0 +1,11 @@
+-- Background jobs, queued, running and finished
+CREATE TABLE jobs (
+    id text PRIMARY KEY,
+    -- Queue order, kept when a job is updated
+    seq bigserial NOT NULL,
+    status text NOT NULL,
+    created_at bigint NOT NULL,
+    job jsonb NOT NULL
+);
+
+CREATE INDEX jobs_created_at ON jobs (created_at);
@@ -0,0 +1,21 @@
+-- State of running rooms, for whichever instance runs them next
+CREATE TABLE rooms (
+    room_id     text PRIMARY KEY,
+    room        jsonb NOT NULL,
+    saved_at    bigint NOT NULL
+);
+
+-- Instance each room runs on
+CREATE TABLE room_homes (
+    room_id     text PRIMARY KEY,
+    home        text NOT NULL,
+    claimed_at  bigint NOT NULL
+);
+
+CREATE INDEX room_homes_home ON room_homes (home);
+
+-- When each instance was last heard from
+CREATE TABLE instances (
+    url         text PRIMARY KEY,
+    seen_at     bigint NOT NULL
+);
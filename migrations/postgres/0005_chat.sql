@@ -0,0 +1,12 @@
+-- Chat messages as they are sent, so a room moving to another instance
+-- keeps its history
+CREATE TABLE chat_messages (
+    seq         bigserial PRIMARY KEY,
+    room_id     text NOT NULL,
+    peer_id     text NOT NULL,
+    sent_at     bigint NOT NULL,
+    message     text NOT NULL
+);
+
+CREATE INDEX chat_messages_room_id ON chat_messages (room_id, seq);
+CREATE INDEX chat_messages_sent_at ON chat_messages (sent_at);
@@ -0,0 +1,11 @@
+-- Tenant API keys issued through the admin API, by the hash of the key
+CREATE TABLE api_keys (
+    id          text PRIMARY KEY,
+    tenant      text NOT NULL,
+    key_hash    text NOT NULL UNIQUE,
+    label       text,
+    created_by  text NOT NULL,
+    created_at  bigint NOT NULL
+);
+
+CREATE INDEX api_keys_tenant ON api_keys (tenant);
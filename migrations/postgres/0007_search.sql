@@ -0,0 +1,19 @@
+-- Words each room can be found by, indexed for prefix search
+ALTER TABLE room_records ADD COLUMN search_words text NOT NULL DEFAULT '';
+
+-- Records saved before the index get the values of their metadata,
+-- summary and transcript entries
+UPDATE room_records SET search_words = lower(concat_ws(' ',
+    (SELECT string_agg(value, ' ') FROM jsonb_each_text(
+        CASE WHEN jsonb_typeof(call->'metadata') = 'object'
+             THEN call->'metadata' ELSE '{}' END)),
+    transcript->>'summary',
+    (SELECT string_agg(entry->>'text', ' ') FROM jsonb_array_elements(
+        CASE WHEN jsonb_typeof(transcript->'entries') = 'array'
+             THEN transcript->'entries' ELSE '[]' END) AS entry)
+));
+
+ALTER TABLE room_records ADD COLUMN search_vector tsvector
+    GENERATED ALWAYS AS (to_tsvector('simple', search_words)) STORED;
+
+CREATE INDEX room_records_search ON room_records USING gin (search_vector);
@@ -0,0 +1,515 @@
+//! Operator-facing admin API
+//!
+//! All routes under `/admin` require `Authorization: Bearer <token>`, where
+//! the token is the configured `admin_token`, one of the `admin_keys` or an
+//! admin JWT signed with `jwt_secret`. The API is disabled when none is set.
+//!
+//! Each token acts as a principal with a role. Viewers may read rooms,
+//! calls, stats and logs; operators may also act on rooms, jobs, webhooks,
+//! recordings and exports; owners may also change global feature flags
+//! and reload the configuration. `admin_token` and admin JWTs without a
+//! `role` are owners. Privileged actions are audited with the principal.
+
+use std::ops::ControlFlow;
+
+use axum::{
+    Json,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::models::{
+    AuditEvent, LeaveReason, PeerDetails, RoomDetails, RoomMetadata, RoomStatus, StoredClientError,
+    TimelineKind, WsMessage,
+};
+use crate::state::AppState;
+use crate::token::{self, TokenScope, secret_eq};
+
+/// What an admin principal may do; each role may do what the ones before
+/// it may
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminRole {
+    /// Read rooms, calls, stats and logs
+    Viewer,
+    /// Also close rooms, remove peers, retry jobs and webhooks, and handle
+    /// recordings and exports
+    Operator,
+    /// Also change global feature flags and reload the configuration
+    #[default]
+    Owner,
+}
+
+/// A named admin API key
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdminKey {
+    /// Who uses the key, as shown in the audit trail
+    pub name: String,
+    pub token: String,
+    pub role: AdminRole,
+}
+
+/// Extractor for the admin principal of a request; any role is accepted
+#[derive(Debug, Clone)]
+pub struct AdminAuth {
+    /// Who is acting, for the audit trail
+    pub principal: String,
+    pub role: AdminRole,
+}
+
+/// Extractor for an admin principal with at least the operator role
+pub struct OperatorAuth(pub AdminAuth);
+
+/// Extractor for an admin principal with the owner role
+pub struct OwnerAuth(pub AdminAuth);
+
+impl AdminAuth {
+    /// The principal of `parts`, if it has at least the `needed` role
+    fn from_parts(
+        parts: &Parts,
+        state: &AppState,
+        needed: AdminRole,
+    ) -> Result<Self, (StatusCode, &'static str)> {
+        let config = state.config();
+        if !config.admin_enabled() {
+            return Err((StatusCode::SERVICE_UNAVAILABLE, "Admin API is disabled"));
+        }
+
+        let provided = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        let Some(auth) = provided.and_then(|token| admin_principal(&config, token)) else {
+            warn!("Rejected admin request to {}", parts.uri.path());
+            return Err((StatusCode::UNAUTHORIZED, "Invalid admin token"));
+        };
+        if auth.role < needed {
+            warn!(
+                "Refused {} {} to {} ({:?})",
+                parts.method,
+                parts.uri.path(),
+                auth.principal,
+                auth.role
+            );
+            return Err((StatusCode::FORBIDDEN, "Your admin role does not allow this"));
+        }
+        Ok(auth)
+    }
+}
+
+impl FromRequestParts<AppState> for AdminAuth {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        AdminAuth::from_parts(parts, state, AdminRole::Viewer).map_err(IntoResponse::into_response)
+    }
+}
+
+impl FromRequestParts<AppState> for OperatorAuth {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        AdminAuth::from_parts(parts, state, AdminRole::Operator)
+            .map(OperatorAuth)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+impl FromRequestParts<AppState> for OwnerAuth {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        AdminAuth::from_parts(parts, state, AdminRole::Owner)
+            .map(OwnerAuth)
+            .map_err(IntoResponse::into_response)
+    }
+}
+
+impl Config {
+    /// Whether any token can use the admin API
+    pub fn admin_enabled(&self) -> bool {
+        self.admin_token.is_some() || self.jwt_secret.is_some() || !self.admin_keys.is_empty()
+    }
+}
+
+/// Who `token` acts as on the admin API, if anyone
+pub fn admin_principal(config: &Config, token: &str) -> Option<AdminAuth> {
+    if config
+        .admin_token
+        .as_deref()
+        .is_some_and(|admin| secret_eq(admin, token))
+    {
+        return Some(AdminAuth {
+            principal: "admin".to_string(),
+            role: AdminRole::Owner,
+        });
+    }
+    if let Some(key) = config
+        .admin_keys
+        .iter()
+        .find(|k| secret_eq(&k.token, token))
+    {
+        return Some(AdminAuth {
+            principal: key.name.clone(),
+            role: key.role,
+        });
+    }
+    let claims = token::verify(config.jwt_secret.as_deref()?, token).ok()?;
+    (claims.scope == TokenScope::Admin).then(|| AdminAuth {
+        principal: claims.sub.unwrap_or_else(|| "admin".to_string()),
+        role: claims.role.unwrap_or_default(),
+    })
+}
+
+/// Whether `token` is the `admin_token`, an admin key or an admin JWT
+pub fn is_admin_token(config: &Config, token: &str) -> bool {
+    admin_principal(config, token).is_some()
+}
+
+/// Query parameters for the client error listing
+#[derive(Debug, Deserialize)]
+pub struct ClientErrorQuery {
+    pub room_id: Option<String>,
+}
+
+/// List client error reports
+#[utoipa::path(
+    get,
+    path = "/admin/client-errors",
+    tag = "Admin",
+    params(
+        ("room_id" = Option<String>, Query, description = "Only reports for this room")
+    ),
+    responses(
+        (status = 200, description = "Stored reports, newest first", body = Vec<StoredClientError>),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_client_errors(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+    Query(query): Query<ClientErrorQuery>,
+) -> Json<Vec<StoredClientError>> {
+    Json(state.list_client_errors(query.room_id.as_deref()).await)
+}
+
+/// Reload the configuration file without restarting
+#[utoipa::path(
+    post,
+    path = "/admin/reload",
+    tag = "Admin",
+    responses(
+        (status = 204, description = "Configuration reloaded"),
+        (status = 400, description = "Config file is invalid; the old config stays active", body = String),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn reload_config(OwnerAuth(auth): OwnerAuth, State(state): State<AppState>) -> Response {
+    match state.reload_config(&auth.principal).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            warn!("Config reload failed: {}", e);
+            (StatusCode::BAD_REQUEST, e).into_response()
+        }
+    }
+}
+
+/// Query parameters for the audit trail
+#[derive(Debug, Deserialize)]
+pub struct AuditQuery {
+    pub room_id: Option<String>,
+}
+
+/// List audit events
+#[utoipa::path(
+    get,
+    path = "/admin/audit",
+    tag = "Admin",
+    params(
+        ("room_id" = Option<String>, Query, description = "Only events for this room")
+    ),
+    responses(
+        (status = 200, description = "Audit events, newest first", body = Vec<AuditEvent>),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_audit(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Json<Vec<AuditEvent>> {
+    Json(state.list_audit(query.room_id.as_deref()).await)
+}
+
+/// List active rooms
+///
+/// Every query parameter filters on metadata, e.g. `?customer=acme` keeps
+/// rooms tagged `customer: acme`.
+#[utoipa::path(
+    get,
+    path = "/admin/rooms",
+    tag = "Admin",
+    params(
+        ("key" = Option<String>, Query, description = "Only rooms whose metadata `key` has this value; any metadata key may be used")
+    ),
+    responses(
+        (status = 200, description = "Active rooms", body = Vec<RoomStatus>),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_rooms(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+    Query(filter): Query<RoomMetadata>,
+) -> Json<Vec<RoomStatus>> {
+    let mut rooms = state.list_rooms().await;
+    rooms.retain(|room| {
+        filter
+            .iter()
+            .all(|(key, value)| room.metadata.get(key) == Some(value))
+    });
+    Json(rooms)
+}
+
+/// Close a room, disconnecting everyone in it
+#[utoipa::path(
+    delete,
+    path = "/admin/rooms/{room_id}",
+    tag = "Admin",
+    params(
+        ("room_id" = String, Path, description = "The room identifier")
+    ),
+    responses(
+        (status = 204, description = "Room closed"),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "Room not found")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn close_room(
+    OperatorAuth(auth): OperatorAuth,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+) -> StatusCode {
+    match state.close_room(&room_id, &auth.principal).await {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+/// Peers, devices and quality of a running room
+#[utoipa::path(
+    get,
+    path = "/admin/rooms/{room_id}",
+    tag = "Admin",
+    params(
+        ("room_id" = String, Path, description = "The room identifier")
+    ),
+    responses(
+        (status = 200, description = "The room and its peers", body = RoomDetails),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 404, description = "Room not found")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn get_room(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+) -> Response {
+    match state.room_details(&room_id).await {
+        Some(details) => Json(details).into_response(),
+        None => (StatusCode::NOT_FOUND, "Room not found").into_response(),
+    }
+}
+
+/// Query parameters for removing a peer
+#[derive(Debug, Deserialize)]
+pub struct KickQuery {
+    /// Also ban the identity the peer joined with
+    #[serde(default)]
+    pub ban: bool,
+}
+
+/// Remove a peer from a room
+#[utoipa::path(
+    delete,
+    path = "/admin/rooms/{room_id}/peers/{peer_id}",
+    tag = "Admin",
+    params(
+        ("room_id" = String, Path, description = "The room identifier"),
+        ("peer_id" = String, Path, description = "The peer to remove"),
+        ("ban" = Option<bool>, Query, description = "Also keep the peer's identity out of the room")
+    ),
+    responses(
+        (status = 204, description = "Peer removed"),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "Room or peer not found"),
+        (status = 409, description = "Ban requested for a peer that joined without an identity")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn kick_peer(
+    OperatorAuth(auth): OperatorAuth,
+    State(state): State<AppState>,
+    Path((room_id, peer_id)): Path<(String, String)>,
+    Query(query): Query<KickQuery>,
+) -> Response {
+    if query.ban {
+        return match state.ban_peer(&room_id, &peer_id, &auth.principal).await {
+            Ok(()) => StatusCode::NO_CONTENT.into_response(),
+            Err(rejection) => rejection.into_response(),
+        };
+    }
+    match state.kick_peer(&room_id, &peer_id, &auth.principal).await {
+        true => StatusCode::NO_CONTENT.into_response(),
+        false => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+impl AppState {
+    /// A running room with its peers
+    pub async fn room_details(&self, room_id: &str) -> Option<RoomDetails> {
+        let id = room_id.to_string();
+        self.with_room(room_id, move |room| RoomDetails {
+            room_id: id,
+            mode: room.mode(),
+            dial_code: room.dial_code.clone(),
+            features: room.features,
+            sharing: room.sharing.clone(),
+            recording: room.recording,
+            waiting: room.waiting.len(),
+            metadata: room.metadata.clone(),
+            peers: room
+                .peers
+                .iter()
+                .map(|peer| PeerDetails {
+                    peer_id: peer.id.clone(),
+                    role: peer.role,
+                    identity: peer.identity.clone(),
+                    device: peer.device.clone(),
+                    quality: peer.quality.clone(),
+                })
+                .collect(),
+            bans: room.bans.clone(),
+        })
+        .await
+    }
+
+    /// Remove a peer from a room; returns false if it was not there
+    pub async fn kick_peer(&self, room_id: &str, peer_id: &str, actor: &str) -> bool {
+        let id = peer_id.to_string();
+        let kicked = self.with_room(room_id, move |room| {
+            if room.role_of(&id).is_none() {
+                return false;
+            }
+            room.send_to(
+                &id,
+                WsMessage::error("You were removed from this room by an administrator"),
+            );
+            room.leave(&id, LeaveReason::Kicked)
+        });
+        if kicked.await != Some(true) {
+            return false;
+        }
+        info!(
+            "Removed peer {} from room {} on request of {}",
+            peer_id, room_id, actor
+        );
+        self.record_disconnect(room_id, peer_id, LeaveReason::Kicked)
+            .await;
+        self.record_audit(Some(room_id), actor, "peer.kick", peer_id)
+            .await;
+        true
+    }
+
+    /// Status of every active room
+    pub async fn list_rooms(&self) -> Vec<RoomStatus> {
+        let mut list = Vec::new();
+        for (room_id, room) in self.room_handles().await {
+            let status = room.call(|room| RoomStatus {
+                room_id,
+                peer_count: room.peers.len(),
+                available: !room.is_full(),
+                mode: room.mode(),
+                metadata: room.metadata.clone(),
+            });
+            list.extend(status.await);
+        }
+        list.sort_by(|a, b| a.room_id.cmp(&b.room_id));
+        list
+    }
+
+    /// Remove a room and kick its peers; returns false if it did not exist
+    pub async fn close_room(&self, room_id: &str, actor: &str) -> bool {
+        let Some(room) = self.rooms.lock().await.remove(room_id) else {
+            return false;
+        };
+        let (id, page) = (room_id.to_string(), self.config().post_call_page);
+        let closed = room.call_until(move |room| {
+            room.broadcast_to_all(&WsMessage::error(
+                "This room was closed by an administrator",
+            ));
+            room.end_call_for_all(&id, page);
+            let peer_ids: Vec<String> = room.peers.iter().map(|p| p.id.clone()).collect();
+            for peer_id in &peer_ids {
+                room.leave(peer_id, LeaveReason::Kicked);
+            }
+            ControlFlow::Break(peer_ids)
+        });
+        let Some(peer_ids) = closed.await else {
+            return false;
+        };
+        info!("Closing room {} on request of {}", room_id, actor);
+
+        for peer_id in &peer_ids {
+            self.record_disconnect(room_id, peer_id, LeaveReason::Kicked)
+                .await;
+        }
+        self.record_timeline(
+            room_id,
+            TimelineKind::Closed,
+            None,
+            format!("closed by {}", actor),
+        )
+        .await;
+        self.close_call_records(&[room_id.to_string()]).await;
+        self.forget_room_states(&[room_id.to_string()]).await;
+        self.record_audit(Some(room_id), actor, "room.close", "")
+            .await;
+        true
+    }
+}
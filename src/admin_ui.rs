@@ -0,0 +1,25 @@
+//! Operator dashboard
+//!
+//! `/admin/ui` serves a page over the admin API: the running rooms, the
+//! peers of the one selected with their devices and connection quality, a
+//! graph of its MOS, buttons to kick a peer or close the room, and the
+//! failed jobs and latest webhook deliveries. The page itself is public;
+//! the operator signs in with the admin token, kept in the browser tab and
+//! sent as a bearer token with every API call.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+
+use crate::state::AppState;
+
+/// The dashboard page; not found while the admin API is disabled
+pub async fn admin_ui(State(state): State<AppState>) -> Response {
+    let config = state.config();
+    if !config.admin_enabled() {
+        return (StatusCode::NOT_FOUND, "Admin API is disabled").into_response();
+    }
+    Html(state.assets.link(include_str!("../static/admin.html"))).into_response()
+}
@@ -0,0 +1,576 @@
+//! Alert rules
+//!
+//! With an `alerts` section, a background task checks a list of rules
+//! every `interval_secs`: too many active rooms, too many joins failing,
+//! TURN servers not answering, or call quality dropping below a MOS. A
+//! rule that starts failing fires once, and resolves once when it passes
+//! again; each change is posted to every sink, a JSON webhook, a Slack
+//! incoming webhook or email through an SMTP relay, as a background job
+//! retried like any other. `GET /admin/alerts` lists where every rule
+//! stands.
+//!
+//! TURN servers are probed with a STUN binding request over UDP, or a TCP
+//! connection for `turns:` and `?transport=tcp` URLs.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::{Json, extract::State};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UdpSocket, lookup_host};
+use tracing::{info, warn};
+
+use crate::admin::AdminAuth;
+use crate::models::{AlertState, AlertStatus, JobKind};
+use crate::state::{AppState, unix_timestamp};
+use crate::stun;
+
+/// Minutes of join outcomes kept for the failure rate
+const JOIN_HISTORY_MINUTES: usize = 60;
+
+/// How long a TURN server has to answer a probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long one SMTP exchange step may take
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Rules, how often they are checked and where alerts go
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertsConfig {
+    /// Seconds between evaluations (read at startup only)
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    pub rules: Vec<AlertRule>,
+    pub sinks: Vec<AlertSink>,
+}
+
+fn default_interval_secs() -> u64 {
+    60
+}
+
+/// A named condition to alert on
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    #[serde(flatten)]
+    pub condition: AlertCondition,
+}
+
+/// What a rule measures, tagged by `metric`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "metric", rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// More rooms running on this instance than `above`
+    ActiveRooms { above: usize },
+    /// More than `above` percent of joins failing over the window, once at
+    /// least `min_joins` were tried
+    JoinFailureRate {
+        above: f64,
+        #[serde(default = "default_window_secs")]
+        window_secs: u64,
+        #[serde(default = "default_min_joins")]
+        min_joins: u64,
+    },
+    /// Any configured TURN server not answering
+    TurnUnhealthy,
+    /// Any running room's MOS (its worst-off peer) below `below`
+    Mos { below: f64 },
+    /// The latest signaling and TURN probe failed, or its signaling
+    /// exchange took longer than `above_ms`
+    SignalingProbe {
+        #[serde(default)]
+        above_ms: Option<u64>,
+    },
+}
+
+fn default_window_secs() -> u64 {
+    300
+}
+
+fn default_min_joins() -> u64 {
+    10
+}
+
+/// Where alerts are sent, tagged by `type`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertSink {
+    /// POST the rule's status as JSON
+    Webhook { url: String },
+    /// Slack incoming webhook
+    Slack { url: String },
+    /// Mail through an SMTP relay (`host:port`), without TLS or login
+    Email {
+        smtp: String,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+/// Joins started and failed in one minute
+#[derive(Debug, Clone, Copy)]
+struct JoinMinute {
+    minute: u64,
+    joins: u64,
+    failures: u64,
+}
+
+/// Recent join outcomes and the last status of every rule
+#[derive(Debug, Default)]
+pub struct Alerting {
+    joins: VecDeque<JoinMinute>,
+    statuses: BTreeMap<String, AlertStatus>,
+}
+
+impl Alerting {
+    fn record_join(&mut self, failed: bool) {
+        let minute = unix_timestamp() / 60;
+        if self.joins.back().is_none_or(|m| m.minute != minute) {
+            self.joins.push_back(JoinMinute {
+                minute,
+                joins: 0,
+                failures: 0,
+            });
+            if self.joins.len() > JOIN_HISTORY_MINUTES {
+                self.joins.pop_front();
+            }
+        }
+        let current = self.joins.back_mut().expect("a minute was just added");
+        current.joins += 1;
+        current.failures += u64::from(failed);
+    }
+
+    /// Joins and failures over the last `window_secs`, in whole minutes
+    fn join_counts(&self, window_secs: u64) -> (u64, u64) {
+        let since = (unix_timestamp() / 60).saturating_sub(window_secs.div_ceil(60).max(1) - 1);
+        self.joins
+            .iter()
+            .filter(|m| m.minute >= since)
+            .fold((0, 0), |(joins, failures), m| {
+                (joins + m.joins, failures + m.failures)
+            })
+    }
+}
+
+/// A measurement, whether it breaks the rule, and what to tell operators
+struct Reading {
+    value: Option<f64>,
+    threshold: Option<f64>,
+    failing: bool,
+    message: String,
+}
+
+impl AppState {
+    /// Count a join that reached its room, for the failure rate
+    pub async fn record_join_outcome(&self, failed: bool) {
+        self.alerting.lock().await.record_join(failed);
+    }
+
+    /// Check every rule, notifying the sinks of those that fired or
+    /// resolved, and return where each stands
+    pub async fn evaluate_alerts(&self) -> Vec<AlertStatus> {
+        let Some(config) = self.config().alerts.clone() else {
+            return Vec::new();
+        };
+        let now = unix_timestamp();
+        let mut changed = Vec::new();
+        for rule in &config.rules {
+            let reading = self.measure(&rule.condition).await;
+            let state = match reading.failing {
+                true => AlertState::Firing,
+                false => AlertState::Ok,
+            };
+            let mut alerting = self.alerting.lock().await;
+            let previous = alerting.statuses.get(&rule.name);
+            // A rule starts out passing, so only firing is news at first
+            let was = previous.map_or(AlertState::Ok, |s| s.state);
+            let since = match previous {
+                Some(p) if p.state == state => p.since,
+                _ => now,
+            };
+            let status = AlertStatus {
+                rule: rule.name.clone(),
+                state,
+                value: reading.value,
+                threshold: reading.threshold,
+                message: reading.message,
+                since,
+                evaluated_at: now,
+            };
+            if state != was {
+                changed.push(status.clone());
+            }
+            alerting.statuses.insert(rule.name.clone(), status);
+        }
+        let statuses = {
+            let mut alerting = self.alerting.lock().await;
+            // Forget rules dropped by a reload
+            alerting
+                .statuses
+                .retain(|name, _| config.rules.iter().any(|r| &r.name == name));
+            alerting.statuses.values().cloned().collect()
+        };
+        for status in changed {
+            self.send_alert(&config.sinks, &status).await;
+        }
+        statuses
+    }
+
+    async fn measure(&self, condition: &AlertCondition) -> Reading {
+        match *condition {
+            AlertCondition::ActiveRooms { above } => {
+                let rooms = self.rooms.lock().await.len();
+                Reading {
+                    value: Some(rooms as f64),
+                    threshold: Some(above as f64),
+                    failing: rooms > above,
+                    message: format!("{} active rooms (limit {})", rooms, above),
+                }
+            }
+            AlertCondition::JoinFailureRate {
+                above,
+                window_secs,
+                min_joins,
+            } => {
+                let (joins, failures) = self.alerting.lock().await.join_counts(window_secs);
+                let rate = (joins > 0).then(|| failures as f64 * 100.0 / joins as f64);
+                Reading {
+                    value: rate,
+                    threshold: Some(above),
+                    failing: joins >= min_joins && rate.is_some_and(|r| r > above),
+                    message: format!(
+                        "{} of {} joins failed in the last {} minutes (limit {}%)",
+                        failures,
+                        joins,
+                        window_secs.div_ceil(60).max(1),
+                        above
+                    ),
+                }
+            }
+            AlertCondition::TurnUnhealthy => {
+                let urls = self.config().turn_urls();
+                let probes = urls.iter().map(|url| probe_turn(url));
+                let results = futures::future::join_all(probes).await;
+                let down: Vec<String> = urls
+                    .iter()
+                    .zip(results)
+                    .filter_map(|(url, result)| {
+                        let error = result.err()?;
+                        Some(format!("{} ({})", url, error))
+                    })
+                    .collect();
+                let message = match down.is_empty() {
+                    true => format!("All {} TURN servers answer", urls.len()),
+                    false => format!("TURN servers not answering: {}", down.join(", ")),
+                };
+                Reading {
+                    value: Some(down.len() as f64),
+                    threshold: None,
+                    failing: !down.is_empty(),
+                    message,
+                }
+            }
+            AlertCondition::Mos { below } => {
+                let mut scores = Vec::new();
+                for (_, room) in self.room_handles().await {
+                    scores.extend(room.call(|room| room.mos()).await.flatten());
+                }
+                let lowest = scores.iter().copied().min_by(f64::total_cmp);
+                let poor = scores.iter().filter(|&&mos| mos < below).count();
+                Reading {
+                    value: lowest,
+                    threshold: Some(below),
+                    failing: poor > 0,
+                    message: match lowest {
+                        Some(lowest) => format!(
+                            "{} of {} rooms below MOS {} (lowest {:.2})",
+                            poor,
+                            scores.len(),
+                            below,
+                            lowest
+                        ),
+                        None => "No room has reported its quality".to_string(),
+                    },
+                }
+            }
+            AlertCondition::SignalingProbe { above_ms } => {
+                let Some(run) = self.last_canary_run().await else {
+                    return Reading {
+                        value: None,
+                        threshold: above_ms.map(|ms| ms as f64),
+                        failing: false,
+                        message: "No signaling probe has run yet".to_string(),
+                    };
+                };
+                let slow = run
+                    .signaling_ms
+                    .zip(above_ms)
+                    .is_some_and(|(signaling, limit)| signaling > limit);
+                let message = match (&run.error, run.signaling_ms) {
+                    (Some(error), _) => format!("Signaling probe failed: {}", error),
+                    (None, Some(ms)) => format!("Signaling probe negotiated in {} ms", ms),
+                    (None, None) => "Signaling probe succeeded".to_string(),
+                };
+                Reading {
+                    value: run.signaling_ms.map(|ms| ms as f64),
+                    threshold: above_ms.map(|ms| ms as f64),
+                    failing: !run.ok || slow,
+                    message,
+                }
+            }
+        }
+    }
+
+    /// Queue a post of a fired or resolved alert to every sink
+    async fn send_alert(&self, sinks: &[AlertSink], status: &AlertStatus) {
+        let label = match status.state {
+            AlertState::Firing => "FIRING",
+            AlertState::Ok => "RESOLVED",
+        };
+        let text = format!("[{}] {}: {}", label, status.rule, status.message);
+        match status.state {
+            AlertState::Firing => warn!("Alert {}", text),
+            AlertState::Ok => info!("Alert {}", text),
+        }
+        for sink in sinks {
+            let kind = match sink {
+                AlertSink::Webhook { url } => {
+                    let mut body = serde_json::to_value(status).unwrap_or_default();
+                    body["text"] = json!(text);
+                    JobKind::Notify {
+                        url: url.clone(),
+                        body,
+                    }
+                }
+                AlertSink::Slack { url } => JobKind::Notify {
+                    url: url.clone(),
+                    body: json!({ "text": text }),
+                },
+                AlertSink::Email { smtp, from, to } => JobKind::Email {
+                    smtp: smtp.clone(),
+                    from: from.clone(),
+                    to: to.clone(),
+                    subject: format!("[axi-vid] {} {}", label, status.rule),
+                    text: text.clone(),
+                },
+            };
+            self.enqueue_job(kind).await;
+        }
+    }
+}
+
+impl crate::config::Config {
+    /// TURN server URLs handed to browsers, with or without minted
+    /// credentials
+    pub fn turn_urls(&self) -> Vec<String> {
+        let minted = self.turn.iter().flat_map(|t| &t.urls);
+        let fixed = self.ice_servers.iter().flat_map(|s| &s.urls);
+        let mut urls: Vec<String> = minted
+            .chain(fixed)
+            .filter(|url| url.starts_with("turn:") || url.starts_with("turns:"))
+            .cloned()
+            .collect();
+        urls.sort();
+        urls.dedup();
+        urls
+    }
+}
+
+/// Address of a TURN URL, and whether it is reached over TCP
+pub(crate) async fn turn_address(url: &str) -> Result<(SocketAddr, bool), String> {
+    let (scheme, rest) = url.split_once(':').ok_or("not a TURN URL")?;
+    let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let tls = scheme == "turns";
+    let tcp = tls || query.split('&').any(|p| p == "transport=tcp");
+    let default_port = if tls { 5349 } else { 3478 };
+    let has_port = match address.rfind(']') {
+        Some(end) => address[end..].contains(':'),
+        None => address.contains(':'),
+    };
+    let address = match has_port {
+        true => address.to_string(),
+        false => format!("{}:{}", address, default_port),
+    };
+    let target = lookup_host(address.as_str())
+        .await
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or("no address")?;
+    Ok((target, tcp))
+}
+
+/// A UDP socket of the same family as `target`
+pub(crate) async fn udp_socket_for(target: SocketAddr) -> Result<UdpSocket, String> {
+    let bind = if target.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    UdpSocket::bind(bind).await.map_err(|e| e.to_string())
+}
+
+/// Check that a TURN server answers at the address of its URL
+pub async fn probe_turn(url: &str) -> Result<(), String> {
+    let (target, tcp) = turn_address(url).await?;
+    if tcp {
+        return match tokio::time::timeout(PROBE_TIMEOUT, TcpStream::connect(target)).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("timed out".to_string()),
+        };
+    }
+    let socket = udp_socket_for(target).await?;
+    socket.connect(target).await.map_err(|e| e.to_string())?;
+    let request = stun::Message::new(stun::BINDING);
+    socket
+        .send(&request.encode())
+        .await
+        .map_err(|e| e.to_string())?;
+    let answered = async {
+        let mut buf = [0u8; 1500];
+        loop {
+            let len = socket.recv(&mut buf).await.map_err(|e| e.to_string())?;
+            // Any response to our request, success or error, shows it is up
+            match stun::Message::decode(&buf[..len]) {
+                Some(response) if response.transaction == request.transaction => return Ok(()),
+                _ => continue,
+            }
+        }
+    };
+    tokio::time::timeout(PROBE_TIMEOUT, answered)
+        .await
+        .unwrap_or_else(|_| Err("timed out".to_string()))
+}
+
+/// Send a plain-text mail through an SMTP relay
+pub(crate) async fn send_mail(
+    smtp: &str,
+    from: &str,
+    to: &[String],
+    subject: &str,
+    text: &str,
+) -> Result<(), String> {
+    let stream = tokio::time::timeout(SMTP_TIMEOUT, TcpStream::connect(smtp))
+        .await
+        .map_err(|_| "Timed out connecting".to_string())?
+        .map_err(|e| e.to_string())?;
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    smtp_reply(&mut read, 220).await?;
+    let mut commands = vec![
+        ("EHLO axi-vid".to_string(), 250),
+        (format!("MAIL FROM:<{}>", from), 250),
+    ];
+    commands.extend(to.iter().map(|to| (format!("RCPT TO:<{}>", to), 250)));
+    commands.push(("DATA".to_string(), 354));
+    for (command, code) in commands {
+        if command.contains(['\r', '\n']) {
+            return Err("Address contains a line break".to_string());
+        }
+        write
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        smtp_reply(&mut read, code).await?;
+    }
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        from,
+        to.join(", "),
+        subject.replace(['\r', '\n'], " "),
+        chrono::Utc::now().to_rfc2822()
+    );
+    for line in text.lines() {
+        // Dot-stuffing, so a line of "." does not end the message
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push_str(".\r\n");
+    write
+        .write_all(message.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    smtp_reply(&mut read, 250).await?;
+    let _ = write.write_all(b"QUIT\r\n").await;
+    Ok(())
+}
+
+/// Read a (possibly multi-line) SMTP reply and check its code
+async fn smtp_reply(
+    read: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    expected: u16,
+) -> Result<(), String> {
+    loop {
+        let mut line = String::new();
+        let n = tokio::time::timeout(SMTP_TIMEOUT, read.read_line(&mut line))
+            .await
+            .map_err(|_| "Timed out waiting for the relay".to_string())?
+            .map_err(|e| e.to_string())?;
+        if n == 0 {
+            return Err("Relay closed the connection".to_string());
+        }
+        let code: u16 = line
+            .get(..3)
+            .and_then(|c| c.parse().ok())
+            .ok_or_else(|| format!("Unexpected reply: {}", line.trim_end()))?;
+        if code != expected {
+            return Err(format!("Relay answered {}", line.trim_end()));
+        }
+        // `250-` continues a multi-line reply; `250 ` ends it
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok(());
+        }
+    }
+}
+
+/// Spawn the task checking alert rules if any are configured
+pub fn spawn_alerting(state: AppState) {
+    let Some(config) = state.config().alerts.clone() else {
+        return;
+    };
+    info!(
+        "Checking {} alert rules every {}s",
+        config.rules.len(),
+        config.interval_secs
+    );
+    tokio::spawn(async move {
+        let period = Duration::from_secs(config.interval_secs.max(1));
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            state.evaluate_alerts().await;
+        }
+    });
+}
+
+/// Where every alert rule stands
+#[utoipa::path(
+    get,
+    path = "/admin/alerts",
+    tag = "Admin",
+    responses(
+        (status = 200, description = "Last evaluation of each rule", body = [AlertStatus]),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_alerts(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+) -> Json<Vec<AlertStatus>> {
+    let statuses = state
+        .alerting
+        .lock()
+        .await
+        .statuses
+        .values()
+        .cloned()
+        .collect();
+    Json(statuses)
+}
@@ -0,0 +1,251 @@
+//! Tenant API keys issued at runtime
+//!
+//! Besides the `api_keys` of a tenant's configuration, operators can issue
+//! keys with `POST /admin/tenants/{tenant}/api-keys` and revoke them with
+//! `DELETE /admin/tenants/{tenant}/api-keys/{id}`, without a reload. An
+//! issued key works like a configured one. It is shown once, when issued;
+//! only its SHA-256 hash is kept. Keys live in memory, or in the key store
+//! when one is attached (the `postgres` feature), where they outlast
+//! restarts and every instance sees them, revocations included, at once.
+//! Keys of a tenant removed from the configuration stop working.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use ring::rand::{SecureRandom, SystemRandom};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::admin::{AdminAuth, OperatorAuth};
+use crate::models::{ApiKey, ApiKeyRequest};
+use crate::state::{AppState, unix_timestamp};
+
+/// Most keys kept in memory
+pub const MAX_API_KEYS: usize = 10_000;
+
+/// Issued keys by the hash of the key, while no key store is attached
+pub type IssuedKeys = HashMap<String, ApiKey>;
+
+/// Somewhere issued keys are shared between instances
+pub trait KeyStore: Debug + Send + Sync {
+    /// Insert a key, known by the hash of its secret
+    fn save_key(&self, key: ApiKey, hash: String) -> BoxFuture<'_, Result<(), String>>;
+    /// The tenant of the key with this hash, if any
+    fn key_tenant(&self, hash: String) -> BoxFuture<'_, Result<Option<String>, String>>;
+    /// Every key of `tenant`, without their secrets
+    fn list_keys(&self, tenant: String) -> BoxFuture<'_, Result<Vec<ApiKey>, String>>;
+    /// Delete one of `tenant`'s keys; returns false if there was none
+    fn delete_key(&self, tenant: String, id: String) -> BoxFuture<'_, Result<bool, String>>;
+}
+
+/// A new random key
+fn new_key() -> Result<String, &'static str> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| "No randomness available")?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// What a key is kept as
+fn key_hash(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+impl AppState {
+    /// Keep issued keys in `store` from now on
+    pub fn attach_key_store(&self, store: Arc<dyn KeyStore>) -> Result<(), String> {
+        self.key_store
+            .set(store)
+            .map_err(|_| "A key store is already attached".to_string())
+    }
+
+    /// The tenant owning `api_key`, configured or issued
+    pub async fn tenant_for_key(&self, api_key: &str) -> Option<String> {
+        let config = self.config();
+        if let Some(tenant) = config.tenant_for_key(api_key) {
+            return Some(tenant.to_string());
+        }
+        let hash = key_hash(api_key);
+        let tenant = match self.key_store.get() {
+            Some(store) => match store.key_tenant(hash).await {
+                Ok(tenant) => tenant,
+                Err(e) => {
+                    warn!("Failed to look up an API key: {}", e);
+                    None
+                }
+            },
+            None => (self.api_keys.lock().await.get(&hash)).map(|k| k.tenant.clone()),
+        };
+        tenant.filter(|t| config.tenants.contains_key(t))
+    }
+}
+
+type Rejection = (StatusCode, &'static str);
+
+/// Issue a tenant API key
+#[utoipa::path(
+    post,
+    path = "/admin/tenants/{tenant}/api-keys",
+    tag = "Admin",
+    params(
+        ("tenant" = String, Path, description = "The tenant's name in the configuration")
+    ),
+    request_body(content = Option<ApiKeyRequest>, description = "What the key is for"),
+    responses(
+        (status = 200, description = "The new key, shown only this once", body = ApiKey),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "No such tenant"),
+        (status = 503, description = "The key store is unavailable")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn create_api_key(
+    OperatorAuth(auth): OperatorAuth,
+    State(state): State<AppState>,
+    Path(tenant): Path<String>,
+    body: Option<Json<ApiKeyRequest>>,
+) -> Response {
+    if !state.config().tenants.contains_key(&tenant) {
+        return (StatusCode::NOT_FOUND, "No such tenant").into_response();
+    }
+    let Json(request) = body.unwrap_or_default();
+    let secret = match new_key() {
+        Ok(secret) => secret,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    let key = ApiKey {
+        id: Uuid::new_v4().to_string(),
+        tenant: tenant.clone(),
+        label: request.label,
+        key: None,
+        created_by: auth.principal.clone(),
+        created_at: unix_timestamp(),
+    };
+    if let Err(rejection) = save_key(&state, key.clone(), key_hash(&secret)).await {
+        return rejection.into_response();
+    }
+
+    info!("Issued API key {} for tenant {}", key.id, tenant);
+    state
+        .record_audit(None, &auth.principal, "api_key.create", key.id.as_str())
+        .await;
+    Json(ApiKey {
+        key: Some(secret),
+        ..key
+    })
+    .into_response()
+}
+
+/// Keep a new key in the key store, or in memory without one
+async fn save_key(state: &AppState, key: ApiKey, hash: String) -> Result<(), Rejection> {
+    if let Some(store) = state.key_store.get() {
+        return store.save_key(key, hash).await.map_err(|e| {
+            warn!("Failed to store an API key: {}", e);
+            (StatusCode::SERVICE_UNAVAILABLE, "Failed to store the key")
+        });
+    }
+    let mut keys = state.api_keys.lock().await;
+    if keys.len() >= MAX_API_KEYS {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, "Too many API keys"));
+    }
+    keys.insert(hash, key);
+    Ok(())
+}
+
+/// List a tenant's issued API keys, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/tenants/{tenant}/api-keys",
+    tag = "Admin",
+    params(
+        ("tenant" = String, Path, description = "The tenant's name in the configuration")
+    ),
+    responses(
+        (status = 200, description = "Keys without their secrets", body = Vec<ApiKey>),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 503, description = "The key store is unavailable")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_api_keys(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+    Path(tenant): Path<String>,
+) -> Response {
+    let mut keys: Vec<ApiKey> = match state.key_store.get() {
+        Some(store) => match store.list_keys(tenant).await {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Failed to list API keys: {}", e);
+                return (StatusCode::SERVICE_UNAVAILABLE, "Failed to list keys").into_response();
+            }
+        },
+        None => (state.api_keys.lock().await.values())
+            .filter(|k| k.tenant == tenant)
+            .cloned()
+            .collect(),
+    };
+    keys.sort_by_key(|k| Reverse(k.created_at));
+    Json(keys).into_response()
+}
+
+/// Revoke a tenant API key
+#[utoipa::path(
+    delete,
+    path = "/admin/tenants/{tenant}/api-keys/{id}",
+    tag = "Admin",
+    params(
+        ("tenant" = String, Path, description = "The tenant's name in the configuration"),
+        ("id" = String, Path, description = "The key's identifier")
+    ),
+    responses(
+        (status = 204, description = "Key revoked"),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "No such key"),
+        (status = 503, description = "The key store is unavailable")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn revoke_api_key(
+    OperatorAuth(auth): OperatorAuth,
+    State(state): State<AppState>,
+    Path((tenant, id)): Path<(String, String)>,
+) -> Response {
+    let revoked = match state.key_store.get() {
+        Some(store) => match store.delete_key(tenant.clone(), id.clone()).await {
+            Ok(revoked) => revoked,
+            Err(e) => {
+                warn!("Failed to revoke an API key: {}", e);
+                return (StatusCode::SERVICE_UNAVAILABLE, "Failed to revoke the key")
+                    .into_response();
+            }
+        },
+        None => {
+            let mut keys = state.api_keys.lock().await;
+            let before = keys.len();
+            keys.retain(|_, k| k.tenant != tenant || k.id != id);
+            keys.len() < before
+        }
+    };
+    if !revoked {
+        return (StatusCode::NOT_FOUND, "No such key").into_response();
+    }
+    info!("Revoked API key {} of tenant {}", id, tenant);
+    state
+        .record_audit(None, &auth.principal, "api_key.revoke", id.as_str())
+        .await;
+    StatusCode::NO_CONTENT.into_response()
+}
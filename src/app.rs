@@ -0,0 +1,537 @@
+//! HTTP application: routes and OpenAPI document
+
+use axum::{
+    Router, middleware,
+    routing::{delete, get, post, put},
+};
+use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_scalar::{Scalar, Servable};
+
+use crate::admin::{
+    close_room, get_room, kick_peer, list_audit, list_client_errors, list_rooms, reload_config,
+};
+use crate::admin_ui::admin_ui;
+use crate::alerts::list_alerts;
+use crate::api_keys::{create_api_key, list_api_keys, revoke_api_key};
+use crate::archive::list_archive;
+use crate::assets::static_asset;
+use crate::audio_levels::list_audio_levels;
+use crate::bans::unban;
+use crate::cache::list_caches;
+use crate::call_history::list_user_calls;
+use crate::calls::{get_call, place_call};
+use crate::canary::list_canary_runs;
+use crate::cdr::{call_analytics, list_calls, submit_feedback};
+use crate::cleanup::cleanup_stats;
+use crate::cluster::cluster_gossip;
+use crate::config::IceServer;
+use crate::contacts::{add_contact, get_contact, list_contacts, remove_contact, update_contact};
+use crate::debug_bundle::get_debug_bundle;
+use crate::deep_links::{apple_app_site_association, asset_links, join_page};
+use crate::directory::directory_search;
+use crate::entitlements::{create_entitlement, list_entitlements, revoke_entitlement};
+use crate::event_rooms::{cancel_registration, get_attendance, get_registration, register};
+use crate::export::{download_export, export_room, get_export};
+use crate::features::{get_features, set_features, set_room_features};
+use crate::handlers::{
+    client_config, create_room, health_check, ice_servers, index_redirect, report_client_error,
+    room_page, room_status, ws_handler, ws_message_schema,
+};
+use crate::ice_policy::IcePolicy;
+use crate::jobs::{list_jobs, retry_job};
+use crate::listener::RouteScope;
+use crate::lobby::{lobby_events, lobby_page};
+use crate::media_relay::media_relay_ws;
+use crate::models::{
+    AlertState, AlertStatus, ApiKey, ApiKeyRequest, ArchivedRoom, ArtifactRetention, AssetEntry,
+    AttendanceReport, Attendee, AuditEvent, CacheStats, CallAnalytics, CallDebugBundle,
+    CallDirection, CallFeedback, CallHistoryEntry, CallHistoryPage, CallRecord, CallState,
+    CanaryRun, CategoryCount, CleanupStats, ClientConfig, ClientErrorKind, ClientErrorReport,
+    ClusterGossip, CodecPolicy, ComplaintCategory, ConsentPolicy, Contact, ContactRequest,
+    CreateInviteRequest, CreateRoomRequest, CreateRoomResponse, DeepLinks, DeliveryStatus,
+    DeviceInfo, DiagnosticIssue, DirectedCall, DirectoryEntry, Entitlement, EntitlementRequest,
+    EventSettings, ExperimentAssignment, ExportJob, ExportStatus, FeatureFlags, FeatureOverrides,
+    FeatureStatus, FeedbackRequest, InviteLink, Job, JobKind, JobStatus, LeaveReason, LobbyStatus,
+    MediaBytes, OpusSettings, PeerAudio, PeerConnectionState, PeerDetails, PeerDevice, PeerQuality,
+    PeerRole, PeerSignaling, PeerTraffic, PermissionMatrix, PlaceCallRequest, PresenceInfo,
+    PresenceStatus, PresentDeniedReason, PresentPolicy, PrivacyMode, PushPlatform, PushToken,
+    PushTokenRequest, QuotaUsage, ReapReason, Recording, RecordingArtifact, RecordingStep,
+    RecordingStepState, RegistrationRequest, RegistrationStatus, RegistrationTicket,
+    ReinviteResponse, RetentionArtifact, RetentionStats, RolePermissions, RoomAudio, RoomControls,
+    RoomDetails, RoomMode, RoomQuality, RoomSettings, RoomStatus, RoomTimeline, RoomTranscript,
+    ScanStatus, SdpPolicy, SdpSummary, SearchField, SearchMatch, SearchResponse, SearchResult,
+    SignUrlRequest, SignedUrl, StageLayout, StoredClientError, SummaryResponse, TimelineEvent,
+    TimelineKind, TranscriptEntry, TranscriptKind, TranscriptionSettings, UploadProbeResult,
+    VariantStats, WebhookDelivery, WebhookEvent, WebhookPayload, WebhookReplayRequest,
+    WebhookReplayResponse, WebhookTestResult, WsMessage,
+};
+use crate::nettest::{nettest_download, nettest_upload, nettest_ws};
+use crate::personal_rooms::personal_room;
+use crate::post_call::ended_page;
+use crate::presence::{get_presence, presence_ws};
+use crate::preview::{get_preview, put_preview};
+use crate::pstn::{twilio_gather, twilio_voice};
+use crate::push::{list_push_tokens, register_push_token, unregister_push_token};
+use crate::quality::room_quality;
+use crate::quotas::list_quotas;
+use crate::ratelimit::rate_limit;
+use crate::reconnect::create_reinvite;
+use crate::recordings::{delete_recording, get_recording_file, list_recordings, upload_recording};
+use crate::retention::retention_stats;
+use crate::saml::{saml_acs, saml_login, saml_metadata};
+use crate::scim::{create_user, delete_user, get_user, list_users, patch_user, replace_user};
+use crate::search::search;
+use crate::share_links::{create_invite, list_invites, revoke_invite};
+use crate::signed_urls::sign_url;
+use crate::state::AppState;
+use crate::timeline::get_timeline;
+use crate::traffic::list_traffic;
+use crate::transcript::{create_summary, get_transcript};
+use crate::transcription::submit_audio;
+use crate::voicemail::{delete_voicemail, get_voicemail, leave_voicemail};
+use crate::webhooks::{list_webhooks, replay_webhooks, retry_webhook, test_webhook};
+use crate::{
+    admin, alerts, api_keys, archive, audio_levels, bans, cache, call_history, calls, canary, cdr,
+    cleanup, cluster, contacts, debug_bundle, directory, entitlements, event_rooms, export,
+    features, handlers, jobs, lobby, nettest, personal_rooms, presence, preview, push, quality,
+    quotas, reconnect, recordings, retention, search, share_links, signed_urls, timeline, traffic,
+    transcript, transcription, voicemail, webhooks,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Axi-Vid API",
+        description = "A simple 1:1 video chat application using Axum and WebRTC. This API provides endpoints for room management and real-time communication via WebSockets.",
+        version = "0.1.0"
+    ),
+    tags(
+        (name = "Rooms", description = "Room management endpoints"),
+        (name = "Health", description = "Health check endpoints"),
+        (name = "WebSocket", description = "Real-time communication"),
+        (name = "Network Test", description = "Pre-call connectivity checks"),
+        (name = "Diagnostics", description = "Client-side failure reporting"),
+        (name = "Transcription", description = "Live captions"),
+        (name = "Analytics", description = "Call feedback and quality"),
+        (name = "Presence", description = "Who can be called"),
+        (name = "Calls", description = "Ringing another user"),
+        (name = "Cluster", description = "Traffic between instances"),
+        (name = "Admin", description = "Operator endpoints (require admin token)")
+    ),
+    modifiers(&AdminSecurity),
+    paths(
+        handlers::create_room,
+        handlers::room_status,
+        reconnect::create_reinvite,
+        share_links::create_invite,
+        share_links::list_invites,
+        share_links::revoke_invite,
+        signed_urls::sign_url,
+        entitlements::create_entitlement,
+        entitlements::list_entitlements,
+        entitlements::revoke_entitlement,
+        event_rooms::register,
+        event_rooms::get_registration,
+        event_rooms::cancel_registration,
+        event_rooms::get_attendance,
+        personal_rooms::personal_room,
+        presence::get_presence,
+        calls::place_call,
+        calls::get_call,
+        voicemail::leave_voicemail,
+        voicemail::get_voicemail,
+        voicemail::delete_voicemail,
+        call_history::list_user_calls,
+        contacts::list_contacts,
+        contacts::add_contact,
+        contacts::get_contact,
+        contacts::update_contact,
+        contacts::remove_contact,
+        directory::directory_search,
+        push::list_push_tokens,
+        push::register_push_token,
+        push::unregister_push_token,
+        handlers::health_check,
+        handlers::ice_servers,
+        handlers::client_config,
+        handlers::ws_message_schema,
+        nettest::nettest_download,
+        nettest::nettest_upload,
+        handlers::report_client_error,
+        transcription::submit_audio,
+        transcript::get_transcript,
+        transcript::create_summary,
+        admin::list_client_errors,
+        admin::list_audit,
+        admin::reload_config,
+        admin::list_rooms,
+        admin::get_room,
+        admin::close_room,
+        admin::kick_peer,
+        bans::unban,
+        api_keys::create_api_key,
+        api_keys::list_api_keys,
+        api_keys::revoke_api_key,
+        debug_bundle::get_debug_bundle,
+        features::get_features,
+        features::set_features,
+        features::set_room_features,
+        cdr::submit_feedback,
+        quality::room_quality,
+        timeline::get_timeline,
+        cdr::list_calls,
+        cdr::call_analytics,
+        search::search,
+        export::export_room,
+        export::get_export,
+        export::download_export,
+        recordings::upload_recording,
+        recordings::list_recordings,
+        recordings::get_recording_file,
+        recordings::delete_recording,
+        preview::put_preview,
+        preview::get_preview,
+        lobby::lobby_events,
+        cleanup::cleanup_stats,
+        quotas::list_quotas,
+        retention::retention_stats,
+        cache::list_caches,
+        archive::list_archive,
+        traffic::list_traffic,
+        audio_levels::list_audio_levels,
+        cluster::cluster_gossip,
+        alerts::list_alerts,
+        canary::list_canary_runs,
+        jobs::list_jobs,
+        jobs::retry_job,
+        webhooks::list_webhooks,
+        webhooks::retry_webhook,
+        webhooks::test_webhook,
+        webhooks::replay_webhooks,
+    ),
+    components(
+        schemas(
+            AlertState,
+            AlertStatus,
+            ArchivedRoom,
+            AssetEntry,
+            AuditEvent,
+            CacheStats,
+            CanaryRun,
+            CallAnalytics,
+            CallDebugBundle,
+            CallDirection,
+            CallFeedback,
+            CallHistoryEntry,
+            CallHistoryPage,
+            CallRecord,
+            CallState,
+            CategoryCount,
+            CleanupStats,
+            QuotaUsage,
+            ClientConfig,
+            ClientErrorKind,
+            ClientErrorReport,
+            CodecPolicy,
+            ClusterGossip,
+            ComplaintCategory,
+            CreateInviteRequest,
+            CreateRoomRequest,
+            ConsentPolicy,
+            Contact,
+            ContactRequest,
+            DirectoryEntry,
+            CreateRoomResponse,
+            DeepLinks,
+            DiagnosticIssue,
+            DirectedCall,
+            DeliveryStatus,
+            DeviceInfo,
+            ExperimentAssignment,
+            ExportJob,
+            ExportStatus,
+            FeatureFlags,
+            FeatureOverrides,
+            FeatureStatus,
+            Job,
+            JobKind,
+            JobStatus,
+            Recording,
+            RecordingArtifact,
+            RecordingStep,
+            RecordingStepState,
+            FeedbackRequest,
+            IcePolicy,
+            IceServer,
+            InviteLink,
+            LeaveReason,
+            LobbyStatus,
+            MediaBytes,
+            PeerDetails,
+            PeerQuality,
+            PeerRole,
+            PeerSignaling,
+            OpusSettings,
+            PeerAudio,
+            PeerDevice,
+            PeerConnectionState,
+            PeerTraffic,
+            PermissionMatrix,
+            PlaceCallRequest,
+            PresenceInfo,
+            PresenceStatus,
+            PresentDeniedReason,
+            PresentPolicy,
+            PrivacyMode,
+            PushPlatform,
+            PushToken,
+            PushTokenRequest,
+            RolePermissions,
+            ReapReason,
+            RetentionArtifact,
+            RetentionStats,
+            ArtifactRetention,
+            RoomAudio,
+            RoomControls,
+            ReinviteResponse,
+            RoomDetails,
+            RoomMode,
+            RoomQuality,
+            RoomSettings,
+            RoomStatus,
+            RoomTimeline,
+            RoomTranscript,
+            ScanStatus,
+            SdpPolicy,
+            SdpSummary,
+            SearchField,
+            SearchMatch,
+            SearchResponse,
+            SearchResult,
+            SignUrlRequest,
+            SignedUrl,
+            EntitlementRequest,
+            Entitlement,
+            ApiKeyRequest,
+            ApiKey,
+            EventSettings,
+            RegistrationRequest,
+            RegistrationStatus,
+            RegistrationTicket,
+            Attendee,
+            AttendanceReport,
+            StageLayout,
+            StoredClientError,
+            SummaryResponse,
+            TimelineEvent,
+            TimelineKind,
+            TranscriptEntry,
+            TranscriptKind,
+            TranscriptionSettings,
+            UploadProbeResult,
+            VariantStats,
+            WebhookDelivery,
+            WebhookEvent,
+            WebhookPayload,
+            WebhookReplayRequest,
+            WebhookReplayResponse,
+            WebhookTestResult,
+            WsMessage
+        )
+    )
+)]
+pub struct ApiDoc;
+
+/// Registers the bearer scheme used by the admin endpoints
+struct AdminSecurity;
+
+impl Modify for AdminSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+/// Router for a listener restricted to `scope`
+pub fn build_app(state: AppState, scope: RouteScope) -> Router {
+    let routes = match scope {
+        RouteScope::All => public_routes(&state).merge(admin_routes()),
+        RouteScope::Public => public_routes(&state),
+        RouteScope::Admin => admin_routes().route("/health", get(health_check)),
+    };
+    routes
+        // Middleware
+        .layer(TraceLayer::new_for_http())
+        .layer(CorsLayer::permissive())
+        // Shared state
+        .with_state(state)
+}
+
+/// Routes used by browsers and third-party webhooks
+fn public_routes(state: &AppState) -> Router<AppState> {
+    // Rate limited per client address (IPv6: per /64)
+    let limited = Router::new()
+        .route("/", get(index_redirect))
+        .route("/api/create-room", post(create_room))
+        .route("/api/room/{room_id}/register", post(register))
+        // Each summary is a paid LLM request
+        .route("/api/room/{room_id}/summary", post(create_summary))
+        .route("/ws/{room_id}", get(ws_handler))
+        // Bandwidth probes move up to 10 MiB each
+        .route("/api/nettest/download", get(nettest_download))
+        .route("/api/nettest/upload", post(nettest_upload))
+        .route_layer(middleware::from_fn_with_state(state.clone(), rate_limit));
+
+    Router::new()
+        .merge(limited)
+        // Scalar API documentation
+        .merge(Scalar::with_url("/docs", ApiDoc::openapi()))
+        // API routes
+        .route("/api/room/{room_id}/status", get(room_status))
+        .route("/api/room/{room_id}/audio", post(submit_audio))
+        .route("/api/room/{room_id}/transcript", get(get_transcript))
+        // Operator downloads, also opened by signed URLs
+        .route("/api/exports/{id}/download", get(download_export))
+        .route("/api/recordings/{id}/files/{name}", get(get_recording_file))
+        .route("/api/room/{room_id}/feedback", post(submit_feedback))
+        .route("/api/room/{room_id}/reinvite", post(create_reinvite))
+        // Uploads from recorders (listing is an operator route)
+        .route("/api/room/{room_id}/recordings", post(upload_recording))
+        .route(
+            "/api/room/{room_id}/preview.jpg",
+            get(get_preview).put(put_preview),
+        )
+        .route(
+            "/api/room/{room_id}/invites",
+            get(list_invites).post(create_invite),
+        )
+        .route("/api/room/{room_id}/invites/{token}", delete(revoke_invite))
+        .route(
+            "/api/room/{room_id}/registrations/{id}",
+            get(get_registration).delete(cancel_registration),
+        )
+        .route("/api/personal-room", get(personal_room))
+        .route("/api/presence/{user}", get(get_presence))
+        .route("/api/calls", post(place_call))
+        .route("/api/calls/{room_id}", get(get_call))
+        .route(
+            "/api/calls/{room_id}/voicemail",
+            get(get_voicemail)
+                .post(leave_voicemail)
+                .delete(delete_voicemail),
+        )
+        .route("/api/users/{user}/calls", get(list_user_calls))
+        .route("/api/contacts", get(list_contacts).post(add_contact))
+        .route(
+            "/api/contacts/{id}",
+            get(get_contact).put(update_contact).delete(remove_contact),
+        )
+        .route("/api/directory/search", get(directory_search))
+        .route(
+            "/api/push/tokens",
+            get(list_push_tokens).post(register_push_token),
+        )
+        .route("/api/push/tokens/{token}", delete(unregister_push_token))
+        .route("/api/room/{room_id}/quality", get(room_quality))
+        .route("/api/ice-servers", get(ice_servers))
+        .route("/api/config", get(client_config))
+        .route("/api/schema/ws-messages.json", get(ws_message_schema))
+        .route("/health", get(health_check))
+        // Pre-call network test
+        .route("/api/nettest/ws", get(nettest_ws))
+        .route("/api/client-errors", post(report_client_error))
+        // Room locations from other instances
+        .route("/cluster/gossip", post(cluster_gossip))
+        // Phone dial-in webhooks
+        .route("/api/twilio/voice", post(twilio_voice))
+        .route("/api/twilio/gather", post(twilio_gather))
+        // SAML single sign-on
+        .route("/saml/metadata", get(saml_metadata))
+        .route("/saml/login", get(saml_login))
+        .route("/saml/acs", post(saml_acs))
+        // SCIM user provisioning
+        .route("/scim/v2/Users", get(list_users).post(create_user))
+        .route(
+            "/scim/v2/Users/{id}",
+            get(get_user)
+                .put(replace_user)
+                .patch(patch_user)
+                .delete(delete_user),
+        )
+        // Room page
+        .route("/room/{room_id}", get(room_page))
+        .route("/room/{room_id}/ended", get(ended_page))
+        .route("/room/{room_id}/lobby", get(lobby_page))
+        .route("/api/room/{room_id}/lobby/events", get(lobby_events))
+        .route("/join/{room_id}", get(join_page))
+        .route(
+            "/.well-known/apple-app-site-association",
+            get(apple_app_site_association),
+        )
+        .route("/.well-known/assetlinks.json", get(asset_links))
+        // Presence of users holding a user token
+        .route("/ws/presence", get(presence_ws))
+        // Media relay (signaling itself is in `limited` above)
+        .route("/ws/{room_id}/media", get(media_relay_ws))
+        // Static files (JS, CSS)
+        .route("/static/{*path}", get(static_asset))
+}
+
+/// Operator API
+fn admin_routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/client-errors", get(list_client_errors))
+        .route("/admin/audit", get(list_audit))
+        .route("/admin/reload", post(reload_config))
+        .route("/admin/signed-urls", post(sign_url))
+        .route(
+            "/admin/entitlements",
+            get(list_entitlements).post(create_entitlement),
+        )
+        .route("/admin/entitlements/{code}", delete(revoke_entitlement))
+        .route(
+            "/admin/tenants/{tenant}/api-keys",
+            get(list_api_keys).post(create_api_key),
+        )
+        .route(
+            "/admin/tenants/{tenant}/api-keys/{id}",
+            delete(revoke_api_key),
+        )
+        .route("/admin/rooms", get(list_rooms))
+        .route("/admin/ui", get(admin_ui))
+        .route("/admin/rooms/{room_id}", get(get_room).delete(close_room))
+        .route("/admin/rooms/{room_id}/peers/{peer_id}", delete(kick_peer))
+        .route("/admin/rooms/{room_id}/bans/{identity}", delete(unban))
+        .route("/admin/rooms/{room_id}/features", put(set_room_features))
+        .route("/admin/rooms/{room_id}/debug", get(get_debug_bundle))
+        .route("/api/room/{room_id}/timeline", get(get_timeline))
+        .route("/admin/rooms/{room_id}/attendance", get(get_attendance))
+        .route("/admin/features", get(get_features).put(set_features))
+        .route("/admin/calls", get(list_calls))
+        .route("/admin/analytics", get(call_analytics))
+        .route("/api/search", get(search))
+        .route("/api/room/{room_id}/export", get(export_room))
+        .route("/api/exports/{id}", get(get_export))
+        .route("/api/room/{room_id}/recordings", get(list_recordings))
+        .route("/api/recordings/{id}", delete(delete_recording))
+        .route("/admin/cleanup", get(cleanup_stats))
+        .route("/admin/quotas", get(list_quotas))
+        .route("/admin/retention", get(retention_stats))
+        .route("/admin/caches", get(list_caches))
+        .route("/admin/archive", get(list_archive))
+        .route("/admin/traffic", get(list_traffic))
+        .route("/admin/audio-levels", get(list_audio_levels))
+        .route("/admin/alerts", get(list_alerts))
+        .route("/admin/canary", get(list_canary_runs))
+        .route("/admin/jobs", get(list_jobs))
+        .route("/admin/jobs/{id}/retry", post(retry_job))
+        .route("/admin/webhooks", get(list_webhooks))
+        .route("/admin/webhooks/{id}/retry", post(retry_webhook))
+        .route("/admin/webhooks/test", post(test_webhook))
+        .route("/admin/webhooks/replay", post(replay_webhooks))
+}
@@ -0,0 +1,169 @@
+//! Archive of reaped rooms
+//!
+//! Rooms removed by the cleanup task are moved here instead of vanishing,
+//! so their call record, transcript and timeline stay reachable from one
+//! place. Archived rooms, and the data they reference, are purged by a
+//! separate retention policy.
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::admin::AdminAuth;
+use crate::models::{ArchivedRoom, ReapReason, RoomSettings};
+use crate::state::{AppState, unix_timestamp};
+
+/// Retention policy for archived rooms
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct ArchivePolicy {
+    /// Seconds an archived room is kept before it is purged
+    pub retention_secs: u64,
+    /// Most archived rooms kept; the oldest are purged first
+    pub max_rooms: usize,
+}
+
+impl Default for ArchivePolicy {
+    fn default() -> Self {
+        Self {
+            retention_secs: 7 * 24 * 3600,
+            max_rooms: 10_000,
+        }
+    }
+}
+
+/// A reaped room held in the archive
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    pub settings: RoomSettings,
+    pub reason: ReapReason,
+    /// Unix timestamp (seconds)
+    pub archived_at: u64,
+}
+
+impl AppState {
+    /// Move reaped rooms into the archive
+    pub async fn archive_rooms(&self, rooms: Vec<(String, RoomSettings, ReapReason)>) {
+        let mut archive = self.archive.lock().await;
+        let now = unix_timestamp();
+        for (room_id, settings, reason) in rooms {
+            archive.insert(
+                room_id,
+                ArchiveEntry {
+                    settings,
+                    reason,
+                    archived_at: now,
+                },
+            );
+        }
+    }
+
+    /// Purge archived rooms past the retention policy, with their records;
+    /// returns how many rooms were, or in a dry run would be, purged
+    pub async fn purge_archive(&self, policy: &ArchivePolicy, dry_run: bool) -> usize {
+        let cutoff = unix_timestamp().saturating_sub(policy.retention_secs);
+        if !dry_run {
+            self.prune_stored(cutoff).await;
+        }
+        let mut archive = self.archive.lock().await;
+
+        let mut purged: Vec<String> = archive
+            .iter()
+            .filter(|(_, entry)| entry.archived_at < cutoff)
+            .map(|(id, _)| id.clone())
+            .collect();
+        let over = archive
+            .len()
+            .saturating_sub(purged.len() + policy.max_rooms);
+        if over > 0 {
+            let mut oldest: Vec<(&String, &ArchiveEntry)> = archive
+                .iter()
+                .filter(|(id, _)| !purged.contains(id))
+                .collect();
+            oldest.sort_by_key(|(_, entry)| entry.archived_at);
+            purged.extend(oldest.into_iter().take(over).map(|(id, _)| id.clone()));
+        }
+        if purged.is_empty() || dry_run {
+            return purged.len();
+        }
+        for id in &purged {
+            archive.remove(id);
+        }
+        drop(archive);
+
+        let mut calls = self.calls.lock().await;
+        let mut transcripts = self.transcripts.lock().await;
+        let mut timelines = self.timelines.lock().await;
+        let mut registrations = self.event_registrations.lock().await;
+        for id in &purged {
+            calls.remove(id);
+            transcripts.remove(id);
+            timelines.remove(id);
+            registrations.remove(id);
+        }
+        drop((calls, transcripts, timelines, registrations));
+        info!("Purged {} archived rooms", purged.len());
+        let count = purged.len();
+        self.forget_stored(purged).await;
+        count
+    }
+
+    /// List archived rooms with their retained records, newest first
+    pub async fn list_archive(&self, room_id: Option<&str>) -> Vec<ArchivedRoom> {
+        let archive = self.archive.lock().await;
+        let calls = self.calls.lock().await;
+        let transcripts = self.transcripts.lock().await;
+        let timelines = self.timelines.lock().await;
+
+        let mut rooms: Vec<ArchivedRoom> = archive
+            .iter()
+            .filter(|(id, _)| room_id.is_none() || room_id == Some(id.as_str()))
+            .map(|(id, entry)| {
+                let transcript = transcripts.get(id);
+                ArchivedRoom {
+                    room_id: id.clone(),
+                    settings: entry.settings.clone(),
+                    reason: entry.reason,
+                    archived_at: entry.archived_at,
+                    call: calls.get(id).cloned(),
+                    transcript_entries: transcript.map_or(0, |t| t.entries.len()),
+                    has_summary: transcript.is_some_and(|t| t.summary.is_some()),
+                    timeline_events: timelines.get(id).map_or(0, |t| t.events.len()),
+                }
+            })
+            .collect();
+        rooms.sort_by_key(|r| std::cmp::Reverse(r.archived_at));
+        rooms
+    }
+}
+
+/// Query parameters for the archive listing
+#[derive(Debug, Deserialize)]
+pub struct ArchiveQuery {
+    pub room_id: Option<String>,
+}
+
+/// List archived rooms
+#[utoipa::path(
+    get,
+    path = "/admin/archive",
+    tag = "Admin",
+    params(
+        ("room_id" = Option<String>, Query, description = "Only this room")
+    ),
+    responses(
+        (status = 200, description = "Archived rooms, newest first", body = Vec<ArchivedRoom>),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_archive(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+    Query(query): Query<ArchiveQuery>,
+) -> Json<Vec<ArchivedRoom>> {
+    Json(state.list_archive(query.room_id.as_deref()).await)
+}
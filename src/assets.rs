@@ -0,0 +1,293 @@
+//! Static assets
+//!
+//! Everything under `static/` is read once at startup and served from
+//! memory under `/static`. Each file is also served under a name carrying
+//! a hash of its content (`app.3f9a0c2b41d7e865.js`), which never changes
+//! meaning and so is cached for a year; the pages the server renders link
+//! to those names. The plain names stay available for anything that hard
+//! codes them, and are revalidated with their `ETag` on every use.
+//!
+//! Pages refer to assets as `{{asset:app.js}}`, rendered as the
+//! fingerprinted URL, and `{{integrity:app.js}}`, rendered as its
+//! subresource integrity hash. The same manifest is served to scripts in
+//! `/api/config`.
+//!
+//! Text assets are compressed with Brotli and gzip up front, and served
+//! compressed to clients that accept it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::Path as FsPath;
+use std::sync::{Arc, OnceLock};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use sha2::{Digest, Sha256, Sha384};
+use tracing::warn;
+
+use crate::models::AssetEntry;
+use crate::state::AppState;
+
+/// Cache lifetime of fingerprinted names
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+/// Plain names are cached but checked with the server before each use
+const REVALIDATE: &str = "public, no-cache";
+/// Hex digits of the content hash used in names and ETags
+const HASH_LEN: usize = 16;
+
+/// A static file, ready to serve
+#[derive(Debug)]
+pub struct Asset {
+    /// Path under `static/`, e.g. `app.js`
+    pub path: String,
+    /// Path with the content hash, e.g. `app.3f9a0c2b41d7e865.js`
+    pub hashed_path: String,
+    /// Subresource integrity hash, e.g. `sha384-...`
+    pub integrity: String,
+    content_type: &'static str,
+    hash: String,
+    body: Bytes,
+    brotli: Option<Bytes>,
+    gzip: Option<Bytes>,
+}
+
+/// All static files, by plain and by fingerprinted path
+#[derive(Debug, Default)]
+pub struct Assets {
+    by_path: HashMap<String, Arc<Asset>>,
+    by_hashed_path: HashMap<String, Arc<Asset>>,
+}
+
+impl Assets {
+    /// The files under `static/`, read on first use and shared by every
+    /// server in the process
+    pub fn bundled() -> Arc<Assets> {
+        static BUNDLED: OnceLock<Arc<Assets>> = OnceLock::new();
+        BUNDLED
+            .get_or_init(|| Arc::new(Self::load(FsPath::new("static"))))
+            .clone()
+    }
+
+    /// Read, hash and compress every file under `dir`
+    pub fn load(dir: &FsPath) -> Self {
+        let mut assets = Self::default();
+        let mut files = Vec::new();
+        collect_files(dir, "", &mut files);
+        for (path, body) in files {
+            let asset = Arc::new(Asset::new(path, body));
+            assets
+                .by_hashed_path
+                .insert(asset.hashed_path.clone(), asset.clone());
+            assets.by_path.insert(asset.path.clone(), asset);
+        }
+        assets
+    }
+
+    /// URL to link an asset by; its fingerprinted name when known
+    pub fn url(&self, path: &str) -> String {
+        match self.by_path.get(path) {
+            Some(asset) => format!("/static/{}", asset.hashed_path),
+            None => format!("/static/{}", path),
+        }
+    }
+
+    /// Fingerprinted URL and integrity hash of every asset
+    pub fn manifest(&self) -> BTreeMap<String, AssetEntry> {
+        self.by_path
+            .values()
+            .map(|asset| {
+                let entry = AssetEntry {
+                    url: format!("/static/{}", asset.hashed_path),
+                    integrity: asset.integrity.clone(),
+                };
+                (asset.path.clone(), entry)
+            })
+            .collect()
+    }
+
+    /// Fill in a page's `{{asset:...}}` and `{{integrity:...}}` references
+    pub fn link(&self, html: &str) -> String {
+        let mut html = html.to_string();
+        for asset in self.by_path.values() {
+            let url = format!("{{{{asset:{}}}}}", asset.path);
+            if html.contains(&url) {
+                html = html.replace(&url, &format!("/static/{}", asset.hashed_path));
+            }
+            let integrity = format!("{{{{integrity:{}}}}}", asset.path);
+            if html.contains(&integrity) {
+                html = html.replace(&integrity, &asset.integrity);
+            }
+        }
+        html
+    }
+
+    /// The asset at a plain or fingerprinted path, and whether it was the
+    /// fingerprinted one
+    fn get(&self, path: &str) -> Option<(&Asset, bool)> {
+        if let Some(asset) = self.by_hashed_path.get(path) {
+            return Some((asset, true));
+        }
+        self.by_path.get(path).map(|asset| (asset.as_ref(), false))
+    }
+}
+
+/// Files under `dir`, with their paths relative to the top directory
+fn collect_files(dir: &FsPath, prefix: &str, files: &mut Vec<(String, Vec<u8>)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Could not read static assets in {}: {}", dir.display(), e);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = format!("{}{}", prefix, name);
+        let file = entry.path();
+        if file.is_dir() {
+            collect_files(&file, &format!("{}/", path), files);
+            continue;
+        }
+        match fs::read(&file) {
+            Ok(body) => files.push((path, body)),
+            Err(e) => warn!("Could not read static asset {}: {}", file.display(), e),
+        }
+    }
+}
+
+impl Asset {
+    fn new(path: String, body: Vec<u8>) -> Self {
+        let hash: String = Sha256::digest(&body)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()[..HASH_LEN]
+            .to_string();
+        let hashed_path = match path.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() && !stem.ends_with('/') => {
+                format!("{}.{}.{}", stem, hash, ext)
+            }
+            _ => format!("{}.{}", path, hash),
+        };
+        let integrity = format!("sha384-{}", STANDARD.encode(Sha384::digest(&body)));
+        let content_type = content_type(&path);
+        let compressible = content_type.starts_with("text/")
+            || content_type.starts_with("application/javascript")
+            || content_type.starts_with("application/json")
+            || content_type.starts_with("image/svg+xml");
+        // A compressed copy is only kept if it saves something
+        let smaller = |packed: Vec<u8>| (packed.len() < body.len()).then(|| Bytes::from(packed));
+        let (brotli, gzip) = match compressible {
+            true => (
+                compress_brotli(&body).and_then(smaller),
+                compress_gzip(&body).and_then(smaller),
+            ),
+            false => (None, None),
+        };
+        Self {
+            path,
+            hashed_path,
+            integrity,
+            content_type,
+            hash,
+            body: Bytes::from(body),
+            brotli,
+            gzip,
+        }
+    }
+}
+
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("js") => "application/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("html") => "text/html; charset=utf-8",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+fn compress_brotli(body: &[u8]) -> Option<Vec<u8>> {
+    let mut packed = Vec::new();
+    {
+        let mut writer = brotli::CompressorWriter::new(&mut packed, 4096, 11, 22);
+        writer.write_all(body).ok()?;
+    }
+    Some(packed)
+}
+
+fn compress_gzip(body: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(body).ok()?;
+    encoder.finish().ok()
+}
+
+/// Whether the client's `Accept-Encoding` lists an encoding
+fn accepts(headers: &HeaderMap, encoding: &str) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            parts.next() == Some(encoding) && !parts.any(|p| p == "q=0")
+        })
+}
+
+/// Serve a static file by its plain or fingerprinted path
+pub async fn static_asset(
+    Path(path): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    let Some((asset, fingerprinted)) = state.assets.get(&path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let (body, encoding) = match (&asset.brotli, &asset.gzip) {
+        (Some(br), _) if accepts(&headers, "br") => (br.clone(), Some("br")),
+        (_, Some(gz)) if accepts(&headers, "gzip") => (gz.clone(), Some("gzip")),
+        _ => (asset.body.clone(), None),
+    };
+    // Each encoding is a different representation, so gets its own tag
+    let etag = match encoding {
+        Some(encoding) => format!("\"{}-{}\"", asset.hash, encoding),
+        None => format!("\"{}\"", asset.hash),
+    };
+    let cache_control = if fingerprinted { IMMUTABLE } else { REVALIDATE };
+
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(cache_control),
+    );
+    response_headers.insert(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, value);
+    }
+    let fresh = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|tags| tags.split(',').any(|t| t.trim() == etag || t.trim() == "*"));
+    if fresh {
+        return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+    }
+    response_headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(asset.content_type),
+    );
+    if let Some(encoding) = encoding {
+        response_headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+    }
+    (response_headers, body).into_response()
+}
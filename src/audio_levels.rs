@@ -0,0 +1,272 @@
+//! Audio-level telemetry and one-way audio detection
+//!
+//! Clients report the level of the audio they send with `audio_level`
+//! every `report_interval_ms`, twice a second by default. The server keeps
+//! each peer's last `AUDIO_WINDOW` reports.
+//! A peer whose recent reports are all silence while another peer in the
+//! room is heard points at one-way audio: a muted or broken microphone, or
+//! media that never leaves the device. It is written to the room's timeline
+//! once, re-arming after it clears, and operators can watch every room's
+//! levels at `/admin/audio-levels`.
+//!
+//! The same reports pick the room's active speaker, announced with
+//! `active_speaker`. To keep the main tile from flickering, a speaker holds
+//! the floor for at least `SPEAKER_HOLD`, another peer takes it only by
+//! being `SWITCH_MARGIN` times louder, and silence changes nothing. Only
+//! reports from the last two report intervals count towards speaking, so a
+//! peer that stops reporting, as the web client does while muted, is not
+//! handed the floor on what it said earlier.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::admin::AdminAuth;
+use crate::models::{PeerAudio, RoomAudio, TimelineKind, WsMessage};
+use crate::state::{AppState, Room};
+
+/// Reports kept per peer, a minute's worth at the default interval
+const AUDIO_WINDOW: usize = 120;
+
+/// Reports needed before a peer can count as silent, 20 seconds' worth at
+/// the default interval
+const MIN_REPORTS: usize = 40;
+
+/// Levels below this are silence
+const SILENCE_LEVEL: f64 = 0.01;
+
+/// Latest reports averaged to tell who is speaking
+const SPEAKER_WINDOW: usize = 3;
+
+/// Average level at which a peer counts as speaking
+const SPEAKING_LEVEL: f64 = 0.05;
+
+/// How much louder than the active speaker another peer must be to take over
+const SWITCH_MARGIN: f64 = 1.5;
+
+/// Shortest time an active speaker keeps the floor
+pub const SPEAKER_HOLD: Duration = Duration::from_millis(1500);
+
+/// How often clients report their audio level
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AudioLevelConfig {
+    /// Milliseconds between a client's reports; reports older than two
+    /// intervals no longer count towards speaking
+    pub report_interval_ms: u64,
+}
+
+impl Default for AudioLevelConfig {
+    fn default() -> Self {
+        // The web client's audio level timer
+        Self {
+            report_interval_ms: 500,
+        }
+    }
+}
+
+impl AudioLevelConfig {
+    /// Age past which a report no longer counts towards speaking
+    fn max_age(&self) -> Duration {
+        Duration::from_millis(self.report_interval_ms.saturating_mul(2))
+    }
+}
+
+/// A peer's recent audio levels
+#[derive(Debug, Default)]
+pub struct AudioLevels {
+    recent: VecDeque<f64>,
+    /// When each of `recent` arrived
+    received: VecDeque<Instant>,
+    /// Reports received since the peer joined
+    pub reports: u64,
+}
+
+impl AudioLevels {
+    fn record(&mut self, level: f64) {
+        if self.recent.len() == AUDIO_WINDOW {
+            self.recent.pop_front();
+            self.received.pop_front();
+        }
+        self.recent.push_back(level);
+        self.received.push_back(Instant::now());
+        self.reports += 1;
+    }
+
+    /// Whether every recent report was silence
+    pub fn silent(&self) -> bool {
+        self.recent.len() >= MIN_REPORTS && self.recent.iter().all(|l| *l < SILENCE_LEVEL)
+    }
+
+    /// Whether any recent report was above silence
+    pub fn audible(&self) -> bool {
+        self.recent.iter().any(|l| *l >= SILENCE_LEVEL)
+    }
+
+    /// Average of the latest few reports younger than `max_age`; 0.0
+    /// without any
+    fn loudness(&self, max_age: Duration) -> f64 {
+        let (sum, count) = self
+            .recent
+            .iter()
+            .zip(&self.received)
+            .rev()
+            .take(SPEAKER_WINDOW)
+            .take_while(|(_, at)| at.elapsed() <= max_age)
+            .fold((0.0, 0), |(sum, count), (level, _)| {
+                (sum + level, count + 1)
+            });
+        match count {
+            0 => 0.0,
+            _ => sum / count as f64,
+        }
+    }
+
+    fn mean(&self) -> Option<f64> {
+        let count = self.recent.len();
+        (count > 0).then(|| self.recent.iter().sum::<f64>() / count as f64)
+    }
+}
+
+impl Room {
+    /// A peer that stays silent while another is heard
+    pub fn one_way_audio(&self) -> Option<&str> {
+        if !self.peers.iter().any(|p| p.audio.audible()) {
+            return None;
+        }
+        self.peers
+            .iter()
+            .find(|p| p.audio.silent())
+            .map(|p| p.id.as_str())
+    }
+
+    /// Hand the floor to the loudest speaking peer if the hysteresis allows,
+    /// telling everyone; reports older than `max_age` are ignored
+    pub fn update_active_speaker(&mut self, max_age: Duration) {
+        let Some((loudest, level)) = self
+            .peers
+            .iter()
+            .map(|p| (p, p.audio.loudness(max_age)))
+            .filter(|(_, level)| *level >= SPEAKING_LEVEL)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        else {
+            return;
+        };
+        let loudest = loudest.id.clone();
+        if let Some((current, since)) = &self.active_speaker {
+            if *current == loudest {
+                return;
+            }
+            // A speaker who left holds nothing
+            if let Some(speaker) = self.peers.iter().find(|p| p.id == *current)
+                && (since.elapsed() < SPEAKER_HOLD
+                    || level < speaker.audio.loudness(max_age) * SWITCH_MARGIN)
+            {
+                return;
+            }
+        }
+        self.active_speaker = Some((loudest.clone(), Instant::now()));
+        self.broadcast_to_all(&WsMessage::ActiveSpeaker { peer_id: loudest });
+    }
+}
+
+impl AppState {
+    /// Record a peer's audio level and flag one-way audio
+    pub async fn record_audio_level(&self, room_id: &str, peer_id: &str, level: f64) {
+        if !(0.0..=1.0).contains(&level) {
+            return;
+        }
+        let id = peer_id.to_string();
+        let max_age = self.config().audio_levels.max_age();
+        let flagged = self.with_room(room_id, move |room| {
+            room.peers
+                .iter_mut()
+                .find(|p| p.id == id)?
+                .audio
+                .record(level);
+            room.update_active_speaker(max_age);
+            let silent = room.one_way_audio().map(str::to_string);
+            let newly = silent.is_some() && !room.one_way_audio;
+            room.one_way_audio = silent.is_some();
+            silent.filter(|_| newly)
+        });
+        let Some(Some(silent)) = flagged.await else {
+            return;
+        };
+
+        warn!(
+            "Peer {} in room {} is silent while others are heard",
+            silent, room_id
+        );
+        self.record_timeline(
+            room_id,
+            TimelineKind::OneWayAudio,
+            Some(&silent),
+            "Silent while others are heard",
+        )
+        .await;
+    }
+
+    /// Audio levels of every running room
+    pub async fn list_audio_levels(&self, one_way_only: bool) -> Vec<RoomAudio> {
+        let mut list = Vec::new();
+        for (room_id, room) in self.room_handles().await {
+            let audio = room.call(move |room| RoomAudio {
+                room_id,
+                one_way_audio: room.one_way_audio().map(str::to_string),
+                peers: room
+                    .peers
+                    .iter()
+                    .map(|peer| PeerAudio {
+                        peer_id: peer.id.clone(),
+                        level: peer.audio.recent.back().copied(),
+                        mean_level: peer.audio.mean(),
+                        reports: peer.audio.reports,
+                        silent: peer.audio.silent(),
+                    })
+                    .collect(),
+            });
+            list.extend(
+                audio
+                    .await
+                    .filter(|a| a.one_way_audio.is_some() || !one_way_only),
+            );
+        }
+        list
+    }
+}
+
+/// Query parameters for the audio-level listing
+#[derive(Debug, Deserialize)]
+pub struct AudioLevelQuery {
+    #[serde(default)]
+    pub one_way: bool,
+}
+
+/// Recent audio levels per room and peer
+#[utoipa::path(
+    get,
+    path = "/admin/audio-levels",
+    tag = "Admin",
+    params(
+        ("one_way" = Option<bool>, Query, description = "Only rooms with one-way audio")
+    ),
+    responses(
+        (status = 200, description = "Audio levels of the running rooms", body = Vec<RoomAudio>),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_audio_levels(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+    Query(query): Query<AudioLevelQuery>,
+) -> Json<Vec<RoomAudio>> {
+    Json(state.list_audio_levels(query.one_way).await)
+}
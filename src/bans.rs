@@ -0,0 +1,108 @@
+//! Keeping removed people out of a room
+//!
+//! An operator removing a peer with `?ban=true` also bans the identity it
+//! joined with, the `sub` of its room JWT or user token, until they lift
+//! the ban. Joins under a banned identity are refused, whichever token
+//! carries it. Peers that joined without a token naming them cannot be
+//! banned, since nothing would recognise them on their way back. Bans live
+//! as long as their room and move with it through a room store.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use tracing::info;
+
+use crate::admin::OperatorAuth;
+use crate::state::{AppState, Room};
+
+impl Room {
+    /// Whether `identity` is banned from the room
+    pub fn is_banned(&self, identity: &str) -> bool {
+        self.bans.iter().any(|b| b == identity)
+    }
+}
+
+impl AppState {
+    /// Ban the identity `peer_id` joined with and remove the peer
+    pub async fn ban_peer(
+        &self,
+        room_id: &str,
+        peer_id: &str,
+        actor: &str,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        let id = peer_id.to_string();
+        let banned = self
+            .with_room(room_id, move |room| {
+                let peer = room.peers.iter().find(|p| p.id == id);
+                let identity = peer.ok_or((StatusCode::NOT_FOUND, "Peer not found"))?;
+                let identity = identity.identity.clone().ok_or((
+                    StatusCode::CONFLICT,
+                    "Only peers that joined with a token naming them can be banned",
+                ))?;
+                if !room.is_banned(&identity) {
+                    room.bans.push(identity.clone());
+                }
+                Ok(identity)
+            })
+            .await
+            .unwrap_or(Err((StatusCode::NOT_FOUND, "Room not found")))?;
+        info!(
+            "Banned {} from room {} on request of {}",
+            banned, room_id, actor
+        );
+        self.record_audit(Some(room_id), actor, "peer.ban", banned.as_str())
+            .await;
+        self.kick_peer(room_id, peer_id, actor).await;
+        self.save_room_state(room_id).await;
+        Ok(())
+    }
+
+    /// Lift a ban; returns false if there was none
+    pub async fn unban(&self, room_id: &str, identity: &str, actor: &str) -> bool {
+        let who = identity.to_string();
+        let lifted = self
+            .with_room(room_id, move |room| {
+                let before = room.bans.len();
+                room.bans.retain(|b| *b != who);
+                room.bans.len() < before
+            })
+            .await;
+        if lifted != Some(true) {
+            return false;
+        }
+        self.record_audit(Some(room_id), actor, "peer.unban", identity)
+            .await;
+        self.save_room_state(room_id).await;
+        true
+    }
+}
+
+/// Lift a ban
+#[utoipa::path(
+    delete,
+    path = "/admin/rooms/{room_id}/bans/{identity}",
+    tag = "Admin",
+    params(
+        ("room_id" = String, Path, description = "The room identifier"),
+        ("identity" = String, Path, description = "The banned token subject")
+    ),
+    responses(
+        (status = 204, description = "Ban lifted"),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "Room or ban not found")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn unban(
+    OperatorAuth(auth): OperatorAuth,
+    State(state): State<AppState>,
+    Path((room_id, identity)): Path<(String, String)>,
+) -> Response {
+    match state.unban(&room_id, &identity, &auth.principal).await {
+        true => StatusCode::NO_CONTENT.into_response(),
+        false => (StatusCode::NOT_FOUND, "No such ban").into_response(),
+    }
+}
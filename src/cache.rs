@@ -0,0 +1,241 @@
+//! Bounded, expiring maps for short-lived state
+//!
+//! Tokens, idempotency keys, per-client counters and the like only matter
+//! for a while and must not grow without bound. [`EphemeralCache`] holds
+//! them with a time to live and a capacity: expired entries are dropped
+//! when touched or swept, and inserting into a full cache evicts the least
+//! recently used entry. Each cache counts hits, misses, evictions and
+//! expirations; `/admin/caches` lists them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use axum::{Json, extract::State};
+
+use crate::admin::AdminAuth;
+use crate::models::CacheStats;
+use crate::state::AppState;
+
+/// A map whose entries expire and are evicted least recently used first
+#[derive(Debug)]
+pub struct EphemeralCache<K, V> {
+    name: &'static str,
+    capacity: usize,
+    ttl: Duration,
+    /// Whether a hit restarts the entry's time to live
+    sliding: bool,
+    entries: HashMap<K, Slot<V>>,
+    /// Keys by last use, oldest first
+    order: BTreeMap<u64, K>,
+    clock: u64,
+    stats: Counters,
+}
+
+#[derive(Debug)]
+struct Slot<V> {
+    value: V,
+    expires_at: Instant,
+    used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+    expirations: u64,
+}
+
+impl<K: Clone + Eq + Hash, V> EphemeralCache<K, V> {
+    /// A cache of at most `capacity` entries, each living `ttl` from insert
+    pub fn new(name: &'static str, capacity: usize, ttl: Duration) -> Self {
+        Self {
+            name,
+            capacity: capacity.max(1),
+            ttl,
+            sliding: false,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            clock: 0,
+            stats: Counters::default(),
+        }
+    }
+
+    /// Make every hit restart the entry's time to live, so entries expire
+    /// after `ttl` unused
+    pub fn sliding(mut self) -> Self {
+        self.sliding = true;
+        self
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Drop `key` if it has expired; true if it is (still) present
+    fn check(&mut self, key: &K) -> bool {
+        let Some(slot) = self.entries.get(key) else {
+            return false;
+        };
+        if slot.expires_at > Instant::now() {
+            return true;
+        }
+        self.order.remove(&slot.used);
+        self.entries.remove(key);
+        self.stats.expirations += 1;
+        false
+    }
+
+    /// Mark `key`, which must be present, as just used
+    fn touch(&mut self, key: &K) {
+        let tick = self.tick();
+        let (ttl, sliding) = (self.ttl, self.sliding);
+        let slot = self.entries.get_mut(key).expect("touched key is present");
+        self.order.remove(&slot.used);
+        slot.used = tick;
+        if sliding {
+            slot.expires_at = Instant::now() + ttl;
+        }
+        self.order.insert(tick, key.clone());
+    }
+
+    /// The live value under `key`, marking it used
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        if !self.check(key) {
+            self.stats.misses += 1;
+            return None;
+        }
+        self.stats.hits += 1;
+        self.touch(key);
+        self.entries.get_mut(key).map(|slot| &mut slot.value)
+    }
+
+    /// The live value under `key`, inserting `make()` if there is none
+    pub fn get_or_insert_with(&mut self, key: K, make: impl FnOnce() -> V) -> &mut V {
+        if self.check(&key) {
+            self.stats.hits += 1;
+            self.touch(&key);
+        } else {
+            self.stats.misses += 1;
+            self.insert(key.clone(), make());
+        }
+        &mut self
+            .entries
+            .get_mut(&key)
+            .expect("present after insert")
+            .value
+    }
+
+    /// Insert or replace the value under `key`, evicting the least recently
+    /// used entry if the cache is full
+    pub fn insert(&mut self, key: K, value: V) {
+        self.remove(&key);
+        if self.entries.len() >= self.capacity {
+            self.purge_expired();
+        }
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+            self.stats.evictions += 1;
+        }
+        let used = self.tick();
+        self.order.insert(used, key.clone());
+        self.entries.insert(
+            key,
+            Slot {
+                value,
+                expires_at: Instant::now() + self.ttl,
+                used,
+            },
+        );
+    }
+
+    /// Take the live value under `key` out of the cache
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        if !self.check(key) {
+            return None;
+        }
+        let slot = self.entries.remove(key)?;
+        self.order.remove(&slot.used);
+        Some(slot.value)
+    }
+
+    /// Drop every expired entry
+    pub fn purge_expired(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<K> = self
+            .entries
+            .iter()
+            .filter(|(_, slot)| slot.expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            if let Some(slot) = self.entries.remove(&key) {
+                self.order.remove(&slot.used);
+                self.stats.expirations += 1;
+            }
+        }
+    }
+
+    /// Number of entries, including expired ones not yet swept
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Size and counters
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            name: self.name.to_string(),
+            entries: self.entries.len(),
+            capacity: self.capacity,
+            ttl_secs: self.ttl.as_secs(),
+            hits: self.stats.hits,
+            misses: self.stats.misses,
+            evictions: self.stats.evictions,
+            expirations: self.stats.expirations,
+        }
+    }
+}
+
+impl AppState {
+    /// Sweep expired entries out of every cache
+    pub async fn purge_caches(&self) {
+        self.rate_limits.lock().await.purge_expired();
+        self.exports.lock().await.purge_expired();
+        self.saml_assertions.lock().await.purge_expired();
+        self.saml_requests.lock().await.purge_expired();
+    }
+
+    /// Size and counters of every cache
+    pub async fn cache_stats(&self) -> Vec<CacheStats> {
+        vec![
+            self.rate_limits.lock().await.stats(),
+            self.exports.lock().await.stats(),
+            self.saml_assertions.lock().await.stats(),
+            self.saml_requests.lock().await.stats(),
+        ]
+    }
+}
+
+/// Ephemeral cache sizes and hit rates
+#[utoipa::path(
+    get,
+    path = "/admin/caches",
+    tag = "Admin",
+    responses(
+        (status = 200, description = "Every ephemeral cache", body = [CacheStats]),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_caches(_auth: AdminAuth, State(state): State<AppState>) -> Json<Vec<CacheStats>> {
+    Json(state.cache_stats().await)
+}
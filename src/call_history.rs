@@ -0,0 +1,159 @@
+//! Per-user call log
+//!
+//! Every call placed with `/api/calls` is logged for both parties, and the
+//! entry follows the call as it is answered, declined or missed and once it
+//! ends. The log outlives the call's room and CDR; it keeps the most recent
+//! `MAX_CALL_HISTORY` calls per user. `GET /api/users/{user}/calls` pages
+//! through it, newest first, for that user only.
+
+use std::collections::{HashMap, VecDeque};
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::models::{CallDirection, CallHistoryEntry, CallHistoryPage, CallState, DirectedCall};
+use crate::state::{AppState, unix_timestamp};
+use crate::token::UserAuth;
+
+/// Calls kept per user; the oldest are dropped first
+pub const MAX_CALL_HISTORY: usize = 500;
+
+/// Calls returned per page unless `limit` says otherwise
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Largest page that can be requested
+const MAX_PAGE_SIZE: usize = 200;
+
+/// Directed calls by token subject of either party, oldest first
+pub type CallHistory = HashMap<String, VecDeque<DirectedCall>>;
+
+impl DirectedCall {
+    /// This call as seen by `user`
+    fn entry_for(&self, user: &str) -> CallHistoryEntry {
+        let (direction, with) = match self.caller == user {
+            true => (CallDirection::Outgoing, &self.callee),
+            false => (CallDirection::Incoming, &self.caller),
+        };
+        CallHistoryEntry {
+            room_id: self.room_id.clone(),
+            direction,
+            with: with.clone(),
+            state: self.state,
+            started_at: self.rang_at,
+            duration_secs: self
+                .answered_at
+                .zip(self.ended_at)
+                .map(|(answered, ended)| ended.saturating_sub(answered)),
+            voicemail_url: self
+                .voicemail_at
+                .map(|_| format!("/api/calls/{}/voicemail", self.room_id)),
+            voicemail_scan: self.voicemail_scan,
+        }
+    }
+}
+
+impl AppState {
+    /// Log the latest state of a directed call for both parties
+    pub async fn record_call_history(&self, call: &DirectedCall) {
+        let mut history = self.call_history.lock().await;
+        for user in [&call.caller, &call.callee] {
+            let log = history.entry(user.clone()).or_default();
+            match log.iter_mut().rev().find(|c| c.room_id == call.room_id) {
+                Some(logged) => *logged = call.clone(),
+                None => {
+                    if log.len() >= MAX_CALL_HISTORY {
+                        log.pop_front();
+                    }
+                    log.push_back(call.clone());
+                }
+            }
+        }
+    }
+
+    /// Note that everyone has left the room of an answered directed call
+    pub async fn end_directed_call(&self, room_id: &str) {
+        let ended = {
+            let mut calls = self.calls.lock().await;
+            calls
+                .get_mut(room_id)
+                .and_then(|c| c.directed.as_mut())
+                .filter(|c| c.state == CallState::Accepted && c.ended_at.is_none())
+                .map(|c| {
+                    c.ended_at = Some(unix_timestamp());
+                    c.clone()
+                })
+        };
+        if let Some(call) = ended {
+            self.record_call_history(&call).await;
+        }
+    }
+
+    /// A page of `user`'s calls, newest first
+    pub async fn call_history_page(
+        &self,
+        user: &str,
+        offset: usize,
+        limit: usize,
+    ) -> CallHistoryPage {
+        let history = self.call_history.lock().await;
+        let log = history.get(user);
+        let total = log.map_or(0, VecDeque::len);
+        let calls = log
+            .into_iter()
+            .flat_map(|log| log.iter().rev())
+            .skip(offset)
+            .take(limit)
+            .map(|c| c.entry_for(user))
+            .collect();
+        CallHistoryPage {
+            calls,
+            total,
+            next_offset: Some(offset + limit).filter(|&next| next < total),
+        }
+    }
+}
+
+/// Query parameters for the call log
+#[derive(Debug, Deserialize)]
+pub struct CallHistoryQuery {
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+/// Page through a user's call log
+#[utoipa::path(
+    get,
+    path = "/api/users/{user}/calls",
+    tag = "Calls",
+    params(
+        ("user" = String, Path, description = "The user's token subject"),
+        ("offset" = Option<usize>, Query, description = "Calls to skip, newest first"),
+        ("limit" = Option<usize>, Query, description = "Calls per page (default 50, at most 200)")
+    ),
+    responses(
+        (status = 200, description = "The user's calls, newest first", body = CallHistoryPage),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 403, description = "Not the caller's own call log")
+    )
+)]
+pub async fn list_user_calls(
+    Path(user): Path<String>,
+    State(state): State<AppState>,
+    UserAuth(caller): UserAuth,
+    Query(query): Query<CallHistoryQuery>,
+) -> Response {
+    if caller != user {
+        return (StatusCode::FORBIDDEN, "You can only see your own calls").into_response();
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    Json(state.call_history_page(&user, query.offset, limit).await).into_response()
+}
@@ -0,0 +1,123 @@
+//! Call duration limits
+//!
+//! A room's `max_duration_secs` comes from its creation request, its
+//! template or, failing both, `call_limits.default_max_duration_secs`; a
+//! request may shorten the default but not extend it, so the default can
+//! serve as a free-tier cap. Rooms started without a request, from `GET /`
+//! or by joining an unknown room, get the default too. The clock starts when
+//! the first peer joins.
+//! Peers get `time_remaining` as the time left crosses each of
+//! `warn_at_secs`, and once it runs out everyone gets `room_expired`, then
+//! `call_ended`, and the room closes with its call record marked
+//! `expired`. An expired room cannot be started again by joining it for as
+//! long as the archive keeps it, so reconnecting does not reset the clock;
+//! personal rooms, which recur under one ID, are the exception.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use tracing::info;
+
+use crate::models::{ReapReason, WsMessage};
+use crate::state::{AppState, Room};
+
+/// Duration limits applied to every room
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CallLimitsConfig {
+    /// Limit for rooms that set none; unlimited when unset
+    pub default_max_duration_secs: Option<u64>,
+    /// Seconds before the end at which peers are warned
+    pub warn_at_secs: Vec<u64>,
+    /// Seconds between checks of every room's remaining time
+    pub check_interval_secs: u64,
+}
+
+impl Default for CallLimitsConfig {
+    fn default() -> Self {
+        Self {
+            default_max_duration_secs: None,
+            warn_at_secs: vec![300, 60],
+            check_interval_secs: 5,
+        }
+    }
+}
+
+impl CallLimitsConfig {
+    /// Limit for a room asking for `requested` seconds
+    pub fn max_duration(&self, requested: Option<u64>) -> Option<u64> {
+        match (requested, self.default_max_duration_secs) {
+            (Some(requested), Some(default)) => Some(requested.min(default)),
+            (requested, default) => requested.or(default),
+        }
+    }
+}
+
+impl Room {
+    /// Time left before the call reaches its maximum duration
+    pub fn time_remaining(&self) -> Option<Duration> {
+        let (started, max) = (self.started_at?, self.settings.max_duration_secs?);
+        Some(Duration::from_secs(max).saturating_sub(started.elapsed()))
+    }
+
+    /// Warn peers once the time left crosses a threshold not yet announced
+    ///
+    /// Returns whether the call is out of time.
+    pub fn warn_time_remaining(&mut self, thresholds: &[u64]) -> bool {
+        let Some(remaining) = self.time_remaining() else {
+            return false;
+        };
+        if remaining.is_zero() {
+            return true;
+        }
+        let crossed = thresholds
+            .iter()
+            .copied()
+            .filter(|&t| remaining <= Duration::from_secs(t))
+            .filter(|&t| self.duration_warned.is_none_or(|warned| t < warned))
+            .min();
+        if let Some(threshold) = crossed {
+            self.duration_warned = Some(threshold);
+            // Rounded up, so a warning never claims less time than is left
+            let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+            self.broadcast_to_all(&WsMessage::TimeRemaining { secs });
+        }
+        false
+    }
+}
+
+impl AppState {
+    /// Whether `room_id` closed at its maximum duration and is still archived
+    pub async fn has_expired(&self, room_id: &str) -> bool {
+        (self.archive.lock().await.get(room_id)).is_some_and(|e| e.reason == ReapReason::Expired)
+    }
+
+    /// Warn rooms running out of time and close those out of it
+    pub async fn enforce_call_limits(&self) {
+        let thresholds = self.config().call_limits.warn_at_secs.clone();
+        let mut expired = false;
+        for (_, handle) in self.room_handles().await {
+            let thresholds = thresholds.clone();
+            expired |= handle
+                .call(move |room| room.warn_time_remaining(&thresholds))
+                .await
+                .unwrap_or(false);
+        }
+        if expired {
+            info!("Closing rooms that reached their maximum duration");
+            self.cleanup_inactive_rooms().await;
+        }
+    }
+}
+
+/// Spawn background task checking rooms against their duration limits
+pub fn spawn_call_limits(state: AppState) {
+    let interval = state.config().call_limits.check_interval_secs.max(1);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            interval.tick().await;
+            state.enforce_call_limits().await;
+        }
+    });
+}
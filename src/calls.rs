@@ -0,0 +1,212 @@
+//! Directed calls between users
+//!
+//! `POST /api/calls` rings a user who is online and available on its
+//! presence socket, or who is offline with a mobile device registered for
+//! push. The server creates a room open to the two of them only, which both
+//! join with their user tokens, and sends the callee `incoming_call`. The callee answers with `answer_call`; a call nobody
+//! answers within `ring_timeout_secs` is missed. Either way both parties'
+//! presence sockets and the room are told how it went with `call_status`,
+//! and the outcome is kept in the call's CDR.
+
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::models::{
+    CallState, DirectedCall, PlaceCallRequest, PresenceStatus, RoomSettings, WsMessage,
+};
+use crate::state::{AppState, unix_timestamp};
+use crate::token::UserAuth;
+
+impl AppState {
+    /// Ring `callee` on behalf of `caller`
+    pub async fn place_call(
+        &self,
+        caller: &str,
+        callee: &str,
+    ) -> Result<DirectedCall, (StatusCode, &'static str)> {
+        if caller == callee {
+            return Err((StatusCode::BAD_REQUEST, "You cannot call yourself"));
+        }
+        match self.presence_of(callee).await.status {
+            PresenceStatus::Available => {}
+            PresenceStatus::DoNotDisturb => {
+                return Err((StatusCode::CONFLICT, "User does not want to be disturbed"));
+            }
+            PresenceStatus::Offline if !self.can_push_to(callee).await => {
+                return Err((StatusCode::CONFLICT, "User is offline"));
+            }
+            PresenceStatus::Offline => {}
+        }
+
+        let room_id = Uuid::new_v4().to_string();
+        self.create_room(room_id.clone(), RoomSettings::default())
+            .await;
+        self.invite_only(&room_id, vec![caller.to_string(), callee.to_string()])
+            .await;
+        let call = DirectedCall {
+            room_id: room_id.clone(),
+            caller: caller.to_string(),
+            callee: callee.to_string(),
+            state: CallState::Ringing,
+            rang_at: unix_timestamp(),
+            answered_at: None,
+            ended_at: None,
+            voicemail_at: None,
+            voicemail_scan: None,
+        };
+        if let Some(record) = self.calls.lock().await.get_mut(&room_id) {
+            record.directed = Some(call.clone());
+        }
+        self.record_call_history(&call).await;
+
+        let config = self.config();
+        let ring = WsMessage::IncomingCall {
+            room_id: room_id.clone(),
+            from: caller.to_string(),
+            url: format!(
+                "{}/room/{}",
+                config.public_url.trim_end_matches('/'),
+                room_id
+            ),
+        };
+        // Offline callees are rung on their mobile devices instead; either
+        // may have changed since the check
+        if let Err(e) = self.notify_user(callee, ring.clone()).await {
+            let expires_at = unix_timestamp() + config.ring_timeout_secs;
+            if self.push_to_user(callee, &ring, expires_at).await == 0 {
+                let _ = self.settle_call(&room_id, None, CallState::Missed).await;
+                return Err((StatusCode::CONFLICT, e));
+            }
+        }
+        info!("{} is calling {} in room {}", caller, callee, room_id);
+
+        let (state, ring_timeout) = (self.clone(), Duration::from_secs(config.ring_timeout_secs));
+        tokio::spawn(async move {
+            tokio::time::sleep(ring_timeout).await;
+            let _ = state.settle_call(&room_id, None, CallState::Missed).await;
+        });
+        Ok(call)
+    }
+
+    /// Pick up or decline a call ringing for `user`
+    pub async fn answer_call(
+        &self,
+        user: &str,
+        room_id: &str,
+        accept: bool,
+    ) -> Result<(), &'static str> {
+        let state = match accept {
+            true => CallState::Accepted,
+            false => CallState::Declined,
+        };
+        self.settle_call(room_id, Some(user), state).await?;
+        info!(
+            "{} answered the call in room {}: {:?}",
+            user, room_id, state
+        );
+        Ok(())
+    }
+
+    /// End the ringing of a call, answered by `callee` if given
+    async fn settle_call(
+        &self,
+        room_id: &str,
+        callee: Option<&str>,
+        state: CallState,
+    ) -> Result<(), &'static str> {
+        let call = {
+            let mut calls = self.calls.lock().await;
+            let call = calls
+                .get_mut(room_id)
+                .and_then(|c| c.directed.as_mut())
+                .filter(|c| callee.is_none_or(|u| c.callee == u))
+                .ok_or("No such call")?;
+            if call.state != CallState::Ringing {
+                return Err("The call is no longer ringing");
+            }
+            call.state = state;
+            if state == CallState::Accepted {
+                call.answered_at = Some(unix_timestamp());
+            }
+            call.clone()
+        };
+        self.record_call_history(&call).await;
+
+        let status = WsMessage::CallStatus {
+            room_id: room_id.to_string(),
+            state,
+        };
+        self.tell_user(&call.caller, status.clone()).await;
+        self.tell_user(&call.callee, status.clone()).await;
+        self.with_room(room_id, move |room| room.broadcast_to_all(&status))
+            .await;
+        Ok(())
+    }
+
+    /// A directed call, if `user` took part in it
+    pub async fn directed_call(&self, room_id: &str, user: &str) -> Option<DirectedCall> {
+        let calls = self.calls.lock().await;
+        calls
+            .get(room_id)?
+            .directed
+            .clone()
+            .filter(|c| c.caller == user || c.callee == user)
+    }
+}
+
+/// Ring an online user
+#[utoipa::path(
+    post,
+    path = "/api/calls",
+    tag = "Calls",
+    request_body = PlaceCallRequest,
+    responses(
+        (status = 200, description = "The callee is ringing", body = DirectedCall),
+        (status = 400, description = "Caller and callee are the same user"),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 409, description = "The callee is on do-not-disturb, or offline with no device to push to")
+    )
+)]
+pub async fn place_call(
+    State(state): State<AppState>,
+    UserAuth(caller): UserAuth,
+    Json(request): Json<PlaceCallRequest>,
+) -> Response {
+    match state.place_call(&caller, &request.callee).await {
+        Ok(call) => Json(call).into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Look up a call the caller took part in
+#[utoipa::path(
+    get,
+    path = "/api/calls/{room_id}",
+    tag = "Calls",
+    params(
+        ("room_id" = String, Path, description = "The call's room")
+    ),
+    responses(
+        (status = 200, description = "The call", body = DirectedCall),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 404, description = "No such call, or not one of the caller's")
+    )
+)]
+pub async fn get_call(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+    UserAuth(user): UserAuth,
+) -> Response {
+    match state.directed_call(&room_id, &user).await {
+        Some(call) => Json(call).into_response(),
+        None => (StatusCode::NOT_FOUND, "No such call").into_response(),
+    }
+}
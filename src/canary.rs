@@ -0,0 +1,725 @@
+//! Synthetic loopback calls
+//!
+//! With a `canary` section, the server probes itself every `interval_secs`.
+//! Two peers built on the client SDK join a fresh room over the server's
+//! own signaling URL, fetch the ICE servers browsers get, and trade an
+//! offer, an answer and a candidate each way. When those ICE servers
+//! include a UDP TURN URL with credentials, the caller also allocates a
+//! relayed address there with the minted credentials, signals it as its
+//! candidate, and sends a message through the relay to a second socket,
+//! which echoes it back.
+//!
+//! Then the peers call each other with webrtc-rs, as browsers would: the
+//! caller offers an Opus track, the callee answers, candidates trickle over
+//! signaling, and the run waits until the callee receives the caller's
+//! first audio packet over ICE, DTLS and SRTP. With TURN available and
+//! `relay_media` set (the default), the peers only gather relayed
+//! candidates, so the media crosses the TURN server.
+//!
+//! Each run records how long the joins, the signaling exchange, the TURN
+//! allocation, the TURN echo and the call's media took, or why it failed.
+//! `GET /admin/canary` lists recent runs, and the `signaling_probe` alert
+//! metric fires when the latest run failed or was slow.
+//!
+//! Running probes needs the `canary` feature; a server built without it logs
+//! that the canary is unavailable.
+
+use std::collections::VecDeque;
+
+use axum::{Json, extract::State};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::admin::AdminAuth;
+use crate::models::CanaryRun;
+use crate::state::AppState;
+
+/// Runs kept for `/admin/canary`; the oldest are dropped first
+pub const MAX_CANARY_RUNS: usize = 100;
+
+/// How and how often the server probes itself
+#[derive(Debug, Clone, Deserialize)]
+pub struct CanaryConfig {
+    /// Seconds between probes (read at startup only)
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// Server to probe; `public_url` when unset
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Seconds a probe may take before it counts as failed
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Only let the loopback call's media through TURN, when there is one
+    #[serde(default = "default_relay_media")]
+    pub relay_media: bool,
+}
+
+fn default_interval_secs() -> u64 {
+    300
+}
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+fn default_relay_media() -> bool {
+    true
+}
+
+/// Recent canary runs, oldest first
+pub type CanaryRuns = VecDeque<CanaryRun>;
+
+impl AppState {
+    /// Keep a run, dropping the oldest past [`MAX_CANARY_RUNS`]
+    pub async fn record_canary_run(&self, run: CanaryRun) {
+        let mut runs = self.canary_runs.lock().await;
+        if runs.len() >= MAX_CANARY_RUNS {
+            runs.pop_front();
+        }
+        runs.push_back(run);
+    }
+
+    /// The most recent canary run
+    pub async fn last_canary_run(&self) -> Option<CanaryRun> {
+        self.canary_runs.lock().await.back().cloned()
+    }
+}
+
+/// Spawn the task running probes if a canary is configured
+pub fn spawn_canary(state: AppState) {
+    let Some(config) = state.config().canary.clone() else {
+        return;
+    };
+    if !cfg!(feature = "canary") {
+        warn!("A canary is configured, but this server was built without the canary feature");
+        return;
+    }
+    info!("Placing a loopback call every {}s", config.interval_secs);
+    #[cfg(feature = "canary")]
+    tokio::spawn(async move {
+        let period = std::time::Duration::from_secs(config.interval_secs.max(1));
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            // Reloads may change everything but the interval
+            let config = state.config().canary.clone().unwrap_or(config.clone());
+            state.run_canary(&config).await;
+        }
+    });
+}
+
+#[cfg(feature = "canary")]
+mod probe {
+    use std::fmt::Display;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use axum::body::Bytes;
+    use md5::{Digest, Md5};
+    use tokio::net::UdpSocket;
+    use tokio::sync::mpsc;
+    use tokio::time::Instant;
+    use webrtc::api::media_engine::MIME_TYPE_OPUS;
+    use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+    use webrtc::media::Sample;
+    use webrtc::peer_connection::RTCPeerConnection;
+    use webrtc::peer_connection::policy::ice_transport_policy::RTCIceTransportPolicy;
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+    use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+    use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+
+    use super::*;
+    use crate::alerts::{turn_address, udp_socket_for};
+    use crate::client::{AxiVidClient, RoomConnection, RoomSender};
+    use crate::config::IceServer;
+    use crate::models::{CreateRoomRequest, WsMessage};
+    use crate::rtc;
+    use crate::state::unix_timestamp;
+    use crate::stun::{self, Message};
+
+    /// Sent through the relay and expected back
+    const ECHO: &[u8] = b"canary";
+
+    /// An Opus frame of silence, sent by the loopback call's caller
+    const SILENCE: &[u8] = &[0xf8, 0xff, 0xfe];
+
+    /// Audio sent per packet in the loopback call
+    const FRAME: Duration = Duration::from_millis(20);
+
+    /// Milliseconds of a successful probe, and the TURN URL allocated on
+    #[derive(Default)]
+    struct Timings {
+        join_ms: u64,
+        signaling_ms: u64,
+        turn: Option<String>,
+        turn_allocate_ms: Option<u64>,
+        turn_echo_ms: Option<u64>,
+        media_ms: u64,
+        media_relayed: bool,
+    }
+
+    fn fail(e: impl Display) -> String {
+        e.to_string()
+    }
+
+    fn millis(since: Instant) -> u64 {
+        since.elapsed().as_millis() as u64
+    }
+
+    impl AppState {
+        /// Run one loopback call and keep its outcome
+        pub async fn run_canary(&self, config: &CanaryConfig) -> CanaryRun {
+            let base = config
+                .url
+                .clone()
+                .unwrap_or_else(|| self.config().public_url.clone());
+            let started_at = unix_timestamp();
+            let timeout = Duration::from_secs(config.timeout_secs);
+            let outcome = tokio::time::timeout(timeout, probe(&base, config.relay_media))
+                .await
+                .unwrap_or_else(|_| Err(format!("Timed out after {}s", config.timeout_secs)));
+            let run = match outcome {
+                Ok(timings) => {
+                    info!(
+                        "Signaling probe negotiated in {} ms, TURN echo in {:?} ms, media in {} ms",
+                        timings.signaling_ms, timings.turn_echo_ms, timings.media_ms
+                    );
+                    CanaryRun {
+                        started_at,
+                        ok: true,
+                        join_ms: Some(timings.join_ms),
+                        signaling_ms: Some(timings.signaling_ms),
+                        turn: timings.turn,
+                        turn_allocate_ms: timings.turn_allocate_ms,
+                        turn_echo_ms: timings.turn_echo_ms,
+                        media_ms: Some(timings.media_ms),
+                        media_relayed: Some(timings.media_relayed),
+                        error: None,
+                    }
+                }
+                Err(error) => {
+                    warn!("Signaling probe failed: {}", error);
+                    CanaryRun {
+                        started_at,
+                        ok: false,
+                        join_ms: None,
+                        signaling_ms: None,
+                        turn: None,
+                        turn_allocate_ms: None,
+                        turn_echo_ms: None,
+                        media_ms: None,
+                        media_relayed: None,
+                        error: Some(error),
+                    }
+                }
+            };
+            self.record_canary_run(run.clone()).await;
+            run
+        }
+    }
+
+    /// Signal between two peers on `base`, echo through its TURN server,
+    /// then call from one peer to the other; only through TURN with
+    /// `relay_media`
+    async fn probe(base: &str, relay_media: bool) -> Result<Timings, String> {
+        let start = Instant::now();
+        let client = AxiVidClient::new(base);
+        let request = CreateRoomRequest {
+            metadata: [("canary".to_string(), "true".to_string())].into(),
+            ..Default::default()
+        };
+        let room = client.create_room(&request).await.map_err(fail)?;
+        let ice_servers = client.ice_servers().await.map_err(fail)?;
+        let mut caller = client.connect(&room.room_id).await.map_err(fail)?;
+        let mut callee = client.connect(&room.room_id).await.map_err(fail)?;
+        let mut timings = Timings {
+            join_ms: millis(start),
+            ..Default::default()
+        };
+
+        let allocation = match relay_server(&ice_servers) {
+            Some((url, username, credential)) => {
+                let relay_start = Instant::now();
+                let allocation = Allocation::new(&url, &username, &credential)
+                    .await
+                    .map_err(|e| format!("TURN {}: {}", url, e))?;
+                timings.turn_allocate_ms = Some(millis(relay_start));
+                timings.turn = Some(url);
+                Some(allocation)
+            }
+            None => None,
+        };
+        let relayed = allocation.as_ref().map(|a| a.relayed);
+        let negotiated = negotiate(&mut caller, &mut callee, relayed).await;
+        timings.signaling_ms = negotiated.map(|()| millis(start))?;
+
+        if let Some(allocation) = allocation {
+            let echo_start = Instant::now();
+            let echoed = allocation.echo().await;
+            allocation.release().await;
+            echoed.map_err(|e| format!("TURN relay: {}", e))?;
+            timings.turn_echo_ms = Some(millis(echo_start));
+        }
+
+        timings.media_relayed = relay_media && timings.turn.is_some();
+        let call_start = Instant::now();
+        let called = call(
+            &mut caller,
+            &mut callee,
+            &ice_servers,
+            timings.media_relayed,
+        )
+        .await;
+        caller.close().await;
+        callee.close().await;
+        timings.media_ms = called
+            .map(|()| millis(call_start))
+            .map_err(|e| format!("Loopback call: {}", e))?;
+        Ok(timings)
+    }
+
+    /// One side of the loopback call
+    struct Leg {
+        connection: Arc<RTCPeerConnection>,
+        /// Candidates that arrived before the other side's description
+        early: Vec<RTCIceCandidateInit>,
+    }
+
+    impl Leg {
+        /// A peer connection trickling its candidates to `to`
+        async fn new(
+            ice_servers: &[IceServer],
+            relay: bool,
+            sender: RoomSender,
+            to: &str,
+        ) -> Result<Self, String> {
+            let mut config = rtc::configuration(ice_servers);
+            if relay {
+                config.ice_transport_policy = RTCIceTransportPolicy::Relay;
+            }
+            let api = rtc::api().map_err(fail)?;
+            let connection = Arc::new(api.new_peer_connection(config).await.map_err(fail)?);
+            let to = to.to_string();
+            connection.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+                if let Some(msg) =
+                    candidate.and_then(|c| rtc::candidate_message(&c, Some(to.clone())))
+                {
+                    let _ = sender.send(msg);
+                }
+                Box::pin(async {})
+            }));
+            Ok(Self {
+                connection,
+                early: Vec::new(),
+            })
+        }
+
+        /// Take the other side's description, then the candidates that
+        /// came before it
+        async fn remote(&mut self, description: RTCSessionDescription) -> Result<(), String> {
+            self.connection
+                .set_remote_description(description)
+                .await
+                .map_err(fail)?;
+            for candidate in std::mem::take(&mut self.early) {
+                self.connection
+                    .add_ice_candidate(candidate)
+                    .await
+                    .map_err(fail)?;
+            }
+            Ok(())
+        }
+
+        /// Take a candidate from an `ice` message
+        async fn candidate(&mut self, msg: WsMessage) -> Result<(), String> {
+            let WsMessage::IceCandidate {
+                candidate,
+                sdp_mid,
+                sdp_m_line_index,
+                ..
+            } = msg
+            else {
+                return Ok(());
+            };
+            let init = rtc::candidate_init(candidate, sdp_mid, sdp_m_line_index);
+            if self.connection.remote_description().await.is_none() {
+                self.early.push(init);
+                return Ok(());
+            }
+            self.connection.add_ice_candidate(init).await.map_err(fail)
+        }
+    }
+
+    /// Call `callee` from `caller` with an audio track, until the callee
+    /// receives its first packet
+    async fn call(
+        caller: &mut RoomConnection,
+        callee: &mut RoomConnection,
+        ice_servers: &[IceServer],
+        relay: bool,
+    ) -> Result<(), String> {
+        let callee_id = callee.peer_id().to_string();
+        let caller_id = caller.peer_id().to_string();
+        let mut outgoing = Leg::new(ice_servers, relay, caller.sender(), &callee_id).await?;
+        let mut incoming = Leg::new(ice_servers, relay, callee.sender(), &caller_id).await?;
+
+        let track = Arc::new(TrackLocalStaticSample::new(
+            RTCRtpCodecCapability {
+                mime_type: MIME_TYPE_OPUS.to_string(),
+                clock_rate: 48000,
+                channels: 2,
+                ..Default::default()
+            },
+            "audio".to_string(),
+            "canary".to_string(),
+        ));
+        outgoing
+            .connection
+            .add_track(track.clone())
+            .await
+            .map_err(fail)?;
+        let (heard, mut hearing) = mpsc::channel(1);
+        incoming.connection.on_track(Box::new(move |remote, _, _| {
+            let heard = heard.clone();
+            Box::pin(async move {
+                if remote.read_rtp().await.is_ok() {
+                    let _ = heard.try_send(());
+                }
+            })
+        }));
+
+        let result = async {
+            let offer = outgoing.connection.create_offer(None).await.map_err(fail)?;
+            caller
+                .send(WsMessage::Offer {
+                    sdp: offer.sdp.clone(),
+                    peer_id: Some(callee_id.clone()),
+                })
+                .map_err(fail)?;
+            outgoing
+                .connection
+                .set_local_description(offer)
+                .await
+                .map_err(fail)?;
+            // Audio goes out from the moment a path is found
+            let mut frames = tokio::time::interval(FRAME);
+            loop {
+                tokio::select! {
+                    _ = hearing.recv() => return Ok(()),
+                    _ = frames.tick() => {
+                        let sample = Sample {
+                            data: Bytes::from_static(SILENCE),
+                            duration: FRAME,
+                            ..Default::default()
+                        };
+                        // Nothing is sent, without error, until connected
+                        track.write_sample(&sample).await.map_err(fail)?;
+                    }
+                    msg = callee.recv_message() => match msg {
+                        Some(WsMessage::Offer { sdp, .. }) => {
+                            let offer = RTCSessionDescription::offer(sdp).map_err(fail)?;
+                            incoming.remote(offer).await?;
+                            let answer = incoming
+                                .connection
+                                .create_answer(None)
+                                .await
+                                .map_err(fail)?;
+                            callee
+                                .send(WsMessage::Answer {
+                                    sdp: answer.sdp.clone(),
+                                    peer_id: Some(caller_id.clone()),
+                                })
+                                .map_err(fail)?;
+                            incoming
+                                .connection
+                                .set_local_description(answer)
+                                .await
+                                .map_err(fail)?;
+                        }
+                        Some(msg @ WsMessage::IceCandidate { .. }) => incoming.candidate(msg).await?,
+                        Some(WsMessage::Error { message, .. }) => return Err(message),
+                        Some(_) => {}
+                        None => return Err("Signaling connection closed".to_string()),
+                    },
+                    msg = caller.recv_message() => match msg {
+                        Some(WsMessage::Answer { sdp, .. }) => {
+                            let answer = RTCSessionDescription::answer(sdp).map_err(fail)?;
+                            outgoing.remote(answer).await?;
+                        }
+                        Some(msg @ WsMessage::IceCandidate { .. }) => outgoing.candidate(msg).await?,
+                        Some(WsMessage::Error { message, .. }) => return Err(message),
+                        Some(_) => {}
+                        None => return Err("Signaling connection closed".to_string()),
+                    },
+                }
+            }
+        }
+        .await;
+        let _ = outgoing.connection.close().await;
+        let _ = incoming.connection.close().await;
+        result
+    }
+
+    /// The first UDP TURN URL with credentials
+    fn relay_server(servers: &[IceServer]) -> Option<(String, String, String)> {
+        servers.iter().find_map(|server| {
+            let url = server
+                .urls
+                .iter()
+                .find(|url| url.starts_with("turn:") && !url.contains("transport=tcp"))?;
+            Some((
+                url.clone(),
+                server.username.clone()?,
+                server.credential.clone()?,
+            ))
+        })
+    }
+
+    /// Trade an offer, an answer and a candidate each way over signaling
+    async fn negotiate(
+        caller: &mut RoomConnection,
+        callee: &mut RoomConnection,
+        relayed: Option<SocketAddr>,
+    ) -> Result<(), String> {
+        let callee_id = callee.peer_id().to_string();
+        let caller_id = caller.peer_id().to_string();
+        caller
+            .send(WsMessage::Offer {
+                sdp: sdp("actpass"),
+                peer_id: Some(callee_id.clone()),
+            })
+            .map_err(fail)?;
+        caller
+            .send(candidate(relayed, 1, &callee_id))
+            .map_err(fail)?;
+        let (mut offer, mut candidate_in) = (false, false);
+        while !(offer && candidate_in) {
+            match callee.recv_message().await {
+                Some(WsMessage::Offer { .. }) => {
+                    offer = true;
+                    callee
+                        .send(WsMessage::Answer {
+                            sdp: sdp("active"),
+                            peer_id: Some(caller_id.clone()),
+                        })
+                        .map_err(fail)?;
+                    callee
+                        .send(candidate(relayed, 2, &caller_id))
+                        .map_err(fail)?;
+                }
+                Some(WsMessage::IceCandidate { .. }) => candidate_in = true,
+                Some(WsMessage::Error { message, .. }) => return Err(message),
+                Some(_) => {}
+                None => return Err("Signaling connection closed".to_string()),
+            }
+        }
+        let (mut answer, mut candidate_out) = (false, false);
+        while !(answer && candidate_out) {
+            match caller.recv_message().await {
+                Some(WsMessage::Answer { .. }) => answer = true,
+                Some(WsMessage::IceCandidate { .. }) => candidate_out = true,
+                Some(WsMessage::Error { message, .. }) => return Err(message),
+                Some(_) => {}
+                None => return Err("Signaling connection closed".to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Minimal SDP with the given DTLS role
+    fn sdp(setup: &str) -> String {
+        format!(
+            "v=0\r\no=- {} 2 IN IP4 127.0.0.1\r\ns=canary\r\nt=0 0\r\na=setup:{}\r\n",
+            unix_timestamp(),
+            setup
+        )
+    }
+
+    /// The relayed address as a candidate, or a documentation host
+    /// address without TURN
+    fn candidate(relayed: Option<SocketAddr>, foundation: u32, to: &str) -> WsMessage {
+        let candidate = match relayed {
+            Some(addr) => format!(
+                "candidate:{} 1 udp 16777215 {} {} typ relay raddr 0.0.0.0 rport 0",
+                foundation,
+                addr.ip(),
+                addr.port()
+            ),
+            None => format!(
+                "candidate:{} 1 udp 2122260223 192.0.2.{} 50000 typ host",
+                foundation, foundation
+            ),
+        };
+        WsMessage::IceCandidate {
+            candidate,
+            sdp_m_line_index: 0,
+            sdp_mid: Some("0".to_string()),
+            peer_id: Some(to.to_string()),
+        }
+    }
+
+    /// A TURN allocation, signed with long-term credentials
+    struct Allocation {
+        socket: UdpSocket,
+        server: SocketAddr,
+        relayed: SocketAddr,
+        username: String,
+        realm: Vec<u8>,
+        nonce: Vec<u8>,
+        key: Vec<u8>,
+    }
+
+    impl Allocation {
+        /// Allocate a relayed address, answering the server's challenge
+        async fn new(url: &str, username: &str, credential: &str) -> Result<Self, String> {
+            let (server, tcp) = turn_address(url).await?;
+            if tcp {
+                return Err("only UDP relays can be checked".to_string());
+            }
+            let socket = udp_socket_for(server).await?;
+            let allocate =
+                || Message::new(stun::ALLOCATE).with(stun::REQUESTED_TRANSPORT, [17, 0, 0, 0]);
+            let challenge = request(&socket, server, allocate(), None).await?;
+            if challenge.error_code() != Some(401) {
+                return Err("expected a 401 challenge".to_string());
+            }
+            let realm = challenge.get(stun::REALM).ok_or("no REALM")?.to_vec();
+            let nonce = challenge.get(stun::NONCE).ok_or("no NONCE")?.to_vec();
+            let key = Md5::digest(format!(
+                "{}:{}:{}",
+                username,
+                String::from_utf8_lossy(&realm),
+                credential
+            ))
+            .to_vec();
+            let mut allocation = Self {
+                socket,
+                server,
+                relayed: server,
+                username: username.to_string(),
+                realm,
+                nonce,
+                key,
+            };
+            let response = allocation.signed(allocate()).await?;
+            allocation.relayed = response
+                .address(stun::XOR_RELAYED_ADDRESS)
+                .ok_or("no XOR-RELAYED-ADDRESS")?;
+            Ok(allocation)
+        }
+
+        /// Send a request with credentials; errors on an error response
+        async fn signed(&self, message: Message) -> Result<Message, String> {
+            let message = message
+                .with(stun::USERNAME, self.username.as_bytes())
+                .with(stun::REALM, self.realm.clone())
+                .with(stun::NONCE, self.nonce.clone());
+            let response = request(&self.socket, self.server, message, Some(&self.key)).await?;
+            match response.error_code() {
+                Some(code) => Err(format!("error {}", code)),
+                None => Ok(response),
+            }
+        }
+
+        /// Send a message from a second socket through the relay, which
+        /// that socket echoes back
+        async fn echo(&self) -> Result<(), String> {
+            let peer = udp_socket_for(self.server).await?;
+            // The address the TURN server sees the peer socket at
+            let binding = request(&peer, self.server, Message::new(stun::BINDING), None).await?;
+            let peer_addr = binding
+                .address(stun::XOR_MAPPED_ADDRESS)
+                .ok_or("no XOR-MAPPED-ADDRESS")?;
+            self.signed(
+                Message::new(stun::CREATE_PERMISSION)
+                    .with_address(stun::XOR_PEER_ADDRESS, peer_addr),
+            )
+            .await?;
+            let send = Message::new(stun::SEND | stun::INDICATION)
+                .with_address(stun::XOR_PEER_ADDRESS, peer_addr)
+                .with(stun::DATA_ATTR, ECHO);
+            self.socket
+                .send_to(&send.encode(), self.server)
+                .await
+                .map_err(fail)?;
+
+            // The peer answers whoever relayed the message: the relayed
+            // address
+            let mut buf = [0u8; 1500];
+            let (len, from) = peer.recv_from(&mut buf).await.map_err(fail)?;
+            if &buf[..len] != ECHO {
+                return Err("the peer got something else".to_string());
+            }
+            peer.send_to(ECHO, from).await.map_err(fail)?;
+            loop {
+                let (len, _) = self.socket.recv_from(&mut buf).await.map_err(fail)?;
+                let Some(data) = Message::decode(&buf[..len]) else {
+                    continue;
+                };
+                if data.kind == stun::DATA | stun::INDICATION
+                    && data.get(stun::DATA_ATTR) == Some(ECHO)
+                {
+                    return Ok(());
+                }
+            }
+        }
+
+        /// Give the allocation back
+        async fn release(self) {
+            let refresh = Message::new(stun::REFRESH).with(stun::LIFETIME, [0, 0, 0, 0]);
+            let _ = self.signed(refresh).await;
+        }
+    }
+
+    /// Send a request to `server` and wait for its response
+    async fn request(
+        socket: &UdpSocket,
+        server: SocketAddr,
+        message: Message,
+        key: Option<&[u8]>,
+    ) -> Result<Message, String> {
+        let bytes = match key {
+            Some(key) => message.encode_signed(key),
+            None => message.encode(),
+        };
+        socket.send_to(&bytes, server).await.map_err(fail)?;
+        let mut buf = [0u8; 1500];
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await.map_err(fail)?;
+            match Message::decode(&buf[..len]) {
+                Some(response) if from == server && response.transaction == message.transaction => {
+                    return Ok(response);
+                }
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// Recent signaling and TURN probes
+#[utoipa::path(
+    get,
+    path = "/admin/canary",
+    tag = "Admin",
+    responses(
+        (status = 200, description = "Canary runs, newest first", body = [CanaryRun]),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_canary_runs(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+) -> Json<Vec<CanaryRun>> {
+    Json(
+        state
+            .canary_runs
+            .lock()
+            .await
+            .iter()
+            .rev()
+            .cloned()
+            .collect(),
+    )
+}
@@ -0,0 +1,267 @@
+//! Signaling capture
+//!
+//! With a `signaling_capture` section, every message of a room's
+//! signaling, in and out of each peer's socket, is appended to a JSON
+//! lines file in `dir`, one file per room session, named after the room
+//! and the time of its first join. A capture ends when the room closes.
+//! `axi-vid replay` feeds a capture back through a server (see
+//! [`crate::replay`]).
+//!
+//! Captures are anonymized as they are written: peer IDs become `peer-1`,
+//! `peer-2` and so on, the room ID becomes `room`, IP addresses in SDP
+//! and candidates are swapped for documentation addresses (a given address
+//! always maps to the same one), SDP usernames and ICE ufrags and
+//! passwords are redacted (see [`crate::scrub`]), tokens are dropped and
+//! chat text is replaced by its length.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::scrub::redact_credentials;
+use crate::state::{AppState, unix_timestamp};
+
+/// Where captures go and which rooms are captured
+#[derive(Debug, Clone, Deserialize)]
+pub struct CaptureConfig {
+    /// Directory the capture files are written to
+    pub dir: PathBuf,
+    /// Rooms to capture; every room when empty
+    #[serde(default)]
+    pub rooms: Vec<String>,
+}
+
+impl CaptureConfig {
+    pub fn captures(&self, room_id: &str) -> bool {
+        self.rooms.is_empty() || self.rooms.iter().any(|r| r == room_id)
+    }
+}
+
+/// What a capture entry records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureEvent {
+    /// The peer joined the room
+    Join,
+    /// The peer sent a message
+    In,
+    /// The peer was sent a message
+    Out,
+    /// The peer left the room
+    Leave,
+}
+
+/// One line of a capture file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureEntry {
+    /// Milliseconds since the capture started
+    pub at_ms: u64,
+    /// Alias of the peer, e.g. `peer-1`
+    pub peer: String,
+    pub event: CaptureEvent,
+    /// The message, anonymized; unset for joins and leaves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<Value>,
+}
+
+/// Keys whose values are dropped from captured messages
+const SECRET_KEYS: &[&str] = &["resume_token", "token"];
+
+/// A room's capture in progress
+#[derive(Debug)]
+pub struct RoomCapture {
+    room_id: String,
+    path: PathBuf,
+    started: Instant,
+    peers: HashMap<String, String>,
+    addresses: HashMap<IpAddr, IpAddr>,
+}
+
+impl RoomCapture {
+    fn new(dir: &std::path::Path, room_id: &str) -> Self {
+        // Room IDs are URL path segments, but keep the name a plain file
+        let name: String = room_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        Self {
+            room_id: room_id.to_string(),
+            path: dir.join(format!("{}-{}.jsonl", name, unix_timestamp())),
+            started: Instant::now(),
+            peers: HashMap::new(),
+            addresses: HashMap::new(),
+        }
+    }
+
+    /// Alias of a peer, assigned in order of appearance
+    fn alias(&mut self, peer_id: &str) -> String {
+        let next = self.peers.len() + 1;
+        self.peers
+            .entry(peer_id.to_string())
+            .or_insert_with(|| format!("peer-{}", next))
+            .clone()
+    }
+
+    /// Documentation address standing in for `ip`
+    fn mask_ip(&mut self, ip: IpAddr) -> IpAddr {
+        if ip.is_unspecified() || ip.is_loopback() {
+            return ip;
+        }
+        let next = self.addresses.len() as u32 + 1;
+        *self.addresses.entry(ip).or_insert_with(|| match ip {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(192, 0, 2, (next % 254) as u8)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, next as u16)),
+        })
+    }
+
+    /// Every IP address among the space-separated words of a line
+    fn mask_words(&mut self, line: &str) -> String {
+        let words: Vec<String> = line
+            .split(' ')
+            .map(|word| {
+                // SDP attributes glue the first word to the attribute name
+                let (prefix, word) = match word.rsplit_once(':') {
+                    Some((prefix, rest)) if word.parse::<IpAddr>().is_err() => {
+                        (format!("{}:", prefix), rest)
+                    }
+                    _ => (String::new(), word),
+                };
+                match word.parse::<IpAddr>() {
+                    Ok(ip) => format!("{}{}", prefix, self.mask_ip(ip)),
+                    Err(_) => format!("{}{}", prefix, word),
+                }
+            })
+            .collect();
+        words.join(" ")
+    }
+
+    /// Mask addresses and ICE credentials line by line, keeping SDP line
+    /// endings
+    fn mask_text(&mut self, text: &str) -> String {
+        text.split("\r\n")
+            .map(|line| self.mask_words(&redact_credentials(line)))
+            .collect::<Vec<_>>()
+            .join("\r\n")
+    }
+
+    /// Peer and room IDs swapped for aliases, also inside longer strings
+    /// such as URLs
+    fn mask_ids(&self, text: &str) -> String {
+        let mut text = text.replace(&self.room_id, "room");
+        for (peer_id, alias) in &self.peers {
+            if text.contains(peer_id.as_str()) {
+                text = text.replace(peer_id.as_str(), alias);
+            }
+        }
+        text
+    }
+
+    /// Anonymize a message in place
+    fn anonymize(&mut self, value: &mut Value, key: Option<&str>, chat: bool) {
+        match value {
+            Value::Object(map) => {
+                let chat = chat || map.get("type").and_then(Value::as_str) == Some("chat");
+                map.retain(|k, _| !SECRET_KEYS.contains(&k.as_str()));
+                for (k, v) in map.iter_mut() {
+                    self.anonymize(v, Some(k), chat);
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    self.anonymize(item, key, chat);
+                }
+            }
+            Value::String(s) => {
+                *s = match key {
+                    Some("text" | "message") if chat => format!("[{} chars]", s.chars().count()),
+                    Some("sdp" | "candidate") => self.mask_text(s),
+                    _ => self.mask_ids(s),
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// The entry for an event, anonymized
+    fn entry(&mut self, peer_id: &str, event: CaptureEvent, text: Option<&str>) -> CaptureEntry {
+        let peer = self.alias(peer_id);
+        let message = text.map(|text| {
+            let mut value =
+                serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string()));
+            self.anonymize(&mut value, None, false);
+            value
+        });
+        CaptureEntry {
+            at_ms: self.started.elapsed().as_millis() as u64,
+            peer,
+            event,
+            message,
+        }
+    }
+}
+
+impl AppState {
+    /// Whether a room's signaling is being captured
+    pub fn capturing(&self, room_id: &str) -> bool {
+        self.config()
+            .signaling_capture
+            .as_ref()
+            .is_some_and(|c| c.captures(room_id))
+    }
+
+    /// Append an event to a room's capture, starting one if needed
+    pub async fn capture(
+        &self,
+        room_id: &str,
+        peer_id: &str,
+        event: CaptureEvent,
+        text: Option<&str>,
+    ) {
+        let Some(config) = self.config().signaling_capture.clone() else {
+            return;
+        };
+        // Held while writing so lines keep their order
+        let mut captures = self.captures.lock().await;
+        let capture = captures
+            .entry(room_id.to_string())
+            .or_insert_with(|| RoomCapture::new(&config.dir, room_id));
+        let entry = capture.entry(peer_id, event, text);
+        let Ok(mut line) = serde_json::to_string(&entry) else {
+            return;
+        };
+        line.push('\n');
+        let written = async {
+            tokio::fs::create_dir_all(&config.dir).await?;
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&capture.path)
+                .await?;
+            file.write_all(line.as_bytes()).await
+        };
+        if let Err(e) = written.await {
+            warn!(
+                "Failed to write signaling capture {}: {}",
+                capture.path.display(),
+                e
+            );
+        }
+    }
+
+    /// Stop capturing a room; its next session starts a new file
+    pub async fn end_capture(&self, room_id: &str) {
+        self.captures.lock().await.remove(room_id);
+    }
+}
@@ -0,0 +1,329 @@
+//! Call detail records, end-of-call surveys and analytics
+//!
+//! A record is opened when a room is created and closed when the room is
+//! reaped. Clients may attach a quality survey to it at any time, and the
+//! admin analytics endpoint aggregates over all stored records.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::admin::AdminAuth;
+use crate::experiments::{ExperimentUnit, variant_stats};
+use crate::models::{
+    CallAnalytics, CallFeedback, CallRecord, CategoryCount, DisconnectRecord, FeedbackRequest,
+    LeaveReason, RoomMetadata, RoomMode, WebhookPayload,
+};
+use crate::state::{AppState, unix_timestamp};
+
+/// Number of call records kept in memory
+pub const MAX_CALL_RECORDS: usize = 10_000;
+
+/// Disconnects kept per call
+pub const MAX_CALL_DISCONNECTS: usize = 1000;
+
+/// Peers whose experiment variants are kept per call
+pub const MAX_CALL_PEER_EXPERIMENTS: usize = 1000;
+
+/// Surveys accepted per call
+pub const MAX_FEEDBACK_PER_CALL: usize = 200;
+
+/// Maximum length of a survey comment
+const MAX_FEEDBACK_COMMENT: usize = 2000;
+
+impl AppState {
+    /// Open a call record for a newly created room
+    pub async fn open_call_record(
+        &self,
+        room_id: &str,
+        mode: RoomMode,
+        metadata: RoomMetadata,
+        tenant: Option<String>,
+    ) {
+        let mut calls = self.calls.lock().await;
+        if calls.contains_key(room_id) {
+            return;
+        }
+        if calls.len() >= MAX_CALL_RECORDS {
+            // Evict the oldest record
+            let oldest = calls
+                .iter()
+                .min_by_key(|(_, c)| c.started_at)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                calls.remove(&oldest);
+            }
+        }
+
+        let experiments = self.config().variants(ExperimentUnit::Room, room_id);
+        calls.insert(
+            room_id.to_string(),
+            CallRecord {
+                room_id: room_id.to_string(),
+                mode,
+                started_at: unix_timestamp(),
+                ended_at: None,
+                total_joins: 0,
+                peak_peers: 0,
+                feedback: Vec::new(),
+                disconnects: Vec::new(),
+                average_mos: None,
+                min_mos: None,
+                directed: None,
+                metadata,
+                devices: Vec::new(),
+                experiments,
+                peer_experiments: BTreeMap::new(),
+                tenant,
+                expired: false,
+                mos_samples: 0,
+            },
+        );
+    }
+
+    /// Count a join against the call record, noting the peer's variants
+    pub async fn record_call_join(&self, room_id: &str, peer_id: &str, peer_count: usize) {
+        let variants = self.config().variants(ExperimentUnit::Peer, peer_id);
+        if let Some(call) = self.calls.lock().await.get_mut(room_id) {
+            call.total_joins += 1;
+            call.peak_peers = call.peak_peers.max(peer_count);
+            if !variants.is_empty() && call.peer_experiments.len() < MAX_CALL_PEER_EXPERIMENTS {
+                call.peer_experiments.insert(peer_id.to_string(), variants);
+            }
+        }
+    }
+
+    /// Note why a peer left the call
+    pub async fn record_call_disconnect(&self, room_id: &str, peer_id: &str, reason: LeaveReason) {
+        if let Some(call) = self.calls.lock().await.get_mut(room_id)
+            && call.disconnects.len() < MAX_CALL_DISCONNECTS
+        {
+            call.disconnects.push(DisconnectRecord {
+                peer_id: peer_id.to_string(),
+                reason,
+                at: unix_timestamp(),
+            });
+        }
+    }
+
+    /// Note that the call was cut off at its maximum duration
+    pub async fn record_call_expired(&self, room_id: &str) {
+        if let Some(call) = self.calls.lock().await.get_mut(room_id) {
+            call.expired = true;
+        }
+    }
+
+    /// Mark call records as ended
+    pub async fn close_call_records(&self, room_ids: &[String]) {
+        let now = unix_timestamp();
+        let mut calls = self.calls.lock().await;
+        let mut ended = Vec::new();
+        for room_id in room_ids {
+            if let Some(call) = calls.get_mut(room_id)
+                && call.ended_at.is_none()
+            {
+                call.ended_at = Some(now);
+                ended.push(Box::new(call.clone()));
+            }
+        }
+        drop(calls);
+        for call in ended {
+            let room_id = call.room_id.clone();
+            self.queue_webhook(&room_id, WebhookPayload::CallEnded { call })
+                .await;
+        }
+        // A directed call still in progress ends with its room
+        for room_id in room_ids {
+            self.end_directed_call(room_id).await;
+        }
+        self.persist_rooms(room_ids).await;
+    }
+
+    /// Fold a MOS sample into the call record
+    pub async fn record_call_mos(&self, room_id: &str, mos: f64) {
+        if let Some(call) = self.calls.lock().await.get_mut(room_id) {
+            let n = call.mos_samples as f64;
+            call.average_mos = Some((call.average_mos.unwrap_or(0.0) * n + mos) / (n + 1.0));
+            call.min_mos = Some(call.min_mos.map_or(mos, |m| m.min(mos)));
+            call.mos_samples += 1;
+        }
+    }
+
+    /// Attach a survey to a call record
+    pub async fn submit_feedback(
+        &self,
+        room_id: &str,
+        survey: FeedbackRequest,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        let mut calls = self.calls.lock().await;
+        let call = calls
+            .get_mut(room_id)
+            .ok_or((StatusCode::NOT_FOUND, "No call record for this room"))?;
+        if call.feedback.len() >= MAX_FEEDBACK_PER_CALL {
+            return Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too much feedback for this call",
+            ));
+        }
+
+        call.feedback.push(CallFeedback {
+            submitted_at: unix_timestamp(),
+            survey,
+        });
+        drop(calls);
+        self.persist_rooms(&[room_id.to_string()]).await;
+        Ok(())
+    }
+
+    /// List call records, newest first
+    pub async fn list_call_records(&self, room_id: Option<&str>) -> Vec<CallRecord> {
+        let calls = self.calls.lock().await;
+        let mut records: Vec<CallRecord> = calls
+            .values()
+            .filter(|c| room_id.is_none() || Some(c.room_id.as_str()) == room_id)
+            .cloned()
+            .collect();
+        records.sort_by_key(|c| Reverse(c.started_at));
+        records
+    }
+
+    /// Aggregate durations and survey results over all call records
+    pub async fn call_analytics(&self) -> CallAnalytics {
+        let calls = self.calls.lock().await;
+
+        let durations: Vec<u64> = calls
+            .values()
+            .filter_map(CallRecord::duration_secs)
+            .collect();
+        let ratings: Vec<u8> = calls
+            .values()
+            .flat_map(|c| c.feedback.iter().map(|f| f.survey.rating))
+            .collect();
+        let mut categories = BTreeMap::new();
+        for feedback in calls.values().flat_map(|c| &c.feedback) {
+            for category in &feedback.survey.categories {
+                *categories.entry(*category).or_insert(0) += 1;
+            }
+        }
+        let mut complaint_categories: Vec<CategoryCount> = categories
+            .into_iter()
+            .map(|(category, count)| CategoryCount { category, count })
+            .collect();
+        complaint_categories.sort_by_key(|c| Reverse(c.count));
+
+        let call_mos: Vec<f64> = calls.values().filter_map(|c| c.average_mos).collect();
+        let average_mos =
+            (!call_mos.is_empty()).then(|| call_mos.iter().sum::<f64>() / call_mos.len() as f64);
+
+        CallAnalytics {
+            total_calls: calls.len(),
+            ended_calls: durations.len(),
+            average_duration_secs: mean(durations.iter().copied()),
+            feedback_count: ratings.len(),
+            average_rating: mean(ratings.iter().map(|&r| u64::from(r))),
+            complaint_categories,
+            average_mos,
+            experiments: variant_stats(calls.values()),
+        }
+    }
+}
+
+fn mean(values: impl ExactSizeIterator<Item = u64>) -> Option<f64> {
+    let n = values.len();
+    (n > 0).then(|| values.sum::<u64>() as f64 / n as f64)
+}
+
+/// Submit an end-of-call quality survey
+#[utoipa::path(
+    post,
+    path = "/api/room/{room_id}/feedback",
+    tag = "Analytics",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room")
+    ),
+    request_body = FeedbackRequest,
+    responses(
+        (status = 201, description = "Feedback stored"),
+        (status = 400, description = "Invalid rating or comment", body = String),
+        (status = 404, description = "No call record for this room", body = String)
+    )
+)]
+pub async fn submit_feedback(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+    Json(survey): Json<FeedbackRequest>,
+) -> Response {
+    if !(1..=5).contains(&survey.rating) {
+        return (StatusCode::BAD_REQUEST, "Rating must be between 1 and 5").into_response();
+    }
+    if survey
+        .comment
+        .as_ref()
+        .is_some_and(|c| c.len() > MAX_FEEDBACK_COMMENT)
+    {
+        return (StatusCode::BAD_REQUEST, "Comment too long").into_response();
+    }
+
+    let rating = survey.rating;
+    match state.submit_feedback(&room_id, survey).await {
+        Ok(()) => {
+            info!("Feedback for room {}: rating {}", room_id, rating);
+            StatusCode::CREATED.into_response()
+        }
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Query parameters for the call record listing
+#[derive(Debug, Deserialize)]
+pub struct CallQuery {
+    pub room_id: Option<String>,
+}
+
+/// List call detail records
+#[utoipa::path(
+    get,
+    path = "/admin/calls",
+    tag = "Admin",
+    params(
+        ("room_id" = Option<String>, Query, description = "Only the record for this room")
+    ),
+    responses(
+        (status = 200, description = "Call records, newest first", body = Vec<CallRecord>),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_calls(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+    Query(query): Query<CallQuery>,
+) -> Json<Vec<CallRecord>> {
+    Json(state.list_call_records(query.room_id.as_deref()).await)
+}
+
+/// Aggregate call durations and survey results
+#[utoipa::path(
+    get,
+    path = "/admin/analytics",
+    tag = "Admin",
+    responses(
+        (status = 200, description = "Aggregated call analytics", body = CallAnalytics),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn call_analytics(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+) -> Json<CallAnalytics> {
+    Json(state.call_analytics().await)
+}
@@ -0,0 +1,240 @@
+//! Room cleanup policies and schedule
+//!
+//! Empty rooms are reaped after a timeout that depends on whether anyone
+//! ever joined, and the timeouts can be overridden per room mode. The sweep
+//! runs on a fixed interval or, if configured, on a cron expression, as the
+//! first step of the retention sweep (see [`crate::retention`]). Reaps are
+//! counted by reason for the admin API. Reaped rooms move to the archive.
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::str::FromStr;
+use std::time::Duration;
+
+use axum::{Json, extract::State};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::admin::AdminAuth;
+use crate::archive::ArchivePolicy;
+use crate::models::{CleanupStats, LeaveReason, ReapReason, RoomMode, TimelineKind, WsMessage};
+use crate::retention::RetentionConfig;
+use crate::state::{AppState, Room, unix_timestamp};
+
+/// Default seconds an empty room is kept
+pub const DEFAULT_ROOM_TIMEOUT_SECS: u64 = 300;
+
+/// Timeouts applied to empty rooms
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct CleanupPolicy {
+    /// Seconds to keep a room nobody has joined yet
+    pub never_joined_timeout_secs: u64,
+    /// Seconds to keep a room after its last peer left
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for CleanupPolicy {
+    fn default() -> Self {
+        Self {
+            never_joined_timeout_secs: DEFAULT_ROOM_TIMEOUT_SECS,
+            idle_timeout_secs: DEFAULT_ROOM_TIMEOUT_SECS,
+        }
+    }
+}
+
+/// When and how rooms are reaped
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CleanupConfig {
+    /// Seconds between sweeps, unless `schedule` is set
+    pub interval_secs: u64,
+    /// Cron expression with seconds, e.g. `"0 */5 * * * *"`; overrides `interval_secs`
+    pub schedule: Option<String>,
+    /// Policy for rooms without a per-mode override
+    pub default: CleanupPolicy,
+    /// Per-mode overrides, keyed by `interactive` / `broadcast`
+    pub modes: HashMap<RoomMode, CleanupPolicy>,
+    /// Retention of reaped rooms in the archive
+    pub archive: ArchivePolicy,
+    /// Retention of chat, call records, recordings and client errors
+    pub retention: RetentionConfig,
+}
+
+impl Default for CleanupConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 60,
+            schedule: None,
+            default: CleanupPolicy::default(),
+            modes: HashMap::new(),
+            archive: ArchivePolicy::default(),
+            retention: RetentionConfig::default(),
+        }
+    }
+}
+
+impl CleanupConfig {
+    /// Policy applying to rooms of `mode`
+    pub fn policy_for(&self, mode: RoomMode) -> &CleanupPolicy {
+        self.modes.get(&mode).unwrap_or(&self.default)
+    }
+
+    /// Check the config for errors
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(expr) = &self.schedule {
+            cron::Schedule::from_str(expr)
+                .map_err(|e| format!("Invalid cleanup schedule {:?}: {}", expr, e))?;
+        }
+        if self.schedule.is_none() && self.interval_secs == 0 {
+            return Err("cleanup.interval_secs must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Room {
+    /// Why this room should be reaped under `policy`, if at all
+    pub fn reap_reason(&self, policy: &CleanupPolicy) -> Option<ReapReason> {
+        // Personal rooms stay up for their owners, and event rooms until
+        // their first join
+        if self.owner.is_some() || (self.settings.event.is_some() && self.started_at.is_none()) {
+            return None;
+        }
+        if self.peers.is_empty() && self.reserved_slots() == 0 {
+            let (timeout, reason) = match self.started_at {
+                None => (policy.never_joined_timeout_secs, ReapReason::NeverJoined),
+                Some(_) => (policy.idle_timeout_secs, ReapReason::Idle),
+            };
+            if self.last_activity.elapsed() > Duration::from_secs(timeout) {
+                return Some(reason);
+            }
+        }
+        self.is_expired().then_some(ReapReason::Expired)
+    }
+}
+
+impl AppState {
+    /// Reap rooms according to the configured policies
+    pub async fn cleanup_inactive_rooms(&self) {
+        let config = self.config();
+        let mut closed = Vec::new();
+        let mut expired = Vec::new();
+
+        for (id, handle) in self.room_handles().await {
+            let (config, room_id) = (config.clone(), id.clone());
+            let reaped = handle.call_until(move |room| {
+                let policy = config.cleanup.policy_for(room.mode());
+                let Some(reason) = room.reap_reason(policy) else {
+                    return ControlFlow::Continue(None);
+                };
+                let mut peer_ids = Vec::new();
+                if reason == ReapReason::Expired {
+                    let max_duration_secs = room.settings.max_duration_secs.unwrap_or_default();
+                    // Sent directly, so it cannot trail the room's close
+                    for peer in &room.peers {
+                        room.send_to(&peer.id, WsMessage::RoomExpired { max_duration_secs });
+                    }
+                    room.end_call_for_all(&room_id, config.post_call_page);
+                    peer_ids = room.peers.iter().map(|p| p.id.clone()).collect();
+                    for peer_id in &peer_ids {
+                        room.leave(peer_id, LeaveReason::RoomExpired);
+                    }
+                }
+                ControlFlow::Break(Some((room.settings.clone(), reason, peer_ids)))
+            });
+            let Some(Some((settings, reason, peer_ids))) = reaped.await else {
+                continue;
+            };
+            self.forget_room(&id, &handle).await;
+            match reason {
+                ReapReason::NeverJoined => info!("Cleaning up unused room: {}", id),
+                ReapReason::Idle => info!("Cleaning up inactive room: {}", id),
+                ReapReason::Expired => {
+                    info!("Closing room {} after reaching its maximum duration", id)
+                }
+            }
+            expired.extend(peer_ids.into_iter().map(|peer_id| (id.clone(), peer_id)));
+            closed.push((id, settings, reason));
+        }
+
+        let mut stats = self.cleanup_stats.lock().await;
+        stats.runs += 1;
+        stats.last_run = Some(unix_timestamp());
+        for (_, _, reason) in &closed {
+            match reason {
+                ReapReason::NeverJoined => stats.never_joined += 1,
+                ReapReason::Idle => stats.idle += 1,
+                ReapReason::Expired => stats.expired += 1,
+            }
+        }
+        drop(stats);
+
+        for (room_id, peer_id) in expired {
+            self.record_disconnect(&room_id, &peer_id, LeaveReason::RoomExpired)
+                .await;
+        }
+        if !closed.is_empty() {
+            info!("Cleaned up {} inactive rooms", closed.len());
+            let ids: Vec<String> = closed.iter().map(|(id, _, _)| id.clone()).collect();
+            for (id, _, reason) in &closed {
+                self.record_timeline(id, TimelineKind::Closed, None, format!("{:?}", reason))
+                    .await;
+                if *reason == ReapReason::Expired {
+                    self.record_call_expired(id).await;
+                }
+            }
+            // Last, as it stores the finished records
+            self.close_call_records(&ids).await;
+            self.forget_room_states(&ids).await;
+            self.archive_rooms(closed).await;
+        }
+    }
+}
+
+/// Spawn background task running the retention sweep, room cleanup
+/// included
+pub fn spawn_cleanup_task(state: AppState) {
+    let cleanup = state.config().cleanup.clone();
+    let schedule = cleanup
+        .schedule
+        .as_deref()
+        .and_then(|expr| cron::Schedule::from_str(expr).ok());
+
+    tokio::spawn(async move {
+        match schedule {
+            Some(schedule) => loop {
+                let Some(next) = schedule.upcoming(chrono::Utc).next() else {
+                    warn!("Cleanup schedule has no upcoming runs; stopping cleanup");
+                    return;
+                };
+                let wait = (next - chrono::Utc::now()).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+                state.run_retention().await;
+            },
+            None => {
+                let mut interval =
+                    tokio::time::interval(Duration::from_secs(cleanup.interval_secs));
+                loop {
+                    interval.tick().await;
+                    state.run_retention().await;
+                }
+            }
+        }
+    });
+}
+
+/// Room reap counters
+#[utoipa::path(
+    get,
+    path = "/admin/cleanup",
+    tag = "Admin",
+    responses(
+        (status = 200, description = "Reaped rooms by reason", body = CleanupStats),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn cleanup_stats(_auth: AdminAuth, State(state): State<AppState>) -> Json<CleanupStats> {
+    Json(state.cleanup_stats.lock().await.clone())
+}
@@ -0,0 +1,269 @@
+//! Command line interface
+//!
+//! `serve` (the default) runs the server; the other subcommands are
+//! operator tools that either work offline from the config file or talk to
+//! a running instance's admin API.
+
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use reqwest::StatusCode;
+use uuid::Uuid;
+
+use crate::admin::AdminRole;
+use crate::config::{CONFIG_ENV, Config};
+use crate::models::RoomStatus;
+use crate::personal_rooms::PersonalRoom;
+use crate::replay::ReplayArgs;
+use crate::simulate::SimulateArgs;
+use crate::token::{self, Claims, TokenScope};
+
+/// A simple 1:1 video chat server
+#[derive(Debug, Parser)]
+#[command(name = "axi-vid", version)]
+pub struct Cli {
+    /// JSON config file
+    #[arg(long, global = true, env = CONFIG_ENV)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the server (the default)
+    Serve,
+    /// Validate the configuration and exit
+    CheckConfig,
+    /// Mint a signed token with the configured `jwt_secret`
+    GenerateToken {
+        #[command(subcommand)]
+        kind: TokenKind,
+    },
+    /// Inspect or close rooms on a running server
+    Rooms {
+        #[command(flatten)]
+        remote: Remote,
+        #[command(subcommand)]
+        action: RoomsAction,
+    },
+    /// Load-test a running server with synthetic clients
+    Simulate(SimulateArgs),
+    /// Play a signaling capture against a running server
+    Replay(ReplayArgs),
+    /// Record a room's media as a hidden peer
+    #[cfg(feature = "recorder")]
+    Record(crate::recorder::RecordArgs),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TokenKind {
+    /// Token for joining one room
+    Room {
+        room_id: String,
+        #[command(flatten)]
+        args: TokenArgs,
+    },
+    /// Token for the admin API
+    Admin {
+        /// What the token may do
+        #[arg(long, value_enum, default_value_t = AdminRole::Owner)]
+        role: AdminRole,
+        #[command(flatten)]
+        args: TokenArgs,
+    },
+    /// Token for recording one room as a hidden peer
+    Recorder {
+        room_id: String,
+        #[command(flatten)]
+        args: TokenArgs,
+    },
+    /// Token for the personal room of `--sub`
+    User {
+        #[command(flatten)]
+        args: TokenArgs,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct TokenArgs {
+    /// Lifetime in seconds
+    #[arg(long, default_value_t = 24 * 60 * 60)]
+    pub ttl: u64,
+    /// Who the token is issued to
+    #[arg(long)]
+    pub sub: Option<String>,
+    /// Email address of the holder, for invite-only rooms
+    #[arg(long)]
+    pub email: Option<String>,
+}
+
+/// Where to reach the admin API
+#[derive(Debug, Args)]
+pub struct Remote {
+    /// Base URL of the server (defaults to `public_url`)
+    #[arg(long, global = true)]
+    pub url: Option<String>,
+    /// Admin bearer token (defaults to `admin_token`, or a JWT minted from
+    /// `jwt_secret`)
+    #[arg(
+        long,
+        global = true,
+        env = "AXI_VID_ADMIN_TOKEN",
+        hide_env_values = true
+    )]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RoomsAction {
+    /// List active rooms
+    List,
+    /// Close a room, disconnecting everyone in it
+    Close { room_id: String },
+}
+
+/// Run a non-`serve` command
+pub async fn run(command: Command, config: Config) -> Result<(), String> {
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::CheckConfig => check_config(&config),
+        Command::GenerateToken { kind } => generate_token(&config, kind),
+        Command::Rooms { remote, action } => rooms(&config, remote, action).await,
+        Command::Simulate(args) => crate::simulate::run(&config, args).await,
+        Command::Replay(args) => crate::replay::run(&config, args).await,
+        #[cfg(feature = "recorder")]
+        Command::Record(args) => crate::recorder::run(&config, args).await,
+    }
+}
+
+/// Checks beyond parsing: TLS material must load
+fn check_config(config: &Config) -> Result<(), String> {
+    for listener in config.listeners() {
+        if let Some(tls) = &listener.tls {
+            crate::listener::tls_acceptor(tls).map_err(|e| format!("Invalid TLS setup: {}", e))?;
+        }
+    }
+    match &config.source {
+        Some(path) => println!("{}: OK", path.display()),
+        None => println!("No config file given; defaults are valid"),
+    }
+    if !config.admin_enabled() {
+        println!("Note: the admin API is disabled (no admin_token, admin_keys or jwt_secret)");
+    }
+    Ok(())
+}
+
+fn generate_token(config: &Config, kind: TokenKind) -> Result<(), String> {
+    let secret = config
+        .jwt_secret
+        .as_deref()
+        .ok_or("jwt_secret is not set in the configuration")?;
+
+    let (claims, room_id) = match kind {
+        TokenKind::Room { room_id, args } => {
+            if Uuid::parse_str(&room_id).is_err() {
+                return Err(format!("Invalid room ID: {}", room_id));
+            }
+            let mut claims = Claims::new(TokenScope::Room, Some(room_id.clone()), args.ttl);
+            claims.sub = args.sub;
+            claims.email = args.email;
+            (claims, Some(room_id))
+        }
+        TokenKind::Admin { role, args } => {
+            let mut claims = Claims::new(TokenScope::Admin, None, args.ttl);
+            claims.sub = args.sub;
+            claims.email = args.email;
+            claims.role = Some(role);
+            (claims, None)
+        }
+        TokenKind::Recorder { room_id, args } => {
+            if Uuid::parse_str(&room_id).is_err() {
+                return Err(format!("Invalid room ID: {}", room_id));
+            }
+            let mut claims = Claims::new(TokenScope::Recorder, Some(room_id), args.ttl);
+            claims.sub = args.sub;
+            claims.email = args.email;
+            (claims, None)
+        }
+        TokenKind::User { args } => {
+            let sub = args.sub.ok_or("User tokens need --sub")?;
+            let (room_id, _) = PersonalRoom::of(secret, &sub);
+            let mut claims = Claims::new(TokenScope::User, None, args.ttl);
+            claims.sub = Some(sub);
+            claims.email = args.email;
+            (claims, Some(room_id))
+        }
+    };
+
+    let token = token::mint(secret, &claims);
+    println!("{}", token);
+    if let Some(room_id) = room_id {
+        let base = config.public_url.trim_end_matches('/');
+        eprintln!("Join link: {}/room/{}?token={}", base, room_id, token);
+    }
+    Ok(())
+}
+
+async fn rooms(config: &Config, remote: Remote, action: RoomsAction) -> Result<(), String> {
+    let base = remote.url.unwrap_or_else(|| config.public_url.clone());
+    let base = base.trim_end_matches('/');
+    let bearer = match (remote.token, &config.admin_token, &config.jwt_secret) {
+        (Some(token), _, _) => token,
+        (None, Some(token), _) => token.clone(),
+        (None, None, Some(secret)) => {
+            token::mint(secret, &Claims::new(TokenScope::Admin, None, 60))
+        }
+        (None, None, None) => return Err("No admin token given or configured".to_string()),
+    };
+    let http = reqwest::Client::new();
+
+    match action {
+        RoomsAction::List => {
+            let response = http
+                .get(format!("{}/admin/rooms", base))
+                .bearer_auth(&bearer)
+                .send()
+                .await
+                .map_err(|e| format!("Request failed: {}", e))?;
+            let response = check_status(response).await?;
+            let rooms: Vec<RoomStatus> = response
+                .json()
+                .await
+                .map_err(|e| format!("Unexpected response: {}", e))?;
+
+            println!("{:<36}  {:<11}  {:>5}  AVAILABLE", "ROOM", "MODE", "PEERS");
+            for room in rooms {
+                let mode = format!("{:?}", room.mode).to_lowercase();
+                println!(
+                    "{:<36}  {:<11}  {:>5}  {}",
+                    room.room_id, mode, room.peer_count, room.available
+                );
+            }
+        }
+        RoomsAction::Close { room_id } => {
+            let response = http
+                .delete(format!("{}/admin/rooms/{}", base, room_id))
+                .bearer_auth(&bearer)
+                .send()
+                .await
+                .map_err(|e| format!("Request failed: {}", e))?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Err(format!("Room {} not found", room_id));
+            }
+            check_status(response).await?;
+            println!("Closed room {}", room_id);
+        }
+    }
+    Ok(())
+}
+
+/// Turn a non-2xx response into an error carrying the server's message
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, String> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(format!("Server returned {}: {}", status, body))
+}
@@ -0,0 +1,501 @@
+//! Async client for bots, tests and headless peers
+//!
+//! Enabled by the `client` feature. [`AxiVidClient`] wraps the HTTP API and
+//! opens a [`RoomConnection`] per room. A background task keeps each
+//! connection alive with WebSocket pings and, if the link drops, rejoins
+//! with the peer's resume token so the peer keeps its ID and slot.
+
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use reqwest::StatusCode;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{debug, warn};
+
+use crate::config::IceServer;
+use crate::models::{
+    CreateRoomRequest, CreateRoomResponse, LeaveReason, Recording, RoomStatus, WsMessage,
+};
+
+/// Longest wait between reconnect attempts
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Connection tuning
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// Interval between WebSocket pings
+    pub heartbeat_interval: Duration,
+    /// The link counts as lost after this long without any frame
+    pub heartbeat_timeout: Duration,
+    /// Reconnect attempts after a drop; 0 disables reconnection
+    pub max_reconnect_attempts: u32,
+    /// Delay before the first reconnect attempt, doubled for each retry
+    pub reconnect_delay: Duration,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_secs(15),
+            heartbeat_timeout: Duration::from_secs(45),
+            max_reconnect_attempts: 5,
+            reconnect_delay: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Errors returned by [`AxiVidClient`] and [`RoomConnection`]
+#[derive(Debug)]
+pub enum ClientError {
+    /// The HTTP request failed
+    Http(reqwest::Error),
+    /// The server answered with a non-2xx status
+    Status(StatusCode, String),
+    /// The WebSocket could not be opened
+    WebSocket(tungstenite::Error),
+    /// The server refused the join (full room, bad token, ...)
+    Rejected(String),
+    /// The connection has ended
+    Closed,
+    /// Setting up or recording media failed
+    Media(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Http(e) => write!(f, "Request failed: {}", e),
+            ClientError::Status(status, body) => write!(f, "Server returned {}: {}", status, body),
+            ClientError::WebSocket(e) => write!(f, "WebSocket error: {}", e),
+            ClientError::Rejected(reason) => write!(f, "Join rejected: {}", reason),
+            ClientError::Closed => write!(f, "Connection closed"),
+            ClientError::Media(e) => write!(f, "Media error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+/// Handle to a server's HTTP and WebSocket API
+#[derive(Debug, Clone)]
+pub struct AxiVidClient {
+    base: String,
+    http: reqwest::Client,
+    options: ClientOptions,
+}
+
+impl AxiVidClient {
+    /// Client for the server at `base_url`, e.g. `https://video.example.com`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        let base: String = base_url.into();
+        Self {
+            base: base.trim_end_matches('/').to_string(),
+            http: reqwest::Client::new(),
+            options: ClientOptions::default(),
+        }
+    }
+
+    /// Replace the heartbeat and reconnect settings
+    pub fn with_options(mut self, options: ClientOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Create a room
+    pub async fn create_room(
+        &self,
+        request: &CreateRoomRequest,
+    ) -> Result<CreateRoomResponse, ClientError> {
+        let response = self
+            .http
+            .post(format!("{}/api/create-room", self.base))
+            .json(request)
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Occupancy of a room
+    pub async fn room_status(&self, room_id: &str) -> Result<RoomStatus, ClientError> {
+        let response = self
+            .http
+            .get(format!("{}/api/room/{}/status", self.base, room_id))
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// STUN/TURN servers to use for peer connections
+    pub async fn ice_servers(&self) -> Result<Vec<IceServer>, ClientError> {
+        let response = self
+            .http
+            .get(format!("{}/api/ice-servers", self.base))
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Upload a recorded file to the room's recordings with a recorder token
+    pub async fn upload_recording(
+        &self,
+        room_id: &str,
+        token: &str,
+        path: &Path,
+        content_type: &str,
+    ) -> Result<Recording, ClientError> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| ClientError::Media(format!("Cannot read {}: {}", path.display(), e)))?;
+        let response = self
+            .http
+            .post(format!("{}/api/room/{}/recordings", self.base, room_id))
+            .bearer_auth(token)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(file)
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Join `room_id` as a new peer
+    pub async fn connect(&self, room_id: &str) -> Result<RoomConnection, ClientError> {
+        self.connect_with_token(room_id, None).await
+    }
+
+    /// Join `room_id` presenting a room, resume or re-invite token
+    pub async fn connect_with_token(
+        &self,
+        room_id: &str,
+        token: Option<&str>,
+    ) -> Result<RoomConnection, ClientError> {
+        let ws_base = match self.base.split_once("://") {
+            Some(("https", rest)) => format!("wss://{}", rest),
+            Some((_, rest)) => format!("ws://{}", rest),
+            None => format!("ws://{}", self.base),
+        };
+        let url = format!("{}/ws/{}", ws_base, room_id);
+        let (socket, welcome) = open(&url, token).await?;
+        let WsMessage::Welcome {
+            peer_id,
+            resume_token,
+            ..
+        } = &welcome
+        else {
+            unreachable!("open only returns a welcome");
+        };
+
+        let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let session = Session {
+            url,
+            peer_id: peer_id.clone(),
+            resume_token: resume_token.clone(),
+            options: self.options.clone(),
+            commands: commands_rx,
+            events: events_tx,
+            pending: Vec::new(),
+        };
+        Ok(RoomConnection {
+            peer_id: peer_id.clone(),
+            welcome,
+            commands: commands_tx,
+            events: events_rx,
+            task: tokio::spawn(session.run(socket)),
+        })
+    }
+}
+
+/// What a [`RoomConnection`] delivers
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// A message from the server or the other peer
+    Message(WsMessage),
+    /// The link dropped; a reconnect attempt is about to start
+    Reconnecting { attempt: u32 },
+    /// The peer rejoined its room with the same ID
+    Reconnected,
+}
+
+enum Command {
+    Send(WsMessage),
+    Close,
+}
+
+/// A peer's membership in one room
+///
+/// Messages sent while reconnecting are queued and delivered once the link
+/// is back. Dropping the connection closes it like [`RoomConnection::close`]
+/// without waiting.
+pub struct RoomConnection {
+    peer_id: String,
+    welcome: WsMessage,
+    commands: mpsc::UnboundedSender<Command>,
+    events: mpsc::UnboundedReceiver<ClientEvent>,
+    task: JoinHandle<()>,
+}
+
+impl RoomConnection {
+    /// This peer's ID, kept across reconnects
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    /// The `Welcome` the server sent on the first join
+    pub fn welcome(&self) -> &WsMessage {
+        &self.welcome
+    }
+
+    /// Queue a message for the server
+    pub fn send(&self, msg: WsMessage) -> Result<(), ClientError> {
+        self.sender().send(msg)
+    }
+
+    /// A handle for sending from other tasks; the connection stays open
+    /// while any handle is alive
+    pub fn sender(&self) -> RoomSender {
+        RoomSender(self.commands.clone())
+    }
+
+    /// Next event, or `None` once the connection has ended for good
+    pub async fn recv(&mut self) -> Option<ClientEvent> {
+        self.events.recv().await
+    }
+
+    /// Next message, skipping reconnect notices
+    pub async fn recv_message(&mut self) -> Option<WsMessage> {
+        loop {
+            if let ClientEvent::Message(msg) = self.recv().await? {
+                return Some(msg);
+            }
+        }
+    }
+
+    /// Leave the room with a hang-up reason and close the socket
+    pub async fn close(self) {
+        let _ = self.commands.send(Command::Close);
+        let _ = self.task.await;
+    }
+}
+
+/// Clonable sending half of a [`RoomConnection`]
+#[derive(Clone)]
+pub struct RoomSender(mpsc::UnboundedSender<Command>);
+
+impl RoomSender {
+    /// Queue a message for the server
+    pub fn send(&self, msg: WsMessage) -> Result<(), ClientError> {
+        self.0
+            .send(Command::Send(msg))
+            .map_err(|_| ClientError::Closed)
+    }
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Why a connected session stopped
+enum Ended {
+    /// Closed locally or by the server
+    Closed,
+    /// The link failed; worth reconnecting
+    Lost(String),
+}
+
+/// Background state of a [`RoomConnection`]
+struct Session {
+    url: String,
+    peer_id: String,
+    resume_token: String,
+    options: ClientOptions,
+    commands: mpsc::UnboundedReceiver<Command>,
+    events: mpsc::UnboundedSender<ClientEvent>,
+    /// Messages queued while reconnecting
+    pending: Vec<WsMessage>,
+}
+
+impl Session {
+    async fn run(mut self, mut socket: Socket) {
+        loop {
+            let reason = match self.pump(&mut socket).await {
+                Ended::Closed => return,
+                Ended::Lost(reason) => reason,
+            };
+            warn!("Lost connection to {}: {}", self.url, reason);
+            match self.reconnect().await {
+                Some(resumed) => socket = resumed,
+                None => return,
+            }
+        }
+    }
+
+    /// Relay between the socket and the handle until the link ends
+    async fn pump(&mut self, socket: &mut Socket) -> Ended {
+        for msg in std::mem::take(&mut self.pending) {
+            if let Err(e) = send(socket, &msg).await {
+                return Ended::Lost(e.to_string());
+            }
+        }
+
+        let mut heartbeat = tokio::time::interval(self.options.heartbeat_interval);
+        heartbeat.tick().await;
+        let mut last_seen = Instant::now();
+        loop {
+            tokio::select! {
+                command = self.commands.recv() => match command {
+                    Some(Command::Send(msg)) => {
+                        if let Err(e) = send(socket, &msg).await {
+                            self.pending.push(msg);
+                            return Ended::Lost(e.to_string());
+                        }
+                    }
+                    Some(Command::Close) | None => {
+                        let leave = WsMessage::Leave {
+                            peer_id: None,
+                            reason: Some(LeaveReason::UserHangup),
+                        };
+                        let _ = send(socket, &leave).await;
+                        let _ = socket.close(None).await;
+                        return Ended::Closed;
+                    }
+                },
+                frame = socket.next() => {
+                    last_seen = Instant::now();
+                    match frame {
+                        Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                            Ok(msg) => {
+                                if let WsMessage::Welcome { resume_token, .. } = &msg {
+                                    self.resume_token = resume_token.clone();
+                                }
+                                let _ = self.events.send(ClientEvent::Message(msg));
+                            }
+                            Err(e) => debug!("Ignoring unknown message {}: {}", text, e),
+                        },
+                        Some(Ok(Message::Close(_))) => return Ended::Closed,
+                        Some(Ok(_)) => {}
+                        Some(Err(e)) => return Ended::Lost(e.to_string()),
+                        None => return Ended::Lost("connection reset".to_string()),
+                    }
+                }
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > self.options.heartbeat_timeout {
+                        return Ended::Lost("heartbeat timed out".to_string());
+                    }
+                    if let Err(e) = socket.send(Message::Ping(Default::default())).await {
+                        return Ended::Lost(e.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Rejoin with the resume token; `None` when giving up or closed meanwhile
+    async fn reconnect(&mut self) -> Option<Socket> {
+        let mut delay = self.options.reconnect_delay;
+        for attempt in 1..=self.options.max_reconnect_attempts {
+            let _ = self.events.send(ClientEvent::Reconnecting { attempt });
+            let wake = Instant::now() + delay;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            // Keep accepting messages while waiting so a close is not delayed
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(wake) => break,
+                    command = self.commands.recv() => match command {
+                        Some(Command::Send(msg)) => self.pending.push(msg),
+                        Some(Command::Close) | None => return None,
+                    },
+                }
+            }
+
+            match open(&self.url, Some(&self.resume_token)).await {
+                Ok((mut socket, welcome)) => {
+                    let WsMessage::Welcome {
+                        peer_id,
+                        resume_token,
+                        ..
+                    } = welcome
+                    else {
+                        unreachable!("open only returns a welcome");
+                    };
+                    // An expired token joins as a new peer instead of resuming
+                    if peer_id != self.peer_id {
+                        warn!("Could not rejoin {}: slot was released", self.url);
+                        let _ = socket.close(None).await;
+                        return None;
+                    }
+                    self.resume_token = resume_token;
+                    let _ = self.events.send(ClientEvent::Reconnected);
+                    return Some(socket);
+                }
+                Err(ClientError::Rejected(reason)) => {
+                    warn!("Could not rejoin {}: {}", self.url, reason);
+                    return None;
+                }
+                Err(e) => debug!("Reconnect attempt {} failed: {}", attempt, e),
+            }
+        }
+        None
+    }
+}
+
+/// Open the WebSocket and wait for the server's `Welcome`
+async fn open(url: &str, token: Option<&str>) -> Result<(Socket, WsMessage), ClientError> {
+    let url = match token {
+        Some(token) => format!("{}?token={}", url, token),
+        None => url.to_string(),
+    };
+    let (mut socket, _) = match tokio_tungstenite::connect_async(url.as_str()).await {
+        Ok(connected) => connected,
+        Err(tungstenite::Error::Http(response)) => {
+            let body = response
+                .body()
+                .as_deref()
+                .map(String::from_utf8_lossy)
+                .unwrap_or_default();
+            return Err(ClientError::Rejected(format!(
+                "{} {}",
+                response.status(),
+                body
+            )));
+        }
+        Err(e) => return Err(ClientError::WebSocket(e)),
+    };
+
+    while let Some(frame) = socket.next().await {
+        let Message::Text(text) = frame.map_err(ClientError::WebSocket)? else {
+            continue;
+        };
+        match serde_json::from_str(&text) {
+            Ok(welcome @ WsMessage::Welcome { .. }) => return Ok((socket, welcome)),
+            Ok(WsMessage::Error { message }) => return Err(ClientError::Rejected(message)),
+            _ => debug!("Ignoring message before welcome: {}", text),
+        }
+    }
+    Err(ClientError::Closed)
+}
+
+async fn send(socket: &mut Socket, msg: &WsMessage) -> Result<(), tungstenite::Error> {
+    let text = serde_json::to_string(msg).expect("WsMessage always serializes");
+    socket.send(Message::text(text)).await
+}
+
+/// Turn a non-2xx response into an error carrying the server's message
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, ClientError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(ClientError::Status(status, body))
+}
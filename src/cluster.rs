@@ -0,0 +1,305 @@
+//! Cluster membership and room location gossip
+//!
+//! Small deployments can run a few instances without a shared store. With
+//! `cluster` configured, every `gossip_interval_secs` each instance posts
+//! the IDs of its running rooms to every other member at `/cluster/gossip`
+//! and gets theirs back. Members come from the static `members` list, from
+//! resolving `dns` (e.g. a headless service), and from whoever gossips
+//! with the instance. A member not heard from for `MISSED_ROUNDS` rounds is
+//! presumed gone. A peer connecting for a room that a live member runs is
+//! proxied there: its WebSocket is piped to the room's home, which checks
+//! its token and handles it as if it had connected directly. The proxy
+//! presents the cluster secret as bearer token, so the home neither proxies
+//! it again nor lets an outside client skip proxying. A room nobody runs
+//! yet starts wherever its first peer connects.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use axum::{
+    Json,
+    extract::{
+        State,
+        ws::{self, WebSocket},
+    },
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest};
+use tracing::{debug, info, warn};
+
+use crate::models::ClusterGossip;
+use crate::state::AppState;
+use crate::token::secret_eq;
+
+/// Rounds a member may miss before it is presumed gone
+const MISSED_ROUNDS: u32 = 3;
+
+/// Membership settings
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClusterConfig {
+    /// Base URL other members reach this instance at, e.g.
+    /// `http://10.0.0.5:3000`
+    pub advertise_url: String,
+    /// Base URLs of other members
+    #[serde(default)]
+    pub members: Vec<String>,
+    /// `host:port` resolving to every member's address
+    #[serde(default)]
+    pub dns: Option<String>,
+    /// Shared secret members present as bearer token
+    pub secret: String,
+    /// Seconds between gossip rounds
+    #[serde(default = "default_gossip_interval_secs")]
+    pub gossip_interval_secs: u64,
+}
+
+fn default_gossip_interval_secs() -> u64 {
+    5
+}
+
+impl ClusterConfig {
+    pub(crate) fn member_ttl(&self) -> Duration {
+        Duration::from_secs(self.gossip_interval_secs) * MISSED_ROUNDS
+    }
+}
+
+/// What this instance knows about the other members
+#[derive(Debug, Default)]
+pub struct ClusterView {
+    members: HashMap<String, Member>,
+}
+
+#[derive(Debug)]
+struct Member {
+    last_seen: Instant,
+    rooms: HashSet<String>,
+}
+
+impl AppState {
+    /// Remember what `url` said it runs
+    async fn record_member(&self, url: &str, rooms: Vec<String>) {
+        let mut view = self.cluster.lock().await;
+        let member = Member {
+            last_seen: Instant::now(),
+            rooms: rooms.into_iter().collect(),
+        };
+        if view.members.insert(url.to_string(), member).is_none() {
+            info!("Cluster member {} joined", url);
+        }
+    }
+
+    /// The live member running `room_id`, unless it runs here
+    ///
+    /// With a room store attached the store decides, and a room nobody
+    /// holds is claimed for this instance.
+    pub async fn room_home(&self, room_id: &str) -> Option<String> {
+        let config = self.config();
+        let cluster = config.cluster.as_ref()?;
+        if self.rooms.lock().await.contains_key(room_id) {
+            return None;
+        }
+        if let Some(home) = self.claim_room(room_id).await {
+            return (home != cluster.advertise_url).then_some(home);
+        }
+        let view = self.cluster.lock().await;
+        view.members
+            .iter()
+            .filter(|(url, m)| {
+                **url != cluster.advertise_url && m.last_seen.elapsed() < cluster.member_ttl()
+            })
+            .find(|(_, m)| m.rooms.contains(room_id))
+            .map(|(url, _)| url.clone())
+    }
+
+    /// This instance's side of a gossip exchange
+    async fn local_gossip(&self, advertise_url: &str) -> ClusterGossip {
+        ClusterGossip {
+            from: advertise_url.to_string(),
+            rooms: self.rooms.lock().await.keys().cloned().collect(),
+        }
+    }
+
+    /// Members to gossip with: configured, discovered and known
+    async fn gossip_targets(&self, cluster: &ClusterConfig) -> Vec<String> {
+        let mut targets: HashSet<String> = cluster.members.iter().cloned().collect();
+        if let Some(dns) = &cluster.dns {
+            match tokio::net::lookup_host(dns.as_str()).await {
+                Ok(addrs) => targets.extend(addrs.map(|addr| format!("http://{}", addr))),
+                Err(e) => warn!("Cluster discovery of {} failed: {}", dns, e),
+            }
+        }
+        let view = self.cluster.lock().await;
+        targets.extend(view.members.keys().cloned());
+        targets.remove(&cluster.advertise_url);
+        targets.into_iter().collect()
+    }
+
+    /// Exchange room locations with every member once
+    pub async fn gossip(&self) {
+        let config = self.config();
+        let Some(cluster) = config.cluster.as_ref() else {
+            return;
+        };
+        self.room_store_heartbeat().await;
+        let ours = self.local_gossip(&cluster.advertise_url).await;
+        for target in self.gossip_targets(cluster).await {
+            let url = format!("{}/cluster/gossip", target.trim_end_matches('/'));
+            let reply = self
+                .http
+                .post(&url)
+                .bearer_auth(&cluster.secret)
+                .json(&ours)
+                .timeout(Duration::from_secs(cluster.gossip_interval_secs.max(1)))
+                .send()
+                .await
+                .and_then(|r| r.error_for_status());
+            let theirs = match reply {
+                Ok(reply) => reply.json::<ClusterGossip>().await,
+                Err(e) => Err(e),
+            };
+            match theirs {
+                // Keyed by the address that worked rather than the one it
+                // advertises
+                Ok(theirs) => self.record_member(&target, theirs.rooms).await,
+                Err(e) => debug!("Gossip with {} failed: {}", target, e),
+            }
+        }
+        let mut view = self.cluster.lock().await;
+        view.members.retain(|url, m| {
+            let alive = m.last_seen.elapsed() < cluster.member_ttl();
+            if !alive {
+                info!("Cluster member {} is gone", url);
+            }
+            alive
+        });
+    }
+}
+
+/// Spawn the periodic gossip task if clustering is configured
+pub fn spawn_gossip(state: AppState) {
+    let Some(cluster) = state.config().cluster.clone() else {
+        return;
+    };
+    info!(
+        "Gossiping with cluster members as {}",
+        cluster.advertise_url
+    );
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(cluster.gossip_interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            state.gossip().await;
+        }
+    });
+}
+
+/// Exchange room locations with another member
+#[utoipa::path(
+    post,
+    path = "/cluster/gossip",
+    tag = "Cluster",
+    request_body = ClusterGossip,
+    responses(
+        (status = 200, description = "The receiving member's rooms", body = ClusterGossip),
+        (status = 401, description = "Missing or wrong cluster secret"),
+        (status = 404, description = "Clustering is not enabled")
+    )
+)]
+pub async fn cluster_gossip(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(gossip): Json<ClusterGossip>,
+) -> Response {
+    let config = state.config();
+    let Some(cluster) = config.cluster.as_ref() else {
+        return (StatusCode::NOT_FOUND, "Clustering is not enabled").into_response();
+    };
+    if !from_member(&headers, cluster) {
+        warn!("Rejected gossip claiming to be from {}", gossip.from);
+        return (StatusCode::UNAUTHORIZED, "Invalid cluster secret").into_response();
+    }
+    state.record_member(&gossip.from, gossip.rooms).await;
+    Json(state.local_gossip(&cluster.advertise_url).await).into_response()
+}
+
+/// Whether a request carries the cluster secret as bearer token
+pub fn from_member(headers: &HeaderMap, cluster: &ClusterConfig) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|p| secret_eq(p, &cluster.secret))
+}
+
+/// Pipe a peer's signaling socket to the member running its room,
+/// presenting `secret` so the home does not proxy it again
+pub async fn proxy_socket(
+    socket: WebSocket,
+    home: String,
+    secret: String,
+    room_id: String,
+    token: Option<String>,
+) {
+    let base = home.trim_end_matches('/').replacen("http", "ws", 1);
+    let mut url = format!("{}/ws/{}", base, room_id);
+    if let Some(token) = token {
+        url = format!("{}?token={}", url, token);
+    }
+    let upstream = match url.as_str().into_client_request() {
+        Ok(mut request) => match HeaderValue::from_str(&format!("Bearer {}", secret)) {
+            Ok(bearer) => {
+                request.headers_mut().insert(header::AUTHORIZATION, bearer);
+                tokio_tungstenite::connect_async(request).await
+            }
+            Err(e) => Err(tungstenite::Error::from(tungstenite::http::Error::from(e))),
+        },
+        Err(e) => Err(e),
+    };
+    let upstream = match upstream {
+        Ok((upstream, _)) => upstream,
+        Err(e) => {
+            warn!("Could not reach {} for room {}: {}", home, room_id, e);
+            return;
+        }
+    };
+    info!("Proxying a peer of room {} to {}", room_id, home);
+
+    let (mut client_tx, mut client_rx) = socket.split();
+    let (mut upstream_tx, mut upstream_rx) = upstream.split();
+    let to_home = async {
+        while let Some(Ok(msg)) = client_rx.next().await {
+            let msg = match msg {
+                ws::Message::Text(text) => tungstenite::Message::text(text.as_str()),
+                ws::Message::Binary(data) => tungstenite::Message::Binary(data),
+                ws::Message::Close(_) => break,
+                ws::Message::Ping(_) | ws::Message::Pong(_) => continue,
+            };
+            if upstream_tx.send(msg).await.is_err() {
+                break;
+            }
+        }
+        let _ = upstream_tx.close().await;
+    };
+    let from_home = async {
+        while let Some(Ok(msg)) = upstream_rx.next().await {
+            let msg = match msg {
+                tungstenite::Message::Text(text) => ws::Message::Text(text.as_str().into()),
+                tungstenite::Message::Binary(data) => ws::Message::Binary(data),
+                tungstenite::Message::Close(_) => break,
+                _ => continue,
+            };
+            if client_tx.send(msg).await.is_err() {
+                break;
+            }
+        }
+        let _ = client_tx.close().await;
+    };
+    tokio::select! {
+        _ = to_home => {}
+        _ = from_home => {}
+    }
+}
@@ -0,0 +1,490 @@
+//! Server configuration
+//!
+//! Loaded at startup from the JSON file given by `--config` or
+//! `AXI_VID_CONFIG`, and again on SIGHUP or `POST /admin/reload`. Every field
+//! is optional; missing values fall back to built-in defaults. A reload
+//! applies to everything read per request (templates, ICE servers, webhooks,
+//! thresholds, admin token); the SIP gateway, transcription backend and
+//! cleanup schedule keep the values they started with until restart.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::ice_policy::IcePolicy;
+use crate::models::{
+    ClientCapabilities, CodecPolicy, ConsentPolicy, CreateRoomRequest, PermissionMatrix,
+    PresentPolicy, PrivacyMode, RoomMode, RoomSettings, SdpPolicy, TranscriptionSettings,
+};
+use crate::ratelimit::{IpCidr, RateLimitConfig};
+
+/// Environment variable naming the config file (`--config` takes precedence)
+pub const CONFIG_ENV: &str = "AXI_VID_CONFIG";
+
+/// Top-level server configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Socket to accept HTTP connections on (read at startup only)
+    pub listen: crate::listener::ListenConfig,
+    /// Several sockets with their own TLS and route settings; replaces
+    /// `listen` when non-empty (read at startup only)
+    pub listeners: Vec<crate::listener::ListenerConfig>,
+    /// Externally reachable base URL, used in links sent to third parties
+    pub public_url: String,
+    /// Named room presets selectable at room creation
+    pub templates: HashMap<String, RoomTemplate>,
+    /// STUN/TURN servers handed to browsers
+    pub ice_servers: Vec<IceServer>,
+    /// TURN servers handed out with short-lived credentials; disabled when
+    /// unset
+    pub turn: Option<crate::turn::TurnConfig>,
+    /// Candidates kept off the signaling channel, e.g. to hide local IPs
+    pub ice_policy: IcePolicy,
+    /// Bearer token for the `/admin` API, with the owner role; the admin
+    /// API is disabled when neither this, `admin_keys` nor `jwt_secret` is
+    /// set
+    pub admin_token: Option<String>,
+    /// Named bearer tokens for the `/admin` API, each with its own role
+    pub admin_keys: Vec<crate::admin::AdminKey>,
+    /// Key for signing and verifying room and admin JWTs
+    pub jwt_secret: Option<String>,
+    /// Refuse to let peers join without a room JWT (or a held-slot token)
+    pub require_room_token: bool,
+    /// Reverse proxies whose `X-Forwarded-For`/`Forwarded` headers are
+    /// believed, as addresses or ranges (`10.0.0.0/8`, `fd00::/8`)
+    pub trusted_proxies: Vec<IpCidr>,
+    /// Room creation and join limits per client; disabled when unset
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Browser compatibility gate applied to `Capabilities` messages
+    pub compatibility: CompatibilityConfig,
+    /// Relay the camera, microphone and platform peers report on join and
+    /// keep them on the call record; when off, `DeviceInfo` is dropped
+    pub device_info: bool,
+    /// Send peers to `/room/{id}/ended` when their call ends; when off,
+    /// `call_ended` carries no redirect and the page is not served
+    pub post_call_page: bool,
+    /// Client features offered in rooms, unless overridden at runtime or
+    /// for the room
+    pub features: crate::models::FeatureFlags,
+    /// A/B experiments splitting rooms or peers into variants
+    pub experiments: Vec<crate::experiments::Experiment>,
+    /// Slack/Discord call-start notifications
+    pub integrations: crate::integrations::IntegrationsConfig,
+    /// Speech-to-text backend for live captions; disabled when unset
+    pub transcription: Option<crate::transcription::SttConfig>,
+    /// LLM backend for post-call summaries; disabled when unset
+    pub summary: Option<crate::transcript::SummaryConfig>,
+    /// Room reaping policies and sweep schedule
+    pub cleanup: crate::cleanup::CleanupConfig,
+    /// Seconds a dropped peer's slot is held for it to reconnect; 0 disables
+    pub reconnect_grace_secs: u64,
+    /// Seconds a call placed with `/api/calls` rings before it is missed
+    pub ring_timeout_secs: u64,
+    /// MOS alerting
+    pub quality: crate::quality::QualityConfig,
+    /// Rules checked periodically and where their alerts go; disabled when
+    /// unset
+    pub alerts: Option<crate::alerts::AlertsConfig>,
+    /// Calls the server places to itself to check signaling and TURN;
+    /// disabled when unset
+    pub canary: Option<crate::canary::CanaryConfig>,
+    /// Anonymized signaling captures for replaying; disabled when unset
+    pub signaling_capture: Option<crate::capture::CaptureConfig>,
+    /// Scrubbing of logs, debug bundles and stored records; disabled when
+    /// unset
+    pub scrubbing: Option<crate::scrub::ScrubConfig>,
+    /// Expected signaling volume per peer and what to do about top talkers
+    pub traffic: crate::traffic::TrafficConfig,
+    /// Maximum call duration and the warnings before it
+    pub call_limits: crate::call_limits::CallLimitsConfig,
+    /// Codes granting room creation; not taken when unset
+    pub entitlements: Option<crate::entitlements::EntitlementsConfig>,
+    /// Automatic ICE restarts for dead transports
+    pub ice_restart: crate::ice_restart::IceRestartConfig,
+    /// How often clients report audio levels, for the active speaker
+    pub audio_levels: crate::audio_levels::AudioLevelConfig,
+    /// Last-resort media relay through this server; disabled when unset
+    pub media_relay: Option<crate::media_relay::MediaRelayConfig>,
+    /// Waiting line for full rooms; joins are rejected outright when unset
+    pub join_queue: Option<crate::join_queue::JoinQueueConfig>,
+    /// What happens when someone already in a room connects again
+    pub duplicate_sessions: crate::sessions::DuplicateSessions,
+    /// Personal meeting rooms for holders of user tokens; disabled when unset
+    pub personal_rooms: Option<crate::personal_rooms::PersonalRoomsConfig>,
+    /// Voice messages for unanswered calls; disabled when unset
+    pub voicemail: Option<crate::voicemail::VoicemailConfig>,
+    /// Malware scanner for files users upload for each other; disabled
+    /// when unset
+    pub scanning: Option<crate::scanning::ScanConfig>,
+    /// Twilio dial-in webhooks; disabled when unset
+    pub twilio: Option<crate::pstn::TwilioConfig>,
+    /// SIP trunk bridge; disabled when unset
+    #[cfg(feature = "sip")]
+    pub sip: Option<crate::sip::SipConfig>,
+    /// Postgres database keeping room records across restarts; records
+    /// stay in memory only when unset (read at startup only)
+    #[cfg(feature = "postgres")]
+    pub postgres: Option<crate::postgres::PostgresConfig>,
+    /// Gossip with other instances and proxy their rooms' peers to them;
+    /// disabled when unset
+    pub cluster: Option<crate::cluster::ClusterConfig>,
+    /// Instances that take over calls on shutdown; peers are sent away with
+    /// `leave` when unset
+    pub drain: Option<crate::migration::DrainConfig>,
+    /// Endpoint receiving room events; disabled when unset
+    pub webhooks: Option<crate::webhooks::WebhookConfig>,
+    /// SAML identity provider signing users in; disabled when unset
+    pub saml: Option<crate::saml::SamlConfig>,
+    /// Key signing shareable links to recordings, exports and transcripts;
+    /// disabled when unset
+    pub signed_urls: Option<crate::signed_urls::SignedUrlConfig>,
+    /// Identity provider provisioning users over SCIM; disabled when unset
+    pub scim: Option<crate::scim::ScimConfig>,
+    /// LDAP directory searched for people to invite; disabled when unset
+    pub directory: Option<crate::directory::DirectoryConfig>,
+    /// Customers with their own API keys and notification targets
+    pub tenants: HashMap<String, crate::tenants::TenantConfig>,
+    /// Recording uploads and their post-processing; disabled when unset
+    pub recordings: Option<crate::recordings::RecordingsConfig>,
+    /// Resizing, metadata stripping and conversion of uploaded images;
+    /// images are served as uploaded when unset
+    pub images: Option<crate::images::ImagesConfig>,
+    /// Where recordings and export bundles are kept; `recordings.dir` and
+    /// memory when unset. Read at startup
+    pub storage: Option<crate::storage::StorageConfig>,
+    /// FCM and APNs credentials for ringing native apps; disabled when unset
+    pub push: Option<crate::push::PushConfig>,
+    /// Links handing joins off to the mobile apps; disabled when unset
+    pub deep_links: Option<crate::deep_links::DeepLinkConfig>,
+    /// File this config was read from, re-read on reload
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: Default::default(),
+            listeners: Vec::new(),
+            public_url: "http://localhost:3000".to_string(),
+            templates: RoomTemplate::builtin(),
+            ice_servers: IceServer::defaults(),
+            turn: None,
+            ice_policy: IcePolicy::default(),
+            admin_token: None,
+            admin_keys: Vec::new(),
+            jwt_secret: None,
+            require_room_token: false,
+            trusted_proxies: Vec::new(),
+            rate_limit: None,
+            compatibility: CompatibilityConfig::default(),
+            device_info: true,
+            post_call_page: true,
+            features: Default::default(),
+            experiments: Vec::new(),
+            integrations: Default::default(),
+            transcription: None,
+            summary: None,
+            cleanup: Default::default(),
+            reconnect_grace_secs: 30,
+            ring_timeout_secs: 30,
+            quality: Default::default(),
+            alerts: None,
+            canary: None,
+            signaling_capture: None,
+            scrubbing: None,
+            call_limits: Default::default(),
+            entitlements: None,
+            ice_restart: Default::default(),
+            audio_levels: Default::default(),
+            traffic: Default::default(),
+            media_relay: None,
+            join_queue: None,
+            duplicate_sessions: Default::default(),
+            personal_rooms: None,
+            voicemail: None,
+            scanning: None,
+            twilio: None,
+            #[cfg(feature = "sip")]
+            sip: None,
+            #[cfg(feature = "postgres")]
+            postgres: None,
+            cluster: None,
+            drain: None,
+            webhooks: None,
+            saml: None,
+            directory: None,
+            scim: None,
+            signed_urls: None,
+            tenants: HashMap::new(),
+            recordings: None,
+            images: None,
+            storage: None,
+            push: None,
+            deep_links: None,
+            source: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load configuration from `path`, or the defaults when `None`
+    ///
+    /// Templates from the file are merged over the built-in presets.
+    pub fn load_from(path: Option<&Path>) -> Result<Self, String> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        let mut config: Config = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?;
+
+        for (name, template) in RoomTemplate::builtin() {
+            config.templates.entry(name).or_insert(template);
+        }
+        config.cleanup.validate()?;
+        if let Some(directory) = &config.directory {
+            directory.validate()?;
+        }
+        if config.require_room_token && config.jwt_secret.is_none() {
+            return Err("require_room_token needs jwt_secret to be set".to_string());
+        }
+        if config.drain.is_some() && config.jwt_secret.is_none() {
+            return Err("drain needs jwt_secret to be set".to_string());
+        }
+        config.source = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    /// Listeners to bind: `listeners`, or `listen` serving every route
+    pub fn listeners(&self) -> Vec<crate::listener::ListenerConfig> {
+        if !self.listeners.is_empty() {
+            return self.listeners.clone();
+        }
+        vec![crate::listener::ListenerConfig {
+            bind: self.listen.clone(),
+            tls: None,
+            routes: Default::default(),
+        }]
+    }
+
+    /// Resolve the effective settings for a room creation request
+    pub fn resolve_room_settings(
+        &self,
+        request: &CreateRoomRequest,
+    ) -> Result<RoomSettings, &'static str> {
+        let mut settings = RoomSettings::default();
+
+        if let Some(name) = &request.template {
+            let template = self.templates.get(name).ok_or("Unknown room template")?;
+            template.apply(&mut settings);
+        }
+        if let Some(mode) = request.mode {
+            settings.mode = mode;
+        }
+        if request.max_duration_secs.is_some() {
+            settings.max_duration_secs = request.max_duration_secs;
+        }
+        settings.max_duration_secs = self.call_limits.max_duration(settings.max_duration_secs);
+        if let Some(permissions) = request.permissions {
+            settings.permissions = permissions;
+        }
+        if request.transcription.is_some() {
+            settings.transcription = request.transcription.clone();
+        }
+        if let Some(policy) = request.consent_policy {
+            settings.consent_policy = policy;
+        }
+        if let Some(privacy) = request.privacy_mode {
+            settings.privacy_mode = privacy;
+        }
+        if let Some(policy) = request.present_policy {
+            settings.present_policy = policy;
+        }
+        if request.codecs.is_some() {
+            settings.codecs = request.codecs.clone().map(Box::new);
+        }
+        if request.sdp.is_some() {
+            settings.sdp = request.sdp.clone().map(Box::new);
+        }
+        settings.features = request.features;
+        settings.event = request.event.clone().map(Box::new);
+
+        Ok(settings)
+    }
+}
+
+/// A named room preset; unset fields keep their defaults
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RoomTemplate {
+    pub mode: Option<RoomMode>,
+    pub permissions: Option<PermissionMatrix>,
+    /// Shorthand to disable chat for every role
+    pub chat: Option<bool>,
+    pub max_viewers: Option<usize>,
+    pub audio: Option<bool>,
+    pub video: Option<bool>,
+    pub max_duration_secs: Option<u64>,
+    pub transcription: Option<TranscriptionSettings>,
+    pub consent_policy: Option<ConsentPolicy>,
+    pub privacy_mode: Option<PrivacyMode>,
+    pub present_policy: Option<PresentPolicy>,
+    pub codecs: Option<CodecPolicy>,
+    pub sdp: Option<SdpPolicy>,
+}
+
+impl RoomTemplate {
+    /// Presets available without any config file
+    fn builtin() -> HashMap<String, RoomTemplate> {
+        HashMap::from([
+            (
+                "interview".to_string(),
+                RoomTemplate {
+                    chat: Some(false),
+                    max_duration_secs: Some(60 * 60),
+                    ..Default::default()
+                },
+            ),
+            (
+                "webinar".to_string(),
+                RoomTemplate {
+                    mode: Some(RoomMode::Broadcast),
+                    max_viewers: Some(100),
+                    ..Default::default()
+                },
+            ),
+        ])
+    }
+
+    /// Apply this template on top of existing settings
+    pub fn apply(&self, settings: &mut RoomSettings) {
+        if let Some(mode) = self.mode {
+            settings.mode = mode;
+        }
+        if let Some(permissions) = self.permissions {
+            settings.permissions = permissions;
+        }
+        if let Some(chat) = self.chat {
+            settings.permissions.host.chat = chat;
+            settings.permissions.participant.chat = chat;
+            settings.permissions.viewer.chat = chat;
+        }
+        if let Some(max_viewers) = self.max_viewers {
+            settings.max_viewers = max_viewers;
+        }
+        if let Some(audio) = self.audio {
+            settings.audio = audio;
+        }
+        if let Some(video) = self.video {
+            settings.video = video;
+        }
+        if self.max_duration_secs.is_some() {
+            settings.max_duration_secs = self.max_duration_secs;
+        }
+        if self.transcription.is_some() {
+            settings.transcription = self.transcription.clone();
+        }
+        if let Some(policy) = self.consent_policy {
+            settings.consent_policy = policy;
+        }
+        if let Some(privacy) = self.privacy_mode {
+            settings.privacy_mode = privacy;
+        }
+        if let Some(policy) = self.present_policy {
+            settings.present_policy = policy;
+        }
+        if self.codecs.is_some() {
+            settings.codecs = self.codecs.clone().map(Box::new);
+        }
+        if self.sdp.is_some() {
+            settings.sdp = self.sdp.clone().map(Box::new);
+        }
+    }
+}
+
+/// A STUN or TURN server, in `RTCIceServer` shape
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, JsonSchema)]
+pub struct IceServer {
+    #[schema(example = json!(["stun:stun.l.google.com:19302"]))]
+    pub urls: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+impl IceServer {
+    /// Public STUN servers used when none are configured
+    fn defaults() -> Vec<Self> {
+        [
+            "stun:stun.l.google.com:19302",
+            "stun:stun1.l.google.com:19302",
+        ]
+        .into_iter()
+        .map(|url| Self {
+            urls: vec![url.to_string()],
+            username: None,
+            credential: None,
+        })
+        .collect()
+    }
+}
+
+/// Rules for rejecting clients that are known not to work
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CompatibilityConfig {
+    /// Reject peers instead of only logging incompatibilities
+    pub enforce: bool,
+    /// Minimum supported major version per browser name (lowercase)
+    pub min_browser_versions: HashMap<String, u32>,
+}
+
+impl CompatibilityConfig {
+    /// Check a client's capabilities, returning a user-facing reason on failure
+    pub fn check(&self, caps: &ClientCapabilities) -> Result<(), String> {
+        let browser = caps.browser.to_lowercase();
+        let Some(min) = self.min_browser_versions.get(&browser) else {
+            return Ok(());
+        };
+
+        let major = caps
+            .version
+            .split('.')
+            .next()
+            .and_then(|v| v.parse::<u32>().ok());
+        match major {
+            Some(major) if major < *min => Err(format!(
+                "{} {} is not supported; please update to version {} or later",
+                caps.browser, caps.version, min
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Check that two peers share at least one codec
+pub fn check_codec_overlap(a: &ClientCapabilities, b: &ClientCapabilities) -> Result<(), String> {
+    if a.codecs.is_empty() || b.codecs.is_empty() {
+        return Ok(());
+    }
+    let shared = a
+        .codecs
+        .iter()
+        .any(|c| b.codecs.iter().any(|o| o.eq_ignore_ascii_case(c)));
+    if shared {
+        Ok(())
+    } else {
+        Err(format!(
+            "No codec in common with the other participant ({} {}); try a different browser",
+            b.browser, b.version
+        ))
+    }
+}
@@ -0,0 +1,222 @@
+//! Recording consent workflow
+//!
+//! A request to start recording opens a consent round: every peer receives
+//! `ConsentRequest` and answers with `ConsentResponse`. Recording becomes
+//! active only once the room's `ConsentPolicy` is satisfied. Every request,
+//! answer and outcome is written to the audit trail.
+
+use std::collections::HashMap;
+
+use tracing::info;
+
+use crate::models::{ConsentPolicy, PeerRole, Permission, WsMessage};
+use crate::state::{AppState, Room};
+
+/// An in-progress recording consent round
+#[derive(Debug)]
+pub struct ConsentRound {
+    pub requested_by: String,
+    /// Answers received so far, by peer ID
+    pub responses: HashMap<String, bool>,
+}
+
+/// Result of evaluating a consent round against the room policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsentOutcome {
+    Granted,
+    Declined,
+}
+
+impl Room {
+    /// Open a consent round on behalf of `requester`, who implicitly consents
+    pub fn start_consent(&mut self, requester: &str) -> Result<(), &'static str> {
+        if self.recording {
+            return Err("Recording is already active");
+        }
+        if self.consent.is_some() {
+            return Err("A recording consent request is already pending");
+        }
+
+        self.consent = Some(ConsentRound {
+            requested_by: requester.to_string(),
+            responses: HashMap::from([(requester.to_string(), true)]),
+        });
+        self.broadcast_to_others(
+            requester,
+            &WsMessage::ConsentRequest {
+                requested_by: requester.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Record a peer's answer to the pending round
+    pub fn answer_consent(&mut self, peer_id: &str, granted: bool) -> Result<(), &'static str> {
+        let round = self
+            .consent
+            .as_mut()
+            .ok_or("No recording consent request is pending")?;
+        round.responses.insert(peer_id.to_string(), granted);
+        Ok(())
+    }
+
+    /// Evaluate the pending round against the policy without changing state
+    pub fn evaluate_consent(&self) -> Option<ConsentOutcome> {
+        let round = self.consent.as_ref()?;
+        let answer = |peer_id: &str| round.responses.get(peer_id).copied();
+        let total = self.peers.len();
+        let yes = self
+            .peers
+            .iter()
+            .filter(|p| answer(&p.id) == Some(true))
+            .count();
+        let no = self
+            .peers
+            .iter()
+            .filter(|p| answer(&p.id) == Some(false))
+            .count();
+
+        match self.settings.consent_policy {
+            ConsentPolicy::All if no > 0 => Some(ConsentOutcome::Declined),
+            ConsentPolicy::All if yes == total => Some(ConsentOutcome::Granted),
+            ConsentPolicy::Majority if yes * 2 > total => Some(ConsentOutcome::Granted),
+            // Even if every outstanding peer agreed there would be no majority
+            ConsentPolicy::Majority if (total - no) * 2 <= total => Some(ConsentOutcome::Declined),
+            ConsentPolicy::HostOnly => {
+                let host = self.peers.iter().find(|p| p.role == PeerRole::Host)?;
+                match answer(&host.id)? {
+                    true => Some(ConsentOutcome::Granted),
+                    false => Some(ConsentOutcome::Declined),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Close the pending round if the policy is decided, notifying peers
+    pub fn settle_consent(&mut self) -> Option<ConsentOutcome> {
+        let outcome = self.evaluate_consent()?;
+        let round = self.consent.take()?;
+
+        match outcome {
+            ConsentOutcome::Granted => {
+                self.recording = true;
+                self.broadcast_to_all(&WsMessage::Recording { active: true });
+                // Participants send recorders media only from now on
+                for recorder in &self.recorders {
+                    self.broadcast_to_all(&WsMessage::RecorderJoined {
+                        peer_id: recorder.id.clone(),
+                    });
+                }
+            }
+            ConsentOutcome::Declined => {
+                let declined =
+                    WsMessage::error("Recording was declined by the room's consent policy");
+                match self.recorders.iter().find(|r| r.id == round.requested_by) {
+                    Some(recorder) => {
+                        let _ = recorder.sender.send(declined);
+                    }
+                    None => self.send_to(&round.requested_by, declined),
+                }
+                self.broadcast_to_all(&WsMessage::Recording { active: false });
+            }
+        }
+        Some(outcome)
+    }
+}
+
+/// What a recording request did in the room
+enum RecordingRequest {
+    Stop {
+        stopped: bool,
+    },
+    Start {
+        outcome: Option<ConsentOutcome>,
+        policy: ConsentPolicy,
+    },
+}
+
+impl AppState {
+    /// Handle a peer's request to start or stop recording
+    pub async fn request_recording(
+        &self,
+        room_id: &str,
+        peer_id: &str,
+        active: bool,
+    ) -> Result<(), &'static str> {
+        let id = peer_id.to_string();
+        let requested = self.with_room(room_id, move |room| -> Result<_, &'static str> {
+            room.check_permitted(&id, Permission::Record)?;
+            if !active {
+                let stopped = room.recording;
+                if stopped {
+                    room.recording = false;
+                    room.broadcast_to_all(&WsMessage::Recording { active: false });
+                }
+                return Ok(RecordingRequest::Stop { stopped });
+            }
+            room.start_consent(&id)?;
+            Ok(RecordingRequest::Start {
+                outcome: room.settle_consent(),
+                policy: room.settings.consent_policy,
+            })
+        });
+        let (outcome, policy) = match requested.await.ok_or("Room not found")?? {
+            RecordingRequest::Stop { stopped } => {
+                if stopped {
+                    self.record_audit(Some(room_id), peer_id, "recording.stop", "")
+                        .await;
+                }
+                return Ok(());
+            }
+            RecordingRequest::Start { outcome, policy } => (outcome, policy),
+        };
+
+        info!("Peer {} requested recording in room {}", peer_id, room_id);
+        self.record_audit(
+            Some(room_id),
+            peer_id,
+            "recording.request",
+            format!("policy {:?}", policy),
+        )
+        .await;
+        if let Some(outcome) = outcome {
+            self.record_consent_outcome(room_id, outcome).await;
+        }
+        Ok(())
+    }
+
+    /// Handle a peer's consent answer
+    pub async fn answer_consent(
+        &self,
+        room_id: &str,
+        peer_id: &str,
+        granted: bool,
+    ) -> Result<(), &'static str> {
+        let id = peer_id.to_string();
+        let answered = self.with_room(room_id, move |room| -> Result<_, &'static str> {
+            room.answer_consent(&id, granted)?;
+            Ok(room.settle_consent())
+        });
+        let outcome = answered.await.ok_or("Room not found")??;
+
+        let answer = if granted { "granted" } else { "declined" };
+        self.record_audit(Some(room_id), peer_id, "recording.consent_response", answer)
+            .await;
+        if let Some(outcome) = outcome {
+            self.record_consent_outcome(room_id, outcome).await;
+        }
+        Ok(())
+    }
+
+    /// Write a settled consent round to the audit trail
+    pub async fn record_consent_outcome(&self, room_id: &str, outcome: ConsentOutcome) {
+        self.record_audit(
+            Some(room_id),
+            "server",
+            "recording.consent",
+            format!("{:?}", outcome),
+        )
+        .await;
+    }
+}
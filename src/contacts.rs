@@ -0,0 +1,280 @@
+//! Per-user address books
+//!
+//! Each holder of a user token keeps a list of people to call, managed under
+//! `/api/contacts`. A contact names another user by token subject, the same
+//! name `/api/calls` rings, with an optional display name. Listings carry
+//! each contact's presence, so a client can show who is reachable. Address
+//! books are kept in memory, like every other record, up to `MAX_CONTACTS`
+//! entries per user.
+
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use uuid::Uuid;
+
+use crate::models::{Contact, ContactRequest};
+use crate::state::AppState;
+use crate::token::UserAuth;
+
+/// Contacts kept per user
+pub const MAX_CONTACTS: usize = 1000;
+
+/// Longest token subject or display name accepted
+const MAX_CONTACT_FIELD_LEN: usize = 256;
+
+/// A stored contact
+#[derive(Debug, Clone)]
+pub struct ContactEntry {
+    pub id: String,
+    pub user: String,
+    pub name: Option<String>,
+}
+
+/// Address books by token subject of their owner
+pub type AddressBooks = HashMap<String, Vec<ContactEntry>>;
+
+type Rejection = (StatusCode, &'static str);
+
+impl ContactRequest {
+    fn validate(&self, owner: &str) -> Result<(), Rejection> {
+        if self.user.trim().is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "A contact needs a user"));
+        }
+        if self.user == owner {
+            return Err((StatusCode::BAD_REQUEST, "You cannot add yourself"));
+        }
+        let name_len = self.name.as_ref().map_or(0, String::len);
+        if self.user.len() > MAX_CONTACT_FIELD_LEN || name_len > MAX_CONTACT_FIELD_LEN {
+            return Err((StatusCode::BAD_REQUEST, "User or name is too long"));
+        }
+        Ok(())
+    }
+}
+
+impl AppState {
+    /// Describe a stored contact with its current presence
+    async fn describe_contact(&self, entry: ContactEntry) -> Contact {
+        let status = self.presence_of(&entry.user).await.status;
+        Contact {
+            id: entry.id,
+            user: entry.user,
+            name: entry.name,
+            status,
+        }
+    }
+
+    /// Every contact of `owner`, in the order they were added
+    pub async fn list_contacts(&self, owner: &str) -> Vec<Contact> {
+        let entries = self
+            .contacts
+            .lock()
+            .await
+            .get(owner)
+            .cloned()
+            .unwrap_or_default();
+        let mut contacts = Vec::with_capacity(entries.len());
+        for entry in entries {
+            contacts.push(self.describe_contact(entry).await);
+        }
+        contacts
+    }
+
+    /// One contact of `owner`
+    pub async fn get_contact(&self, owner: &str, id: &str) -> Option<Contact> {
+        let entry = {
+            let books = self.contacts.lock().await;
+            books.get(owner)?.iter().find(|c| c.id == id)?.clone()
+        };
+        Some(self.describe_contact(entry).await)
+    }
+
+    /// Add a contact to `owner`'s address book
+    pub async fn add_contact(
+        &self,
+        owner: &str,
+        request: ContactRequest,
+    ) -> Result<Contact, Rejection> {
+        request.validate(owner)?;
+        let entry = {
+            let mut books = self.contacts.lock().await;
+            let book = books.entry(owner.to_string()).or_default();
+            if book.iter().any(|c| c.user == request.user) {
+                return Err((StatusCode::CONFLICT, "Already a contact"));
+            }
+            if book.len() >= MAX_CONTACTS {
+                return Err((StatusCode::BAD_REQUEST, "Too many contacts"));
+            }
+            let entry = ContactEntry {
+                id: Uuid::new_v4().simple().to_string(),
+                user: request.user,
+                name: request.name,
+            };
+            book.push(entry.clone());
+            entry
+        };
+        Ok(self.describe_contact(entry).await)
+    }
+
+    /// Replace the user and name of one of `owner`'s contacts
+    pub async fn update_contact(
+        &self,
+        owner: &str,
+        id: &str,
+        request: ContactRequest,
+    ) -> Result<Contact, Rejection> {
+        request.validate(owner)?;
+        let entry = {
+            let mut books = self.contacts.lock().await;
+            let book = books
+                .get_mut(owner)
+                .ok_or((StatusCode::NOT_FOUND, "No such contact"))?;
+            if book.iter().any(|c| c.user == request.user && c.id != id) {
+                return Err((StatusCode::CONFLICT, "Already a contact"));
+            }
+            let entry = book
+                .iter_mut()
+                .find(|c| c.id == id)
+                .ok_or((StatusCode::NOT_FOUND, "No such contact"))?;
+            entry.user = request.user;
+            entry.name = request.name;
+            entry.clone()
+        };
+        Ok(self.describe_contact(entry).await)
+    }
+
+    /// Remove one of `owner`'s contacts; returns false if there is none
+    pub async fn remove_contact(&self, owner: &str, id: &str) -> bool {
+        let mut books = self.contacts.lock().await;
+        let Some(book) = books.get_mut(owner) else {
+            return false;
+        };
+        let before = book.len();
+        book.retain(|c| c.id != id);
+        book.len() != before
+    }
+}
+
+/// List the caller's contacts
+#[utoipa::path(
+    get,
+    path = "/api/contacts",
+    tag = "Calls",
+    responses(
+        (status = 200, description = "Contacts in the order they were added", body = Vec<Contact>),
+        (status = 401, description = "Missing or invalid user token")
+    )
+)]
+pub async fn list_contacts(
+    State(state): State<AppState>,
+    UserAuth(owner): UserAuth,
+) -> Json<Vec<Contact>> {
+    Json(state.list_contacts(&owner).await)
+}
+
+/// Add a contact
+#[utoipa::path(
+    post,
+    path = "/api/contacts",
+    tag = "Calls",
+    request_body = ContactRequest,
+    responses(
+        (status = 201, description = "Contact added", body = Contact),
+        (status = 400, description = "Empty, too long or own user, or address book full"),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 409, description = "The user is already a contact")
+    )
+)]
+pub async fn add_contact(
+    State(state): State<AppState>,
+    UserAuth(owner): UserAuth,
+    Json(request): Json<ContactRequest>,
+) -> Response {
+    match state.add_contact(&owner, request).await {
+        Ok(contact) => (StatusCode::CREATED, Json(contact)).into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Look up one contact
+#[utoipa::path(
+    get,
+    path = "/api/contacts/{id}",
+    tag = "Calls",
+    params(
+        ("id" = String, Path, description = "The contact's ID")
+    ),
+    responses(
+        (status = 200, description = "The contact", body = Contact),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 404, description = "No such contact")
+    )
+)]
+pub async fn get_contact(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    UserAuth(owner): UserAuth,
+) -> Response {
+    match state.get_contact(&owner, &id).await {
+        Some(contact) => Json(contact).into_response(),
+        None => (StatusCode::NOT_FOUND, "No such contact").into_response(),
+    }
+}
+
+/// Change a contact
+#[utoipa::path(
+    put,
+    path = "/api/contacts/{id}",
+    tag = "Calls",
+    params(
+        ("id" = String, Path, description = "The contact's ID")
+    ),
+    request_body = ContactRequest,
+    responses(
+        (status = 200, description = "Contact changed", body = Contact),
+        (status = 400, description = "Empty, too long or own user"),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 404, description = "No such contact"),
+        (status = 409, description = "The user is already another contact")
+    )
+)]
+pub async fn update_contact(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    UserAuth(owner): UserAuth,
+    Json(request): Json<ContactRequest>,
+) -> Response {
+    match state.update_contact(&owner, &id, request).await {
+        Ok(contact) => Json(contact).into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Remove a contact
+#[utoipa::path(
+    delete,
+    path = "/api/contacts/{id}",
+    tag = "Calls",
+    params(
+        ("id" = String, Path, description = "The contact's ID")
+    ),
+    responses(
+        (status = 204, description = "Contact removed"),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 404, description = "No such contact")
+    )
+)]
+pub async fn remove_contact(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    UserAuth(owner): UserAuth,
+) -> Response {
+    match state.remove_contact(&owner, &id).await {
+        true => StatusCode::NO_CONTENT.into_response(),
+        false => (StatusCode::NOT_FOUND, "No such contact").into_response(),
+    }
+}
@@ -0,0 +1,186 @@
+//! Per-call debug bundles
+//!
+//! `GET /admin/rooms/{room_id}/debug` puts everything the server knows
+//! about a call into one JSON document support can attach to a bug report:
+//! the live room with its peers' devices and stats, the call detail record,
+//! the timeline, client error reports and a digest of each peer's
+//! signaling. Session descriptions are not kept; the digest has the size,
+//! hash, DTLS fingerprints and media sections of each peer's last offer and
+//! answer, and how many ICE candidates of each type it sent. Like
+//! timelines, digests outlive the room.
+
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+use crate::admin::AdminAuth;
+use crate::models::{CallDebugBundle, PeerSignaling, SdpSummary, WsMessage};
+use crate::state::{AppState, unix_timestamp};
+use crate::timeline::{MAX_STORED_TIMELINES, unix_timestamp_ms};
+
+/// Signaling digest of a room
+#[derive(Debug, Default)]
+pub struct RoomSignaling {
+    /// Unix timestamp (milliseconds) of the last update
+    updated_ms: u64,
+    peers: BTreeMap<String, PeerSignaling>,
+}
+
+/// Signaling digests by room ID
+pub type SignalingDigests = HashMap<String, RoomSignaling>;
+
+/// Something a peer sent that goes into the digest
+#[derive(Debug)]
+pub enum SignalingNote {
+    Offer(SdpSummary),
+    Answer(SdpSummary),
+    /// An ICE candidate, by type
+    Candidate(String),
+}
+
+impl SignalingNote {
+    /// The note for a message, if it is an offer, answer or candidate
+    pub fn of(msg: &WsMessage) -> Option<Self> {
+        match msg {
+            WsMessage::Offer { sdp, .. } => Some(Self::Offer(summarize_sdp(sdp))),
+            WsMessage::Answer { sdp, .. } => Some(Self::Answer(summarize_sdp(sdp))),
+            WsMessage::IceCandidate { candidate, .. } => {
+                candidate_type(candidate).map(|t| Self::Candidate(t.to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Size, hash, fingerprints and media sections of a description
+pub fn summarize_sdp(sdp: &str) -> SdpSummary {
+    let sha256 = Sha256::digest(sdp.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    SdpSummary {
+        timestamp_ms: unix_timestamp_ms(),
+        size_bytes: sdp.len(),
+        sha256,
+        dtls_fingerprints: sdp
+            .lines()
+            .filter_map(|l| l.strip_prefix("a=fingerprint:"))
+            .map(|f| f.trim().to_string())
+            .collect(),
+        media: sdp
+            .lines()
+            .filter_map(|l| l.strip_prefix("m="))
+            .filter_map(|l| l.split(' ').next())
+            .map(str::to_string)
+            .collect(),
+    }
+}
+
+/// The `typ` of a candidate line, also found inside a JSON message
+pub fn candidate_type(text: &str) -> Option<&str> {
+    let (_, rest) = text.split_once(" typ ")?;
+    rest.split([' ', '"']).next().filter(|t| !t.is_empty())
+}
+
+impl AppState {
+    /// Add something a peer sent to its room's signaling digest
+    pub async fn note_signaling(&self, room_id: &str, peer_id: &str, note: SignalingNote) {
+        let mut digests = self.signaling_digests.lock().await;
+        if !digests.contains_key(room_id) && digests.len() >= MAX_STORED_TIMELINES {
+            // Evict the digest that was least recently written
+            let oldest = digests
+                .iter()
+                .min_by_key(|(_, d)| d.updated_ms)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                digests.remove(&oldest);
+            }
+        }
+        let digest = digests.entry(room_id.to_string()).or_default();
+        digest.updated_ms = unix_timestamp_ms();
+        let peer = digest
+            .peers
+            .entry(peer_id.to_string())
+            .or_insert_with(|| PeerSignaling {
+                peer_id: peer_id.to_string(),
+                ..Default::default()
+            });
+        match note {
+            SignalingNote::Offer(summary) => peer.last_offer = Some(summary),
+            SignalingNote::Answer(summary) => peer.last_answer = Some(summary),
+            SignalingNote::Candidate(kind) => *peer.candidates.entry(kind).or_default() += 1,
+        }
+    }
+
+    /// Put together a room's debug bundle; `None` if nothing is known of
+    /// the room
+    pub async fn debug_bundle(&self, room_id: &str) -> Option<CallDebugBundle> {
+        let room = self.room_details(room_id).await;
+        let call = self.calls.lock().await.get(room_id).cloned();
+        let timeline = self
+            .get_timeline(room_id)
+            .await
+            .map(|t| t.events)
+            .unwrap_or_default();
+        let signaling: Vec<PeerSignaling> = self
+            .signaling_digests
+            .lock()
+            .await
+            .get(room_id)
+            .map(|d| d.peers.values().cloned().collect())
+            .unwrap_or_default();
+        let client_errors = self.list_client_errors(Some(room_id)).await;
+        if room.is_none() && call.is_none() && timeline.is_empty() && signaling.is_empty() {
+            return None;
+        }
+        Some(CallDebugBundle {
+            room_id: room_id.to_string(),
+            generated_at: unix_timestamp(),
+            room,
+            call,
+            timeline,
+            signaling,
+            client_errors,
+        })
+    }
+}
+
+/// Everything known about a call, in one document
+#[utoipa::path(
+    get,
+    path = "/admin/rooms/{room_id}/debug",
+    tag = "Admin",
+    params(
+        ("room_id" = String, Path, description = "The room identifier")
+    ),
+    responses(
+        (status = 200, description = "The call's debug bundle", body = CallDebugBundle),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 404, description = "Nothing known about this room")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn get_debug_bundle(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+) -> Response {
+    let Some(bundle) = state.debug_bundle(&room_id).await else {
+        return (StatusCode::NOT_FOUND, "Nothing known about this room").into_response();
+    };
+    match state
+        .config()
+        .scrubbing
+        .as_ref()
+        .filter(|s| s.debug_bundles)
+    {
+        Some(scrubbing) => Json(scrubbing.serialized(&bundle)).into_response(),
+        None => Json(bundle).into_response(),
+    }
+}
@@ -0,0 +1,178 @@
+//! Deep links into the mobile apps
+//!
+//! With a `deep_links` section, room creation, share links and re-invites
+//! also return a pair of links that hand a join off to the app: the app's
+//! own URL, made from the configured template, and a web link to
+//! `/join/{room}` on this server. Phones with the app installed open the
+//! web link in the app, as the server publishes the app IDs it may be
+//! handed to in `apple-app-site-association` and `assetlinks.json`.
+//! Elsewhere the link serves a small page that tries the app, then offers
+//! the store listings or the browser call.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::handlers::WsQuery;
+use crate::models::DeepLinks;
+use crate::state::AppState;
+
+/// How rooms are opened in the mobile apps
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeepLinkConfig {
+    /// App URL of a room; `{room}` is replaced by the room ID and `{token}`
+    /// by the link's token, or nothing
+    pub app_url: String,
+    /// Name shown on the fallback page
+    #[serde(default = "default_app_name")]
+    pub app_name: String,
+    /// App Store listing offered when the app is not installed
+    pub app_store_url: Option<String>,
+    /// Google Play listing offered when the app is not installed
+    pub play_store_url: Option<String>,
+    /// iOS apps (`TEAMID.bundle.id`) that open `/join/` links
+    #[serde(default)]
+    pub apple_app_ids: Vec<String>,
+    /// Android package that opens `/join/` links
+    pub android_package: Option<String>,
+    /// SHA-256 fingerprints of the Android package's signing certificates
+    #[serde(default)]
+    pub android_cert_fingerprints: Vec<String>,
+}
+
+fn default_app_name() -> String {
+    "the app".to_string()
+}
+
+impl Config {
+    /// App and web links into a room, when deep links are configured
+    pub fn deep_links(&self, room_id: &str, token: Option<&str>) -> Option<DeepLinks> {
+        let links = self.deep_links.as_ref()?;
+        let app_url = links
+            .app_url
+            .replace("{room}", room_id)
+            .replace("{token}", token.unwrap_or_default());
+        let mut web_url = format!("{}/join/{}", self.public_url.trim_end_matches('/'), room_id);
+        if let Some(token) = token {
+            web_url.push_str(&format!("?token={}", token));
+        }
+        Some(DeepLinks { app_url, web_url })
+    }
+}
+
+/// Whether a token or ID can go into a link as it is; the ones the server
+/// hands out always can
+pub(crate) fn url_safe(text: &str) -> bool {
+    text.chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+/// Escape text for an HTML attribute or element
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Page handing a join off to the app, or to the browser call
+pub async fn join_page(
+    Path(room_id): Path<String>,
+    Query(query): Query<WsQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    if Uuid::parse_str(&room_id).is_err() {
+        return (StatusCode::BAD_REQUEST, "Invalid room ID format").into_response();
+    }
+    // Anything else could not have come from one of our links
+    let token = query.token.as_deref().filter(|t| url_safe(t));
+    let browser_url = match token {
+        Some(token) => format!("/room/{}?token={}", room_id, token),
+        None => format!("/room/{}", room_id),
+    };
+    let config = state.config();
+    let (Some(settings), Some(links)) = (
+        config.deep_links.as_ref(),
+        config.deep_links(&room_id, token),
+    ) else {
+        return Redirect::to(&browser_url).into_response();
+    };
+    let stores: String = [
+        ("App Store", settings.app_store_url.as_deref()),
+        ("Google Play", settings.play_store_url.as_deref()),
+    ]
+    .into_iter()
+    .filter_map(|(name, url)| {
+        let url = url?;
+        Some(format!(
+            r#"<a class="btn btn-secondary" href="{}">Get it on {}</a>"#,
+            escape_html(url),
+            name
+        ))
+    })
+    .collect();
+    let html = include_str!("../static/join.html")
+        .replace("{{APP_NAME}}", &escape_html(&settings.app_name))
+        .replace("{{APP_URL}}", &escape_html(&links.app_url))
+        .replace("{{BROWSER_URL}}", &escape_html(&browser_url))
+        .replace("{{STORE_LINKS}}", &stores);
+    Html(state.assets.link(&html)).into_response()
+}
+
+/// iOS apps allowed to open `/join/` links
+pub async fn apple_app_site_association(State(state): State<AppState>) -> Response {
+    let config = state.config();
+    let Some(ids) = config
+        .deep_links
+        .as_ref()
+        .map(|l| &l.apple_app_ids)
+        .filter(|ids| !ids.is_empty())
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    Json(json!({
+        "applinks": {
+            "details": [{
+                "appIDs": ids,
+                "components": [{"/": "/join/*"}]
+            }]
+        }
+    }))
+    .into_response()
+}
+
+/// Android package allowed to open `/join/` links
+pub async fn asset_links(State(state): State<AppState>) -> Response {
+    let config = state.config();
+    let Some((package, settings)) = config
+        .deep_links
+        .as_ref()
+        .and_then(|l| Some((l.android_package.as_ref()?, l)))
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    Json(json!([{
+        "relation": ["delegate_permission/common.handle_all_urls"],
+        "target": {
+            "namespace": "android_app",
+            "package_name": package,
+            "sha256_cert_fingerprints": settings.android_cert_fingerprints
+        }
+    }]))
+    .into_response()
+}
@@ -0,0 +1,71 @@
+//! Peer device details
+//!
+//! After joining, a client may send `device_info` with its camera and
+//! microphone labels, operating system and browser. The room relays it to
+//! the other peers with the sender's ID in `from`, so their UI can say
+//! "Alice is on mobile", and hands the details of everyone present to
+//! later joiners. Each report is also kept on the call record for
+//! diagnostics. Deployments that treat device labels as private set
+//! `device_info` to false; reports are then dropped unread.
+
+use crate::models::{DeviceInfo, PeerDevice, WsMessage};
+use crate::state::{AppState, Room, unix_timestamp};
+
+/// Device reports kept per call
+pub const MAX_CALL_DEVICES: usize = 1000;
+
+impl DeviceInfo {
+    /// The relayed `DeviceInfo` message for a peer
+    pub fn message(&self, peer_id: &str) -> WsMessage {
+        WsMessage::DeviceInfo {
+            camera_label: self.camera_label.clone(),
+            mic_label: self.mic_label.clone(),
+            os: self.os.clone(),
+            browser: self.browser.clone(),
+            from: Some(peer_id.to_string()),
+        }
+    }
+}
+
+impl Room {
+    /// Tell a newly joined peer about the devices of those already here
+    pub fn send_devices_to(&self, peer_id: &str) {
+        for peer in self.peers.iter().filter(|p| p.id != peer_id) {
+            if let Some(device) = &peer.device {
+                self.send_to(peer_id, device.message(&peer.id));
+            }
+        }
+    }
+}
+
+impl AppState {
+    /// Keep a peer's device report and relay it to the rest of the room
+    pub async fn register_device(&self, room_id: &str, peer_id: &str, device: DeviceInfo) {
+        if !self.config().device_info {
+            return;
+        }
+        let id = peer_id.to_string();
+        let stored = device.clone();
+        let present = self.with_room(room_id, move |room| {
+            let msg = stored.message(&id);
+            let Some(peer) = room.peers.iter_mut().find(|p| p.id == id) else {
+                return false;
+            };
+            peer.device = Some(stored);
+            let _ = room.route(&id, msg);
+            true
+        });
+        if present.await != Some(true) {
+            return;
+        }
+        if let Some(call) = self.calls.lock().await.get_mut(room_id)
+            && call.devices.len() < MAX_CALL_DEVICES
+        {
+            call.devices.push(PeerDevice {
+                peer_id: peer_id.to_string(),
+                device,
+                at: unix_timestamp(),
+            });
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! One-way media diagnostics
+//!
+//! Clients periodically report how many media bytes they have sent and
+//! received with `media_stats`. Between two reports a peer's counters show
+//! what it sent and what reached it. When one peer keeps sending audio or
+//! video while another receives none of it over a whole interval, media
+//! flows only one way between them, usually because a firewall drops UDP in
+//! one direction. Both peers get a `diagnostic_hint` with a suggestion and
+//! the incident goes into the room's timeline. A hint is sent once per
+//! receiver and kind of media, re-arming once that media arrives.
+
+use tracing::warn;
+
+use crate::models::{DiagnosticIssue, MediaBytes, TimelineKind, WsMessage};
+use crate::state::{AppState, Room};
+
+/// What to try when media does not get through
+const REMEDY: &str = "Check that the firewall allows UDP, or reconnect through a TURN relay.";
+
+/// Media counters of one peer
+#[derive(Debug, Default)]
+pub struct MediaFlow {
+    /// Counters of the latest report: sent, received
+    last: Option<(MediaBytes, MediaBytes)>,
+    /// Bytes sent and received between the last two reports
+    delta: Option<(MediaBytes, MediaBytes)>,
+    /// Issues already hinted with this peer as the receiver
+    hinted: Vec<DiagnosticIssue>,
+}
+
+impl MediaFlow {
+    fn record(&mut self, sent: MediaBytes, received: MediaBytes) {
+        self.delta = self.last.and_then(|(last_sent, last_received)| {
+            // Counters restart with a new peer connection
+            Some((since(sent, last_sent)?, since(received, last_received)?))
+        });
+        self.last = Some((sent, received));
+    }
+
+    /// Bytes sent and received between the last two reports
+    pub fn delta(&self) -> Option<(MediaBytes, MediaBytes)> {
+        self.delta
+    }
+}
+
+/// Bytes added since `then`, unless the counters went backwards
+fn since(now: MediaBytes, then: MediaBytes) -> Option<MediaBytes> {
+    Some(MediaBytes {
+        audio: now.audio.checked_sub(then.audio)?,
+        video: now.video.checked_sub(then.video)?,
+    })
+}
+
+impl DiagnosticIssue {
+    const ALL: [DiagnosticIssue; 2] = [DiagnosticIssue::OneWayAudio, DiagnosticIssue::OneWayVideo];
+
+    fn media(self) -> &'static str {
+        match self {
+            DiagnosticIssue::OneWayAudio => "audio",
+            DiagnosticIssue::OneWayVideo => "video",
+        }
+    }
+
+    fn bytes(self, counters: MediaBytes) -> u64 {
+        match self {
+            DiagnosticIssue::OneWayAudio => counters.audio,
+            DiagnosticIssue::OneWayVideo => counters.video,
+        }
+    }
+}
+
+/// Media one peer sent that did not reach another
+#[derive(Debug)]
+pub struct MediaIncident {
+    pub issue: DiagnosticIssue,
+    pub sender: String,
+    pub receiver: String,
+}
+
+impl Room {
+    /// Look for media that flows one way, hinting both ends of each new
+    /// incident
+    pub fn diagnose_media(&mut self) -> Vec<MediaIncident> {
+        let mut incidents = Vec::new();
+        for receiver in 0..self.peers.len() {
+            let Some((_, received)) = self.peers[receiver].media.delta else {
+                continue;
+            };
+            for issue in DiagnosticIssue::ALL {
+                let flow = &mut self.peers[receiver].media;
+                if issue.bytes(received) > 0 {
+                    flow.hinted.retain(|i| *i != issue);
+                    continue;
+                }
+                if flow.hinted.contains(&issue) {
+                    continue;
+                }
+                let sender = self.peers.iter().enumerate().find(|(i, p)| {
+                    *i != receiver && p.media.delta.is_some_and(|(sent, _)| issue.bytes(sent) > 0)
+                });
+                let Some((_, sender)) = sender else {
+                    continue;
+                };
+                incidents.push(MediaIncident {
+                    issue,
+                    sender: sender.id.clone(),
+                    receiver: self.peers[receiver].id.clone(),
+                });
+                self.peers[receiver].media.hinted.push(issue);
+            }
+        }
+
+        for incident in &incidents {
+            let media = incident.issue.media();
+            let hints = [
+                (
+                    &incident.receiver,
+                    format!("No {} is arriving from the other side. {}", media, REMEDY),
+                ),
+                (
+                    &incident.sender,
+                    format!("Your {} is not reaching the other side. {}", media, REMEDY),
+                ),
+            ];
+            for (peer_id, suggestion) in hints {
+                let hint = WsMessage::DiagnosticHint {
+                    issue: incident.issue,
+                    suggestion,
+                };
+                self.send_to(peer_id, hint);
+            }
+        }
+        incidents
+    }
+}
+
+impl AppState {
+    /// Record a peer's media counters and diagnose one-way media
+    pub async fn record_media_stats(
+        &self,
+        room_id: &str,
+        peer_id: &str,
+        sent: MediaBytes,
+        received: MediaBytes,
+    ) {
+        let id = peer_id.to_string();
+        let diagnosed = self.with_room(room_id, move |room| {
+            room.peers
+                .iter_mut()
+                .find(|p| p.id == id)?
+                .media
+                .record(sent, received);
+            Some((room.diagnose_media(), room.note_starvation(&id)))
+        });
+        let Some(Some((incidents, starved))) = diagnosed.await else {
+            return;
+        };
+        if let Some(other) = starved {
+            self.schedule_ice_restart(room_id, peer_id, &other);
+        }
+
+        for incident in incidents {
+            let media = incident.issue.media();
+            warn!(
+                "In room {}, {} from {} is not reaching {}",
+                room_id, media, incident.sender, incident.receiver
+            );
+            let detail = format!("No {} arriving from {}", media, incident.sender);
+            self.record_timeline(
+                room_id,
+                TimelineKind::OneWayMedia,
+                Some(&incident.receiver),
+                detail,
+            )
+            .await;
+        }
+    }
+}
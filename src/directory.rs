@@ -0,0 +1,504 @@
+//! Directory lookup for invites
+//!
+//! With a `directory` section, `GET /api/directory/search?q=` looks people
+//! up in an LDAP directory such as Active Directory, so the invite form can
+//! suggest colleagues while an invite-only room is being set up. Only
+//! holders of a user token may search, and only the name, email and
+//! subject attributes come back; the directory itself is never exposed.
+//!
+//! Each search binds (anonymously without `bind_dn`), finds entries of
+//! `object_class` whose search attributes start with the query, and
+//! unbinds. With `tls` the connection is encrypted, either from the start
+//! (`ldaps`) or after a StartTLS request (`start_tls`), and the server's
+//! certificate is checked against `ca_file` or the web PKI roots. A
+//! `bind_password` is refused without TLS, so it never crosses the network
+//! in the clear.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls;
+use tracing::warn;
+
+use crate::models::DirectoryEntry;
+use crate::state::AppState;
+use crate::token::UserAuth;
+
+/// Shortest query searched for
+pub const MIN_QUERY_CHARS: usize = 2;
+
+/// How long one search, from connecting to the last result, may take
+const LDAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest LDAP message read
+const MAX_MESSAGE_BYTES: usize = 1 << 20;
+
+/// LDAP result codes accepted at the end of a search: success and size
+/// limit exceeded
+const SEARCH_DONE_CODES: [u32; 2] = [0, 4];
+
+/// Name of the StartTLS extended operation
+const START_TLS_OID: &str = "1.3.6.1.4.1.1466.20037";
+
+/// How the connection to the directory is secured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectoryTls {
+    /// Plain LDAP; only for anonymous binds
+    #[default]
+    None,
+    /// TLS from the start, usually on port 636
+    Ldaps,
+    /// Plain LDAP upgraded with StartTLS before binding, usually on port 389
+    StartTls,
+}
+
+/// The directory server and which attributes to use
+#[derive(Debug, Clone, Deserialize)]
+pub struct DirectoryConfig {
+    /// LDAP server as `host:port`; the host is checked against the
+    /// server's certificate
+    pub server: String,
+    #[serde(default)]
+    pub tls: DirectoryTls,
+    /// PEM certificates to trust instead of the web PKI roots, e.g. an
+    /// internal CA
+    #[serde(default)]
+    pub ca_file: Option<std::path::PathBuf>,
+    /// DN to bind as; binds anonymously when unset
+    #[serde(default)]
+    pub bind_dn: Option<String>,
+    /// Needs `tls`
+    #[serde(default)]
+    pub bind_password: Option<String>,
+    /// Where searches start, e.g. `dc=example,dc=com`
+    pub base_dn: String,
+    /// Object class of people
+    #[serde(default = "default_object_class")]
+    pub object_class: String,
+    /// Attributes the query is matched against, as a prefix
+    #[serde(default = "default_search_attributes")]
+    pub search_attributes: Vec<String>,
+    #[serde(default = "default_name_attribute")]
+    pub name_attribute: String,
+    #[serde(default = "default_email_attribute")]
+    pub email_attribute: String,
+    /// Attribute holding the token subject people sign in with, if not
+    /// their email
+    #[serde(default)]
+    pub subject_attribute: Option<String>,
+    /// Most entries returned per search
+    #[serde(default = "default_max_results")]
+    pub max_results: u32,
+}
+
+fn default_object_class() -> String {
+    "person".to_string()
+}
+
+fn default_search_attributes() -> Vec<String> {
+    ["displayName", "mail", "sAMAccountName"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_name_attribute() -> String {
+    "displayName".to_string()
+}
+
+fn default_email_attribute() -> String {
+    "mail".to_string()
+}
+
+fn default_max_results() -> u32 {
+    10
+}
+
+impl DirectoryConfig {
+    /// Refuse settings that would send the bind password in the clear
+    pub fn validate(&self) -> Result<(), String> {
+        if self.bind_password.is_some() && self.tls == DirectoryTls::None {
+            return Err("directory.bind_password needs directory.tls to be set".to_string());
+        }
+        Ok(())
+    }
+
+    /// TLS client trusting `ca_file`, or the web PKI roots without one
+    fn tls_connector(&self) -> Result<TlsConnector, String> {
+        use rustls::pki_types::CertificateDer;
+        use rustls::pki_types::pem::PemObject;
+
+        let mut roots = rustls::RootCertStore::empty();
+        match &self.ca_file {
+            Some(path) => {
+                let invalid = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+                for cert in CertificateDer::pem_file_iter(path).map_err(|e| invalid(&e))? {
+                    roots
+                        .add(cert.map_err(|e| invalid(&e))?)
+                        .map_err(|e| invalid(&e))?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let client = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(TlsConnector::from(Arc::new(client)))
+    }
+
+    /// Host part of `server`, as named in its certificate
+    fn host(&self) -> &str {
+        let host = self
+            .server
+            .rsplit_once(':')
+            .map_or(self.server.as_str(), |(host, _)| host);
+        host.trim_start_matches('[').trim_end_matches(']')
+    }
+
+    /// Attributes requested for each entry
+    fn attributes(&self) -> Vec<&str> {
+        let mut attributes = vec![self.name_attribute.as_str(), self.email_attribute.as_str()];
+        attributes.extend(self.subject_attribute.as_deref());
+        attributes
+    }
+
+    /// `(&(objectClass=<class>)(|(<attr>=<query>*)...))`
+    fn filter(&self, query: &str) -> Vec<u8> {
+        let prefixes: Vec<u8> = self
+            .search_attributes
+            .iter()
+            .flat_map(|attribute| {
+                let initial = tlv(0x80, query.as_bytes());
+                tlv(0xa4, &[octets(attribute), tlv(0x30, &initial)].concat())
+            })
+            .collect();
+        let class = tlv(
+            0xa3,
+            &[octets("objectClass"), octets(&self.object_class)].concat(),
+        );
+        tlv(0xa0, &[class, tlv(0xa1, &prefixes)].concat())
+    }
+}
+
+/// People whose search attributes start with `query`
+pub async fn search_directory(
+    config: &DirectoryConfig,
+    query: &str,
+) -> Result<Vec<DirectoryEntry>, String> {
+    tokio::time::timeout(LDAP_TIMEOUT, search(config, query))
+        .await
+        .map_err(|_| "Timed out".to_string())?
+}
+
+/// A connection to the directory, encrypted or not
+trait LdapStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> LdapStream for S {}
+
+/// Connect to the directory, securing the connection as configured
+async fn connect(config: &DirectoryConfig) -> Result<Box<dyn LdapStream>, String> {
+    config.validate()?;
+    let mut tcp = TcpStream::connect(&config.server)
+        .await
+        .map_err(|e| e.to_string())?;
+    if config.tls == DirectoryTls::None {
+        return Ok(Box::new(tcp));
+    }
+    if config.tls == DirectoryTls::StartTls {
+        send(
+            &mut tcp,
+            1,
+            &tlv(0x77, &tlv(0x80, START_TLS_OID.as_bytes())),
+        )
+        .await?;
+        match read_message(&mut tcp).await? {
+            (0x78, result) => match result_code(&result)? {
+                0 => {}
+                code => return Err(format!("StartTLS failed with result {}", code)),
+            },
+            (op, _) => return Err(format!("Unexpected answer {:#x} to StartTLS", op)),
+        }
+    }
+    let name = rustls::pki_types::ServerName::try_from(config.host().to_string())
+        .map_err(|e| e.to_string())?;
+    let tls = config
+        .tls_connector()?
+        .connect(name, tcp)
+        .await
+        .map_err(|e| format!("TLS: {}", e))?;
+    Ok(Box::new(tls))
+}
+
+async fn search(config: &DirectoryConfig, query: &str) -> Result<Vec<DirectoryEntry>, String> {
+    let mut stream = connect(config).await?;
+
+    let bind = tlv(
+        0x60,
+        &[
+            integer(0x02, 3),
+            octets(config.bind_dn.as_deref().unwrap_or_default()),
+            tlv(
+                0x80,
+                config
+                    .bind_password
+                    .as_deref()
+                    .unwrap_or_default()
+                    .as_bytes(),
+            ),
+        ]
+        .concat(),
+    );
+    send(&mut stream, 2, &bind).await?;
+    match read_message(&mut stream).await? {
+        (0x61, result) => match result_code(&result)? {
+            0 => {}
+            code => return Err(format!("Bind failed with result {}", code)),
+        },
+        (op, _) => return Err(format!("Unexpected answer {:#x} to bind", op)),
+    }
+
+    let attributes: Vec<u8> = config.attributes().into_iter().flat_map(octets).collect();
+    let request = tlv(
+        0x63,
+        &[
+            octets(&config.base_dn),
+            // Whole subtree, never dereferencing aliases
+            integer(0x0a, 2),
+            integer(0x0a, 0),
+            integer(0x02, config.max_results),
+            integer(0x02, LDAP_TIMEOUT.as_secs() as u32),
+            tlv(0x01, &[0]),
+            config.filter(query),
+            tlv(0x30, &attributes),
+        ]
+        .concat(),
+    );
+    send(&mut stream, 3, &request).await?;
+
+    let mut entries = Vec::new();
+    loop {
+        match read_message(&mut stream).await? {
+            (0x64, entry) => entries.push(read_entry(config, &entry)?),
+            // Referrals to other servers are not followed
+            (0x73, _) => {}
+            (0x65, result) => match result_code(&result)? {
+                code if SEARCH_DONE_CODES.contains(&code) => break,
+                code => return Err(format!("Search failed with result {}", code)),
+            },
+            (op, _) => return Err(format!("Unexpected answer {:#x} to search", op)),
+        }
+    }
+    // Unbind; the server closes the connection without answering
+    let _ = send(&mut stream, 4, &[0x42, 0x00]).await;
+    entries.truncate(config.max_results as usize);
+    Ok(entries)
+}
+
+/// A search result entry, keeping the configured attributes
+fn read_entry(config: &DirectoryConfig, entry: &[u8]) -> Result<DirectoryEntry, String> {
+    let malformed = || "Malformed search result".to_string();
+    let (_, _dn, rest) = ber_element(entry).ok_or_else(malformed)?;
+    let (_, mut attributes, _) = ber_element(rest).ok_or_else(malformed)?;
+    let mut found = DirectoryEntry::default();
+    while !attributes.is_empty() {
+        let (_, attribute, rest) = ber_element(attributes).ok_or_else(malformed)?;
+        attributes = rest;
+        let (_, name, values) = ber_element(attribute).ok_or_else(malformed)?;
+        let (_, values, _) = ber_element(values).ok_or_else(malformed)?;
+        let value = ber_element(values)
+            .map(|(_, value, _)| String::from_utf8_lossy(value).into_owned())
+            .filter(|v| !v.is_empty());
+        let name = String::from_utf8_lossy(name);
+        // Attribute names are not case-sensitive
+        let slot = if name.eq_ignore_ascii_case(&config.name_attribute) {
+            &mut found.name
+        } else if name.eq_ignore_ascii_case(&config.email_attribute) {
+            &mut found.email
+        } else if config
+            .subject_attribute
+            .as_deref()
+            .is_some_and(|s| name.eq_ignore_ascii_case(s))
+        {
+            &mut found.subject
+        } else {
+            continue;
+        };
+        *slot = value;
+    }
+    Ok(found)
+}
+
+/// Result code of an LDAPResult
+fn result_code(result: &[u8]) -> Result<u32, String> {
+    match ber_element(result) {
+        Some((0x0a, code, _)) if code.len() <= 4 => {
+            Ok(code.iter().fold(0, |value, b| (value << 8) | u32::from(*b)))
+        }
+        _ => Err("Malformed LDAP result".to_string()),
+    }
+}
+
+/// Send `op` as message `id`
+async fn send<S: AsyncWrite + Unpin + ?Sized>(
+    stream: &mut S,
+    id: u32,
+    op: &[u8],
+) -> Result<(), String> {
+    let message = tlv(0x30, &[integer(0x02, id), op.to_vec()].concat());
+    stream.write_all(&message).await.map_err(|e| e.to_string())
+}
+
+/// Tag and content of the operation in the next message
+async fn read_message<S: AsyncRead + Unpin + ?Sized>(
+    stream: &mut S,
+) -> Result<(u8, Vec<u8>), String> {
+    let mut head = [0; 2];
+    stream
+        .read_exact(&mut head)
+        .await
+        .map_err(|e| e.to_string())?;
+    let len = match head[1] {
+        short if short < 0x80 => usize::from(short),
+        long => {
+            let count = usize::from(long & 0x7f);
+            if !(1..=4).contains(&count) {
+                return Err("Unsupported LDAP message length".to_string());
+            }
+            let mut bytes = [0; 4];
+            stream
+                .read_exact(&mut bytes[4 - count..])
+                .await
+                .map_err(|e| e.to_string())?;
+            u32::from_be_bytes(bytes) as usize
+        }
+    };
+    if head[0] != 0x30 || len > MAX_MESSAGE_BYTES {
+        return Err("Not an LDAP message".to_string());
+    }
+    let mut message = vec![0; len];
+    stream
+        .read_exact(&mut message)
+        .await
+        .map_err(|e| e.to_string())?;
+    let malformed = || "Malformed LDAP message".to_string();
+    let (_, _id, rest) = ber_element(&message).ok_or_else(malformed)?;
+    let (op, content, _) = ber_element(rest).ok_or_else(malformed)?;
+    Ok((op, content.to_vec()))
+}
+
+/// A BER element: tag, definite length, content
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match content.len() {
+        len if len < 0x80 => out.push(len as u8),
+        len => {
+            let bytes = (len as u32).to_be_bytes();
+            let skip = bytes.iter().take_while(|b| **b == 0).count();
+            out.push(0x80 | (4 - skip) as u8);
+            out.extend_from_slice(&bytes[skip..]);
+        }
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn octets(value: &str) -> Vec<u8> {
+    tlv(0x04, value.as_bytes())
+}
+
+/// A non-negative INTEGER (tag 0x02) or ENUMERATED (tag 0x0a)
+fn integer(tag: u8, value: u32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(3);
+    // A leading 1 bit would make it negative
+    let pad = bytes[skip] & 0x80 != 0;
+    let content: Vec<u8> = pad
+        .then_some(0)
+        .into_iter()
+        .chain(bytes[skip..].iter().copied())
+        .collect();
+    tlv(tag, &content)
+}
+
+/// Tag, content and what follows of the BER element `input` starts with
+fn ber_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        short if short < 0x80 => (usize::from(short), rest),
+        long => {
+            let count = usize::from(long & 0x7f);
+            if !(1..=4).contains(&count) {
+                return None;
+            }
+            let (bytes, rest) = rest.split_at_checked(count)?;
+            let len = bytes
+                .iter()
+                .fold(0usize, |len, b| (len << 8) | usize::from(*b));
+            (len, rest)
+        }
+    };
+    let (content, rest) = rest.split_at_checked(len)?;
+    Some((tag, content, rest))
+}
+
+/// Query parameters of a directory search
+#[derive(Debug, Deserialize)]
+pub struct DirectoryQuery {
+    pub q: String,
+}
+
+/// Search the directory for people to invite
+#[utoipa::path(
+    get,
+    path = "/api/directory/search",
+    tag = "Rooms",
+    params(
+        ("q" = String, Query, description = "Start of a name, email or account name")
+    ),
+    responses(
+        (status = 200, description = "Matching people, at most `max_results`", body = Vec<DirectoryEntry>),
+        (status = 400, description = "Query shorter than 2 characters"),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 404, description = "No directory is configured"),
+        (status = 502, description = "The directory could not be searched")
+    )
+)]
+pub async fn directory_search(
+    State(state): State<AppState>,
+    _auth: UserAuth,
+    Query(query): Query<DirectoryQuery>,
+) -> Response {
+    let config = state.config();
+    let Some(directory) = config.directory.as_ref() else {
+        return (StatusCode::NOT_FOUND, "No directory is configured").into_response();
+    };
+    let q = query.q.trim();
+    if q.chars().count() < MIN_QUERY_CHARS {
+        return (StatusCode::BAD_REQUEST, "Query is too short").into_response();
+    }
+    match search_directory(directory, q).await {
+        Ok(entries) => Json(entries).into_response(),
+        Err(e) => {
+            warn!("Directory search failed: {}", e);
+            (
+                StatusCode::BAD_GATEWAY,
+                "The directory could not be searched",
+            )
+                .into_response()
+        }
+    }
+}
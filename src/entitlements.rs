@@ -0,0 +1,299 @@
+//! Entitlement codes for room creation
+//!
+//! With an `entitlements` section, operators mint codes with
+//! `POST /admin/entitlements` and hand them out, e.g. to a workshop's
+//! attendees. A code creates up to `rooms` rooms before it expires, and may
+//! carry a `max_duration_secs` that lifts its rooms' duration limit past
+//! the server default (see [`crate::call_limits`]). While `required` is
+//! set, rooms created without a tenant API key need a code: anonymous users
+//! get room creation rights without an account. That covers every way a
+//! room comes about: `POST /api/create-room`, `GET /` (with `?code=`) and
+//! joining an unknown room, which then fails instead of starting it. A code
+//! is checked before anything else about the request, and a request that
+//! fails later gives its room back, so codes are only used up by rooms that
+//! were created. They live in memory and are lost on restart.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::admin::{AdminAuth, OperatorAuth};
+use crate::models::{Entitlement, EntitlementRequest};
+use crate::state::{AppState, unix_timestamp};
+
+/// Most codes kept; expired and used-up ones are dropped first
+pub const MAX_ENTITLEMENTS: usize = 10_000;
+
+/// Characters of a code: Crockford's base32, without look-alikes
+const CODE_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Characters in a code, shown in groups of four
+const CODE_LEN: usize = 12;
+
+/// Minted codes by their characters, without dashes
+pub type Entitlements = HashMap<String, Entitlement>;
+
+/// Whether room creation takes codes, and how long they last
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EntitlementsConfig {
+    /// Whether rooms created without an API key need a code
+    pub required: bool,
+    /// Lifetime of codes minted without `expires_in_secs`
+    pub default_ttl_secs: u64,
+}
+
+impl Default for EntitlementsConfig {
+    fn default() -> Self {
+        Self {
+            required: true,
+            default_ttl_secs: 30 * 24 * 3600,
+        }
+    }
+}
+
+/// A new random code, as `XXXX-XXXX-XXXX`
+fn new_code() -> Result<String, &'static str> {
+    let mut bytes = [0u8; CODE_LEN];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| "No randomness available")?;
+    let chars: Vec<char> = bytes
+        .iter()
+        .map(|b| CODE_ALPHABET[(b % 32) as usize] as char)
+        .collect();
+    Ok(chars
+        .chunks(4)
+        .map(|group| group.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-"))
+}
+
+/// A code as typed, reduced to its characters; case and dashes do not
+/// matter, and the letters Crockford's base32 leaves out read as the
+/// digits they look like
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        })
+        .collect()
+}
+
+impl Entitlement {
+    /// Whether the code can still create a room at unix time `now`
+    pub fn is_live(&self, now: u64) -> bool {
+        now < self.expires_at && (self.redeemed.len() as u32) < self.rooms
+    }
+}
+
+type Rejection = (StatusCode, &'static str);
+
+impl AppState {
+    /// Check the code given for a new room and use it up for `room_id`
+    ///
+    /// Returns the duration limit the code grants. A request with a tenant
+    /// API key (`keyed`) needs no code.
+    pub async fn redeem_entitlement(
+        &self,
+        code: Option<&str>,
+        room_id: &str,
+        keyed: bool,
+    ) -> Result<Option<u64>, Rejection> {
+        let config = self.config();
+        let Some(entitlements) = config.entitlements.as_ref() else {
+            return Ok(None);
+        };
+        let Some(code) = code else {
+            if entitlements.required && !keyed {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    "An entitlement code is needed to create a room",
+                ));
+            }
+            return Ok(None);
+        };
+        let now = unix_timestamp();
+        let mut codes = self.entitlements.lock().await;
+        let entitlement = codes
+            .get_mut(&normalize_code(code))
+            .filter(|e| e.is_live(now))
+            .ok_or((StatusCode::FORBIDDEN, "Invalid or used-up entitlement code"))?;
+        entitlement.redeemed.push(room_id.to_string());
+        let (grant, label) = (entitlement.max_duration_secs, entitlement.label.clone());
+        drop(codes);
+        info!("Room {} created with an entitlement code", room_id);
+        let detail = label.unwrap_or_default();
+        self.record_audit(Some(room_id), "anonymous", "entitlement.redeem", detail)
+            .await;
+        Ok(grant)
+    }
+
+    /// Give back the room a failed request redeemed `code` for
+    pub async fn release_entitlement(&self, code: Option<&str>, room_id: &str) {
+        let Some(code) = code else {
+            return;
+        };
+        let mut codes = self.entitlements.lock().await;
+        let Some(entitlement) = codes.get_mut(&normalize_code(code)) else {
+            return;
+        };
+        let before = entitlement.redeemed.len();
+        entitlement.redeemed.retain(|id| id != room_id);
+        let released = entitlement.redeemed.len() < before;
+        drop(codes);
+        if released {
+            self.record_audit(Some(room_id), "anonymous", "entitlement.release", "")
+                .await;
+        }
+    }
+
+    /// Whether joining an unknown room may start it; not while rooms need
+    /// entitlement codes
+    pub fn may_start_rooms(&self) -> bool {
+        !self
+            .config()
+            .entitlements
+            .as_ref()
+            .is_some_and(|e| e.required)
+    }
+}
+
+/// Mint an entitlement code
+#[utoipa::path(
+    post,
+    path = "/admin/entitlements",
+    tag = "Admin",
+    request_body(content = Option<EntitlementRequest>, description = "What the code grants"),
+    responses(
+        (status = 200, description = "The new code", body = Entitlement),
+        (status = 400, description = "The code would never be usable"),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "Entitlement codes are not enabled")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn create_entitlement(
+    OperatorAuth(auth): OperatorAuth,
+    State(state): State<AppState>,
+    body: Option<Json<EntitlementRequest>>,
+) -> Response {
+    let config = state.config();
+    let Some(settings) = config.entitlements.as_ref() else {
+        return (StatusCode::NOT_FOUND, "Entitlement codes are not enabled").into_response();
+    };
+    let Json(request) = body.unwrap_or_default();
+    let rooms = request.rooms.unwrap_or(1);
+    if rooms == 0 || request.expires_in_secs == Some(0) || request.max_duration_secs == Some(0) {
+        return (StatusCode::BAD_REQUEST, "Code would never be usable").into_response();
+    }
+    let code = match new_code() {
+        Ok(code) => code,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    };
+    let now = unix_timestamp();
+    let entitlement = Entitlement {
+        code: code.clone(),
+        label: request.label,
+        rooms,
+        redeemed: Vec::new(),
+        max_duration_secs: request.max_duration_secs,
+        expires_at: now + request.expires_in_secs.unwrap_or(settings.default_ttl_secs),
+        created_by: auth.principal.clone(),
+        created_at: now,
+    };
+
+    let mut codes = state.entitlements.lock().await;
+    if codes.len() >= MAX_ENTITLEMENTS {
+        codes.retain(|_, e| e.is_live(now));
+    }
+    if codes.len() >= MAX_ENTITLEMENTS {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many entitlement codes",
+        )
+            .into_response();
+    }
+    codes.insert(normalize_code(&code), entitlement.clone());
+    drop(codes);
+
+    info!("Minted an entitlement code for {} rooms", rooms);
+    let detail = format!(
+        "rooms={} max_duration_secs={:?} expires_at={}",
+        rooms, entitlement.max_duration_secs, entitlement.expires_at
+    );
+    state
+        .record_audit(None, &auth.principal, "entitlement.create", detail)
+        .await;
+    Json(entitlement).into_response()
+}
+
+/// List entitlement codes, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/entitlements",
+    tag = "Admin",
+    responses(
+        (status = 200, description = "Codes with the rooms created with them", body = Vec<Entitlement>),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_entitlements(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+) -> Json<Vec<Entitlement>> {
+    let mut codes: Vec<Entitlement> = state.entitlements.lock().await.values().cloned().collect();
+    codes.sort_by_key(|e| Reverse(e.created_at));
+    Json(codes)
+}
+
+/// Revoke an entitlement code
+#[utoipa::path(
+    delete,
+    path = "/admin/entitlements/{code}",
+    tag = "Admin",
+    params(
+        ("code" = String, Path, description = "The code, with or without dashes")
+    ),
+    responses(
+        (status = 204, description = "Code revoked"),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "No such code")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn revoke_entitlement(
+    OperatorAuth(auth): OperatorAuth,
+    Path(code): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    let removed = state
+        .entitlements
+        .lock()
+        .await
+        .remove(&normalize_code(&code));
+    let Some(entitlement) = removed else {
+        return (StatusCode::NOT_FOUND, "No such code").into_response();
+    };
+    info!("Revoked an entitlement code");
+    let detail = entitlement.label.unwrap_or_default();
+    state
+        .record_audit(None, &auth.principal, "entitlement.revoke", detail)
+        .await;
+    StatusCode::NO_CONTENT.into_response()
+}
@@ -0,0 +1,421 @@
+//! Event rooms with registration
+//!
+//! A room created with `event` is joined by registering: `POST
+//! /api/room/{room_id}/register` with a name and email returns a ticket
+//! with a personal room token, while fewer than `capacity` people hold a
+//! place. Past that, registrations go on a waitlist when `waitlist` is set
+//! and are refused otherwise; a cancellation moves the first in line up.
+//! The ticket's `id` is all its holder needs to look the registration up
+//! again, e.g. to collect their token after moving up, or to cancel it.
+//!
+//! Event rooms are invite-only (see [`crate::invites`]): registrants are
+//! let in by their token's subject, and speakers or organizers are listed
+//! as `invitees` when the room is created. Combined with broadcast mode,
+//! this makes a small webinar. Event rooms wait for their first join
+//! without being reaped. Operators get attendance, who came and for how
+//! long, at `GET /admin/rooms/{room_id}/attendance`, until the room is
+//! purged from the archive.
+
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::admin::AdminAuth;
+use crate::models::{
+    AttendanceReport, Attendee, EventSettings, RegistrationRequest, RegistrationStatus,
+    RegistrationTicket,
+};
+use crate::state::{AppState, unix_timestamp};
+use crate::token::{self, Claims, TokenScope};
+
+/// Registrations kept per event, cancelled and waitlisted ones included
+pub const MAX_REGISTRATIONS: usize = 100_000;
+
+/// Lifetime of a registrant's room token
+pub const REGISTRATION_TOKEN_TTL_SECS: u64 = 30 * 24 * 3600;
+
+/// Longest name accepted
+const MAX_NAME_LEN: usize = 100;
+
+/// Longest email accepted
+const MAX_EMAIL_LEN: usize = 254;
+
+/// Registrations of each event room, by room ID
+pub type EventRegistrations = HashMap<String, EventRegistry>;
+
+/// One event's registrations, in order
+#[derive(Debug, Clone)]
+pub struct EventRegistry {
+    pub event: EventSettings,
+    pub attendees: Vec<Attendee>,
+    /// Registrants in the room, by peer ID, with when they joined
+    present: HashMap<String, (String, u64)>,
+}
+
+impl EventRegistry {
+    fn count(&self, status: RegistrationStatus) -> usize {
+        self.attendees.iter().filter(|a| a.status == status).count()
+    }
+
+    /// Place of `id` on the waitlist, from 1
+    fn position(&self, id: &str) -> Option<usize> {
+        self.attendees
+            .iter()
+            .filter(|a| a.status == RegistrationStatus::Waitlisted)
+            .position(|a| a.id == id)
+            .map(|i| i + 1)
+    }
+}
+
+/// Token subject of a registration
+fn subject(id: &str) -> String {
+    format!("registration:{}", id)
+}
+
+type Rejection = (StatusCode, &'static str);
+
+impl AppState {
+    /// Start taking registrations for an event room
+    pub async fn open_event(&self, room_id: &str, event: EventSettings) {
+        self.event_registrations.lock().await.insert(
+            room_id.to_string(),
+            EventRegistry {
+                event,
+                attendees: Vec::new(),
+                present: HashMap::new(),
+            },
+        );
+    }
+
+    /// A registration as its holder sees it, with a fresh token while it
+    /// holds a place
+    fn ticket(
+        &self,
+        room_id: &str,
+        registry: &EventRegistry,
+        attendee: &Attendee,
+    ) -> RegistrationTicket {
+        let config = self.config();
+        let token = (attendee.status == RegistrationStatus::Registered)
+            .then_some(config.jwt_secret.as_deref())
+            .flatten()
+            .map(|secret| {
+                let mut claims = Claims::new(
+                    TokenScope::Room,
+                    Some(room_id.to_string()),
+                    REGISTRATION_TOKEN_TTL_SECS,
+                );
+                claims.sub = Some(subject(&attendee.id));
+                claims.email = Some(attendee.email.clone());
+                token::mint(secret, &claims)
+            });
+        let url = token.as_ref().map(|token| {
+            format!(
+                "{}/room/{}?token={}",
+                config.public_url.trim_end_matches('/'),
+                room_id,
+                token
+            )
+        });
+        RegistrationTicket {
+            id: attendee.id.clone(),
+            status: attendee.status,
+            position: registry.position(&attendee.id),
+            token,
+            url,
+        }
+    }
+
+    /// Register for an event room, taking a place or one on the waitlist
+    pub async fn register(
+        &self,
+        room_id: &str,
+        request: RegistrationRequest,
+    ) -> Result<RegistrationTicket, Rejection> {
+        let name = request.name.trim();
+        let email = request.email.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            return Err((StatusCode::BAD_REQUEST, "Invalid name"));
+        }
+        if !email.contains('@') || email.len() > MAX_EMAIL_LEN {
+            return Err((StatusCode::BAD_REQUEST, "Invalid email"));
+        }
+        let mut events = self.event_registrations.lock().await;
+        let registry = events
+            .get_mut(room_id)
+            .ok_or((StatusCode::NOT_FOUND, "No such event"))?;
+        if registry.attendees.iter().any(|a| {
+            a.status != RegistrationStatus::Cancelled && a.email.eq_ignore_ascii_case(email)
+        }) {
+            return Err((StatusCode::CONFLICT, "This email is already registered"));
+        }
+        if registry.attendees.len() >= MAX_REGISTRATIONS {
+            return Err((StatusCode::SERVICE_UNAVAILABLE, "Too many registrations"));
+        }
+        let status = if registry.count(RegistrationStatus::Registered) < registry.event.capacity {
+            RegistrationStatus::Registered
+        } else if registry.event.waitlist {
+            RegistrationStatus::Waitlisted
+        } else {
+            return Err((StatusCode::CONFLICT, "This event is full"));
+        };
+        let attendee = Attendee {
+            id: Uuid::new_v4().simple().to_string(),
+            name: name.to_string(),
+            email: email.to_string(),
+            status,
+            registered_at: unix_timestamp(),
+            first_joined_at: None,
+            attended_secs: 0,
+        };
+        registry.attendees.push(attendee.clone());
+        let ticket = self.ticket(room_id, registry, &attendee);
+        drop(events);
+
+        info!("New registration for event {}: {:?}", room_id, status);
+        if status == RegistrationStatus::Registered {
+            self.admit_registrant(room_id, &attendee.id).await;
+        }
+        Ok(ticket)
+    }
+
+    /// A registration, looked up by its ID
+    pub async fn registration(&self, room_id: &str, id: &str) -> Option<RegistrationTicket> {
+        let events = self.event_registrations.lock().await;
+        let registry = events.get(room_id)?;
+        let attendee = registry.attendees.iter().find(|a| a.id == id)?;
+        Some(self.ticket(room_id, registry, attendee))
+    }
+
+    /// Cancel a registration, giving its place to the first on the
+    /// waitlist; false if there is no such registration
+    pub async fn cancel_registration(&self, room_id: &str, id: &str) -> bool {
+        let mut events = self.event_registrations.lock().await;
+        let Some(registry) = events.get_mut(room_id) else {
+            return false;
+        };
+        let Some(attendee) = registry.attendees.iter_mut().find(|a| a.id == id) else {
+            return false;
+        };
+        let held_place = attendee.status == RegistrationStatus::Registered;
+        attendee.status = RegistrationStatus::Cancelled;
+        let promoted = held_place
+            .then(|| {
+                let next = registry
+                    .attendees
+                    .iter_mut()
+                    .find(|a| a.status == RegistrationStatus::Waitlisted)?;
+                next.status = RegistrationStatus::Registered;
+                Some(next.id.clone())
+            })
+            .flatten();
+        drop(events);
+
+        info!("Registration cancelled for event {}", room_id);
+        if held_place {
+            let sub = subject(id);
+            self.with_room(room_id, move |room| {
+                if let Some(invitees) = room.invitees.as_mut() {
+                    invitees.retain(|i| *i != sub);
+                }
+            })
+            .await;
+        }
+        if let Some(promoted) = promoted {
+            info!("Moved a registrant of event {} off the waitlist", room_id);
+            self.admit_registrant(room_id, &promoted).await;
+        }
+        true
+    }
+
+    /// Let a registrant's token into the room
+    async fn admit_registrant(&self, room_id: &str, id: &str) {
+        let sub = subject(id);
+        self.with_room(room_id, move |room| {
+            room.invitees.get_or_insert_with(Vec::new).push(sub);
+        })
+        .await;
+    }
+
+    /// Note a registrant joining their event
+    pub async fn record_attendance_join(&self, room_id: &str, peer_id: &str) {
+        if !self.event_registrations.lock().await.contains_key(room_id) {
+            return;
+        }
+        let id = peer_id.to_string();
+        let identity = self
+            .with_room(room_id, move |room| {
+                room.peers
+                    .iter()
+                    .find(|p| p.id == id)
+                    .and_then(|p| p.identity.clone())
+            })
+            .await
+            .flatten();
+        let Some(registration) = identity
+            .as_deref()
+            .and_then(|i| i.strip_prefix("registration:"))
+        else {
+            return;
+        };
+        let now = unix_timestamp();
+        let mut events = self.event_registrations.lock().await;
+        let Some(registry) = events.get_mut(room_id) else {
+            return;
+        };
+        if let Some(attendee) = registry.attendees.iter_mut().find(|a| a.id == registration) {
+            attendee.first_joined_at.get_or_insert(now);
+            registry
+                .present
+                .insert(peer_id.to_string(), (registration.to_string(), now));
+        }
+    }
+
+    /// Note a registrant leaving their event, counting the time they spent
+    pub async fn record_attendance_leave(&self, room_id: &str, peer_id: &str) {
+        let mut events = self.event_registrations.lock().await;
+        let Some(registry) = events.get_mut(room_id) else {
+            return;
+        };
+        let Some((id, joined_at)) = registry.present.remove(peer_id) else {
+            return;
+        };
+        if let Some(attendee) = registry.attendees.iter_mut().find(|a| a.id == id) {
+            attendee.attended_secs += unix_timestamp().saturating_sub(joined_at);
+        }
+    }
+
+    /// Who registered for an event room and how much of it they attended
+    pub async fn attendance(&self, room_id: &str) -> Option<AttendanceReport> {
+        let events = self.event_registrations.lock().await;
+        let registry = events.get(room_id)?;
+        let now = unix_timestamp();
+        let mut attendees = registry.attendees.clone();
+        // Count the time of those still in the room
+        for (id, joined_at) in registry.present.values() {
+            if let Some(attendee) = attendees.iter_mut().find(|a| a.id == *id) {
+                attendee.attended_secs += now.saturating_sub(*joined_at);
+            }
+        }
+        Some(AttendanceReport {
+            room_id: room_id.to_string(),
+            event: registry.event.clone(),
+            registered: registry.count(RegistrationStatus::Registered),
+            waitlisted: registry.count(RegistrationStatus::Waitlisted),
+            attended: attendees
+                .iter()
+                .filter(|a| a.first_joined_at.is_some())
+                .count(),
+            attendees,
+        })
+    }
+}
+
+/// Register for an event
+#[utoipa::path(
+    post,
+    path = "/api/room/{room_id}/register",
+    tag = "Rooms",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the event room")
+    ),
+    request_body = RegistrationRequest,
+    responses(
+        (status = 200, description = "Registered, with a join token, or on the waitlist", body = RegistrationTicket),
+        (status = 400, description = "Invalid name or email"),
+        (status = 404, description = "No such event"),
+        (status = 409, description = "The email is already registered, or the event is full"),
+        (status = 503, description = "Too many registrations")
+    )
+)]
+pub async fn register(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+    Json(request): Json<RegistrationRequest>,
+) -> Response {
+    match state.register(&room_id, request).await {
+        Ok(ticket) => Json(ticket).into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Look up a registration, e.g. for the token after leaving the waitlist
+#[utoipa::path(
+    get,
+    path = "/api/room/{room_id}/registrations/{id}",
+    tag = "Rooms",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the event room"),
+        ("id" = String, Path, description = "The registration's ID")
+    ),
+    responses(
+        (status = 200, description = "The registration", body = RegistrationTicket),
+        (status = 404, description = "No such registration")
+    )
+)]
+pub async fn get_registration(
+    Path((room_id, id)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> Response {
+    match state.registration(&room_id, &id).await {
+        Some(ticket) => Json(ticket).into_response(),
+        None => (StatusCode::NOT_FOUND, "No such registration").into_response(),
+    }
+}
+
+/// Cancel a registration
+#[utoipa::path(
+    delete,
+    path = "/api/room/{room_id}/registrations/{id}",
+    tag = "Rooms",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the event room"),
+        ("id" = String, Path, description = "The registration's ID")
+    ),
+    responses(
+        (status = 204, description = "Registration cancelled"),
+        (status = 404, description = "No such registration")
+    )
+)]
+pub async fn cancel_registration(
+    Path((room_id, id)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> Response {
+    match state.cancel_registration(&room_id, &id).await {
+        true => StatusCode::NO_CONTENT.into_response(),
+        false => (StatusCode::NOT_FOUND, "No such registration").into_response(),
+    }
+}
+
+/// Registrations of an event room and who attended
+#[utoipa::path(
+    get,
+    path = "/admin/rooms/{room_id}/attendance",
+    tag = "Admin",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the event room")
+    ),
+    responses(
+        (status = 200, description = "Registrants and their attendance", body = AttendanceReport),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 404, description = "No such event")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn get_attendance(
+    _auth: AdminAuth,
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    match state.attendance(&room_id).await {
+        Some(report) => Json(report).into_response(),
+        None => (StatusCode::NOT_FOUND, "No such event").into_response(),
+    }
+}
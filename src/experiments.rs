@@ -0,0 +1,172 @@
+//! A/B experiments
+//!
+//! Each configured experiment splits rooms, or peers, into weighted
+//! variants. Assignment needs no stored state: a SHA-256 of the
+//! experiment's salt and the room or peer ID picks a point in the total
+//! weight, so every instance puts a room in the same variant, and changing
+//! the salt reshuffles everyone. A variant carries free-form settings for
+//! the client, such as media constraints, returned with the assignment by
+//! `/api/config`.
+//!
+//! Call records keep the variants of their room and of each peer that
+//! joined, and `/admin/analytics` breaks calls, MOS and ratings down by
+//! variant.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::models::{CallRecord, ExperimentAssignment, VariantStats};
+
+/// An experiment and its variants
+#[derive(Debug, Clone, Deserialize)]
+pub struct Experiment {
+    pub name: String,
+    /// Mixed into the hash so experiments split independently; the name
+    /// when unset
+    #[serde(default)]
+    pub salt: Option<String>,
+    /// What is split into variants
+    #[serde(default)]
+    pub unit: ExperimentUnit,
+    pub variants: Vec<Variant>,
+}
+
+/// What an experiment assigns variants to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExperimentUnit {
+    /// Everyone in a room gets the same variant
+    #[default]
+    Room,
+    /// Each peer gets its own variant
+    Peer,
+}
+
+/// One arm of an experiment
+#[derive(Debug, Clone, Deserialize)]
+pub struct Variant {
+    pub name: String,
+    /// Share of rooms or peers, relative to the other variants
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// Settings handed to clients in this variant
+    #[serde(default)]
+    pub settings: serde_json::Map<String, serde_json::Value>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl Experiment {
+    /// The variant of a room or peer; `None` when no variant has weight
+    pub fn assign(&self, id: &str) -> Option<&Variant> {
+        let total: u64 = self.variants.iter().map(|v| u64::from(v.weight)).sum();
+        if total == 0 {
+            return None;
+        }
+        let salt = self.salt.as_deref().unwrap_or(&self.name);
+        let digest = Sha256::digest(format!("{}:{}", salt, id));
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        let mut point = u64::from_be_bytes(bytes) % total;
+        self.variants.iter().find(|v| {
+            let weight = u64::from(v.weight);
+            if point < weight {
+                return true;
+            }
+            point -= weight;
+            false
+        })
+    }
+}
+
+impl Config {
+    /// Variants of the experiments of one unit, by experiment name
+    pub fn variants(&self, unit: ExperimentUnit, id: &str) -> BTreeMap<String, String> {
+        self.experiments
+            .iter()
+            .filter(|e| e.unit == unit)
+            .filter_map(|e| Some((e.name.clone(), e.assign(id)?.name.clone())))
+            .collect()
+    }
+
+    /// Assignments with their client settings, for the experiments whose
+    /// unit is given
+    pub fn experiment_assignments(
+        &self,
+        room_id: Option<&str>,
+        peer_id: Option<&str>,
+    ) -> BTreeMap<String, ExperimentAssignment> {
+        self.experiments
+            .iter()
+            .filter_map(|e| {
+                let id = match e.unit {
+                    ExperimentUnit::Room => room_id?,
+                    ExperimentUnit::Peer => peer_id?,
+                };
+                let variant = e.assign(id)?;
+                let assignment = ExperimentAssignment {
+                    variant: variant.name.clone(),
+                    settings: variant.settings.clone(),
+                };
+                Some((e.name.clone(), assignment))
+            })
+            .collect()
+    }
+}
+
+/// Running totals for one variant
+#[derive(Default)]
+struct Tally {
+    calls: BTreeSet<String>,
+    mos: Vec<f64>,
+    ratings: Vec<u64>,
+}
+
+/// Calls, MOS and ratings per experiment variant
+///
+/// A room variant counts the whole call and all its surveys; a peer
+/// variant counts the calls the peer was in and the peer's own surveys.
+pub(crate) fn variant_stats<'a>(calls: impl Iterator<Item = &'a CallRecord>) -> Vec<VariantStats> {
+    let mut tallies: BTreeMap<(String, String), Tally> = BTreeMap::new();
+    for call in calls {
+        let mut count = |experiment: &str, variant: &str, peer: Option<&str>| {
+            let tally = tallies
+                .entry((experiment.to_string(), variant.to_string()))
+                .or_default();
+            if tally.calls.insert(call.room_id.clone()) {
+                tally.mos.extend(call.average_mos);
+            }
+            let ratings = call
+                .feedback
+                .iter()
+                .filter(|f| peer.is_none() || f.survey.peer_id.as_deref() == peer)
+                .map(|f| u64::from(f.survey.rating));
+            tally.ratings.extend(ratings);
+        };
+        for (experiment, variant) in &call.experiments {
+            count(experiment, variant, None);
+        }
+        for (peer_id, variants) in &call.peer_experiments {
+            for (experiment, variant) in variants {
+                count(experiment, variant, Some(peer_id));
+            }
+        }
+    }
+    tallies
+        .into_iter()
+        .map(|((experiment, variant), tally)| VariantStats {
+            experiment,
+            variant,
+            calls: tally.calls.len(),
+            average_mos: (!tally.mos.is_empty())
+                .then(|| tally.mos.iter().sum::<f64>() / tally.mos.len() as f64),
+            average_rating: (!tally.ratings.is_empty())
+                .then(|| tally.ratings.iter().sum::<u64>() as f64 / tally.ratings.len() as f64),
+        })
+        .collect()
+}
@@ -0,0 +1,337 @@
+//! Room data bundles
+//!
+//! For compliance requests and debugging, `GET /api/room/{id}/export`
+//! packs what the server knows about a room into a zip: the chat, the full
+//! transcript, the call record, live quality stats while the room runs,
+//! and the timeline. Small rooms get the zip straight away. Larger ones
+//! are built by a background job: the request returns an export job to
+//! poll at `/api/exports/{id}`, and the finished zip is fetched from
+//! `/api/exports/{id}/download`. Finished bundles are held in memory, or
+//! in the [`storage`](crate::storage) backend if there is one, and can be
+//! downloaded for an hour. Stored bundles are not deleted when they expire;
+//! give `exports/` a lifecycle rule in the bucket.
+
+use std::io::{Cursor, Write};
+use std::time::Duration;
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use tracing::warn;
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+use crate::admin::OperatorAuth;
+use crate::cache::EphemeralCache;
+use crate::models::{
+    ExportJob, ExportStatus, Job, JobKind, JobStatus, TranscriptEntry, TranscriptKind,
+};
+use crate::signed_urls::OperatorOrSigned;
+use crate::state::AppState;
+
+/// Transcript lines and timeline events above which an export is built in
+/// the background
+pub const SYNC_EXPORT_LIMIT: usize = 1000;
+
+/// Finished bundles kept at once
+pub const MAX_EXPORTS: usize = 32;
+
+/// How long a finished bundle can be downloaded
+pub const EXPORT_TTL: Duration = Duration::from_secs(3600);
+
+/// A finished bundle
+#[derive(Debug, Clone)]
+pub enum ExportBundle {
+    /// The zip itself
+    Held(Bytes),
+    /// The size of the zip kept in the blob store
+    Stored(usize),
+}
+
+impl ExportBundle {
+    fn len(&self) -> usize {
+        match self {
+            ExportBundle::Held(bundle) => bundle.len(),
+            ExportBundle::Stored(size) => *size,
+        }
+    }
+}
+
+/// Finished bundles by export job ID
+pub type Exports = EphemeralCache<String, ExportBundle>;
+
+/// Key of a bundle in the blob store
+fn blob_key(id: &str) -> String {
+    format!("exports/{}.zip", id)
+}
+
+/// Everything that goes into a bundle, gathered up front so the zip can be
+/// written off the async runtime
+struct RoomData {
+    files: Vec<(&'static str, Vec<u8>)>,
+}
+
+impl RoomData {
+    fn add(&mut self, name: &'static str, value: &impl Serialize) {
+        match serde_json::to_vec_pretty(value) {
+            Ok(json) => self.files.push((name, json)),
+            Err(e) => warn!("Failed to serialize {} for export: {}", name, e),
+        }
+    }
+
+    fn zip(self) -> Result<Bytes, String> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        for (name, contents) in self.files {
+            zip.start_file(name, options).map_err(|e| e.to_string())?;
+            zip.write_all(&contents).map_err(|e| e.to_string())?;
+        }
+        let cursor = zip.finish().map_err(|e| e.to_string())?;
+        Ok(Bytes::from(cursor.into_inner()))
+    }
+}
+
+impl AppState {
+    /// Collect a room's data; None if the server knows nothing about it
+    async fn room_data(&self, room_id: &str) -> Option<(RoomData, usize)> {
+        let transcript = self.get_transcript(room_id).await;
+        let call = self.calls.lock().await.get(room_id).cloned();
+        let quality = self.room_quality(room_id).await;
+        let timeline = self.get_timeline(room_id).await;
+        if transcript.is_none() && call.is_none() && quality.is_none() && timeline.is_none() {
+            return None;
+        }
+
+        let mut size = 0;
+        let mut data = RoomData { files: Vec::new() };
+        if let Some(transcript) = &transcript {
+            let chat: Vec<&TranscriptEntry> = transcript
+                .entries
+                .iter()
+                .filter(|e| e.kind == TranscriptKind::Chat)
+                .collect();
+            data.add("chat.json", &chat);
+            data.add("transcript.json", transcript);
+            size += transcript.entries.len();
+        }
+        if let Some(call) = &call {
+            data.add("cdr.json", call);
+        }
+        if let Some(quality) = &quality {
+            data.add("quality.json", quality);
+        }
+        if let Some(timeline) = &timeline {
+            data.add("timeline.json", timeline);
+            size += timeline.events.len();
+        }
+        Some((data, size))
+    }
+
+    /// Start building a room's bundle in the background
+    pub async fn start_export(&self, room_id: &str) -> ExportJob {
+        let job = self
+            .enqueue_job(JobKind::Export {
+                room_id: room_id.to_string(),
+            })
+            .await;
+        export_job(&job, room_id, None)
+    }
+
+    /// Build a room's bundle and keep it under the export job's ID
+    pub(crate) async fn build_export(&self, id: &str, room_id: &str) -> Result<(), String> {
+        let (data, _) = self
+            .room_data(room_id)
+            .await
+            .ok_or_else(|| "Nothing known about the room".to_string())?;
+        let bundle = tokio::task::spawn_blocking(move || data.zip())
+            .await
+            .map_err(|e| e.to_string())??;
+        let bundle = match &self.blobs {
+            Some(blobs) => {
+                let size = bundle.len();
+                blobs
+                    .put(&blob_key(id), "application/zip", bundle)
+                    .await
+                    .map_err(|e| format!("Failed to store the bundle: {}", e))?;
+                ExportBundle::Stored(size)
+            }
+            None => ExportBundle::Held(bundle),
+        };
+        self.exports.lock().await.insert(id.to_string(), bundle);
+        Ok(())
+    }
+
+    /// An export job, with its bundle once ready; None if unknown, or
+    /// finished and expired
+    pub async fn export(&self, id: &str) -> Option<(ExportJob, Option<ExportBundle>)> {
+        let job = self.job(id).await?;
+        let JobKind::Export { room_id } = &job.kind else {
+            return None;
+        };
+        let bundle = self.exports.lock().await.get_mut(&id.to_string()).cloned();
+        if job.status == JobStatus::Done && bundle.is_none() {
+            return None;
+        }
+        Some((export_job(&job, room_id, bundle.as_ref()), bundle))
+    }
+}
+
+/// How an export job is shown
+fn export_job(job: &Job, room_id: &str, bundle: Option<&ExportBundle>) -> ExportJob {
+    ExportJob {
+        id: job.id.clone(),
+        room_id: room_id.to_string(),
+        status: match job.status {
+            JobStatus::Done => ExportStatus::Ready,
+            JobStatus::Failed => ExportStatus::Failed,
+            JobStatus::Pending | JobStatus::Running => ExportStatus::Pending,
+        },
+        created_at: job.created_at,
+        finished_at: job.finished_at,
+        size_bytes: bundle.map(ExportBundle::len),
+        error: job.last_error.clone(),
+    }
+}
+
+/// The zip as a download
+fn bundle_response(room_id: &str, bundle: Bytes) -> Response {
+    let disposition = format!("attachment; filename=\"room-{}.zip\"", room_id);
+    (
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        bundle,
+    )
+        .into_response()
+}
+
+/// Export a room's chat, transcript, call record, quality and timeline
+///
+/// Rooms with many transcript lines or timeline events are exported in the
+/// background; poll the returned job until it is ready.
+#[utoipa::path(
+    get,
+    path = "/api/room/{room_id}/export",
+    tag = "Admin",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room")
+    ),
+    responses(
+        (status = 200, description = "Zip of JSON files", body = Vec<u8>, content_type = "application/zip"),
+        (status = 202, description = "Export started in the background", body = ExportJob),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "Nothing known about this room")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn export_room(
+    OperatorAuth(auth): OperatorAuth,
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some((data, size)) = state.room_data(&room_id).await else {
+        return (StatusCode::NOT_FOUND, "Room not found").into_response();
+    };
+    state
+        .record_audit(Some(&room_id), &auth.principal, "room.export", "")
+        .await;
+    if size > SYNC_EXPORT_LIMIT {
+        let job = state.start_export(&room_id).await;
+        let location = format!("/api/exports/{}", job.id);
+        return (
+            StatusCode::ACCEPTED,
+            [(header::LOCATION, location)],
+            Json(job),
+        )
+            .into_response();
+    }
+    match data.zip() {
+        Ok(bundle) => bundle_response(&room_id, bundle),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+    }
+}
+
+/// Status of a background export
+#[utoipa::path(
+    get,
+    path = "/api/exports/{id}",
+    tag = "Admin",
+    params(
+        ("id" = String, Path, description = "Export job ID")
+    ),
+    responses(
+        (status = 200, description = "The export job", body = ExportJob),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "Unknown or expired export")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn get_export(
+    _auth: OperatorAuth,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    match state.export(&id).await {
+        Some((job, _)) => Json(job).into_response(),
+        None => (StatusCode::NOT_FOUND, "No such export").into_response(),
+    }
+}
+
+/// Download a finished background export
+#[utoipa::path(
+    get,
+    path = "/api/exports/{id}/download",
+    tag = "Admin",
+    params(
+        ("id" = String, Path, description = "Export job ID"),
+        ("expires" = Option<u64>, Query, description = "Expiry of a signed URL"),
+        ("signature" = Option<String>, Query, description = "Signature of a signed URL")
+    ),
+    responses(
+        (status = 200, description = "Zip of JSON files", body = Vec<u8>, content_type = "application/zip"),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low, or the signed URL has expired"),
+        (status = 404, description = "Unknown or expired export"),
+        (status = 409, description = "Export still running or failed"),
+        (status = 502, description = "Storage is unavailable")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn download_export(
+    OperatorOrSigned(actor): OperatorOrSigned,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    match state.export(&id).await {
+        Some((job, Some(bundle))) => {
+            let bundle = match (bundle, &state.blobs) {
+                (ExportBundle::Held(bundle), _) => bundle,
+                (ExportBundle::Stored(_), Some(blobs)) => match blobs.get(&blob_key(&id)).await {
+                    Ok(Some(bundle)) => bundle,
+                    Ok(None) => return (StatusCode::NOT_FOUND, "No such export").into_response(),
+                    Err(e) => {
+                        warn!("Failed to fetch export {}: {}", id, e);
+                        return (StatusCode::BAD_GATEWAY, "Storage is unavailable").into_response();
+                    }
+                },
+                (ExportBundle::Stored(_), None) => {
+                    return (StatusCode::NOT_FOUND, "No such export").into_response();
+                }
+            };
+            state
+                .record_audit(Some(&job.room_id), &actor, "export.download", &id)
+                .await;
+            bundle_response(&job.room_id, bundle)
+        }
+        Some(_) => (StatusCode::CONFLICT, "Export not ready").into_response(),
+        None => (StatusCode::NOT_FOUND, "No such export").into_response(),
+    }
+}
@@ -0,0 +1,185 @@
+//! Feature flags
+//!
+//! The client features a room offers (chat, reactions, recording, E2EE and
+//! transcription) come from three layers, each overriding the one before:
+//! the config's `features` section, flags an operator sets at runtime with
+//! `PUT /admin/features`, and the room's own, given at room creation or
+//! with `PUT /admin/rooms/{id}/features`. Rooms hear of their flags in
+//! `room_info`, sent again to everyone whenever a layer changes, and
+//! `/api/config?room_id=` returns them over HTTP.
+//!
+//! Flags tell clients what to show. What peers may actually do is still
+//! up to the room's controls and permission matrix.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use crate::admin::{AdminAuth, OperatorAuth, OwnerAuth};
+use crate::models::{FeatureFlags, FeatureOverrides, FeatureStatus};
+use crate::state::{AppState, Room};
+
+impl FeatureOverrides {
+    /// `flags` with the overridden ones changed
+    pub fn apply(&self, flags: FeatureFlags) -> FeatureFlags {
+        FeatureFlags {
+            chat: self.chat.unwrap_or(flags.chat),
+            reactions: self.reactions.unwrap_or(flags.reactions),
+            recording: self.recording.unwrap_or(flags.recording),
+            e2ee: self.e2ee.unwrap_or(flags.e2ee),
+            transcription: self.transcription.unwrap_or(flags.transcription),
+        }
+    }
+}
+
+impl Room {
+    /// Switch to new flags, telling everyone if they changed
+    fn set_features(&mut self, features: FeatureFlags) {
+        if self.features != features {
+            self.features = features;
+            self.broadcast_to_all(&self.room_info());
+        }
+    }
+}
+
+impl AppState {
+    /// Flags of a room with the given overrides, over the server-wide ones
+    pub async fn feature_flags(&self, room: &FeatureOverrides) -> FeatureFlags {
+        let runtime = *self.feature_overrides.lock().await;
+        room.apply(runtime.apply(self.config().features))
+    }
+
+    /// Flags of a running room
+    pub async fn room_features(&self, room_id: &str) -> Option<FeatureFlags> {
+        self.with_room(room_id, |room| room.features).await
+    }
+
+    /// Recompute every running room's flags after a layer changed
+    pub async fn refresh_features(&self) {
+        let global = self.feature_flags(&FeatureOverrides::default()).await;
+        for (_, room) in self.room_handles().await {
+            room.call(move |room| {
+                let features = room.settings.features.apply(global);
+                room.set_features(features);
+            })
+            .await;
+        }
+    }
+
+    /// Replace the runtime flags, applying them to running rooms
+    pub async fn set_feature_overrides(&self, overrides: FeatureOverrides, actor: &str) {
+        *self.feature_overrides.lock().await = overrides;
+        self.refresh_features().await;
+        let detail = serde_json::to_string(&overrides).unwrap_or_default();
+        self.record_audit(None, actor, "features.update", detail)
+            .await;
+    }
+
+    /// Replace a running room's own flags; `None` if it is not running
+    pub async fn set_room_features(
+        &self,
+        room_id: &str,
+        overrides: FeatureOverrides,
+        actor: &str,
+    ) -> Option<FeatureFlags> {
+        let global = self.feature_flags(&FeatureOverrides::default()).await;
+        let features = self
+            .with_room(room_id, move |room| {
+                room.settings.features = overrides;
+                room.set_features(overrides.apply(global));
+                room.features
+            })
+            .await?;
+        let detail = serde_json::to_string(&overrides).unwrap_or_default();
+        self.record_audit(Some(room_id), actor, "room.features", detail)
+            .await;
+        Some(features)
+    }
+}
+
+/// Flags for new rooms and the runtime overrides behind them
+#[utoipa::path(
+    get,
+    path = "/admin/features",
+    tag = "Admin",
+    responses(
+        (status = 200, description = "Current feature flags", body = FeatureStatus),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn get_features(_auth: AdminAuth, State(state): State<AppState>) -> Json<FeatureStatus> {
+    let overrides = *state.feature_overrides.lock().await;
+    Json(FeatureStatus {
+        flags: state.feature_flags(&FeatureOverrides::default()).await,
+        overrides,
+    })
+}
+
+/// Set feature flags at runtime, over the config's, in every room
+///
+/// Replaces the previous runtime overrides; send `{}` to go back to the
+/// config. Rooms' own flags still take precedence.
+#[utoipa::path(
+    put,
+    path = "/admin/features",
+    tag = "Admin",
+    request_body = FeatureOverrides,
+    responses(
+        (status = 200, description = "Flags now in effect for new rooms", body = FeatureStatus),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn set_features(
+    OwnerAuth(auth): OwnerAuth,
+    State(state): State<AppState>,
+    Json(overrides): Json<FeatureOverrides>,
+) -> Json<FeatureStatus> {
+    state
+        .set_feature_overrides(overrides, &auth.principal)
+        .await;
+    Json(FeatureStatus {
+        flags: state.feature_flags(&FeatureOverrides::default()).await,
+        overrides,
+    })
+}
+
+/// Set a running room's own feature flags
+///
+/// Replaces the flags the room was created with; the room's peers get
+/// the result in `room_info`.
+#[utoipa::path(
+    put,
+    path = "/admin/rooms/{room_id}/features",
+    tag = "Admin",
+    params(
+        ("room_id" = String, Path, description = "The room identifier")
+    ),
+    request_body = FeatureOverrides,
+    responses(
+        (status = 200, description = "Flags now in effect in the room", body = FeatureStatus),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "Room not found")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn set_room_features(
+    OperatorAuth(auth): OperatorAuth,
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+    Json(overrides): Json<FeatureOverrides>,
+) -> Response {
+    match state
+        .set_room_features(&room_id, overrides, &auth.principal)
+        .await
+    {
+        Some(flags) => Json(FeatureStatus { flags, overrides }).into_response(),
+        None => (StatusCode::NOT_FOUND, "Room not found").into_response(),
+    }
+}
@@ -1,121 +1,394 @@
 //! HTTP and WebSocket handlers for the video chat application
 
 use axum::{
+    Json,
     extract::{
-        ws::{Message, WebSocket},
-        Path, State, WebSocketUpgrade,
+        Path, Query, State, WebSocketUpgrade,
+        ws::{Message, Utf8Bytes, WebSocket},
     },
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{Html, IntoResponse, Response},
-    Json,
 };
-use futures::{SinkExt, StreamExt};
-use tokio::sync::mpsc;
+use futures::{
+    SinkExt, StreamExt,
+    stream::{SplitSink, SplitStream},
+};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::sync::oneshot;
 use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
-use crate::models::{CreateRoomResponse, RoomStatus, WsMessage};
-use crate::state::AppState;
+use crate::capture::CaptureEvent;
+use crate::cluster::{from_member, proxy_socket};
+use crate::config::IceServer;
+use crate::debug_bundle::SignalingNote;
+use crate::deep_links::url_safe;
+use crate::integrations::announce_room_created;
+use crate::join_queue::Admission;
+use crate::lanes::{PeerReceiver, peer_channel};
+use crate::models::{
+    ClientCapabilities, ClientConfig, ClientErrorReport, CreateRoomRequest, CreateRoomResponse,
+    DeviceInfo, FeatureOverrides, LeaveReason, Permission, RoomSettings, RoomStatus,
+    StoredClientError, TimelineKind, TranscriptKind, WsMessage, validate_metadata,
+};
+use crate::recorders::handle_recorder_socket;
+use crate::state::{AppState, JoinedRoom, unix_timestamp};
+use crate::traffic::Verdict;
 
 /// Create a new room and return its ID
 #[utoipa::path(
     post,
     path = "/api/create-room",
     tag = "Rooms",
+    request_body(content = Option<CreateRoomRequest>, description = "Optional room settings"),
     responses(
-        (status = 200, description = "Room created successfully", body = CreateRoomResponse)
+        (status = 200, description = "Room created successfully", body = CreateRoomResponse),
+        (status = 400, description = "Unknown room template, invitees or an event without jwt_secret, or an event without capacity", body = String),
+        (status = 401, description = "API key of no tenant", body = String),
+        (status = 403, description = "Entitlement code missing, invalid or used up", body = String)
     )
 )]
-pub async fn create_room(State(state): State<AppState>) -> Json<CreateRoomResponse> {
+pub async fn create_room(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Option<Json<CreateRoomRequest>>,
+) -> Response {
+    let Json(request) = body.unwrap_or_default();
+    // With tenants configured, a bearer key names the room's tenant
+    let config = state.config();
+    let api_key = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .filter(|_| !config.tenants.is_empty());
+    let tenant = match api_key {
+        Some(key) => match state.tenant_for_key(key).await {
+            Some(name) => Some(name),
+            None => return (StatusCode::UNAUTHORIZED, "Unknown API key").into_response(),
+        },
+        None => None,
+    };
+    // The code is checked first, and given back if the request fails later
     let room_id = Uuid::new_v4().to_string();
-    state.create_room(room_id.clone()).await;
+    let grant = match state
+        .redeem_entitlement(request.code.as_deref(), &room_id, tenant.is_some())
+        .await
+    {
+        Ok(grant) => grant,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let mut settings = match room_settings(&state, &request, tenant.as_deref()).await {
+        Ok(settings) => settings,
+        Err(rejection) => {
+            state
+                .release_entitlement(request.code.as_deref(), &room_id)
+                .await;
+            return rejection.into_response();
+        }
+    };
+    if let Some(max) = grant {
+        let requested = request.max_duration_secs.unwrap_or(max);
+        settings.max_duration_secs = Some(requested.min(max));
+    }
+    let dial_code = state
+        .create_tagged_room(
+            room_id.clone(),
+            settings.clone(),
+            request.metadata,
+            tenant.clone(),
+        )
+        .await;
+    if let Some(invitees) = &request.invitees {
+        state.send_invitations(&room_id, invitees).await;
+    }
+    // Event rooms admit registrants besides any invitees
+    if let Some(event) = settings.event.clone() {
+        state.open_event(&room_id, *event).await;
+    }
+    if request.invitees.is_some() || settings.event.is_some() {
+        let invitees = request.invitees.unwrap_or_default();
+        state.invite_only(&room_id, invitees).await;
+    }
+    announce_room_created(&state, &room_id, request.notify, tenant.as_deref()).await;
 
     Json(CreateRoomResponse {
         room_id: room_id.clone(),
         ws_url: format!("/ws/{}", room_id),
+        settings,
+        dial_code,
+        links: state.config().deep_links(&room_id, None),
+        presenter_token: state.presenter_token(&room_id).await,
     })
+    .into_response()
+}
+
+/// Settings for the room `request` asks for, or why it cannot be created
+async fn room_settings(
+    state: &AppState,
+    request: &CreateRoomRequest,
+    tenant: Option<&str>,
+) -> Result<RoomSettings, (StatusCode, &'static str)> {
+    let mut settings = state
+        .config()
+        .resolve_room_settings(request)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    state.quota_settings(tenant, &mut settings).await;
+    validate_metadata(&request.metadata).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if request.invitees.is_some() && state.config().jwt_secret.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Invite-only rooms need jwt_secret to be configured",
+        ));
+    }
+    if let Some(event) = &settings.event {
+        if state.config().jwt_secret.is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Event rooms need jwt_secret to be configured",
+            ));
+        }
+        if event.capacity == 0 {
+            return Err((StatusCode::BAD_REQUEST, "Event capacity must be positive"));
+        }
+    }
+    Ok(settings)
 }
 
 /// Serve the room page with embedded room ID
-pub async fn room_page(Path(room_id): Path<String>) -> Response {
+///
+/// Visitors who would have to wait for the room's host are sent to its
+/// lobby instead.
+pub async fn room_page(
+    Path(room_id): Path<String>,
+    Query(query): Query<WsQuery>,
+    State(state): State<AppState>,
+) -> Response {
     // Validate room ID format (should be UUID)
     if Uuid::parse_str(&room_id).is_err() {
         return (StatusCode::BAD_REQUEST, "Invalid room ID format").into_response();
     }
+    let token = query.token.as_deref();
+    if state.must_wait_for_owner(&room_id, token).await {
+        let lobby = match token.filter(|t| url_safe(t)) {
+            Some(token) => format!("/room/{}/lobby?token={}", room_id, token),
+            None => format!("/room/{}/lobby", room_id),
+        };
+        return axum::response::Redirect::to(&lobby).into_response();
+    }
 
     // Serve the index.html with room ID injected
-    let html = include_str!("/home/kunal/axi-vid/static/index.html").replace("{{ROOM_ID}}", &room_id);
-    Html(html).into_response()
+    let html = include_str!("../static/index.html").replace("{{ROOM_ID}}", &room_id);
+    Html(state.assets.link(&html)).into_response()
+}
+
+/// Query parameters for the front page
+#[derive(Debug, Deserialize)]
+pub struct IndexQuery {
+    /// Entitlement code, where creating rooms needs one
+    pub code: Option<String>,
 }
 
 /// Redirect root to a new room
-pub async fn index_redirect(State(state): State<AppState>) -> Response {
+pub async fn index_redirect(
+    State(state): State<AppState>,
+    Query(query): Query<IndexQuery>,
+) -> Response {
     let room_id = Uuid::new_v4().to_string();
-    state.create_room(room_id.clone()).await;
+    let mut settings = RoomSettings::default();
+    match state
+        .redeem_entitlement(query.code.as_deref(), &room_id, false)
+        .await
+    {
+        Ok(grant) => settings.max_duration_secs = grant,
+        Err(rejection) => return rejection.into_response(),
+    }
+    state.create_room(room_id.clone(), settings).await;
 
     axum::response::Redirect::to(&format!("/room/{}", room_id)).into_response()
 }
 
+/// Query parameters for the WebSocket endpoint
+#[derive(Debug, Deserialize)]
+pub struct WsQuery {
+    /// Resume or re-invite token claiming a held slot, or a room or
+    /// recorder JWT
+    pub token: Option<String>,
+}
+
 /// WebSocket upgrade handler
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
     Path(room_id): Path<String>,
+    Query(query): Query<WsQuery>,
     State(state): State<AppState>,
+    headers: HeaderMap,
 ) -> Response {
     // Validate room ID
     if Uuid::parse_str(&room_id).is_err() {
         return (StatusCode::BAD_REQUEST, "Invalid room ID").into_response();
     }
+    // The room's home checks the token; sockets another member proxied
+    // here stay
+    let config = state.config();
+    if let Some(cluster) = config.cluster.as_ref()
+        && !from_member(&headers, cluster)
+        && let Some(home) = state.room_home(&room_id).await
+    {
+        let secret = cluster.secret.clone();
+        return ws
+            .on_upgrade(move |socket| proxy_socket(socket, home, secret, room_id, query.token));
+    }
+    // A room that ran elsewhere, or before a restart, picks up its state
+    state.restore_room(&room_id).await;
+    if state.is_recorder_token(&room_id, query.token.as_deref()) {
+        let token = query.token.unwrap_or_default();
+        return ws.on_upgrade(move |socket| handle_recorder_socket(socket, room_id, token, state));
+    }
+    // The presenter token was handed to the room's creator
+    let presenting = state
+        .is_presenter_token(&room_id, query.token.as_deref())
+        .await;
+    if !presenting && !state.may_join(&room_id, query.token.as_deref()).await {
+        warn!(
+            "Rejected join to room {} without a valid room token",
+            room_id
+        );
+        return (StatusCode::UNAUTHORIZED, "A valid room token is required").into_response();
+    }
+    if !presenting && !state.is_invited(&room_id, query.token.as_deref()).await {
+        warn!("Rejected uninvited join to room {}", room_id);
+        return (StatusCode::FORBIDDEN, "This room is invite-only").into_response();
+    }
 
     info!("WebSocket upgrade request for room: {}", room_id);
 
-    ws.on_upgrade(move |socket| handle_socket(socket, room_id, state))
+    ws.on_upgrade(move |socket| handle_socket(socket, room_id, query.token, state))
 }
 
 /// Handle an individual WebSocket connection
-async fn handle_socket(socket: WebSocket, room_id: String, state: AppState) {
+async fn handle_socket(socket: WebSocket, room_id: String, token: Option<String>, state: AppState) {
     let peer_id = Uuid::new_v4().to_string();
-    info!("New WebSocket connection: peer {} in room {}", peer_id, room_id);
+    info!(
+        "New WebSocket connection: peer {} in room {}",
+        peer_id, room_id
+    );
 
     // Create channel for sending messages to this peer
-    let (tx, mut rx) = mpsc::unbounded_channel::<WsMessage>();
+    let (tx, mut rx) = peer_channel();
 
-    // Try to join the room
-    let peer_count = match state.join_room(&room_id, peer_id.clone(), tx).await {
-        Ok(count) => count,
+    // Split socket into sender and receiver
+    let (mut ws_tx, mut ws_rx) = socket.split();
+
+    // Try to join the room, waiting in line if it is full
+    let joined = match state
+        .join_or_queue(&room_id, peer_id.clone(), tx, token.as_deref())
+        .await
+    {
+        Ok(Admission::Joined(joined)) => Ok(joined),
+        Ok(Admission::Queued {
+            max_wait, admitted, ..
+        }) => {
+            let line = (&state, room_id.as_str(), peer_id.as_str());
+            let waited = wait_in_line(line, &mut ws_tx, &mut ws_rx, &mut rx, admitted, max_wait);
+            match waited.await {
+                Ok(joined) => {
+                    state.record_join(&room_id, &joined, false, false).await;
+                    Ok(joined)
+                }
+                Err(WaitEnded::Admitted(joined)) => {
+                    // Admitted just as the peer gave up
+                    let session = Some(joined.resume_token.as_str());
+                    state
+                        .leave_session(&room_id, &joined.peer_id, session, LeaveReason::UserHangup)
+                        .await;
+                    return;
+                }
+                Err(WaitEnded::GaveUp) => return,
+                Err(WaitEnded::Rejected(e)) => Err(e),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    state.record_join_outcome(joined.is_err()).await;
+    let joined = match joined {
+        Ok(joined) => joined,
         Err(e) => {
             error!("Failed to join room {}: {}", room_id, e);
+            state
+                .record_timeline(&room_id, TimelineKind::Error, Some(&peer_id), e)
+                .await;
             // Send error and close
-            let (mut ws_tx, _) = socket.split();
             let error_msg = serde_json::to_string(&WsMessage::error(e)).unwrap();
             let _ = ws_tx.send(Message::Text(error_msg.into())).await;
             return;
         }
     };
 
-    // Split socket into sender and receiver
-    let (mut ws_tx, mut ws_rx) = socket.split();
+    let layout = joined.layout_update();
+    // A resumed peer keeps its previous ID
+    let peer_id = joined.peer_id;
+    let session = joined.resume_token.clone();
+    let capturing = state.capturing(&room_id);
+    if capturing {
+        state
+            .capture(&room_id, &peer_id, CaptureEvent::Join, None)
+            .await;
+    }
 
-    // Send room info to the new peer
-    let room_info = WsMessage::room_info(peer_count);
-    if let Ok(msg) = serde_json::to_string(&room_info) {
-        let _ = ws_tx.send(Message::Text(msg.into())).await;
+    // Send identity and room info to the new peer
+    let config = state.config();
+    let policy = Some(config.ice_policy.for_room(&joined.settings))
+        .filter(|p| p.is_restrictive())
+        .map(|policy| WsMessage::IcePolicy { policy });
+    let info = WsMessage::room_info(
+        joined.peer_count,
+        joined.settings.codecs.as_deref(),
+        joined.features,
+    );
+    let welcome = WsMessage::Welcome {
+        peer_id: peer_id.clone(),
+        presenter: joined.presenter,
+        role: joined.role,
+        settings: joined.settings,
+        resume_token: joined.resume_token,
+        sharing: joined.sharing,
+    };
+    let relay = config.media_relay.as_ref().map(|r| r.offer(&room_id));
+    for msg in [welcome, info]
+        .into_iter()
+        .chain(layout)
+        .chain(policy)
+        .chain(relay)
+    {
+        if let Ok(text) = serde_json::to_string(&msg) {
+            if capturing {
+                state
+                    .capture(&room_id, &peer_id, CaptureEvent::Out, Some(&text))
+                    .await;
+            }
+            let _ = ws_tx.send(Message::Text(text.into())).await;
+        }
     }
 
-    // Notify other peer about the new joiner
+    // Notify other peer(s) about the new joiner
     state
-        .relay_message(&room_id, &peer_id, WsMessage::Join)
-        .await;
-    state
-        .relay_message(&room_id, &peer_id, WsMessage::room_info(peer_count))
+        .announce_join(&room_id, &peer_id, joined.peer_count)
         .await;
 
     // Spawn task to forward messages from channel to WebSocket
+    let (capture_room, capture_peer, capture_state) =
+        (room_id.clone(), peer_id.clone(), state.clone());
     let ws_sender = tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            match serde_json::to_string(&msg) {
+        while let Some(frame) = rx.recv_frame().await {
+            match frame.into_text() {
                 Ok(text) => {
-                    if ws_tx.send(Message::Text(text.into())).await.is_err() {
+                    if capturing {
+                        capture_state
+                            .capture(&capture_room, &capture_peer, CaptureEvent::Out, Some(&text))
+                            .await;
+                    }
+                    if ws_tx.send(Message::Text(text)).await.is_err() {
                         break;
                     }
                 }
@@ -135,13 +408,47 @@ async fn handle_socket(socket: WebSocket, room_id: String, state: AppState) {
         while let Some(result) = ws_rx.next().await {
             match result {
                 Ok(Message::Text(text)) => {
-                    handle_text_message(&text, &room_id_clone, &peer_id_clone, &state_clone).await;
+                    if capturing {
+                        state_clone
+                            .capture(
+                                &room_id_clone,
+                                &peer_id_clone,
+                                CaptureEvent::In,
+                                Some(&text),
+                            )
+                            .await;
+                    }
+                    match state_clone
+                        .record_inbound(&room_id_clone, &peer_id_clone, text.len())
+                        .await
+                    {
+                        Verdict::Accept => {
+                            handle_text_message(&text, &room_id_clone, &peer_id_clone, &state_clone)
+                                .await
+                        }
+                        Verdict::Drop => {}
+                        Verdict::Disconnect => return LeaveReason::Kicked,
+                    }
                 }
                 Ok(Message::Binary(data)) => {
-                    // Try to parse binary as text
-                    if let Ok(text) = String::from_utf8(data.to_vec()) {
-                        handle_text_message(&text, &room_id_clone, &peer_id_clone, &state_clone)
-                            .await;
+                    match state_clone
+                        .record_inbound(&room_id_clone, &peer_id_clone, data.len())
+                        .await
+                    {
+                        // Try to parse binary as text
+                        Verdict::Accept => {
+                            if let Ok(text) = String::from_utf8(data.to_vec()) {
+                                handle_text_message(
+                                    &text.into(),
+                                    &room_id_clone,
+                                    &peer_id_clone,
+                                    &state_clone,
+                                )
+                                .await;
+                            }
+                        }
+                        Verdict::Drop => {}
+                        Verdict::Disconnect => return LeaveReason::Kicked,
                     }
                 }
                 Ok(Message::Ping(data)) => {
@@ -154,7 +461,7 @@ async fn handle_socket(socket: WebSocket, room_id: String, state: AppState) {
                 }
                 Ok(Message::Close(_)) => {
                     info!("Peer {} closed connection", peer_id_clone);
-                    break;
+                    return LeaveReason::UserHangup;
                 }
                 Err(e) => {
                     error!("WebSocket error for peer {}: {}", peer_id_clone, e);
@@ -162,55 +469,323 @@ async fn handle_socket(socket: WebSocket, room_id: String, state: AppState) {
                 }
             }
         }
+        LeaveReason::NetworkTimeout
     };
 
     // Wait for either task to complete
-    tokio::select! {
-        _ = ws_receiver => {
+    let reason = tokio::select! {
+        reason = ws_receiver => {
             debug!("WebSocket receiver ended for peer {}", peer_id);
+            reason
         }
         _ = ws_sender => {
             debug!("WebSocket sender ended for peer {}", peer_id);
+            LeaveReason::NetworkTimeout
         }
-    }
+    };
 
     // Clean up: remove peer from room
-    state.leave_room(&room_id, &peer_id).await;
+    if capturing {
+        state
+            .capture(&room_id, &peer_id, CaptureEvent::Leave, None)
+            .await;
+    }
+    state
+        .leave_session(&room_id, &peer_id, Some(&session), reason)
+        .await;
     info!("Peer {} disconnected from room {}", peer_id, room_id);
 }
 
+/// How waiting in line ended other than by admission
+enum WaitEnded {
+    /// The peer hung up
+    GaveUp,
+    /// The peer hung up, but had been seated meanwhile
+    Admitted(Box<JoinedRoom>),
+    /// The room closed or the wait ran out
+    Rejected(&'static str),
+}
+
+/// Wait in a full room's line, passing position updates on, until admitted
+///
+/// A peer that stops waiting leaves the line before this returns.
+async fn wait_in_line(
+    (state, room_id, peer_id): (&AppState, &str, &str),
+    ws_tx: &mut SplitSink<WebSocket, Message>,
+    ws_rx: &mut SplitStream<WebSocket>,
+    rx: &mut PeerReceiver,
+    mut admitted: oneshot::Receiver<JoinedRoom>,
+    max_wait: Duration,
+) -> Result<JoinedRoom, WaitEnded> {
+    let deadline = tokio::time::sleep(max_wait);
+    tokio::pin!(deadline);
+    let ended = loop {
+        tokio::select! {
+            joined = &mut admitted => {
+                return joined.map_err(|_| WaitEnded::Rejected("Room closed"));
+            }
+            _ = &mut deadline => break WaitEnded::Rejected("Timed out waiting for a slot"),
+            Some(frame) = rx.recv_frame() => {
+                if let Ok(text) = frame.into_text()
+                    && ws_tx.send(Message::Text(text)).await.is_err()
+                {
+                    break WaitEnded::GaveUp;
+                }
+            }
+            msg = ws_rx.next() => {
+                // Anything but a close is ignored until the peer is seated
+                if matches!(msg, None | Some(Err(_)) | Some(Ok(Message::Close(_)))) {
+                    break WaitEnded::GaveUp;
+                }
+            }
+        }
+    };
+    // Seated between the last poll and leaving the line, if at all
+    state.leave_queue(room_id, peer_id).await;
+    match (admitted.try_recv(), ended) {
+        (Ok(joined), WaitEnded::GaveUp) => Err(WaitEnded::Admitted(Box::new(joined))),
+        (Ok(joined), _) => Ok(joined),
+        (Err(_), ended) => Err(ended),
+    }
+}
+
 /// Process an incoming text message
-async fn handle_text_message(text: &str, room_id: &str, peer_id: &str, state: &AppState) {
+///
+/// Plain offers, answers and candidates are passed on as `text` itself when
+/// they qualify (see [`raw_relay`](crate::raw_relay)).
+pub async fn handle_text_message(text: &Utf8Bytes, room_id: &str, peer_id: &str, state: &AppState) {
     // Parse the message
     let msg: WsMessage = match serde_json::from_str(text) {
         Ok(m) => m,
         Err(e) => {
-            warn!("Invalid JSON from peer {}: {} - {}", peer_id, e, text);
+            warn!(
+                "Invalid JSON from peer {}: {} - {}",
+                peer_id,
+                e,
+                state.log_text(text)
+            );
             return;
         }
     };
+    if state.relay_raw(room_id, peer_id, &msg, text).await {
+        return;
+    }
 
-    debug!("Received {:?} from peer {} in room {}", msg, peer_id, room_id);
+    debug!(
+        "Received {} from peer {} in room {}",
+        state.log_message(&msg),
+        peer_id,
+        room_id
+    );
 
     // Handle different message types
     match &msg {
         WsMessage::Offer { .. }
         | WsMessage::Answer { .. }
         | WsMessage::IceCandidate { .. }
-        | WsMessage::Chat { .. }
-        | WsMessage::MediaStatus { .. } => {
-            // Relay signaling and chat messages to the other peer
-            state.relay_message(room_id, peer_id, msg).await;
+        | WsMessage::MediaStatus { .. }
+        | WsMessage::Hold
+        | WsMessage::Resume
+        | WsMessage::TransferRequest { .. } => {
+            let negotiation = match msg {
+                WsMessage::Offer { .. } => Some(TimelineKind::Offer),
+                WsMessage::Answer { .. } => Some(TimelineKind::Answer),
+                _ => None,
+            };
+            let note = SignalingNote::of(&msg);
+            // Relay signaling and chat messages to the other peer(s)
+            let relayed = match msg.check_payload() {
+                Ok(()) => state.relay_message(room_id, peer_id, msg).await,
+                Err(e) => Err(e),
+            };
+            match relayed {
+                Ok(()) => {
+                    if let Some(kind) = negotiation {
+                        state
+                            .record_timeline(room_id, kind, Some(peer_id), "")
+                            .await;
+                    }
+                    if let Some(note) = note {
+                        state.note_signaling(room_id, peer_id, note).await;
+                    }
+                }
+                Err(e) => {
+                    warn!("Rejected message from peer {}: {}", peer_id, e);
+                    state
+                        .record_timeline(room_id, TimelineKind::Error, Some(peer_id), e)
+                        .await;
+                    state
+                        .send_to_peer(room_id, peer_id, WsMessage::error(e))
+                        .await;
+                }
+            }
         }
-        WsMessage::Ping => {
-            // Respond with pong (application-level keepalive)
+        WsMessage::Chat { message } => {
+            match state.relay_message(room_id, peer_id, msg.clone()).await {
+                Ok(()) => {
+                    state
+                        .record_transcript(room_id, peer_id, TranscriptKind::Chat, message)
+                        .await;
+                }
+                Err(e) => {
+                    warn!("Rejected message from peer {}: {}", peer_id, e);
+                    state
+                        .send_to_peer(room_id, peer_id, WsMessage::error(e))
+                        .await;
+                }
+            }
+        }
+        WsMessage::Recording { active } => {
+            if let Err(e) = state.request_recording(room_id, peer_id, *active).await {
+                state
+                    .send_to_peer(room_id, peer_id, WsMessage::error(e))
+                    .await;
+            }
+        }
+        WsMessage::PresentRequest { .. } => {
+            if let Err(e) = state.request_present(room_id, peer_id).await {
+                state
+                    .send_to_peer(room_id, peer_id, WsMessage::error(e))
+                    .await;
+            }
+        }
+        WsMessage::ScreenShare { active, .. } => {
+            let stopped = match active {
+                true => Err("Send present_request to share your screen"),
+                false => state.stop_presenting(room_id, peer_id).await,
+            };
+            if let Err(e) = stopped {
+                state
+                    .send_to_peer(room_id, peer_id, WsMessage::error(e))
+                    .await;
+            }
+        }
+        WsMessage::ConsentResponse { granted } => {
+            if let Err(e) = state.answer_consent(room_id, peer_id, *granted).await {
+                state
+                    .send_to_peer(room_id, peer_id, WsMessage::error(e))
+                    .await;
+            }
+        }
+        WsMessage::Dtmf { digits } => {
+            if !WsMessage::valid_dtmf(digits) {
+                state
+                    .send_to_peer(room_id, peer_id, WsMessage::error("Invalid DTMF digits"))
+                    .await;
+                return;
+            }
+            if let Err(e) = state.relay_message(room_id, peer_id, msg).await {
+                state
+                    .send_to_peer(room_id, peer_id, WsMessage::error(e))
+                    .await;
+            }
+        }
+        WsMessage::Reaction { emoji, .. } => {
+            let reaction = WsMessage::Reaction {
+                emoji: emoji.clone(),
+                from: Some(peer_id.to_string()),
+            };
+            let relayed = match msg.check_payload() {
+                Ok(()) => state.relay_message(room_id, peer_id, reaction).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = relayed {
+                state
+                    .send_to_peer(room_id, peer_id, WsMessage::error(e))
+                    .await;
+            }
+        }
+        WsMessage::Permissions { .. }
+        | WsMessage::SetRole { .. }
+        | WsMessage::RoomSettingsUpdate { .. }
+        | WsMessage::PresentGrant { .. }
+        | WsMessage::LayoutUpdate { .. } => {
+            if let Err(e) = state.host_command(room_id, peer_id, msg).await {
+                warn!("Rejected host command from peer {}: {}", peer_id, e);
+                state
+                    .send_to_peer(room_id, peer_id, WsMessage::error(e))
+                    .await;
+            }
+        }
+        WsMessage::Capabilities {
+            codecs,
+            browser,
+            version,
+        } => {
+            let caps = ClientCapabilities {
+                codecs: codecs.clone(),
+                browser: browser.clone(),
+                version: version.clone(),
+            };
+            state.register_capabilities(room_id, peer_id, caps).await;
+        }
+        WsMessage::DeviceInfo {
+            camera_label,
+            mic_label,
+            os,
+            browser,
+            ..
+        } => {
+            if let Err(e) = msg.check_payload() {
+                state
+                    .send_to_peer(room_id, peer_id, WsMessage::error(e))
+                    .await;
+                return;
+            }
+            let device = DeviceInfo {
+                camera_label: camera_label.clone(),
+                mic_label: mic_label.clone(),
+                os: os.clone(),
+                browser: browser.clone(),
+            };
+            state.register_device(room_id, peer_id, device).await;
+        }
+        WsMessage::Invite => {
+            let reply = if state.permits(room_id, peer_id, Permission::Invite).await {
+                WsMessage::InviteLink {
+                    url: format!("/room/{}", room_id),
+                }
+            } else {
+                WsMessage::error("Your role is not permitted to invite others")
+            };
+            state.send_to_peer(room_id, peer_id, reply).await;
+        }
+        WsMessage::QualityStats {
+            rtt_ms,
+            jitter_ms,
+            packet_loss,
+        } => {
             state
-                .relay_message(room_id, peer_id, WsMessage::Pong)
+                .record_quality(room_id, peer_id, *rtt_ms, *jitter_ms, *packet_loss)
                 .await;
         }
-        WsMessage::Leave => {
-            // Will be handled when connection closes
+        WsMessage::MediaStats { sent, received } => {
+            state
+                .record_media_stats(room_id, peer_id, *sent, *received)
+                .await;
+        }
+        WsMessage::ConnectionState {
+            state: conn,
+            peer_id: other,
+        } => {
+            state
+                .record_connection_state(room_id, peer_id, *conn, other.clone())
+                .await;
+        }
+        WsMessage::AudioLevel { level } => {
+            state.record_audio_level(room_id, peer_id, *level).await;
+        }
+        WsMessage::Ping => {
+            // Respond with pong (application-level keepalive)
+            let _ = state.relay_message(room_id, peer_id, WsMessage::Pong).await;
+        }
+        WsMessage::Leave { reason, .. } => {
+            // The peer is removed when the connection closes; keep its reason
             info!("Peer {} signaling leave from room {}", peer_id, room_id);
+            let reason = reason.unwrap_or(LeaveReason::UserHangup);
+            state.set_leave_reason(room_id, peer_id, reason).await;
+            let ended = WsMessage::call_ended(room_id, peer_id, state.config().post_call_page);
+            state.send_to_peer(room_id, peer_id, ended).await;
         }
         _ => {
             debug!("Ignoring message type from peer {}", peer_id);
@@ -247,11 +822,134 @@ pub async fn room_status(
     Path(room_id): Path<String>,
     State(state): State<AppState>,
 ) -> Json<RoomStatus> {
-    let peer_count = state.get_peer_count(&room_id).await;
+    let (peer_count, capacity, mode) = state.get_room_summary(&room_id).await;
+    let metadata = state
+        .with_room(&room_id, |room| room.metadata.clone())
+        .await
+        .unwrap_or_default();
 
     Json(RoomStatus {
         room_id,
         peer_count,
-        available: peer_count < 2,
+        available: peer_count < capacity,
+        mode,
+        metadata,
+    })
+}
+
+/// STUN/TURN servers for browsers
+#[utoipa::path(
+    get,
+    path = "/api/ice-servers",
+    tag = "Rooms",
+    responses(
+        (status = 200, description = "ICE servers for RTCPeerConnection", body = Vec<IceServer>)
+    )
+)]
+pub async fn ice_servers(State(state): State<AppState>) -> Json<Vec<IceServer>> {
+    Json(state.ice_servers(None))
+}
+
+/// Query parameters of the client settings
+#[derive(Debug, Deserialize)]
+pub struct ClientConfigQuery {
+    /// Room whose feature flags and experiment variants to return
+    pub room_id: Option<String>,
+    /// Peer whose experiment variants to return
+    pub peer_id: Option<String>,
+}
+
+/// Settings for the web client, including the static asset manifest
+#[utoipa::path(
+    get,
+    path = "/api/config",
+    tag = "Rooms",
+    params(
+        ("room_id" = Option<String>, Query, description = "Room whose feature flags and experiment variants to return"),
+        ("peer_id" = Option<String>, Query, description = "Peer whose experiment variants to return")
+    ),
+    responses(
+        (status = 200, description = "Client settings", body = ClientConfig)
+    )
+)]
+pub async fn client_config(
+    State(state): State<AppState>,
+    Query(query): Query<ClientConfigQuery>,
+) -> Json<ClientConfig> {
+    let room = match &query.room_id {
+        Some(room_id) => state.room_features(room_id).await,
+        None => None,
+    };
+    let features = match room {
+        Some(features) => features,
+        None => state.feature_flags(&FeatureOverrides::default()).await,
+    };
+    let experiments = state
+        .config()
+        .experiment_assignments(query.room_id.as_deref(), query.peer_id.as_deref());
+    Json(ClientConfig {
+        assets: state.assets.manifest(),
+        features,
+        experiments,
     })
 }
+
+/// JSON Schema of the WebSocket protocol, for generating client SDKs
+#[utoipa::path(
+    get,
+    path = "/api/schema/ws-messages.json",
+    tag = "WebSocket",
+    responses(
+        (status = 200, description = "JSON Schema for every `WsMessage` variant", body = Object)
+    )
+)]
+pub async fn ws_message_schema() -> Json<schemars::Schema> {
+    Json(schemars::schema_for!(WsMessage))
+}
+
+/// Maximum length of a client error message
+const MAX_CLIENT_ERROR_MESSAGE: usize = 4096;
+
+/// Submit a client-side error report
+#[utoipa::path(
+    post,
+    path = "/api/client-errors",
+    tag = "Diagnostics",
+    request_body = ClientErrorReport,
+    responses(
+        (status = 202, description = "Report accepted", body = String),
+        (status = 400, description = "Report rejected", body = String)
+    )
+)]
+pub async fn report_client_error(
+    State(state): State<AppState>,
+    Json(report): Json<ClientErrorReport>,
+) -> Response {
+    if report.message.len() > MAX_CLIENT_ERROR_MESSAGE {
+        return (StatusCode::BAD_REQUEST, "Error message too long").into_response();
+    }
+
+    let id = Uuid::new_v4().to_string();
+    warn!(
+        "Client error {} ({:?}) in room {:?}: {}",
+        id,
+        report.kind,
+        report.room_id,
+        state.log_text(&report.message)
+    );
+    if let Some(room_id) = &report.room_id {
+        let detail = format!("{:?}: {}", report.kind, report.message);
+        state
+            .record_timeline(room_id, TimelineKind::ClientError, None, detail)
+            .await;
+    }
+    state
+        .record_client_error(StoredClientError {
+            id: id.clone(),
+            received_at: unix_timestamp(),
+            report,
+        })
+        .await;
+
+    (StatusCode::ACCEPTED, id).into_response()
+}
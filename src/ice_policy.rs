@@ -0,0 +1,111 @@
+//! ICE candidate filtering
+//!
+//! Privacy-sensitive deployments can keep local addresses from leaking to
+//! the other side of a call. The policy is sent to every peer after it
+//! joins so browsers can avoid gathering or sending the candidates, and is
+//! enforced on the signaling path as well: unwanted `ice` messages are
+//! dropped and unwanted `a=candidate` lines are stripped from offers and
+//! answers before they are relayed.
+//!
+//! A room created with `privacy_mode: relay_only` tightens the deployment
+//! policy to relay candidates only, and non-relay `ice` messages sent in it
+//! are rejected with an error instead of being dropped silently.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::{PrivacyMode, RoomSettings, WsMessage};
+
+/// Which ICE candidates may pass through the signaling channel
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema,
+)]
+#[serde(default)]
+pub struct IcePolicy {
+    /// Drop host candidates whose address is an mDNS `.local` name
+    pub drop_mdns: bool,
+    /// Drop every host candidate, exposing only reflexive and relay addresses
+    pub drop_host: bool,
+    /// Allow only relay (TURN) candidates; clients should set
+    /// `iceTransportPolicy: "relay"`
+    pub relay_only: bool,
+}
+
+impl IcePolicy {
+    /// This deployment policy as it applies in a room
+    pub fn for_room(self, settings: &RoomSettings) -> Self {
+        Self {
+            relay_only: self.relay_only || settings.privacy_mode == PrivacyMode::RelayOnly,
+            ..self
+        }
+    }
+
+    /// Apply this deployment policy to a message relayed in a room
+    pub fn enforce(
+        self,
+        settings: &RoomSettings,
+        msg: WsMessage,
+    ) -> Result<Option<WsMessage>, &'static str> {
+        let policy = self.for_room(settings);
+        if let WsMessage::IceCandidate { candidate, .. } = &msg
+            && settings.privacy_mode == PrivacyMode::RelayOnly
+            && !policy.allows(candidate)
+        {
+            return Err("Only relay candidates are allowed in this room");
+        }
+        Ok(policy.apply(msg))
+    }
+
+    /// Whether the policy filters anything at all
+    pub fn is_restrictive(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// Whether a candidate line may be relayed
+    ///
+    /// The empty end-of-candidates marker and lines too short to classify
+    /// are allowed; malformed candidates are rejected separately.
+    pub fn allows(&self, candidate: &str) -> bool {
+        let body = candidate.strip_prefix("a=").unwrap_or(candidate);
+        // foundation component transport priority address port typ type ...
+        let fields: Vec<&str> = body.split_ascii_whitespace().collect();
+        let (Some(address), Some(kind)) = (fields.get(4), fields.get(7)) else {
+            return true;
+        };
+        match *kind {
+            "relay" => true,
+            _ if self.relay_only => false,
+            "host" if self.drop_host => false,
+            "host" => !(self.drop_mdns && address.ends_with(".local")),
+            _ => true,
+        }
+    }
+
+    /// Remove disallowed candidate lines from a session description
+    pub fn strip_sdp(&self, sdp: &str) -> String {
+        sdp.split_inclusive('\n')
+            .filter(|line| !line.starts_with("a=candidate:") || self.allows(line.trim_end()))
+            .collect()
+    }
+
+    /// Apply the policy to a relayed message, returning `None` when it
+    /// should be dropped
+    pub fn apply(&self, msg: WsMessage) -> Option<WsMessage> {
+        if !self.is_restrictive() {
+            return Some(msg);
+        }
+        match msg {
+            WsMessage::IceCandidate { ref candidate, .. } if !self.allows(candidate) => None,
+            WsMessage::Offer { sdp, peer_id } => Some(WsMessage::Offer {
+                sdp: self.strip_sdp(&sdp),
+                peer_id,
+            }),
+            WsMessage::Answer { sdp, peer_id } => Some(WsMessage::Answer {
+                sdp: self.strip_sdp(&sdp),
+                peer_id,
+            }),
+            msg => Some(msg),
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! Automatic ICE restarts
+//!
+//! Clients report their peer connection's state with `connection_state` on
+//! every change. A connection counts as dead while its client reports it
+//! `disconnected` or `failed`, or while the client's `media_stats` show it
+//! receiving nothing at all although the other end sends. Once it has been
+//! dead for `dead_after_secs`, both ends get an `ice_restart` carrying a
+//! fresh ICE server list, with newly minted TURN credentials when `turn` is
+//! configured. One end is named the offerer, so the two do not offer at
+//! once: the presenter in broadcast rooms, otherwise the peer that joined
+//! first. A connection still dead `dead_after_secs` after a restart gets
+//! another one, up to `max_attempts` until it works again.
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::config::IceServer;
+use crate::models::{PeerConnectionState, TimelineKind, WsMessage};
+use crate::state::{AppState, Room};
+
+/// When to restart ICE
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct IceRestartConfig {
+    /// Seconds a connection stays dead before a restart is ordered
+    pub dead_after_secs: u64,
+    /// Restarts tried before giving up on a connection; 0 disables restarts
+    pub max_attempts: u32,
+}
+
+impl Default for IceRestartConfig {
+    fn default() -> Self {
+        Self {
+            dead_after_secs: 5,
+            max_attempts: 3,
+        }
+    }
+}
+
+/// One peer's view of its connection to another
+#[derive(Debug, Default)]
+pub struct Transport {
+    /// The client reports the connection disconnected or failed
+    reported_down: bool,
+    /// The client receives no media although the other end sends
+    starved: bool,
+    /// When the connection was seen dead, until a restart is ordered
+    dead_since: Option<Instant>,
+    /// Restarts ordered since the connection last worked
+    restarts: u32,
+}
+
+impl Transport {
+    /// Note whether the connection is dead; returns true if it just died
+    fn update(&mut self) -> bool {
+        if !self.reported_down && !self.starved {
+            self.dead_since = None;
+            self.restarts = 0;
+            return false;
+        }
+        if self.dead_since.is_some() {
+            return false;
+        }
+        self.dead_since = Some(Instant::now());
+        true
+    }
+}
+
+/// What came of a due restart
+enum Restart {
+    Ordered { offerer: String },
+    GaveUp,
+}
+
+impl Room {
+    /// The other end of `peer_id`'s connection, as named or implied
+    fn counterpart(&self, peer_id: &str, named: Option<&str>) -> Option<String> {
+        let mut others = self
+            .peers
+            .iter()
+            .map(|p| p.id.as_str())
+            .filter(|id| *id != peer_id);
+        let other = match (named, self.presenter.as_deref()) {
+            (Some(named), _) => others.find(|id| *id == named)?,
+            (None, Some(presenter)) if presenter != peer_id => presenter,
+            (None, _) => {
+                let other = others.next()?;
+                // A presenter has to say which viewer it means
+                if others.next().is_some() {
+                    return None;
+                }
+                other
+            }
+        };
+        Some(other.to_string())
+    }
+
+    /// Apply a change to `peer_id`'s view of its connection to `other`;
+    /// returns true if the connection just died
+    fn update_transport(
+        &mut self,
+        peer_id: &str,
+        other: &str,
+        change: impl FnOnce(&mut Transport),
+    ) -> bool {
+        let Some(peer) = self.peers.iter_mut().find(|p| p.id == peer_id) else {
+            return false;
+        };
+        let transport = peer.transports.entry(other.to_string()).or_default();
+        change(transport);
+        transport.update()
+    }
+
+    /// Take `peer_id`'s latest media stats into account; returns the other
+    /// end if its connection just died
+    pub fn note_starvation(&mut self, peer_id: &str) -> Option<String> {
+        let other = self.counterpart(peer_id, None)?;
+        let (_, received) = self.peers.iter().find(|p| p.id == peer_id)?.media.delta()?;
+        let other_sent = self
+            .peers
+            .iter()
+            .find(|p| p.id == other)
+            .and_then(|p| p.media.delta())
+            .map(|(sent, _)| sent);
+        let starved = received.audio + received.video == 0
+            && other_sent.is_some_and(|s| s.audio + s.video > 0);
+        self.update_transport(peer_id, &other, |t| t.starved = starved)
+            .then_some(other)
+    }
+
+    /// The end that re-offers after a restart of `a`'s connection to `b`
+    fn offerer(&self, a: &str, b: &str) -> Option<String> {
+        if let Some(presenter) = self.presenter.as_deref().filter(|p| *p == a || *p == b) {
+            return Some(presenter.to_string());
+        }
+        self.peers
+            .iter()
+            .find(|p| p.id == a || p.id == b)
+            .map(|p| p.id.clone())
+    }
+
+    /// Order a restart of `peer_id`'s connection to `other` if it has been
+    /// dead for `dead_after_secs`
+    fn restart_ice(
+        &mut self,
+        peer_id: &str,
+        other: &str,
+        config: &IceRestartConfig,
+        servers: [Vec<IceServer>; 2],
+    ) -> Option<Restart> {
+        if !self.peers.iter().any(|p| p.id == other) {
+            return None;
+        }
+        let offerer = self.offerer(peer_id, other)?;
+        let transport = self
+            .peers
+            .iter_mut()
+            .find(|p| p.id == peer_id)?
+            .transports
+            .get_mut(other)?;
+        let dead_after = Duration::from_secs(config.dead_after_secs);
+        if transport
+            .dead_since
+            .is_none_or(|since| since.elapsed() < dead_after)
+        {
+            return None;
+        }
+        transport.dead_since = None;
+        if transport.restarts >= config.max_attempts {
+            // Give up once, then stay quiet until the connection works
+            let first = transport.restarts == config.max_attempts;
+            transport.restarts += 1;
+            return first.then_some(Restart::GaveUp);
+        }
+        transport.restarts += 1;
+        // The other end's view of the connection starts over as well
+        if let Some(peer) = self.peers.iter_mut().find(|p| p.id == other)
+            && let Some(transport) = peer.transports.get_mut(peer_id)
+        {
+            transport.dead_since = None;
+        }
+
+        let [own, theirs] = servers;
+        for (to, end, ice_servers) in [(peer_id, other, own), (other, peer_id, theirs)] {
+            let restart = WsMessage::IceRestart {
+                peer_id: end.to_string(),
+                offerer: offerer == to,
+                ice_servers,
+            };
+            self.send_to(to, restart);
+        }
+        Some(Restart::Ordered { offerer })
+    }
+}
+
+impl AppState {
+    /// Record the state a client reports for its connection
+    pub async fn record_connection_state(
+        &self,
+        room_id: &str,
+        peer_id: &str,
+        state: PeerConnectionState,
+        other: Option<String>,
+    ) {
+        let id = peer_id.to_string();
+        let died = self.with_room(room_id, move |room| {
+            let other = room.counterpart(&id, other.as_deref())?;
+            let down = match state {
+                PeerConnectionState::Connected => Some(false),
+                PeerConnectionState::Disconnected | PeerConnectionState::Failed => Some(true),
+                PeerConnectionState::New | PeerConnectionState::Connecting => None,
+                PeerConnectionState::Closed => {
+                    let peer = room.peers.iter_mut().find(|p| p.id == id)?;
+                    peer.transports.remove(&other);
+                    return None;
+                }
+            };
+            room.update_transport(&id, &other, |t| {
+                if let Some(down) = down {
+                    t.reported_down = down;
+                }
+            })
+            .then_some(other)
+        });
+        if let Some(Some(other)) = died.await {
+            self.schedule_ice_restart(room_id, peer_id, &other);
+        }
+    }
+
+    /// Check back on a dead connection once `dead_after_secs` have passed
+    pub fn schedule_ice_restart(&self, room_id: &str, peer_id: &str, other: &str) {
+        let state = self.clone();
+        let (room_id, peer_id, other) =
+            (room_id.to_string(), peer_id.to_string(), other.to_string());
+        let dead_after = Duration::from_secs(self.config().ice_restart.dead_after_secs);
+        tokio::spawn(async move {
+            tokio::time::sleep(dead_after).await;
+            state.restart_ice(&room_id, &peer_id, &other).await;
+        });
+    }
+
+    /// Tell both ends of a connection that stayed dead to restart ICE
+    async fn restart_ice(&self, room_id: &str, peer_id: &str, other: &str) {
+        let config = self.config().ice_restart.clone();
+        let servers = [
+            self.ice_servers(Some(peer_id)),
+            self.ice_servers(Some(other)),
+        ];
+        let (id, other_id) = (peer_id.to_string(), other.to_string());
+        let restart = self.with_room(room_id, move |room| {
+            room.restart_ice(&id, &other_id, &config, servers)
+        });
+        match restart.await.flatten() {
+            Some(Restart::Ordered { offerer }) => {
+                info!(
+                    "Restarting ICE between {} and {} in room {}, {} offers",
+                    peer_id, other, room_id, offerer
+                );
+                let detail = format!("Connection to {} restarted", other);
+                self.record_timeline(room_id, TimelineKind::IceRestart, Some(peer_id), detail)
+                    .await;
+            }
+            Some(Restart::GaveUp) => warn!(
+                "Gave up restarting ICE between {} and {} in room {}",
+                peer_id, other, room_id
+            ),
+            None => {}
+        }
+    }
+}
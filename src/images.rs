@@ -0,0 +1,153 @@
+//! Server-side image processing
+//!
+//! With `images` configured, uploaded images are decoded and encoded again
+//! in-process before anyone is served them: turned upright as their EXIF
+//! orientation says, scaled down to fit `max_width` by `max_height`, and
+//! converted to WebP (or JPEG) without EXIF or any other metadata. Clients
+//! then get images of a known size and format that do not leak where or on
+//! what they were taken. Images whose header claims more than
+//! [`MAX_SOURCE_SIDE`] pixels a side are refused before decoding.
+//! Room previews are the one image upload so far.
+
+use std::io::Cursor;
+
+use axum::{body::Bytes, http::StatusCode};
+use image::codecs::jpeg::{JpegDecoder, JpegEncoder};
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ExtendedColorType, ImageDecoder, ImageResult, Limits};
+use serde::Deserialize;
+use tracing::warn;
+
+/// Longest side accepted in an uploaded image, before scaling
+pub const MAX_SOURCE_SIDE: u32 = 8192;
+
+/// Format images are converted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    Webp,
+    Jpeg,
+}
+
+impl ImageFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Jpeg => "image/jpeg",
+        }
+    }
+}
+
+/// How uploaded images are processed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ImagesConfig {
+    /// Largest width served; wider images are scaled down
+    pub max_width: u32,
+    /// Largest height served; taller images are scaled down
+    pub max_height: u32,
+    pub format: ImageFormat,
+    /// JPEG quality, 1 (smallest) to 100 (best); WebP is always lossless
+    pub quality: u8,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            max_width: 1280,
+            max_height: 720,
+            format: ImageFormat::Webp,
+            quality: 80,
+        }
+    }
+}
+
+type Rejection = (StatusCode, &'static str);
+
+/// Width and height from a JPEG's frame header
+pub fn jpeg_dimensions(jpeg: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    loop {
+        // Markers may be padded with any number of 0xFF bytes
+        while *jpeg.get(at)? == 0xFF && *jpeg.get(at + 1)? == 0xFF {
+            at += 1;
+        }
+        if *jpeg.get(at)? != 0xFF {
+            return None;
+        }
+        let marker = *jpeg.get(at + 1)?;
+        if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+            at += 2;
+            continue;
+        }
+        let length = u16::from_be_bytes([*jpeg.get(at + 2)?, *jpeg.get(at + 3)?]) as usize;
+        match marker {
+            // Start of frame, in any coding but the DHT, JPG and DAC markers
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                let height = u16::from_be_bytes([*jpeg.get(at + 5)?, *jpeg.get(at + 6)?]);
+                let width = u16::from_be_bytes([*jpeg.get(at + 7)?, *jpeg.get(at + 8)?]);
+                return Some((width as u32, height as u32));
+            }
+            // Start of scan or end of image before any frame
+            0xDA | 0xD9 => return None,
+            _ => at += 2 + length,
+        }
+    }
+}
+
+/// Check a JPEG's size and re-encode it as configured
+pub async fn process_jpeg(config: &ImagesConfig, jpeg: &[u8]) -> Result<Bytes, Rejection> {
+    let Some((width, height)) = jpeg_dimensions(jpeg) else {
+        return Err((StatusCode::BAD_REQUEST, "Not a JPEG"));
+    };
+    if width == 0 || height == 0 || width.max(height) > MAX_SOURCE_SIDE {
+        return Err((StatusCode::BAD_REQUEST, "Image dimensions out of range"));
+    }
+    let (config, jpeg) = (config.clone(), jpeg.to_vec());
+    match tokio::task::spawn_blocking(move || reencode(&config, &jpeg)).await {
+        Ok(Ok(image)) => Ok(Bytes::from(image)),
+        Ok(Err(e)) => {
+            warn!("Failed to process an image: {}", e);
+            Err((StatusCode::BAD_REQUEST, "Unreadable image"))
+        }
+        Err(e) => {
+            warn!("Processing an image failed: {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to process the image",
+            ))
+        }
+    }
+}
+
+/// Decode a JPEG, turn it upright, scale it down and encode it again,
+/// leaving its metadata behind
+fn reencode(config: &ImagesConfig, jpeg: &[u8]) -> ImageResult<Vec<u8>> {
+    let mut decoder = JpegDecoder::new(Cursor::new(jpeg))?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_SIDE);
+    limits.max_image_height = Some(MAX_SOURCE_SIDE);
+    decoder.set_limits(limits)?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    let (max_width, max_height) = (config.max_width.max(1), config.max_height.max(1));
+    if image.width() > max_width || image.height() > max_height {
+        image = image.resize(max_width, max_height, FilterType::Lanczos3);
+    }
+    let image = image.to_rgb8();
+    let mut out = Vec::new();
+    match config.format {
+        ImageFormat::Webp => WebPEncoder::new_lossless(&mut out).encode(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ExtendedColorType::Rgb8,
+        )?,
+        ImageFormat::Jpeg => JpegEncoder::new_with_quality(&mut out, config.quality.clamp(1, 100))
+            .encode_image(&image)?,
+    }
+    Ok(out)
+}
@@ -0,0 +1,83 @@
+//! Outbound chat integrations
+//!
+//! Posts a "call started" message with the room link to Slack and/or Discord
+//! incoming webhooks when a room is created. A tenant's rooms are announced
+//! on the tenant's own channels, and by email from its sender if it has
+//! one.
+
+use crate::models::JobKind;
+use crate::state::AppState;
+use serde::Deserialize;
+use serde_json::json;
+
+/// Slack/Discord webhook settings
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct IntegrationsConfig {
+    /// Slack incoming webhook URL
+    pub slack_webhook_url: Option<String>,
+    /// Discord channel webhook URL
+    pub discord_webhook_url: Option<String>,
+    /// Only announce rooms created with `"notify": true`
+    pub only_tagged: bool,
+}
+
+impl IntegrationsConfig {
+    fn is_enabled(&self) -> bool {
+        self.slack_webhook_url.is_some() || self.discord_webhook_url.is_some()
+    }
+}
+
+/// Announce a newly created room on the channels of `tenant`, or the
+/// top-level ones
+///
+/// Each post is a background job, retried if it fails.
+pub async fn announce_room_created(
+    state: &AppState,
+    room_id: &str,
+    notify: bool,
+    tenant: Option<&str>,
+) {
+    let app_config = state.config();
+    let Some(config) = app_config.integrations_for(tenant) else {
+        return;
+    };
+    let email = tenant
+        .and_then(|name| app_config.tenants.get(name))
+        .and_then(|t| t.email.as_ref());
+    if (!config.is_enabled() && email.is_none()) || (config.only_tagged && !notify) {
+        return;
+    }
+
+    let link = format!(
+        "{}/room/{}",
+        app_config.public_url.trim_end_matches('/'),
+        room_id
+    );
+    let text = format!("Call started — join here: {}", link);
+    let targets = [
+        config
+            .slack_webhook_url
+            .clone()
+            .map(|url| (url, json!({ "text": text }))),
+        config
+            .discord_webhook_url
+            .clone()
+            .map(|url| (url, json!({ "content": text }))),
+    ];
+
+    for (url, body) in targets.into_iter().flatten() {
+        state.enqueue_job(JobKind::Notify { url, body }).await;
+    }
+    if let Some(email) = email {
+        state
+            .enqueue_job(JobKind::Email {
+                smtp: email.smtp.clone(),
+                from: email.from.clone(),
+                to: email.to.clone(),
+                subject: "Call started".to_string(),
+                text,
+            })
+            .await;
+    }
+}
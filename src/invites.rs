@@ -0,0 +1,64 @@
+//! Invite-only rooms
+//!
+//! A room created with `invitees` only admits holders of a room JWT for it,
+//! or of a user token, whose `sub` or `email` is on the list, so a leaked
+//! link alone does not get anyone in. Emails match regardless of case; subjects match exactly. A peer
+//! that was admitted may still come back with its resume token, but
+//! re-invite links do not open such a room.
+
+use crate::state::{AppState, Room};
+use crate::token::{TokenScope, secret_eq, verify};
+
+impl Room {
+    /// Whether a token naming `sub` and `email` is on the room's list
+    pub fn invites(&self, sub: Option<&str>, email: Option<&str>) -> bool {
+        let Some(invitees) = &self.invitees else {
+            return true;
+        };
+        invitees.iter().any(|entry| {
+            sub == Some(entry.as_str()) || email.is_some_and(|e| e.eq_ignore_ascii_case(entry))
+        })
+    }
+
+    /// Whether `token` belongs to a peer this room already admitted
+    pub fn admitted_before(&self, token: &str) -> bool {
+        self.peers.iter().any(|p| secret_eq(&p.resume_token, token))
+            || self
+                .reservations
+                .iter()
+                .any(|r| secret_eq(&r.token, token) && r.peer_id.is_some())
+    }
+}
+
+impl AppState {
+    /// Restrict a room to the given emails and token subjects
+    pub async fn invite_only(&self, room_id: &str, invitees: Vec<String>) {
+        self.with_room(room_id, move |room| room.invitees = Some(invitees))
+            .await;
+        self.save_room_state(room_id).await;
+    }
+
+    /// Whether `token` lets its holder into `room_id` if the room is
+    /// invite-only; true for every other room
+    pub async fn is_invited(&self, room_id: &str, token: Option<&str>) -> bool {
+        let config = self.config();
+        let claims = config
+            .jwt_secret
+            .as_deref()
+            .zip(token)
+            .and_then(|(secret, token)| verify(secret, token).ok())
+            .filter(|c| match c.scope {
+                TokenScope::Room => c.room.as_deref() == Some(room_id),
+                TokenScope::User => self.is_provisioned(c.sub.as_deref(), c.email.as_deref()),
+                _ => false,
+            });
+        let token = token.map(str::to_string);
+        self.with_room(room_id, move |room| {
+            room.invitees.is_none()
+                || claims.is_some_and(|c| room.invites(c.sub.as_deref(), c.email.as_deref()))
+                || token.is_some_and(|t| room.admitted_before(&t))
+        })
+        .await
+        .unwrap_or(true)
+    }
+}
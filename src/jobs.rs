@@ -0,0 +1,365 @@
+//! Background jobs
+//!
+//! Work that happens after a request has been answered (building export
+//! bundles, post-processing recordings, posting chat and alert
+//! notifications, mailing alerts, ringing mobile devices) goes through a job queue
+//! instead of a fire-and-forget task. A job starts as soon as it is queued;
+//! if it fails it is retried with exponential backoff, and after its last
+//! attempt it is kept as failed. With a record store attached, the queue is
+//! stored as well, so jobs queued or running when the server stopped are
+//! run again on startup. `GET /admin/jobs` lists jobs and
+//! `POST /admin/jobs/{id}/retry` runs one again straight away.
+//!
+//! Webhook events keep their own outbox, as they must go out in order.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::admin::{AdminAuth, OperatorAuth};
+use crate::alerts::send_mail;
+use crate::models::{Job, JobKind, JobStatus};
+use crate::state::{AppState, unix_timestamp};
+
+/// Finished and failed jobs kept for inspection, each; the oldest are
+/// dropped first
+pub const MAX_FINISHED_JOBS: usize = 10_000;
+
+/// Attempts before a job is given up on
+pub const JOB_ATTEMPTS: u32 = 5;
+
+/// Seconds before the first retry; doubled after each further failure
+const JOB_RETRY_SECS: u64 = 5;
+
+/// Longest wait between retries
+const MAX_JOB_RETRY_SECS: u64 = 3600;
+
+/// How long one attempt may take
+const JOB_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How long one notification post may take
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Queued, running, finished and failed jobs, oldest first
+#[derive(Debug, Default)]
+pub struct JobQueue {
+    jobs: VecDeque<Job>,
+}
+
+impl JobQueue {
+    pub fn get(&self, id: &str) -> Option<&Job> {
+        self.jobs.iter().find(|j| j.id == id)
+    }
+
+    fn get_mut(&mut self, id: &str) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|j| j.id == id)
+    }
+
+    /// Add a job, dropping the oldest finished and failed ones past the
+    /// limit
+    fn push(&mut self, job: Job) {
+        self.jobs.push_back(job);
+        self.trim(JobStatus::Done);
+        self.trim(JobStatus::Failed);
+    }
+
+    /// Drop the oldest jobs in `status` beyond [`MAX_FINISHED_JOBS`]
+    fn trim(&mut self, status: JobStatus) {
+        let mut excess = self
+            .jobs
+            .iter()
+            .filter(|j| j.status == status)
+            .count()
+            .saturating_sub(MAX_FINISHED_JOBS);
+        self.jobs.retain(|j| {
+            let drop = excess > 0 && j.status == status;
+            excess -= usize::from(drop);
+            !drop
+        });
+    }
+
+    /// Mark a job running and count the attempt
+    fn claim(&mut self, id: &str) -> Option<Job> {
+        let job = self.get_mut(id)?;
+        job.status = JobStatus::Running;
+        job.attempts += 1;
+        Some(job.clone())
+    }
+}
+
+/// Wait before the next attempt, after `attempts` failed ones
+fn backoff(attempts: u32) -> u64 {
+    let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+    JOB_RETRY_SECS
+        .saturating_mul(factor)
+        .min(MAX_JOB_RETRY_SECS)
+}
+
+impl AppState {
+    /// Queue a job and start it
+    pub async fn enqueue_job(&self, kind: JobKind) -> Job {
+        let now = unix_timestamp();
+        let job = Job {
+            id: Uuid::new_v4().to_string(),
+            kind,
+            status: JobStatus::Pending,
+            attempts: 0,
+            max_attempts: JOB_ATTEMPTS,
+            created_at: now,
+            next_attempt_at: now,
+            finished_at: None,
+            last_error: None,
+        };
+        let job = {
+            let mut queue = self.jobs.lock().await;
+            let id = job.id.clone();
+            queue.push(job);
+            queue.claim(&id).expect("job was just queued")
+        };
+        self.store_job(job.clone()).await;
+        self.start_job(job.clone());
+        job
+    }
+
+    /// Put jobs read back from the record store into the queue; those that
+    /// were running when the server stopped are run again
+    pub(crate) async fn restore_jobs(&self, jobs: Vec<Job>) {
+        let mut queue = self.jobs.lock().await;
+        for mut job in jobs {
+            if job.status == JobStatus::Running {
+                job.status = JobStatus::Pending;
+            }
+            if queue.get(&job.id).is_none() {
+                queue.push(job);
+            }
+        }
+    }
+
+    /// Start every pending job whose next attempt is due
+    pub async fn run_due_jobs(&self) {
+        let now = unix_timestamp();
+        let claimed: Vec<Job> = {
+            let mut queue = self.jobs.lock().await;
+            let due: Vec<String> = queue
+                .jobs
+                .iter()
+                .filter(|j| j.status == JobStatus::Pending && j.next_attempt_at <= now)
+                .map(|j| j.id.clone())
+                .collect();
+            due.iter().filter_map(|id| queue.claim(id)).collect()
+        };
+        for job in claimed {
+            self.store_job(job.clone()).await;
+            self.start_job(job);
+        }
+    }
+
+    /// Run one attempt of a claimed job in the background
+    fn start_job(&self, job: Job) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            let result = tokio::time::timeout(JOB_TIMEOUT, state.execute_job(&job))
+                .await
+                .unwrap_or_else(|_| Err("Timed out".to_string()));
+            state.finish_job(&job.id, result).await;
+        });
+    }
+
+    /// Record the outcome of an attempt
+    async fn finish_job(&self, id: &str, result: Result<(), String>) {
+        let mut queue = self.jobs.lock().await;
+        let Some(job) = queue.get_mut(id) else {
+            return;
+        };
+        match result {
+            Ok(()) => {
+                debug!("Job {} done", id);
+                job.status = JobStatus::Done;
+                job.finished_at = Some(unix_timestamp());
+                job.last_error = None;
+            }
+            Err(e) if job.attempts >= job.max_attempts => {
+                warn!(
+                    "Giving up on job {} after {} attempts: {}",
+                    id, job.attempts, e
+                );
+                job.status = JobStatus::Failed;
+                job.finished_at = Some(unix_timestamp());
+                job.last_error = Some(e);
+            }
+            Err(e) => {
+                let wait = backoff(job.attempts);
+                warn!(
+                    "Job {} failed (attempt {}), retrying in {}s: {}",
+                    id, job.attempts, wait, e
+                );
+                job.status = JobStatus::Pending;
+                job.next_attempt_at = unix_timestamp() + wait;
+                job.last_error = Some(e);
+            }
+        }
+        let job = job.clone();
+        drop(queue);
+        self.store_job(job).await;
+    }
+
+    /// Do a job's work once
+    async fn execute_job(&self, job: &Job) -> Result<(), String> {
+        match &job.kind {
+            JobKind::Export { room_id } => self.build_export(&job.id, room_id).await,
+            JobKind::ScanVoicemail { room_id } => self.scan_voicemail(room_id).await,
+            JobKind::ProcessRecording { recording_id, step } => {
+                self.process_recording(recording_id, *step).await
+            }
+            JobKind::Push {
+                user,
+                platform,
+                token,
+                message,
+                expires_at,
+            } => {
+                self.send_push(user, *platform, token, message, *expires_at)
+                    .await
+            }
+            JobKind::Email {
+                smtp,
+                from,
+                to,
+                subject,
+                text,
+            } => send_mail(smtp, from, to, subject, text).await,
+            JobKind::Notify { url, body } => {
+                let response = self
+                    .http
+                    .post(url)
+                    .json(body)
+                    .timeout(NOTIFY_TIMEOUT)
+                    .send()
+                    .await
+                    .map_err(|e| e.to_string())?;
+                match response.status().is_success() {
+                    true => Ok(()),
+                    false => Err(format!("Endpoint answered {}", response.status())),
+                }
+            }
+        }
+    }
+
+    /// A job by ID
+    pub async fn job(&self, id: &str) -> Option<Job> {
+        self.jobs.lock().await.get(id).cloned()
+    }
+
+    /// Jobs in the queue, newest first, optionally only those in `status`
+    pub async fn list_jobs(&self, status: Option<JobStatus>) -> Vec<Job> {
+        self.jobs
+            .lock()
+            .await
+            .jobs
+            .iter()
+            .rev()
+            .filter(|j| status.is_none_or(|s| j.status == s))
+            .cloned()
+            .collect()
+    }
+
+    /// Run a job now with a fresh set of attempts, whether it is waiting
+    /// for a retry, done or failed; a running one is left as it is
+    pub async fn retry_job(&self, id: &str) -> Option<Job> {
+        let claimed = {
+            let mut queue = self.jobs.lock().await;
+            let job = queue.get_mut(id)?;
+            if job.status == JobStatus::Running {
+                return Some(job.clone());
+            }
+            job.status = JobStatus::Pending;
+            job.attempts = 0;
+            job.next_attempt_at = unix_timestamp();
+            job.finished_at = None;
+            queue.claim(id)?
+        };
+        self.store_job(claimed.clone()).await;
+        self.start_job(claimed.clone());
+        Some(claimed)
+    }
+}
+
+/// Spawn the task retrying failed jobs and running restored ones
+pub fn spawn_job_runner(state: AppState) {
+    info!("Running background jobs");
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            state.run_due_jobs().await;
+        }
+    });
+}
+
+/// Query parameters for the job list
+#[derive(Debug, Deserialize)]
+pub struct JobQuery {
+    pub status: Option<JobStatus>,
+}
+
+/// List background jobs
+#[utoipa::path(
+    get,
+    path = "/admin/jobs",
+    tag = "Admin",
+    params(
+        ("status" = Option<JobStatus>, Query, description = "Only jobs in this state, e.g. `failed`")
+    ),
+    responses(
+        (status = 200, description = "Jobs, newest first", body = [Job]),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_jobs(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+    Query(query): Query<JobQuery>,
+) -> Json<Vec<Job>> {
+    Json(state.list_jobs(query.status).await)
+}
+
+/// Run a job again now
+#[utoipa::path(
+    post,
+    path = "/admin/jobs/{id}/retry",
+    tag = "Admin",
+    params(
+        ("id" = String, Path, description = "Job ID")
+    ),
+    responses(
+        (status = 200, description = "The job, running again unless it already was", body = Job),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "No such job")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn retry_job(
+    OperatorAuth(auth): OperatorAuth,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let Some(job) = state.retry_job(&id).await else {
+        return (StatusCode::NOT_FOUND, "No such job").into_response();
+    };
+    info!("Retrying job {}", id);
+    state
+        .record_audit(None, &auth.principal, "jobs.retry", &id)
+        .await;
+    Json(job).into_response()
+}
@@ -0,0 +1,166 @@
+//! Waiting line for full rooms
+//!
+//! With `join_queue` configured, a peer connecting to a full room is not
+//! turned away. It is told its place with `queue_position` and admitted in
+//! arrival order as slots free up, for example when a dropped host's held
+//! slot runs out. While anyone is waiting, new joiners line up behind them
+//! even if a slot happens to be free; holders of a reservation (resuming or
+//! re-invited peers) skip the line. A peer gives up by closing its socket
+//! and is turned away after `max_wait_secs`.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::sync::oneshot;
+use tracing::{debug, info};
+
+use crate::models::WsMessage;
+use crate::state::{AppState, JoinedRoom, PeerSender, Room};
+
+/// Limits of a room's waiting line; joins to full rooms are rejected
+/// immediately when unset
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JoinQueueConfig {
+    /// Peers that may wait per room; later ones are rejected
+    pub max_waiting: usize,
+    /// Seconds a peer may wait before it is turned away
+    pub max_wait_secs: u64,
+}
+
+impl Default for JoinQueueConfig {
+    fn default() -> Self {
+        Self {
+            max_waiting: 20,
+            max_wait_secs: 120,
+        }
+    }
+}
+
+impl JoinQueueConfig {
+    pub fn max_wait(&self) -> Duration {
+        Duration::from_secs(self.max_wait_secs)
+    }
+}
+
+/// A peer waiting for a slot
+#[derive(Debug)]
+pub struct Waiter {
+    pub peer_id: String,
+    sender: PeerSender,
+    admit: oneshot::Sender<JoinedRoom>,
+}
+
+/// Outcome of asking to join, possibly by waiting
+#[derive(Debug)]
+pub enum Admission {
+    Joined(JoinedRoom),
+    /// Queued at `position` (1-based); `admitted` resolves once the peer
+    /// is seated and fails if the room closes first
+    Queued {
+        position: usize,
+        max_wait: Duration,
+        admitted: oneshot::Receiver<JoinedRoom>,
+    },
+}
+
+impl Room {
+    /// Put a peer at the back of the line and tell it its place
+    pub fn enqueue(
+        &mut self,
+        peer_id: String,
+        sender: PeerSender,
+        config: &JoinQueueConfig,
+    ) -> Result<Admission, &'static str> {
+        if self.waiting.len() >= config.max_waiting {
+            return Err("Room is full and its waiting line too");
+        }
+        let (admit, admitted) = oneshot::channel();
+        let position = self.waiting.len() + 1;
+        let _ = sender.send(WsMessage::QueuePosition { n: position });
+        self.waiting.push_back(Waiter {
+            peer_id,
+            sender,
+            admit,
+        });
+        Ok(Admission::Queued {
+            position,
+            max_wait: config.max_wait(),
+            admitted,
+        })
+    }
+
+    /// Remove a peer from the line, telling those behind it they moved up
+    pub fn dequeue(&mut self, peer_id: &str) {
+        let before = self.waiting.len();
+        self.waiting.retain(|w| w.peer_id != peer_id);
+        if self.waiting.len() != before {
+            self.announce_positions();
+        }
+    }
+
+    /// Seat waiting peers in order while there is room
+    pub fn admit_waiting(&mut self) {
+        let mut moved = false;
+        while !self.is_full() && !self.awaits_owner() {
+            let Some(waiter) = self.waiting.pop_front() else {
+                break;
+            };
+            moved = true;
+            // Gave up meanwhile
+            if waiter.admit.is_closed() {
+                continue;
+            }
+            let peer_id = waiter.peer_id.clone();
+            let Ok(joined) = self.seat(waiter.peer_id, waiter.sender) else {
+                continue;
+            };
+            if waiter.admit.send(joined).is_err() {
+                self.remove_peer(&peer_id);
+                continue;
+            }
+            info!("Admitted peer {} from the waiting line", peer_id);
+        }
+        if moved {
+            self.announce_positions();
+        }
+    }
+
+    fn announce_positions(&self) {
+        for (i, waiter) in self.waiting.iter().enumerate() {
+            let _ = waiter.sender.send(WsMessage::QueuePosition { n: i + 1 });
+        }
+    }
+}
+
+impl AppState {
+    /// Join a room, or wait in its line if it is full and queuing is on
+    ///
+    /// A queued peer is recorded with [`AppState::record_join`] once it is
+    /// admitted.
+    pub async fn join_or_queue(
+        &self,
+        room_id: &str,
+        peer_id: String,
+        sender: PeerSender,
+        token: Option<&str>,
+    ) -> Result<Admission, &'static str> {
+        let admission = self
+            .enter_room(room_id, peer_id.clone(), sender, token, true)
+            .await?;
+        if let Admission::Queued { position, .. } = &admission {
+            debug!(
+                "Peer {} is number {} in line for room {}",
+                peer_id, position, room_id
+            );
+        }
+        Ok(admission)
+    }
+
+    /// Give up waiting for a slot
+    pub async fn leave_queue(&self, room_id: &str, peer_id: &str) {
+        let peer_id = peer_id.to_string();
+        self.with_room(room_id, move |room| room.dequeue(&peer_id))
+            .await;
+    }
+}
@@ -0,0 +1,426 @@
+//! Prioritized per-peer delivery queues
+//!
+//! Messages for a peer are queued in four lanes drained in priority order:
+//! control (room membership, errors, call control), signaling (SDP, ICE,
+//! media state), chat, and telemetry (captions, stats). A peer whose socket
+//! is congested therefore still gets an offer promptly even with a backlog
+//! of chat behind it. Each lane has its own bound and overflow policy:
+//! control and signaling refuse new messages when full (the caller logs the
+//! failure), chat drops the newest message, and telemetry drops the oldest,
+//! since only recent values are worth showing.
+//!
+//! Control messages fanned out to a whole room (room info, joins, leaves,
+//! role and recording changes) do not go through the lanes at all. Each
+//! room has one broadcast channel that every peer subscribes to when it is
+//! added, so an announcement is sent once under the rooms lock instead of
+//! being cloned into every peer's queue; each receiver clones it on the
+//! way out. Room events are delivered ahead of the control lane. A peer
+//! that falls more than [`ROOM_EVENT_CAPACITY`] events behind skips the
+//! oldest ones, which only happens to a connection that has stalled.
+//!
+//! Lanes hold [`Frame`]s: either a message to serialize on the way out, or
+//! JSON text relayed verbatim from another peer (see [`crate::raw_relay`]),
+//! which shares the sender's receive buffer instead of being copied.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::ws::Utf8Bytes;
+use tokio::sync::Notify;
+use tokio::sync::broadcast::{self, error::RecvError, error::TryRecvError};
+use tracing::warn;
+
+use crate::models::WsMessage;
+
+/// Room announcements buffered per room before slow peers start missing
+/// them
+pub const ROOM_EVENT_CAPACITY: usize = 256;
+
+/// A control message fanned out to every peer in a room
+#[derive(Debug, Clone)]
+pub struct RoomEvent {
+    pub msg: Arc<WsMessage>,
+    /// Peer that does not receive the event, usually its cause
+    pub except: Option<Arc<str>>,
+}
+
+/// Sending half of a room's announcement channel
+pub type RoomEvents = broadcast::Sender<RoomEvent>;
+
+/// Create a room's announcement channel
+pub fn room_events() -> RoomEvents {
+    broadcast::channel(ROOM_EVENT_CAPACITY).0
+}
+
+/// A peer's subscription to its room's announcements
+#[derive(Debug)]
+struct RoomSubscription {
+    events: broadcast::Receiver<RoomEvent>,
+    peer_id: String,
+}
+
+impl RoomSubscription {
+    fn accepts(&self, event: &RoomEvent) -> bool {
+        event.except.as_deref() != Some(self.peer_id.as_str())
+    }
+}
+
+/// Pending change to a peer's room subscription, picked up by its receiver
+#[derive(Debug)]
+enum Membership {
+    Attach(RoomSubscription),
+    Detach,
+}
+
+/// One queued outbound message
+#[derive(Debug, Clone)]
+pub enum Frame {
+    Message(WsMessage),
+    /// Serialized message text forwarded unchanged
+    Raw(Utf8Bytes),
+}
+
+impl Frame {
+    /// Text to write to the peer's socket
+    pub fn into_text(self) -> serde_json::Result<Utf8Bytes> {
+        match self {
+            Frame::Message(msg) => serde_json::to_string(&msg).map(Utf8Bytes::from),
+            Frame::Raw(text) => Ok(text),
+        }
+    }
+
+    /// The frame as a message, parsing raw text
+    pub fn into_message(self) -> serde_json::Result<WsMessage> {
+        match self {
+            Frame::Message(msg) => Ok(msg),
+            Frame::Raw(text) => serde_json::from_str(text.as_str()),
+        }
+    }
+}
+
+/// Delivery lane, highest priority first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    Control,
+    Signaling,
+    Chat,
+    Telemetry,
+}
+
+/// What a full lane does with one more message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Refuse the new message and report it to the sender
+    Reject,
+    /// Discard the new message silently
+    DropNewest,
+    /// Discard the oldest queued message to make room
+    DropOldest,
+}
+
+impl Lane {
+    const ALL: [Lane; 4] = [Lane::Control, Lane::Signaling, Lane::Chat, Lane::Telemetry];
+
+    /// The lane a message travels in
+    pub fn of(msg: &WsMessage) -> Self {
+        match msg {
+            WsMessage::Offer { .. }
+            | WsMessage::Answer { .. }
+            | WsMessage::IceCandidate { .. }
+            | WsMessage::Capabilities { .. }
+            | WsMessage::DeviceInfo { .. }
+            | WsMessage::MediaStatus { .. }
+            | WsMessage::ScreenShare { .. }
+            | WsMessage::Dtmf { .. } => Lane::Signaling,
+            WsMessage::Chat { .. } | WsMessage::Reaction { .. } => Lane::Chat,
+            WsMessage::Caption { .. }
+            | WsMessage::QualityStats { .. }
+            | WsMessage::AudioLevel { .. }
+            | WsMessage::MediaStats { .. }
+            | WsMessage::PeerStatus { .. }
+            | WsMessage::NetTestProbe { .. }
+            | WsMessage::NetTestReport { .. } => Lane::Telemetry,
+            _ => Lane::Control,
+        }
+    }
+
+    /// Messages the lane holds before overflowing
+    pub fn bound(self) -> usize {
+        match self {
+            Lane::Control => 256,
+            Lane::Signaling => 1024,
+            Lane::Chat => 128,
+            Lane::Telemetry => 32,
+        }
+    }
+
+    pub fn overflow(self) -> Overflow {
+        match self {
+            Lane::Control | Lane::Signaling => Overflow::Reject,
+            Lane::Chat => Overflow::DropNewest,
+            Lane::Telemetry => Overflow::DropOldest,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Why a message was not queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The peer's connection has gone away
+    Closed,
+    /// The lane is full and refuses new messages
+    Full(Lane),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Closed => write!(f, "peer connection closed"),
+            SendError::Full(lane) => write!(f, "{:?} lane full", lane),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+#[derive(Debug)]
+struct Shared {
+    lanes: Mutex<[VecDeque<Frame>; 4]>,
+    /// Wakes the receiver; holds a permit if it is not waiting yet
+    notify: Notify,
+    senders: AtomicUsize,
+    receiver_closed: AtomicBool,
+    membership: Mutex<Option<Membership>>,
+}
+
+/// Sending half of a peer's lanes; the receiver ends once every clone is
+/// dropped
+#[derive(Debug)]
+pub struct PeerSender(Arc<Shared>);
+
+/// Receiving half of a peer's lanes, owned by its socket writer
+#[derive(Debug)]
+pub struct PeerReceiver {
+    shared: Arc<Shared>,
+    room: Option<RoomSubscription>,
+}
+
+/// Create a peer's delivery lanes
+pub fn peer_channel() -> (PeerSender, PeerReceiver) {
+    let shared = Arc::new(Shared {
+        lanes: Mutex::new(Default::default()),
+        notify: Notify::new(),
+        senders: AtomicUsize::new(1),
+        receiver_closed: AtomicBool::new(false),
+        membership: Mutex::new(None),
+    });
+    let receiver = PeerReceiver {
+        shared: shared.clone(),
+        room: None,
+    };
+    (PeerSender(shared), receiver)
+}
+
+impl PeerSender {
+    /// Queue a message in its lane, applying the lane's overflow policy
+    ///
+    /// A message discarded by `DropNewest` or `DropOldest` still counts as
+    /// sent.
+    pub fn send(&self, msg: WsMessage) -> Result<(), SendError> {
+        self.push(Lane::of(&msg), Frame::Message(msg))
+    }
+
+    /// Queue already serialized text in `lane`, which the caller derived
+    /// from the text's `type` tag
+    pub fn send_raw(&self, lane: Lane, text: Utf8Bytes) -> Result<(), SendError> {
+        self.push(lane, Frame::Raw(text))
+    }
+
+    fn push(&self, lane: Lane, frame: Frame) -> Result<(), SendError> {
+        if self.0.receiver_closed.load(Ordering::Acquire) {
+            return Err(SendError::Closed);
+        }
+        {
+            let mut lanes = self.0.lanes.lock().unwrap_or_else(|e| e.into_inner());
+            let queue = &mut lanes[lane.index()];
+            if queue.len() >= lane.bound() {
+                match lane.overflow() {
+                    Overflow::Reject => return Err(SendError::Full(lane)),
+                    Overflow::DropNewest => return Ok(()),
+                    Overflow::DropOldest => {
+                        queue.pop_front();
+                    }
+                }
+            }
+            queue.push_back(frame);
+        }
+        self.0.notify.notify_one();
+        Ok(())
+    }
+
+    /// Whether the receiving side has gone away
+    pub fn is_closed(&self) -> bool {
+        self.0.receiver_closed.load(Ordering::Acquire)
+    }
+
+    /// Start receiving a room's announcements as `peer_id`
+    pub fn attach_room(&self, events: &RoomEvents, peer_id: &str) {
+        let subscription = RoomSubscription {
+            events: events.subscribe(),
+            peer_id: peer_id.to_string(),
+        };
+        self.set_membership(Membership::Attach(subscription));
+    }
+
+    /// Stop receiving room announcements, including any not yet delivered
+    pub fn detach_room(&self) {
+        self.set_membership(Membership::Detach);
+    }
+
+    fn set_membership(&self, membership: Membership) {
+        *self.0.membership.lock().unwrap_or_else(|e| e.into_inner()) = Some(membership);
+        self.0.notify.notify_one();
+    }
+}
+
+impl Clone for PeerSender {
+    fn clone(&self) -> Self {
+        self.0.senders.fetch_add(1, Ordering::AcqRel);
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for PeerSender {
+    fn drop(&mut self) {
+        if self.0.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.notify.notify_one();
+        }
+    }
+}
+
+impl PeerReceiver {
+    /// Next frame by priority, or `None` once every sender is gone and
+    /// nothing is left to deliver
+    pub async fn recv_frame(&mut self) -> Option<Frame> {
+        loop {
+            if let Some(frame) = self.try_recv_frame() {
+                return Some(frame);
+            }
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                return self.try_recv_frame();
+            }
+            let shared = self.shared.clone();
+            let Some(room) = &mut self.room else {
+                shared.notify.notified().await;
+                continue;
+            };
+            let event = tokio::select! {
+                _ = shared.notify.notified() => continue,
+                event = room.events.recv() => event,
+            };
+            match event {
+                // A membership change queued meanwhile makes the event stale
+                Ok(event) if !self.adopt_membership() && self.accepts(&event) => {
+                    return Some(Frame::Message(Arc::unwrap_or_clone(event.msg)));
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(missed)) => self.lagged(missed),
+                Err(RecvError::Closed) => self.room = None,
+            }
+        }
+    }
+
+    /// Next queued frame by priority, without waiting
+    pub fn try_recv_frame(&mut self) -> Option<Frame> {
+        self.adopt_membership();
+        if let Some(msg) = self.try_recv_room() {
+            return Some(Frame::Message(msg));
+        }
+        let mut lanes = self.shared.lanes.lock().unwrap_or_else(|e| e.into_inner());
+        Lane::ALL
+            .iter()
+            .find_map(|lane| lanes[lane.index()].pop_front())
+    }
+
+    /// Next message by priority, parsing relayed text; `None` once every
+    /// sender is gone and nothing is left to deliver
+    pub async fn recv(&mut self) -> Option<WsMessage> {
+        loop {
+            if let Some(msg) = Self::parsed(self.recv_frame().await?) {
+                return Some(msg);
+            }
+        }
+    }
+
+    /// Next queued message by priority, without waiting
+    pub fn try_recv(&mut self) -> Option<WsMessage> {
+        loop {
+            if let Some(msg) = Self::parsed(self.try_recv_frame()?) {
+                return Some(msg);
+            }
+        }
+    }
+
+    fn parsed(frame: Frame) -> Option<WsMessage> {
+        frame
+            .into_message()
+            .inspect_err(|e| warn!("Dropped unparsable relayed message: {}", e))
+            .ok()
+    }
+
+    fn try_recv_room(&mut self) -> Option<WsMessage> {
+        loop {
+            let room = self.room.as_mut()?;
+            match room.events.try_recv() {
+                Ok(event) if room.accepts(&event) => {
+                    return Some(Arc::unwrap_or_clone(event.msg));
+                }
+                Ok(_) => {}
+                Err(TryRecvError::Lagged(missed)) => self.lagged(missed),
+                Err(TryRecvError::Closed) => self.room = None,
+                Err(TryRecvError::Empty) => return None,
+            }
+        }
+    }
+
+    fn accepts(&self, event: &RoomEvent) -> bool {
+        self.room.as_ref().is_some_and(|room| room.accepts(event))
+    }
+
+    /// Apply a pending subscription change; true if there was one
+    fn adopt_membership(&mut self) -> bool {
+        let pending = self
+            .shared
+            .membership
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        match pending {
+            Some(Membership::Attach(subscription)) => self.room = Some(subscription),
+            Some(Membership::Detach) => self.room = None,
+            None => return false,
+        }
+        true
+    }
+
+    fn lagged(&self, missed: u64) {
+        if let Some(room) = &self.room {
+            warn!(
+                "Peer {} fell behind and missed {} room events",
+                room.peer_id, missed
+            );
+        }
+    }
+}
+
+impl Drop for PeerReceiver {
+    fn drop(&mut self) {
+        self.shared.receiver_closed.store(true, Ordering::Release);
+    }
+}
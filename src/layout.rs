@@ -0,0 +1,62 @@
+//! Shared stage layout
+//!
+//! The host decides how everyone's stage is arranged with `layout_update`:
+//! a `grid`, or a `speaker` view, optionally with one peer pinned. The room
+//! keeps the arrangement, broadcasts every change and sends it to each
+//! joiner after `welcome`, so all participants see the same stage. A pin is
+//! dropped when the pinned peer leaves.
+
+use tracing::info;
+
+use crate::models::{StageLayout, WsMessage};
+use crate::state::{JoinedRoom, Room};
+
+impl Room {
+    /// Apply the host's `LayoutUpdate`
+    pub fn set_layout(
+        &mut self,
+        host_id: &str,
+        pinned_peer: Option<String>,
+        layout: StageLayout,
+    ) -> Result<(), &'static str> {
+        if let Some(pinned) = &pinned_peer
+            && !self.peers.iter().any(|p| p.id == *pinned)
+        {
+            return Err("No such peer in this room");
+        }
+        self.layout = layout;
+        self.pinned_peer = pinned_peer;
+        info!(
+            "Host {} set the stage to {:?}, pinning {:?}",
+            host_id, self.layout, self.pinned_peer
+        );
+        self.broadcast_to_all(&self.layout_message());
+        Ok(())
+    }
+
+    /// Drop the pin, telling everyone
+    pub fn unpin(&mut self) {
+        if self.pinned_peer.take().is_some() {
+            self.broadcast_to_all(&self.layout_message());
+        }
+    }
+
+    fn layout_message(&self) -> WsMessage {
+        WsMessage::LayoutUpdate {
+            pinned_peer: self.pinned_peer.clone(),
+            layout: self.layout,
+        }
+    }
+}
+
+impl JoinedRoom {
+    /// The stage arrangement to send after `welcome`, unless it is the
+    /// default grid
+    pub fn layout_update(&self) -> Option<WsMessage> {
+        let arranged = self.layout != StageLayout::default() || self.pinned_peer.is_some();
+        arranged.then(|| WsMessage::LayoutUpdate {
+            pinned_peer: self.pinned_peer.clone(),
+            layout: self.layout,
+        })
+    }
+}
@@ -0,0 +1,107 @@
+//! Axi-Vid: A simple 1:1 video chat application using Axum and WebRTC
+//!
+//! This application provides peer-to-peer video calling through WebRTC,
+//! with Axum serving as the signaling server for SDP and ICE exchange.
+//! The `axi-vid` binary is a thin wrapper around this library.
+
+pub mod admin;
+pub mod admin_ui;
+pub mod alerts;
+pub mod api_keys;
+pub mod app;
+pub mod archive;
+pub mod assets;
+pub mod audio_levels;
+pub mod bans;
+pub mod cache;
+pub mod call_history;
+pub mod call_limits;
+pub mod calls;
+pub mod canary;
+pub mod capture;
+pub mod cdr;
+pub mod cleanup;
+pub mod cli;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod cluster;
+pub mod config;
+pub mod consent;
+pub mod contacts;
+pub mod debug_bundle;
+pub mod deep_links;
+pub mod devices;
+pub mod diagnostics;
+pub mod directory;
+pub mod entitlements;
+pub mod event_rooms;
+pub mod experiments;
+pub mod export;
+pub mod features;
+pub mod handlers;
+pub mod ice_policy;
+pub mod ice_restart;
+pub mod images;
+pub mod integrations;
+pub mod invites;
+pub mod jobs;
+pub mod join_queue;
+pub mod lanes;
+pub mod layout;
+pub mod listener;
+pub mod lobby;
+pub mod media_relay;
+pub mod migration;
+pub mod models;
+pub mod nettest;
+pub mod persistence;
+pub mod personal_rooms;
+pub mod post_call;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod presence;
+pub mod presenting;
+pub mod preview;
+pub mod pstn;
+pub mod push;
+pub mod quality;
+pub mod quotas;
+pub mod ratelimit;
+pub mod raw_relay;
+pub mod reconnect;
+#[cfg(feature = "recorder")]
+pub mod recorder;
+pub mod recorders;
+pub mod recordings;
+pub mod replay;
+pub mod retention;
+pub mod room_actor;
+pub mod room_store;
+#[cfg(any(feature = "recorder", feature = "canary"))]
+pub mod rtc;
+pub mod saml;
+pub mod scanning;
+pub mod scim;
+pub mod scrub;
+pub mod sdp;
+pub mod search;
+pub mod sessions;
+pub mod share_links;
+pub mod signed_urls;
+pub mod simulate;
+#[cfg(feature = "sip")]
+pub mod sip;
+pub mod state;
+pub mod storage;
+pub mod stun;
+pub mod tenants;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod timeline;
+pub mod token;
+pub mod traffic;
+pub mod transcript;
+pub mod transcription;
+pub mod turn;
+pub mod voicemail;
+pub mod webhooks;
@@ -0,0 +1,319 @@
+//! Sockets the HTTP server listens on
+//!
+//! Each listener is a TCP address (optionally serving HTTPS directly), a
+//! Unix domain socket (for deployments reachable only through a local
+//! reverse proxy), or a socket inherited from systemd socket activation
+//! (`LISTEN_FDS`). Several can run at once, each restricted to a subset of
+//! the routes.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::Router;
+use axum::serve::ListenerExt;
+use serde::Deserialize;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls;
+use tokio_rustls::server::TlsStream;
+use tracing::{debug, info, warn};
+
+/// How long a client gets to finish the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where to accept HTTP connections
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenConfig {
+    /// `{"tcp": "0.0.0.0:3000"}`; `[::]:3000` is dual-stack and accepts
+    /// IPv4 clients too
+    Tcp(SocketAddr),
+    /// `{"unix": "/run/axi-vid.sock"}`; a stale socket file is replaced
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// `"systemd"`: the first socket passed by systemd socket activation
+    #[cfg(unix)]
+    Systemd,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self::Tcp(SocketAddr::from(([0, 0, 0, 0], 3000)))
+    }
+}
+
+/// One entry of the `listeners` config list
+#[derive(Debug, Clone, Deserialize)]
+pub struct ListenerConfig {
+    pub bind: ListenConfig,
+    /// Serve HTTPS with this certificate; TCP sockets only
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Which routes this listener exposes
+    #[serde(default)]
+    pub routes: RouteScope,
+}
+
+/// PEM files for an HTTPS listener
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsConfig {
+    /// Certificate chain, leaf first
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Subset of routes a listener serves
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteScope {
+    /// Everything
+    #[default]
+    All,
+    /// Everything except the operator API: `/admin` and the operator-only
+    /// `/api` routes such as search, exports and recording listings.
+    /// Downloads that signed URLs open stay public
+    Public,
+    /// Only the operator API and `/health`
+    Admin,
+}
+
+/// A bound listener of any supported kind
+pub enum Listener {
+    Tcp(TcpListener),
+    Tls(TlsListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl Listener {
+    /// Bind (or inherit) the configured socket, wrapping it in TLS if asked
+    pub async fn bind(config: &ListenerConfig) -> std::io::Result<Self> {
+        let listener = match &config.bind {
+            ListenConfig::Tcp(addr) => Self::Tcp(bind_tcp(*addr)?),
+            #[cfg(unix)]
+            ListenConfig::Unix(path) => {
+                if path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+                Self::Unix(tokio::net::UnixListener::bind(path)?)
+            }
+            #[cfg(unix)]
+            ListenConfig::Systemd => systemd_listener()?,
+        };
+        let listener = match (listener, &config.tls) {
+            (listener, None) => listener,
+            (Self::Tcp(tcp), Some(tls)) => Self::Tls(TlsListener::new(tcp, tls_acceptor(tls)?)?),
+            (_, Some(_)) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "TLS is only supported on TCP listeners",
+                ));
+            }
+        };
+        info!(
+            "Listening on {} ({:?} routes)",
+            listener.describe(),
+            config.routes
+        );
+        Ok(listener)
+    }
+
+    /// Human-readable address for logs
+    fn describe(&self) -> String {
+        match self {
+            Self::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => format!("http://{}", addr),
+                Err(_) => "tcp".to_string(),
+            },
+            Self::Tls(listener) => format!("https://{}", listener.local_addr),
+            #[cfg(unix)]
+            Self::Unix(listener) => match listener.local_addr() {
+                Ok(addr) => match addr.as_pathname() {
+                    Some(path) => format!("unix:{}", path.display()),
+                    None => "unix socket".to_string(),
+                },
+                Err(_) => "unix socket".to_string(),
+            },
+        }
+    }
+
+    /// Serve `app` until `shutdown` resolves
+    pub async fn serve(
+        self,
+        app: Router,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> std::io::Result<()> {
+        match self {
+            Self::Tcp(listener) => {
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(shutdown)
+                .await
+            }
+            Self::Tls(listener) => {
+                // axum only provides `SocketAddr` connect info for its own
+                // listener types; a no-op `tap_io` wraps ours in one
+                axum::serve(
+                    listener.tap_io(|_| {}),
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(shutdown)
+                .await
+            }
+            #[cfg(unix)]
+            Self::Unix(listener) => {
+                axum::serve(listener, app)
+                    .with_graceful_shutdown(shutdown)
+                    .await
+            }
+        }
+    }
+}
+
+/// Bind a TCP socket; an IPv6 socket also accepts IPv4 clients (seen as
+/// IPv4-mapped addresses) whatever the system's `bindv6only` default
+pub fn bind_tcp(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// TCP listener that hands out connections after a completed TLS handshake
+///
+/// Handshakes run in their own tasks so a slow client cannot hold up
+/// accepting others.
+pub struct TlsListener {
+    incoming: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    fn new(tcp: TcpListener, acceptor: TlsAcceptor) -> std::io::Result<Self> {
+        let local_addr = tcp.local_addr()?;
+        let (tx, incoming) = mpsc::channel(64);
+        tokio::spawn(async move {
+            while !tx.is_closed() {
+                let (stream, addr) = match tcp.accept().await {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        warn!("Failed to accept connection: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(tls)) => {
+                            let _ = tx.send((tls, addr)).await;
+                        }
+                        Ok(Err(e)) => debug!("TLS handshake with {} failed: {}", addr, e),
+                        Err(_) => debug!("TLS handshake with {} timed out", addr),
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            incoming,
+            local_addr,
+        })
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.incoming.recv().await {
+            Some(conn) => conn,
+            // The accept task only stops once we are dropped
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// Load the certificate and key into a rustls acceptor
+pub fn tls_acceptor(config: &TlsConfig) -> std::io::Result<TlsAcceptor> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    let invalid = |what: &PathBuf, e: &dyn std::fmt::Display| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{}: {}", what.display(), e),
+        )
+    };
+    let certs = CertificateDer::pem_file_iter(&config.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(&config.cert, &e))?;
+    let key = PrivateKeyDer::from_pem_file(&config.key).map_err(|e| invalid(&config.key, &e))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut server = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| invalid(&config.cert, &e))?;
+    server.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(server)))
+}
+
+/// First file descriptor passed by systemd (`SD_LISTEN_FDS_START`)
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+/// Take over the socket passed via systemd socket activation
+#[cfg(unix)]
+fn systemd_listener() -> std::io::Result<Listener> {
+    use std::io::{Error, ErrorKind};
+    use std::os::fd::{FromRawFd, IntoRawFd};
+
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<u32>().ok())
+        .unwrap_or(0);
+    if !for_us || count == 0 {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "No socket passed by systemd (LISTEN_FDS/LISTEN_PID not set for this process)",
+        ));
+    }
+
+    // SAFETY: systemd hands us ownership of this descriptor, and nothing
+    // else in the process uses it.
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    if tcp.local_addr().is_ok() {
+        tcp.set_nonblocking(true)?;
+        return Ok(Listener::Tcp(TcpListener::from_std(tcp)?));
+    }
+
+    // Not an inet socket; treat it as a Unix socket
+    // SAFETY: the descriptor was released from the TcpListener above.
+    let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+    unix.set_nonblocking(true)?;
+    Ok(Listener::Unix(tokio::net::UnixListener::from_std(unix)?))
+}
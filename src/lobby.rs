@@ -0,0 +1,127 @@
+//! Pre-join lobby page
+//!
+//! `/room/{id}/lobby` lets a visitor check their camera and microphone
+//! before joining, and shows how many people are in the room, kept current
+//! over server-sent events from `/api/room/{id}/lobby/events`. Once the
+//! room has started the page moves on to the room itself.
+//!
+//! The room page sends visitors here while a personal room waits for its
+//! owner, the one room kind that needs its host before anyone may join;
+//! the owner, and peers holding a slot, go straight in.
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{
+        Html, IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures::stream;
+use uuid::Uuid;
+
+use crate::handlers::WsQuery;
+use crate::models::LobbyStatus;
+use crate::state::AppState;
+
+/// How often a lobby's event stream checks on the room
+const LOBBY_POLL: Duration = Duration::from_secs(1);
+
+impl AppState {
+    /// Where a room stands for someone waiting to join it
+    pub async fn lobby_status(&self, room_id: &str) -> LobbyStatus {
+        let running = self
+            .with_room(room_id, |room| {
+                (room.peers.len(), room.waiting.len(), room.awaits_owner())
+            })
+            .await;
+        match running {
+            Some((peer_count, waiting, awaits_owner)) => LobbyStatus {
+                peer_count,
+                waiting,
+                started: !awaits_owner,
+            },
+            // Only personal rooms wait to be started by someone in particular
+            None => LobbyStatus {
+                peer_count: 0,
+                waiting: 0,
+                started: !self.personal_rooms.lock().await.contains_key(room_id),
+            },
+        }
+    }
+
+    /// Whether a visitor has to wait for the room's owner before joining
+    pub async fn must_wait_for_owner(&self, room_id: &str, token: Option<&str>) -> bool {
+        let Some(personal) = self.personal_room(room_id, token).await else {
+            return false;
+        };
+        let who = self.identity_of(room_id, token);
+        if who.as_deref() == Some(personal.owner.as_str()) {
+            return false;
+        }
+        let token = token.map(str::to_string);
+        self.with_room(room_id, move |room| {
+            room.awaits_owner() && !room.lets_in_early(who.as_deref(), token.as_deref())
+        })
+        .await
+        .unwrap_or(true)
+    }
+}
+
+/// Serve the lobby page of a room
+pub async fn lobby_page(Path(room_id): Path<String>, State(state): State<AppState>) -> Response {
+    if Uuid::parse_str(&room_id).is_err() {
+        return (StatusCode::BAD_REQUEST, "Invalid room ID format").into_response();
+    }
+    let html = include_str!("../static/lobby.html").replace("{{ROOM_ID}}", &room_id);
+    Html(state.assets.link(&html)).into_response()
+}
+
+/// Follow a room's peer count and whether it has started
+///
+/// Sends a `status` event with the room's [`LobbyStatus`] on connecting and
+/// whenever it changes.
+#[utoipa::path(
+    get,
+    path = "/api/room/{room_id}/lobby/events",
+    tag = "Rooms",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room"),
+        ("token" = Option<String>, Query, description = "Room token, when the room requires one")
+    ),
+    responses(
+        (status = 200, description = "Event stream of `status` events", body = LobbyStatus, content_type = "text/event-stream"),
+        (status = 401, description = "A valid room token is required")
+    )
+)]
+pub async fn lobby_events(
+    Path(room_id): Path<String>,
+    Query(query): Query<WsQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    if !state.may_join(&room_id, query.token.as_deref()).await {
+        return (StatusCode::UNAUTHORIZED, "A valid room token is required").into_response();
+    }
+    let events = stream::unfold(
+        (state, room_id, None),
+        |(state, room_id, last): (AppState, String, Option<LobbyStatus>)| async move {
+            loop {
+                if last.is_some() {
+                    tokio::time::sleep(LOBBY_POLL).await;
+                }
+                let status = state.lobby_status(&room_id).await;
+                if last.as_ref() == Some(&status) {
+                    continue;
+                }
+                let event = Event::default().event("status").json_data(&status).ok()?;
+                return Some((Ok::<_, Infallible>(event), (state, room_id, Some(status))));
+            }
+        },
+    );
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
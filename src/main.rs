@@ -3,54 +3,50 @@
 //! This application provides peer-to-peer video calling through WebRTC,
 //! with Axum serving as the signaling server for SDP and ICE exchange.
 
-mod handlers;
-mod models;
-mod state;
-
-use axum::{
-    routing::{get, post},
-    Router,
-};
-use std::net::SocketAddr;
-use tower_http::{
-    cors::CorsLayer,
-    services::ServeDir,
-    trace::TraceLayer,
-};
+use clap::Parser;
+use tokio::sync::watch;
 use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use utoipa::OpenApi;
-use utoipa_scalar::{Scalar, Servable};
-
-use crate::handlers::{create_room, health_check, index_redirect, room_page, room_status, ws_handler};
-use crate::models::{CreateRoomResponse, RoomStatus};
-use crate::state::{spawn_cleanup_task, AppState};
-
-#[derive(OpenApi)]
-#[openapi(
-    info(
-        title = "Axi-Vid API",
-        description = "A simple 1:1 video chat application using Axum and WebRTC. This API provides endpoints for room management and real-time communication via WebSockets.",
-        version = "0.1.0"
-    ),
-    tags(
-        (name = "Rooms", description = "Room management endpoints"),
-        (name = "Health", description = "Health check endpoints"),
-        (name = "WebSocket", description = "Real-time communication")
-    ),
-    paths(
-        handlers::create_room,
-        handlers::room_status,
-        handlers::health_check,
-    ),
-    components(
-        schemas(CreateRoomResponse, RoomStatus)
-    )
-)]
-struct ApiDoc;
+
+use axi_vid::alerts::spawn_alerting;
+use axi_vid::app::build_app;
+use axi_vid::call_limits::spawn_call_limits;
+use axi_vid::canary::spawn_canary;
+use axi_vid::cleanup::spawn_cleanup_task;
+use axi_vid::cli::{self, Cli, Command};
+use axi_vid::cluster::spawn_gossip;
+use axi_vid::config::Config;
+use axi_vid::jobs::spawn_job_runner;
+use axi_vid::listener::Listener;
+use axi_vid::persistence::spawn_record_sync;
+use axi_vid::quotas::spawn_quota_metering;
+use axi_vid::state::AppState;
+use axi_vid::webhooks::spawn_webhook_delivery;
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    let config = match Config::load_from(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config).await,
+        command => {
+            if let Err(e) = cli::run(command, config).await {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Run the server until Ctrl+C or SIGTERM
+async fn serve(config: Config) {
     // Initialize tracing
     tracing_subscriber::registry()
         .with(
@@ -61,38 +57,141 @@ async fn main() {
         .init();
 
     // Create shared state
-    let state = AppState::new();
+    if let Some(path) = &config.source {
+        info!("Loaded configuration from {}", path.display());
+    }
+    let state = AppState::new(config);
+
+    // Restore room records kept in Postgres, and share running rooms and
+    // API keys there
+    #[cfg(feature = "postgres")]
+    if let Some(postgres) = state.config().postgres.clone() {
+        let store = axi_vid::postgres::PostgresStore::connect(&postgres)
+            .await
+            .expect("Failed to open Postgres");
+        let store = std::sync::Arc::new(store);
+        state
+            .attach_room_store(store.clone())
+            .expect("Failed to share rooms through Postgres");
+        state
+            .attach_key_store(store.clone())
+            .expect("Failed to share API keys through Postgres");
+        let restored = state
+            .attach_store(store)
+            .await
+            .expect("Failed to restore room records");
+        info!("Restored the records of {} rooms from Postgres", restored);
+    }
+
+    let recordings = state.load_recordings().await;
+    if recordings > 0 {
+        info!("Found {} stored recordings", recordings);
+    }
 
     // Spawn background cleanup task
     spawn_cleanup_task(state.clone());
 
-    // Build the router
-    let app = Router::new()
-        // Scalar API documentation
-        .merge(Scalar::with_url("/docs", ApiDoc::openapi()))
-        // API routes
-        .route("/api/create-room", post(create_room))
-        .route("/api/room/{room_id}/status", get(room_status))
-        .route("/health", get(health_check))
-        // Room page
-        .route("/", get(index_redirect))
-        .route("/room/{room_id}", get(room_page))
-        // WebSocket endpoint
-        .route("/ws/{room_id}", get(ws_handler))
-        // Static files (JS, CSS)
-        .nest_service("/static", ServeDir::new("static"))
-        // Middleware
-        .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::permissive())
-        // Shared state
-        .with_state(state);
-
-    // Start server
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
-    info!("Starting Axi-Vid server on http://{}", addr);
-    info!("Open http://localhost:3000 in your browser to start a video call");
-    info!("API documentation available at http://localhost:3000/docs");
-
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    // Warn and close calls reaching their maximum duration
+    spawn_call_limits(state.clone());
+
+    // Meter tenants' usage against their quotas
+    spawn_quota_metering(state.clone());
+
+    // Exchange room locations with other instances
+    spawn_gossip(state.clone());
+
+    // Read back records other instances store
+    spawn_record_sync(state.clone());
+
+    // Post room events to the webhook, retrying failures
+    spawn_webhook_delivery(state.clone());
+
+    // Retry failed background jobs and run those restored from the store
+    spawn_job_runner(state.clone());
+
+    // Check alert rules
+    spawn_alerting(state.clone());
+
+    // Call ourselves to check that calls connect
+    spawn_canary(state.clone());
+
+    // Reload configuration on SIGHUP
+    #[cfg(unix)]
+    spawn_reload_on_sighup(state.clone());
+
+    // Start the SIP gateway if configured
+    #[cfg(feature = "sip")]
+    if let Some(sip_config) = state.config().sip.clone() {
+        axi_vid::sip::spawn(sip_config, state.clone())
+            .await
+            .expect("Failed to start SIP gateway");
+    }
+
+    // Bind every listener before serving so a bad socket fails startup
+    let mut listeners = Vec::new();
+    for config in state.config().listeners() {
+        let listener = Listener::bind(&config)
+            .await
+            .expect("Failed to bind listener");
+        listeners.push((listener, build_app(state.clone(), config.routes)));
+    }
+    let public_url = state.config().public_url.clone();
+    info!("Starting Axi-Vid server");
+    info!("Open {} in your browser to start a video call", public_url);
+    info!("API documentation available at {}/docs", public_url);
+
+    // One shutdown signal stops every listener
+    let (stop, stopped) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal(state).await;
+        let _ = stop.send(true);
+    });
+    let servers = listeners.into_iter().map(|(listener, app)| {
+        let mut stopped = stopped.clone();
+        listener.serve(app, async move {
+            let _ = stopped.wait_for(|stopped| *stopped).await;
+        })
+    });
+    futures::future::try_join_all(servers).await.unwrap();
+}
+
+/// Reload the config file whenever the process receives SIGHUP
+#[cfg(unix)]
+fn spawn_reload_on_sighup(state: AppState) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match state.reload_config("sighup").await {
+                Ok(()) => info!("Configuration reloaded"),
+                Err(e) => tracing::warn!("Config reload failed, keeping current config: {}", e),
+            }
+        }
+    });
+}
+
+/// Resolve on Ctrl+C or SIGTERM after telling connected peers we are going away
+async fn shutdown_signal(state: AppState) {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    info!("Shutdown signal received");
+    state.shutdown().await;
 }
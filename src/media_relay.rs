@@ -0,0 +1,282 @@
+//! Last-resort media relay through the signaling server
+//!
+//! For networks where neither a direct path nor a TURN server works. When
+//! `media_relay` is configured, every joined peer is sent a `media_relay`
+//! message naming a WebSocket it may open with its resume token. Binary
+//! frames on that socket are forwarded to the other peers' relay sockets the
+//! same way signaling is routed: to the other side in a 1:1 room, from the
+//! presenter to every viewer and from viewers to the presenter in a
+//! broadcast room.
+//!
+//! Frames are data-channel-like: one stream ID byte (audio, video, ...)
+//! followed by an opaque payload. The server never inspects the payload;
+//! clients are expected to encrypt it end to end with keys agreed over
+//! signaling. Each sender is held to `max_bitrate_kbps`, and frames over
+//! budget or to a receiver that is not keeping up are dropped rather than
+//! queued, like packets on a congested link.
+
+use std::time::Instant;
+
+use axum::{
+    body::Bytes,
+    extract::{
+        Path, Query, State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::handlers::WsQuery;
+use crate::models::{RoomMode, WsMessage};
+use crate::state::AppState;
+use crate::token::secret_eq;
+
+/// Frames queued per receiver before new ones are dropped
+const RELAY_QUEUE_FRAMES: usize = 64;
+
+/// Media relay settings; the relay is disabled when unset
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MediaRelayConfig {
+    /// Most a single sender may push through the relay, in kbit/s
+    pub max_bitrate_kbps: u32,
+    /// Largest frame accepted, in bytes including the stream ID
+    pub max_frame_bytes: usize,
+}
+
+impl Default for MediaRelayConfig {
+    fn default() -> Self {
+        Self {
+            max_bitrate_kbps: 1000,
+            max_frame_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl MediaRelayConfig {
+    /// Advertisement sent to a peer after it joins
+    pub fn offer(&self, room_id: &str) -> WsMessage {
+        WsMessage::MediaRelay {
+            url: format!("/ws/{}/media", room_id),
+            max_bitrate_kbps: self.max_bitrate_kbps,
+            max_frame_bytes: self.max_frame_bytes,
+        }
+    }
+}
+
+/// One peer's open relay socket
+#[derive(Debug)]
+pub struct RelayLink {
+    pub peer_id: String,
+    /// Whether the peer was the broadcast presenter when it attached
+    pub presenter: bool,
+    sender: mpsc::Sender<Bytes>,
+}
+
+/// Token bucket holding a sender to its bitrate cap, with a second of burst
+#[derive(Debug)]
+struct BitrateCap {
+    bytes_per_sec: f64,
+    available: f64,
+    refilled: Instant,
+}
+
+impl BitrateCap {
+    fn new(max_bitrate_kbps: u32) -> Self {
+        let bytes_per_sec = f64::from(max_bitrate_kbps) * 1000.0 / 8.0;
+        Self {
+            bytes_per_sec,
+            available: bytes_per_sec,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Spend `len` bytes of budget, or refuse if there is not enough
+    fn admit(&mut self, len: usize) -> bool {
+        let now = Instant::now();
+        let earned = now.duration_since(self.refilled).as_secs_f64() * self.bytes_per_sec;
+        self.available = (self.available + earned).min(self.bytes_per_sec);
+        self.refilled = now;
+        if self.available < len as f64 {
+            return false;
+        }
+        self.available -= len as f64;
+        true
+    }
+}
+
+impl AppState {
+    /// Resolve a resume token to the peer holding it: its ID, whether the
+    /// room is a broadcast and whether the peer is presenting
+    async fn media_relay_peer(&self, room_id: &str, token: &str) -> Option<(String, bool, bool)> {
+        let token = token.to_string();
+        self.with_room(room_id, move |room| {
+            let peer = room
+                .peers
+                .iter()
+                .find(|p| secret_eq(&p.resume_token, &token))?;
+            let broadcast = room.settings.mode == RoomMode::Broadcast;
+            let presenter = room.presenter.as_deref() == Some(peer.id.as_str());
+            Some((peer.id.clone(), broadcast, presenter))
+        })
+        .await?
+    }
+
+    /// Register a peer's relay socket, replacing any earlier one
+    async fn attach_media_relay(
+        &self,
+        room_id: &str,
+        peer_id: &str,
+        presenter: bool,
+    ) -> mpsc::Receiver<Bytes> {
+        let (sender, receiver) = mpsc::channel(RELAY_QUEUE_FRAMES);
+        let mut relays = self.media_relays.lock().await;
+        let links = relays.entry(room_id.to_string()).or_default();
+        links.retain(|l| l.peer_id != peer_id);
+        links.push(RelayLink {
+            peer_id: peer_id.to_string(),
+            presenter,
+            sender,
+        });
+        receiver
+    }
+
+    /// Drop a peer's relay socket, which closes it
+    pub async fn close_media_relay(&self, room_id: &str, peer_id: &str) {
+        let mut relays = self.media_relays.lock().await;
+        if let Some(links) = relays.get_mut(room_id) {
+            links.retain(|l| l.peer_id != peer_id);
+            if links.is_empty() {
+                relays.remove(room_id);
+            }
+        }
+    }
+
+    /// Forget relay sockets that have gone away
+    async fn prune_media_relays(&self, room_id: &str) {
+        let mut relays = self.media_relays.lock().await;
+        if let Some(links) = relays.get_mut(room_id) {
+            links.retain(|l| !l.sender.is_closed());
+            if links.is_empty() {
+                relays.remove(room_id);
+            }
+        }
+    }
+
+    /// Forward a frame to the sender's counterparts, returning how many
+    /// receivers were too far behind to take it
+    async fn forward_media(
+        &self,
+        room_id: &str,
+        sender_id: &str,
+        broadcast: bool,
+        frame: Bytes,
+    ) -> usize {
+        let relays = self.media_relays.lock().await;
+        let Some(links) = relays.get(room_id) else {
+            return 0;
+        };
+        let from_presenter = links.iter().any(|l| l.peer_id == sender_id && l.presenter);
+        links
+            .iter()
+            .filter(|l| l.peer_id != sender_id)
+            .filter(|l| !broadcast || from_presenter || l.presenter)
+            .filter(|l| l.sender.try_send(frame.clone()).is_err())
+            .count()
+    }
+}
+
+/// Media relay WebSocket, authenticated by the peer's resume token
+pub async fn media_relay_ws(
+    ws: WebSocketUpgrade,
+    Path(room_id): Path<String>,
+    Query(query): Query<WsQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(config) = state.config().media_relay.clone() else {
+        return (StatusCode::NOT_FOUND, "Media relay is disabled").into_response();
+    };
+    let Some(token) = query.token else {
+        return (StatusCode::UNAUTHORIZED, "A resume token is required").into_response();
+    };
+    let Some((peer_id, broadcast, presenter)) = state.media_relay_peer(&room_id, &token).await
+    else {
+        return (StatusCode::UNAUTHORIZED, "Not a peer in this room").into_response();
+    };
+
+    ws.on_upgrade(move |socket| {
+        run_media_relay(
+            socket, room_id, peer_id, broadcast, presenter, config, state,
+        )
+    })
+}
+
+/// Pump frames between one peer's relay socket and the others
+async fn run_media_relay(
+    socket: WebSocket,
+    room_id: String,
+    peer_id: String,
+    broadcast: bool,
+    presenter: bool,
+    config: MediaRelayConfig,
+    state: AppState,
+) {
+    info!(
+        "Peer {} opened the media relay in room {}",
+        peer_id, room_id
+    );
+    let mut frames = state
+        .attach_media_relay(&room_id, &peer_id, presenter)
+        .await;
+    let (mut ws_tx, mut ws_rx) = socket.split();
+
+    let ws_sender = async move {
+        while let Some(frame) = frames.recv().await {
+            if ws_tx.send(Message::Binary(frame)).await.is_err() {
+                break;
+            }
+        }
+    };
+
+    let ws_receiver = async {
+        let mut cap = BitrateCap::new(config.max_bitrate_kbps);
+        let (mut over_budget, mut stalled) = (0usize, 0usize);
+        while let Some(Ok(frame)) = ws_rx.next().await {
+            let data = match frame {
+                Message::Binary(data) => data,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            if data.len() < 2 || data.len() > config.max_frame_bytes {
+                warn!("Dropped malformed relay frame from peer {}", peer_id);
+                continue;
+            }
+            if !cap.admit(data.len()) {
+                over_budget += 1;
+                continue;
+            }
+            stalled += state
+                .forward_media(&room_id, &peer_id, broadcast, data)
+                .await;
+        }
+        debug!(
+            "Relay from peer {} dropped {} frames over budget, {} to slow receivers",
+            peer_id, over_budget, stalled
+        );
+    };
+
+    tokio::select! {
+        _ = ws_receiver => {}
+        _ = ws_sender => {}
+    }
+    state.prune_media_relays(&room_id).await;
+    info!(
+        "Peer {} closed the media relay in room {}",
+        peer_id, room_id
+    );
+}
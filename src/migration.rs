@@ -0,0 +1,98 @@
+//! Moving calls to another instance on shutdown
+//!
+//! Instances share no state, so a room cannot be handed over as a whole.
+//! What can move is the signaling session: media flows between the peers
+//! directly and keeps going while they switch servers. With `drain`
+//! configured, a shutting-down instance sends every peer `redirect` with the
+//! room's WebSocket URL on `redirect_url` and a resume token instead of
+//! `leave`. The resume token is a JWT signed with the shared `jwt_secret`,
+//! so whichever instance the peer lands on lets it back into the room under
+//! its old peer ID, and the other peer sees the same ID rejoin. Chat and
+//! the timeline stay behind. Room settings, invitations and links move
+//! along only through a [room store](crate::room_store); without one the
+//! room starts afresh on the new instance.
+
+use std::ops::ControlFlow;
+
+use serde::Deserialize;
+use tracing::info;
+
+use crate::models::{LeaveReason, TimelineKind, WsMessage};
+use crate::room_actor::RoomHandle;
+use crate::state::AppState;
+use crate::token::{self, Claims, TokenScope};
+
+/// Where calls go when this instance shuts down
+#[derive(Debug, Clone, Deserialize)]
+pub struct DrainConfig {
+    /// WebSocket base URL of the instances taking over, e.g.
+    /// `wss://vid.example.com`
+    pub redirect_url: String,
+    /// Seconds a resume token stays valid
+    #[serde(default = "default_token_ttl_secs")]
+    pub token_ttl_secs: u64,
+}
+
+fn default_token_ttl_secs() -> u64 {
+    60
+}
+
+impl AppState {
+    /// The peer ID a migrated peer had, if `token` is a resume token for
+    /// `room_id`
+    pub fn migrated_peer_id(&self, room_id: &str, token: Option<&str>) -> Option<String> {
+        let config = self.config();
+        let claims = token::verify(config.jwt_secret.as_deref()?, token?).ok()?;
+        if claims.scope != TokenScope::Migrate || claims.room.as_deref() != Some(room_id) {
+            return None;
+        }
+        claims.sub
+    }
+
+    /// Send every peer of a room elsewhere and close it; returns false if
+    /// draining is not configured
+    pub(crate) async fn migrate_room(&self, room_id: &str, room: &RoomHandle) -> bool {
+        let config = self.config();
+        let (Some(drain), Some(secret)) = (config.drain.clone(), config.jwt_secret.clone()) else {
+            return false;
+        };
+        let url = format!(
+            "{}/ws/{}",
+            drain.redirect_url.trim_end_matches('/'),
+            room_id
+        );
+        let (id, to) = (room_id.to_string(), url.clone());
+        let peer_ids = room
+            .call_until(move |room| {
+                let peer_ids: Vec<String> = room.peers.iter().map(|p| p.id.clone()).collect();
+                for peer_id in &peer_ids {
+                    let mut claims =
+                        Claims::new(TokenScope::Migrate, Some(id.clone()), drain.token_ttl_secs);
+                    claims.sub = Some(peer_id.clone());
+                    let redirect = WsMessage::Redirect {
+                        url: to.clone(),
+                        resume_token: token::mint(&secret, &claims),
+                    };
+                    room.send_to(peer_id, redirect);
+                    room.remove_peer(peer_id);
+                }
+                ControlFlow::Break(peer_ids)
+            })
+            .await
+            .unwrap_or_default();
+        info!(
+            "Redirected {} peers of room {} to {}",
+            peer_ids.len(),
+            room_id,
+            url
+        );
+        for peer_id in peer_ids {
+            self.record_disconnect(room_id, &peer_id, LeaveReason::ServerShutdown)
+                .await;
+        }
+        let detail = format!("migrated to {}", url);
+        self.record_timeline(room_id, TimelineKind::Closed, None, detail)
+            .await;
+        true
+    }
+}
@@ -3,18 +3,36 @@
 //! All messages are JSON-serialized and use a tagged enum pattern
 //! for type discrimination.
 
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+use crate::config::IceServer;
+use crate::ice_policy::IcePolicy;
+use crate::state::MAX_BROADCAST_VIEWERS;
+
 /// Incoming messages from WebSocket clients
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum WsMessage {
     /// WebRTC SDP offer from caller
-    Offer { sdp: String },
+    ///
+    /// `peer_id` addresses a specific peer when sent by a client and names
+    /// the originating peer when relayed by the server (broadcast rooms).
+    Offer {
+        sdp: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer_id: Option<String>,
+    },
 
     /// WebRTC SDP answer from callee
-    Answer { sdp: String },
+    Answer {
+        sdp: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer_id: Option<String>,
+    },
 
     /// ICE candidate for NAT traversal
     #[serde(rename = "ice")]
@@ -24,16 +42,35 @@ pub enum WsMessage {
         sdp_m_line_index: u32,
         #[serde(rename = "sdpMid")]
         sdp_mid: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer_id: Option<String>,
     },
 
     /// Peer joined notification
-    Join,
+    Join {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer_id: Option<String>,
+    },
 
-    /// Peer left notification
-    Leave,
+    /// Peer left notification; clients may send it with a reason before closing
+    Leave {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<LeaveReason>,
+    },
 
     /// Text chat message
-    Chat { message: String },
+    Chat {
+        message: String,
+    },
+
+    /// Short emoji reaction; relayed with the sender's ID in `from`
+    Reaction {
+        emoji: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from: Option<String>,
+    },
 
     /// Media status update (mute/unmute)
     MediaStatus {
@@ -42,19 +79,425 @@ pub enum WsMessage {
     },
 
     /// Peer status broadcast
-    PeerStatus { status: String },
+    PeerStatus {
+        status: String,
+    },
 
     /// Error message
-    Error { message: String },
+    Error {
+        message: String,
+    },
 
     /// Room info (peer count, etc.)
-    RoomInfo { peer_count: usize },
+    RoomInfo {
+        peer_count: usize,
+        /// Codecs the room standardizes on; clients should prefer them
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        codecs: Option<CodecPolicy>,
+        /// Features clients should offer in this room
+        #[serde(default)]
+        features: FeatureFlags,
+    },
+
+    /// Sent once to a newly joined peer describing its place in the room
+    Welcome {
+        peer_id: String,
+        presenter: bool,
+        role: PeerRole,
+        settings: RoomSettings,
+        /// Pass as `?token=` when reconnecting to reclaim this peer's slot
+        resume_token: String,
+        /// Peer currently sharing its screen, if any
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sharing: Option<String>,
+    },
+
+    /// A peer dropped and its slot is held while it reconnects
+    PeerReconnecting {
+        peer_id: String,
+        grace_secs: u64,
+    },
+
+    /// The server is shutting down; reconnect to `url` with
+    /// `?token=<resume_token>` to rejoin the room under the same peer ID
+    Redirect {
+        url: String,
+        resume_token: String,
+    },
+
+    /// The call is over for this peer: the other side did not come back
+    /// (clients may offer to call them back), the peer hung up, or the room
+    /// closed. Clients go to `redirect`, the post-call page, when set
+    CallEnded {
+        can_redial: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        redirect: Option<String>,
+    },
+
+    /// Screen share started or stopped; broadcast by the server with the
+    /// sharing peer in `peer_id`. Clients start sharing once granted the
+    /// slot with `present_request` and send `active: false` to stop
+    ScreenShare {
+        active: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer_id: Option<String>,
+    },
+
+    /// Ask for the room's single screen-share slot (requires
+    /// `screen_share` permission). Under the `host` present policy the
+    /// server forwards it to the host with the requester in `peer_id`
+    PresentRequest {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer_id: Option<String>,
+    },
+
+    /// Host grants or declines a `present_request`; declining the current
+    /// presenter stops its share
+    PresentGrant {
+        peer_id: String,
+        granted: bool,
+    },
+
+    /// Host arranges everyone's stage: a grid, or one peer pinned as the
+    /// speaker. Broadcast to all peers and sent to joiners after `welcome`
+    LayoutUpdate {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pinned_peer: Option<String>,
+        layout: StageLayout,
+    },
+
+    /// The screen-share slot was not granted
+    PresentDenied {
+        reason: PresentDeniedReason,
+        /// Peer holding the slot, when it is taken
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        presenter: Option<String>,
+    },
+
+    /// Recording started/stopped (requires `record` permission)
+    ///
+    /// A start request triggers a consent round; the server broadcasts
+    /// `recording` with `active: true` only once the room's policy is met.
+    Recording {
+        active: bool,
+    },
+
+    /// Server asks every peer to consent to recording
+    ConsentRequest {
+        requested_by: String,
+    },
+
+    /// A peer's answer to a `ConsentRequest`
+    ConsentResponse {
+        granted: bool,
+    },
+
+    /// Request a shareable link for this room (requires `invite` permission)
+    Invite,
+
+    /// Shareable room link returned in response to `Invite`
+    InviteLink {
+        url: String,
+    },
+
+    /// Host replaces the room's permission matrix; broadcast to all peers
+    Permissions {
+        permissions: PermissionMatrix,
+    },
+
+    /// Host changes a peer's role; broadcast to all peers
+    SetRole {
+        peer_id: String,
+        role: PeerRole,
+    },
+
+    /// Host switches room-wide controls; omitted fields are unchanged.
+    /// Broadcast to all peers with every field set
+    RoomSettingsUpdate {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        chat: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reactions: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        screen_share: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        recording: Option<bool>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        preview: Option<bool>,
+    },
+
+    /// Client capabilities, sent once after joining and relayed to the other side
+    Capabilities {
+        codecs: Vec<String>,
+        browser: String,
+        version: String,
+    },
+
+    /// Camera, microphone and platform of a peer, sent once after joining
+    /// and relayed with the sender's ID in `from`
+    DeviceInfo {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        camera_label: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mic_label: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        os: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        browser: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from: Option<String>,
+    },
+
+    /// DTMF tones (`0-9`, `*`, `#`, `A-D`, `,` for a pause)
+    Dtmf {
+        digits: String,
+    },
+
+    /// Ask the other side to put the call on hold
+    Hold,
+
+    /// Resume a held call
+    Resume,
+
+    /// Ask the other side to transfer the call to another destination
+    TransferRequest {
+        target: String,
+    },
+
+    /// Live caption produced by the transcription pipeline
+    Caption {
+        peer_id: String,
+        text: String,
+        #[serde(rename = "final")]
+        is_final: bool,
+    },
+
+    /// A phone caller is being connected to the room
+    PhoneParticipant {
+        caller: String,
+    },
+
+    /// Pre-call network test probe; clients echo it back unchanged
+    NetTestProbe {
+        seq: u32,
+    },
+
+    /// Result of a pre-call network test
+    NetTestReport {
+        samples: usize,
+        lost: usize,
+        rtt_ms: f64,
+        jitter_ms: f64,
+    },
+
+    /// Periodic connection stats from a client, used for MOS scoring
+    QualityStats {
+        rtt_ms: f64,
+        jitter_ms: f64,
+        /// Packet loss in percent (0-100)
+        packet_loss: f64,
+    },
+
+    /// Periodic level of the audio a client sends, from 0.0 (silence) to
+    /// 1.0 (full scale), used to spot one-way audio and the active speaker
+    AudioLevel {
+        level: f64,
+    },
+
+    /// Server tells the room who is speaking, so clients can show them in
+    /// the main tile
+    ActiveSpeaker {
+        peer_id: String,
+    },
+
+    /// Periodic media byte counters from a client, cumulative since its
+    /// connection started; correlated across peers to spot one-way media
+    MediaStats {
+        sent: MediaBytes,
+        received: MediaBytes,
+    },
+
+    /// State of a client's peer connection, reported on every change
+    ConnectionState {
+        state: PeerConnectionState,
+        /// The other end of the connection; the room's other peer, or the
+        /// presenter for a viewer, when omitted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer_id: Option<String>,
+    },
+
+    /// The transport to `peer_id` has been dead too long; restart ICE with
+    /// these servers. Only the `offerer` sends a new offer, the other end
+    /// waits for it
+    IceRestart {
+        peer_id: String,
+        offerer: bool,
+        ice_servers: Vec<IceServer>,
+    },
+
+    /// The server noticed a media problem and suggests a fix
+    DiagnosticHint {
+        issue: DiagnosticIssue,
+        suggestion: String,
+    },
+
+    /// A recorder joined; peers that agree send it their media, addressing
+    /// an offer to its `peer_id`
+    RecorderJoined {
+        peer_id: String,
+    },
+
+    /// A recorder left; close the connection to it
+    RecorderLeft {
+        peer_id: String,
+    },
+
+    /// ICE candidate policy for this peer; sent after `welcome` when the
+    /// deployment restricts candidates
+    IcePolicy {
+        policy: IcePolicy,
+    },
+
+    /// The room is full; the peer is `n`th in line (from 1) and gets
+    /// `welcome` once admitted
+    QueuePosition {
+        n: usize,
+    },
+
+    /// The room's owner has not arrived yet; the peer waits in its lobby
+    /// and gets `welcome` once they do
+    WaitingForHost,
+
+    /// Media can be relayed through the server over `url` (a WebSocket,
+    /// opened with the resume token) when no direct or TURN path works
+    MediaRelay {
+        url: String,
+        max_bitrate_kbps: u32,
+        max_frame_bytes: usize,
+    },
+
+    /// On the presence socket: the user's status, set by the client and
+    /// echoed to each of its connections
+    Presence {
+        status: PresenceStatus,
+    },
+
+    /// On the presence socket: the user was invited into a room
+    RoomInvite {
+        room_id: String,
+        url: String,
+    },
+
+    /// On the presence socket: `from` is calling; answer with
+    /// `answer_call`, then join `room_id` to pick up
+    IncomingCall {
+        room_id: String,
+        from: String,
+        url: String,
+    },
+
+    /// On the presence socket: pick up or decline a ringing call
+    AnswerCall {
+        room_id: String,
+        accept: bool,
+    },
+
+    /// A directed call stopped ringing; sent to both parties' presence
+    /// sockets and into the call's room
+    CallStatus {
+        room_id: String,
+        state: CallState,
+    },
+
+    /// On the presence socket: `from` left a voice message for an
+    /// unanswered call; fetch it with `GET /api/calls/{room_id}/voicemail`
+    Voicemail {
+        room_id: String,
+        from: String,
+    },
+
+    /// The call reaches its maximum duration in `secs` seconds
+    TimeRemaining {
+        secs: u64,
+    },
+
+    /// The room's tenant went past its usage quota; the call goes on with
+    /// what is still allowed. Clients stop sending video when `video` is
+    /// false, and recording is switched off when `recording` is false
+    QuotaDowngrade {
+        video: bool,
+        recording: bool,
+    },
+
+    /// The call reached its maximum duration; `call_ended` follows
+    RoomExpired {
+        max_duration_secs: u64,
+    },
 
     /// Ping/pong for keepalive
     Ping,
     Pong,
 }
 
+/// Why a peer left a room
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaveReason {
+    /// The user hung up or closed the page
+    UserHangup,
+    /// The connection dropped without a clean close
+    NetworkTimeout,
+    /// The server removed the peer
+    Kicked,
+    ServerShutdown,
+    /// The room reached its maximum duration
+    RoomExpired,
+}
+
+/// Longest DTMF sequence accepted in a single message
+pub const MAX_DTMF_DIGITS: usize = 32;
+
+/// Largest session description accepted for relay
+pub const MAX_SDP_LEN: usize = 64 * 1024;
+
+/// Largest ICE candidate line accepted for relay
+pub const MAX_CANDIDATE_LEN: usize = 1024;
+
+/// Largest reaction accepted for relay, in bytes
+pub const MAX_REACTION_LEN: usize = 32;
+
+/// Longest field of a `DeviceInfo` message, in bytes
+pub const MAX_DEVICE_FIELD_LEN: usize = 256;
+
+/// Most metadata entries a room may carry
+pub const MAX_METADATA_ENTRIES: usize = 32;
+
+/// Longest metadata key, in bytes
+pub const MAX_METADATA_KEY_LEN: usize = 64;
+
+/// Longest metadata value, in bytes
+pub const MAX_METADATA_VALUE_LEN: usize = 512;
+
+/// Free-form key/value tags set on room creation, e.g. `external_id` or
+/// `customer`, for reconciling calls with other systems
+pub type RoomMetadata = BTreeMap<String, String>;
+
+/// Check metadata against the size limits
+pub fn validate_metadata(metadata: &RoomMetadata) -> Result<(), &'static str> {
+    if metadata.len() > MAX_METADATA_ENTRIES {
+        return Err("Too many metadata entries");
+    }
+    for (key, value) in metadata {
+        if key.is_empty() || key.len() > MAX_METADATA_KEY_LEN {
+            return Err("Metadata keys must be 1 to 64 bytes long");
+        }
+        if value.len() > MAX_METADATA_VALUE_LEN {
+            return Err("Metadata values must be at most 512 bytes long");
+        }
+    }
+    Ok(())
+}
+
 impl WsMessage {
     /// Create an error message
     pub fn error(msg: impl Into<String>) -> Self {
@@ -63,14 +506,602 @@ impl WsMessage {
         }
     }
 
+    /// Check that a DTMF digit string is well formed
+    pub fn valid_dtmf(digits: &str) -> bool {
+        !digits.is_empty()
+            && digits.len() <= MAX_DTMF_DIGITS
+            && digits
+                .chars()
+                .all(|c| c.is_ascii_digit() || matches!(c, '*' | '#' | 'A'..='D' | ','))
+    }
+
+    /// Check that a session description is plausibly SDP
+    ///
+    /// Starts with `v=0`, and every line has the `<letter>=<value>` shape.
+    pub fn valid_sdp(sdp: &str) -> bool {
+        sdp.len() <= MAX_SDP_LEN
+            && sdp.starts_with("v=0")
+            && sdp
+                .lines()
+                .map(|line| line.trim_end_matches('\r'))
+                .filter(|line| !line.is_empty())
+                .all(|line| {
+                    let bytes = line.as_bytes();
+                    bytes.len() >= 2 && bytes[0].is_ascii_lowercase() && bytes[1] == b'='
+                })
+    }
+
+    /// Check that an ICE candidate line is well formed
+    ///
+    /// An empty string (end of candidates) is allowed.
+    pub fn valid_candidate(candidate: &str) -> bool {
+        if candidate.is_empty() {
+            return true;
+        }
+        let body = candidate.strip_prefix("a=").unwrap_or(candidate);
+        let Some(body) = body.strip_prefix("candidate:") else {
+            return false;
+        };
+        // foundation component transport priority address port typ type ...
+        let fields: Vec<&str> = body.split_ascii_whitespace().collect();
+        candidate.len() <= MAX_CANDIDATE_LEN
+            && fields.len() >= 8
+            && fields[1].parse::<u16>().is_ok()
+            && fields[3].parse::<u32>().is_ok()
+            && fields[5].parse::<u16>().is_ok()
+            && fields[6] == "typ"
+    }
+
+    /// Reject signaling messages whose payload is malformed
+    pub fn check_payload(&self) -> Result<(), &'static str> {
+        match self {
+            WsMessage::Offer { sdp, .. } | WsMessage::Answer { sdp, .. }
+                if !Self::valid_sdp(sdp) =>
+            {
+                Err("Malformed session description")
+            }
+            WsMessage::IceCandidate { candidate, .. } if !Self::valid_candidate(candidate) => {
+                Err("Malformed ICE candidate")
+            }
+            WsMessage::Reaction { emoji, .. }
+                if emoji.is_empty() || emoji.len() > MAX_REACTION_LEN =>
+            {
+                Err("Malformed reaction")
+            }
+            WsMessage::DeviceInfo {
+                camera_label,
+                mic_label,
+                os,
+                browser,
+                ..
+            } if [camera_label, mic_label, os, browser]
+                .into_iter()
+                .flatten()
+                .any(|field| field.len() > MAX_DEVICE_FIELD_LEN) =>
+            {
+                Err("Malformed device info")
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Create a room info message
-    pub fn room_info(peer_count: usize) -> Self {
-        WsMessage::RoomInfo { peer_count }
+    pub fn room_info(
+        peer_count: usize,
+        codecs: Option<&CodecPolicy>,
+        features: FeatureFlags,
+    ) -> Self {
+        WsMessage::RoomInfo {
+            peer_count,
+            codecs: codecs.cloned(),
+            features,
+        }
     }
 }
 
+/// Room topology
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomMode {
+    /// Symmetric 1:1 call where either peer may offer
+    #[default]
+    Interactive,
+    /// One presenter sends offers, many viewers only answer and receive
+    Broadcast,
+}
+
+/// Role of a peer within a room
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerRole {
+    /// Room owner; may change roles and permissions at runtime
+    Host,
+    #[default]
+    Participant,
+    /// Receive-only peer (broadcast viewers)
+    Viewer,
+}
+
+/// Actions gated by the permission matrix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Chat,
+    /// Send reactions; granted with chat
+    React,
+    ScreenShare,
+    Record,
+    Invite,
+}
+
+/// Permissions granted to a single role
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+pub struct RolePermissions {
+    pub chat: bool,
+    pub screen_share: bool,
+    pub record: bool,
+    pub invite: bool,
+}
+
+impl RolePermissions {
+    /// Check whether this role may perform an action
+    pub fn allows(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::Chat | Permission::React => self.chat,
+            Permission::ScreenShare => self.screen_share,
+            Permission::Record => self.record,
+            Permission::Invite => self.invite,
+        }
+    }
+}
+
+/// Per-room permission matrix (role -> allowed actions)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+pub struct PermissionMatrix {
+    pub host: RolePermissions,
+    pub participant: RolePermissions,
+    pub viewer: RolePermissions,
+}
+
+impl PermissionMatrix {
+    /// Permissions for a role
+    pub fn for_role(&self, role: PeerRole) -> RolePermissions {
+        match role {
+            PeerRole::Host => self.host,
+            PeerRole::Participant => self.participant,
+            PeerRole::Viewer => self.viewer,
+        }
+    }
+}
+
+impl Default for PermissionMatrix {
+    fn default() -> Self {
+        Self {
+            host: RolePermissions {
+                chat: true,
+                screen_share: true,
+                record: true,
+                invite: true,
+            },
+            participant: RolePermissions {
+                chat: true,
+                screen_share: true,
+                record: false,
+                invite: true,
+            },
+            viewer: RolePermissions {
+                chat: true,
+                screen_share: false,
+                record: false,
+                invite: false,
+            },
+        }
+    }
+}
+
+/// Who must agree before recording starts
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsentPolicy {
+    /// Every peer in the room must consent
+    #[default]
+    All,
+    /// More than half of the peers must consent
+    Majority,
+    /// The host's consent is sufficient
+    HostOnly,
+}
+
+/// How clients arrange the participants' videos
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum StageLayout {
+    /// Every participant in an equal tile
+    #[default]
+    Grid,
+    /// The pinned peer large, everyone else in a strip
+    Speaker,
+}
+
+/// Who decides whether a peer may take the screen-share slot
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentPolicy {
+    /// The first peer to ask gets the slot while it is free
+    #[default]
+    FirstCome,
+    /// The host approves each request; its own are granted at once
+    Host,
+}
+
+/// Why a `present_request` was turned down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PresentDeniedReason {
+    /// Another peer is already sharing its screen
+    SlotTaken,
+    /// The host declined the request
+    Declined,
+    /// The policy needs a host and the room has none
+    NoHost,
+}
+
+/// How much of the peers' network addresses a room may expose
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivacyMode {
+    /// Candidates allowed by the deployment's ICE policy
+    #[default]
+    Standard,
+    /// Media only through TURN; the server rejects any other candidate
+    RelayOnly,
+}
+
+/// Entry in the audit trail
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AuditEvent {
+    /// Unix timestamp (seconds)
+    #[schema(example = 1718000000)]
+    pub timestamp: u64,
+    pub room_id: Option<String>,
+    /// Peer or principal that performed the action
+    pub actor: String,
+    #[schema(example = "recording.consent")]
+    pub action: String,
+    pub detail: String,
+}
+
+/// Effective settings of a room, resolved from template and request overrides
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+pub struct RoomSettings {
+    pub mode: RoomMode,
+    pub permissions: PermissionMatrix,
+    /// Viewer cap for broadcast rooms
+    #[schema(example = 100)]
+    pub max_viewers: usize,
+    /// Whether peers should publish audio
+    pub audio: bool,
+    /// Whether peers should publish video
+    pub video: bool,
+    /// Maximum call length in seconds, counted from the first join
+    #[schema(example = 3600)]
+    pub max_duration_secs: Option<u64>,
+    /// Live captions; disabled when unset
+    #[serde(default)]
+    pub transcription: Option<TranscriptionSettings>,
+    /// Consent required before recording starts
+    #[serde(default)]
+    pub consent_policy: ConsentPolicy,
+    /// Whether peers must hide their addresses behind TURN
+    #[serde(default)]
+    pub privacy_mode: PrivacyMode,
+    /// Who grants the screen-share slot
+    #[serde(default)]
+    pub present_policy: PresentPolicy,
+    /// Features the host has switched on or off for everyone
+    #[serde(default)]
+    pub controls: RoomControls,
+    /// Codec preferences; clients choose their own when unset
+    #[serde(default)]
+    pub codecs: Option<Box<CodecPolicy>>,
+    /// Media constraints enforced on relayed SDP; relayed as sent when unset
+    #[serde(default)]
+    pub sdp: Option<Box<SdpPolicy>>,
+    /// Feature flags set for this room, over the server-wide ones
+    #[serde(default)]
+    pub features: FeatureOverrides,
+    /// Registration for event rooms; anyone invited may join otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<Box<EventSettings>>,
+}
+
+/// An event room's title and how many may register
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+pub struct EventSettings {
+    #[schema(example = "Quarterly all-hands")]
+    pub title: String,
+    /// Registrations accepted before the event is full
+    #[schema(example = 500)]
+    pub capacity: usize,
+    /// Whether registrations past `capacity` wait for a cancellation
+    /// instead of being refused
+    #[serde(default)]
+    pub waitlist: bool,
+}
+
+/// Client features rolled out by flag, without a redeploy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+#[serde(default)]
+pub struct FeatureFlags {
+    pub chat: bool,
+    pub reactions: bool,
+    pub recording: bool,
+    /// End-to-end encrypted media (insertable streams)
+    pub e2ee: bool,
+    /// Live captions and transcripts
+    pub transcription: bool,
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        Self {
+            chat: true,
+            reactions: true,
+            recording: true,
+            e2ee: false,
+            transcription: false,
+        }
+    }
+}
+
+/// Feature flags in effect, and the overrides they come from
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FeatureStatus {
+    pub flags: FeatureFlags,
+    pub overrides: FeatureOverrides,
+}
+
+/// Feature flags to change; unset ones keep the value beneath
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema,
+)]
+#[serde(default)]
+pub struct FeatureOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reactions: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recording: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e2ee: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transcription: Option<bool>,
+}
+
+/// Room-wide switches the host can flip mid-call, applied on top of the
+/// permission matrix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+pub struct RoomControls {
+    pub chat: bool,
+    pub reactions: bool,
+    pub screen_share: bool,
+    pub recording: bool,
+    /// Recorders may publish a preview image of the call; off unless the
+    /// host opts in
+    #[serde(default)]
+    pub preview: bool,
+}
+
+impl Default for RoomControls {
+    fn default() -> Self {
+        Self {
+            chat: true,
+            reactions: true,
+            screen_share: true,
+            recording: true,
+            preview: false,
+        }
+    }
+}
+
+impl RoomControls {
+    /// Whether the action is switched on for the room
+    pub fn allows(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::Chat => self.chat,
+            Permission::React => self.reactions,
+            Permission::ScreenShare => self.screen_share,
+            Permission::Record => self.recording,
+            Permission::Invite => true,
+        }
+    }
+
+    /// Apply a host's `RoomSettingsUpdate`
+    pub fn update(
+        &mut self,
+        chat: Option<bool>,
+        reactions: Option<bool>,
+        screen_share: Option<bool>,
+        recording: Option<bool>,
+        preview: Option<bool>,
+    ) {
+        self.chat = chat.unwrap_or(self.chat);
+        self.reactions = reactions.unwrap_or(self.reactions);
+        self.screen_share = screen_share.unwrap_or(self.screen_share);
+        self.recording = recording.unwrap_or(self.recording);
+        self.preview = preview.unwrap_or(self.preview);
+    }
+
+    /// The full state, as broadcast to peers
+    pub fn message(&self) -> WsMessage {
+        WsMessage::RoomSettingsUpdate {
+            chat: Some(self.chat),
+            reactions: Some(self.reactions),
+            screen_share: Some(self.screen_share),
+            recording: Some(self.recording),
+            preview: Some(self.preview),
+        }
+    }
+}
+
+/// Per-room live transcription options
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+pub struct TranscriptionSettings {
+    /// ISO-639-1 language code passed to the STT backend
+    #[schema(example = "en")]
+    pub language: String,
+}
+
+/// Codecs a room standardizes on, e.g. hardware-friendly H.264
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+pub struct CodecPolicy {
+    /// Codec names in order of preference, ahead of any others the peers
+    /// support
+    #[schema(example = json!(["H264", "VP9"]))]
+    #[serde(default)]
+    pub preferred: Vec<String>,
+    /// Opus parameters set on every audio section
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opus: Option<OpusSettings>,
+}
+
+/// Opus `fmtp` parameters; unset ones are left as negotiated
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+pub struct OpusSettings {
+    /// Target bitrate in bits per second (`maxaveragebitrate`)
+    #[schema(example = 32000)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_average_bitrate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stereo: Option<bool>,
+    /// Discontinuous transmission (`usedtx`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dtx: Option<bool>,
+    /// In-band forward error correction (`useinbandfec`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fec: Option<bool>,
+}
+
+/// Constraints the server writes into every offer and answer it relays
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+pub struct SdpPolicy {
+    /// Bandwidth cap for each video section in kbit/s (`b=AS`)
+    #[schema(example = 1500)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_video_kbps: Option<u32>,
+    /// Bandwidth cap for each audio section in kbit/s (`b=AS`)
+    #[schema(example = 64)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_audio_kbps: Option<u32>,
+    /// Codec names to remove, with their retransmission formats
+    #[schema(example = json!(["AV1"]))]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub strip_codecs: Vec<String>,
+    /// RTP header extension URIs to remove
+    #[schema(example = json!(["http://www.webrtc.org/experiments/rtp-hdrext/abs-capture-time"]))]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub strip_extensions: Vec<String>,
+}
+
+impl Default for RoomSettings {
+    fn default() -> Self {
+        Self {
+            mode: RoomMode::Interactive,
+            permissions: PermissionMatrix::default(),
+            max_viewers: MAX_BROADCAST_VIEWERS,
+            audio: true,
+            video: true,
+            max_duration_secs: None,
+            transcription: None,
+            consent_policy: ConsentPolicy::All,
+            privacy_mode: PrivacyMode::Standard,
+            present_policy: PresentPolicy::FirstCome,
+            controls: RoomControls::default(),
+            codecs: None,
+            sdp: None,
+            features: FeatureOverrides::default(),
+            event: None,
+        }
+    }
+}
+
+/// Optional body for room creation
+///
+/// Explicit fields override the values of the selected template.
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct CreateRoomRequest {
+    /// Name of a configured room template
+    #[schema(example = "interview")]
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Room topology (defaults to `interactive`)
+    #[serde(default)]
+    pub mode: Option<RoomMode>,
+    /// Permission matrix (defaults apply when omitted)
+    #[serde(default)]
+    pub permissions: Option<PermissionMatrix>,
+    /// Maximum call length in seconds; cannot exceed the server default
+    #[schema(example = 1800)]
+    #[serde(default)]
+    pub max_duration_secs: Option<u64>,
+    /// Enable live captions for this room
+    #[serde(default)]
+    pub transcription: Option<TranscriptionSettings>,
+    /// Consent required before recording starts
+    #[serde(default)]
+    pub consent_policy: Option<ConsentPolicy>,
+    /// Set to `relay_only` to keep peers' addresses private
+    #[serde(default)]
+    pub privacy_mode: Option<PrivacyMode>,
+    /// Set to `host` to have the host approve screen shares
+    #[serde(default)]
+    pub present_policy: Option<PresentPolicy>,
+    /// Codecs clients should prefer, enforced on relayed SDP
+    #[serde(default)]
+    pub codecs: Option<CodecPolicy>,
+    /// Bandwidth caps and codecs or extensions to strip from relayed SDP
+    #[serde(default)]
+    pub sdp: Option<SdpPolicy>,
+    /// Feature flags for this room, over the server-wide ones
+    #[serde(default)]
+    pub features: FeatureOverrides,
+    /// Make this an event room, joined by registering; requires
+    /// `jwt_secret`
+    #[serde(default)]
+    pub event: Option<EventSettings>,
+    /// Tag the room for call-start notifications (Slack/Discord)
+    #[serde(default)]
+    pub notify: bool,
+    /// Emails or token subjects allowed to join; requires `jwt_secret`.
+    /// Anyone with the link may join when omitted
+    #[schema(example = json!(["alice@example.com"]))]
+    #[serde(default)]
+    pub invitees: Option<Vec<String>>,
+    /// Tags returned with the room's status, call record and webhook events
+    #[schema(value_type = HashMap<String, String>, example = json!({"external_id": "A-1042", "customer": "acme"}))]
+    #[serde(default)]
+    pub metadata: RoomMetadata,
+    /// Entitlement code letting an anonymous user create the room, or
+    /// allowing longer calls in it
+    #[schema(example = "7KQ2-M9XD-40FT")]
+    #[serde(default)]
+    pub code: Option<String>,
+}
+
 /// Response for room creation
-#[derive(Debug, Serialize, ToSchema)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreateRoomResponse {
     /// The unique identifier for the created room (UUID)
     #[schema(example = "550e8400-e29b-41d4-a716-446655440000")]
@@ -78,10 +1109,1216 @@ pub struct CreateRoomResponse {
     /// The WebSocket URL path for connecting to this room
     #[schema(example = "/ws/550e8400-e29b-41d4-a716-446655440000")]
     pub ws_url: String,
+    /// Effective room settings
+    pub settings: RoomSettings,
+    /// Numeric code for joining by phone
+    #[schema(example = "482913")]
+    pub dial_code: String,
+    /// Links that open the room in the mobile app, when configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<DeepLinks>,
+    /// Token to join a broadcast room with as its presenter; only the
+    /// creator gets it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presenter_token: Option<String>,
 }
 
-/// Room status response
+/// Links into a room for handing a join off to the mobile app
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DeepLinks {
+    /// Opens the app directly
+    #[schema(example = "myapp://join/550e8400-e29b-41d4-a716-446655440000")]
+    pub app_url: String,
+    /// Opens the app where it handles the server's links (universal or
+    /// app links), otherwise a page offering the app or the browser
+    #[schema(example = "http://localhost:3000/join/550e8400-e29b-41d4-a716-446655440000")]
+    pub web_url: String,
+}
+
+/// Settings the web client reads at startup
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ClientConfig {
+    /// Static assets by path under `/static/`
+    pub assets: BTreeMap<String, AssetEntry>,
+    /// Features to offer, in the requested room or in new rooms
+    pub features: FeatureFlags,
+    /// Variants of the requested room and peer, by experiment
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub experiments: BTreeMap<String, ExperimentAssignment>,
+}
+
+/// The variant of an experiment a room or peer is in
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExperimentAssignment {
+    #[schema(example = "on")]
+    pub variant: String,
+    /// Client settings of the variant
+    #[schema(value_type = Object, example = json!({"opus_fec": true}))]
+    pub settings: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Where a static asset is served and how to check it
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AssetEntry {
+    /// Fingerprinted URL, cached for a year
+    #[schema(example = "/static/app.3f9a0c2b41d7e865.js")]
+    pub url: String,
+    /// Subresource integrity hash of the uncompressed file
+    #[schema(example = "sha384-oqVuAfXRKap7fdgcCY5uykM6+R9GqQ8K/uxy9rx7HNQlGYl1kPzQho1wx4JwY8wC")]
+    pub integrity: String,
+}
+
+/// Result of an upload bandwidth probe
 #[derive(Debug, Serialize, ToSchema)]
+pub struct UploadProbeResult {
+    /// Number of bytes received by the server
+    #[schema(example = 1048576)]
+    pub bytes: usize,
+    /// Time the server spent receiving the body
+    #[schema(example = 420)]
+    pub elapsed_ms: u64,
+    /// Server-observed throughput in kilobits per second
+    #[schema(example = 19972.9)]
+    pub kbps: f64,
+}
+
+/// Browser and codec support reported by a client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ClientCapabilities {
+    /// Supported codec MIME types, e.g. `video/VP8`
+    pub codecs: Vec<String>,
+    #[schema(example = "chrome")]
+    pub browser: String,
+    #[schema(example = "126.0.6478.61")]
+    pub version: String,
+}
+
+/// A peer's devices and platform, as its client reports them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DeviceInfo {
+    #[schema(example = "FaceTime HD Camera")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_label: Option<String>,
+    #[schema(example = "MacBook Pro Microphone")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mic_label: Option<String>,
+    #[schema(example = "iOS")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    #[schema(example = "Safari")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub browser: Option<String>,
+}
+
+/// The devices a peer reported during a call
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PeerDevice {
+    pub peer_id: String,
+    #[serde(flatten)]
+    pub device: DeviceInfo,
+    /// Unix timestamp (seconds) of the report
+    pub at: u64,
+}
+
+/// Category of a client-side failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientErrorKind {
+    /// getUserMedia rejected (permissions, missing device, device busy)
+    GetUserMedia,
+    /// ICE negotiation failed or the connection dropped
+    IceFailure,
+    /// SDP offer/answer could not be applied
+    Signaling,
+    Other,
+}
+
+/// Structured error report submitted by a frontend
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ClientErrorReport {
+    pub kind: ClientErrorKind,
+    /// Human-readable error, e.g. the DOMException name and message
+    #[schema(example = "NotAllowedError: Permission denied")]
+    pub message: String,
+    /// Room the client was in, if any
+    #[serde(default)]
+    pub room_id: Option<String>,
+    /// Peer ID assigned by the server, if the client had joined
+    #[serde(default)]
+    pub peer_id: Option<String>,
+    #[serde(default)]
+    #[schema(example = "Chrome 126")]
+    pub browser: Option<String>,
+    #[serde(default)]
+    #[schema(example = "macOS 14.5")]
+    pub os: Option<String>,
+    /// Free-form extra context (ICE state, failing candidate pair, etc.)
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub details: Option<serde_json::Value>,
+}
+
+/// A client error report as stored by the server
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StoredClientError {
+    #[schema(example = "7c9e6679-7425-40de-944b-e07fc1f90ae7")]
+    pub id: String,
+    /// Unix timestamp (seconds) when the report was received
+    #[schema(example = 1718000000)]
+    pub received_at: u64,
+    #[serde(flatten)]
+    pub report: ClientErrorReport,
+}
+
+/// Source of a transcript line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptKind {
+    Chat,
+    Caption,
+}
+
+/// One line of a room transcript
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TranscriptEntry {
+    /// Unix timestamp (seconds)
+    #[schema(example = 1718000000)]
+    pub timestamp: u64,
+    pub peer_id: String,
+    pub kind: TranscriptKind,
+    #[schema(example = "Let's ship it on Friday")]
+    pub text: String,
+}
+
+/// Stored chat and captions of a room
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct RoomTranscript {
+    pub room_id: String,
+    pub entries: Vec<TranscriptEntry>,
+    /// Generated summary, if one was requested
+    pub summary: Option<String>,
+}
+
+/// Response for summary generation
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SummaryResponse {
+    pub room_id: String,
+    #[schema(example = "The team agreed to ship on Friday.")]
+    pub summary: String,
+}
+
+/// Problem area a caller can flag in the end-of-call survey
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ComplaintCategory {
+    Audio,
+    Video,
+    Connection,
+    Latency,
+    Other,
+}
+
+/// End-of-call survey submitted by a client
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FeedbackRequest {
+    /// Quality rating from 1 (bad) to 5 (excellent)
+    #[schema(example = 4)]
+    pub rating: u8,
+    #[serde(default)]
+    pub categories: Vec<ComplaintCategory>,
+    #[serde(default)]
+    #[schema(example = "Video froze a couple of times")]
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub peer_id: Option<String>,
+}
+
+/// A survey stored on a call record
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CallFeedback {
+    /// Unix timestamp (seconds)
+    #[schema(example = 1718000000)]
+    pub submitted_at: u64,
+    #[serde(flatten)]
+    pub survey: FeedbackRequest,
+}
+
+/// Call detail record, kept after the room is closed
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CallRecord {
+    pub room_id: String,
+    pub mode: RoomMode,
+    /// Unix timestamp (seconds) when the room was opened
+    #[schema(example = 1718000000)]
+    pub started_at: u64,
+    /// Unix timestamp (seconds) when the room was closed
+    pub ended_at: Option<u64>,
+    /// Number of peers that joined over the call's lifetime
+    pub total_joins: usize,
+    /// Largest number of peers present at once
+    pub peak_peers: usize,
+    pub feedback: Vec<CallFeedback>,
+    /// Why each peer left, in order
+    pub disconnects: Vec<DisconnectRecord>,
+    /// Mean estimated MOS over all quality reports
+    pub average_mos: Option<f64>,
+    /// Lowest estimated MOS reported during the call
+    pub min_mos: Option<f64>,
+    /// Who rang whom and how it went, for calls placed with `/api/calls`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directed: Option<DirectedCall>,
+    /// Tags set on room creation
+    #[schema(value_type = HashMap<String, String>)]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: RoomMetadata,
+    /// Devices each peer reported, for diagnostics
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<PeerDevice>,
+    /// Variant of each room experiment, by experiment
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub experiments: BTreeMap<String, String>,
+    /// Variants of the peer experiments, by peer, then experiment
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub peer_experiments: BTreeMap<String, BTreeMap<String, String>>,
+    /// Tenant whose API key created the room
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// Whether the call was ended for reaching its maximum duration
+    #[serde(default)]
+    pub expired: bool,
+    #[serde(skip)]
+    pub mos_samples: usize,
+}
+
+impl CallRecord {
+    /// Length of the call, once it has ended
+    pub fn duration_secs(&self) -> Option<u64> {
+        self.ended_at.map(|end| end.saturating_sub(self.started_at))
+    }
+}
+
+/// A peer's departure from a call
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DisconnectRecord {
+    pub peer_id: String,
+    pub reason: LeaveReason,
+    /// Unix timestamp (seconds)
+    #[schema(example = 1718000000)]
+    pub at: u64,
+}
+
+/// Number of surveys flagging a complaint category
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CategoryCount {
+    pub category: ComplaintCategory,
+    pub count: usize,
+}
+
+/// Aggregates over stored call records
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CallAnalytics {
+    pub total_calls: usize,
+    pub ended_calls: usize,
+    /// Mean duration of ended calls in seconds
+    pub average_duration_secs: Option<f64>,
+    pub feedback_count: usize,
+    /// Mean survey rating (1-5)
+    #[schema(example = 4.2)]
+    pub average_rating: Option<f64>,
+    /// Mean estimated MOS over calls that reported stats
+    pub average_mos: Option<f64>,
+    /// Complaint categories, most frequent first
+    pub complaint_categories: Vec<CategoryCount>,
+    /// Results per experiment variant
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub experiments: Vec<VariantStats>,
+}
+
+/// Call results of one experiment variant
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct VariantStats {
+    #[schema(example = "opus-fec")]
+    pub experiment: String,
+    #[schema(example = "on")]
+    pub variant: String,
+    /// Calls with a room or peer in the variant
+    pub calls: usize,
+    /// Mean estimated MOS over those calls
+    pub average_mos: Option<f64>,
+    /// Mean survey rating (1-5) from the variant's rooms or peers
+    pub average_rating: Option<f64>,
+}
+
+/// Latest connection quality reported by a peer
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PeerQuality {
+    pub peer_id: String,
+    #[schema(example = 80.0)]
+    pub rtt_ms: f64,
+    #[schema(example = 12.5)]
+    pub jitter_ms: f64,
+    /// Packet loss in percent
+    #[schema(example = 0.5)]
+    pub packet_loss: f64,
+    /// Estimated mean opinion score (1.0-4.5)
+    #[schema(example = 4.3)]
+    pub mos: f64,
+    /// Unix timestamp (seconds)
+    pub updated_at: u64,
+}
+
+/// Inbound signaling volume of one connected peer
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PeerTraffic {
+    pub room_id: String,
+    pub peer_id: String,
+    pub role: PeerRole,
+    /// Messages received since the peer joined
+    #[schema(example = 42)]
+    pub messages: u64,
+    /// Bytes received since the peer joined
+    #[schema(example = 18000)]
+    pub bytes: u64,
+    /// Messages discarded by throttling
+    pub dropped: u64,
+    /// Messages received in the current window
+    pub window_messages: u32,
+    /// Whether the peer has exceeded the expected volume
+    pub flagged: bool,
+}
+
+/// Media payload bytes in one direction, per kind
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema,
+)]
+pub struct MediaBytes {
+    #[serde(default)]
+    pub audio: u64,
+    #[serde(default)]
+    pub video: u64,
+}
+
+/// `RTCPeerConnection.connectionState` as reported by a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerConnectionState {
+    New,
+    Connecting,
+    Connected,
+    Disconnected,
+    Failed,
+    Closed,
+}
+
+/// Media problem spotted by correlating peers' stats
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticIssue {
+    /// One peer sends audio that the other does not receive
+    OneWayAudio,
+    /// One peer sends video that the other does not receive
+    OneWayVideo,
+}
+
+/// Recent audio levels reported by a peer
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PeerAudio {
+    pub peer_id: String,
+    /// Latest reported level (0.0-1.0)
+    #[schema(example = 0.2)]
+    pub level: Option<f64>,
+    /// Mean of the recent reports
+    #[schema(example = 0.15)]
+    pub mean_level: Option<f64>,
+    /// Reports received since the peer joined
+    pub reports: u64,
+    /// Whether every recent report was silence
+    pub silent: bool,
+}
+
+/// Audio levels of one room
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RoomAudio {
+    pub room_id: String,
+    /// Peer that stays silent while another is heard
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one_way_audio: Option<String>,
+    pub peers: Vec<PeerAudio>,
+}
+
+/// Current call quality of a room
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RoomQuality {
+    pub room_id: String,
+    /// Score of the worst-off peer, if any have reported stats
+    pub mos: Option<f64>,
+    pub peers: Vec<PeerQuality>,
+}
+
+/// A running room as operators see it
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RoomDetails {
+    pub room_id: String,
+    pub mode: RoomMode,
+    #[schema(example = "482913")]
+    pub dial_code: String,
+    pub features: FeatureFlags,
+    /// Peer holding the screen-share slot
+    pub sharing: Option<String>,
+    /// Whether recording is active
+    pub recording: bool,
+    /// Peers waiting for a slot
+    pub waiting: usize,
+    /// Tags set on room creation
+    #[schema(value_type = HashMap<String, String>)]
+    pub metadata: RoomMetadata,
+    pub peers: Vec<PeerDetails>,
+    /// Token subjects banned from the room
+    pub bans: Vec<String>,
+}
+
+/// A peer in a running room
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PeerDetails {
+    pub peer_id: String,
+    pub role: PeerRole,
+    /// Subject of the room token the peer joined with
+    pub identity: Option<String>,
+    /// Devices the client reported
+    pub device: Option<DeviceInfo>,
+    /// Latest connection quality report
+    pub quality: Option<PeerQuality>,
+}
+
+/// Kind of entry in a room's event timeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineKind {
+    Created,
+    Joined,
+    Left,
+    Offer,
+    /// An offer after the first one in the room
+    Renegotiation,
+    Answer,
+    /// Server-side error or rejection
+    Error,
+    /// Error reported by a client via `/api/client-errors`
+    ClientError,
+    QualityDrop,
+    /// A peer stayed silent while another was heard
+    OneWayAudio,
+    /// Media one peer sent did not reach another
+    OneWayMedia,
+    /// A peer dropped and its slot is being held
+    Reconnecting,
+    /// A dead transport was told to restart ICE
+    IceRestart,
+    Closed,
+}
+
+/// One entry in a room's event timeline
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TimelineEvent {
+    /// Unix timestamp (milliseconds)
+    #[schema(example = 1718000000000u64)]
+    pub timestamp_ms: u64,
+    pub kind: TimelineKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub detail: String,
+}
+
+/// Ordered event history of a room, kept after the room is reaped
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct RoomTimeline {
+    pub room_id: String,
+    pub events: Vec<TimelineEvent>,
+}
+
+/// What can be told of a session description without keeping it
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SdpSummary {
+    /// Unix timestamp (milliseconds) when the peer sent it
+    #[schema(example = 1718000000000u64)]
+    pub timestamp_ms: u64,
+    #[schema(example = 3412)]
+    pub size_bytes: usize,
+    /// SHA-256 of the description, hex-encoded
+    pub sha256: String,
+    /// Its `a=fingerprint` values, e.g. `sha-256 AB:CD:...`
+    pub dtls_fingerprints: Vec<String>,
+    /// Kind of each media section, in order
+    #[schema(example = json!(["audio", "video"]))]
+    pub media: Vec<String>,
+}
+
+/// Signaling a peer sent during a call
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct PeerSignaling {
+    pub peer_id: String,
+    pub last_offer: Option<SdpSummary>,
+    pub last_answer: Option<SdpSummary>,
+    /// ICE candidates sent, by type (`host`, `srflx`, `prflx`, `relay`)
+    pub candidates: BTreeMap<String, u64>,
+}
+
+/// Everything known about a call, for attaching to bug reports
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CallDebugBundle {
+    pub room_id: String,
+    /// Unix timestamp (seconds) when the bundle was put together
+    #[schema(example = 1718000000)]
+    pub generated_at: u64,
+    /// The room with its peers' devices and stats, while it runs
+    pub room: Option<RoomDetails>,
+    /// Call detail record
+    pub call: Option<CallRecord>,
+    pub timeline: Vec<TimelineEvent>,
+    /// Last descriptions and candidate counts, by peer
+    pub signaling: Vec<PeerSignaling>,
+    /// Client error reports, newest first
+    pub client_errors: Vec<StoredClientError>,
+}
+
+/// Re-invite link for calling a dropped peer back
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReinviteResponse {
+    pub token: String,
+    #[schema(
+        example = "http://localhost:3000/room/550e8400-e29b-41d4-a716-446655440000?token=9f2c"
+    )]
+    pub url: String,
+    #[schema(example = 600)]
+    pub expires_in_secs: u64,
+    /// Links that open the room in the mobile app, when configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub links: Option<DeepLinks>,
+}
+
+/// Options for a new share link
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct CreateInviteRequest {
+    /// Joins the link allows; unlimited when omitted
+    #[schema(example = 5)]
+    #[serde(default)]
+    pub max_uses: Option<u32>,
+    /// Seconds until the link expires; never when omitted
+    #[schema(example = 86400)]
+    #[serde(default)]
+    pub expires_in_secs: Option<u64>,
+}
+
+/// Who is registering for an event
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RegistrationRequest {
+    #[schema(example = "Alice Liddell")]
+    pub name: String,
+    #[schema(example = "alice@example.com")]
+    pub email: String,
+}
+
+/// Where a registration stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistrationStatus {
+    /// Holds a place and can join
+    Registered,
+    /// Waits for a place to free up
+    Waitlisted,
+    Cancelled,
+}
+
+/// A registration, as its holder sees it
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RegistrationTicket {
+    /// Keep it private: it fetches and cancels the registration
+    pub id: String,
+    pub status: RegistrationStatus,
+    /// Place on the waitlist, from 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<usize>,
+    /// Room token to join with, once registered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Room link carrying the token, once registered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+/// A registrant and how much of the event they attended
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Attendee {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    pub status: RegistrationStatus,
+    /// Unix timestamp (seconds)
+    #[schema(example = 1718000000)]
+    pub registered_at: u64,
+    /// Unix timestamp (seconds) of their first join, if they came
+    pub first_joined_at: Option<u64>,
+    /// Seconds spent in the room, over all their joins
+    pub attended_secs: u64,
+}
+
+/// Registration and attendance of an event room
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AttendanceReport {
+    pub room_id: String,
+    pub event: EventSettings,
+    pub registered: usize,
+    pub waitlisted: usize,
+    /// Registrants who joined at least once
+    pub attended: usize,
+    /// In order of registration
+    pub attendees: Vec<Attendee>,
+}
+
+/// What a new entitlement code grants
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct EntitlementRequest {
+    /// Rooms the code can create; 1 when omitted
+    #[schema(example = 20)]
+    #[serde(default)]
+    pub rooms: Option<u32>,
+    /// Longest call in those rooms, even past the server default
+    #[schema(example = 10800)]
+    #[serde(default)]
+    pub max_duration_secs: Option<u64>,
+    /// Seconds until the code expires; `default_ttl_secs` when omitted
+    #[schema(example = 604800)]
+    #[serde(default)]
+    pub expires_in_secs: Option<u64>,
+    /// Note for operators, e.g. the event the code is for
+    #[schema(example = "Rust workshop, March")]
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// A redeemable code granting room creation
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Entitlement {
+    #[schema(example = "7KQ2-M9XD-40FT")]
+    pub code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Rooms the code can create
+    pub rooms: u32,
+    /// Rooms created with the code so far
+    pub redeemed: Vec<String>,
+    /// Longest call in the code's rooms, past the server default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_duration_secs: Option<u64>,
+    /// Unix timestamp (seconds)
+    #[schema(example = 1718000000)]
+    pub expires_at: u64,
+    /// Admin principal who minted the code
+    pub created_by: String,
+    /// Unix timestamp (seconds)
+    pub created_at: u64,
+}
+
+/// What a new tenant API key is for
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct ApiKeyRequest {
+    /// Note for operators, e.g. the integration using the key
+    #[schema(example = "Billing service")]
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// A tenant API key issued through the admin API
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ApiKey {
+    #[schema(example = "6f1c2d3e-4b5a-4c7d-8e9f-0a1b2c3d4e5f")]
+    pub id: String,
+    pub tenant: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// The key itself, shown only when it is issued
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Admin principal who issued the key
+    pub created_by: String,
+    /// Unix timestamp (seconds)
+    pub created_at: u64,
+}
+
+/// An outstanding link into a room
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct InviteLink {
+    pub token: String,
+    #[schema(
+        example = "http://localhost:3000/room/550e8400-e29b-41d4-a716-446655440000?token=9f2c"
+    )]
+    pub url: String,
+    /// Joins the link allows, if limited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<u32>,
+    /// Joins made with the link so far
+    pub uses: u32,
+    /// Unix timestamp (seconds) when the link expires, if it does
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Whether the link holds a slot open (re-invites)
+    pub holds_slot: bool,
+    /// Links that open the room in the mobile app, when configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub links: Option<DeepLinks>,
+}
+
+/// Whether a user can be reached
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    /// Connected and accepting invitations
+    Available,
+    /// Connected but not accepting invitations
+    DoNotDisturb,
+    /// Not connected, or appearing so
+    #[default]
+    Offline,
+}
+
+/// A user's presence
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PresenceInfo {
+    #[schema(example = "alice@example.com")]
+    pub user: String,
+    pub status: PresenceStatus,
+    /// Unix timestamp (seconds) of the last change, unless offline
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+}
+
+/// Where a directed call stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CallState {
+    Ringing,
+    Accepted,
+    Declined,
+    /// Not answered within the ring timeout
+    Missed,
+}
+
+/// A call placed by one user to another
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DirectedCall {
+    /// The room both parties join; also identifies the call
+    pub room_id: String,
+    /// Token subject of the user who called
+    pub caller: String,
+    /// Token subject of the user who was rung
+    pub callee: String,
+    pub state: CallState,
+    /// Unix timestamp (seconds) when the callee was rung
+    #[schema(example = 1718000000)]
+    pub rang_at: u64,
+    /// Unix timestamp (seconds) when the callee picked up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub answered_at: Option<u64>,
+    /// Unix timestamp (seconds) when the last party left an answered call
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<u64>,
+    /// Unix timestamp (seconds) when the caller left a voice message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voicemail_at: Option<u64>,
+    /// How the malware scan of the voice message went, with scanning on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voicemail_scan: Option<ScanStatus>,
+}
+
+/// Where the malware scan of an upload stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanStatus {
+    /// Not scanned yet; not downloadable
+    Pending,
+    Clean,
+    /// Malware was found; kept, but never served
+    Quarantined,
+}
+
+/// Which way a call went, from one party's point of view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CallDirection {
+    Incoming,
+    Outgoing,
+}
+
+/// A call in a user's call log
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CallHistoryEntry {
+    pub room_id: String,
+    pub direction: CallDirection,
+    /// Token subject of the other party
+    #[schema(example = "bob@example.com")]
+    pub with: String,
+    pub state: CallState,
+    /// Unix timestamp (seconds) when the call rang
+    #[schema(example = 1718000000)]
+    pub started_at: u64,
+    /// Seconds from pickup until the last party left, once it has ended
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<u64>,
+    /// Where to fetch the voice message the caller left, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "/api/calls/550e8400-e29b-41d4-a716-446655440000/voicemail")]
+    pub voicemail_url: Option<String>,
+    /// How the malware scan of the voice message went, with scanning on;
+    /// it can be fetched once `clean`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voicemail_scan: Option<ScanStatus>,
+}
+
+/// One page of a user's call log, newest first
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CallHistoryPage {
+    pub calls: Vec<CallHistoryEntry>,
+    /// Calls in the log altogether
+    pub total: usize,
+    /// `offset` of the next page, if there is one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<usize>,
+}
+
+/// Request body for ringing a user
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct PlaceCallRequest {
+    /// Token subject of the user to ring
+    #[schema(example = "bob@example.com")]
+    pub callee: String,
+}
+
+/// A person in a user's address book
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Contact {
+    #[schema(example = "4f1c2b9e8d7a4c3b")]
+    pub id: String,
+    /// The contact's token subject, as used to call them
+    #[schema(example = "bob@example.com")]
+    pub user: String,
+    /// Display name chosen by the address book's owner
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "Bob")]
+    pub name: Option<String>,
+    /// Whether the contact can be called right now
+    pub status: PresenceStatus,
+}
+
+/// A person found in the directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct DirectoryEntry {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "Bob Smith")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "bob@example.com")]
+    pub email: Option<String>,
+    /// The person's token subject, when the directory maps one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "bsmith")]
+    pub subject: Option<String>,
+}
+
+/// Request body for adding or changing a contact
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ContactRequest {
+    /// The contact's token subject
+    #[schema(example = "bob@example.com")]
+    pub user: String,
+    #[serde(default)]
+    #[schema(example = "Bob")]
+    pub name: Option<String>,
+}
+
+/// Push service a device token belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PushPlatform {
+    /// Firebase Cloud Messaging, for Android apps
+    Fcm,
+    /// Apple Push Notification service, for iOS apps
+    Apns,
+}
+
+/// A device registered to be rung with push notifications
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PushToken {
+    pub platform: PushPlatform,
+    /// Registration token or device token issued to the app
+    #[schema(example = "740f4707bebcf74f9b7c25d48e3358945f6aa01da5ddb387462c7eaf61bb78ad")]
+    pub token: String,
+    /// Unix timestamp (seconds) of the last registration
+    #[schema(example = 1718000000)]
+    pub registered_at: u64,
+}
+
+/// Request body for registering a device for push notifications
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct PushTokenRequest {
+    pub platform: PushPlatform,
+    #[schema(example = "740f4707bebcf74f9b7c25d48e3358945f6aa01da5ddb387462c7eaf61bb78ad")]
+    pub token: String,
+}
+
+/// Why the cleanup task removed a room
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReapReason {
+    /// Nobody joined before the never-joined timeout
+    NeverJoined,
+    /// Empty for longer than the idle timeout after a call
+    Idle,
+    /// Reached its maximum duration
+    Expired,
+}
+
+/// Counters kept by the cleanup task
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct CleanupStats {
+    pub runs: u64,
+    /// Unix timestamp (seconds) of the last sweep
+    pub last_run: Option<u64>,
+    pub never_joined: u64,
+    pub idle: u64,
+    pub expired: u64,
+}
+
+/// A tenant's participant-minutes in the current quota period
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QuotaUsage {
+    pub tenant: String,
+    /// Unix timestamp (seconds)
+    #[schema(example = 1718000000)]
+    pub period_start: u64,
+    #[schema(example = 8412.5)]
+    pub used_minutes: f64,
+    #[schema(example = 10000.0)]
+    pub quota_minutes: f64,
+    /// Whether `quota.warning` was sent
+    pub warned: bool,
+    /// Whether `quota.exceeded` was sent
+    pub exceeded: bool,
+    /// Whether the tenant's rooms are downgraded
+    pub downgraded: bool,
+}
+
+/// Kind of data the retention sweep deletes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RetentionArtifact {
+    /// Reaped rooms with their records
+    ArchivedRooms,
+    /// Chat lines of transcripts
+    Chat,
+    CallRecords,
+    Recordings,
+    ClientErrors,
+}
+
+/// How the retention sweep went for one kind of data
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ArtifactRetention {
+    pub artifact: RetentionArtifact,
+    /// Age past which items are deleted; unset when no policy applies
+    pub max_age_secs: Option<u64>,
+    /// Items deleted by the last sweep, or that it would have deleted in
+    /// a dry run
+    pub last_matched: u64,
+    /// Items deleted since startup
+    pub total_deleted: u64,
+}
+
+/// Counters kept by the retention sweep
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct RetentionStats {
+    pub runs: u64,
+    /// Unix timestamp (seconds) of the last sweep
+    pub last_run: Option<u64>,
+    /// Whether the last sweep only reported what it would delete
+    pub dry_run: bool,
+    /// How long the last sweep took
+    pub last_duration_ms: u64,
+    pub artifacts: Vec<ArtifactRetention>,
+}
+
+/// Size and counters of one ephemeral cache
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CacheStats {
+    #[schema(example = "rate_limits")]
+    pub name: String,
+    /// Entries held, including expired ones not yet swept
+    pub entries: usize,
+    /// Entries held before the least recently used is evicted
+    pub capacity: usize,
+    pub ttl_secs: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room
+    pub evictions: u64,
+    /// Entries dropped after their time to live
+    pub expirations: u64,
+}
+
+/// A room event posted to the webhook
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookEvent {
+    /// Unique per event and repeated on redelivery
+    pub id: String,
+    pub room_id: String,
+    /// Unix timestamp (seconds) when the event happened
+    #[schema(example = 1718000000)]
+    pub at: u64,
+    /// Tags set on the room's creation
+    #[schema(value_type = HashMap<String, String>)]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: RoomMetadata,
+    /// Tenant the room belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    #[serde(flatten)]
+    pub payload: WebhookPayload,
+}
+
+/// What happened, tagged by `type`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookPayload {
+    RoomCreated,
+    PeerJoined {
+        peer_id: String,
+    },
+    PeerLeft {
+        peer_id: String,
+        reason: String,
+    },
+    RoomClosed {
+        reason: String,
+    },
+    /// The room's call record, once the room has closed
+    CallEnded {
+        call: Box<CallRecord>,
+    },
+    /// The tenant used `warn_at_percent` of its quota for the period
+    #[serde(rename = "quota.warning")]
+    QuotaWarning {
+        used_minutes: f64,
+        quota_minutes: f64,
+        /// Unix timestamp (seconds)
+        period_start: u64,
+    },
+    /// The tenant used up its quota for the period
+    #[serde(rename = "quota.exceeded")]
+    QuotaExceeded {
+        used_minutes: f64,
+        quota_minutes: f64,
+        /// Unix timestamp (seconds)
+        period_start: u64,
+    },
+    /// Sample sent by `/admin/webhooks/test`
+    Test,
+}
+
+/// Where a webhook event's delivery stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    /// Given up on after `max_attempts`
+    Failed,
+}
+
+/// A webhook event in the outbox
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub event: WebhookEvent,
+    pub status: DeliveryStatus,
+    /// Attempts made so far
+    pub attempts: u32,
+    /// Unix timestamp (seconds) of the next attempt, while pending
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+    /// Unix timestamp (seconds)
+    pub delivered_at: Option<u64>,
+}
+
+/// Delivered webhook events to send again
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct WebhookReplayRequest {
+    /// Unix timestamp (seconds); events from then on are sent again
+    #[schema(example = 1718000000)]
+    pub since: u64,
+    /// Only events of this room
+    #[serde(default)]
+    pub room_id: Option<String>,
+}
+
+/// Outcome of sending a sample webhook event
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WebhookTestResult {
+    pub event: WebhookEvent,
+    /// Whether the endpoint answered 2xx
+    pub delivered: bool,
+    pub error: Option<String>,
+    /// Time taken by the request
+    pub elapsed_ms: u64,
+}
+
+/// Result of a webhook replay
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WebhookReplayResponse {
+    /// Events queued again
+    pub requeued: usize,
+}
+
+/// Request body for signing a URL
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct SignUrlRequest {
+    /// Path to sign, e.g. a recording file or an export download
+    #[schema(example = "/api/exports/4f1c2b9e8d7a4c3b/download")]
+    pub path: String,
+    /// Seconds the URL stays valid; `default_ttl_secs` when unset
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+}
+
+/// A URL anyone can fetch until it expires
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SignedUrl {
+    pub url: String,
+    /// Unix timestamp (seconds) after which the URL is refused
+    pub expires_at: u64,
+}
+
+/// A reaped room and what is retained about it
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ArchivedRoom {
+    pub room_id: String,
+    pub settings: RoomSettings,
+    pub reason: ReapReason,
+    /// Unix timestamp (seconds)
+    #[schema(example = 1718000000)]
+    pub archived_at: u64,
+    pub call: Option<CallRecord>,
+    /// Lines in the stored transcript (see `/api/room/{id}/transcript`)
+    pub transcript_entries: usize,
+    pub has_summary: bool,
+    /// Events in the stored timeline (see `/api/room/{id}/timeline`)
+    pub timeline_events: usize,
+}
+
+/// Room status response
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct RoomStatus {
     /// The room identifier
     #[schema(example = "550e8400-e29b-41d4-a716-446655440000")]
@@ -89,7 +2326,301 @@ pub struct RoomStatus {
     /// Number of peers currently in the room
     #[schema(example = 1)]
     pub peer_count: usize,
-    /// Whether the room can accept more peers (max 2)
+    /// Whether the room can accept more peers
     #[schema(example = true)]
     pub available: bool,
+    /// Room topology
+    pub mode: RoomMode,
+    /// Tags set on room creation
+    #[schema(value_type = HashMap<String, String>)]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: RoomMetadata,
+}
+
+/// A room as seen from its lobby
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct LobbyStatus {
+    /// Number of peers in the room
+    #[schema(example = 1)]
+    pub peer_count: usize,
+    /// Peers waiting for a slot or for the host
+    #[schema(example = 0)]
+    pub waiting: usize,
+    /// Whether the room may be joined now, rather than waiting for its host
+    pub started: bool,
+}
+
+/// Room locations one cluster member tells another
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ClusterGossip {
+    /// Base URL the sender advertises
+    #[schema(example = "http://10.0.0.5:3000")]
+    pub from: String,
+    /// IDs of the rooms running on the sender
+    pub rooms: Vec<String>,
+}
+
+/// Where a search term was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchField {
+    Metadata,
+    Chat,
+    Caption,
+    Summary,
+}
+
+/// One line of a room that matched a search
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchMatch {
+    pub field: SearchField,
+    /// Who wrote or said it, for chat and captions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    /// Unix timestamp (seconds), for chat and captions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    #[schema(example = "Let's ship it on Friday")]
+    pub text: String,
+}
+
+/// A room that matched a search
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchResult {
+    pub room_id: String,
+    /// Number of words that matched; higher ranks first
+    pub score: usize,
+    /// Unix timestamp (seconds) when the call started, if recorded
+    pub started_at: Option<u64>,
+    #[schema(value_type = HashMap<String, String>)]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: RoomMetadata,
+    /// The first few lines that matched
+    pub matches: Vec<SearchMatch>,
+}
+
+/// Response for a search
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SearchResponse {
+    #[schema(example = "ship friday")]
+    pub query: String,
+    pub results: Vec<SearchResult>,
+}
+
+/// Where a background export stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+/// A room export built in the background
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExportJob {
+    pub id: String,
+    pub room_id: String,
+    pub status: ExportStatus,
+    /// Unix timestamp (seconds)
+    #[schema(example = 1718000000)]
+    pub created_at: u64,
+    /// Unix timestamp (seconds) when the bundle was built or failed
+    pub finished_at: Option<u64>,
+    /// Size of the zip, once ready
+    pub size_bytes: Option<usize>,
+    /// Why the export failed
+    pub error: Option<String>,
+}
+
+/// Outcome of one synthetic signaling and TURN probe
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CanaryRun {
+    /// Unix timestamp (seconds)
+    pub started_at: u64,
+    /// Whether the signaling exchange completed, with TURN a message
+    /// echoed through the relay, and the loopback call carried audio
+    pub ok: bool,
+    /// Milliseconds until both peers had joined the room
+    #[schema(example = 35)]
+    pub join_ms: Option<u64>,
+    /// Milliseconds from creating the room until both peers had each
+    /// other's description and candidate
+    #[schema(example = 80)]
+    pub signaling_ms: Option<u64>,
+    /// TURN URL a relayed address was allocated on; unset without TURN
+    pub turn: Option<String>,
+    /// Milliseconds to allocate the relayed address
+    #[schema(example = 40)]
+    pub turn_allocate_ms: Option<u64>,
+    /// Round trip of a message through the relay, in milliseconds
+    #[schema(example = 2)]
+    pub turn_echo_ms: Option<u64>,
+    /// Milliseconds from the loopback call's offer until the callee got
+    /// the caller's first audio packet
+    #[schema(example = 450)]
+    pub media_ms: Option<u64>,
+    /// Whether the call's media was only allowed through TURN
+    pub media_relayed: Option<bool>,
+    pub error: Option<String>,
+}
+
+/// Whether an alert rule is failing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Ok,
+    Firing,
+}
+
+/// The last evaluation of an alert rule
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AlertStatus {
+    #[schema(example = "too-many-rooms")]
+    pub rule: String,
+    pub state: AlertState,
+    /// What was measured; absent when there was nothing to measure
+    #[schema(example = 120.0)]
+    pub value: Option<f64>,
+    /// Limit the value is compared with
+    #[schema(example = 100.0)]
+    pub threshold: Option<f64>,
+    #[schema(example = "120 active rooms (limit 100)")]
+    pub message: String,
+    /// Unix timestamp (seconds) since which the rule is in its state
+    pub since: u64,
+    /// Unix timestamp (seconds) of the evaluation
+    pub evaluated_at: u64,
+}
+
+/// Work done by a background job
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobKind {
+    /// Build a room's export bundle
+    Export { room_id: String },
+    /// Post a JSON message to a chat or alerting webhook
+    Notify {
+        url: String,
+        #[schema(value_type = Object)]
+        body: serde_json::Value,
+    },
+    /// Run one post-processing step on an uploaded recording
+    ProcessRecording {
+        recording_id: String,
+        step: RecordingStep,
+    },
+    /// Mail an alert or announcement through an SMTP relay
+    Email {
+        /// Relay as `host:port`
+        smtp: String,
+        from: String,
+        to: Vec<String>,
+        subject: String,
+        text: String,
+    },
+    /// Ring one of a user's devices with a push notification
+    Push {
+        user: String,
+        platform: PushPlatform,
+        token: String,
+        /// The `incoming_call` or `room_invite` the device is told about
+        message: Box<WsMessage>,
+        /// Unix timestamp (seconds) after which the push is dropped
+        expires_at: u64,
+    },
+    /// Scan the voice message of a call for malware
+    ScanVoicemail { room_id: String },
+}
+
+/// Where a background job stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    /// Waiting for its first or next attempt
+    Pending,
+    Running,
+    Done,
+    /// Given up on after its last attempt
+    Failed,
+}
+
+/// A background job
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Job {
+    pub id: String,
+    #[serde(flatten)]
+    pub kind: JobKind,
+    pub status: JobStatus,
+    /// Attempts started so far
+    pub attempts: u32,
+    pub max_attempts: u32,
+    /// Unix timestamp (seconds)
+    #[schema(example = 1718000000)]
+    pub created_at: u64,
+    /// Unix timestamp (seconds) of the next attempt, while pending
+    pub next_attempt_at: u64,
+    /// Unix timestamp (seconds) when the job finished or was given up on
+    pub finished_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// Post-processing done on an uploaded recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingStep {
+    /// Read the duration and bitrate
+    Probe,
+    /// Copy the streams into an MP4 container
+    RemuxMp4,
+    /// Pick a representative frame as a JPEG
+    Thumbnail,
+    /// Extract the audio as AAC
+    AudioOnly,
+}
+
+/// A file made from a recording by a post-processing step
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RecordingArtifact {
+    pub step: RecordingStep,
+    #[schema(example = "recording.mp4")]
+    pub name: String,
+    #[schema(example = "video/mp4")]
+    pub content_type: String,
+    pub size_bytes: u64,
+}
+
+/// Progress of one post-processing step
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RecordingStepState {
+    pub step: RecordingStep,
+    /// ID of the background job running it
+    pub job_id: String,
+    pub status: JobStatus,
+    pub error: Option<String>,
+}
+
+/// A recording uploaded for a room
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Recording {
+    pub id: String,
+    pub room_id: String,
+    /// Name of the uploaded file
+    #[schema(example = "original.webm")]
+    pub file: String,
+    #[schema(example = "video/webm")]
+    pub content_type: String,
+    pub size_bytes: u64,
+    /// Unix timestamp (seconds)
+    #[schema(example = 1718000000)]
+    pub uploaded_at: u64,
+    /// Length in seconds, once probed
+    pub duration_secs: Option<f64>,
+    /// Overall bitrate in bits per second, once probed
+    pub bitrate: Option<u64>,
+    /// Files made by post-processing so far
+    pub artifacts: Vec<RecordingArtifact>,
+    /// Post-processing steps and how they are going
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<RecordingStepState>,
 }
@@ -0,0 +1,181 @@
+//! Pre-call connectivity checks
+//!
+//! Lets clients measure round-trip time, jitter and bandwidth against the
+//! actual signaling server before joining a room.
+
+use std::time::{Duration, Instant};
+
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{
+        Query, Request, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::models::{UploadProbeResult, WsMessage};
+
+/// Number of echo probes sent per test
+pub const PROBE_COUNT: u32 = 10;
+
+/// Delay between consecutive probes
+pub const PROBE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How long to wait for an echo before counting the probe as lost
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Largest payload served or accepted by the bandwidth probes (10 MiB)
+pub const MAX_PROBE_BYTES: usize = 10 * 1024 * 1024;
+
+/// Default payload served by the download probe (1 MiB)
+const DEFAULT_PROBE_BYTES: usize = 1024 * 1024;
+
+/// Query parameters for the download probe
+#[derive(Debug, Deserialize)]
+pub struct DownloadParams {
+    pub bytes: Option<usize>,
+}
+
+/// WebSocket echo test measuring RTT and jitter
+pub async fn nettest_ws(ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(run_echo_test)
+}
+
+/// Send numbered probes, time their echoes and finish with a report
+async fn run_echo_test(mut socket: WebSocket) {
+    let mut rtts = Vec::with_capacity(PROBE_COUNT as usize);
+
+    for seq in 0..PROBE_COUNT {
+        let Ok(probe) = serde_json::to_string(&WsMessage::NetTestProbe { seq }) else {
+            return;
+        };
+        let sent = Instant::now();
+        if socket.send(Message::Text(probe.into())).await.is_err() {
+            return;
+        }
+
+        match tokio::time::timeout(PROBE_TIMEOUT, wait_for_echo(&mut socket, seq)).await {
+            Ok(true) => rtts.push(sent.elapsed()),
+            Ok(false) => return,
+            Err(_) => debug!("Net test probe {} timed out", seq),
+        }
+        tokio::time::sleep(PROBE_INTERVAL).await;
+    }
+
+    let report = build_report(&rtts, PROBE_COUNT as usize);
+    if let Ok(text) = serde_json::to_string(&report) {
+        let _ = socket.send(Message::Text(text.into())).await;
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+/// Wait for the echo of probe `seq`; returns false if the socket closed
+async fn wait_for_echo(socket: &mut WebSocket, seq: u32) -> bool {
+    while let Some(Ok(msg)) = socket.recv().await {
+        match msg {
+            Message::Text(text) => match serde_json::from_str::<WsMessage>(&text) {
+                Ok(WsMessage::NetTestProbe { seq: echoed }) if echoed == seq => return true,
+                Ok(_) => {}
+                Err(e) => warn!("Invalid net test echo: {}", e),
+            },
+            Message::Close(_) => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Summarize RTT samples; jitter is the mean absolute difference between
+/// consecutive samples (as in RFC 3550)
+fn build_report(rtts: &[Duration], sent: usize) -> WsMessage {
+    let ms: Vec<f64> = rtts.iter().map(|d| d.as_secs_f64() * 1000.0).collect();
+    let rtt_ms = if ms.is_empty() {
+        0.0
+    } else {
+        ms.iter().sum::<f64>() / ms.len() as f64
+    };
+    let jitter_ms = if ms.len() < 2 {
+        0.0
+    } else {
+        ms.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f64>() / (ms.len() - 1) as f64
+    };
+
+    WsMessage::NetTestReport {
+        samples: ms.len(),
+        lost: sent - ms.len(),
+        rtt_ms,
+        jitter_ms,
+    }
+}
+
+/// Download bandwidth probe
+#[utoipa::path(
+    get,
+    path = "/api/nettest/download",
+    tag = "Network Test",
+    params(
+        ("bytes" = Option<usize>, Query, description = "Payload size in bytes (max 10 MiB, default 1 MiB)")
+    ),
+    responses(
+        (status = 200, description = "Incompressible payload of the requested size", content_type = "application/octet-stream")
+    )
+)]
+pub async fn nettest_download(Query(params): Query<DownloadParams>) -> Response {
+    let size = params
+        .bytes
+        .unwrap_or(DEFAULT_PROBE_BYTES)
+        .min(MAX_PROBE_BYTES);
+
+    // Cheap xorshift noise so proxies can't compress the payload away
+    let mut seed: u32 = 0x9e37_79b9;
+    let payload: Vec<u8> = (0..size)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        })
+        .collect();
+
+    (
+        [
+            (header::CONTENT_TYPE, "application/octet-stream"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        Body::from(payload),
+    )
+        .into_response()
+}
+
+/// Upload bandwidth probe
+#[utoipa::path(
+    post,
+    path = "/api/nettest/upload",
+    tag = "Network Test",
+    request_body(content = Vec<u8>, description = "Arbitrary payload (max 10 MiB)", content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "Server-side measurement of the upload", body = UploadProbeResult),
+        (status = 413, description = "Payload too large")
+    )
+)]
+pub async fn nettest_upload(request: Request) -> Response {
+    let started = Instant::now();
+    let body: Bytes = match axum::body::to_bytes(request.into_body(), MAX_PROBE_BYTES).await {
+        Ok(body) => body,
+        Err(_) => return (StatusCode::PAYLOAD_TOO_LARGE, "Payload too large").into_response(),
+    };
+    let elapsed = started.elapsed();
+    let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+
+    Json(UploadProbeResult {
+        bytes: body.len(),
+        elapsed_ms: elapsed.as_millis() as u64,
+        kbps: body.len() as f64 * 8.0 / 1000.0 / secs,
+    })
+    .into_response()
+}
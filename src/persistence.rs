@@ -0,0 +1,201 @@
+//! Durable copies of what rooms leave behind
+//!
+//! Everything the server knows lives in memory and is gone after a restart.
+//! With a record store attached, a room's call record, transcript (chat and
+//! captions) and timeline are written out when the room closes, and again
+//! when feedback or a summary is added later. On startup the records of
+//! rooms still within the archive's retention are read back. Purging the
+//! archive deletes the stored copies as well. Instances sharing a store
+//! read back each other's new records every [`RECORD_SYNC_INTERVAL`]. The
+//! webhook outbox and the job queue are stored too, so queued events are
+//! still delivered, and queued jobs still run, after a restart. Running
+//! rooms, and chat as it is sent, are shared through a
+//! [`RoomStore`](crate::room_store::RoomStore) instead, bans included, and
+//! issued API keys through a [`KeyStore`](crate::api_keys::KeyStore).
+//!
+//! The one store so far is Postgres, behind the `postgres` feature.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tracing::warn;
+
+use crate::models::{CallRecord, Job, RoomTimeline, RoomTranscript, WebhookDelivery};
+use crate::state::{AppState, unix_timestamp};
+
+/// How often records saved by other instances are read back
+pub const RECORD_SYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What one room leaves behind
+#[derive(Debug, Clone)]
+pub struct RoomRecords {
+    pub room_id: String,
+    pub call: Option<CallRecord>,
+    pub transcript: Option<RoomTranscript>,
+    pub timeline: Option<RoomTimeline>,
+}
+
+/// Somewhere to keep room records across restarts
+pub trait RecordStore: Debug + Send + Sync {
+    /// Insert or replace a room's records
+    fn save(&self, records: RoomRecords) -> BoxFuture<'_, Result<(), String>>;
+    /// Records saved at or after `since` (Unix seconds)
+    fn load(&self, since: u64) -> BoxFuture<'_, Result<Vec<RoomRecords>, String>>;
+    /// Records of up to [`MAX_STORED_CANDIDATES`] rooms, newest first,
+    /// whose metadata or transcript contains every one of `terms`
+    /// (lowercase words); only rooms of `tenant` when given
+    ///
+    /// [`MAX_STORED_CANDIDATES`]: crate::search::MAX_STORED_CANDIDATES
+    fn search(
+        &self,
+        terms: Vec<String>,
+        tenant: Option<String>,
+    ) -> BoxFuture<'_, Result<Vec<RoomRecords>, String>>;
+    /// Delete the records of these rooms
+    fn delete(&self, room_ids: Vec<String>) -> BoxFuture<'_, Result<(), String>>;
+    /// Delete records saved, and webhook events and jobs settled, before
+    /// `before` (Unix seconds)
+    fn prune(&self, before: u64) -> BoxFuture<'_, Result<(), String>>;
+    /// Insert or replace a webhook delivery
+    fn save_delivery(&self, delivery: WebhookDelivery) -> BoxFuture<'_, Result<(), String>>;
+    /// Every stored webhook delivery, in the order they were first saved
+    fn load_deliveries(&self) -> BoxFuture<'_, Result<Vec<WebhookDelivery>, String>>;
+    /// Insert or replace a background job
+    fn save_job(&self, job: Job) -> BoxFuture<'_, Result<(), String>>;
+    /// Every stored job, in the order they were first saved
+    fn load_jobs(&self) -> BoxFuture<'_, Result<Vec<Job>, String>>;
+}
+
+impl AppState {
+    /// Keep records in `store` from now on, restoring those it holds;
+    /// returns how many rooms were restored
+    pub async fn attach_store(&self, store: Arc<dyn RecordStore>) -> Result<usize, String> {
+        let retention = self.config().cleanup.archive.retention_secs;
+        let stored = store
+            .load(unix_timestamp().saturating_sub(retention))
+            .await?;
+        let deliveries = store.load_deliveries().await?;
+        let jobs = store.load_jobs().await?;
+        self.store
+            .set(store)
+            .map_err(|_| "A record store is already attached".to_string())?;
+
+        let count = stored.len();
+        self.merge_records(stored).await;
+        self.restore_deliveries(deliveries).await;
+        self.restore_jobs(jobs).await;
+        Ok(count)
+    }
+
+    /// Take in records of rooms this instance does not know yet
+    async fn merge_records(&self, stored: Vec<RoomRecords>) {
+        let mut calls = self.calls.lock().await;
+        let mut transcripts = self.transcripts.lock().await;
+        let mut timelines = self.timelines.lock().await;
+        for records in stored {
+            let id = records.room_id;
+            if let Some(call) = records.call {
+                calls.entry(id.clone()).or_insert(call);
+            }
+            if let Some(transcript) = records.transcript {
+                transcripts.entry(id.clone()).or_insert(transcript);
+            }
+            if let Some(timeline) = records.timeline {
+                timelines.entry(id).or_insert(timeline);
+            }
+        }
+    }
+
+    /// Read back records saved since `since` (Unix seconds), such as those
+    /// of rooms other instances closed
+    pub async fn sync_stored_records(&self, since: u64) {
+        let Some(store) = self.store.get() else {
+            return;
+        };
+        match store.load(since).await {
+            Ok(stored) => self.merge_records(stored).await,
+            Err(e) => warn!("Failed to read stored records: {}", e),
+        }
+    }
+
+    /// Write the current records of these rooms to the store, if any
+    pub async fn persist_rooms(&self, room_ids: &[String]) {
+        let Some(store) = self.store.get() else {
+            return;
+        };
+        let scrubbing = self.config().scrubbing.clone().filter(|s| s.persisted);
+        for room_id in room_ids {
+            let mut records = RoomRecords {
+                room_id: room_id.clone(),
+                call: self.calls.lock().await.get(room_id).cloned(),
+                transcript: self.transcripts.lock().await.get(room_id).cloned(),
+                timeline: self.timelines.lock().await.get(room_id).cloned(),
+            };
+            if let Some(scrubbing) = &scrubbing {
+                records = scrubbing.records(records);
+            }
+            if let Err(e) = store.save(records).await {
+                warn!("Failed to store the records of room {}: {}", room_id, e);
+            }
+        }
+    }
+
+    /// Write a webhook delivery to the store, if any
+    pub async fn store_delivery(&self, delivery: WebhookDelivery) {
+        if let Some(store) = self.store.get()
+            && let Err(e) = store.save_delivery(delivery).await
+        {
+            warn!("Failed to store a webhook delivery: {}", e);
+        }
+    }
+
+    /// Write a background job to the store, if any
+    pub async fn store_job(&self, job: Job) {
+        if let Some(store) = self.store.get()
+            && let Err(e) = store.save_job(job).await
+        {
+            warn!("Failed to store a job: {}", e);
+        }
+    }
+
+    /// Delete stored records of purged rooms
+    pub async fn forget_stored(&self, room_ids: Vec<String>) {
+        if let Some(store) = self.store.get()
+            && let Err(e) = store.delete(room_ids).await
+        {
+            warn!("Failed to delete stored records: {}", e);
+        }
+    }
+
+    /// Delete stored records past retention, including those of rooms
+    /// restored at startup, which never reach the archive
+    pub async fn prune_stored(&self, before: u64) {
+        if let Some(store) = self.store.get()
+            && let Err(e) = store.prune(before).await
+        {
+            warn!("Failed to prune stored records: {}", e);
+        }
+    }
+}
+
+/// Spawn the task reading back records other instances save, if a store
+/// is attached
+pub fn spawn_record_sync(state: AppState) {
+    if state.store.get().is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RECORD_SYNC_INTERVAL);
+        interval.tick().await;
+        loop {
+            let since = unix_timestamp();
+            interval.tick().await;
+            // Saves made while the last read ran are read again
+            state
+                .sync_stored_records(since.saturating_sub(RECORD_SYNC_INTERVAL.as_secs()))
+                .await;
+        }
+    });
+}
@@ -0,0 +1,210 @@
+//! Personal meeting rooms
+//!
+//! With `personal_rooms` configured, every holder of a user token (scope
+//! `user`) has a room of its own. Its ID and dial-in code are derived from
+//! the token's `sub` and `jwt_secret`, so the link stays the same across
+//! restarts, and cleanup never reaps it. `GET /api/personal-room` opens it.
+//!
+//! Nobody starts the meeting but the owner: while the room is empty, anyone
+//! else who opens it is told `waiting_for_host` and waits in a lobby, which
+//! is the room's waiting line. When the owner connects with its user token
+//! the lobby is let in in arrival order. The meeting goes on if the owner
+//! leaves early; the lobby closes again once the room is empty.
+
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use tracing::info;
+use uuid::Builder;
+
+use crate::join_queue::{Admission, JoinQueueConfig};
+use crate::models::{CreateRoomResponse, RoomMetadata, RoomMode, WsMessage};
+use crate::state::{AppState, DIAL_CODE_DIGITS, PeerSender, Room};
+use crate::token::{UserAuth, secret_eq};
+
+/// Limits of a personal room's lobby
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PersonalRoomsConfig {
+    /// Guests that may wait for the owner; later ones are rejected
+    pub max_waiting: usize,
+    /// Seconds a guest may wait before it is turned away
+    pub max_wait_secs: u64,
+}
+
+impl Default for PersonalRoomsConfig {
+    fn default() -> Self {
+        Self {
+            max_waiting: 20,
+            max_wait_secs: 600,
+        }
+    }
+}
+
+impl PersonalRoomsConfig {
+    pub fn lobby(&self) -> JoinQueueConfig {
+        JoinQueueConfig {
+            max_waiting: self.max_waiting,
+            max_wait_secs: self.max_wait_secs,
+        }
+    }
+}
+
+/// A user's room, as remembered while it is not running
+#[derive(Debug, Clone)]
+pub struct PersonalRoom {
+    pub owner: String,
+    pub dial_code: String,
+}
+
+impl PersonalRoom {
+    /// The personal room of `owner` and its ID
+    pub fn of(secret: &str, owner: &str) -> (String, Self) {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(b"personal-room:");
+        mac.update(owner.as_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        let (id, code) = digest.split_at(16);
+        let id = Builder::from_random_bytes(id.try_into().expect("16 bytes"));
+        let code = u128::from_be_bytes(code.try_into().expect("16 bytes"));
+        let room = Self {
+            owner: owner.to_string(),
+            dial_code: format!(
+                "{:0width$}",
+                code % 10u128.pow(DIAL_CODE_DIGITS as u32),
+                width = DIAL_CODE_DIGITS
+            ),
+        };
+        (id.into_uuid().to_string(), room)
+    }
+
+    /// A fresh room for the owner
+    pub fn start(&self) -> Room {
+        let mut room = Room::new();
+        room.owner = Some(self.owner.clone());
+        room.dial_code = self.dial_code.clone();
+        room
+    }
+}
+
+impl Room {
+    /// Whether this is a personal room nobody has started yet
+    pub fn awaits_owner(&self) -> bool {
+        self.owner.is_some() && self.peers.is_empty()
+    }
+
+    /// Whether someone may enter while the room awaits its owner: the owner
+    /// itself, or a peer holding a slot
+    pub fn lets_in_early(&self, identity: Option<&str>, token: Option<&str>) -> bool {
+        (identity.is_some() && identity == self.owner.as_deref())
+            || self
+                .reservations
+                .iter()
+                .any(|r| token.is_some_and(|t| secret_eq(&r.token, t)))
+    }
+
+    /// Put a guest in the lobby until the owner arrives
+    pub fn wait_for_owner(
+        &mut self,
+        peer_id: String,
+        sender: PeerSender,
+        lobby: Option<&JoinQueueConfig>,
+    ) -> Result<Admission, &'static str> {
+        let lobby = lobby.ok_or("The room's owner has not arrived yet")?;
+        if self.waiting.len() >= lobby.max_waiting {
+            return Err("The lobby is full");
+        }
+        let _ = sender.send(WsMessage::WaitingForHost);
+        self.enqueue(peer_id, sender, lobby)
+    }
+}
+
+impl AppState {
+    /// The personal room `room_id`, if it is one
+    ///
+    /// The owner connecting with its user token registers the room, so its
+    /// link works after a restart before anyone opens it through the API.
+    pub async fn personal_room(&self, room_id: &str, token: Option<&str>) -> Option<PersonalRoom> {
+        let config = self.config();
+        config.personal_rooms.as_ref()?;
+        if let Some(room) = self.personal_rooms.lock().await.get(room_id) {
+            return Some(room.clone());
+        }
+        let owner = self.user_of(token)?;
+        let (id, room) = PersonalRoom::of(config.jwt_secret.as_deref()?, &owner);
+        if id != room_id {
+            return None;
+        }
+        self.personal_rooms.lock().await.insert(id, room.clone());
+        Some(room)
+    }
+
+    /// Start `owner`'s personal room if needed, returning its ID and
+    /// dial-in code
+    pub async fn open_personal_room(&self, secret: &str, owner: &str) -> (String, String) {
+        let (room_id, room) = PersonalRoom::of(secret, owner);
+        self.personal_rooms
+            .lock()
+            .await
+            .insert(room_id.clone(), room.clone());
+        let dial_code = self
+            .start_room(
+                &room_id,
+                RoomMode::Interactive,
+                RoomMetadata::new(),
+                None,
+                || room.start(),
+            )
+            .await;
+        (room_id, dial_code)
+    }
+}
+
+/// Open the caller's personal meeting room
+///
+/// Authenticates with a user token as bearer. The room ID is the same on
+/// every call.
+#[utoipa::path(
+    get,
+    path = "/api/personal-room",
+    tag = "Rooms",
+    responses(
+        (status = 200, description = "The caller's room", body = CreateRoomResponse),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 404, description = "Personal rooms are not enabled")
+    )
+)]
+pub async fn personal_room(State(state): State<AppState>, UserAuth(owner): UserAuth) -> Response {
+    let config = state.config();
+    let Some(secret) = config
+        .jwt_secret
+        .as_deref()
+        .filter(|_| config.personal_rooms.is_some())
+    else {
+        return (StatusCode::NOT_FOUND, "Personal rooms are not enabled").into_response();
+    };
+
+    let (room_id, dial_code) = state.open_personal_room(secret, &owner).await;
+    info!("Opened the personal room of {}", owner);
+    let settings = state
+        .with_room(&room_id, |room| room.settings.clone())
+        .await
+        .unwrap_or_default();
+    Json(CreateRoomResponse {
+        ws_url: format!("/ws/{}", room_id),
+        links: config.deep_links(&room_id, None),
+        presenter_token: state.presenter_token(&room_id).await,
+        room_id,
+        settings,
+        dial_code,
+    })
+    .into_response()
+}
@@ -0,0 +1,115 @@
+//! Post-call landing page
+//!
+//! When a peer hangs up, or the room is closed by an operator or reaches its
+//! maximum duration, the server sends it `call_ended` with a `redirect` to
+//! `/room/{id}/ended`. That page shows how long the call lasted, a button to
+//! rejoin while the room is still running, and the end-of-call survey,
+//! posted to the room's feedback endpoint. Setting `post_call_page` to false
+//! leaves the redirect out, for embedders with their own end screen.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::deep_links::{escape_html, url_safe};
+use crate::models::WsMessage;
+use crate::state::{AppState, Room, unix_timestamp};
+
+/// Post-call page of a room, for `peer_id`
+fn ended_url(room_id: &str, peer_id: &str) -> String {
+    format!("/room/{}/ended?peer_id={}", room_id, peer_id)
+}
+
+impl WsMessage {
+    /// Tell a peer its call is over, with the post-call page if enabled
+    pub fn call_ended(room_id: &str, peer_id: &str, page: bool) -> Self {
+        WsMessage::CallEnded {
+            can_redial: false,
+            redirect: page.then(|| ended_url(room_id, peer_id)),
+        }
+    }
+}
+
+impl Room {
+    /// Tell every peer the call is over before the room closes
+    pub fn end_call_for_all(&self, room_id: &str, page: bool) {
+        for peer in &self.peers {
+            self.send_to(&peer.id, WsMessage::call_ended(room_id, &peer.id, page));
+        }
+    }
+}
+
+/// A duration as `h:mm:ss`, or `m:ss` under an hour
+fn format_duration(secs: u64) -> String {
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match h {
+        0 => format!("{}:{:02}", m, s),
+        h => format!("{}:{:02}:{:02}", h, m, s),
+    }
+}
+
+/// Query parameters of the post-call page
+#[derive(Debug, Deserialize)]
+pub struct EndedQuery {
+    /// The peer the page is for, to attribute feedback and its duration
+    pub peer_id: Option<String>,
+    /// Room token to rejoin with
+    pub token: Option<String>,
+}
+
+impl AppState {
+    /// How long a call lasted, up to `peer_id` leaving if it has, or so far
+    pub async fn call_duration(&self, room_id: &str, peer_id: Option<&str>) -> Option<u64> {
+        let calls = self.calls.lock().await;
+        let call = calls.get(room_id)?;
+        let left = call
+            .disconnects
+            .iter()
+            .rev()
+            .find(|d| Some(d.peer_id.as_str()) == peer_id)
+            .map(|d| d.at);
+        let end = left.or(call.ended_at).unwrap_or_else(unix_timestamp);
+        Some(end.saturating_sub(call.started_at))
+    }
+}
+
+/// Page shown to peers once their call has ended
+pub async fn ended_page(
+    Path(room_id): Path<String>,
+    Query(query): Query<EndedQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    if Uuid::parse_str(&room_id).is_err() {
+        return (StatusCode::BAD_REQUEST, "Invalid room ID format").into_response();
+    }
+    if !state.config().post_call_page {
+        return (StatusCode::NOT_FOUND, "Post-call page not enabled").into_response();
+    }
+    // Anything else did not come from us
+    let peer_id = query.peer_id.filter(|p| url_safe(p));
+    let token = query.token.filter(|t| url_safe(t));
+
+    let duration = match state.call_duration(&room_id, peer_id.as_deref()).await {
+        Some(secs) => format!("Your call lasted {}.", format_duration(secs)),
+        None => "Your call has ended.".to_string(),
+    };
+    let running = state.rooms.lock().await.contains_key(&room_id);
+    let rejoin = match (running, token) {
+        (false, _) => String::new(),
+        (true, Some(token)) => format!("/room/{}?token={}", room_id, token),
+        (true, None) => format!("/room/{}", room_id),
+    };
+    let html = include_str!("../static/ended.html")
+        .replace("{{ROOM_ID}}", &room_id)
+        .replace(
+            "{{PEER_ID}}",
+            &escape_html(peer_id.as_deref().unwrap_or("")),
+        )
+        .replace("{{DURATION}}", &duration)
+        .replace("{{REJOIN_URL}}", &escape_html(&rejoin));
+    Html(state.assets.link(&html)).into_response()
+}
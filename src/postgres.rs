@@ -0,0 +1,628 @@
+//! Postgres record store
+//!
+//! Keeps each room's records in one `room_records` row, with the call
+//! record, transcript and timeline as JSONB next to the call's start and
+//! end for querying, and the words the room can be found by in a full-text
+//! index. Running rooms are shared as a [`RoomStore`]: their
+//! snapshots in `rooms`, their claims in `room_homes`, each instance's
+//! last heartbeat in `instances`, and chat as it is sent in
+//! `chat_messages`. Issued API keys are kept, by their hash, in `api_keys`.
+//! The schema ships with the crate under
+//! `migrations/postgres` and is brought up to date on connect; instances
+//! starting together take turns through an advisory lock. Queries go
+//! through a connection pool, which replaces connections the database
+//! drops, and connections use TLS as the URL's `sslmode` asks.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use sqlx::postgres::{PgConnectOptions, PgConnection, PgPool, PgPoolOptions, PgRow};
+use sqlx::types::Json;
+use sqlx::{Connection, Row};
+use tracing::info;
+
+use crate::api_keys::KeyStore;
+use crate::models::{
+    ApiKey, CallRecord, DeliveryStatus, Job, JobStatus, RoomTimeline, RoomTranscript,
+    TranscriptEntry, TranscriptKind, WebhookDelivery,
+};
+use crate::persistence::{RecordStore, RoomRecords};
+use crate::room_store::{RoomSnapshot, RoomStore};
+use crate::search::{MAX_STORED_CANDIDATES, search_words};
+use crate::state::unix_timestamp;
+
+/// Schema changes, applied in order
+const MIGRATIONS: &[(i32, &str)] = &[
+    (
+        1,
+        include_str!("../migrations/postgres/0001_room_records.sql"),
+    ),
+    (
+        2,
+        include_str!("../migrations/postgres/0002_webhook_outbox.sql"),
+    ),
+    (3, include_str!("../migrations/postgres/0003_jobs.sql")),
+    (4, include_str!("../migrations/postgres/0004_rooms.sql")),
+    (5, include_str!("../migrations/postgres/0005_chat.sql")),
+    (6, include_str!("../migrations/postgres/0006_api_keys.sql")),
+    (7, include_str!("../migrations/postgres/0007_search.sql")),
+];
+
+/// Key of the advisory lock held while migrating
+const MIGRATION_LOCK: i64 = 0x0061_7869_2d76_6964;
+
+/// Postgres connection settings
+#[derive(Debug, Clone, Deserialize)]
+pub struct PostgresConfig {
+    /// Connection URL, e.g.
+    /// `postgres://axi:secret@db/axi_vid?sslmode=verify-full`
+    pub url: String,
+    /// Most connections kept open at once
+    #[serde(default = "default_max_connections")]
+    pub max_connections: u32,
+    /// Seconds a query may wait for a free connection
+    #[serde(default = "default_acquire_timeout_secs")]
+    pub acquire_timeout_secs: u64,
+}
+
+fn default_max_connections() -> u32 {
+    10
+}
+
+fn default_acquire_timeout_secs() -> u64 {
+    10
+}
+
+/// Room records in Postgres
+#[derive(Debug, Clone)]
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    /// Connect and migrate the schema
+    pub async fn connect(config: &PostgresConfig) -> Result<Self, String> {
+        let options = PgConnectOptions::from_str(&config.url)
+            .map_err(|e| format!("Invalid Postgres URL: {}", e))?;
+        let pool = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+            .connect_with(options)
+            .await
+            .map_err(|e| format!("Failed to connect to Postgres: {}", e))?;
+        let store = Self { pool };
+        store
+            .migrate()
+            .await
+            .map_err(|e| format!("Migration failed: {}", e))?;
+        Ok(store)
+    }
+
+    async fn migrate(&self) -> Result<(), sqlx::Error> {
+        // Advisory locks belong to a session, so hold one connection
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(MIGRATION_LOCK)
+            .execute(&mut *conn)
+            .await?;
+        let result = apply_migrations(&mut conn).await;
+        sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(MIGRATION_LOCK)
+            .execute(&mut *conn)
+            .await?;
+        result
+    }
+}
+
+async fn apply_migrations(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version integer PRIMARY KEY,
+            applied_at bigint NOT NULL
+        )",
+    )
+    .execute(&mut *conn)
+    .await?;
+    for (version, sql) in MIGRATIONS {
+        let applied = sqlx::query("SELECT 1 FROM schema_migrations WHERE version = $1")
+            .bind(version)
+            .fetch_optional(&mut *conn)
+            .await?;
+        if applied.is_some() {
+            continue;
+        }
+        info!("Applying Postgres migration {}", version);
+        let mut tx = conn.begin().await?;
+        sqlx::raw_sql(sql).execute(&mut *tx).await?;
+        sqlx::query("INSERT INTO schema_migrations VALUES ($1, $2)")
+            .bind(version)
+            .bind(unix_timestamp() as i64)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+/// The records in a `room_id, call, transcript, timeline` row
+fn room_records(row: &PgRow) -> Result<RoomRecords, String> {
+    Ok(RoomRecords {
+        room_id: row.try_get(0).map_err(|e| e.to_string())?,
+        call: row
+            .try_get::<Option<Json<CallRecord>>, _>(1)
+            .map_err(|e| e.to_string())?
+            .map(|j| j.0),
+        transcript: row
+            .try_get::<Option<Json<RoomTranscript>>, _>(2)
+            .map_err(|e| e.to_string())?
+            .map(|j| j.0),
+        timeline: row
+            .try_get::<Option<Json<RoomTimeline>>, _>(3)
+            .map_err(|e| e.to_string())?
+            .map(|j| j.0),
+    })
+}
+
+impl RecordStore for PostgresStore {
+    fn save(&self, records: RoomRecords) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let started_at = records.call.as_ref().map(|c| c.started_at as i64);
+            let ended_at = records
+                .call
+                .as_ref()
+                .and_then(|c| c.ended_at)
+                .map(|t| t as i64);
+            let words = search_words(
+                records.call.as_ref().map(|c| &c.metadata),
+                records.transcript.as_ref(),
+            );
+            sqlx::query(
+                "INSERT INTO room_records
+                    (room_id, started_at, ended_at, call, transcript, timeline, saved_at,
+                     search_words)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                 ON CONFLICT (room_id) DO UPDATE SET
+                    started_at = EXCLUDED.started_at,
+                    ended_at = EXCLUDED.ended_at,
+                    call = EXCLUDED.call,
+                    transcript = EXCLUDED.transcript,
+                    timeline = EXCLUDED.timeline,
+                    saved_at = EXCLUDED.saved_at,
+                    search_words = EXCLUDED.search_words",
+            )
+            .bind(&records.room_id)
+            .bind(started_at)
+            .bind(ended_at)
+            .bind(records.call.as_ref().map(Json))
+            .bind(records.transcript.as_ref().map(Json))
+            .bind(records.timeline.as_ref().map(Json))
+            .bind(unix_timestamp() as i64)
+            .bind(&words)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+        })
+    }
+
+    fn load(&self, since: u64) -> BoxFuture<'_, Result<Vec<RoomRecords>, String>> {
+        Box::pin(async move {
+            let rows = sqlx::query(
+                "SELECT room_id, call, transcript, timeline FROM room_records
+                 WHERE saved_at >= $1",
+            )
+            .bind(since as i64)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+            rows.iter().map(room_records).collect()
+        })
+    }
+
+    fn search(
+        &self,
+        terms: Vec<String>,
+        tenant: Option<String>,
+    ) -> BoxFuture<'_, Result<Vec<RoomRecords>, String>> {
+        Box::pin(async move {
+            // Terms are alphanumeric, so cannot break out of the query
+            let query: Vec<String> = terms.iter().map(|t| format!("{}:*", t)).collect();
+            let rows = sqlx::query(
+                "SELECT room_id, call, transcript, timeline FROM room_records
+                 WHERE ($2::text IS NULL OR call->>'tenant' = $2)
+                   AND search_vector @@ to_tsquery('simple', $1)
+                 ORDER BY saved_at DESC
+                 LIMIT $3",
+            )
+            .bind(query.join(" & "))
+            .bind(&tenant)
+            .bind(MAX_STORED_CANDIDATES as i64)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+            rows.iter().map(room_records).collect()
+        })
+    }
+
+    fn delete(&self, room_ids: Vec<String>) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            for sql in [
+                "DELETE FROM room_records WHERE room_id = ANY($1)",
+                "DELETE FROM chat_messages WHERE room_id = ANY($1)",
+            ] {
+                sqlx::query(sql)
+                    .bind(&room_ids)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        })
+    }
+
+    fn prune(&self, before: u64) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let before = before as i64;
+            for sql in [
+                "DELETE FROM room_records WHERE saved_at < $1",
+                "DELETE FROM chat_messages WHERE sent_at < $1",
+                "DELETE FROM webhook_deliveries WHERE status <> 'pending' AND created_at < $1",
+                "DELETE FROM jobs WHERE status IN ('done', 'failed') AND created_at < $1",
+            ] {
+                sqlx::query(sql)
+                    .bind(before)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        })
+    }
+
+    fn save_delivery(&self, delivery: WebhookDelivery) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let status = match delivery.status {
+                DeliveryStatus::Pending => "pending",
+                DeliveryStatus::Delivered => "delivered",
+                DeliveryStatus::Failed => "failed",
+            };
+            sqlx::query(
+                "INSERT INTO webhook_deliveries (id, room_id, status, created_at, delivery)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (id) DO UPDATE SET
+                    status = EXCLUDED.status,
+                    delivery = EXCLUDED.delivery",
+            )
+            .bind(&delivery.event.id)
+            .bind(&delivery.event.room_id)
+            .bind(status)
+            .bind(delivery.event.at as i64)
+            .bind(Json(&delivery))
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+        })
+    }
+
+    fn load_deliveries(&self) -> BoxFuture<'_, Result<Vec<WebhookDelivery>, String>> {
+        Box::pin(async move {
+            sqlx::query_scalar::<_, Json<WebhookDelivery>>(
+                "SELECT delivery FROM webhook_deliveries ORDER BY seq",
+            )
+            .fetch_all(&self.pool)
+            .await
+            .map(|rows| rows.into_iter().map(|j| j.0).collect())
+            .map_err(|e| e.to_string())
+        })
+    }
+
+    fn save_job(&self, job: Job) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let status = match job.status {
+                JobStatus::Pending => "pending",
+                JobStatus::Running => "running",
+                JobStatus::Done => "done",
+                JobStatus::Failed => "failed",
+            };
+            sqlx::query(
+                "INSERT INTO jobs (id, status, created_at, job)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (id) DO UPDATE SET
+                    status = EXCLUDED.status,
+                    job = EXCLUDED.job",
+            )
+            .bind(&job.id)
+            .bind(status)
+            .bind(job.created_at as i64)
+            .bind(Json(&job))
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+        })
+    }
+
+    fn load_jobs(&self) -> BoxFuture<'_, Result<Vec<Job>, String>> {
+        Box::pin(async move {
+            sqlx::query_scalar::<_, Json<Job>>("SELECT job FROM jobs ORDER BY seq")
+                .fetch_all(&self.pool)
+                .await
+                .map(|rows| rows.into_iter().map(|j| j.0).collect())
+                .map_err(|e| e.to_string())
+        })
+    }
+}
+
+impl RoomStore for PostgresStore {
+    fn save_room(&self, room_id: String, room: RoomSnapshot) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            sqlx::query(
+                "INSERT INTO rooms (room_id, room, saved_at) VALUES ($1, $2, $3)
+                 ON CONFLICT (room_id) DO UPDATE SET
+                    room = EXCLUDED.room,
+                    saved_at = EXCLUDED.saved_at",
+            )
+            .bind(&room_id)
+            .bind(Json(&room))
+            .bind(unix_timestamp() as i64)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+        })
+    }
+
+    fn load_room(&self, room_id: String) -> BoxFuture<'_, Result<Option<RoomSnapshot>, String>> {
+        Box::pin(async move {
+            sqlx::query_scalar::<_, Json<RoomSnapshot>>("SELECT room FROM rooms WHERE room_id = $1")
+                .bind(&room_id)
+                .fetch_optional(&self.pool)
+                .await
+                .map(|room| room.map(|j| j.0))
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    fn delete_room(&self, room_id: String) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            for sql in [
+                "DELETE FROM rooms WHERE room_id = $1",
+                "DELETE FROM room_homes WHERE room_id = $1",
+            ] {
+                sqlx::query(sql)
+                    .bind(&room_id)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        })
+    }
+
+    fn claim_room(
+        &self,
+        room_id: String,
+        instance: String,
+        ttl_secs: u64,
+    ) -> BoxFuture<'_, Result<String, String>> {
+        Box::pin(async move {
+            let now = unix_timestamp();
+            let alive_since = now.saturating_sub(ttl_secs) as i64;
+            // A claim released between the two statements is tried again
+            for _ in 0..2 {
+                let claimed = sqlx::query_scalar::<_, String>(
+                    "INSERT INTO room_homes (room_id, home, claimed_at) VALUES ($1, $2, $3)
+                     ON CONFLICT (room_id) DO UPDATE SET
+                        home = EXCLUDED.home,
+                        claimed_at = EXCLUDED.claimed_at
+                     WHERE room_homes.home = EXCLUDED.home
+                        OR NOT EXISTS (
+                            SELECT 1 FROM instances
+                            WHERE url = room_homes.home AND seen_at >= $4
+                        )
+                     RETURNING home",
+                )
+                .bind(&room_id)
+                .bind(&instance)
+                .bind(now as i64)
+                .bind(alive_since)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| e.to_string())?;
+                if let Some(home) = claimed {
+                    return Ok(home);
+                }
+                let held = sqlx::query_scalar::<_, String>(
+                    "SELECT home FROM room_homes WHERE room_id = $1",
+                )
+                .bind(&room_id)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| e.to_string())?;
+                if let Some(home) = held {
+                    return Ok(home);
+                }
+            }
+            Err("The room's claim keeps changing".to_string())
+        })
+    }
+
+    fn heartbeat(
+        &self,
+        instance: String,
+        running: Vec<String>,
+        ttl_secs: u64,
+    ) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            let now = unix_timestamp();
+            sqlx::query(
+                "INSERT INTO instances (url, seen_at) VALUES ($1, $2)
+                 ON CONFLICT (url) DO UPDATE SET seen_at = EXCLUDED.seen_at",
+            )
+            .bind(&instance)
+            .bind(now as i64)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+            // Claims made for peers that never got in
+            sqlx::query(
+                "DELETE FROM room_homes
+                 WHERE home = $1 AND room_id <> ALL($2) AND claimed_at < $3",
+            )
+            .bind(&instance)
+            .bind(&running)
+            .bind(now.saturating_sub(ttl_secs) as i64)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+        })
+    }
+
+    fn release_rooms(&self, instance: String) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            for sql in [
+                "DELETE FROM room_homes WHERE home = $1",
+                "DELETE FROM instances WHERE url = $1",
+            ] {
+                sqlx::query(sql)
+                    .bind(&instance)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        })
+    }
+
+    fn save_chat(
+        &self,
+        room_id: String,
+        entry: TranscriptEntry,
+    ) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            sqlx::query(
+                "INSERT INTO chat_messages (room_id, peer_id, sent_at, message)
+                 VALUES ($1, $2, $3, $4)",
+            )
+            .bind(&room_id)
+            .bind(&entry.peer_id)
+            .bind(entry.timestamp as i64)
+            .bind(&entry.text)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+        })
+    }
+
+    fn load_chat(&self, room_id: String) -> BoxFuture<'_, Result<Vec<TranscriptEntry>, String>> {
+        Box::pin(async move {
+            let rows = sqlx::query(
+                "SELECT peer_id, sent_at, message FROM chat_messages
+                 WHERE room_id = $1 ORDER BY seq",
+            )
+            .bind(&room_id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+            rows.iter()
+                .map(|row| {
+                    Ok(TranscriptEntry {
+                        peer_id: row.try_get(0)?,
+                        timestamp: row.try_get::<i64, _>(1)? as u64,
+                        kind: TranscriptKind::Chat,
+                        text: row.try_get(2)?,
+                    })
+                })
+                .collect::<Result<_, sqlx::Error>>()
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    fn expire_chat(&self, before: u64) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            sqlx::query("DELETE FROM chat_messages WHERE sent_at < $1")
+                .bind(before as i64)
+                .execute(&self.pool)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+    }
+}
+
+/// An API key from its `api_keys` row
+fn api_key(row: &PgRow) -> Result<ApiKey, sqlx::Error> {
+    Ok(ApiKey {
+        id: row.try_get(0)?,
+        tenant: row.try_get(1)?,
+        label: row.try_get(2)?,
+        key: None,
+        created_by: row.try_get(3)?,
+        created_at: row.try_get::<i64, _>(4)? as u64,
+    })
+}
+
+impl KeyStore for PostgresStore {
+    fn save_key(&self, key: ApiKey, hash: String) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(async move {
+            sqlx::query(
+                "INSERT INTO api_keys (id, tenant, key_hash, label, created_by, created_at)
+                 VALUES ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(&key.id)
+            .bind(&key.tenant)
+            .bind(&hash)
+            .bind(&key.label)
+            .bind(&key.created_by)
+            .bind(key.created_at as i64)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+        })
+    }
+
+    fn key_tenant(&self, hash: String) -> BoxFuture<'_, Result<Option<String>, String>> {
+        Box::pin(async move {
+            sqlx::query_scalar("SELECT tenant FROM api_keys WHERE key_hash = $1")
+                .bind(&hash)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    fn list_keys(&self, tenant: String) -> BoxFuture<'_, Result<Vec<ApiKey>, String>> {
+        Box::pin(async move {
+            let rows = sqlx::query(
+                "SELECT id, tenant, label, created_by, created_at FROM api_keys
+                 WHERE tenant = $1",
+            )
+            .bind(&tenant)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| e.to_string())?;
+            rows.iter()
+                .map(api_key)
+                .collect::<Result<_, _>>()
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    fn delete_key(&self, tenant: String, id: String) -> BoxFuture<'_, Result<bool, String>> {
+        Box::pin(async move {
+            sqlx::query("DELETE FROM api_keys WHERE tenant = $1 AND id = $2")
+                .bind(&tenant)
+                .bind(&id)
+                .execute(&self.pool)
+                .await
+                .map(|done| done.rows_affected() > 0)
+                .map_err(|e| e.to_string())
+        })
+    }
+}
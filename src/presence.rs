@@ -0,0 +1,250 @@
+//! Presence and do-not-disturb
+//!
+//! A holder of a user token is online while it keeps a presence socket open
+//! at `/ws/presence?token=...`, from any number of devices. It starts out
+//! `available` and changes its status by sending `presence`; the new status
+//! is echoed to every one of its sockets. `offline` lets it appear offline.
+//! Other users look it up with `GET /api/presence/{user}` before calling.
+//!
+//! Invitations and calls are delivered over the presence socket, and only to
+//! users who are available: a user on do-not-disturb is not disturbed. Rung
+//! users answer calls over the same socket. Users with no socket open are
+//! reached through their mobile devices instead, if they registered any.
+
+use std::collections::HashMap;
+
+use axum::{
+    Json,
+    extract::{
+        Path, Query, State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::handlers::WsQuery;
+use crate::models::{LeaveReason, PresenceInfo, PresenceStatus, WsMessage};
+use crate::push::INVITE_PUSH_TTL_SECS;
+use crate::state::{AppState, unix_timestamp};
+use crate::token::UserAuth;
+
+/// Messages to one presence socket
+pub type PresenceSender = mpsc::UnboundedSender<WsMessage>;
+
+/// An online user
+#[derive(Debug)]
+pub struct UserPresence {
+    pub status: PresenceStatus,
+    /// Unix timestamp (seconds) of the last change
+    pub since: u64,
+    sockets: Vec<PresenceSender>,
+}
+
+/// Online users by token subject
+pub type PresenceMap = HashMap<String, UserPresence>;
+
+impl AppState {
+    /// Register a presence socket, returning the user's status
+    pub async fn go_online(&self, user: &str, socket: PresenceSender) -> PresenceStatus {
+        let mut presence = self.presence.lock().await;
+        let entry = presence
+            .entry(user.to_string())
+            .or_insert_with(|| UserPresence {
+                status: PresenceStatus::Available,
+                since: unix_timestamp(),
+                sockets: Vec::new(),
+            });
+        entry.sockets.push(socket);
+        entry.status
+    }
+
+    /// Drop a presence socket; the user is offline once none is left
+    pub async fn go_offline(&self, user: &str, socket: &PresenceSender) {
+        let mut presence = self.presence.lock().await;
+        let Some(entry) = presence.get_mut(user) else {
+            return;
+        };
+        entry.sockets.retain(|s| !s.same_channel(socket));
+        if entry.sockets.is_empty() {
+            presence.remove(user);
+        }
+    }
+
+    /// Change an online user's status and tell all of its sockets
+    pub async fn set_presence(&self, user: &str, status: PresenceStatus) {
+        let mut presence = self.presence.lock().await;
+        let Some(entry) = presence.get_mut(user) else {
+            return;
+        };
+        entry.status = status;
+        entry.since = unix_timestamp();
+        for socket in &entry.sockets {
+            let _ = socket.send(WsMessage::Presence { status });
+        }
+    }
+
+    /// Look up a user's presence
+    pub async fn presence_of(&self, user: &str) -> PresenceInfo {
+        let presence = self.presence.lock().await;
+        let entry = presence
+            .get(user)
+            .filter(|p| p.status != PresenceStatus::Offline);
+        PresenceInfo {
+            user: user.to_string(),
+            status: entry.map(|p| p.status).unwrap_or_default(),
+            since: entry.map(|p| p.since),
+        }
+    }
+
+    /// Close every presence socket of a user, who goes offline
+    pub async fn sign_out(&self, user: &str) {
+        let Some(entry) = self.presence.lock().await.remove(user) else {
+            return;
+        };
+        for socket in &entry.sockets {
+            let _ = socket.send(WsMessage::Leave {
+                peer_id: None,
+                reason: Some(LeaveReason::Kicked),
+            });
+        }
+    }
+
+    /// Deliver `msg` to every socket of a user who is available
+    pub async fn notify_user(&self, user: &str, msg: WsMessage) -> Result<(), &'static str> {
+        let presence = self.presence.lock().await;
+        let entry = presence.get(user);
+        match entry.map(|p| p.status).unwrap_or_default() {
+            PresenceStatus::Available => {}
+            PresenceStatus::DoNotDisturb => return Err("User does not want to be disturbed"),
+            PresenceStatus::Offline => return Err("User is offline"),
+        }
+        for socket in entry.into_iter().flat_map(|p| &p.sockets) {
+            let _ = socket.send(msg.clone());
+        }
+        Ok(())
+    }
+
+    /// Deliver `msg` to every socket of a user, whatever its status
+    pub async fn tell_user(&self, user: &str, msg: WsMessage) {
+        let presence = self.presence.lock().await;
+        for socket in presence.get(user).into_iter().flat_map(|p| &p.sockets) {
+            let _ = socket.send(msg.clone());
+        }
+    }
+
+    /// Tell the invitees of a room who are available or reachable by push
+    /// about it
+    pub async fn send_invitations(&self, room_id: &str, invitees: &[String]) {
+        let config = self.config();
+        let url = format!(
+            "{}/room/{}",
+            config.public_url.trim_end_matches('/'),
+            room_id
+        );
+        for user in invitees {
+            let invite = WsMessage::RoomInvite {
+                room_id: room_id.to_string(),
+                url: url.clone(),
+            };
+            let Err(e) = self.notify_user(user, invite.clone()).await else {
+                info!("Invited {} into room {}", user, room_id);
+                continue;
+            };
+            let expires_at = unix_timestamp() + INVITE_PUSH_TTL_SECS;
+            match self.push_to_user(user, &invite, expires_at).await {
+                0 => debug!("Not inviting {} into room {}: {}", user, room_id, e),
+                _ => info!("Pushed invitation into room {} to {}", room_id, user),
+            }
+        }
+    }
+}
+
+/// Presence socket of the user named by `?token=`
+pub async fn presence_ws(
+    ws: WebSocketUpgrade,
+    Query(query): Query<WsQuery>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(user) = state.user_of(query.token.as_deref()) else {
+        return (StatusCode::UNAUTHORIZED, "User token required").into_response();
+    };
+    ws.on_upgrade(move |socket| handle_presence_socket(socket, user, state))
+}
+
+async fn handle_presence_socket(socket: WebSocket, user: String, state: AppState) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let status = state.go_online(&user, tx.clone()).await;
+    let _ = tx.send(WsMessage::Presence { status });
+    info!("{} is online", user);
+
+    let (mut ws_tx, mut ws_rx) = socket.split();
+    loop {
+        tokio::select! {
+            outgoing = rx.recv() => {
+                let Some(msg) = outgoing else {
+                    break;
+                };
+                let Ok(text) = serde_json::to_string(&msg) else {
+                    continue;
+                };
+                // A leave is only sent when the user was signed out
+                let signed_out = matches!(msg, WsMessage::Leave { .. });
+                if ws_tx.send(Message::Text(text.into())).await.is_err() || signed_out {
+                    break;
+                }
+            }
+            incoming = ws_rx.next() => match incoming {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<WsMessage>(&text) {
+                    Ok(WsMessage::Presence { status }) => state.set_presence(&user, status).await,
+                    Ok(WsMessage::AnswerCall { room_id, accept }) => {
+                        if let Err(e) = state.answer_call(&user, &room_id, accept).await {
+                            let _ = tx.send(WsMessage::error(e));
+                        }
+                    }
+                    Ok(WsMessage::Ping) => {
+                        let _ = tx.send(WsMessage::Pong);
+                    }
+                    Ok(_) => {
+                        let _ = tx.send(WsMessage::error(
+                            "Only presence updates and call answers are accepted here",
+                        ));
+                    }
+                    Err(e) => {
+                        warn!("Invalid presence message from {}: {}", user, e);
+                        let _ = tx.send(WsMessage::error("Invalid message format"));
+                    }
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    state.go_offline(&user, &tx).await;
+    info!("{} went offline", user);
+}
+
+/// Look up whether a user can be called
+#[utoipa::path(
+    get,
+    path = "/api/presence/{user}",
+    tag = "Presence",
+    params(
+        ("user" = String, Path, description = "The user's token subject")
+    ),
+    responses(
+        (status = 200, description = "The user's presence", body = PresenceInfo),
+        (status = 401, description = "Missing or invalid user token")
+    )
+)]
+pub async fn get_presence(
+    Path(user): Path<String>,
+    State(state): State<AppState>,
+    _auth: UserAuth,
+) -> Json<PresenceInfo> {
+    Json(state.presence_of(&user).await)
+}
@@ -0,0 +1,130 @@
+//! Screen-share slot
+//!
+//! A room has one screen-share slot, so two peers never share at once. A
+//! peer asks for it with `present_request`; under the `first_come` policy
+//! it gets the slot if nobody holds it, under `host` the host is asked and
+//! answers with `present_grant` (the host's own requests are granted at
+//! once). While the slot is taken, requests are turned down with
+//! `present_denied` naming the presenter. The slot is freed when the
+//! presenter sends `screen_share` with `active: false`, leaves, or the host
+//! switches screen sharing off; every change is broadcast as `screen_share`.
+
+use tracing::info;
+
+use crate::models::{PeerRole, Permission, PresentDeniedReason, PresentPolicy, WsMessage};
+use crate::state::{AppState, Room};
+
+impl Room {
+    /// Handle a peer's request for the screen-share slot
+    pub fn request_present(&mut self, peer_id: &str) -> Result<(), &'static str> {
+        self.check_permitted(peer_id, Permission::ScreenShare)?;
+        if self.sharing.as_deref() == Some(peer_id) {
+            return Ok(());
+        }
+        if self.sharing.is_some() {
+            self.deny_present(peer_id, PresentDeniedReason::SlotTaken);
+            return Ok(());
+        }
+        let host = self
+            .peers
+            .iter()
+            .find(|p| p.role == PeerRole::Host)
+            .map(|p| p.id.clone());
+        match (self.settings.present_policy, host) {
+            (PresentPolicy::FirstCome, _) => self.start_sharing(peer_id),
+            (PresentPolicy::Host, Some(host)) if host == peer_id => self.start_sharing(peer_id),
+            (PresentPolicy::Host, Some(host)) => {
+                if !self.present_requests.iter().any(|id| id == peer_id) {
+                    self.present_requests.push(peer_id.to_string());
+                }
+                let request = WsMessage::PresentRequest {
+                    peer_id: Some(peer_id.to_string()),
+                };
+                self.send_to(&host, request);
+            }
+            (PresentPolicy::Host, None) => self.deny_present(peer_id, PresentDeniedReason::NoHost),
+        }
+        Ok(())
+    }
+
+    /// Apply the host's answer to a request, or stop the current share
+    pub fn grant_present(
+        &mut self,
+        host_id: &str,
+        peer_id: &str,
+        granted: bool,
+    ) -> Result<(), &'static str> {
+        if !granted && self.sharing.as_deref() == Some(peer_id) {
+            info!("Host {} stopped the screen share of {}", host_id, peer_id);
+            self.stop_sharing();
+            return Ok(());
+        }
+        let pos = self
+            .present_requests
+            .iter()
+            .position(|id| id == peer_id)
+            .ok_or("That peer has not asked to present")?;
+        self.present_requests.remove(pos);
+        match (granted, self.sharing.is_some()) {
+            (false, _) => self.deny_present(peer_id, PresentDeniedReason::Declined),
+            (true, true) => self.deny_present(peer_id, PresentDeniedReason::SlotTaken),
+            (true, false) => self.start_sharing(peer_id),
+        }
+        Ok(())
+    }
+
+    /// Give up the slot held by `peer_id`
+    pub fn stop_presenting(&mut self, peer_id: &str) -> Result<(), &'static str> {
+        if self.sharing.as_deref() != Some(peer_id) {
+            return Err("You are not sharing your screen");
+        }
+        self.stop_sharing();
+        Ok(())
+    }
+
+    /// Free the slot, telling everyone who held it
+    pub fn stop_sharing(&mut self) {
+        if let Some(peer_id) = self.sharing.take() {
+            info!("Peer {} stopped sharing its screen", peer_id);
+            self.broadcast_to_all(&WsMessage::ScreenShare {
+                active: false,
+                peer_id: Some(peer_id),
+            });
+        }
+    }
+
+    fn start_sharing(&mut self, peer_id: &str) {
+        info!("Peer {} is sharing its screen", peer_id);
+        self.sharing = Some(peer_id.to_string());
+        self.broadcast_to_all(&WsMessage::ScreenShare {
+            active: true,
+            peer_id: Some(peer_id.to_string()),
+        });
+    }
+
+    fn deny_present(&self, peer_id: &str, reason: PresentDeniedReason) {
+        let denied = WsMessage::PresentDenied {
+            reason,
+            presenter: self.sharing.clone(),
+        };
+        self.send_to(peer_id, denied);
+    }
+}
+
+impl AppState {
+    /// Handle a peer's request for the screen-share slot
+    pub async fn request_present(&self, room_id: &str, peer_id: &str) -> Result<(), &'static str> {
+        let peer_id = peer_id.to_string();
+        self.with_room(room_id, move |room| room.request_present(&peer_id))
+            .await
+            .unwrap_or(Err("Room not found"))
+    }
+
+    /// Give up the screen-share slot
+    pub async fn stop_presenting(&self, room_id: &str, peer_id: &str) -> Result<(), &'static str> {
+        let peer_id = peer_id.to_string();
+        self.with_room(room_id, move |room| room.stop_presenting(&peer_id))
+            .await
+            .unwrap_or(Err("Room not found"))
+    }
+}
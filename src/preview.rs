@@ -0,0 +1,190 @@
+//! Preview images of running calls
+//!
+//! Admin dashboards and "rejoin your meeting" cards show a still of the
+//! call. The server does not decode media, so a recorder in the room
+//! captures a keyframe every so often and puts it as a JPEG to
+//! `PUT /api/room/{id}/preview.jpg`, authenticated with its recorder token.
+//! Previews are off unless the host switches on the `preview` room control;
+//! switching it off drops the current image. The latest image is served
+//! from `GET /api/room/{id}/preview.jpg` to an operator or to anyone who
+//! may join the room, and goes away with the room. With
+//! [`images`](crate::images) configured, each upload is scaled, stripped of
+//! metadata and converted before it is kept, so the image served may be a
+//! WebP despite the path.
+
+use axum::{
+    body::{Bytes, to_bytes},
+    extract::{Path, Query, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::admin::is_admin_token;
+use crate::images::process_jpeg;
+use crate::state::{AppState, unix_timestamp};
+
+/// Largest preview image accepted
+pub const MAX_PREVIEW_BYTES: usize = 1024 * 1024;
+
+/// Start of every JPEG file
+const JPEG_MAGIC: [u8; 3] = [0xFF, 0xD8, 0xFF];
+
+/// A room's latest preview image
+#[derive(Debug, Clone)]
+pub struct Preview {
+    pub image: Bytes,
+    pub content_type: &'static str,
+    /// Unix timestamp of the upload
+    pub captured_at: u64,
+}
+
+impl AppState {
+    /// Replace a room's preview image
+    pub async fn set_preview(
+        &self,
+        room_id: &str,
+        jpeg: Bytes,
+    ) -> Result<(), (StatusCode, &'static str)> {
+        let preview = match self.config().images.clone() {
+            Some(images) => Preview {
+                image: process_jpeg(&images, &jpeg).await?,
+                content_type: images.format.content_type(),
+                captured_at: unix_timestamp(),
+            },
+            None => Preview {
+                image: jpeg,
+                content_type: "image/jpeg",
+                captured_at: unix_timestamp(),
+            },
+        };
+        self.with_room(room_id, move |room| {
+            if !room.settings.controls.preview {
+                return Err((StatusCode::FORBIDDEN, "Previews not enabled by the host"));
+            }
+            room.preview = Some(preview);
+            Ok(())
+        })
+        .await
+        .unwrap_or(Err((StatusCode::NOT_FOUND, "Room not found")))
+    }
+
+    /// A running room's latest preview image
+    pub async fn preview(&self, room_id: &str) -> Option<Preview> {
+        self.with_room(room_id, |room| room.preview.clone())
+            .await
+            .flatten()
+    }
+}
+
+/// Bearer token from the request headers
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// A Unix timestamp as an HTTP date
+fn http_date(secs: u64) -> String {
+    chrono::DateTime::from_timestamp(secs as i64, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// Query parameters for fetching a preview
+#[derive(Debug, Deserialize)]
+pub struct PreviewQuery {
+    /// Room token, for `<img>` tags that cannot send headers
+    pub token: Option<String>,
+}
+
+/// Upload a room's preview image
+///
+/// Authenticated with a recorder token for the room. Accepted only while
+/// the host has previews switched on.
+#[utoipa::path(
+    put,
+    path = "/api/room/{room_id}/preview.jpg",
+    tag = "Rooms",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room")
+    ),
+    request_body(content = Vec<u8>, description = "Keyframe as a JPEG", content_type = "image/jpeg"),
+    responses(
+        (status = 204, description = "Preview replaced"),
+        (status = 400, description = "Not a JPEG, or not one that can be processed"),
+        (status = 401, description = "Missing or invalid recorder token"),
+        (status = 403, description = "Previews not enabled by the host"),
+        (status = 404, description = "Room not found"),
+        (status = 413, description = "Image too large")
+    )
+)]
+pub async fn put_preview(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+    request: Request,
+) -> Response {
+    if !state.is_recorder_token(&room_id, bearer(request.headers())) {
+        return (StatusCode::UNAUTHORIZED, "Recorder token required").into_response();
+    }
+    let Ok(jpeg) = to_bytes(request.into_body(), MAX_PREVIEW_BYTES).await else {
+        return (StatusCode::PAYLOAD_TOO_LARGE, "Image too large").into_response();
+    };
+    if !jpeg.starts_with(&JPEG_MAGIC) {
+        return (StatusCode::BAD_REQUEST, "Not a JPEG").into_response();
+    }
+    let size = jpeg.len();
+    match state.set_preview(&room_id, jpeg).await {
+        Ok(()) => {
+            debug!("Preview of room {} updated ({} bytes)", room_id, size);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Latest preview image of a running room
+///
+/// Served to operators and to anyone holding a token that may join the
+/// room, as a Bearer header or `?token=`.
+#[utoipa::path(
+    get,
+    path = "/api/room/{room_id}/preview.jpg",
+    tag = "Rooms",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room"),
+        ("token" = Option<String>, Query, description = "Room token, instead of a Bearer header")
+    ),
+    responses(
+        (status = 200, description = "The preview", body = Vec<u8>, content_type = "image/jpeg"),
+        (status = 401, description = "A valid room or admin token is required"),
+        (status = 404, description = "No preview for this room")
+    )
+)]
+pub async fn get_preview(
+    Path(room_id): Path<String>,
+    Query(query): Query<PreviewQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    let token = bearer(&headers).or(query.token.as_deref());
+    let admin = token.is_some_and(|t| is_admin_token(&state.config(), t));
+    if !admin && !state.may_join(&room_id, token).await {
+        return (StatusCode::UNAUTHORIZED, "A valid room token is required").into_response();
+    }
+    let Some(preview) = state.preview(&room_id).await else {
+        return (StatusCode::NOT_FOUND, "No preview").into_response();
+    };
+    (
+        [
+            (header::CONTENT_TYPE, preview.content_type.to_string()),
+            (header::CACHE_CONTROL, "no-cache".to_string()),
+            (header::LAST_MODIFIED, http_date(preview.captured_at)),
+        ],
+        preview.image,
+    )
+        .into_response()
+}
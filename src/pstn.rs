@@ -0,0 +1,178 @@
+//! PSTN dial-in via Twilio Programmable Voice webhooks
+//!
+//! Callers dial the configured Twilio number, enter a room's numeric dial
+//! code and are then connected with `<Dial><Sip>` to the SIP gateway, which
+//! joins the call leg to the room as an audio-only virtual peer. The web
+//! peers are told a phone participant is on the way via `PhoneParticipant`.
+//! Webhooks must carry a valid `X-Twilio-Signature`, and rooms that need a
+//! room token or an invitation cannot be dialed into.
+
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    extract::{Form, State},
+    http::{HeaderMap, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha1::Sha1;
+use tracing::{info, warn};
+
+use crate::models::WsMessage;
+use crate::state::{AppState, DIAL_CODE_DIGITS};
+
+/// Twilio webhook settings
+#[derive(Debug, Clone, Deserialize)]
+pub struct TwilioConfig {
+    /// Account auth token used to verify `X-Twilio-Signature`
+    pub auth_token: String,
+    /// Public base URL Twilio posts to, e.g. `https://vid.example.com`
+    pub public_url: String,
+    /// SIP URI the caller is dialed into; `{room_id}` is substituted
+    #[serde(default = "default_sip_uri")]
+    pub sip_uri: String,
+}
+
+fn default_sip_uri() -> String {
+    "sip:{room_id}@localhost:5060".to_string()
+}
+
+/// Incoming call webhook: ask the caller for a dial code
+pub async fn twilio_voice(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    uri: Uri,
+    Form(params): Form<HashMap<String, String>>,
+) -> Response {
+    if let Err(status) = verify(&state, &headers, &uri, &params) {
+        return status.into_response();
+    }
+
+    info!(
+        "Incoming phone call {}",
+        params.get("CallSid").map(String::as_str).unwrap_or("?")
+    );
+    twiml(&format!(
+        "<Gather input=\"dtmf\" numDigits=\"{digits}\" action=\"/api/twilio/gather\" method=\"POST\">\
+         <Say>Welcome. Please enter your {digits} digit meeting code.</Say>\
+         </Gather>\
+         <Say>We did not receive a code. Goodbye.</Say>",
+        digits = DIAL_CODE_DIGITS
+    ))
+}
+
+/// Gather webhook: map the entered code to a room and connect the caller
+pub async fn twilio_gather(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    uri: Uri,
+    Form(params): Form<HashMap<String, String>>,
+) -> Response {
+    if let Err(status) = verify(&state, &headers, &uri, &params) {
+        return status.into_response();
+    }
+    let config = state.config();
+    let Some(config) = config.twilio.as_ref() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let digits = params.get("Digits").map(String::as_str).unwrap_or_default();
+    let Some(room_id) = state.find_room_by_dial_code(digits).await else {
+        return twiml(
+            "<Say>That code was not recognised.</Say>\
+             <Redirect method=\"POST\">/api/twilio/voice</Redirect>",
+        );
+    };
+
+    // The dial code is all a caller has, so it only opens rooms anyone
+    // with the link may join
+    if !state.may_join(&room_id, None).await || !state.is_invited(&room_id, None).await {
+        info!("Refusing phone dial-in to protected room {}", room_id);
+        return twiml("<Say>That meeting cannot be joined by phone. Goodbye.</Say>");
+    }
+
+    let (peer_count, capacity, _) = state.get_room_summary(&room_id).await;
+    if peer_count >= capacity {
+        return twiml("<Say>Sorry, that meeting is full. Goodbye.</Say>");
+    }
+
+    let caller = mask_number(params.get("From").map(String::as_str).unwrap_or_default());
+    info!("Phone caller {} connecting to room {}", caller, room_id);
+    state
+        .broadcast_to_room(&room_id, WsMessage::PhoneParticipant { caller })
+        .await;
+
+    let sip_uri = config.sip_uri.replace("{room_id}", &room_id);
+    twiml(&format!(
+        "<Say>Connecting you now.</Say><Dial><Sip>{}</Sip></Dial>",
+        xml_escape(&sip_uri)
+    ))
+}
+
+/// Check `X-Twilio-Signature` against the auth token
+fn verify(
+    state: &AppState,
+    headers: &HeaderMap,
+    uri: &Uri,
+    params: &HashMap<String, String>,
+) -> Result<(), StatusCode> {
+    let config = state.config();
+    let Some(config) = config.twilio.as_ref() else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let token = &config.auth_token;
+
+    let provided = headers
+        .get("X-Twilio-Signature")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| STANDARD.decode(v).ok());
+
+    // Signature covers the full URL followed by the sorted POST parameters
+    let mut payload = format!("{}{}", config.public_url.trim_end_matches('/'), uri);
+    for (key, value) in params.iter().collect::<BTreeMap<_, _>>() {
+        payload.push_str(key);
+        payload.push_str(value);
+    }
+    let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(token.as_bytes()) else {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    };
+    mac.update(payload.as_bytes());
+
+    match provided {
+        Some(signature) if mac.verify_slice(&signature).is_ok() => Ok(()),
+        _ => {
+            warn!("Rejected Twilio webhook with invalid signature");
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+}
+
+/// Hide all but the last four digits of a phone number
+fn mask_number(number: &str) -> String {
+    let digits: Vec<char> = number.chars().filter(char::is_ascii_digit).collect();
+    match digits.len() {
+        0 => "Unknown caller".to_string(),
+        n if n <= 4 => "Phone caller".to_string(),
+        n => format!("•••{}", digits[n - 4..].iter().collect::<String>()),
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn twiml(body: &str) -> Response {
+    (
+        [(header::CONTENT_TYPE, "application/xml")],
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Response>{}</Response>",
+            body
+        ),
+    )
+        .into_response()
+}
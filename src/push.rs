@@ -0,0 +1,612 @@
+//! Mobile push for calls and invitations
+//!
+//! Native apps cannot keep a presence socket open in the background, so
+//! they register their FCM registration token or APNs device token under
+//! `/api/push/tokens` with a user token. A directed call or room invite
+//! for a user with no presence socket open then rings each of their
+//! registered devices through the push service instead: the notification
+//! carries the same `incoming_call` or `room_invite` the socket would have,
+//! and the app answers over its presence socket once it is woken.
+//!
+//! Each push is a background job, retried until it expires with the ring
+//! (or after an hour, for invitations). Tokens the service reports as
+//! unregistered are dropped. APNs is signed in to with an ES256 provider
+//! token made from the `.p8` key; FCM with an OAuth access token exchanged
+//! for an RS256 assertion signed with the service account's key. Both are
+//! reused until they near expiry. Registrations are kept in memory, up to
+//! `MAX_PUSH_TOKENS` devices per user.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use ring::rand::SystemRandom;
+use ring::signature::{
+    ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, RSA_PKCS1_SHA256, RsaKeyPair,
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::{debug, info};
+
+use crate::models::{JobKind, PushPlatform, PushToken, PushTokenRequest, WsMessage};
+use crate::state::{AppState, unix_timestamp};
+use crate::token::UserAuth;
+
+/// Devices kept per user; registering another drops the oldest
+pub const MAX_PUSH_TOKENS: usize = 10;
+
+/// Longest device token accepted
+const MAX_PUSH_TOKEN_LEN: usize = 4096;
+
+/// How long an invitation is worth delivering, in seconds
+pub const INVITE_PUSH_TTL_SECS: u64 = 3600;
+
+/// How long one request to a push service may take
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long an APNs provider token is reused; Apple refuses ones older than
+/// an hour
+const APNS_TOKEN_TTL_SECS: u64 = 30 * 60;
+
+/// OAuth scope for sending FCM messages
+const FCM_SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+
+/// OAuth grant for exchanging a signed assertion
+pub(crate) const JWT_BEARER_GRANT: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+
+/// Push services to ring native apps through
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PushConfig {
+    /// Firebase Cloud Messaging, for Android; disabled when unset
+    pub fcm: Option<FcmConfig>,
+    /// Apple Push Notification service, for iOS; disabled when unset
+    pub apns: Option<ApnsConfig>,
+}
+
+/// FCM HTTP v1 credentials
+#[derive(Debug, Clone, Deserialize)]
+pub struct FcmConfig {
+    /// Service account key file (JSON) from the Firebase console
+    pub service_account: PathBuf,
+    /// FCM API base URL
+    #[serde(default = "default_fcm_url")]
+    pub url: String,
+}
+
+/// APNs token-based credentials
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApnsConfig {
+    /// Signing key (`.p8`) from the Apple developer account
+    pub key_file: PathBuf,
+    pub key_id: String,
+    pub team_id: String,
+    /// The app's bundle ID
+    pub topic: String,
+    /// APNs base URL; `https://api.sandbox.push.apple.com` for development
+    /// builds
+    #[serde(default = "default_apns_url")]
+    pub url: String,
+}
+
+fn default_fcm_url() -> String {
+    "https://fcm.googleapis.com".to_string()
+}
+
+fn default_apns_url() -> String {
+    "https://api.push.apple.com".to_string()
+}
+
+impl PushConfig {
+    fn supports(&self, platform: PushPlatform) -> bool {
+        match platform {
+            PushPlatform::Fcm => self.fcm.is_some(),
+            PushPlatform::Apns => self.apns.is_some(),
+        }
+    }
+}
+
+/// Registered devices by token subject of their user
+pub type PushTokens = HashMap<String, Vec<PushToken>>;
+
+/// Credentials for the push services, reused until they near expiry
+#[derive(Debug, Default)]
+pub struct PushCredentials {
+    apns: Option<ApnsToken>,
+    fcm: Option<FcmAccess>,
+}
+
+#[derive(Debug)]
+struct ApnsToken {
+    key_id: String,
+    jwt: String,
+    issued_at: u64,
+}
+
+#[derive(Debug)]
+struct FcmAccess {
+    service_account: PathBuf,
+    project_id: String,
+    access_token: String,
+    expires_at: u64,
+}
+
+/// The fields of a service account key file used here
+#[derive(Deserialize)]
+pub(crate) struct ServiceAccount {
+    pub project_id: String,
+    pub client_email: String,
+    pub private_key: String,
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+}
+
+fn default_token_uri() -> String {
+    "https://oauth2.googleapis.com/token".to_string()
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AccessToken {
+    pub access_token: String,
+    pub expires_in: u64,
+}
+
+type Rejection = (StatusCode, &'static str);
+
+/// DER body of a PEM file, or of bare base64
+pub(crate) fn pem_der(pem: &str) -> Result<Vec<u8>, String> {
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.starts_with("-----"))
+        .collect();
+    STANDARD
+        .decode(body)
+        .map_err(|e| format!("Invalid PEM key: {}", e))
+}
+
+/// A compact JWT, signed by `sign`
+pub(crate) fn sign_jwt(
+    header: &Value,
+    claims: &Value,
+    sign: impl FnOnce(&[u8]) -> Result<Vec<u8>, String>,
+) -> Result<String, String> {
+    let input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+    let signature = sign(input.as_bytes())?;
+    Ok(format!("{}.{}", input, URL_SAFE_NO_PAD.encode(signature)))
+}
+
+/// ES256 signature with a PKCS#8 P-256 key
+fn es256(pem: &str, input: &[u8]) -> Result<Vec<u8>, String> {
+    let rng = SystemRandom::new();
+    let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pem_der(pem)?, &rng)
+        .map_err(|e| format!("Invalid APNs key: {}", e))?;
+    key.sign(&rng, input)
+        .map(|s| s.as_ref().to_vec())
+        .map_err(|e| format!("Signing failed: {}", e))
+}
+
+/// RS256 signature with a PKCS#8 RSA key
+pub(crate) fn rs256(pem: &str, input: &[u8]) -> Result<Vec<u8>, String> {
+    let key = RsaKeyPair::from_pkcs8(&pem_der(pem)?)
+        .map_err(|e| format!("Invalid service account key: {}", e))?;
+    let mut signature = vec![0; key.public().modulus_len()];
+    key.sign(
+        &RSA_PKCS1_SHA256,
+        &SystemRandom::new(),
+        input,
+        &mut signature,
+    )
+    .map_err(|e| format!("Signing failed: {}", e))?;
+    Ok(signature)
+}
+
+/// Title and text shown for a pushed message
+fn alert(message: &WsMessage) -> (String, String) {
+    match message {
+        WsMessage::IncomingCall { from, .. } => {
+            ("Incoming call".to_string(), format!("{} is calling", from))
+        }
+        _ => (
+            "Call invitation".to_string(),
+            "You are invited to join a call".to_string(),
+        ),
+    }
+}
+
+impl AppState {
+    /// Register one of `user`'s devices, taking it over from any other user
+    pub async fn register_push_token(
+        &self,
+        user: &str,
+        request: PushTokenRequest,
+    ) -> Result<PushToken, Rejection> {
+        let config = self.config();
+        let Some(push) = config.push.as_ref() else {
+            return Err((StatusCode::NOT_FOUND, "Push notifications not enabled"));
+        };
+        if !push.supports(request.platform) {
+            return Err((StatusCode::BAD_REQUEST, "Push platform not configured"));
+        }
+        let token = request.token.trim();
+        if token.is_empty() || token.len() > MAX_PUSH_TOKEN_LEN || token.contains('/') {
+            return Err((StatusCode::BAD_REQUEST, "Invalid push token"));
+        }
+        let entry = PushToken {
+            platform: request.platform,
+            token: token.to_string(),
+            registered_at: unix_timestamp(),
+        };
+        let mut tokens = self.push_tokens.lock().await;
+        // A device signed in to another account now rings this one
+        for devices in tokens.values_mut() {
+            devices.retain(|d| d.token != entry.token);
+        }
+        tokens.retain(|_, devices| !devices.is_empty());
+        let devices = tokens.entry(user.to_string()).or_default();
+        if devices.len() >= MAX_PUSH_TOKENS {
+            devices.remove(0);
+        }
+        devices.push(entry.clone());
+        Ok(entry)
+    }
+
+    /// `user`'s registered devices, oldest first
+    pub async fn push_tokens(&self, user: &str) -> Vec<PushToken> {
+        self.push_tokens
+            .lock()
+            .await
+            .get(user)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Forget one of `user`'s devices; returns false if there is none
+    pub async fn remove_push_token(&self, user: &str, token: &str) -> bool {
+        let mut tokens = self.push_tokens.lock().await;
+        let Some(devices) = tokens.get_mut(user) else {
+            return false;
+        };
+        let before = devices.len();
+        devices.retain(|d| d.token != token);
+        let removed = devices.len() != before;
+        if devices.is_empty() {
+            tokens.remove(user);
+        }
+        removed
+    }
+
+    /// `user`'s devices that can be rung right now: they have no presence
+    /// socket open, so cannot be told there
+    async fn pushable_devices(&self, user: &str) -> Vec<PushToken> {
+        let config = self.config();
+        let Some(push) = config.push.as_ref() else {
+            return Vec::new();
+        };
+        if self.presence.lock().await.contains_key(user) {
+            return Vec::new();
+        }
+        let mut devices = self.push_tokens(user).await;
+        devices.retain(|d| push.supports(d.platform));
+        devices
+    }
+
+    /// Whether `user` is offline but has devices to ring
+    pub async fn can_push_to(&self, user: &str) -> bool {
+        !self.pushable_devices(user).await.is_empty()
+    }
+
+    /// Queue a push of `message` to each of `user`'s devices, returning how
+    /// many were queued
+    pub async fn push_to_user(&self, user: &str, message: &WsMessage, expires_at: u64) -> usize {
+        let devices = self.pushable_devices(user).await;
+        for device in &devices {
+            self.enqueue_job(JobKind::Push {
+                user: user.to_string(),
+                platform: device.platform,
+                token: device.token.clone(),
+                message: Box::new(message.clone()),
+                expires_at,
+            })
+            .await;
+        }
+        devices.len()
+    }
+
+    /// Deliver one push; run by its job
+    pub(crate) async fn send_push(
+        &self,
+        user: &str,
+        platform: PushPlatform,
+        token: &str,
+        message: &WsMessage,
+        expires_at: u64,
+    ) -> Result<(), String> {
+        if unix_timestamp() >= expires_at {
+            debug!("Dropping expired push to {}", user);
+            return Ok(());
+        }
+        let config = self.config();
+        let push = config
+            .push
+            .as_ref()
+            .ok_or("Push notifications not enabled")?;
+        let registered = match platform {
+            PushPlatform::Fcm => {
+                let fcm = push.fcm.as_ref().ok_or("FCM not configured")?;
+                self.send_fcm(fcm, token, message, expires_at).await?
+            }
+            PushPlatform::Apns => {
+                let apns = push.apns.as_ref().ok_or("APNs not configured")?;
+                self.send_apns(apns, token, message, expires_at).await?
+            }
+        };
+        if !registered {
+            info!("Dropping unregistered {:?} device of {}", platform, user);
+            self.remove_push_token(user, token).await;
+        }
+        Ok(())
+    }
+
+    /// A current APNs provider token
+    async fn apns_token(&self, apns: &ApnsConfig) -> Result<String, String> {
+        let now = unix_timestamp();
+        let mut credentials = self.push_credentials.lock().await;
+        if let Some(cached) = &credentials.apns
+            && cached.key_id == apns.key_id
+            && now < cached.issued_at + APNS_TOKEN_TTL_SECS
+        {
+            return Ok(cached.jwt.clone());
+        }
+        let pem = std::fs::read_to_string(&apns.key_file)
+            .map_err(|e| format!("Cannot read {}: {}", apns.key_file.display(), e))?;
+        let jwt = sign_jwt(
+            &json!({"alg": "ES256", "kid": apns.key_id}),
+            &json!({"iss": apns.team_id, "iat": now}),
+            |input| es256(&pem, input),
+        )?;
+        credentials.apns = Some(ApnsToken {
+            key_id: apns.key_id.clone(),
+            jwt: jwt.clone(),
+            issued_at: now,
+        });
+        Ok(jwt)
+    }
+
+    /// Push to an iOS device; returns false if the token is no longer valid
+    async fn send_apns(
+        &self,
+        apns: &ApnsConfig,
+        token: &str,
+        message: &WsMessage,
+        expires_at: u64,
+    ) -> Result<bool, String> {
+        let jwt = self.apns_token(apns).await?;
+        let (title, body) = alert(message);
+        let mut payload = serde_json::to_value(message).map_err(|e| e.to_string())?;
+        payload["aps"] = json!({
+            "alert": {"title": title, "body": body},
+            "sound": "default"
+        });
+        let response = self
+            .http
+            .post(format!(
+                "{}/3/device/{}",
+                apns.url.trim_end_matches('/'),
+                token
+            ))
+            .header(header::AUTHORIZATION, format!("bearer {}", jwt))
+            .header("apns-topic", &apns.topic)
+            .header("apns-push-type", "alert")
+            .header("apns-priority", "10")
+            .header("apns-expiration", expires_at.to_string())
+            .json(&payload)
+            .timeout(PUSH_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(true);
+        }
+        let reason = response
+            .json::<Value>()
+            .await
+            .ok()
+            .and_then(|body| body["reason"].as_str().map(str::to_string))
+            .unwrap_or_default();
+        match (status, reason.as_str()) {
+            (StatusCode::GONE, _) | (_, "BadDeviceToken") => Ok(false),
+            (StatusCode::FORBIDDEN, "ExpiredProviderToken") => {
+                self.push_credentials.lock().await.apns = None;
+                Err(format!("APNs answered {}: {}", status, reason))
+            }
+            _ => Err(format!("APNs answered {}: {}", status, reason)),
+        }
+    }
+
+    /// The FCM project and a current access token for it
+    async fn fcm_access(&self, fcm: &FcmConfig) -> Result<(String, String), String> {
+        let now = unix_timestamp();
+        let mut credentials = self.push_credentials.lock().await;
+        if let Some(cached) = &credentials.fcm
+            && cached.service_account == fcm.service_account
+            && now + 60 < cached.expires_at
+        {
+            return Ok((cached.project_id.clone(), cached.access_token.clone()));
+        }
+        let account: ServiceAccount = std::fs::read_to_string(&fcm.service_account)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Cannot read {}: {}", fcm.service_account.display(), e))?;
+        let assertion = sign_jwt(
+            &json!({"alg": "RS256", "typ": "JWT"}),
+            &json!({
+                "iss": account.client_email,
+                "scope": FCM_SCOPE,
+                "aud": account.token_uri,
+                "iat": now,
+                "exp": now + 3600
+            }),
+            |input| rs256(&account.private_key, input),
+        )?;
+        let response = self
+            .http
+            .post(&account.token_uri)
+            .form(&[("grant_type", JWT_BEARER_GRANT), ("assertion", &assertion)])
+            .timeout(PUSH_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("Token endpoint answered {}", response.status()));
+        }
+        let token: AccessToken = response.json().await.map_err(|e| e.to_string())?;
+        credentials.fcm = Some(FcmAccess {
+            service_account: fcm.service_account.clone(),
+            project_id: account.project_id.clone(),
+            access_token: token.access_token.clone(),
+            expires_at: now + token.expires_in,
+        });
+        Ok((account.project_id, token.access_token))
+    }
+
+    /// Push to an Android device; returns false if the token is no longer
+    /// valid
+    async fn send_fcm(
+        &self,
+        fcm: &FcmConfig,
+        token: &str,
+        message: &WsMessage,
+        expires_at: u64,
+    ) -> Result<bool, String> {
+        let (project_id, access_token) = self.fcm_access(fcm).await?;
+        let (title, body) = alert(message);
+        // FCM data values must be strings
+        let data: serde_json::Map<String, Value> = match serde_json::to_value(message) {
+            Ok(Value::Object(fields)) => fields
+                .into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| match v {
+                    Value::String(s) => (k, Value::String(s)),
+                    other => (k, Value::String(other.to_string())),
+                })
+                .collect(),
+            _ => serde_json::Map::new(),
+        };
+        let ttl = expires_at.saturating_sub(unix_timestamp());
+        let payload = json!({
+            "message": {
+                "token": token,
+                "notification": {"title": title, "body": body},
+                "data": data,
+                "android": {"priority": "high", "ttl": format!("{}s", ttl)}
+            }
+        });
+        let response = self
+            .http
+            .post(format!(
+                "{}/v1/projects/{}/messages:send",
+                fcm.url.trim_end_matches('/'),
+                project_id
+            ))
+            .bearer_auth(access_token)
+            .json(&payload)
+            .timeout(PUSH_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            StatusCode::UNAUTHORIZED => {
+                self.push_credentials.lock().await.fcm = None;
+                Err("FCM refused the access token".to_string())
+            }
+            status => Err(format!("FCM answered {}", status)),
+        }
+    }
+}
+
+/// List the caller's devices registered for push
+#[utoipa::path(
+    get,
+    path = "/api/push/tokens",
+    tag = "Calls",
+    responses(
+        (status = 200, description = "Devices, oldest first", body = Vec<PushToken>),
+        (status = 401, description = "Missing or invalid user token")
+    )
+)]
+pub async fn list_push_tokens(
+    State(state): State<AppState>,
+    UserAuth(user): UserAuth,
+) -> Json<Vec<PushToken>> {
+    Json(state.push_tokens(&user).await)
+}
+
+/// Register a device to be rung with push notifications
+///
+/// Registering a token again refreshes it; a token registered by another
+/// user moves to the caller.
+#[utoipa::path(
+    post,
+    path = "/api/push/tokens",
+    tag = "Calls",
+    request_body = PushTokenRequest,
+    responses(
+        (status = 201, description = "Device registered", body = PushToken),
+        (status = 400, description = "Invalid token, or no credentials for its platform"),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 404, description = "Push notifications not enabled")
+    )
+)]
+pub async fn register_push_token(
+    State(state): State<AppState>,
+    UserAuth(user): UserAuth,
+    Json(request): Json<PushTokenRequest>,
+) -> Response {
+    match state.register_push_token(&user, request).await {
+        Ok(token) => {
+            debug!("{} registered a {:?} device", user, token.platform);
+            (StatusCode::CREATED, Json(token)).into_response()
+        }
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Stop ringing a device
+#[utoipa::path(
+    delete,
+    path = "/api/push/tokens/{token}",
+    tag = "Calls",
+    params(
+        ("token" = String, Path, description = "The device token")
+    ),
+    responses(
+        (status = 204, description = "Device removed"),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 404, description = "No such device")
+    )
+)]
+pub async fn unregister_push_token(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+    UserAuth(user): UserAuth,
+) -> StatusCode {
+    match state.remove_push_token(&user, &token).await {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
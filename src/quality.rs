@@ -0,0 +1,181 @@
+//! MOS-style call quality scoring
+//!
+//! Clients periodically report RTT, jitter and packet loss. Each report is
+//! turned into an estimated mean opinion score with a simplified ITU-T G.107
+//! E-model, stored on the peer and folded into the call record. When a
+//! room's score (its worst-off peer) falls below the configured threshold a
+//! webhook alert is posted once, re-arming after the score recovers.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, warn};
+
+use crate::models::{JobKind, PeerQuality, RoomQuality, TimelineKind};
+use crate::state::{AppState, Room, unix_timestamp};
+
+/// Low-quality alert settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QualityConfig {
+    /// Alert when a room's MOS drops below this value
+    pub alert_threshold: f64,
+    /// Webhook receiving alerts; alerts are only logged when unset
+    pub alert_webhook_url: Option<String>,
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        Self {
+            alert_threshold: 3.0,
+            alert_webhook_url: None,
+        }
+    }
+}
+
+/// Estimate a MOS (1.0-4.5) from network measurements
+///
+/// Uses the common simplification of the E-model: one-way latency plus
+/// twice the jitter gives an effective delay impairment, and each percent of
+/// loss costs 2.5 R points.
+pub fn estimate_mos(rtt_ms: f64, jitter_ms: f64, packet_loss: f64) -> f64 {
+    let effective_latency = rtt_ms / 2.0 + jitter_ms * 2.0 + 10.0;
+    let mut r = if effective_latency < 160.0 {
+        93.2 - effective_latency / 40.0
+    } else {
+        93.2 - (effective_latency - 120.0) / 10.0
+    };
+    r -= packet_loss.clamp(0.0, 100.0) * 2.5;
+    let r = r.clamp(0.0, 100.0);
+
+    1.0 + 0.035 * r + 0.000007 * r * (r - 60.0) * (100.0 - r)
+}
+
+impl Room {
+    /// Score of the worst-off peer, if any have reported stats
+    pub fn mos(&self) -> Option<f64> {
+        self.peers
+            .iter()
+            .filter_map(|p| p.quality.as_ref())
+            .map(|q| q.mos)
+            .min_by(f64::total_cmp)
+    }
+}
+
+impl AppState {
+    /// Score a peer's stats report and alert if the room degrades
+    pub async fn record_quality(
+        &self,
+        room_id: &str,
+        peer_id: &str,
+        rtt_ms: f64,
+        jitter_ms: f64,
+        packet_loss: f64,
+    ) {
+        if ![rtt_ms, jitter_ms, packet_loss]
+            .iter()
+            .all(|v| v.is_finite() && *v >= 0.0)
+        {
+            return;
+        }
+        let mos = estimate_mos(rtt_ms, jitter_ms, packet_loss);
+        let threshold = self.config().quality.alert_threshold;
+
+        let id = peer_id.to_string();
+        let scored = self.with_room(room_id, move |room| {
+            let peer = room.peers.iter_mut().find(|p| p.id == id)?;
+            peer.quality = Some(PeerQuality {
+                peer_id: id,
+                rtt_ms,
+                jitter_ms,
+                packet_loss,
+                mos,
+                updated_at: unix_timestamp(),
+            });
+
+            let room_mos = room.mos().unwrap_or(mos);
+            let alert = if room_mos < threshold && !room.quality_alerted {
+                room.quality_alerted = true;
+                true
+            } else {
+                if room_mos >= threshold {
+                    room.quality_alerted = false;
+                }
+                false
+            };
+            Some((room_mos, alert))
+        });
+        let Some(Some((room_mos, alert))) = scored.await else {
+            return;
+        };
+        debug!("Peer {} in room {} scored MOS {:.2}", peer_id, room_id, mos);
+
+        self.record_call_mos(room_id, mos).await;
+        if alert {
+            let detail = format!("MOS {:.2}", room_mos);
+            self.record_timeline(room_id, TimelineKind::QualityDrop, Some(peer_id), detail)
+                .await;
+            self.send_quality_alert(room_id, room_mos).await;
+        }
+    }
+
+    /// Current quality of a room, if it exists
+    pub async fn room_quality(&self, room_id: &str) -> Option<RoomQuality> {
+        let room_id = room_id.to_string();
+        self.with_room(&room_id.clone(), move |room| RoomQuality {
+            room_id,
+            mos: room.mos(),
+            peers: room
+                .peers
+                .iter()
+                .filter_map(|p| p.quality.clone())
+                .collect(),
+        })
+        .await
+    }
+
+    /// Queue a low-quality alert post
+    async fn send_quality_alert(&self, room_id: &str, mos: f64) {
+        let config = &self.config().quality;
+        warn!(
+            "Room {} quality dropped to MOS {:.2} (threshold {:.2})",
+            room_id, mos, config.alert_threshold
+        );
+        let Some(url) = config.alert_webhook_url.clone() else {
+            return;
+        };
+
+        let body = json!({
+            "text": format!("Call quality in room {} dropped to MOS {:.2}", room_id, mos),
+            "room_id": room_id,
+            "mos": mos,
+            "threshold": config.alert_threshold,
+        });
+        self.enqueue_job(JobKind::Notify { url, body }).await;
+    }
+}
+
+/// Get the current call quality of a room
+#[utoipa::path(
+    get,
+    path = "/api/room/{room_id}/quality",
+    tag = "Analytics",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room")
+    ),
+    responses(
+        (status = 200, description = "Per-peer stats and estimated MOS", body = RoomQuality),
+        (status = 404, description = "Room not found")
+    )
+)]
+pub async fn room_quality(Path(room_id): Path<String>, State(state): State<AppState>) -> Response {
+    match state.room_quality(&room_id).await {
+        Some(quality) => Json(quality).into_response(),
+        None => (StatusCode::NOT_FOUND, "Room not found").into_response(),
+    }
+}
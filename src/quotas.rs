@@ -0,0 +1,353 @@
+//! Tenant usage quotas
+//!
+//! A tenant with a `quota` is metered in participant-minutes: each minute
+//! a peer spends in one of the tenant's rooms counts once. Usage starts
+//! over at the beginning of each `period`, a UTC day or month. When it
+//! reaches `warn_at_percent` of `minutes`, the tenant's webhook gets a
+//! `quota.warning` event, and when it reaches `minutes`, `quota.exceeded`;
+//! each is sent once per period, about one of the rooms in progress.
+//!
+//! Calls are never cut for going over. Once usage passes `minutes` plus
+//! `grace_minutes`, a tenant with `downgrade` set has recording, video or
+//! both switched off in its running rooms and in rooms it creates for the
+//! rest of the period; their peers get `quota_downgrade`.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use axum::{Json, extract::State};
+use chrono::{Datelike, TimeZone, Utc};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::admin::AdminAuth;
+use crate::models::{QuotaUsage, RoomSettings, WebhookPayload, WsMessage};
+use crate::state::{AppState, Room, unix_timestamp};
+
+/// Seconds between meter readings
+const METER_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long usage accumulates before it starts over
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaPeriod {
+    Day,
+    #[default]
+    Month,
+}
+
+impl QuotaPeriod {
+    /// Unix timestamp (seconds) when the period holding `now` began
+    fn start(self, now: u64) -> u64 {
+        let Some(now) = Utc.timestamp_opt(now as i64, 0).single() else {
+            return 0;
+        };
+        let day = now.date_naive();
+        let first = match self {
+            QuotaPeriod::Day => day,
+            QuotaPeriod::Month => day.with_day(1).unwrap_or(day),
+        };
+        first.and_time(Default::default()).and_utc().timestamp() as u64
+    }
+}
+
+/// What is switched off in a tenant's rooms once its grace runs out
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Downgrade {
+    pub recording: bool,
+    pub video: bool,
+}
+
+impl Default for Downgrade {
+    fn default() -> Self {
+        Self {
+            recording: true,
+            video: true,
+        }
+    }
+}
+
+/// A tenant's participant-minute allowance
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuotaConfig {
+    /// Participant-minutes per period; may be fractional
+    pub minutes: f64,
+    #[serde(default)]
+    pub period: QuotaPeriod,
+    /// Share of `minutes` at which `quota.warning` is sent
+    #[serde(default = "default_warn_at_percent")]
+    pub warn_at_percent: u8,
+    /// Minutes allowed past the quota before rooms are downgraded
+    #[serde(default)]
+    pub grace_minutes: f64,
+    /// What to switch off after the grace; calls go on untouched when unset
+    #[serde(default)]
+    pub downgrade: Option<Downgrade>,
+}
+
+fn default_warn_at_percent() -> u8 {
+    80
+}
+
+/// A tenant's usage in the current period
+#[derive(Debug, Clone, Default)]
+pub struct TenantUsage {
+    /// Unix timestamp (seconds)
+    pub period_start: u64,
+    pub participant_ms: u64,
+    pub warned: bool,
+    pub exceeded: bool,
+    pub downgraded: bool,
+}
+
+impl TenantUsage {
+    fn minutes(&self) -> f64 {
+        self.participant_ms as f64 / 60_000.0
+    }
+}
+
+/// Usage of each tenant with a quota, and when it was last metered
+#[derive(Debug, Default)]
+pub struct QuotaMeter {
+    last_reading: Option<Instant>,
+    tenants: HashMap<String, TenantUsage>,
+}
+
+impl Room {
+    /// Switch off what `downgrade` names, telling peers when anything changed
+    pub fn apply_downgrade(&mut self, downgrade: Downgrade) {
+        let video = downgrade.video && self.settings.video;
+        let recording = downgrade.recording && self.settings.controls.recording;
+        if !video && !recording {
+            return;
+        }
+        if video {
+            self.settings.video = false;
+        }
+        if recording {
+            self.settings.controls.recording = false;
+            self.consent = None;
+            if self.recording {
+                self.recording = false;
+                self.broadcast_to_all(&WsMessage::Recording { active: false });
+            }
+            self.broadcast_to_all(&self.settings.controls.message());
+        }
+        self.broadcast_to_all(&WsMessage::QuotaDowngrade {
+            video: self.settings.video,
+            recording: self.settings.controls.recording,
+        });
+    }
+}
+
+impl AppState {
+    /// Add the time peers spent in tenants' rooms since the last reading,
+    /// sending quota events and downgrading rooms as thresholds are passed
+    pub async fn meter_quotas(&self) {
+        let config = self.config();
+        let quotas: HashMap<&str, &QuotaConfig> = config
+            .tenants
+            .iter()
+            .filter_map(|(name, tenant)| Some((name.as_str(), tenant.quota.as_ref()?)))
+            .collect();
+        if quotas.is_empty() {
+            return;
+        }
+        let elapsed = {
+            let mut meter = self.quota_meter.lock().await;
+            let now = Instant::now();
+            meter
+                .last_reading
+                .replace(now)
+                .map(|last| now - last)
+                .unwrap_or_default()
+        };
+
+        // Running rooms of tenants with a quota, and their peer counts
+        let tenant_of: HashMap<String, String> = self
+            .calls
+            .lock()
+            .await
+            .values()
+            .filter(|call| call.ended_at.is_none())
+            .filter_map(|call| Some((call.room_id.clone(), call.tenant.clone()?)))
+            .filter(|(_, tenant)| quotas.contains_key(tenant.as_str()))
+            .collect();
+        let mut rooms: HashMap<&str, Vec<(String, usize)>> = HashMap::new();
+        for (room_id, handle) in self.room_handles().await {
+            let Some(tenant) = tenant_of.get(&room_id) else {
+                continue;
+            };
+            let peers = handle.call(|room| room.peers.len()).await.unwrap_or(0);
+            rooms.entry(tenant).or_default().push((room_id, peers));
+        }
+
+        let now = unix_timestamp();
+        let mut events = Vec::new();
+        let mut downgrades = Vec::new();
+        let mut meter = self.quota_meter.lock().await;
+        for (&tenant, quota) in &quotas {
+            let running = rooms.remove(tenant).unwrap_or_default();
+            let usage = meter.tenants.entry(tenant.to_string()).or_default();
+            let period_start = quota.period.start(now);
+            if usage.period_start != period_start {
+                *usage = TenantUsage {
+                    period_start,
+                    ..Default::default()
+                };
+            }
+            let peers: usize = running.iter().map(|(_, peers)| peers).sum();
+            usage.participant_ms += elapsed.as_millis() as u64 * peers as u64;
+
+            let used = usage.minutes();
+            // Events are about a room in progress, so they reach the
+            // tenant's webhook
+            let busy_room = running
+                .iter()
+                .find(|(_, peers)| *peers > 0)
+                .map(|(room_id, _)| room_id.clone());
+            if let Some(room_id) = busy_room {
+                let used_minutes = (used * 100.0).round() / 100.0;
+                let warn_at = quota.minutes * f64::from(quota.warn_at_percent) / 100.0;
+                if !usage.warned && used >= warn_at {
+                    usage.warned = true;
+                    let payload = WebhookPayload::QuotaWarning {
+                        used_minutes,
+                        quota_minutes: quota.minutes,
+                        period_start,
+                    };
+                    events.push((room_id.clone(), payload));
+                }
+                if !usage.exceeded && used >= quota.minutes {
+                    usage.exceeded = true;
+                    warn!("Tenant {} exceeded its quota", tenant);
+                    let payload = WebhookPayload::QuotaExceeded {
+                        used_minutes,
+                        quota_minutes: quota.minutes,
+                        period_start,
+                    };
+                    events.push((room_id, payload));
+                }
+            }
+            if let Some(downgrade) = quota.downgrade
+                && used >= quota.minutes + quota.grace_minutes
+            {
+                if !usage.downgraded {
+                    usage.downgraded = true;
+                    info!("Downgrading the rooms of tenant {}", tenant);
+                    downgrades.push((tenant, None));
+                }
+                downgrades.extend(
+                    running
+                        .into_iter()
+                        .map(|(room_id, _)| (tenant, Some((room_id, downgrade)))),
+                );
+            }
+        }
+        drop(meter);
+
+        for (room_id, payload) in events {
+            self.queue_webhook(&room_id, payload).await;
+        }
+        for (tenant, room) in downgrades {
+            match room {
+                Some((room_id, downgrade)) => {
+                    self.with_room(&room_id, move |room| room.apply_downgrade(downgrade))
+                        .await;
+                }
+                None => {
+                    let detail = format!("Grace for tenant {} ran out", tenant);
+                    self.record_audit(None, "quota", "tenant.downgrade", detail)
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// Apply `tenant`'s downgrade to a new room's settings while the
+    /// tenant is over its grace
+    pub async fn quota_settings(&self, tenant: Option<&str>, settings: &mut RoomSettings) {
+        let Some(tenant) = tenant else {
+            return;
+        };
+        let config = self.config();
+        let Some(downgrade) = config
+            .tenants
+            .get(tenant)
+            .and_then(|t| t.quota.as_ref())
+            .and_then(|q| q.downgrade)
+        else {
+            return;
+        };
+        let downgraded = self
+            .quota_meter
+            .lock()
+            .await
+            .tenants
+            .get(tenant)
+            .is_some_and(|usage| usage.downgraded);
+        if downgraded {
+            settings.video &= !downgrade.video;
+            settings.controls.recording &= !downgrade.recording;
+        }
+    }
+
+    /// Usage of every tenant with a quota
+    pub async fn quota_usage(&self) -> Vec<QuotaUsage> {
+        let config = self.config();
+        let meter = self.quota_meter.lock().await;
+        let now = unix_timestamp();
+        let mut usage: Vec<QuotaUsage> = config
+            .tenants
+            .iter()
+            .filter_map(|(name, tenant)| {
+                let quota = tenant.quota.as_ref()?;
+                let period_start = quota.period.start(now);
+                let current = meter
+                    .tenants
+                    .get(name)
+                    .filter(|u| u.period_start == period_start)
+                    .cloned()
+                    .unwrap_or_default();
+                Some(QuotaUsage {
+                    tenant: name.clone(),
+                    period_start,
+                    used_minutes: (current.minutes() * 100.0).round() / 100.0,
+                    quota_minutes: quota.minutes,
+                    warned: current.warned,
+                    exceeded: current.exceeded,
+                    downgraded: current.downgraded,
+                })
+            })
+            .collect();
+        usage.sort_by(|a, b| a.tenant.cmp(&b.tenant));
+        usage
+    }
+}
+
+/// Spawn background task metering tenants' usage
+pub fn spawn_quota_metering(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(METER_INTERVAL);
+        loop {
+            interval.tick().await;
+            state.meter_quotas().await;
+        }
+    });
+}
+
+/// Usage of each tenant with a quota in the current period
+#[utoipa::path(
+    get,
+    path = "/admin/quotas",
+    tag = "Admin",
+    responses(
+        (status = 200, description = "Usage per tenant", body = Vec<QuotaUsage>),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_quotas(_auth: AdminAuth, State(state): State<AppState>) -> Json<Vec<QuotaUsage>> {
+    Json(state.quota_usage().await)
+}
@@ -0,0 +1,261 @@
+//! Per-client rate limiting of room creation and joins
+//!
+//! Clients are identified by the address of the connection or, when that
+//! connection comes from one of `trusted_proxies` (or over a Unix socket,
+//! which only a local reverse proxy can reach), by `X-Forwarded-For` or
+//! `Forwarded`. IPv4-mapped addresses from a dual-stack listener count as
+//! the IPv4 address. IPv4 clients are limited per address and IPv6 clients
+//! per /64, since a single host or LAN can use any address in its prefix.
+
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::cache::EphemeralCache;
+use crate::state::AppState;
+
+/// Tracked clients above which the least recently seen are forgotten
+pub const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// How long a client goes unseen before its bucket is forgotten; any
+/// sensible budget has refilled by then
+pub const RATE_BUCKET_TTL: Duration = Duration::from_secs(3600);
+
+/// Request budget per client; rate limiting is disabled when unset
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Sustained requests allowed per minute
+    pub per_minute: u32,
+    /// Requests allowed in a burst on top of the sustained rate
+    pub burst: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_minute: 30,
+            burst: 10,
+        }
+    }
+}
+
+/// An address range such as `10.0.0.0/8`, `fd00::/8` or a single `::1`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpCidr {
+    /// Whether `ip` falls within this range
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid address range: {}", s);
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|_| invalid())?
+            .to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for IpCidr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Buckets by [`rate_key`], as stored in [`AppState`]
+pub type RateBuckets = EphemeralCache<IpAddr, RateBucket>;
+
+/// Token bucket for one client
+#[derive(Debug)]
+pub struct RateBucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateBucket {
+    fn new(limit: &RateLimitConfig) -> Self {
+        Self {
+            tokens: f64::from(limit.burst),
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Take one request's token, or say how long until one is available
+    fn take(&mut self, limit: &RateLimitConfig) -> Result<(), Duration> {
+        let per_sec = f64::from(limit.per_minute) / 60.0;
+        let now = Instant::now();
+        let earned = now.duration_since(self.refilled).as_secs_f64() * per_sec;
+        self.tokens = (self.tokens + earned).min(f64::from(limit.burst.max(1)));
+        self.refilled = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        match per_sec > 0.0 {
+            true => Err(Duration::from_secs_f64((1.0 - self.tokens) / per_sec)),
+            false => Err(Duration::from_secs(60)),
+        }
+    }
+}
+
+/// Key a client is limited by: the IPv4 address, or the IPv6 /64
+pub fn rate_key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & !u128::from(u64::MAX))),
+        v4 => v4,
+    }
+}
+
+/// The client's address, taken from forwarding headers when `peer` is a
+/// trusted proxy (or unknown, as on a Unix socket)
+///
+/// `X-Forwarded-For` is read right to left and the first untrusted hop is
+/// the client; `Forwarded` is consulted when it is absent.
+pub fn client_ip(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[IpCidr]) -> Option<IpAddr> {
+    let peer = peer.map(|ip| ip.to_canonical());
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+    if peer.is_some_and(|ip| !is_trusted(ip)) {
+        return peer;
+    }
+
+    let hops: Vec<IpAddr> = match headers.contains_key("x-forwarded-for") {
+        true => headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(parse_forwarded_node)
+            .collect(),
+        false => headers
+            .get_all(header::FORWARDED)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (key, value) = pair.trim().split_once('=')?;
+                    key.eq_ignore_ascii_case("for").then_some(value)
+                })
+            })
+            .filter_map(parse_forwarded_node)
+            .collect(),
+    };
+    hops.iter()
+        .rev()
+        .copied()
+        .find(|ip| !is_trusted(*ip))
+        .or(hops.first().copied())
+        .or(peer)
+}
+
+/// Parse one forwarded hop: `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1`,
+/// `[2001:db8::1]:80` or any of these in double quotes
+fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip.to_canonical());
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip().to_canonical());
+    }
+    let bracketed = node.strip_prefix('[')?.split(']').next()?;
+    bracketed.parse::<IpAddr>().ok().map(|ip| ip.to_canonical())
+}
+
+impl AppState {
+    /// Spend one request from the client's budget
+    pub async fn check_rate_limit(
+        &self,
+        ip: IpAddr,
+        limit: &RateLimitConfig,
+    ) -> Result<(), Duration> {
+        self.rate_limits
+            .lock()
+            .await
+            .get_or_insert_with(rate_key(ip), || RateBucket::new(limit))
+            .take(limit)
+    }
+}
+
+/// Middleware rejecting clients over their budget with 429
+pub async fn rate_limit(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let config = state.config();
+    let Some(limit) = &config.rate_limit else {
+        return next.run(request).await;
+    };
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let Some(ip) = client_ip(peer, request.headers(), &config.trusted_proxies) else {
+        return next.run(request).await;
+    };
+
+    match state.check_rate_limit(ip, limit).await {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            warn!(
+                "Rate limited {} ({}) on {}",
+                state.log_ip(ip),
+                state.log_ip(rate_key(ip)),
+                request.uri().path()
+            );
+            let secs = retry_after.as_secs().max(1).to_string();
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, secs)],
+                "Too many requests",
+            )
+                .into_response()
+        }
+    }
+}
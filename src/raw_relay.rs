@@ -0,0 +1,125 @@
+//! Pass-through relay of session descriptions and ICE candidates
+//!
+//! Offers, answers and candidates make up nearly all signaling traffic, and
+//! in the common case the server forwards them untouched. Once such a
+//! message is parsed and checked, instead of cloning it per recipient and
+//! serializing it again, the fast path queues the received buffer itself
+//! for the other peer. Descriptions are the large messages, up to
+//! [`MAX_SDP_LEN`](crate::models::MAX_SDP_LEN), so they gain the most.
+//! Anything the fast path does not cover (other message types, addressed
+//! messages, broadcast rooms, a restrictive ICE policy, rooms whose codec or
+//! SDP policy rewrites descriptions, oversized text) goes through the full
+//! handler, which also produces the errors.
+
+use axum::extract::ws::Utf8Bytes;
+use tracing::warn;
+
+use crate::debug_bundle::{SignalingNote, candidate_type, summarize_sdp};
+use crate::lanes::Lane;
+use crate::models::{MAX_SDP_LEN, RoomMode, TimelineKind, WsMessage};
+use crate::state::AppState;
+
+/// Largest candidate message eligible for pass-through; real candidates are
+/// a few hundred bytes
+pub const RAW_RELAY_MAX_BYTES: usize = 2048;
+
+/// Largest offer or answer message eligible for pass-through; escaping the
+/// line breaks of a description for JSON adds far less than its own size
+pub const RAW_SDP_RELAY_MAX_BYTES: usize = 2 * MAX_SDP_LEN;
+
+/// A message the fast path can relay
+enum Relayable<'a> {
+    Candidate,
+    Offer(&'a str),
+    Answer(&'a str),
+}
+
+impl<'a> Relayable<'a> {
+    /// What `msg`, received as `len` bytes of text, is if it is an
+    /// unaddressed, well-formed candidate, offer or answer small enough to
+    /// relay verbatim
+    fn of(msg: &'a WsMessage, len: usize) -> Option<Self> {
+        match msg {
+            WsMessage::IceCandidate {
+                candidate,
+                peer_id: None,
+                ..
+            } if len <= RAW_RELAY_MAX_BYTES && WsMessage::valid_candidate(candidate) => {
+                Some(Self::Candidate)
+            }
+            WsMessage::Offer { sdp, peer_id: None }
+                if len <= RAW_SDP_RELAY_MAX_BYTES && WsMessage::valid_sdp(sdp) =>
+            {
+                Some(Self::Offer(sdp))
+            }
+            WsMessage::Answer { sdp, peer_id: None }
+                if len <= RAW_SDP_RELAY_MAX_BYTES && WsMessage::valid_sdp(sdp) =>
+            {
+                Some(Self::Answer(sdp))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl AppState {
+    /// Relay `msg`, parsed from `text`, as that original text if it
+    /// qualifies for pass-through
+    ///
+    /// Returns false when the caller must handle the message normally.
+    pub async fn relay_raw(
+        &self,
+        room_id: &str,
+        sender_id: &str,
+        msg: &WsMessage,
+        text: &Utf8Bytes,
+    ) -> bool {
+        let Some(relayable) = Relayable::of(msg, text.len()) else {
+            return false;
+        };
+        // Codec and SDP policies rewrite descriptions
+        let rewrites = !matches!(relayable, Relayable::Candidate);
+        let policy = self.config().ice_policy;
+        let (sender, raw) = (sender_id.to_string(), text.clone());
+        let relayed = self.with_room(room_id, move |room| {
+            if room.mode() != RoomMode::Interactive
+                || policy.for_room(&room.settings).is_restrictive()
+                || (rewrites && (room.settings.codecs.is_some() || room.settings.sdp.is_some()))
+            {
+                return false;
+            }
+            for peer in room.peers.iter().filter(|p| p.id != sender) {
+                if let Err(e) = peer.sender.send_raw(Lane::Signaling, raw.clone()) {
+                    warn!("Failed to send to peer {}: {}", peer.id, e);
+                }
+            }
+            true
+        });
+        if !relayed.await.unwrap_or(false) {
+            return false;
+        }
+        let (kind, note) = match relayable {
+            Relayable::Candidate => (
+                None,
+                candidate_type(text.as_str())
+                    .map(|kind| SignalingNote::Candidate(kind.to_string())),
+            ),
+            Relayable::Offer(sdp) => (
+                Some(TimelineKind::Offer),
+                Some(SignalingNote::Offer(summarize_sdp(sdp))),
+            ),
+            Relayable::Answer(sdp) => (
+                Some(TimelineKind::Answer),
+                Some(SignalingNote::Answer(summarize_sdp(sdp))),
+            ),
+        };
+        if let Some(kind) = kind {
+            self.record_timeline(room_id, kind, Some(sender_id), "")
+                .await;
+        }
+        if let Some(note) = note {
+            self.note_signaling(room_id, sender_id, note).await;
+        }
+        true
+    }
+}
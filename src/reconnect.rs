@@ -0,0 +1,216 @@
+//! Reconnect grace window and re-invites
+//!
+//! When a peer's connection drops without a clean close, its slot is held
+//! for `reconnect_grace_secs` and the rest of the room gets
+//! `PeerReconnecting`. The peer can reclaim the slot, keeping its peer ID,
+//! by reconnecting with the `resume_token` from its `Welcome`. If it does
+//! not come back in time the room gets `CallEnded { can_redial: true }`,
+//! and any client can mint a re-invite link that holds a slot open for the
+//! person being called back.
+
+use std::time::{Duration, Instant};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::models::{LeaveReason, ReinviteResponse, TimelineKind, WsMessage};
+use crate::state::{AppState, Room};
+use crate::token::secret_eq;
+
+/// How long a re-invite link holds its slot
+pub const REINVITE_TTL: Duration = Duration::from_secs(600);
+
+/// A slot held open for a returning or invited peer
+#[derive(Debug)]
+pub struct Reservation {
+    pub token: String,
+    /// Peer ID to restore; `None` for re-invites
+    pub peer_id: Option<String>,
+    pub expires_at: Instant,
+}
+
+impl Room {
+    /// Number of slots held by unexpired reservations
+    pub fn reserved_slots(&self) -> usize {
+        let now = Instant::now();
+        self.reservations
+            .iter()
+            .filter(|r| r.expires_at > now)
+            .count()
+    }
+
+    /// Drop reservations that have run out
+    pub fn prune_reservations(&mut self) {
+        let now = Instant::now();
+        self.reservations.retain(|r| r.expires_at > now);
+    }
+
+    /// Take the reservation matching `token`, if it is still valid
+    pub fn claim_reservation(&mut self, token: &str) -> Option<Reservation> {
+        self.prune_reservations();
+        let pos = self
+            .reservations
+            .iter()
+            .position(|r| secret_eq(&r.token, token))?;
+        Some(self.reservations.remove(pos))
+    }
+
+    /// Remove a dropped peer but hold its slot for the grace window
+    ///
+    /// Returns the resume token, or `None` if the peer was not in the room.
+    pub fn suspend(&mut self, peer_id: &str, grace: Duration) -> Option<String> {
+        let peer = self.remove_peer(peer_id)?;
+        self.reservations.push(Reservation {
+            token: peer.resume_token.clone(),
+            peer_id: Some(peer.id),
+            expires_at: Instant::now() + grace,
+        });
+        self.broadcast_to_all(&WsMessage::PeerReconnecting {
+            peer_id: peer_id.to_string(),
+            grace_secs: grace.as_secs(),
+        });
+        self.ensure_host();
+        Some(peer.resume_token)
+    }
+}
+
+impl AppState {
+    /// Hold a dropped peer's slot and end the call if it does not return
+    ///
+    /// Returns false if the peer should leave normally instead.
+    pub async fn suspend_peer(&self, room_id: &str, peer_id: &str, session: Option<&str>) -> bool {
+        let grace = Duration::from_secs(self.config().reconnect_grace_secs);
+        let (id, token) = (peer_id.to_string(), session.map(str::to_string));
+        let suspended = self.with_room(room_id, move |room| {
+            // Nobody is left to wait for the peer
+            if grace.is_zero() || room.peers.len() < 2 || !room.holds(&id, token.as_deref()) {
+                return None;
+            }
+            room.suspend(&id, grace)
+        });
+        let Some(Some(token)) = suspended.await else {
+            return false;
+        };
+
+        info!(
+            "Holding slot for peer {} in room {} for {}s",
+            peer_id,
+            room_id,
+            grace.as_secs()
+        );
+        self.record_timeline(room_id, TimelineKind::Reconnecting, Some(peer_id), "")
+            .await;
+
+        let state = self.clone();
+        let room_id = room_id.to_string();
+        let peer_id = peer_id.to_string();
+        tokio::spawn(async move {
+            tokio::time::sleep(grace).await;
+            state.expire_reconnect(&room_id, &peer_id, &token).await;
+        });
+        true
+    }
+
+    /// End the call for a peer that did not return within the grace window
+    async fn expire_reconnect(&self, room_id: &str, peer_id: &str, token: &str) {
+        let (id, token) = (peer_id.to_string(), token.to_string());
+        let expired = self.with_room(room_id, move |room| {
+            // Already resumed
+            let Some(pos) = room
+                .reservations
+                .iter()
+                .position(|r| secret_eq(&r.token, &token))
+            else {
+                return false;
+            };
+            room.reservations.remove(pos);
+            room.broadcast_to_all(&WsMessage::Leave {
+                peer_id: Some(id),
+                reason: Some(LeaveReason::NetworkTimeout),
+            });
+            room.broadcast_to_all(&WsMessage::CallEnded {
+                can_redial: true,
+                redirect: None,
+            });
+            room.broadcast_to_all(&room.room_info());
+            room.admit_waiting();
+            true
+        });
+        if expired.await != Some(true) {
+            return;
+        }
+
+        info!("Peer {} did not return to room {}", peer_id, room_id);
+        self.record_disconnect(room_id, peer_id, LeaveReason::NetworkTimeout)
+            .await;
+    }
+
+    /// Hold a slot for someone being called back, returning the token
+    pub async fn create_reinvite(
+        &self,
+        room_id: &str,
+    ) -> Result<String, (StatusCode, &'static str)> {
+        self.with_room(room_id, |room| {
+            // Invitees come back with their own room tokens
+            if room.invitees.is_some() {
+                return Err((StatusCode::FORBIDDEN, "This room is invite-only"));
+            }
+            room.prune_reservations();
+            if room.peers.len() + room.reserved_slots() >= room.capacity() {
+                return Err((StatusCode::CONFLICT, "Room has no free slot"));
+            }
+
+            let token = Uuid::new_v4().simple().to_string();
+            room.reservations.push(Reservation {
+                token: token.clone(),
+                peer_id: None,
+                expires_at: Instant::now() + REINVITE_TTL,
+            });
+            Ok(token)
+        })
+        .await
+        .ok_or((StatusCode::NOT_FOUND, "Room not found"))?
+    }
+}
+
+/// Create a re-invite link that holds a slot in the room
+#[utoipa::path(
+    post,
+    path = "/api/room/{room_id}/reinvite",
+    tag = "Rooms",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room")
+    ),
+    responses(
+        (status = 200, description = "Re-invite link created", body = ReinviteResponse),
+        (status = 403, description = "Room is invite-only"),
+        (status = 404, description = "Room not found"),
+        (status = 409, description = "Room has no free slot")
+    )
+)]
+pub async fn create_reinvite(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    match state.create_reinvite(&room_id).await {
+        Ok(token) => {
+            info!("Created re-invite for room {}", room_id);
+            let config = state.config();
+            let base = config.public_url.trim_end_matches('/');
+            Json(ReinviteResponse {
+                url: format!("{}/room/{}?token={}", base, room_id, token),
+                links: config.deep_links(&room_id, Some(&token)),
+                token,
+                expires_in_secs: REINVITE_TTL.as_secs(),
+            })
+            .into_response()
+        }
+        Err(rejection) => rejection.into_response(),
+    }
+}
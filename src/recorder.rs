@@ -0,0 +1,464 @@
+//! Headless recorder (feature `recorder`)
+//!
+//! A [`Recorder`] joins a room through [`AxiVidClient`] with a recorder
+//! token, so the server treats it as a hidden peer (see
+//! [`crate::recorders`]). Once the room consents to recording, every
+//! participant offers it a send-only connection. The recorder answers each
+//! offer with a webrtc-rs peer connection and writes every track it receives
+//! to its own file: Opus audio as Ogg, VP8 and VP9 video as IVF, and H.264
+//! as an Annex B stream. Video senders are asked for a keyframe every few
+//! seconds so each file starts decodable and recovers from loss.
+//!
+//! Recording ends when the connection to the room does or when
+//! [`Recorder::stop`] is called. Either way every file is closed and
+//! described by a [`RecordedTrack`], ready for
+//! [`AxiVidClient::upload_recording`].
+//!
+//! `axi-vid record <room>` runs one from the command line until the room
+//! ends or Ctrl+C, then optionally uploads what it recorded.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use clap::Args;
+use tokio::sync::oneshot;
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{debug, info, warn};
+use webrtc::api::API;
+use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::media::io::Writer;
+use webrtc::media::io::h264_writer::H264Writer;
+use webrtc::media::io::ivf_reader::IVFFileHeader;
+use webrtc::media::io::ivf_writer::IVFWriter;
+use webrtc::media::io::ogg_writer::OggWriter;
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use webrtc::track::track_remote::TrackRemote;
+
+use crate::client::{AxiVidClient, ClientError, RoomConnection, RoomSender};
+use crate::config::Config;
+use crate::models::WsMessage;
+use crate::rtc;
+use crate::token::{self, Claims, TokenScope};
+
+/// How often video senders are asked for a keyframe
+const KEYFRAME_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Options for `axi-vid record`
+#[derive(Debug, Args)]
+pub struct RecordArgs {
+    /// Room to record
+    pub room_id: String,
+    /// Base URL of the server (defaults to `public_url`)
+    #[arg(long)]
+    pub url: Option<String>,
+    /// Recorder token (defaults to one minted from `jwt_secret`)
+    #[arg(long, env = "AXI_VID_RECORDER_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+    /// Directory to write the tracks to
+    #[arg(long, default_value = "recordings")]
+    pub out: PathBuf,
+    /// Upload each track to the server's recordings when done
+    #[arg(long)]
+    pub upload: bool,
+}
+
+/// Record a room until it ends or Ctrl+C, then report the tracks
+pub async fn run(config: &Config, args: RecordArgs) -> Result<(), String> {
+    let base = args.url.unwrap_or_else(|| config.public_url.clone());
+    let token = match (args.token, config.jwt_secret.as_deref()) {
+        (Some(token), _) => token,
+        (None, Some(secret)) => {
+            let claims = Claims::new(TokenScope::Recorder, Some(args.room_id.clone()), 24 * 3600);
+            token::mint(secret, &claims)
+        }
+        (None, None) => return Err("Pass --token or set jwt_secret".to_string()),
+    };
+    let client = AxiVidClient::new(base);
+    let recorder = Recorder::start(&client, &args.room_id, &token, &args.out)
+        .await
+        .map_err(|e| e.to_string())?;
+    println!(
+        "Recording room {} into {}",
+        args.room_id,
+        args.out.display()
+    );
+
+    let tracks = recorder
+        .run_until(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await;
+
+    for track in &tracks {
+        println!(
+            "{}: {} ({} packets)",
+            track.peer_id,
+            track.path.display(),
+            track.packets
+        );
+        if args.upload && track.packets > 0 {
+            let recording = client
+                .upload_recording(&args.room_id, &token, &track.path, track.content_type)
+                .await
+                .map_err(|e| format!("Uploading {} failed: {}", track.path.display(), e))?;
+            println!("  uploaded as recording {}", recording.id);
+        }
+    }
+    if tracks.is_empty() {
+        println!("Nothing was recorded");
+    }
+    Ok(())
+}
+
+/// One track written to disk
+#[derive(Debug, Clone)]
+pub struct RecordedTrack {
+    /// Participant that sent the track
+    pub peer_id: String,
+    pub path: PathBuf,
+    /// Media type to upload the file as
+    pub content_type: &'static str,
+    /// RTP packets written
+    pub packets: u64,
+}
+
+/// A headless peer recording one room
+pub struct Recorder {
+    peer_id: String,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<Vec<RecordedTrack>>,
+}
+
+impl Recorder {
+    /// Join `room_id` with a recorder token and record into `dir`
+    pub async fn start(
+        client: &AxiVidClient,
+        room_id: &str,
+        token: &str,
+        dir: impl Into<PathBuf>,
+    ) -> Result<Self, ClientError> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| ClientError::Media(format!("Cannot create {}: {}", dir.display(), e)))?;
+        let config = rtc::configuration(&client.ice_servers().await?);
+        let api = rtc::api().map_err(media_error)?;
+        let connection = client.connect_with_token(room_id, Some(token)).await?;
+        let peer_id = connection.peer_id().to_string();
+        info!("Recording room {} as {}", room_id, peer_id);
+
+        let (stop, stopped) = oneshot::channel();
+        let session = Session {
+            api,
+            config,
+            sender: connection.sender(),
+            files: Arc::new(Files {
+                dir,
+                next: AtomicUsize::new(1),
+                writers: Mutex::new(JoinSet::new()),
+            }),
+            participants: HashMap::new(),
+            early_candidates: HashMap::new(),
+        };
+        Ok(Self {
+            peer_id,
+            stop,
+            task: tokio::spawn(session.run(connection, stopped)),
+        })
+    }
+
+    /// The recorder's peer ID in the room
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+
+    /// Leave the room and close every file
+    pub async fn stop(self) -> Vec<RecordedTrack> {
+        self.run_until(std::future::ready(())).await
+    }
+
+    /// Record until the room connection ends or `stop` resolves, then
+    /// close every file
+    pub async fn run_until(self, stop: impl Future<Output = ()>) -> Vec<RecordedTrack> {
+        let Self {
+            stop: stopper,
+            mut task,
+            ..
+        } = self;
+        tokio::select! {
+            tracks = &mut task => return tracks.unwrap_or_default(),
+            _ = stop => {}
+        }
+        let _ = stopper.send(());
+        task.await.unwrap_or_default()
+    }
+}
+
+/// Where tracks are written, and the tasks writing them
+struct Files {
+    dir: PathBuf,
+    next: AtomicUsize,
+    writers: Mutex<JoinSet<Option<RecordedTrack>>>,
+}
+
+/// Background state of a [`Recorder`]
+struct Session {
+    api: API,
+    config: RTCConfiguration,
+    sender: RoomSender,
+    files: Arc<Files>,
+    /// Each participant's connection to the recorder
+    participants: HashMap<String, Arc<RTCPeerConnection>>,
+    /// Candidates that arrived before their participant's offer
+    early_candidates: HashMap<String, Vec<RTCIceCandidateInit>>,
+}
+
+impl Session {
+    async fn run(
+        mut self,
+        mut connection: RoomConnection,
+        mut stopped: oneshot::Receiver<()>,
+    ) -> Vec<RecordedTrack> {
+        loop {
+            let msg = tokio::select! {
+                _ = &mut stopped => break,
+                msg = connection.recv_message() => match msg {
+                    Some(msg) => msg,
+                    None => break,
+                },
+            };
+            match msg {
+                WsMessage::Offer {
+                    sdp,
+                    peer_id: Some(from),
+                } => {
+                    if let Err(e) = self.answer(&from, sdp).await {
+                        warn!("Could not answer {}'s offer: {}", from, e);
+                    }
+                }
+                WsMessage::IceCandidate {
+                    candidate,
+                    sdp_mid,
+                    sdp_m_line_index,
+                    peer_id: Some(from),
+                } => {
+                    let init = rtc::candidate_init(candidate, sdp_mid, sdp_m_line_index);
+                    self.add_candidate(from, init).await;
+                }
+                WsMessage::Leave {
+                    peer_id: Some(id), ..
+                } => self.hang_up(&id).await,
+                _ => {}
+            }
+        }
+
+        let ids: Vec<String> = self.participants.keys().cloned().collect();
+        for id in ids {
+            self.hang_up(&id).await;
+        }
+        connection.close().await;
+        let mut writers = std::mem::take(&mut *self.files.writers.lock().unwrap());
+        let mut tracks = Vec::new();
+        while let Some(written) = writers.join_next().await {
+            tracks.extend(written.ok().flatten());
+        }
+        tracks
+    }
+
+    /// Answer a participant's send-only offer with a receiving connection
+    async fn answer(&mut self, from: &str, sdp: String) -> Result<(), webrtc::Error> {
+        let early = self.early_candidates.remove(from).unwrap_or_default();
+        // A fresh offer replaces the participant's previous connection
+        self.hang_up(from).await;
+        let connection = Arc::new(self.api.new_peer_connection(self.config.clone()).await?);
+
+        let sender = self.sender.clone();
+        let to = from.to_string();
+        connection.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+            if let Some(msg) = candidate.and_then(|c| rtc::candidate_message(&c, Some(to.clone())))
+            {
+                let _ = sender.send(msg);
+            }
+            Box::pin(async {})
+        }));
+
+        let files = self.files.clone();
+        let owner = from.to_string();
+        let weak = Arc::downgrade(&connection);
+        connection.on_track(Box::new(move |track, _, _| {
+            let files = files.clone();
+            let owner = owner.clone();
+            let connection = weak.clone();
+            Box::pin(async move {
+                let writers = files.clone();
+                let task = record_track(files, owner, track, connection);
+                writers.writers.lock().unwrap().spawn(task);
+            })
+        }));
+
+        connection
+            .set_remote_description(RTCSessionDescription::offer(sdp)?)
+            .await?;
+        let answer = connection.create_answer(None).await?;
+        let sdp = answer.sdp.clone();
+        connection.set_local_description(answer).await?;
+        for candidate in early {
+            connection.add_ice_candidate(candidate).await?;
+        }
+        let _ = self.sender.send(WsMessage::Answer {
+            sdp,
+            peer_id: Some(from.to_string()),
+        });
+        debug!("Answered {}'s offer", from);
+        self.participants.insert(from.to_string(), connection);
+        Ok(())
+    }
+
+    async fn add_candidate(&mut self, from: String, candidate: RTCIceCandidateInit) {
+        match self.participants.get(&from) {
+            Some(connection) => {
+                if let Err(e) = connection.add_ice_candidate(candidate).await {
+                    debug!("Ignoring {}'s ICE candidate: {}", from, e);
+                }
+            }
+            None => self
+                .early_candidates
+                .entry(from)
+                .or_default()
+                .push(candidate),
+        }
+    }
+
+    /// Close a participant's connection, ending its tracks' files
+    async fn hang_up(&mut self, id: &str) {
+        self.early_candidates.remove(id);
+        if let Some(connection) = self.participants.remove(id) {
+            let _ = connection.close().await;
+        }
+    }
+}
+
+/// Write a track to a new file until it ends
+async fn record_track(
+    files: Arc<Files>,
+    peer_id: String,
+    track: Arc<TrackRemote>,
+    connection: Weak<RTCPeerConnection>,
+) -> Option<RecordedTrack> {
+    let mime = track.codec().capability.mime_type.to_ascii_lowercase();
+    let Some((extension, content_type)) = container(&mime) else {
+        warn!("Not recording {}'s {} track", peer_id, mime);
+        return None;
+    };
+    let n = files.next.fetch_add(1, Ordering::Relaxed);
+    let path = files.dir.join(format!("{}-{}.{}", peer_id, n, extension));
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Cannot create {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let mut writer = match open_writer(&mime, file) {
+        Ok(writer) => writer,
+        Err(e) => {
+            warn!("Cannot start {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    info!(
+        "Recording {}'s {} track to {}",
+        peer_id,
+        mime,
+        path.display()
+    );
+
+    let keyframes = mime.starts_with("video/").then(|| {
+        let ssrc = track.ssrc();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(KEYFRAME_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(connection) = connection.upgrade() else {
+                    break;
+                };
+                let pli = PictureLossIndication {
+                    sender_ssrc: 0,
+                    media_ssrc: ssrc,
+                };
+                if connection.write_rtcp(&[Box::new(pli)]).await.is_err() {
+                    break;
+                }
+            }
+        })
+    });
+
+    let mut packets = 0;
+    while let Ok((packet, _)) = track.read_rtp().await {
+        if let Err(e) = writer.write_rtp(&packet) {
+            warn!("Failed writing {}: {}", path.display(), e);
+            break;
+        }
+        packets += 1;
+    }
+    if let Some(keyframes) = keyframes {
+        keyframes.abort();
+    }
+    if let Err(e) = writer.close() {
+        warn!("Failed to finish {}: {}", path.display(), e);
+    }
+    info!("Finished {} ({} packets)", path.display(), packets);
+    Some(RecordedTrack {
+        peer_id,
+        path,
+        content_type,
+        packets,
+    })
+}
+
+/// File extension and media type for a codec
+fn container(mime: &str) -> Option<(&'static str, &'static str)> {
+    match mime {
+        "audio/opus" => Some(("ogg", "audio/ogg")),
+        "video/vp8" | "video/vp9" => Some(("ivf", "video/x-ivf")),
+        "video/h264" => Some(("h264", "video/h264")),
+        _ => None,
+    }
+}
+
+fn open_writer(mime: &str, file: File) -> Result<Box<dyn Writer + Send>, webrtc::media::Error> {
+    Ok(match mime {
+        "audio/opus" => Box::new(OggWriter::new(file, 48000, 2)?),
+        "video/h264" => Box::new(H264Writer::new(file)),
+        _ => {
+            let four_cc = if mime == "video/vp9" {
+                *b"VP90"
+            } else {
+                *b"VP80"
+            };
+            let header = IVFFileHeader {
+                signature: *b"DKIF",
+                version: 0,
+                header_size: 32,
+                four_cc,
+                width: 640,
+                height: 480,
+                timebase_denominator: 30,
+                timebase_numerator: 1,
+                num_frames: 0,
+                unused: 0,
+            };
+            Box::new(IVFWriter::new(file, &header)?)
+        }
+    })
+}
+
+fn media_error(e: webrtc::Error) -> ClientError {
+    ClientError::Media(e.to_string())
+}
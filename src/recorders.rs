@@ -0,0 +1,196 @@
+//! Hidden recorder peers
+//!
+//! A WebSocket presenting a recorder token joins its room as a recorder
+//! rather than a participant. Recorders take no slot, are not counted in
+//! `room_info` and receive no room traffic except `leave`. A recorder
+//! joining a room that is not being recorded opens a consent round (see
+//! [`crate::consent`]). Only once recording is granted are participants told
+//! about recorders with `recorder_joined`, and only then do browsers send
+//! their media over a separate connection by addressing an offer to the
+//! recorder's `peer_id`.
+
+use axum::extract::ws::{Message, WebSocket};
+use futures::{SinkExt, StreamExt};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::lanes::peer_channel;
+use crate::models::{PeerRole, RoomSettings, WsMessage};
+use crate::state::{AppState, Peer, PeerSender, Room, stamp_sender};
+
+impl Room {
+    /// Deliver signaling addressed to a recorder; returns other messages
+    /// untouched for normal routing
+    pub fn route_to_recorder(&self, sender_id: &str, msg: WsMessage) -> Option<WsMessage> {
+        let target = match &msg {
+            WsMessage::Offer { peer_id, .. }
+            | WsMessage::Answer { peer_id, .. }
+            | WsMessage::IceCandidate { peer_id, .. } => peer_id.as_deref(),
+            _ => None,
+        };
+        let Some(recorder) = target.and_then(|id| self.recorders.iter().find(|r| r.id == id))
+        else {
+            return Some(msg);
+        };
+        if !self.recording {
+            debug!(
+                "Dropping signaling for recorder {} without consent",
+                recorder.id
+            );
+            return None;
+        }
+        let (msg, _) = stamp_sender(msg, sender_id);
+        if let Err(e) = recorder.sender.send(msg) {
+            warn!("Failed to send to recorder {}: {}", recorder.id, e);
+        }
+        None
+    }
+}
+
+impl AppState {
+    /// Add a recorder to an existing room, returning the room's settings
+    ///
+    /// Participants are told about the recorder right away while the room
+    /// is being recorded; otherwise the recorder asks for their consent.
+    pub async fn join_recorder(
+        &self,
+        room_id: &str,
+        peer_id: String,
+        sender: PeerSender,
+    ) -> Result<RoomSettings, &'static str> {
+        let id = peer_id.clone();
+        let joined = self.with_room(room_id, move |room| -> Result<_, &'static str> {
+            if !room.settings.controls.recording {
+                return Err("The host has turned recording off in this room");
+            }
+            room.recorders.push(Peer::new(id.clone(), sender));
+            if room.recording {
+                room.broadcast_to_all(&WsMessage::RecorderJoined { peer_id: id });
+                return Ok((room.settings.clone(), false, None));
+            }
+            let requested = room.consent.is_none();
+            if requested {
+                room.start_consent(&id)?;
+            }
+            Ok((room.settings.clone(), requested, room.settle_consent()))
+        });
+        let (settings, requested, outcome) = joined.await.ok_or("Room not found")??;
+
+        info!("Recorder {} joined room {}", peer_id, room_id);
+        self.record_audit(Some(room_id), &peer_id, "recorder.join", "")
+            .await;
+        if requested {
+            let policy = format!("policy {:?}", settings.consent_policy);
+            self.record_audit(Some(room_id), &peer_id, "recording.request", policy)
+                .await;
+        }
+        if let Some(outcome) = outcome {
+            self.record_consent_outcome(room_id, outcome).await;
+        }
+        Ok(settings)
+    }
+
+    /// Remove a recorder and tell the participants
+    pub async fn leave_recorder(&self, room_id: &str, peer_id: &str) {
+        let id = peer_id.to_string();
+        let left = self.with_room(room_id, move |room| {
+            room.recorders.retain(|r| r.id != id);
+            room.broadcast_to_all(&WsMessage::RecorderLeft { peer_id: id });
+        });
+        if left.await.is_none() {
+            return;
+        }
+
+        info!("Recorder {} left room {}", peer_id, room_id);
+        self.record_audit(Some(room_id), peer_id, "recorder.leave", "")
+            .await;
+    }
+
+    /// Forward a recorder's answer or ICE candidate to the peer it addresses
+    pub async fn relay_from_recorder(&self, room_id: &str, recorder_id: &str, msg: WsMessage) {
+        let policy = self.config().ice_policy;
+        let recorder_id = recorder_id.to_string();
+        self.with_room(room_id, move |room| {
+            let Ok(Some(msg)) = policy.enforce(&room.settings, msg) else {
+                return;
+            };
+            match stamp_sender(msg, &recorder_id) {
+                (
+                    msg @ (WsMessage::Answer { .. } | WsMessage::IceCandidate { .. }),
+                    Some(target),
+                ) => room.send_to(&target, msg),
+                (msg, _) => debug!("Ignoring {:?} from recorder {}", msg, recorder_id),
+            }
+        })
+        .await;
+    }
+}
+
+/// Serve a recorder's WebSocket until either side goes away
+pub async fn handle_recorder_socket(
+    socket: WebSocket,
+    room_id: String,
+    token: String,
+    state: AppState,
+) {
+    let recorder_id = Uuid::new_v4().to_string();
+    let (mut ws_tx, mut ws_rx) = socket.split();
+    let (tx, mut rx) = peer_channel();
+
+    let settings = match state.join_recorder(&room_id, recorder_id.clone(), tx).await {
+        Ok(settings) => settings,
+        Err(e) => {
+            warn!("Recorder rejected from room {}: {}", room_id, e);
+            let error_msg = serde_json::to_string(&WsMessage::error(e)).unwrap();
+            let _ = ws_tx.send(Message::Text(error_msg.into())).await;
+            return;
+        }
+    };
+
+    // Reconnecting with the same token joins as a fresh recorder
+    let welcome = WsMessage::Welcome {
+        peer_id: recorder_id.clone(),
+        presenter: false,
+        role: PeerRole::Viewer,
+        settings,
+        resume_token: token,
+        sharing: None,
+    };
+    if let Ok(text) = serde_json::to_string(&welcome) {
+        let _ = ws_tx.send(Message::Text(text.into())).await;
+    }
+
+    let ws_sender = tokio::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            let Ok(text) = serde_json::to_string(&msg) else {
+                continue;
+            };
+            if ws_tx.send(Message::Text(text.into())).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let ws_receiver = async {
+        while let Some(Ok(frame)) = ws_rx.next().await {
+            let text = match frame {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            match serde_json::from_str::<WsMessage>(&text) {
+                Ok(msg) if msg.check_payload().is_ok() => {
+                    state.relay_from_recorder(&room_id, &recorder_id, msg).await
+                }
+                Ok(_) => warn!("Rejected malformed signaling from recorder {}", recorder_id),
+                Err(e) => warn!("Invalid JSON from recorder {}: {}", recorder_id, e),
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = ws_receiver => {}
+        _ = ws_sender => {}
+    }
+    state.leave_recorder(&room_id, &recorder_id).await;
+}
@@ -0,0 +1,714 @@
+//! Uploaded recordings and their post-processing
+//!
+//! With `recordings` configured, a recorder uploads the finished recording
+//! of its room to `POST /api/room/{id}/recordings`, authenticated with its
+//! recorder token. The file is written under `dir`, and each configured
+//! post-processing step then runs as a background job through ffmpeg:
+//! probing the duration and bitrate, remuxing to MP4, picking a thumbnail
+//! and extracting the audio. The files each step makes are attached to the
+//! recording. `GET /api/room/{id}/recordings` lists a room's recordings
+//! with their artifacts and how each step is going, and files are fetched
+//! from `GET /api/recordings/{id}/files/{name}`. Every recording has a
+//! `recording.json` next to its files, from which the list is read back
+//! on startup. Recordings are kept until deleted with
+//! `DELETE /api/recordings/{id}`.
+//!
+//! With a [`storage`](crate::storage) backend, the files and manifests are
+//! kept there instead, and `dir` only holds working copies for
+//! post-processing: the original is fetched back when a step needs it, and
+//! each artifact is removed once stored.
+
+use std::collections::HashMap;
+use std::path::{Path as FsPath, PathBuf};
+use std::process::Stdio;
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Path, Request, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::admin::{AdminAuth, OperatorAuth};
+use crate::models::{
+    JobKind, JobStatus, Recording, RecordingArtifact, RecordingStep, RecordingStepState,
+};
+use crate::signed_urls::OperatorOrSigned;
+use crate::state::{AppState, unix_timestamp};
+use crate::storage::BlobStore;
+
+/// Name of the file describing a recording, next to its media
+const MANIFEST: &str = "recording.json";
+
+/// Key of a recording's file in the blob store
+fn blob_key(id: &str, name: &str) -> String {
+    format!("recordings/{}/{}", id, name)
+}
+
+/// Where recordings are kept and how they are processed
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecordingsConfig {
+    /// Directory holding one subdirectory per recording
+    pub dir: PathBuf,
+    /// Largest upload accepted, in bytes
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: u64,
+    /// Post-processing run on every upload
+    #[serde(default = "default_steps")]
+    pub steps: Vec<RecordingStep>,
+    /// ffmpeg executable
+    #[serde(default = "default_ffmpeg")]
+    pub ffmpeg: String,
+    /// ffprobe executable
+    #[serde(default = "default_ffprobe")]
+    pub ffprobe: String,
+}
+
+fn default_max_bytes() -> u64 {
+    4 * 1024 * 1024 * 1024
+}
+
+fn default_steps() -> Vec<RecordingStep> {
+    vec![
+        RecordingStep::Probe,
+        RecordingStep::RemuxMp4,
+        RecordingStep::Thumbnail,
+        RecordingStep::AudioOnly,
+    ]
+}
+
+fn default_ffmpeg() -> String {
+    "ffmpeg".to_string()
+}
+
+fn default_ffprobe() -> String {
+    "ffprobe".to_string()
+}
+
+/// Recordings by ID
+pub type Recordings = HashMap<String, Recording>;
+
+type Rejection = (StatusCode, &'static str);
+
+/// File extension for an uploaded media type
+fn extension(content_type: &str) -> &'static str {
+    match content_type.split(';').next().unwrap_or_default().trim() {
+        "video/webm" | "audio/webm" => "webm",
+        "video/mp4" | "audio/mp4" => "mp4",
+        "video/x-matroska" => "mkv",
+        "audio/ogg" | "video/ogg" => "ogg",
+        "video/x-ivf" => "ivf",
+        "video/h264" => "h264",
+        _ => "bin",
+    }
+}
+
+/// The file a step makes and its media type; None for steps that only
+/// read the recording
+fn output(step: RecordingStep) -> Option<(&'static str, &'static str)> {
+    match step {
+        RecordingStep::Probe => None,
+        RecordingStep::RemuxMp4 => Some(("recording.mp4", "video/mp4")),
+        RecordingStep::Thumbnail => Some(("thumbnail.jpg", "image/jpeg")),
+        RecordingStep::AudioOnly => Some(("audio.m4a", "audio/mp4")),
+    }
+}
+
+/// ffmpeg arguments turning `input` into `output` for `step`
+fn ffmpeg_args(step: RecordingStep, input: &FsPath, output: &FsPath) -> Vec<String> {
+    let input = input.to_string_lossy().into_owned();
+    let output = output.to_string_lossy().into_owned();
+    let step_args: &[&str] = match step {
+        RecordingStep::Probe => &[],
+        RecordingStep::RemuxMp4 => &["-c", "copy", "-movflags", "+faststart"],
+        RecordingStep::Thumbnail => &["-vf", "thumbnail", "-frames:v", "1"],
+        RecordingStep::AudioOnly => &["-vn", "-c:a", "aac"],
+    };
+    ["-y", "-v", "error", "-i", &input]
+        .into_iter()
+        .chain(step_args.iter().copied())
+        .chain([output.as_str()])
+        .map(str::to_string)
+        .collect()
+}
+
+/// Run a command, returning its standard output or the tail of its error
+async fn run(program: &str, args: &[String]) -> Result<Vec<u8>, String> {
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if output.status.success() {
+        return Ok(output.stdout);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let detail = stderr.lines().last().unwrap_or_default();
+    Err(format!(
+        "{} exited with {}: {}",
+        program, output.status, detail
+    ))
+}
+
+/// What ffprobe says about a file
+#[derive(Debug, Deserialize)]
+struct Probe {
+    format: ProbeFormat,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbeFormat {
+    duration: Option<String>,
+    bit_rate: Option<String>,
+}
+
+impl AppState {
+    /// Read back the recordings described under `dir`; returns how many
+    pub async fn load_recordings(&self) -> usize {
+        let Some(config) = self.config().recordings.clone() else {
+            return 0;
+        };
+        if let Some(blobs) = self.blobs.clone() {
+            return self.load_stored_recordings(&*blobs).await;
+        }
+        let Ok(mut entries) = tokio::fs::read_dir(&config.dir).await else {
+            return 0;
+        };
+        let mut recordings = self.recordings.lock().await;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let manifest = entry.path().join(MANIFEST);
+            let Ok(json) = tokio::fs::read(&manifest).await else {
+                continue;
+            };
+            match serde_json::from_slice::<Recording>(&json) {
+                Ok(recording) => {
+                    recordings.insert(recording.id.clone(), recording);
+                }
+                Err(e) => warn!("Skipping {}: {}", manifest.display(), e),
+            }
+        }
+        recordings.len()
+    }
+
+    /// Read back the recordings whose manifests are in the blob store
+    async fn load_stored_recordings(&self, blobs: &dyn BlobStore) -> usize {
+        let keys = match blobs.list("recordings/").await {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Failed to list stored recordings: {}", e);
+                return 0;
+            }
+        };
+        let suffix = format!("/{}", MANIFEST);
+        let mut loaded = Vec::new();
+        for key in keys.iter().filter(|k| k.ends_with(&suffix)) {
+            match blobs
+                .get(key)
+                .await
+                .map(|json| json.map(|json| serde_json::from_slice::<Recording>(&json)))
+            {
+                Ok(Some(Ok(recording))) => loaded.push(recording),
+                Ok(Some(Err(e))) => warn!("Skipping {}: {}", key, e),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to read {}: {}", key, e),
+            }
+        }
+        let mut recordings = self.recordings.lock().await;
+        for recording in loaded {
+            recordings.insert(recording.id.clone(), recording);
+        }
+        recordings.len()
+    }
+
+    /// Copy one of a recording's files to the blob store, if there is one
+    async fn publish(
+        &self,
+        id: &str,
+        path: &FsPath,
+        name: &str,
+        content_type: &str,
+    ) -> Result<(), String> {
+        let Some(blobs) = &self.blobs else {
+            return Ok(());
+        };
+        let data = tokio::fs::read(path)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", name, e))?;
+        blobs
+            .put(&blob_key(id, name), content_type, Bytes::from(data))
+            .await
+            .map_err(|e| format!("Failed to store {}: {}", name, e))
+    }
+
+    /// The path of a recording's original in `dir`, fetched from the blob
+    /// store if the working copy is gone
+    async fn local_original(&self, dir: &FsPath, recording: &Recording) -> Result<PathBuf, String> {
+        let path = dir.join(&recording.file);
+        let Some(blobs) = &self.blobs else {
+            return Ok(path);
+        };
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(path);
+        }
+        let data = blobs
+            .get(&blob_key(&recording.id, &recording.file))
+            .await?
+            .ok_or_else(|| "The recording is missing from storage".to_string())?;
+        let failed = |e: std::io::Error| format!("Failed to fetch the recording: {}", e);
+        tokio::fs::create_dir_all(dir).await.map_err(failed)?;
+        tokio::fs::write(&path, data).await.map_err(failed)?;
+        Ok(path)
+    }
+
+    /// Write a recording's manifest next to its files
+    async fn save_manifest(&self, dir: &FsPath, recording: &Recording) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(recording).map_err(|e| e.to_string())?;
+        if let Some(blobs) = &self.blobs {
+            return blobs
+                .put(
+                    &blob_key(&recording.id, MANIFEST),
+                    "application/json",
+                    Bytes::from(json),
+                )
+                .await
+                .map_err(|e| format!("Failed to store the manifest: {}", e));
+        }
+        tokio::fs::write(dir.join(MANIFEST), json)
+            .await
+            .map_err(|e| format!("Failed to write the manifest: {}", e))
+    }
+
+    /// Store an upload for `room_id` and queue its post-processing
+    pub async fn add_recording(
+        &self,
+        room_id: &str,
+        content_type: String,
+        body: axum::body::Body,
+    ) -> Result<Recording, Rejection> {
+        let config = self
+            .config()
+            .recordings
+            .clone()
+            .ok_or((StatusCode::NOT_FOUND, "Recordings are not enabled"))?;
+        let id = Uuid::new_v4().to_string();
+        let dir = config.dir.join(&id);
+        let file = format!("original.{}", extension(&content_type));
+        let stored = self.write_upload(&dir, &file, body, config.max_bytes).await;
+        let size_bytes = match stored {
+            Ok(size) if size > 0 => size,
+            result => {
+                let _ = tokio::fs::remove_dir_all(&dir).await;
+                return Err(match result {
+                    Ok(_) => (StatusCode::BAD_REQUEST, "The recording is empty"),
+                    Err(rejection) => rejection,
+                });
+            }
+        };
+
+        let recording = Recording {
+            id: id.clone(),
+            room_id: room_id.to_string(),
+            file,
+            content_type,
+            size_bytes,
+            uploaded_at: unix_timestamp(),
+            duration_secs: None,
+            bitrate: None,
+            artifacts: Vec::new(),
+            steps: Vec::new(),
+        };
+        let saved = async {
+            let original = dir.join(&recording.file);
+            self.publish(&id, &original, &recording.file, &recording.content_type)
+                .await?;
+            self.save_manifest(&dir, &recording).await
+        };
+        if let Err(e) = saved.await {
+            warn!("Failed to store recording {}: {}", id, e);
+            let _ = tokio::fs::remove_dir_all(&dir).await;
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to store the recording",
+            ));
+        }
+        self.recordings
+            .lock()
+            .await
+            .insert(id.clone(), recording.clone());
+        info!(
+            "Stored recording {} of room {} ({} bytes)",
+            id, room_id, size_bytes
+        );
+        self.record_audit(Some(room_id), "recorder", "recording.upload", &id)
+            .await;
+
+        for step in config.steps {
+            self.enqueue_job(JobKind::ProcessRecording {
+                recording_id: id.clone(),
+                step,
+            })
+            .await;
+        }
+        Ok(self.recording(&id).await.unwrap_or(recording))
+    }
+
+    /// Stream an upload into `dir/file`, up to `max_bytes`
+    async fn write_upload(
+        &self,
+        dir: &FsPath,
+        file: &str,
+        body: axum::body::Body,
+        max_bytes: u64,
+    ) -> Result<u64, Rejection> {
+        let failed = |e: std::io::Error| {
+            warn!("Failed to store a recording: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to store the recording",
+            )
+        };
+        tokio::fs::create_dir_all(dir).await.map_err(failed)?;
+        let mut out = tokio::fs::File::create(dir.join(file))
+            .await
+            .map_err(failed)?;
+        let mut size = 0u64;
+        let mut stream = body.into_data_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|_| (StatusCode::BAD_REQUEST, "Upload interrupted"))?;
+            size += chunk.len() as u64;
+            if size > max_bytes {
+                return Err((StatusCode::PAYLOAD_TOO_LARGE, "Recording too large"));
+            }
+            out.write_all(&chunk).await.map_err(failed)?;
+        }
+        out.flush().await.map_err(failed)?;
+        Ok(size)
+    }
+
+    /// Run one post-processing step and attach what it made
+    pub(crate) async fn process_recording(
+        &self,
+        recording_id: &str,
+        step: RecordingStep,
+    ) -> Result<(), String> {
+        let config = self
+            .config()
+            .recordings
+            .clone()
+            .ok_or_else(|| "Recordings are not enabled".to_string())?;
+        let recording = self
+            .recordings
+            .lock()
+            .await
+            .get(recording_id)
+            .cloned()
+            .ok_or_else(|| "No such recording".to_string())?;
+        let dir = config.dir.join(recording_id);
+        let input = self.local_original(&dir, &recording).await?;
+
+        let (duration_secs, bitrate, artifact) = match output(step) {
+            None => {
+                let args = [
+                    "-v",
+                    "error",
+                    "-show_entries",
+                    "format=duration,bit_rate",
+                    "-of",
+                    "json",
+                ]
+                .into_iter()
+                .map(str::to_string)
+                .chain([input.to_string_lossy().into_owned()])
+                .collect::<Vec<_>>();
+                let stdout = run(&config.ffprobe, &args).await?;
+                let probe: Probe = serde_json::from_slice(&stdout)
+                    .map_err(|e| format!("Unreadable ffprobe output: {}", e))?;
+                let duration = probe.format.duration.and_then(|d| d.parse().ok());
+                let bitrate = probe.format.bit_rate.and_then(|b| b.parse().ok());
+                (duration, bitrate, None)
+            }
+            Some((name, content_type)) => {
+                let path = dir.join(name);
+                run(&config.ffmpeg, &ffmpeg_args(step, &input, &path)).await?;
+                let size_bytes = tokio::fs::metadata(&path)
+                    .await
+                    .map_err(|e| format!("No {} was made: {}", name, e))?
+                    .len();
+                self.publish(recording_id, &path, name, content_type)
+                    .await?;
+                if self.blobs.is_some() {
+                    let _ = tokio::fs::remove_file(&path).await;
+                }
+                let artifact = RecordingArtifact {
+                    step,
+                    name: name.to_string(),
+                    content_type: content_type.to_string(),
+                    size_bytes,
+                };
+                (None, None, Some(artifact))
+            }
+        };
+
+        let mut recordings = self.recordings.lock().await;
+        let recording = recordings
+            .get_mut(recording_id)
+            .ok_or_else(|| "The recording was deleted".to_string())?;
+        if step == RecordingStep::Probe {
+            recording.duration_secs = duration_secs;
+            recording.bitrate = bitrate;
+        }
+        if let Some(artifact) = artifact {
+            recording.artifacts.retain(|a| a.step != step);
+            recording.artifacts.push(artifact);
+        }
+        let recording = recording.clone();
+        drop(recordings);
+        self.save_manifest(&dir, &recording).await
+    }
+
+    /// A recording, with the state of its post-processing
+    pub async fn recording(&self, id: &str) -> Option<Recording> {
+        let mut recording = self.recordings.lock().await.get(id).cloned()?;
+        recording.steps = self
+            .list_jobs(None)
+            .await
+            .into_iter()
+            .rev()
+            .filter_map(|job| match job.kind {
+                JobKind::ProcessRecording { recording_id, step } if recording_id == id => {
+                    Some(RecordingStepState {
+                        step,
+                        job_id: job.id,
+                        status: job.status,
+                        error: job.last_error.filter(|_| job.status != JobStatus::Done),
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        Some(recording)
+    }
+
+    /// A room's recordings, oldest first
+    pub async fn room_recordings(&self, room_id: &str) -> Vec<Recording> {
+        let mut ids: Vec<(u64, String)> = self
+            .recordings
+            .lock()
+            .await
+            .values()
+            .filter(|r| r.room_id == room_id)
+            .map(|r| (r.uploaded_at, r.id.clone()))
+            .collect();
+        ids.sort();
+        let mut recordings = Vec::with_capacity(ids.len());
+        for (_, id) in ids {
+            recordings.extend(self.recording(&id).await);
+        }
+        recordings
+    }
+
+    /// Delete a recording and its files
+    pub async fn delete_recording(&self, id: &str) -> Option<Recording> {
+        let recording = self.recordings.lock().await.remove(id)?;
+        if let Some(blobs) = &self.blobs {
+            let names = [recording.file.as_str(), MANIFEST]
+                .into_iter()
+                .chain(recording.artifacts.iter().map(|a| a.name.as_str()));
+            for name in names {
+                if let Err(e) = blobs.delete(&blob_key(id, name)).await {
+                    warn!("Failed to delete {} of recording {}: {}", name, id, e);
+                }
+            }
+        }
+        // Only working copies are kept locally alongside a blob store
+        if let Some(config) = self.config().recordings.as_ref()
+            && let Err(e) = tokio::fs::remove_dir_all(config.dir.join(id)).await
+            && (self.blobs.is_none() || e.kind() != std::io::ErrorKind::NotFound)
+        {
+            warn!("Failed to delete the files of recording {}: {}", id, e);
+        }
+        Some(recording)
+    }
+}
+
+/// Upload a finished recording
+///
+/// Authenticated with a recorder token for the room. Post-processing starts
+/// in the background; the response lists its steps.
+#[utoipa::path(
+    post,
+    path = "/api/room/{room_id}/recordings",
+    tag = "Rooms",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room")
+    ),
+    request_body(content = Vec<u8>, description = "Recorded media", content_type = "video/webm"),
+    responses(
+        (status = 201, description = "Recording stored", body = Recording),
+        (status = 400, description = "Empty recording"),
+        (status = 401, description = "Missing or invalid recorder token"),
+        (status = 404, description = "Recordings not enabled"),
+        (status = 413, description = "Recording too large")
+    )
+)]
+pub async fn upload_recording(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+    request: Request,
+) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if !state.is_recorder_token(&room_id, token) {
+        return (StatusCode::UNAUTHORIZED, "Recorder token required").into_response();
+    }
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("video/webm")
+        .to_string();
+    match state
+        .add_recording(&room_id, content_type, request.into_body())
+        .await
+    {
+        Ok(recording) => (StatusCode::CREATED, Json(recording)).into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// List a room's recordings with their artifacts
+#[utoipa::path(
+    get,
+    path = "/api/room/{room_id}/recordings",
+    tag = "Admin",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room")
+    ),
+    responses(
+        (status = 200, description = "Recordings, oldest first", body = [Recording]),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_recordings(
+    _auth: AdminAuth,
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+) -> Json<Vec<Recording>> {
+    Json(state.room_recordings(&room_id).await)
+}
+
+/// Download a recording or one of its artifacts
+#[utoipa::path(
+    get,
+    path = "/api/recordings/{id}/files/{name}",
+    tag = "Admin",
+    params(
+        ("id" = String, Path, description = "Recording ID"),
+        ("name" = String, Path, description = "The recording's `file`, or an artifact's `name`"),
+        ("expires" = Option<u64>, Query, description = "Expiry of a signed URL"),
+        ("signature" = Option<String>, Query, description = "Signature of a signed URL")
+    ),
+    responses(
+        (status = 200, description = "The file", body = Vec<u8>, content_type = "application/octet-stream"),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low, or the signed URL has expired"),
+        (status = 404, description = "No such recording or file"),
+        (status = 502, description = "Storage is unavailable")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn get_recording_file(
+    OperatorOrSigned(actor): OperatorOrSigned,
+    Path((id, name)): Path<(String, String)>,
+    State(state): State<AppState>,
+    request: Request,
+) -> Response {
+    let not_found = || (StatusCode::NOT_FOUND, "No such file").into_response();
+    let Some(recording) = state.recording(&id).await else {
+        return not_found();
+    };
+    let content_type = if recording.file == name {
+        recording.content_type.clone()
+    } else {
+        match recording.artifacts.iter().find(|a| a.name == name) {
+            Some(artifact) => artifact.content_type.clone(),
+            None => return not_found(),
+        }
+    };
+    let Some(config) = state.config().recordings.clone() else {
+        return not_found();
+    };
+    state
+        .record_audit(
+            Some(&recording.room_id),
+            &actor,
+            "recording.download",
+            format!("{}/{}", id, name),
+        )
+        .await;
+    if let Some(blobs) = &state.blobs {
+        return match blobs.get(&blob_key(&id, &name)).await {
+            Ok(Some(data)) => ([(header::CONTENT_TYPE, content_type)], data).into_response(),
+            Ok(None) => not_found(),
+            Err(e) => {
+                warn!("Failed to fetch {} of recording {}: {}", name, id, e);
+                (StatusCode::BAD_GATEWAY, "Storage is unavailable").into_response()
+            }
+        };
+    }
+    // Served by extension, with range requests for seeking
+    let path = config.dir.join(&id).join(&name);
+    match ServeFile::new(path).oneshot(request).await {
+        Ok(response) => response.into_response(),
+        Err(never) => match never {},
+    }
+}
+
+/// Delete a recording and its files
+#[utoipa::path(
+    delete,
+    path = "/api/recordings/{id}",
+    tag = "Admin",
+    params(
+        ("id" = String, Path, description = "Recording ID")
+    ),
+    responses(
+        (status = 204, description = "Recording deleted"),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "No such recording")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn delete_recording(
+    OperatorAuth(auth): OperatorAuth,
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    let Some(recording) = state.delete_recording(&id).await else {
+        return (StatusCode::NOT_FOUND, "No such recording").into_response();
+    };
+    state
+        .record_audit(
+            Some(&recording.room_id),
+            &auth.principal,
+            "recording.delete",
+            &id,
+        )
+        .await;
+    StatusCode::NO_CONTENT.into_response()
+}
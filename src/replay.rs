@@ -0,0 +1,340 @@
+//! Replaying signaling captures
+//!
+//! `axi-vid replay <capture>` reads a file written by
+//! [`crate::capture`] and plays it against a running server in a fresh
+//! room: each captured peer becomes a client that joins, sends what the
+//! peer sent and leaves, at the captured times (scaled by `--speed`) and
+//! never before it has been sent as many messages as the captured peer
+//! had been at that point.
+//! Aliases in the messages are swapped for the new clients' peer IDs on
+//! the way out and back on the way in. The report compares, per peer, the
+//! types of message the server sends now with those it sent when the
+//! capture was taken, which is usually enough to see where a negotiation
+//! goes differently.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use clap::Args;
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+use crate::capture::{CaptureEntry, CaptureEvent};
+use crate::config::Config;
+use crate::token::{self, Claims, TokenScope};
+
+/// Options for `axi-vid replay`
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// Capture file to play
+    pub capture: PathBuf,
+    /// Base URL of the target server (defaults to `public_url`)
+    #[arg(long)]
+    pub url: Option<String>,
+    /// Playback speed; 2 plays twice as fast, 0 sends without waiting
+    #[arg(long, default_value_t = 1.0)]
+    pub speed: f64,
+    /// Seconds to keep listening after the last captured event, and at
+    /// most to wait for the messages a peer got before its next one
+    #[arg(long, default_value_t = 2)]
+    pub settle_secs: u64,
+    /// Print every message the clients receive
+    #[arg(long)]
+    pub verbose: bool,
+}
+
+/// How one captured peer fared
+#[derive(Debug, Clone, Default)]
+pub struct PeerReplay {
+    pub alias: String,
+    /// Messages the peer was sent in the capture, by type
+    pub expected: BTreeMap<String, usize>,
+    /// Messages the replayed peer was sent, by type
+    pub received: BTreeMap<String, usize>,
+}
+
+impl PeerReplay {
+    /// Types whose counts differ: captured, then replayed
+    pub fn differences(&self) -> Vec<(String, usize, usize)> {
+        let mut types: Vec<&String> = self.expected.keys().chain(self.received.keys()).collect();
+        types.sort();
+        types.dedup();
+        types
+            .into_iter()
+            .filter_map(|t| {
+                let expected = self.expected.get(t).copied().unwrap_or(0);
+                let received = self.received.get(t).copied().unwrap_or(0);
+                (expected != received).then(|| (t.clone(), expected, received))
+            })
+            .collect()
+    }
+}
+
+/// Outcome of a replay
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    pub room_id: String,
+    pub peers: Vec<PeerReplay>,
+    pub errors: Vec<String>,
+}
+
+/// A message a replayed peer received
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub alias: String,
+    /// Time since the replay started
+    pub at: Duration,
+    /// The message, with peer IDs swapped back for aliases
+    pub message: Value,
+}
+
+/// Read a capture file
+pub fn load_capture(path: &std::path::Path) -> Result<Vec<CaptureEntry>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| serde_json::from_str(line).map_err(|e| format!("Line {}: {}", i + 1, e)))
+        .collect()
+}
+
+/// Run `axi-vid replay` and print a report
+pub async fn run(config: &Config, args: ReplayArgs) -> Result<(), String> {
+    let entries = load_capture(&args.capture)?;
+    let base = args
+        .url
+        .clone()
+        .unwrap_or_else(|| config.public_url.clone());
+    println!(
+        "Replaying {} events from {} against {}",
+        entries.len(),
+        args.capture.display(),
+        base
+    );
+    let settle = Duration::from_secs(args.settle_secs);
+    let print = |r: &Received| {
+        if args.verbose {
+            println!("+{}ms {} <- {}", r.at.as_millis(), r.alias, r.message);
+        }
+    };
+    let report = replay(config, &base, &entries, args.speed, settle, print).await?;
+    println!("Room {}", report.room_id);
+    let mut mismatched = false;
+    for peer in &report.peers {
+        let differences = peer.differences();
+        if differences.is_empty() {
+            println!("{}: received what was captured", peer.alias);
+        }
+        for (kind, expected, received) in differences {
+            mismatched = true;
+            println!(
+                "{}: {} captured {} times, replayed {} times",
+                peer.alias, kind, expected, received
+            );
+        }
+    }
+    for error in &report.errors {
+        println!("Error: {}", error);
+    }
+    match mismatched || !report.errors.is_empty() {
+        true => Err("The replay went differently from the capture".to_string()),
+        false => Ok(()),
+    }
+}
+
+/// Play `entries` against `base` in a fresh room
+pub async fn replay(
+    config: &Config,
+    base: &str,
+    entries: &[CaptureEntry],
+    speed: f64,
+    settle: Duration,
+    on_message: impl Fn(&Received),
+) -> Result<ReplayReport, String> {
+    let ws_base = base
+        .trim_end_matches('/')
+        .replacen("https://", "wss://", 1)
+        .replacen("http://", "ws://", 1);
+    if !ws_base.starts_with("ws://") && !ws_base.starts_with("wss://") {
+        return Err(format!("Unsupported URL: {}", base));
+    }
+    let room_id = Uuid::new_v4().to_string();
+    let url = match config.jwt_secret.as_deref() {
+        Some(secret) => {
+            let claims = Claims::new(TokenScope::Room, Some(room_id.clone()), 3600);
+            let token = token::mint(secret, &claims);
+            format!("{}/ws/{}?token={}", ws_base, room_id, token)
+        }
+        None => format!("{}/ws/{}", ws_base, room_id),
+    };
+
+    let mut report = ReplayReport {
+        room_id,
+        ..Default::default()
+    };
+    let mut peers: BTreeMap<String, PeerReplay> = BTreeMap::new();
+    for entry in entries {
+        let peer = peers
+            .entry(entry.peer.clone())
+            .or_insert_with(|| PeerReplay {
+                alias: entry.peer.clone(),
+                ..Default::default()
+            });
+        if let (CaptureEvent::Out, Some(kind)) =
+            (entry.event, entry.message.as_ref().and_then(kind))
+        {
+            *peer.expected.entry(kind).or_default() += 1;
+        }
+    }
+
+    let started = Instant::now();
+    let (received_tx, mut received_rx) = mpsc::unbounded_channel::<Received>();
+    let mut clients = HashMap::new();
+    // Alias to peer ID, and back
+    let mut ids: HashMap<String, String> = HashMap::new();
+    let aliases: Arc<Mutex<HashMap<String, String>>> = Default::default();
+    // Messages each peer had been sent so far in the capture, and now
+    let mut sent: HashMap<&str, usize> = HashMap::new();
+    let mut got: HashMap<String, usize> = HashMap::new();
+    let mut take = |received: Received, got: &mut HashMap<String, usize>| {
+        on_message(&received);
+        *got.entry(received.alias.clone()).or_default() += 1;
+        if let (Some(peer), Some(kind)) = (peers.get_mut(&received.alias), kind(&received.message))
+        {
+            *peer.received.entry(kind).or_default() += 1;
+        }
+    };
+    for entry in entries {
+        if speed > 0.0 {
+            let due = Duration::from_millis(entry.at_ms).div_f64(speed);
+            tokio::time::sleep_until(started + due).await;
+        }
+        if entry.event == CaptureEvent::Out {
+            *sent.entry(&entry.peer).or_default() += 1;
+            continue;
+        }
+        // Like the captured client, act only once caught up with what
+        // the server sent, giving up after `settle`
+        let wanted = sent.get(entry.peer.as_str()).copied().unwrap_or(0);
+        let deadline = Instant::now() + settle;
+        while got.get(&entry.peer).copied().unwrap_or(0) < wanted {
+            match tokio::time::timeout_at(deadline, received_rx.recv()).await {
+                Ok(Some(received)) => take(received, &mut got),
+                _ => break,
+            }
+        }
+        match entry.event {
+            CaptureEvent::Join => {
+                let connected = tokio_tungstenite::connect_async(url.as_str()).await;
+                let (socket, _) = match connected {
+                    Ok(connected) => connected,
+                    Err(e) => {
+                        report
+                            .errors
+                            .push(format!("{}: connect failed: {}", entry.peer, e));
+                        continue;
+                    }
+                };
+                let (sink, mut stream) = socket.split();
+                let alias = entry.peer.clone();
+                let tx = received_tx.clone();
+                let known = aliases.clone();
+                let (welcomed_tx, welcomed) = tokio::sync::oneshot::channel();
+                let mut welcomed_tx = Some(welcomed_tx);
+                tokio::spawn(async move {
+                    while let Some(Ok(frame)) = stream.next().await {
+                        let Message::Text(text) = frame else { continue };
+                        let Ok(mut message) = serde_json::from_str::<Value>(&text) else {
+                            continue;
+                        };
+                        if let Some(welcomed) = welcomed_tx.take() {
+                            let peer_id = message["peer_id"].as_str().map(str::to_string);
+                            let _ = welcomed.send(peer_id);
+                        }
+                        swap(
+                            &mut message,
+                            &known.lock().unwrap_or_else(|e| e.into_inner()),
+                        );
+                        let at = started.elapsed();
+                        let _ = tx.send(Received {
+                            alias: alias.clone(),
+                            at,
+                            message,
+                        });
+                    }
+                });
+                match welcomed.await {
+                    Ok(Some(peer_id)) => {
+                        aliases
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .insert(peer_id.clone(), entry.peer.clone());
+                        ids.insert(entry.peer.clone(), peer_id);
+                    }
+                    _ => report
+                        .errors
+                        .push(format!("{}: not welcomed to the room", entry.peer)),
+                }
+                clients.insert(entry.peer.clone(), sink);
+            }
+            CaptureEvent::In => {
+                let (Some(sink), Some(message)) = (clients.get_mut(&entry.peer), &entry.message)
+                else {
+                    continue;
+                };
+                let mut message = message.clone();
+                swap(&mut message, &ids);
+                let text = match message {
+                    Value::String(text) => text,
+                    message => message.to_string(),
+                };
+                if let Err(e) = sink.send(Message::text(text)).await {
+                    report
+                        .errors
+                        .push(format!("{}: send failed: {}", entry.peer, e));
+                }
+            }
+            CaptureEvent::Leave => {
+                if let Some(mut sink) = clients.remove(&entry.peer) {
+                    let _ = sink.close().await;
+                }
+            }
+            CaptureEvent::Out => unreachable!("skipped above"),
+        }
+    }
+    let deadline = Instant::now() + settle;
+    while let Ok(Some(received)) = tokio::time::timeout_at(deadline, received_rx.recv()).await {
+        take(received, &mut got);
+    }
+    for (_, mut sink) in clients {
+        let _ = sink.close().await;
+    }
+    report.peers = peers.into_values().collect();
+    Ok(report)
+}
+
+/// A message's `type`
+fn kind(message: &Value) -> Option<String> {
+    message["type"].as_str().map(str::to_string)
+}
+
+/// Replace every string that is a key of `map` with its value
+fn swap(value: &mut Value, map: &HashMap<String, String>) {
+    match value {
+        Value::Object(object) => object.values_mut().for_each(|v| swap(v, map)),
+        Value::Array(items) => items.iter_mut().for_each(|v| swap(v, map)),
+        Value::String(s) => {
+            if let Some(replacement) = map.get(s.as_str()) {
+                *s = replacement.clone();
+            }
+        }
+        _ => {}
+    }
+}
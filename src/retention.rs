@@ -0,0 +1,241 @@
+//! Data retention
+//!
+//! One sweep, on the cleanup schedule, does everything that removes data
+//! by age: it reaps idle rooms (see [`crate::cleanup`]), purges the
+//! archive, then deletes whatever has outlived its own policy under
+//! `cleanup.retention`: chat lines, ended call records, recordings with
+//! their files and client error reports. Each policy is a maximum age in
+//! seconds; without one, chat and call records go when their room leaves
+//! the archive, recordings stay until deleted and client errors are only
+//! capped in number. Rooms whose records changed are written to the record
+//! store again.
+//!
+//! With `dry_run`, the sweep counts what the archive purge and the policies
+//! would delete and deletes nothing; rooms are still reaped. Counts of the
+//! last sweep and totals since startup are served at `/admin/retention`.
+
+use std::collections::BTreeSet;
+
+use axum::{Json, extract::State};
+use serde::Deserialize;
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::admin::AdminAuth;
+use crate::models::{
+    ArtifactRetention, RetentionArtifact, RetentionStats, TranscriptEntry, TranscriptKind,
+};
+use crate::state::{AppState, unix_timestamp};
+
+/// How long each kind of data is kept
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Report what would be deleted without deleting anything
+    pub dry_run: bool,
+    /// Seconds chat lines are kept, e.g. 30 days
+    pub chat_secs: Option<u64>,
+    /// Seconds call records are kept after the call ended, e.g. a year
+    pub call_records_secs: Option<u64>,
+    /// Seconds recordings are kept after upload, e.g. 90 days
+    pub recordings_secs: Option<u64>,
+    /// Seconds client error reports are kept, e.g. 14 days
+    pub client_errors_secs: Option<u64>,
+}
+
+impl AppState {
+    /// Reap rooms, purge the archive and apply every retention policy
+    pub async fn run_retention(&self) -> RetentionStats {
+        let started = Instant::now();
+        let config = self.config();
+        let policy = &config.cleanup.retention;
+        let dry_run = policy.dry_run;
+        let now = unix_timestamp();
+        let cutoff = |secs: Option<u64>| secs.map(|secs| now.saturating_sub(secs));
+
+        self.cleanup_inactive_rooms().await;
+        let archived = self.purge_archive(&config.cleanup.archive, dry_run).await;
+        let mut changed = BTreeSet::new();
+        let chat = match cutoff(policy.chat_secs) {
+            Some(before) => self.expire_chat(before, dry_run, &mut changed).await,
+            None => 0,
+        };
+        let calls = match cutoff(policy.call_records_secs) {
+            Some(before) => {
+                self.expire_call_records(before, dry_run, &mut changed)
+                    .await
+            }
+            None => 0,
+        };
+        let recordings = match cutoff(policy.recordings_secs) {
+            Some(before) => self.expire_recordings(before, dry_run).await,
+            None => 0,
+        };
+        let client_errors = match cutoff(policy.client_errors_secs) {
+            Some(before) => self.expire_client_errors(before, dry_run).await,
+            None => 0,
+        };
+        if !changed.is_empty() {
+            self.persist_rooms(&changed.into_iter().collect::<Vec<_>>())
+                .await;
+        }
+        self.purge_caches().await;
+
+        let swept = [
+            (
+                RetentionArtifact::ArchivedRooms,
+                Some(config.cleanup.archive.retention_secs),
+                archived,
+            ),
+            (RetentionArtifact::Chat, policy.chat_secs, chat),
+            (
+                RetentionArtifact::CallRecords,
+                policy.call_records_secs,
+                calls,
+            ),
+            (
+                RetentionArtifact::Recordings,
+                policy.recordings_secs,
+                recordings,
+            ),
+            (
+                RetentionArtifact::ClientErrors,
+                policy.client_errors_secs,
+                client_errors,
+            ),
+        ];
+        let matched: Vec<String> = swept
+            .iter()
+            .filter(|(_, _, count)| *count > 0)
+            .map(|(artifact, _, count)| format!("{} {:?}", count, artifact))
+            .collect();
+        if !matched.is_empty() {
+            match dry_run {
+                true => info!("Retention dry run would delete {}", matched.join(", ")),
+                false => info!("Retention deleted {}", matched.join(", ")),
+            }
+        }
+
+        let mut stats = self.retention_stats.lock().await;
+        stats.runs += 1;
+        stats.last_run = Some(now);
+        stats.dry_run = dry_run;
+        stats.last_duration_ms = started.elapsed().as_millis() as u64;
+        for (artifact, max_age_secs, count) in swept {
+            let count = count as u64;
+            match stats.artifacts.iter_mut().find(|a| a.artifact == artifact) {
+                Some(entry) => {
+                    entry.max_age_secs = max_age_secs;
+                    entry.last_matched = count;
+                    if !dry_run {
+                        entry.total_deleted += count;
+                    }
+                }
+                None => stats.artifacts.push(ArtifactRetention {
+                    artifact,
+                    max_age_secs,
+                    last_matched: count,
+                    total_deleted: if dry_run { 0 } else { count },
+                }),
+            }
+        }
+        stats.clone()
+    }
+
+    /// Chat lines sent before `before`
+    async fn expire_chat(
+        &self,
+        before: u64,
+        dry_run: bool,
+        changed: &mut BTreeSet<String>,
+    ) -> usize {
+        let mut transcripts = self.transcripts.lock().await;
+        let mut count = 0;
+        for (room_id, transcript) in transcripts.iter_mut() {
+            let expired =
+                |e: &TranscriptEntry| e.kind == TranscriptKind::Chat && e.timestamp < before;
+            let matched = transcript.entries.iter().filter(|e| expired(e)).count();
+            if matched == 0 {
+                continue;
+            }
+            count += matched;
+            if !dry_run {
+                transcript.entries.retain(|e| !expired(e));
+                changed.insert(room_id.clone());
+            }
+        }
+        drop(transcripts);
+        if !dry_run {
+            self.expire_stored_chat(before).await;
+        }
+        count
+    }
+
+    /// Call records of calls that ended before `before`
+    async fn expire_call_records(
+        &self,
+        before: u64,
+        dry_run: bool,
+        changed: &mut BTreeSet<String>,
+    ) -> usize {
+        let mut calls = self.calls.lock().await;
+        let expired: Vec<String> = calls
+            .iter()
+            .filter(|(_, call)| call.ended_at.is_some_and(|t| t < before))
+            .map(|(id, _)| id.clone())
+            .collect();
+        if !dry_run {
+            for id in &expired {
+                calls.remove(id);
+            }
+            changed.extend(expired.iter().cloned());
+        }
+        expired.len()
+    }
+
+    /// Recordings uploaded before `before`, with their files
+    async fn expire_recordings(&self, before: u64, dry_run: bool) -> usize {
+        let expired: Vec<String> = self
+            .recordings
+            .lock()
+            .await
+            .values()
+            .filter(|r| r.uploaded_at < before)
+            .map(|r| r.id.clone())
+            .collect();
+        if !dry_run {
+            for id in &expired {
+                self.delete_recording(id).await;
+            }
+        }
+        expired.len()
+    }
+
+    /// Client error reports received before `before`
+    async fn expire_client_errors(&self, before: u64, dry_run: bool) -> usize {
+        let mut errors = self.client_errors.lock().await;
+        let count = errors.iter().filter(|e| e.received_at < before).count();
+        if !dry_run {
+            errors.retain(|e| e.received_at >= before);
+        }
+        count
+    }
+}
+
+/// Retention sweep counters
+#[utoipa::path(
+    get,
+    path = "/admin/retention",
+    tag = "Admin",
+    responses(
+        (status = 200, description = "What the retention sweep deleted, by kind", body = RetentionStats),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn retention_stats(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+) -> Json<RetentionStats> {
+    Json(state.retention_stats.lock().await.clone())
+}
@@ -0,0 +1,178 @@
+//! One task per room
+//!
+//! Every room's state is owned by a task of its own. Callers send it
+//! closures to run against the [`Room`] and await their results, while the
+//! shared map in [`AppState::rooms`] only holds handles and is locked just
+//! long enough to look one up. Work in one room therefore never waits for
+//! another, and a panic while handling a command stops only that room: it
+//! is logged, the room's peers are disconnected as its state is dropped,
+//! and the room counts as gone from then on.
+//!
+//! A room leaves the map when a command ends it with
+//! [`ControlFlow::Break`] (cleanup, an admin close, shutdown). A caller
+//! that looked the room up just before then finds it gone when its own
+//! command does not run, exactly as if the lookup had come later.
+
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, oneshot};
+use tracing::error;
+
+use crate::state::{AppState, DIAL_CODE_DIGITS, Room};
+
+/// A closure run by a room's task; `Break` stops the room afterwards
+type RoomCommand = Box<dyn FnOnce(&mut Room) -> ControlFlow<()> + Send>;
+
+/// Handle to a running room
+#[derive(Debug, Clone)]
+pub struct RoomHandle {
+    commands: mpsc::UnboundedSender<RoomCommand>,
+    /// The room's dial-in code, fixed when it is created
+    pub dial_code: Arc<str>,
+}
+
+impl RoomHandle {
+    /// Start a task owning `room`
+    pub fn spawn(room_id: String, mut room: Room) -> Self {
+        let (commands, mut rx) = mpsc::unbounded_channel::<RoomCommand>();
+        let dial_code = Arc::from(room.dial_code.as_str());
+        tokio::spawn(async move {
+            while let Some(command) = rx.recv().await {
+                match catch_unwind(AssertUnwindSafe(|| command(&mut room))) {
+                    Ok(ControlFlow::Continue(())) => {}
+                    Ok(ControlFlow::Break(())) => break,
+                    Err(_) => {
+                        error!("Room {} panicked and was closed", room_id);
+                        break;
+                    }
+                }
+            }
+        });
+        Self {
+            commands,
+            dial_code,
+        }
+    }
+
+    /// Run `f` in the room, or `None` if the room has stopped
+    pub async fn call<T, F>(&self, f: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Room) -> T + Send + 'static,
+    {
+        self.call_until(|room| ControlFlow::Continue(f(room))).await
+    }
+
+    /// Run `f` in the room and stop the room if it returns `Break`
+    pub async fn call_until<T, F>(&self, f: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Room) -> ControlFlow<T, T> + Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let command: RoomCommand = Box::new(move |room| {
+            let (value, flow) = match f(room) {
+                ControlFlow::Continue(value) => (value, ControlFlow::Continue(())),
+                ControlFlow::Break(value) => (value, ControlFlow::Break(())),
+            };
+            let _ = reply.send(value);
+            flow
+        });
+        self.commands.send(command).ok()?;
+        result.await.ok()
+    }
+
+    /// Whether the room's task has stopped
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+
+    fn same_room(&self, other: &RoomHandle) -> bool {
+        self.commands.same_channel(&other.commands)
+    }
+}
+
+/// Running rooms by ID, as stored in [`AppState`]
+pub type RoomHandles = HashMap<String, RoomHandle>;
+
+impl AppState {
+    /// Handle to a running room
+    pub async fn room(&self, room_id: &str) -> Option<RoomHandle> {
+        let rooms = self.rooms.lock().await;
+        rooms.get(room_id).filter(|h| !h.is_closed()).cloned()
+    }
+
+    /// Run `f` in a room, or `None` if there is no such room
+    pub async fn with_room<T, F>(&self, room_id: &str, f: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Room) -> T + Send + 'static,
+    {
+        self.room(room_id).await?.call(f).await
+    }
+
+    /// Handles to every running room
+    pub async fn room_handles(&self) -> Vec<(String, RoomHandle)> {
+        let rooms = self.rooms.lock().await;
+        rooms
+            .iter()
+            .filter(|(_, h)| !h.is_closed())
+            .map(|(id, h)| (id.clone(), h.clone()))
+            .collect()
+    }
+
+    /// The running room `room_id`, starting `make()` if there is none; the
+    /// flag is true when the room was started
+    ///
+    /// The room keeps a dial-in code `make()` set unless a running room has
+    /// it, and gets a fresh one otherwise. However it was started, a room
+    /// without a duration limit gets `call_limits.default_max_duration_secs`.
+    pub async fn room_or_start(
+        &self,
+        room_id: &str,
+        make: impl FnOnce() -> Room,
+    ) -> (RoomHandle, bool) {
+        let mut rooms = self.rooms.lock().await;
+        if let Some(handle) = rooms.get(room_id).filter(|h| !h.is_closed()) {
+            return (handle.clone(), false);
+        }
+        let mut room = make();
+        let default_limit = self.config().call_limits.default_max_duration_secs;
+        room.settings.max_duration_secs = room.settings.max_duration_secs.or(default_limit);
+        room.features = self.feature_flags(&room.settings.features).await;
+        if room.dial_code.is_empty() || dial_code_taken(&rooms, &room.dial_code) {
+            room.dial_code = new_dial_code(&rooms);
+        }
+        let handle = RoomHandle::spawn(room_id.to_string(), room);
+        rooms.insert(room_id.to_string(), handle.clone());
+        (handle, true)
+    }
+
+    /// Drop a stopped room from the map, unless it was replaced meanwhile
+    pub async fn forget_room(&self, room_id: &str, handle: &RoomHandle) {
+        let mut rooms = self.rooms.lock().await;
+        if rooms.get(room_id).is_some_and(|h| h.same_room(handle)) {
+            rooms.remove(room_id);
+        }
+    }
+}
+
+/// Pick a dial-in code not used by any running room
+fn new_dial_code(rooms: &RoomHandles) -> String {
+    loop {
+        let n = uuid::Uuid::new_v4().as_u128() % 10u128.pow(DIAL_CODE_DIGITS as u32);
+        let code = format!("{:0width$}", n, width = DIAL_CODE_DIGITS);
+        if !dial_code_taken(rooms, &code) {
+            return code;
+        }
+    }
+}
+
+fn dial_code_taken(rooms: &RoomHandles, code: &str) -> bool {
+    rooms
+        .values()
+        .any(|h| !h.is_closed() && *h.dial_code == *code)
+}
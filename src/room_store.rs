@@ -0,0 +1,279 @@
+//! Room state shared through a database
+//!
+//! A running room lives in the memory of the instance running it. With a
+//! room store attached (the `postgres` feature), what a room should keep
+//! past that instance is written out as a [`RoomSnapshot`]: its settings,
+//! tags, tenant, dial-in code, presenter token, invitees, share links,
+//! owner and bans. It is saved when the room starts and after joins,
+//! invitations, link changes, bans and host commands, and deleted when the
+//! room ends. Chat messages are stored one by one as they are sent, cut to
+//! the scrubbing policy, and expire with the chat retention policy. An
+//! instance that gets a peer for a room it does not run starts the room
+//! from its snapshot and chat history, so a restart, a crash or a drain
+//! loses neither its settings, nor who may or may not join, nor what was
+//! said.
+//!
+//! Instances with `cluster` configured also agree through the store on
+//! where each room runs. The first instance to get a peer for a room
+//! claims it, and peers connecting anywhere else are proxied to the
+//! claimant for as long as it keeps up its heartbeat, sent with every
+//! gossip round. Claims of an instance presumed gone may be taken over,
+//! and an instance releases its claims when it shuts down.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::models::{RoomMetadata, RoomSettings, RoomTranscript, TranscriptEntry, TranscriptKind};
+use crate::share_links::ShareLink;
+use crate::state::{AppState, Room};
+use crate::transcript::MAX_TRANSCRIPT_ENTRIES;
+
+/// What a room keeps when it moves to another instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomSnapshot {
+    pub settings: RoomSettings,
+    #[serde(default)]
+    pub metadata: RoomMetadata,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    pub dial_code: String,
+    #[serde(default)]
+    pub presenter_token: Option<String>,
+    #[serde(default)]
+    pub invitees: Option<Vec<String>>,
+    #[serde(default)]
+    pub share_links: Vec<ShareLink>,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub bans: Vec<String>,
+}
+
+impl RoomSnapshot {
+    /// A room picking up where the snapshot left off
+    pub fn into_room(self) -> Room {
+        Room {
+            metadata: self.metadata,
+            dial_code: self.dial_code,
+            presenter_token: self.presenter_token,
+            invitees: self.invitees,
+            share_links: self.share_links,
+            owner: self.owner,
+            bans: self.bans,
+            ..Room::with_settings(self.settings)
+        }
+    }
+}
+
+impl Room {
+    /// The room's lasting state, on behalf of `tenant`
+    pub fn snapshot(&self, tenant: Option<String>) -> RoomSnapshot {
+        RoomSnapshot {
+            settings: self.settings.clone(),
+            metadata: self.metadata.clone(),
+            tenant,
+            dial_code: self.dial_code.clone(),
+            presenter_token: self.presenter_token.clone(),
+            invitees: self.invitees.clone(),
+            share_links: self.share_links.clone(),
+            owner: self.owner.clone(),
+            bans: self.bans.clone(),
+        }
+    }
+}
+
+/// Somewhere instances share the state of running rooms
+pub trait RoomStore: Debug + Send + Sync {
+    /// Insert or replace a room's snapshot
+    fn save_room(&self, room_id: String, room: RoomSnapshot) -> BoxFuture<'_, Result<(), String>>;
+    /// A room's snapshot, if it is stored
+    fn load_room(&self, room_id: String) -> BoxFuture<'_, Result<Option<RoomSnapshot>, String>>;
+    /// Delete a room's snapshot and claim
+    fn delete_room(&self, room_id: String) -> BoxFuture<'_, Result<(), String>>;
+    /// Claim `room_id` for `instance` unless another instance seen within
+    /// the last `ttl_secs` holds it; returns the instance holding it
+    fn claim_room(
+        &self,
+        room_id: String,
+        instance: String,
+        ttl_secs: u64,
+    ) -> BoxFuture<'_, Result<String, String>>;
+    /// Note that `instance` is alive, dropping its claims older than
+    /// `ttl_secs` on rooms other than `running`
+    fn heartbeat(
+        &self,
+        instance: String,
+        running: Vec<String>,
+        ttl_secs: u64,
+    ) -> BoxFuture<'_, Result<(), String>>;
+    /// Drop every claim of `instance`
+    fn release_rooms(&self, instance: String) -> BoxFuture<'_, Result<(), String>>;
+    /// Append a chat message to a room's history
+    fn save_chat(
+        &self,
+        room_id: String,
+        entry: TranscriptEntry,
+    ) -> BoxFuture<'_, Result<(), String>>;
+    /// A room's chat history, oldest first
+    fn load_chat(&self, room_id: String) -> BoxFuture<'_, Result<Vec<TranscriptEntry>, String>>;
+    /// Delete chat messages sent before `before` (Unix seconds)
+    fn expire_chat(&self, before: u64) -> BoxFuture<'_, Result<(), String>>;
+}
+
+impl AppState {
+    /// Share running rooms through `store` from now on
+    pub fn attach_room_store(&self, store: Arc<dyn RoomStore>) -> Result<(), String> {
+        self.room_store
+            .set(store)
+            .map_err(|_| "A room store is already attached".to_string())
+    }
+
+    /// Write a running room's snapshot to the store, if any
+    pub async fn save_room_state(&self, room_id: &str) {
+        let Some(store) = self.room_store.get() else {
+            return;
+        };
+        let tenant = (self.calls.lock().await.get(room_id)).and_then(|c| c.tenant.clone());
+        let Some(snapshot) = self
+            .with_room(room_id, move |room| room.snapshot(tenant))
+            .await
+        else {
+            return;
+        };
+        if let Err(e) = store.save_room(room_id.to_string(), snapshot).await {
+            warn!("Failed to store the state of room {}: {}", room_id, e);
+        }
+    }
+
+    /// Start `room_id` from its stored snapshot unless it runs here
+    pub async fn restore_room(&self, room_id: &str) {
+        let Some(store) = self.room_store.get() else {
+            return;
+        };
+        if self.room(room_id).await.is_some() {
+            return;
+        }
+        let snapshot = match store.load_room(room_id.to_string()).await {
+            Ok(Some(snapshot)) => snapshot,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Failed to load the state of room {}: {}", room_id, e);
+                return;
+            }
+        };
+        info!("Restoring room {} from the room store", room_id);
+        let (mode, metadata) = (snapshot.settings.mode, snapshot.metadata.clone());
+        let tenant = snapshot.tenant.clone();
+        self.start_room(room_id, mode, metadata, tenant, || snapshot.into_room())
+            .await;
+        match store.load_chat(room_id.to_string()).await {
+            Ok(chat) => self.restore_chat(room_id, chat).await,
+            Err(e) => warn!("Failed to load the chat of room {}: {}", room_id, e),
+        }
+    }
+
+    /// Put a room's stored chat history in its transcript, in place of
+    /// the chat this instance has
+    async fn restore_chat(&self, room_id: &str, chat: Vec<TranscriptEntry>) {
+        if chat.is_empty() {
+            return;
+        }
+        let mut transcripts = self.transcripts.lock().await;
+        let transcript = transcripts
+            .entry(room_id.to_string())
+            .or_insert_with(|| RoomTranscript {
+                room_id: room_id.to_string(),
+                ..Default::default()
+            });
+        transcript
+            .entries
+            .retain(|e| e.kind != TranscriptKind::Chat);
+        transcript.entries.extend(chat);
+        transcript.entries.sort_by_key(|e| e.timestamp);
+        transcript.entries.truncate(MAX_TRANSCRIPT_ENTRIES);
+    }
+
+    /// Write a chat message to the store, if any
+    pub async fn store_chat(&self, room_id: &str, mut entry: TranscriptEntry) {
+        let Some(store) = self.room_store.get() else {
+            return;
+        };
+        if let Some(scrubbing) = self.config().scrubbing.as_ref().filter(|s| s.persisted) {
+            entry.text = scrubbing.chat(&entry.text);
+        }
+        if let Err(e) = store.save_chat(room_id.to_string(), entry).await {
+            warn!("Failed to store a chat message of room {}: {}", room_id, e);
+        }
+    }
+
+    /// Delete stored chat messages sent before `before`
+    pub async fn expire_stored_chat(&self, before: u64) {
+        if let Some(store) = self.room_store.get()
+            && let Err(e) = store.expire_chat(before).await
+        {
+            warn!("Failed to expire stored chat: {}", e);
+        }
+    }
+
+    /// Delete the stored state of rooms that have ended
+    pub async fn forget_room_states(&self, room_ids: &[String]) {
+        let Some(store) = self.room_store.get() else {
+            return;
+        };
+        for room_id in room_ids {
+            if let Err(e) = store.delete_room(room_id.clone()).await {
+                warn!("Failed to delete the state of room {}: {}", room_id, e);
+            }
+        }
+    }
+
+    /// The instance holding `room_id` in the store, claiming it for this
+    /// one if nobody alive does; `None` without a store or cluster
+    pub async fn claim_room(&self, room_id: &str) -> Option<String> {
+        let store = self.room_store.get()?;
+        let config = self.config();
+        let cluster = config.cluster.as_ref()?;
+        let claimed = store
+            .claim_room(
+                room_id.to_string(),
+                cluster.advertise_url.clone(),
+                cluster.member_ttl().as_secs(),
+            )
+            .await;
+        match claimed {
+            Ok(home) => Some(home),
+            Err(e) => {
+                warn!("Failed to claim room {}: {}", room_id, e);
+                None
+            }
+        }
+    }
+
+    /// Keep this instance's claims alive
+    pub async fn room_store_heartbeat(&self) {
+        let (Some(store), Some(cluster)) = (self.room_store.get(), self.config().cluster.clone())
+        else {
+            return;
+        };
+        let running = self.rooms.lock().await.keys().cloned().collect();
+        let ttl = cluster.member_ttl().as_secs();
+        if let Err(e) = store.heartbeat(cluster.advertise_url, running, ttl).await {
+            warn!("Failed to send the room store heartbeat: {}", e);
+        }
+    }
+
+    /// Give up this instance's claims, so other instances take its rooms
+    pub async fn release_room_claims(&self) {
+        let (Some(store), Some(cluster)) = (self.room_store.get(), self.config().cluster.clone())
+        else {
+            return;
+        };
+        if let Err(e) = store.release_rooms(cluster.advertise_url).await {
+            warn!("Failed to release room claims: {}", e);
+        }
+    }
+}
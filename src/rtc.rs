@@ -0,0 +1,71 @@
+//! webrtc-rs plumbing for headless peers
+//!
+//! Used by the recorder and the canary's loopback call: a WebRTC API with
+//! the default codecs and interceptors, peer connection settings from the server's ICE
+//! servers, and conversions between webrtc-rs ICE candidates and `ice`
+//! messages.
+
+use webrtc::api::interceptor_registry::register_default_interceptors;
+use webrtc::api::media_engine::MediaEngine;
+use webrtc::api::{API, APIBuilder};
+use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::interceptor::registry::Registry;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+
+use crate::config::IceServer;
+use crate::models::WsMessage;
+
+/// A WebRTC API that negotiates the default audio and video codecs
+pub fn api() -> Result<API, webrtc::Error> {
+    let mut media = MediaEngine::default();
+    media.register_default_codecs()?;
+    let registry = register_default_interceptors(Registry::new(), &mut media)?;
+    Ok(APIBuilder::new()
+        .with_media_engine(media)
+        .with_interceptor_registry(registry)
+        .build())
+}
+
+/// Peer connection settings using `ice_servers`
+pub fn configuration(ice_servers: &[IceServer]) -> RTCConfiguration {
+    RTCConfiguration {
+        ice_servers: ice_servers
+            .iter()
+            .map(|server| RTCIceServer {
+                urls: server.urls.clone(),
+                username: server.username.clone().unwrap_or_default(),
+                credential: server.credential.clone().unwrap_or_default(),
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// An `ice` message trickling a local candidate to `peer_id`
+pub fn candidate_message(
+    candidate: &RTCIceCandidate,
+    peer_id: Option<String>,
+) -> Option<WsMessage> {
+    let init = candidate.to_json().ok()?;
+    Some(WsMessage::IceCandidate {
+        candidate: init.candidate,
+        sdp_m_line_index: init.sdp_mline_index.unwrap_or_default().into(),
+        sdp_mid: init.sdp_mid,
+        peer_id,
+    })
+}
+
+/// A remote candidate from an `ice` message
+pub fn candidate_init(
+    candidate: String,
+    sdp_mid: Option<String>,
+    index: u32,
+) -> RTCIceCandidateInit {
+    RTCIceCandidateInit {
+        candidate,
+        sdp_mid,
+        sdp_mline_index: u16::try_from(index).ok(),
+        username_fragment: None,
+    }
+}
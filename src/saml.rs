@@ -0,0 +1,766 @@
+//! SAML 2.0 single sign-on
+//!
+//! With a `saml` section, the server is a SAML service provider for
+//! identity providers that do not speak OIDC. `/saml/metadata` describes
+//! it to the identity provider, `/saml/login` sends the browser there with
+//! an authentication request (HTTP-Redirect binding), and the identity
+//! provider posts its response back to `/saml/acs` (HTTP-POST binding).
+//!
+//! A response is accepted when it or its one assertion carries an XML
+//! signature by `idp_certificate` (RSA-SHA256 over exclusive canonical
+//! XML, SHA-256 digest), it is addressed to the ACS URL, the assertion is
+//! issued by `idp_entity_id`, its conditions hold, it names this service
+//! provider as audience and the ACS URL as recipient, and it was not used
+//! before. It must answer an authentication request this server sent
+//! within the last ten minutes, each answered once; responses the identity
+//! provider sends unasked are refused unless `allow_idp_initiated` is set.
+//! Encrypted assertions are not supported.
+//!
+//! The subject's NameID and the `email_attribute` (or an email-shaped
+//! NameID) become the `sub` and `email` of a user token minted with
+//! `jwt_secret`. The browser is sent on to the `RelayState` path with the
+//! token as `?token=`, from where invite-only rooms, personal rooms and
+//! lobbies recognize the user as they do any user token.
+//!
+//! Checking responses needs the `saml` feature; a server built without it
+//! publishes its metadata but refuses every response.
+
+use std::io::Write;
+use std::time::Duration;
+
+use axum::{
+    extract::{Form, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Redirect, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use flate2::{Compression, write::DeflateEncoder};
+use serde::Deserialize;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::cache::EphemeralCache;
+use crate::config::Config;
+use crate::state::{AppState, unix_timestamp};
+use crate::token::{self, Claims, TokenScope};
+
+const PROTOCOL_NS: &str = "urn:oasis:names:tc:SAML:2.0:protocol";
+const ASSERTION_NS: &str = "urn:oasis:names:tc:SAML:2.0:assertion";
+const POST_BINDING: &str = "urn:oasis:names:tc:SAML:2.0:bindings:HTTP-POST";
+
+/// Used assertions remembered against replay; the least recently seen are
+/// forgotten first
+pub const MAX_SEEN_ASSERTIONS: usize = 10_000;
+
+/// How long a used assertion is remembered; assertions valid for longer
+/// are refused
+pub const SEEN_ASSERTION_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// IDs of assertions already used to sign in
+pub type SeenAssertions = EphemeralCache<String, ()>;
+
+/// Authentication requests awaiting an answer; the oldest are forgotten
+/// first
+pub const MAX_PENDING_REQUESTS: usize = 10_000;
+
+/// How long an authentication request can be answered
+pub const PENDING_REQUEST_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// IDs of authentication requests sent and not answered yet
+pub type PendingRequests = EphemeralCache<String, ()>;
+
+/// The identity provider, and how its users are signed in
+#[derive(Debug, Clone, Deserialize)]
+pub struct SamlConfig {
+    /// Single sign-on URL of the identity provider (HTTP-Redirect binding)
+    pub idp_sso_url: String,
+    /// Signing certificate of the identity provider, PEM or base64 DER
+    pub idp_certificate: String,
+    /// Expected `Issuer` of assertions
+    pub idp_entity_id: String,
+    /// Accept responses that answer no request of ours, i.e. sign-ins
+    /// started at the identity provider
+    #[serde(default)]
+    pub allow_idp_initiated: bool,
+    /// Entity ID of this service provider; `{public_url}/saml/metadata`
+    /// when unset
+    #[serde(default)]
+    pub entity_id: Option<String>,
+    /// Assertion attribute holding the user's email address
+    #[serde(default = "default_email_attribute")]
+    pub email_attribute: String,
+    /// Seconds the minted user tokens are valid
+    #[serde(default = "default_token_ttl_secs")]
+    pub token_ttl_secs: u64,
+    /// Seconds of clock difference with the identity provider tolerated
+    #[serde(default = "default_clock_skew_secs")]
+    pub clock_skew_secs: u64,
+}
+
+fn default_email_attribute() -> String {
+    "email".to_string()
+}
+
+fn default_token_ttl_secs() -> u64 {
+    12 * 3600
+}
+
+fn default_clock_skew_secs() -> u64 {
+    60
+}
+
+impl SamlConfig {
+    /// Entity ID of this service provider
+    pub fn sp_entity_id(&self, public_url: &str) -> String {
+        self.entity_id
+            .clone()
+            .unwrap_or_else(|| format!("{}/saml/metadata", public_url.trim_end_matches('/')))
+    }
+
+    /// Where the identity provider posts its responses
+    pub fn acs_url(&self, public_url: &str) -> String {
+        format!("{}/saml/acs", public_url.trim_end_matches('/'))
+    }
+}
+
+/// Who an accepted assertion is about
+#[derive(Debug, Clone)]
+pub struct SamlIdentity {
+    pub name_id: String,
+    pub email: Option<String>,
+    /// ID of the assertion, remembered against replay
+    pub assertion_id: String,
+    /// ID of the authentication request answered; unset when the identity
+    /// provider started the sign-in
+    pub in_response_to: Option<String>,
+}
+
+impl AppState {
+    /// Check a base64 `SAMLResponse` and mint a user token for its subject
+    pub async fn saml_sign_in(&self, encoded: &str) -> Result<(SamlIdentity, String), String> {
+        let config = self.config();
+        let saml = config.saml.as_ref().ok_or("SAML is not enabled")?;
+        let secret = config
+            .jwt_secret
+            .as_deref()
+            .ok_or("SAML sign-in needs jwt_secret")?;
+        let identity = verify_response(saml, &config.public_url, encoded, unix_timestamp())?;
+
+        if let Some(id) = &identity.in_response_to {
+            if self.saml_requests.lock().await.remove(id).is_none() {
+                return Err("Response answers no pending request".to_string());
+            }
+        } else if !saml.allow_idp_initiated {
+            return Err("Unsolicited responses are not accepted".to_string());
+        }
+
+        let mut seen = self.saml_assertions.lock().await;
+        if seen.get_mut(&identity.assertion_id).is_some() {
+            return Err("Assertion was already used".to_string());
+        }
+        seen.insert(identity.assertion_id.clone(), ());
+        drop(seen);
+
+        let mut claims = Claims::new(TokenScope::User, None, saml.token_ttl_secs);
+        claims.sub = Some(identity.name_id.clone());
+        claims.email = identity.email.clone();
+        Ok((identity, token::mint(secret, &claims)))
+    }
+}
+
+/// `&`, `<` and `"` escaped for an XML attribute value
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+}
+
+/// Service provider metadata for the identity provider
+pub fn sp_metadata(config: &Config, saml: &SamlConfig) -> String {
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+            r#"<md:EntityDescriptor xmlns:md="urn:oasis:names:tc:SAML:2.0:metadata" entityID="{entity_id}">"#,
+            r#"<md:SPSSODescriptor AuthnRequestsSigned="false" WantAssertionsSigned="true" protocolSupportEnumeration="{protocol}">"#,
+            r#"<md:NameIDFormat>urn:oasis:names:tc:SAML:1.1:nameid-format:emailAddress</md:NameIDFormat>"#,
+            r#"<md:AssertionConsumerService Binding="{binding}" Location="{acs}" index="0" isDefault="true"/>"#,
+            r#"</md:SPSSODescriptor>"#,
+            r#"</md:EntityDescriptor>"#,
+        ),
+        entity_id = escape_attribute(&saml.sp_entity_id(&config.public_url)),
+        protocol = PROTOCOL_NS,
+        binding = POST_BINDING,
+        acs = escape_attribute(&saml.acs_url(&config.public_url)),
+    )
+}
+
+/// The identity provider's sign-in URL, carrying an authentication request
+/// with ID `id` and `relay_state`
+pub fn login_url(
+    config: &Config,
+    saml: &SamlConfig,
+    id: &str,
+    relay_state: &str,
+) -> Result<String, String> {
+    let request = format!(
+        concat!(
+            r#"<samlp:AuthnRequest xmlns:samlp="{protocol}" xmlns:saml="{assertion}" "#,
+            r#"ID="{id}" Version="2.0" IssueInstant="{now}" Destination="{destination}" "#,
+            r#"AssertionConsumerServiceURL="{acs}" ProtocolBinding="{binding}">"#,
+            r#"<saml:Issuer>{issuer}</saml:Issuer>"#,
+            r#"</samlp:AuthnRequest>"#,
+        ),
+        protocol = PROTOCOL_NS,
+        assertion = ASSERTION_NS,
+        id = escape_attribute(id),
+        now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+        destination = escape_attribute(&saml.idp_sso_url),
+        acs = escape_attribute(&saml.acs_url(&config.public_url)),
+        binding = POST_BINDING,
+        issuer = escape_attribute(&saml.sp_entity_id(&config.public_url)),
+    );
+    // The Redirect binding carries the request raw-deflated, then base64
+    let mut deflater = DeflateEncoder::new(Vec::new(), Compression::default());
+    deflater
+        .write_all(request.as_bytes())
+        .and_then(|()| deflater.finish())
+        .map(|deflated| STANDARD.encode(deflated))
+        .map_err(|e| e.to_string())
+        .and_then(|encoded| {
+            reqwest::Url::parse_with_params(
+                &saml.idp_sso_url,
+                [
+                    ("SAMLRequest", encoded.as_str()),
+                    ("RelayState", relay_state),
+                ],
+            )
+            .map_err(|e| format!("Invalid idp_sso_url: {}", e))
+        })
+        .map(String::from)
+}
+
+/// A same-site path to continue to after signing in, or `/`
+fn relay_path(relay_state: Option<&str>) -> &str {
+    relay_state
+        .filter(|p| p.starts_with('/') && !p.starts_with("//") && !p.contains('\\'))
+        .unwrap_or("/")
+}
+
+#[cfg(feature = "saml")]
+use response::verify_response;
+
+#[cfg(not(feature = "saml"))]
+fn verify_response(
+    _saml: &SamlConfig,
+    _public_url: &str,
+    _encoded: &str,
+    _now: u64,
+) -> Result<SamlIdentity, String> {
+    Err("This server was built without the saml feature".to_string())
+}
+
+#[cfg(feature = "saml")]
+mod response {
+    use ring::signature::{RSA_PKCS1_2048_8192_SHA256, UnparsedPublicKey};
+    use roxmltree::{Document, Node, NodeId, NodeType};
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+    use crate::push::pem_der;
+
+    const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+    const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+    const ENVELOPED: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+    const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+    const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+    const SUCCESS: &str = "urn:oasis:names:tc:SAML:2.0:status:Success";
+
+    /// Check a base64 response at `now` and read who it is about
+    pub fn verify_response(
+        saml: &SamlConfig,
+        public_url: &str,
+        encoded: &str,
+        now: u64,
+    ) -> Result<SamlIdentity, String> {
+        let xml = STANDARD
+            .decode(encoded.split_whitespace().collect::<String>())
+            .map_err(|_| "SAMLResponse is not base64")?;
+        let xml = String::from_utf8(xml).map_err(|_| "SAMLResponse is not UTF-8")?;
+        // DTDs are refused by the parser, so entities cannot expand
+        let doc = Document::parse(&xml).map_err(|e| format!("Malformed SAML response: {}", e))?;
+        let response = doc.root_element();
+        if !response.has_tag_name((PROTOCOL_NS, "Response")) {
+            return Err("Not a SAML response".to_string());
+        }
+        let status = child(response, PROTOCOL_NS, "Status")
+            .and_then(|s| child(s, PROTOCOL_NS, "StatusCode"))
+            .and_then(|c| c.attribute("Value"));
+        if status != Some(SUCCESS) {
+            return Err(format!(
+                "Identity provider answered {}",
+                status.unwrap_or("without a status")
+            ));
+        }
+        let acs_url = saml.acs_url(public_url);
+        if response.attribute("Destination") != Some(acs_url.as_str()) {
+            return Err("Response was sent to another endpoint".to_string());
+        }
+
+        // Exactly one assertion, right under the response, so a signed one
+        // cannot be wrapped next to a forged one
+        let assertions: Vec<Node> = doc
+            .descendants()
+            .filter(|n| n.has_tag_name((ASSERTION_NS, "Assertion")))
+            .collect();
+        let [assertion] = assertions[..] else {
+            return Err("Expected exactly one assertion".to_string());
+        };
+        if assertion.parent_element() != Some(response) {
+            return Err("Assertion is not part of the response".to_string());
+        }
+        let signed = [assertion, response]
+            .into_iter()
+            .find(|n| child(*n, DSIG_NS, "Signature").is_some())
+            .ok_or("Response is not signed")?;
+        let key = certificate_key(&pem_der(&saml.idp_certificate)?)
+            .ok_or("idp_certificate has no RSA public key")?
+            .to_vec();
+        verify_signature(signed, &key)?;
+
+        let issuer = |node| child(node, ASSERTION_NS, "Issuer").and_then(|i| i.text());
+        if issuer(assertion).map(str::trim) != Some(saml.idp_entity_id.as_str())
+            || issuer(response).is_some_and(|i| i.trim() != saml.idp_entity_id)
+        {
+            return Err("Assertion is from another identity provider".to_string());
+        }
+
+        let skew = saml.clock_skew_secs;
+        let conditions =
+            child(assertion, ASSERTION_NS, "Conditions").ok_or("Assertion has no conditions")?;
+        if let Some(not_before) = conditions.attribute("NotBefore")
+            && parse_time(not_before)? > now + skew
+        {
+            return Err("Assertion is not valid yet".to_string());
+        }
+        let expires_at = parse_time(
+            conditions
+                .attribute("NotOnOrAfter")
+                .ok_or("Assertion does not expire")?,
+        )?;
+        if now >= expires_at + skew {
+            return Err("Assertion has expired".to_string());
+        }
+        if expires_at > now + SEEN_ASSERTION_TTL.as_secs() {
+            return Err("Assertion is valid for too long".to_string());
+        }
+        let entity_id = saml.sp_entity_id(public_url);
+        let mut audiences = conditions
+            .descendants()
+            .filter(|n| n.has_tag_name((ASSERTION_NS, "Audience")))
+            .filter_map(|n| n.text());
+        if !audiences.any(|a| a.trim() == entity_id) {
+            return Err("Assertion is meant for another service provider".to_string());
+        }
+
+        let subject =
+            child(assertion, ASSERTION_NS, "Subject").ok_or("Assertion has no subject")?;
+        let confirmations: Vec<Node> = subject
+            .descendants()
+            .filter(|n| n.has_tag_name((ASSERTION_NS, "SubjectConfirmationData")))
+            .collect();
+        if confirmations.is_empty() {
+            return Err("Assertion has no subject confirmation".to_string());
+        }
+        // Taken from the assertion, which is signed, rather than the
+        // response, which may not be
+        let in_response_to = confirmations[0].attribute("InResponseTo");
+        if confirmations
+            .iter()
+            .any(|data| data.attribute("InResponseTo") != in_response_to)
+            || response
+                .attribute("InResponseTo")
+                .is_some_and(|id| Some(id) != in_response_to)
+        {
+            return Err("Response answers more than one request".to_string());
+        }
+        for data in confirmations {
+            if data.attribute("Recipient") != Some(acs_url.as_str()) {
+                return Err("Assertion was sent to another endpoint".to_string());
+            }
+            if let Some(not_on_or_after) = data.attribute("NotOnOrAfter")
+                && now >= parse_time(not_on_or_after)? + skew
+            {
+                return Err("Subject confirmation has expired".to_string());
+            }
+        }
+        let name_id = child(subject, ASSERTION_NS, "NameID")
+            .and_then(|n| n.text())
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .ok_or("Assertion has no NameID")?;
+        let email = assertion
+            .descendants()
+            .filter(|n| n.has_tag_name((ASSERTION_NS, "Attribute")))
+            .filter(|n| n.attribute("Name") == Some(saml.email_attribute.as_str()))
+            .flat_map(|n| n.children())
+            .filter(|n| n.has_tag_name((ASSERTION_NS, "AttributeValue")))
+            .find_map(|n| n.text())
+            .map(str::trim)
+            .or(name_id.contains('@').then_some(name_id));
+
+        Ok(SamlIdentity {
+            name_id: name_id.to_string(),
+            email: email.map(str::to_string),
+            assertion_id: assertion
+                .attribute("ID")
+                .ok_or("Assertion has no ID")?
+                .to_string(),
+            in_response_to: in_response_to.map(str::to_string),
+        })
+    }
+
+    /// The first child element of `node` named `name` in `namespace`
+    fn child<'a, 'input>(
+        node: Node<'a, 'input>,
+        namespace: &str,
+        name: &str,
+    ) -> Option<Node<'a, 'input>> {
+        node.children().find(|n| n.has_tag_name((namespace, name)))
+    }
+
+    /// An `xs:dateTime`, as a Unix timestamp
+    fn parse_time(value: &str) -> Result<u64, String> {
+        chrono::DateTime::parse_from_rfc3339(value.trim())
+            .map(|t| t.timestamp().max(0) as u64)
+            .map_err(|_| format!("Invalid time {}", value))
+    }
+
+    /// Check the enveloped signature of `element` against `key`, an
+    /// RSAPublicKey in DER
+    fn verify_signature(element: Node, key: &[u8]) -> Result<(), String> {
+        let signature = child(element, DSIG_NS, "Signature").ok_or("Missing signature")?;
+        let signed_info =
+            child(signature, DSIG_NS, "SignedInfo").ok_or("Signature has no SignedInfo")?;
+        let canonicalization = child(signed_info, DSIG_NS, "CanonicalizationMethod")
+            .filter(|m| m.attribute("Algorithm") == Some(EXC_C14N))
+            .ok_or("Unsupported canonicalization")?;
+        child(signed_info, DSIG_NS, "SignatureMethod")
+            .filter(|m| m.attribute("Algorithm") == Some(RSA_SHA256))
+            .ok_or("Unsupported signature algorithm")?;
+        let references: Vec<Node> = signed_info
+            .children()
+            .filter(|n| n.has_tag_name((DSIG_NS, "Reference")))
+            .collect();
+        let [reference] = references[..] else {
+            return Err("Expected exactly one signed reference".to_string());
+        };
+        let id = element.attribute("ID").ok_or("Signed element has no ID")?;
+        if reference.attribute("URI") != Some(format!("#{}", id).as_str()) {
+            return Err("Signature does not cover the signed element".to_string());
+        }
+
+        let mut prefixes = Vec::new();
+        let transforms = child(reference, DSIG_NS, "Transforms")
+            .into_iter()
+            .flat_map(|t| t.children())
+            .filter(|n| n.has_tag_name((DSIG_NS, "Transform")));
+        for transform in transforms {
+            match transform.attribute("Algorithm") {
+                Some(ENVELOPED) => {}
+                Some(EXC_C14N) => prefixes = inclusive_prefixes(transform),
+                other => {
+                    return Err(format!("Unsupported transform {}", other.unwrap_or("")));
+                }
+            }
+        }
+        child(reference, DSIG_NS, "DigestMethod")
+            .filter(|m| m.attribute("Algorithm") == Some(SHA256))
+            .ok_or("Unsupported digest algorithm")?;
+        let expected = base64_child(reference, "DigestValue")?;
+        let digest = Sha256::digest(canonicalize(element, Some(signature.id()), &prefixes));
+        if digest.as_slice() != expected {
+            return Err("Signed element was changed".to_string());
+        }
+
+        let value = base64_child(signature, "SignatureValue")?;
+        let signed = canonicalize(signed_info, None, &inclusive_prefixes(canonicalization));
+        UnparsedPublicKey::new(&RSA_PKCS1_2048_8192_SHA256, key)
+            .verify(signed.as_bytes(), &value)
+            .map_err(|_| "Invalid signature".to_string())
+    }
+
+    /// The decoded base64 text of a signature child
+    fn base64_child(node: Node, name: &str) -> Result<Vec<u8>, String> {
+        let text = child(node, DSIG_NS, name)
+            .and_then(|n| n.text())
+            .ok_or_else(|| format!("Signature has no {}", name))?;
+        STANDARD
+            .decode(text.split_whitespace().collect::<String>())
+            .map_err(|_| format!("{} is not base64", name))
+    }
+
+    /// Prefixes listed in a transform's `InclusiveNamespaces`
+    fn inclusive_prefixes(transform: Node) -> Vec<String> {
+        child(transform, EXC_C14N, "InclusiveNamespaces")
+            .and_then(|n| n.attribute("PrefixList"))
+            .map(|list| {
+                list.split_whitespace()
+                    .map(|p| match p {
+                        "#default" => String::new(),
+                        p => p.to_string(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Exclusive XML canonicalization, without comments, of `node` and its
+    /// descendants but `skip`
+    fn canonicalize(node: Node, skip: Option<NodeId>, inclusive: &[String]) -> String {
+        let mut out = String::new();
+        write_canonical(&mut out, node, skip, inclusive, &[]);
+        out
+    }
+
+    /// `rendered` holds the namespace declarations (prefix, URI) in force
+    /// in the output around `node`; the default namespace has prefix ""
+    fn write_canonical(
+        out: &mut String,
+        node: Node,
+        skip: Option<NodeId>,
+        inclusive: &[String],
+        rendered: &[(String, String)],
+    ) {
+        match node.node_type() {
+            NodeType::Element if Some(node.id()) != skip => {}
+            NodeType::Text => {
+                out.push_str(&escape_text(node.text().unwrap_or_default()));
+                return;
+            }
+            NodeType::PI => {
+                if let Some(pi) = node.pi() {
+                    out.push_str("<?");
+                    out.push_str(pi.target);
+                    if let Some(value) = pi.value {
+                        out.push(' ');
+                        out.push_str(value);
+                    }
+                    out.push_str("?>");
+                }
+                return;
+            }
+            _ => return,
+        }
+
+        let input = node.document().input_text();
+        let name = element_qname(input, node);
+        // Prefixes visibly used here, and those always to be declared
+        let mut used = vec![prefix_of(name).to_string()];
+        used.extend(
+            node.attributes()
+                .map(|a| &input[a.range_qname()])
+                .filter(|q| q.contains(':'))
+                .map(|q| prefix_of(q).to_string()),
+        );
+        used.extend(inclusive.iter().cloned());
+        let mut declared: Vec<(String, String)> = Vec::new();
+        for prefix in used {
+            if prefix == "xml" || declared.iter().any(|(p, _)| *p == prefix) {
+                continue;
+            }
+            let uri = node
+                .namespaces()
+                .find(|ns| ns.name().unwrap_or_default() == prefix)
+                .map(|ns| ns.uri())
+                .unwrap_or_default();
+            let in_force = rendered
+                .iter()
+                .rev()
+                .find(|(p, _)| *p == prefix)
+                .map(|(_, u)| u.as_str())
+                .unwrap_or_default();
+            if uri != in_force {
+                declared.push((prefix, uri.to_string()));
+            }
+        }
+        declared.sort();
+
+        out.push('<');
+        out.push_str(name);
+        for (prefix, uri) in &declared {
+            match prefix.is_empty() {
+                true => out.push_str(" xmlns=\""),
+                false => {
+                    out.push_str(" xmlns:");
+                    out.push_str(prefix);
+                    out.push_str("=\"");
+                }
+            }
+            out.push_str(&escape_attribute_value(uri));
+            out.push('"');
+        }
+        let mut attributes: Vec<_> = node.attributes().collect();
+        attributes.sort_by_key(|a| (a.namespace().unwrap_or_default(), a.name()));
+        for attribute in attributes {
+            out.push(' ');
+            out.push_str(&input[attribute.range_qname()]);
+            out.push_str("=\"");
+            out.push_str(&escape_attribute_value(attribute.value()));
+            out.push('"');
+        }
+        out.push('>');
+
+        let mut scope = rendered.to_vec();
+        scope.extend(declared);
+        for child in node.children() {
+            write_canonical(out, child, skip, inclusive, &scope);
+        }
+        out.push_str("</");
+        out.push_str(name);
+        out.push('>');
+    }
+
+    /// The qualified name of an element as written in the source
+    fn element_qname<'a>(input: &'a str, node: Node) -> &'a str {
+        let rest = &input[node.range().start + 1..];
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '/' || c == '>')
+            .unwrap_or(rest.len());
+        &rest[..end]
+    }
+
+    fn prefix_of(qname: &str) -> &str {
+        qname.split_once(':').map(|(p, _)| p).unwrap_or_default()
+    }
+
+    fn escape_text(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('\r', "&#xD;")
+    }
+
+    fn escape_attribute_value(value: &str) -> String {
+        value
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('"', "&quot;")
+            .replace('\t', "&#x9;")
+            .replace('\n', "&#xA;")
+            .replace('\r', "&#xD;")
+    }
+
+    /// The RSAPublicKey inside an X.509 certificate in DER
+    fn certificate_key(der: &[u8]) -> Option<&[u8]> {
+        let (_, certificate, _) = der_element(der)?;
+        let (_, mut tbs, _) = der_element(certificate)?;
+        let mut fields = Vec::new();
+        while !tbs.is_empty() {
+            let (tag, content, rest) = der_element(tbs)?;
+            fields.push((tag, content));
+            tbs = rest;
+        }
+        // Past the optional version: serial number, signature algorithm,
+        // issuer, validity and subject, then the subject's public key
+        let skip = usize::from(fields.first()?.0 == 0xa0) + 5;
+        let (_, public_key_info) = fields.get(skip)?;
+        let (_, _algorithm, rest) = der_element(public_key_info)?;
+        let (tag, bits, _) = der_element(rest)?;
+        // A BIT STRING, whose first byte counts unused bits
+        (tag == 0x03).then_some(bits)?.get(1..)
+    }
+
+    /// Tag, content and what follows of the DER element `input` starts with
+    fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+        let (&tag, rest) = input.split_first()?;
+        let (&first, rest) = rest.split_first()?;
+        let (len, rest) = match first {
+            short if short < 0x80 => (usize::from(short), rest),
+            long => {
+                let count = usize::from(long & 0x7f);
+                if !(1..=4).contains(&count) {
+                    return None;
+                }
+                let (bytes, rest) = rest.split_at_checked(count)?;
+                let len = bytes
+                    .iter()
+                    .fold(0usize, |len, b| (len << 8) | usize::from(*b));
+                (len, rest)
+            }
+        };
+        let (content, rest) = rest.split_at_checked(len)?;
+        Some((tag, content, rest))
+    }
+}
+
+/// Query parameters starting a sign-in
+#[derive(Debug, Deserialize)]
+pub struct SamlLoginQuery {
+    /// Path to continue to once signed in
+    pub relay_state: Option<String>,
+}
+
+/// What the identity provider posts back
+#[derive(Debug, Deserialize)]
+pub struct SamlAcsForm {
+    #[serde(rename = "SAMLResponse")]
+    pub saml_response: String,
+    #[serde(rename = "RelayState", default)]
+    pub relay_state: Option<String>,
+}
+
+/// Service provider metadata
+pub async fn saml_metadata(State(state): State<AppState>) -> Response {
+    let config = state.config();
+    let Some(saml) = config.saml.as_ref() else {
+        return (StatusCode::NOT_FOUND, "SAML is not enabled").into_response();
+    };
+    (
+        [(header::CONTENT_TYPE, "application/samlmetadata+xml")],
+        sp_metadata(&config, saml),
+    )
+        .into_response()
+}
+
+/// Send the browser to the identity provider to sign in
+pub async fn saml_login(
+    State(state): State<AppState>,
+    Query(query): Query<SamlLoginQuery>,
+) -> Response {
+    let config = state.config();
+    let Some(saml) = config.saml.as_ref() else {
+        return (StatusCode::NOT_FOUND, "SAML is not enabled").into_response();
+    };
+    let id = format!("_{}", Uuid::new_v4().simple());
+    match login_url(&config, saml, &id, relay_path(query.relay_state.as_deref())) {
+        Ok(url) => {
+            state.saml_requests.lock().await.insert(id, ());
+            Redirect::to(&url).into_response()
+        }
+        Err(e) => {
+            warn!("Cannot start a SAML sign-in: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e).into_response()
+        }
+    }
+}
+
+/// Assertion consumer service: sign the user in and send them on with a
+/// user token
+pub async fn saml_acs(State(state): State<AppState>, Form(form): Form<SamlAcsForm>) -> Response {
+    if state.config().saml.is_none() {
+        return (StatusCode::NOT_FOUND, "SAML is not enabled").into_response();
+    }
+    match state.saml_sign_in(&form.saml_response).await {
+        Ok((identity, token)) => {
+            info!("Signed in {} with SAML", identity.name_id);
+            let path = relay_path(form.relay_state.as_deref());
+            let separator = if path.contains('?') { '&' } else { '?' };
+            Redirect::to(&format!("{}{}token={}", path, separator, token)).into_response()
+        }
+        Err(e) => {
+            warn!("Refused a SAML response: {}", e);
+            (StatusCode::UNAUTHORIZED, e).into_response()
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Malware scanning of user uploads
+//!
+//! With `scanning` configured, files users upload for other users are
+//! scanned before anyone can download them: by clamd over TCP (its
+//! `INSTREAM` command), or by an HTTP scanner that is sent the file as the
+//! request body and answers `{"clean": true}` or
+//! `{"clean": false, "finding": "..."}`. Scans run as background jobs, so
+//! an unreachable scanner is retried. Until a file is found clean it is
+//! `pending`; an infected one is `quarantined`, kept but never served, and
+//! the finding goes to the audit log. Voice messages are the one such
+//! upload so far.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Longest a single scan may take
+const SCAN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Bytes sent to clamd per `INSTREAM` chunk
+const CLAMD_CHUNK: usize = 64 * 1024;
+
+/// Which scanner to use, by `backend`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum ScanConfig {
+    /// clamd listening on TCP, as `host:port`
+    Clamd { address: String },
+    /// An HTTP scanning service
+    Http {
+        url: String,
+        /// Sent as a Bearer token
+        #[serde(default)]
+        api_key: Option<String>,
+    },
+}
+
+/// What a scan found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    /// Malware, with the scanner's name for it
+    Infected(String),
+}
+
+/// Answer of an HTTP scanner
+#[derive(Debug, Deserialize)]
+struct HttpVerdict {
+    clean: bool,
+    #[serde(default)]
+    finding: Option<String>,
+}
+
+/// Scan `data` with the configured scanner
+pub async fn scan(
+    config: &ScanConfig,
+    http: &reqwest::Client,
+    data: &[u8],
+) -> Result<Verdict, String> {
+    match config {
+        ScanConfig::Clamd { address } => tokio::time::timeout(SCAN_TIMEOUT, clamd(address, data))
+            .await
+            .map_err(|_| "clamd timed out".to_string())?,
+        ScanConfig::Http { url, api_key } => {
+            let mut request = http
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+                .body(data.to_vec())
+                .timeout(SCAN_TIMEOUT);
+            if let Some(key) = api_key {
+                request = request.bearer_auth(key);
+            }
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            if !response.status().is_success() {
+                return Err(format!("Scanner answered {}", response.status()));
+            }
+            let verdict: HttpVerdict = response
+                .json()
+                .await
+                .map_err(|e| format!("Unreadable scanner answer: {}", e))?;
+            Ok(match verdict.clean {
+                true => Verdict::Clean,
+                false => Verdict::Infected(verdict.finding.unwrap_or_else(|| "unknown".into())),
+            })
+        }
+    }
+}
+
+/// Stream `data` to clamd and read its verdict
+async fn clamd(address: &str, data: &[u8]) -> Result<Verdict, String> {
+    let failed = |e: std::io::Error| format!("clamd at {}: {}", address, e);
+    let mut stream = TcpStream::connect(address).await.map_err(failed)?;
+    stream.write_all(b"zINSTREAM\0").await.map_err(failed)?;
+    for chunk in data.chunks(CLAMD_CHUNK) {
+        stream
+            .write_all(&(chunk.len() as u32).to_be_bytes())
+            .await
+            .map_err(failed)?;
+        stream.write_all(chunk).await.map_err(failed)?;
+    }
+    stream.write_all(&[0; 4]).await.map_err(failed)?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await.map_err(failed)?;
+    let reply = String::from_utf8_lossy(&reply);
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    // `stream: OK`, `stream: <name> FOUND` or `<reason> ERROR`
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if result == "OK" {
+        Ok(Verdict::Clean)
+    } else if let Some(name) = result.strip_suffix(" FOUND") {
+        Ok(Verdict::Infected(name.to_string()))
+    } else {
+        Err(format!("clamd answered {:?}", reply))
+    }
+}
@@ -0,0 +1,587 @@
+//! SCIM user provisioning
+//!
+//! With a `scim` section, identity providers such as Okta or Entra ID keep
+//! the server's users in sync through a minimal SCIM 2.0 `/scim/v2/Users`
+//! endpoint, authenticated with `Authorization: Bearer <token>`. Users can
+//! be created, read, listed (filtering on `userName`, `externalId` or `id`
+//! with `eq`), replaced with `PUT`, patched and deleted. Patches may set
+//! `active`, `userName`, `displayName`, `externalId` and `emails`; other
+//! attributes, like groups, are accepted and ignored.
+//!
+//! Provisioned users gate user tokens: with `scim` set, a user token only
+//! works while an active user has its `sub` or `email` as `userName` or as
+//! one of their emails. That covers invite-only rooms, personal rooms,
+//! presence, calls and contacts. Deactivating or deleting a user closes
+//! their presence sockets and removes them from the rooms they are in.
+//!
+//! Users are kept in memory and, with `users_file`, in a JSON file read at
+//! startup, so deprovisioned users stay out across restarts.
+
+use std::path::PathBuf;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::state::AppState;
+use crate::token::secret_eq;
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+const LIST_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+const SCIM_CONTENT_TYPE: &str = "application/scim+json";
+
+/// Most users listed per page
+pub const MAX_USERS_PER_PAGE: usize = 100;
+
+/// Who may provision users, and where they are kept
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScimConfig {
+    /// Bearer token of the identity provider
+    pub token: String,
+    /// JSON file keeping provisioned users across restarts
+    #[serde(default)]
+    pub users_file: Option<PathBuf>,
+}
+
+/// A provisioned user, as served over SCIM
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+    pub schemas: Vec<String>,
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+    pub user_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub emails: Vec<ScimEmail>,
+    pub meta: ScimMeta,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScimEmail {
+    pub value: String,
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub primary: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimMeta {
+    pub resource_type: String,
+    pub created: String,
+    pub last_modified: String,
+    pub location: String,
+}
+
+/// Provisioned users in the order they were created
+pub type ScimUsers = Vec<ScimUser>;
+
+impl ScimUser {
+    /// Whether this user is the one a token's `sub` or `email` names
+    fn is(&self, name: &str) -> bool {
+        self.user_name.eq_ignore_ascii_case(name)
+            || self
+                .emails
+                .iter()
+                .any(|e| e.value.eq_ignore_ascii_case(name))
+    }
+
+    /// Names this user's tokens may carry
+    fn names(&self) -> Vec<String> {
+        std::iter::once(self.user_name.clone())
+            .chain(self.emails.iter().map(|e| e.value.clone()))
+            .collect()
+    }
+}
+
+/// Body of a create or replace request
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UserRequest {
+    #[serde(default)]
+    external_id: Option<String>,
+    user_name: String,
+    #[serde(default)]
+    display_name: Option<String>,
+    #[serde(default = "default_active")]
+    active: bool,
+    #[serde(default)]
+    emails: Vec<ScimEmail>,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// Body of a `PATCH`
+#[derive(Debug, Deserialize)]
+struct PatchRequest {
+    #[serde(rename = "Operations")]
+    operations: Vec<PatchOperation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PatchOperation {
+    op: String,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    value: Value,
+}
+
+/// Query parameters of the user listing
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserListQuery {
+    #[serde(default)]
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub start_index: Option<usize>,
+    #[serde(default)]
+    pub count: Option<usize>,
+}
+
+/// Users stored in `users_file`, if any
+pub fn load_users(config: Option<&ScimConfig>) -> ScimUsers {
+    let Some(path) = config.and_then(|c| c.users_file.as_ref()) else {
+        return Vec::new();
+    };
+    let users = match std::fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| e.to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.to_string()),
+    };
+    users.unwrap_or_else(|e| {
+        warn!("Could not read SCIM users from {}: {}", path.display(), e);
+        Vec::new()
+    })
+}
+
+impl AppState {
+    /// Whether a user token for `sub` or `email` may be used: always
+    /// without `scim`, and only for an active provisioned user with it
+    pub fn is_provisioned(&self, sub: Option<&str>, email: Option<&str>) -> bool {
+        if self.config().scim.is_none() {
+            return true;
+        }
+        let users = self.scim_users.read().unwrap_or_else(|e| e.into_inner());
+        users
+            .iter()
+            .filter(|u| u.active)
+            .any(|u| sub.is_some_and(|s| u.is(s)) || email.is_some_and(|e| u.is(e)))
+    }
+
+    /// Write the users to `users_file`, if set
+    async fn save_users(&self) {
+        let config = self.config();
+        let Some(path) = config.scim.as_ref().and_then(|c| c.users_file.as_ref()) else {
+            return;
+        };
+        let users = self
+            .scim_users
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let json = serde_json::to_vec_pretty(&users).expect("users serialize");
+        let temp = path.with_extension("tmp");
+        let written = match tokio::fs::write(&temp, json).await {
+            Ok(()) => tokio::fs::rename(&temp, path).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            warn!("Could not write SCIM users to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Sign a deactivated or deleted user out everywhere
+    async fn deprovision(&self, user: &ScimUser) {
+        let names = user.names();
+        for name in &names {
+            self.sign_out(name).await;
+        }
+        for (room_id, room) in self.room_handles().await {
+            let names = names.clone();
+            let peers = room.call(move |room| {
+                room.peers
+                    .iter()
+                    .filter(|p| {
+                        p.identity
+                            .as_ref()
+                            .is_some_and(|i| names.iter().any(|n| n.eq_ignore_ascii_case(i)))
+                    })
+                    .map(|p| p.id.clone())
+                    .collect::<Vec<_>>()
+            });
+            for peer_id in peers.await.unwrap_or_default() {
+                self.kick_peer(&room_id, &peer_id, "scim").await;
+            }
+        }
+        info!("Deprovisioned user {}", user.user_name);
+        self.record_audit(None, "scim", "user.deprovision", &user.user_name)
+            .await;
+    }
+
+    /// Store a changed user, saving and signing out as needed
+    async fn user_changed(&self, before: Option<&ScimUser>, after: Option<&ScimUser>) {
+        self.save_users().await;
+        if let Some(before) = before.filter(|u| u.active)
+            && after.is_none_or(|u| !u.active)
+        {
+            self.deprovision(before).await;
+        }
+    }
+}
+
+/// A SCIM error response
+#[derive(Debug)]
+pub struct ScimError(StatusCode, String);
+
+impl ScimError {
+    fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        Self(status, detail.into())
+    }
+}
+
+impl IntoResponse for ScimError {
+    fn into_response(self) -> Response {
+        scim_json(
+            self.0,
+            serde_json::json!({
+                "schemas": [ERROR_SCHEMA],
+                "status": self.0.as_u16().to_string(),
+                "detail": self.1,
+            }),
+        )
+    }
+}
+
+impl UserRequest {
+    fn validate(&self) -> Result<(), ScimError> {
+        if self.user_name.trim().is_empty() {
+            return Err(ScimError::new(
+                StatusCode::BAD_REQUEST,
+                "userName is required",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Set one attribute of `user`; `None` removes it
+fn set_attribute(user: &mut ScimUser, name: &str, value: Option<&Value>) -> Result<(), String> {
+    match name.to_ascii_lowercase().as_str() {
+        "active" => {
+            user.active = match value {
+                Some(Value::Bool(active)) => *active,
+                // Entra ID sends "True" and "False"
+                Some(Value::String(s)) if s.eq_ignore_ascii_case("true") => true,
+                Some(Value::String(s)) if s.eq_ignore_ascii_case("false") => false,
+                _ => return Err("active must be a boolean".to_string()),
+            }
+        }
+        "username" => match value.and_then(Value::as_str) {
+            Some(name) if !name.trim().is_empty() => user.user_name = name.to_string(),
+            _ => return Err("userName must be a non-empty string".to_string()),
+        },
+        "displayname" => user.display_name = value.and_then(Value::as_str).map(str::to_string),
+        "externalid" => user.external_id = value.and_then(Value::as_str).map(str::to_string),
+        "emails" => {
+            user.emails = match value {
+                Some(value) => {
+                    serde_json::from_value(value.clone()).map_err(|e| format!("emails: {}", e))?
+                }
+                None => Vec::new(),
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn apply_patch(user: &mut ScimUser, operation: &PatchOperation) -> Result<(), String> {
+    let remove = match operation.op.to_ascii_lowercase().as_str() {
+        "add" | "replace" => false,
+        "remove" => true,
+        op => return Err(format!("Unsupported operation {}", op)),
+    };
+    match (operation.path.as_deref(), &operation.value) {
+        (Some(path), value) => set_attribute(user, path, (!remove).then_some(value)),
+        (None, Value::Object(attributes)) if !remove => attributes
+            .iter()
+            .try_for_each(|(name, value)| set_attribute(user, name, Some(value))),
+        (None, _) => Err("An operation without path needs an object value".to_string()),
+    }
+}
+
+/// `attribute eq "value"`
+fn parse_filter(filter: &str) -> Option<(&str, String)> {
+    let (attribute, rest) = filter.trim().split_once(' ')?;
+    let (op, value) = rest.trim_start().split_once(' ')?;
+    if !op.eq_ignore_ascii_case("eq") {
+        return None;
+    }
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((attribute, value.replace("\\\"", "\"")))
+}
+
+fn matches_filter(user: &ScimUser, attribute: &str, value: &str) -> bool {
+    match attribute.to_ascii_lowercase().as_str() {
+        "username" => user.user_name.eq_ignore_ascii_case(value),
+        "externalid" => user.external_id.as_deref() == Some(value),
+        "id" => user.id == value,
+        _ => false,
+    }
+}
+
+fn scim_json(status: StatusCode, body: impl Serialize) -> Response {
+    let body = serde_json::to_string(&body).expect("SCIM resources serialize");
+    (status, [(header::CONTENT_TYPE, SCIM_CONTENT_TYPE)], body).into_response()
+}
+
+/// Parse a JSON body whatever its content type; identity providers send
+/// `application/scim+json`
+fn parse_body<T: DeserializeOwned>(body: &[u8]) -> Result<T, ScimError> {
+    serde_json::from_slice(body).map_err(|e| {
+        ScimError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid request body: {}", e),
+        )
+    })
+}
+
+/// Check the identity provider's bearer token
+fn authorize(config: &Config, headers: &HeaderMap) -> Result<(), ScimError> {
+    let Some(scim) = config.scim.as_ref() else {
+        return Err(ScimError::new(
+            StatusCode::NOT_FOUND,
+            "Provisioning is not enabled",
+        ));
+    };
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match provided.is_some_and(|p| secret_eq(p, &scim.token)) {
+        true => Ok(()),
+        false => Err(ScimError::new(
+            StatusCode::UNAUTHORIZED,
+            "Invalid SCIM token",
+        )),
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// List users, optionally filtered
+pub async fn list_users(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<UserListQuery>,
+) -> Result<Response, ScimError> {
+    authorize(&state.config(), &headers)?;
+    let filter = match query.filter.as_deref().map(parse_filter) {
+        Some(None) => {
+            return Err(ScimError::new(
+                StatusCode::BAD_REQUEST,
+                "Only filters of the form `attribute eq \"value\"` are supported",
+            ));
+        }
+        filter => filter.flatten(),
+    };
+    let users = state
+        .scim_users
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    let matching: Vec<ScimUser> = users
+        .into_iter()
+        .filter(|u| {
+            filter
+                .as_ref()
+                .is_none_or(|(attribute, value)| matches_filter(u, attribute, value))
+        })
+        .collect();
+    let start_index = query.start_index.unwrap_or(1).max(1);
+    let count = query
+        .count
+        .unwrap_or(MAX_USERS_PER_PAGE)
+        .min(MAX_USERS_PER_PAGE);
+    let page: Vec<&ScimUser> = matching.iter().skip(start_index - 1).take(count).collect();
+    Ok(scim_json(
+        StatusCode::OK,
+        serde_json::json!({
+            "schemas": [LIST_SCHEMA],
+            "totalResults": matching.len(),
+            "startIndex": start_index,
+            "itemsPerPage": page.len(),
+            "Resources": page,
+        }),
+    ))
+}
+
+/// Provision a user
+pub async fn create_user(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ScimError> {
+    let config = state.config();
+    authorize(&config, &headers)?;
+    let request: UserRequest = parse_body(&body)?;
+    request.validate()?;
+    let id = Uuid::new_v4().simple().to_string();
+    let created = now();
+    let user = ScimUser {
+        schemas: vec![USER_SCHEMA.to_string()],
+        meta: ScimMeta {
+            resource_type: "User".to_string(),
+            created: created.clone(),
+            last_modified: created,
+            location: format!(
+                "{}/scim/v2/Users/{}",
+                config.public_url.trim_end_matches('/'),
+                id
+            ),
+        },
+        id,
+        external_id: request.external_id,
+        user_name: request.user_name,
+        display_name: request.display_name,
+        active: request.active,
+        emails: request.emails,
+    };
+    {
+        let mut users = state.scim_users.write().unwrap_or_else(|e| e.into_inner());
+        if users
+            .iter()
+            .any(|u| u.user_name.eq_ignore_ascii_case(&user.user_name))
+        {
+            return Err(ScimError::new(
+                StatusCode::CONFLICT,
+                "userName is already taken",
+            ));
+        }
+        users.push(user.clone());
+    }
+    info!("Provisioned user {}", user.user_name);
+    state.user_changed(None, Some(&user)).await;
+    Ok(scim_json(StatusCode::CREATED, user))
+}
+
+/// Read one user
+pub async fn get_user(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Response, ScimError> {
+    authorize(&state.config(), &headers)?;
+    let users = state.scim_users.read().unwrap_or_else(|e| e.into_inner());
+    match users.iter().find(|u| u.id == id) {
+        Some(user) => Ok(scim_json(StatusCode::OK, user)),
+        None => Err(ScimError::new(StatusCode::NOT_FOUND, "No such user")),
+    }
+}
+
+/// Change a user with `change`, checking that its userName stays unique
+async fn update_user(
+    state: &AppState,
+    id: &str,
+    change: impl FnOnce(&mut ScimUser) -> Result<(), ScimError>,
+) -> Result<Response, ScimError> {
+    let (before, after) = {
+        let mut users = state.scim_users.write().unwrap_or_else(|e| e.into_inner());
+        let Some(index) = users.iter().position(|u| u.id == id) else {
+            return Err(ScimError::new(StatusCode::NOT_FOUND, "No such user"));
+        };
+        let mut user = users[index].clone();
+        change(&mut user)?;
+        if users
+            .iter()
+            .any(|u| u.id != id && u.user_name.eq_ignore_ascii_case(&user.user_name))
+        {
+            return Err(ScimError::new(
+                StatusCode::CONFLICT,
+                "userName is already taken",
+            ));
+        }
+        user.meta.last_modified = now();
+        (std::mem::replace(&mut users[index], user.clone()), user)
+    };
+    state.user_changed(Some(&before), Some(&after)).await;
+    Ok(scim_json(StatusCode::OK, after))
+}
+
+/// Replace a user
+pub async fn replace_user(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> Result<Response, ScimError> {
+    authorize(&state.config(), &headers)?;
+    let request: UserRequest = parse_body(&body)?;
+    request.validate()?;
+    update_user(&state, &id, move |user| {
+        user.external_id = request.external_id;
+        user.user_name = request.user_name;
+        user.display_name = request.display_name;
+        user.active = request.active;
+        user.emails = request.emails;
+        Ok(())
+    })
+    .await
+}
+
+/// Apply patch operations to a user
+pub async fn patch_user(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> Result<Response, ScimError> {
+    authorize(&state.config(), &headers)?;
+    let request: PatchRequest = parse_body(&body)?;
+    update_user(&state, &id, move |user| {
+        request
+            .operations
+            .iter()
+            .try_for_each(|operation| apply_patch(user, operation))
+            .map_err(|e| ScimError::new(StatusCode::BAD_REQUEST, e))
+    })
+    .await
+}
+
+/// Deprovision a user
+pub async fn delete_user(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ScimError> {
+    authorize(&state.config(), &headers)?;
+    let removed = {
+        let mut users = state.scim_users.write().unwrap_or_else(|e| e.into_inner());
+        let index = users.iter().position(|u| u.id == id);
+        index.map(|index| users.remove(index))
+    };
+    let Some(user) = removed else {
+        return Err(ScimError::new(StatusCode::NOT_FOUND, "No such user"));
+    };
+    state.user_changed(Some(&user), None).await;
+    Ok(StatusCode::NO_CONTENT)
+}
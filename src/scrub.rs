@@ -0,0 +1,250 @@
+//! Scrubbing personal data from diagnostics
+//!
+//! With a `scrubbing` section, logs, debug bundles and the records written
+//! to the record store go through a scrubbing stage first (each can be
+//! turned off on its own). IP addresses are replaced by a salted hash, so
+//! the same address still reads the same everywhere; loopback and
+//! unspecified addresses are kept. SDP session usernames (the first field
+//! of `o=`), ICE ufrags and passwords are redacted, in descriptions and in
+//! candidates. Chat text is cut to `chat_max_chars`, or replaced by its
+//! length when that is 0; chat is kept whole when it is unset.
+//!
+//! What the server keeps in memory and serves over the regular API is not
+//! scrubbed; the stage applies where data leaves for operators and storage.
+
+use std::borrow::Cow;
+use std::net::{IpAddr, SocketAddr};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::models::{TranscriptKind, WsMessage};
+use crate::persistence::RoomRecords;
+use crate::state::AppState;
+
+/// Stands in for a redacted value
+const REDACTED: &str = "[redacted]";
+
+/// Punctuation around a word that is not part of an address
+const WORD_PUNCTUATION: &[char] = &['"', '\'', ',', ';', '(', ')', '{', '}', '<', '>', '='];
+
+/// What gets scrubbed, and where
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScrubConfig {
+    /// Mixed into IP hashes, so they cannot be reversed by hashing every
+    /// address
+    #[serde(default)]
+    pub salt: String,
+    /// Characters of chat text kept; unset keeps it all, 0 keeps only its
+    /// length
+    #[serde(default)]
+    pub chat_max_chars: Option<usize>,
+    /// Scrub log lines that carry messages or addresses
+    #[serde(default = "enabled")]
+    pub logs: bool,
+    /// Scrub `/admin/rooms/{room_id}/debug`
+    #[serde(default = "enabled")]
+    pub debug_bundles: bool,
+    /// Scrub records before they are written to the record store
+    #[serde(default = "enabled")]
+    pub persisted: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl ScrubConfig {
+    /// Salted hash of an address, e.g. `ip-3f9a0c2b41d7`
+    pub fn hash_ip(&self, ip: IpAddr) -> String {
+        let digest = Sha256::digest(format!("{}:{}", self.salt, ip));
+        let hex: String = digest[..6].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("ip-{}", hex)
+    }
+
+    /// Chat text cut to the policy
+    pub fn chat(&self, text: &str) -> String {
+        match self.chat_max_chars {
+            None => text.to_string(),
+            Some(0) => format!("[{} chars]", text.chars().count()),
+            Some(max) if text.chars().count() > max => {
+                format!("{}…", text.chars().take(max).collect::<String>())
+            }
+            Some(_) => text.to_string(),
+        }
+    }
+
+    /// Free text with addresses hashed and SDP credentials redacted
+    ///
+    /// Lines may end with real line breaks or with escaped ones, as in SDP
+    /// inside logged JSON.
+    pub fn text(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while !rest.is_empty() {
+            let (line, separator, tail) = next_line(rest);
+            out.push_str(&self.line(line));
+            out.push_str(separator);
+            rest = tail;
+        }
+        out
+    }
+
+    /// A JSON value with every string scrubbed, and chat cut to the policy
+    pub fn value(&self, value: &mut Value) {
+        self.scrub_value(value, None, false);
+    }
+
+    fn scrub_value(&self, value: &mut Value, key: Option<&str>, chat: bool) {
+        match value {
+            Value::Object(map) => {
+                let chat = chat || map.get("type").and_then(Value::as_str) == Some("chat");
+                for (k, v) in map.iter_mut() {
+                    self.scrub_value(v, Some(k), chat);
+                }
+            }
+            Value::Array(items) => {
+                for item in items {
+                    self.scrub_value(item, key, chat);
+                }
+            }
+            Value::String(s) => {
+                *s = match key {
+                    Some("text" | "message") if chat => self.chat(s),
+                    _ => self.text(s),
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// Anything serializable, scrubbed, as JSON
+    pub fn serialized(&self, data: &impl Serialize) -> Value {
+        let mut value = serde_json::to_value(data).unwrap_or_default();
+        self.value(&mut value);
+        value
+    }
+
+    /// A room's records as they may be stored
+    pub fn records(&self, mut records: RoomRecords) -> RoomRecords {
+        if let Some(transcript) = &mut records.transcript {
+            for entry in &mut transcript.entries {
+                if entry.kind == TranscriptKind::Chat {
+                    entry.text = self.chat(&entry.text);
+                }
+            }
+        }
+        if let Some(timeline) = &mut records.timeline {
+            for event in &mut timeline.events {
+                event.detail = self.text(&event.detail);
+            }
+        }
+        records
+    }
+
+    fn line(&self, line: &str) -> String {
+        let words: Vec<String> = redact_credentials(line)
+            .split(' ')
+            .map(|word| {
+                let core = word.trim_matches(WORD_PUNCTUATION);
+                match self.address(core) {
+                    Some(hashed) => word.replacen(core, &hashed, 1),
+                    None => word.to_string(),
+                }
+            })
+            .collect();
+        words.join(" ")
+    }
+
+    /// The hashed form of a word that is an address, keeping its port or
+    /// prefix length
+    fn address(&self, word: &str) -> Option<String> {
+        let (ip, suffix) = match word.parse::<IpAddr>() {
+            Ok(ip) => (ip, String::new()),
+            Err(_) => match word.split_once('/') {
+                Some((ip, prefix)) => (ip.parse().ok()?, format!("/{}", prefix)),
+                None => {
+                    let addr = word.parse::<SocketAddr>().ok()?;
+                    (addr.ip(), format!(":{}", addr.port()))
+                }
+            },
+        };
+        if ip.is_loopback() || ip.is_unspecified() {
+            return None;
+        }
+        Some(format!("{}{}", self.hash_ip(ip), suffix))
+    }
+}
+
+/// The first line of `text`, the line break after it, and the rest
+fn next_line(text: &str) -> (&str, &str, &str) {
+    let breaks = ["\r\n", "\\r\\n", "\n", "\\n"];
+    let found = breaks
+        .iter()
+        .filter_map(|b| text.find(b).map(|at| (at, *b)))
+        // The earliest break, and the longest one starting there
+        .min_by_key(|(at, b)| (*at, std::cmp::Reverse(b.len())));
+    match found {
+        Some((at, separator)) => (&text[..at], separator, &text[at + separator.len()..]),
+        None => (text, "", ""),
+    }
+}
+
+/// An SDP or candidate line with its session username, ICE ufrag or ICE
+/// password redacted; other lines are returned as they are
+pub fn redact_credentials(line: &str) -> Cow<'_, str> {
+    // SDP inside JSON may start with the opening quote
+    let (quote, body) = match line.strip_prefix('"') {
+        Some(body) => ("\"", body),
+        None => ("", line),
+    };
+    for attribute in ["a=ice-ufrag:", "a=ice-pwd:"] {
+        if let Some(value) = body.strip_prefix(attribute) {
+            return Cow::Owned(format!("{}{}{}", quote, attribute, redact_value(value)));
+        }
+    }
+    if let Some(origin) = body.strip_prefix("o=")
+        && let Some((username, rest)) = origin.split_once(' ')
+        && username != "-"
+    {
+        return Cow::Owned(format!("{}o={} {}", quote, REDACTED, rest));
+    }
+    // Candidates may carry the ufrag as an extension
+    if let Some((before, value)) = line.split_once(" ufrag ") {
+        return Cow::Owned(format!("{} ufrag {}", before, redact_value(value)));
+    }
+    Cow::Borrowed(line)
+}
+
+/// [`REDACTED`] in place of a value, keeping what follows it on the line
+fn redact_value(value: &str) -> String {
+    let end = value.find([' ', '"', '\\']).unwrap_or(value.len());
+    format!("{}{}", REDACTED, &value[end..])
+}
+
+impl AppState {
+    /// Text for a log line, scrubbed if logs are
+    pub fn log_text<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self.config().scrubbing.as_ref().filter(|s| s.logs) {
+            Some(scrubbing) => Cow::Owned(scrubbing.text(text)),
+            None => Cow::Borrowed(text),
+        }
+    }
+
+    /// A signaling message for a log line, scrubbed if logs are
+    pub fn log_message(&self, msg: &WsMessage) -> String {
+        match self.config().scrubbing.as_ref().filter(|s| s.logs) {
+            Some(scrubbing) => scrubbing.serialized(msg).to_string(),
+            None => format!("{:?}", msg),
+        }
+    }
+
+    /// An address for a log line, hashed if logs are scrubbed
+    pub fn log_ip(&self, ip: IpAddr) -> String {
+        match self.config().scrubbing.as_ref().filter(|s| s.logs) {
+            Some(scrubbing) => scrubbing.hash_ip(ip),
+            None => ip.to_string(),
+        }
+    }
+}
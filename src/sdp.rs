@@ -0,0 +1,324 @@
+//! Rewriting relayed session descriptions
+//!
+//! Rooms created with a codec policy tell clients their preferences in
+//! `room_info`, but not every client build honours them. The server
+//! therefore applies the policy to each offer and answer it relays: the
+//! payload types of preferred codecs move to the front of every media
+//! section, in order and with their retransmission formats, and the Opus
+//! parameters are set on the audio sections. Preferring a codec never
+//! removes the others, so a call still connects when peers share no
+//! preferred codec.
+//!
+//! A room's SDP policy goes further, for operators who must enforce media
+//! constraints whatever the client: it strips codecs and RTP header
+//! extensions, and caps each audio and video section's bandwidth with
+//! `b=AS`. A section is left with all its codecs rather than none.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::models::{CodecPolicy, OpusSettings, SdpPolicy, WsMessage};
+use crate::state::Room;
+
+/// A session description split into its session part and media sections
+struct Sdp {
+    /// Session-level lines, then one entry per `m=` section
+    sections: Vec<Vec<String>>,
+    newline: &'static str,
+}
+
+impl Sdp {
+    fn parse(sdp: &str) -> Self {
+        let newline = if sdp.contains("\r\n") { "\r\n" } else { "\n" };
+        let mut sections = vec![Vec::new()];
+        for line in sdp.lines().filter(|l| !l.is_empty()) {
+            if line.starts_with("m=") {
+                sections.push(Vec::new());
+            }
+            if let Some(section) = sections.last_mut() {
+                section.push(line.to_string());
+            }
+        }
+        Self { sections, newline }
+    }
+
+    /// Media sections, each starting with its `m=` line
+    fn media(&mut self) -> impl Iterator<Item = &mut Vec<String>> {
+        self.sections.iter_mut().skip(1)
+    }
+
+    /// Prefer the policy's codecs and set its Opus parameters
+    fn apply_codec_policy(&mut self, policy: &CodecPolicy) {
+        let opus = policy.opus.as_ref().map(opus_params).unwrap_or_default();
+        for section in self.media() {
+            prefer_codecs(section, &policy.preferred);
+            if section[0].starts_with("m=audio ") && !opus.is_empty() {
+                set_opus_params(section, &opus);
+            }
+        }
+    }
+
+    /// Strip codecs and extensions and cap bandwidth
+    fn apply_policy(&mut self, policy: &SdpPolicy) {
+        if !policy.strip_extensions.is_empty() {
+            for section in &mut self.sections {
+                strip_extensions(section, &policy.strip_extensions);
+            }
+        }
+        for section in self.media() {
+            if !policy.strip_codecs.is_empty() {
+                strip_codecs(section, &policy.strip_codecs);
+            }
+            let cap = match section[0].split(' ').next() {
+                Some("m=audio") => policy.max_audio_kbps,
+                Some("m=video") => policy.max_video_kbps,
+                _ => None,
+            };
+            if let Some(kbps) = cap {
+                cap_bandwidth(section, kbps);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Sdp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in self.sections.iter().flatten() {
+            write!(f, "{}{}", line, self.newline)?;
+        }
+        Ok(())
+    }
+}
+
+/// Codec name by payload type, from a section's `a=rtpmap` lines
+fn rtpmaps(section: &[String]) -> HashMap<&str, &str> {
+    section
+        .iter()
+        .filter_map(|line| line.strip_prefix("a=rtpmap:")?.split_once(' '))
+        .map(|(pt, codec)| (pt, codec.split('/').next().unwrap_or(codec)))
+        .collect()
+}
+
+/// Format parameters by payload type, from a section's `a=fmtp` lines
+fn fmtps(section: &[String]) -> HashMap<&str, &str> {
+    section
+        .iter()
+        .filter_map(|line| line.strip_prefix("a=fmtp:")?.split_once(' '))
+        .collect()
+}
+
+/// One parameter of an `fmtp` line
+fn param<'a>(params: &'a str, key: &str) -> Option<&'a str> {
+    params
+        .split(';')
+        .filter_map(|p| p.trim().split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+/// The codec a payload type carries; a retransmission format counts as
+/// the codec it repairs
+fn codec_of<'a>(
+    pt: &str,
+    codecs: &HashMap<&'a str, &'a str>,
+    fmtps: &HashMap<&'a str, &'a str>,
+) -> Option<&'a str> {
+    let codec = codecs.get(pt)?;
+    if codec.eq_ignore_ascii_case("rtx")
+        && let Some(apt) = fmtps.get(pt).and_then(|p| param(p, "apt"))
+    {
+        return codecs.get(apt).copied();
+    }
+    Some(codec)
+}
+
+/// Reorder the payload types on a section's `m=` line so preferred codecs
+/// come first; retransmission formats follow the codec they repair
+fn prefer_codecs(section: &mut [String], preferred: &[String]) {
+    let Some(m_line) = section.first() else {
+        return;
+    };
+    let codecs = rtpmaps(section);
+    let fmtps = fmtps(section);
+    let rank = |pt: &str| {
+        codec_of(pt, &codecs, &fmtps)
+            .and_then(|codec| preferred.iter().position(|p| p.eq_ignore_ascii_case(codec)))
+            .unwrap_or(preferred.len())
+    };
+    let mut fields: Vec<&str> = m_line.split(' ').collect();
+    if fields.len() < 4 {
+        return;
+    }
+    let mut formats = fields.split_off(3);
+    formats.sort_by_key(|pt| rank(pt));
+    fields.extend(formats);
+    let reordered = fields.join(" ");
+    section[0] = reordered;
+}
+
+/// Remove codecs from a section along with their retransmission formats,
+/// unless that would leave it without any
+fn strip_codecs(section: &mut Vec<String>, names: &[String]) {
+    let Some(m_line) = section.first() else {
+        return;
+    };
+    let codecs = rtpmaps(section);
+    let fmtps = fmtps(section);
+    let stripped: HashSet<String> = codecs
+        .keys()
+        .filter(|pt| {
+            codec_of(pt, &codecs, &fmtps)
+                .is_some_and(|codec| names.iter().any(|n| n.eq_ignore_ascii_case(codec)))
+        })
+        .map(|pt| pt.to_string())
+        .collect();
+    let mut fields: Vec<&str> = m_line.split(' ').collect();
+    if stripped.is_empty() || fields.len() < 4 {
+        return;
+    }
+    let formats = fields.split_off(3);
+    let kept: Vec<&str> = formats
+        .into_iter()
+        .filter(|pt| !stripped.contains(*pt))
+        .collect();
+    if kept.is_empty() {
+        return;
+    }
+    fields.extend(kept);
+    let m_line = fields.join(" ");
+    section[0] = m_line;
+    section.retain(|line| {
+        let pt = ["a=rtpmap:", "a=fmtp:", "a=rtcp-fb:"]
+            .iter()
+            .find_map(|prefix| line.strip_prefix(prefix))
+            .and_then(|rest| rest.split(' ').next());
+        !pt.is_some_and(|pt| stripped.contains(pt))
+    });
+}
+
+/// Drop the `a=extmap` lines of the given extension URIs
+fn strip_extensions(lines: &mut Vec<String>, uris: &[String]) {
+    lines.retain(|line| {
+        let uri = line
+            .strip_prefix("a=extmap:")
+            .and_then(|rest| rest.split(' ').nth(1));
+        !uri.is_some_and(|uri| uris.iter().any(|u| u == uri))
+    });
+}
+
+/// Cap a section's bandwidth with `b=AS`, keeping a lower cap already set
+fn cap_bandwidth(section: &mut Vec<String>, kbps: u32) {
+    let line = format!("b=AS:{}", kbps);
+    if let Some(i) = section.iter().position(|l| l.starts_with("b=AS:")) {
+        let set = section[i]["b=AS:".len()..].parse::<u32>().ok();
+        if set.is_none_or(|set| set > kbps) {
+            section[i] = line;
+        }
+        return;
+    }
+    // Bandwidth lines follow the section's title and connection lines
+    let at = section
+        .iter()
+        .skip(1)
+        .position(|l| !l.starts_with("i=") && !l.starts_with("c="))
+        .map_or(section.len(), |i| i + 1);
+    section.insert(at, line);
+}
+
+/// `fmtp` parameters for the Opus settings
+fn opus_params(opus: &OpusSettings) -> Vec<(&'static str, String)> {
+    let flag = |on: bool| u8::from(on).to_string();
+    let mut params = Vec::new();
+    if let Some(bitrate) = opus.max_average_bitrate {
+        params.push(("maxaveragebitrate", bitrate.to_string()));
+    }
+    if let Some(stereo) = opus.stereo {
+        params.push(("stereo", flag(stereo)));
+        params.push(("sprop-stereo", flag(stereo)));
+    }
+    if let Some(dtx) = opus.dtx {
+        params.push(("usedtx", flag(dtx)));
+    }
+    if let Some(fec) = opus.fec {
+        params.push(("useinbandfec", flag(fec)));
+    }
+    params
+}
+
+/// Set parameters on a section's Opus `fmtp` line, adding one after its
+/// `a=rtpmap` if there is none
+fn set_opus_params(section: &mut Vec<String>, params: &[(&'static str, String)]) {
+    let Some(pt) = rtpmaps(section)
+        .into_iter()
+        .find(|(_, codec)| codec.eq_ignore_ascii_case("opus"))
+        .map(|(pt, _)| pt.to_string())
+    else {
+        return;
+    };
+    let prefix = format!("a=fmtp:{} ", pt);
+    let existing = section.iter().position(|l| l.starts_with(&prefix));
+    let mut entries: Vec<String> = existing
+        .map(|i| section[i][prefix.len()..].split(';'))
+        .into_iter()
+        .flatten()
+        .map(|e| e.trim().to_string())
+        .filter(|e| !e.is_empty())
+        .collect();
+    for (key, value) in params {
+        let entry = format!("{}={}", key, value);
+        match entries
+            .iter_mut()
+            .find(|e| e.split('=').next() == Some(key))
+        {
+            Some(e) => *e = entry,
+            None => entries.push(entry),
+        }
+    }
+    let line = format!("{}{}", prefix, entries.join(";"));
+    match existing {
+        Some(i) => section[i] = line,
+        None => {
+            let rtpmap = format!("a=rtpmap:{} ", pt);
+            let at = section
+                .iter()
+                .position(|l| l.starts_with(&rtpmap))
+                .map_or(section.len(), |i| i + 1);
+            section.insert(at, line);
+        }
+    }
+}
+
+/// Apply a room's SDP policy, then its codec policy, to a session
+/// description
+pub fn rewrite(sdp: &str, codecs: Option<&CodecPolicy>, policy: Option<&SdpPolicy>) -> String {
+    let mut parsed = Sdp::parse(sdp);
+    if let Some(policy) = policy {
+        parsed.apply_policy(policy);
+    }
+    if let Some(codecs) = codecs {
+        parsed.apply_codec_policy(codecs);
+    }
+    parsed.to_string()
+}
+
+impl Room {
+    /// Apply the room's policies to a relayed offer or answer
+    pub fn rewrite_sdp(&self, msg: WsMessage) -> WsMessage {
+        let codecs = self.settings.codecs.as_deref();
+        let policy = self.settings.sdp.as_deref();
+        if codecs.is_none() && policy.is_none() {
+            return msg;
+        }
+        match msg {
+            WsMessage::Offer { sdp, peer_id } => WsMessage::Offer {
+                sdp: rewrite(&sdp, codecs, policy),
+                peer_id,
+            },
+            WsMessage::Answer { sdp, peer_id } => WsMessage::Answer {
+                sdp: rewrite(&sdp, codecs, policy),
+                peer_id,
+            },
+            other => other,
+        }
+    }
+}
@@ -0,0 +1,300 @@
+//! Full-text search over past and running calls
+//!
+//! Support staff look for "that call where X was discussed" with
+//! `GET /api/search?q=`. Every room with a call record or transcript is
+//! searched: its metadata, chat, captions and summary. A room matches when
+//! each word of the query starts a word somewhere in it; rooms with more
+//! hits rank first and show the lines that matched. Only text is matched:
+//! metadata values, not their keys. Rooms in memory are copied out of the
+//! shared maps and scored without holding their locks. Besides those, the
+//! record store is asked for rooms it holds that mention every word, which
+//! it finds through a word index (see [`search_words`]), so rooms this
+//! instance never loaded are found too. A tenant's API key searches only
+//! that tenant's rooms; an admin token searches all.
+//!
+//! There is deliberately no embedded index such as tantivy. One would live
+//! in a single instance's data directory, while room records are shared by
+//! every instance through the record store: a room saved by one instance
+//! must be found from all of them, and a room purged by retention must
+//! drop out everywhere at once. The word index is therefore kept next to
+//! the records, as a generated `tsvector` with a GIN index under Postgres
+//! (see `migrations/postgres/0007_search.sql`), and only rooms in memory
+//! are scored here.
+
+use std::collections::{BTreeSet, HashMap};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::admin::admin_principal;
+use crate::models::{
+    CallRecord, RoomMetadata, RoomTranscript, SearchField, SearchMatch, SearchResponse,
+    SearchResult, TranscriptKind,
+};
+use crate::persistence::RoomRecords;
+use crate::state::AppState;
+
+/// Results returned when `limit` is not given
+const DEFAULT_RESULTS: usize = 20;
+
+/// Most results returned at once
+const MAX_RESULTS: usize = 100;
+
+/// Matching lines shown per room
+const MAX_MATCHES_PER_ROOM: usize = 5;
+
+/// Most rooms taken from the record store per search, newest first
+pub const MAX_STORED_CANDIDATES: usize = 1000;
+
+/// Lowercased words of `text`
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// How many words of `text` start with one of `terms`, and which terms
+fn hits(text: &str, terms: &[String], found: &mut [bool]) -> usize {
+    let mut count = 0;
+    for word in words(text) {
+        for (i, term) in terms.iter().enumerate() {
+            if word.starts_with(term.as_str()) {
+                found[i] = true;
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+/// The text of a room that searches match: metadata values, summary,
+/// chat and captions
+fn searched_text<'a>(
+    metadata: Option<&'a RoomMetadata>,
+    transcript: Option<&'a RoomTranscript>,
+) -> impl Iterator<Item = &'a str> {
+    let values = metadata.into_iter().flat_map(|m| m.values());
+    let summary = transcript.and_then(|t| t.summary.as_ref());
+    let entries = transcript.into_iter().flat_map(|t| &t.entries);
+    values
+        .chain(summary)
+        .map(String::as_str)
+        .chain(entries.map(|e| e.text.as_str()))
+}
+
+/// Each word a room can be found by, once, separated by spaces; the record
+/// store indexes rooms by these
+pub fn search_words(
+    metadata: Option<&RoomMetadata>,
+    transcript: Option<&RoomTranscript>,
+) -> String {
+    let words: BTreeSet<String> = searched_text(metadata, transcript)
+        .flat_map(words)
+        .collect();
+    words.into_iter().collect::<Vec<_>>().join(" ")
+}
+
+/// What a room is scored on, copied out of the shared maps
+struct Candidate {
+    room_id: String,
+    started_at: Option<u64>,
+    metadata: RoomMetadata,
+    transcript: Option<RoomTranscript>,
+}
+
+impl Candidate {
+    fn new(room_id: String, call: Option<&CallRecord>) -> Self {
+        Self {
+            room_id,
+            started_at: call.map(|c| c.started_at),
+            metadata: call.map(|c| c.metadata.clone()).unwrap_or_default(),
+            transcript: None,
+        }
+    }
+}
+
+impl From<RoomRecords> for Candidate {
+    fn from(records: RoomRecords) -> Self {
+        Self {
+            transcript: records.transcript,
+            ..Self::new(records.room_id, records.call.as_ref())
+        }
+    }
+}
+
+/// How a room matches every one of `terms`, if it does
+fn score_room(room: Candidate, terms: &[String]) -> Option<SearchResult> {
+    let mut found = vec![false; terms.len()];
+    let mut score = 0;
+    let mut matches = Vec::new();
+    let mut consider = |m: SearchMatch, text: &str, found: &mut [bool]| {
+        let n = hits(text, terms, found);
+        score += n;
+        if n > 0 && matches.len() < MAX_MATCHES_PER_ROOM {
+            matches.push(m);
+        }
+    };
+    for (key, value) in &room.metadata {
+        let line = SearchMatch {
+            field: SearchField::Metadata,
+            peer_id: None,
+            timestamp: None,
+            text: format!("{}: {}", key, value),
+        };
+        consider(line, value, &mut found);
+    }
+    if let Some(transcript) = &room.transcript {
+        if let Some(summary) = &transcript.summary {
+            let line = SearchMatch {
+                field: SearchField::Summary,
+                peer_id: None,
+                timestamp: None,
+                text: summary.clone(),
+            };
+            consider(line, summary, &mut found);
+        }
+        for entry in &transcript.entries {
+            let line = SearchMatch {
+                field: match entry.kind {
+                    TranscriptKind::Chat => SearchField::Chat,
+                    TranscriptKind::Caption => SearchField::Caption,
+                },
+                peer_id: Some(entry.peer_id.clone()),
+                timestamp: Some(entry.timestamp),
+                text: entry.text.clone(),
+            };
+            consider(line, &entry.text, &mut found);
+        }
+    }
+    found.iter().all(|f| *f).then_some(SearchResult {
+        room_id: room.room_id,
+        score,
+        started_at: room.started_at,
+        metadata: room.metadata,
+        matches,
+    })
+}
+
+/// Whether a room with `call` is visible to a caller scoped to `tenant`
+fn in_scope(call: Option<&CallRecord>, tenant: Option<&str>) -> bool {
+    tenant.is_none_or(|tenant| call.and_then(|c| c.tenant.as_deref()) == Some(tenant))
+}
+
+impl AppState {
+    /// Rooms matching every word of `query`, best first; only the rooms of
+    /// `tenant` when given
+    pub async fn search(
+        &self,
+        query: &str,
+        tenant: Option<&str>,
+        limit: usize,
+    ) -> Vec<SearchResult> {
+        let terms: Vec<String> = words(query).collect();
+        if terms.is_empty() {
+            return Vec::new();
+        }
+        // One lock at a time, and none while scoring
+        let mut candidates: HashMap<String, Candidate> = (self.calls.lock().await.iter())
+            .filter(|(_, call)| in_scope(Some(call), tenant))
+            .map(|(id, call)| (id.clone(), Candidate::new(id.clone(), Some(call))))
+            .collect();
+        for (room_id, transcript) in self.transcripts.lock().await.iter() {
+            match candidates.get_mut(room_id) {
+                Some(room) => room.transcript = Some(transcript.clone()),
+                // Rooms without a call record belong to no tenant
+                None if in_scope(None, tenant) => {
+                    let mut room = Candidate::new(room_id.clone(), None);
+                    room.transcript = Some(transcript.clone());
+                    candidates.insert(room_id.clone(), room);
+                }
+                None => {}
+            }
+        }
+
+        if let Some(store) = self.store.get() {
+            let tenant = tenant.map(str::to_string);
+            match store.search(terms.clone(), tenant.clone()).await {
+                Ok(stored) => {
+                    for records in stored {
+                        if !candidates.contains_key(&records.room_id)
+                            && in_scope(records.call.as_ref(), tenant.as_deref())
+                        {
+                            candidates.insert(records.room_id.clone(), records.into());
+                        }
+                    }
+                }
+                Err(e) => warn!("Failed to search the record store: {}", e),
+            }
+        }
+        let mut results: Vec<SearchResult> = candidates
+            .into_values()
+            .filter_map(|room| score_room(room, &terms))
+            .collect();
+        results.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then(b.started_at.cmp(&a.started_at))
+                .then(a.room_id.cmp(&b.room_id))
+        });
+        results.truncate(limit);
+        results
+    }
+}
+
+/// Query parameters of a search
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Search room metadata, chat, captions and summaries
+///
+/// An admin token searches every room; a tenant's API key only the rooms
+/// created with the tenant's keys.
+#[utoipa::path(
+    get,
+    path = "/api/search",
+    tag = "Admin",
+    params(
+        ("q" = String, Query, description = "Words to find; each must start a word in the room"),
+        ("limit" = Option<usize>, Query, description = "Most rooms to return (default 20, at most 100)")
+    ),
+    responses(
+        (status = 200, description = "Matching rooms, best first", body = SearchResponse),
+        (status = 400, description = "Missing query or invalid limit"),
+        (status = 401, description = "Missing or invalid admin token or API key")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn search(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<SearchQuery>,
+) -> Response {
+    let config = state.config();
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let tenant = match token {
+        Some(token) if admin_principal(&config, token).is_some() => None,
+        Some(token) => match state.tenant_for_key(token).await {
+            Some(tenant) => Some(tenant),
+            None => return (StatusCode::UNAUTHORIZED, "Invalid token").into_response(),
+        },
+        None => return (StatusCode::UNAUTHORIZED, "Invalid token").into_response(),
+    };
+    let Some(query) = params.q.filter(|q| !q.trim().is_empty()) else {
+        return (StatusCode::BAD_REQUEST, "Missing query").into_response();
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_RESULTS).min(MAX_RESULTS);
+    let results = state.search(&query, tenant.as_deref(), limit).await;
+    Json(SearchResponse { query, results }).into_response()
+}
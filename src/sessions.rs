@@ -0,0 +1,96 @@
+//! One connection per person
+//!
+//! A peer is recognised on a second connection to the same room by the
+//! resume token of its first one, or by the `sub` of its room JWT or user
+//! token. Under the default `migrate` policy the new connection takes over
+//! the peer's slot, ID and role, and the old one is told and closed; `reject`
+//! turns the new connection away instead. Either way the person keeps a
+//! single slot.
+//!
+//! The old connection is recognised on its way out by its resume token,
+//! which the takeover replaces, so its departure does not remove the peer
+//! that moved.
+
+use serde::Deserialize;
+use tracing::info;
+
+use crate::models::WsMessage;
+use crate::state::{AppState, JoinedRoom, Peer, PeerSender, Room};
+use crate::token::{TokenScope, secret_eq, verify};
+
+/// What happens when someone already in a room connects again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateSessions {
+    /// Move the peer to the new connection and close the old one
+    #[default]
+    Migrate,
+    /// Keep the old connection and refuse the new one
+    Reject,
+}
+
+impl Room {
+    /// Whether `peer_id` is in the room, on the connection whose resume
+    /// token is `session` if given
+    pub fn holds(&self, peer_id: &str, session: Option<&str>) -> bool {
+        self.peers
+            .iter()
+            .any(|p| p.id == peer_id && session.is_none_or(|t| secret_eq(&p.resume_token, t)))
+    }
+
+    /// Handle a connection by someone already in the room
+    ///
+    /// Returns `None` if neither `token` nor `identity` matches a peer.
+    pub fn take_over(
+        &mut self,
+        token: Option<&str>,
+        identity: Option<&str>,
+        policy: DuplicateSessions,
+        sender: PeerSender,
+    ) -> Option<Result<JoinedRoom, &'static str>> {
+        let pos = self.peers.iter().position(|p| {
+            token.is_some_and(|t| secret_eq(&p.resume_token, t))
+                || identity.is_some_and(|i| p.identity.as_deref() == Some(i))
+        })?;
+        if policy == DuplicateSessions::Reject {
+            return Some(Err("You are already in this call on another connection"));
+        }
+
+        let old = &self.peers[pos];
+        let mut peer = Peer::new(old.id.clone(), sender);
+        peer.role = old.role;
+        peer.identity = old.identity.clone();
+        let _ = old.sender.send(WsMessage::error(
+            "This call was moved to another connection",
+        ));
+        old.sender.detach_room();
+        peer.sender.attach_room(&self.events, &peer.id);
+        let resume_token = peer.resume_token.clone();
+        let old = std::mem::replace(&mut self.peers[pos], peer);
+        info!("Peer {} moved to a new connection", old.id);
+        Some(Ok(self.joined_as(old.id, resume_token)))
+    }
+
+    /// Remember the authenticated identity of a peer
+    pub fn set_identity(&mut self, peer_id: &str, identity: Option<String>) {
+        if let Some(peer) = self.peers.iter_mut().find(|p| p.id == peer_id) {
+            peer.identity = identity;
+        }
+    }
+}
+
+impl AppState {
+    /// The subject of a room JWT for `room_id` or of a user token, if
+    /// `token` is one
+    pub fn identity_of(&self, room_id: &str, token: Option<&str>) -> Option<String> {
+        let config = self.config();
+        let claims = verify(config.jwt_secret.as_deref()?, token?).ok()?;
+        let for_room = claims.scope == TokenScope::Room && claims.room.as_deref() == Some(room_id);
+        let for_user = claims.scope == TokenScope::User
+            && self.is_provisioned(claims.sub.as_deref(), claims.email.as_deref());
+        if !for_room && !for_user {
+            return None;
+        }
+        claims.sub
+    }
+}
@@ -0,0 +1,313 @@
+//! Share links with expiry and use limits
+//!
+//! The room's host (authenticating with its resume token) or an operator
+//! can mint links into a room that expire, allow a limited number of joins,
+//! or both. A link's token passes the `require_room_token` check like a
+//! room JWT; a use is counted when it gets someone a slot or a place in
+//! line, and a join on a link that has run out is refused. Links are listed
+//! together with the room's re-invite links, which are single-use and hold a
+//! slot, and either kind can be revoked. Links live as long as their room.
+
+use std::time::Instant;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::admin::{AdminRole, admin_principal};
+use crate::models::{CreateInviteRequest, InviteLink, PeerRole};
+use crate::state::{AppState, Room, unix_timestamp};
+use crate::token::secret_eq;
+
+/// A link into a room
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareLink {
+    pub token: String,
+    pub max_uses: Option<u32>,
+    pub uses: u32,
+    /// Unix timestamp (seconds)
+    pub expires_at: Option<u64>,
+}
+
+impl ShareLink {
+    /// Whether the link can still be used at unix time `now`
+    pub fn is_live(&self, now: u64) -> bool {
+        self.max_uses.is_none_or(|max| self.uses < max) && self.expires_at.is_none_or(|at| now < at)
+    }
+
+    fn describe(&self, url: String) -> InviteLink {
+        InviteLink {
+            token: self.token.clone(),
+            url,
+            max_uses: self.max_uses,
+            uses: self.uses,
+            expires_at: self.expires_at,
+            holds_slot: false,
+            links: None,
+        }
+    }
+}
+
+/// Room link carrying `token`
+fn link_url(base_url: &str, room_id: &str, token: &str) -> String {
+    format!("{}/room/{}?token={}", base_url, room_id, token)
+}
+
+impl Room {
+    /// Refuse a join on a share link that has run out
+    pub fn check_share_link(&self, token: Option<&str>) -> Result<(), &'static str> {
+        let now = unix_timestamp();
+        match self
+            .share_links
+            .iter()
+            .find(|l| token.is_some_and(|t| secret_eq(&l.token, t)))
+        {
+            Some(link) if !link.is_live(now) => Err("This invite link has expired"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a join made with `token` if it is a share link
+    pub fn count_share_link_use(&mut self, token: Option<&str>) {
+        if let Some(link) = self
+            .share_links
+            .iter_mut()
+            .find(|l| token.is_some_and(|t| secret_eq(&l.token, t)))
+        {
+            link.uses += 1;
+        }
+    }
+
+    /// Share and re-invite links that can still be used
+    pub fn invite_links(&self, base_url: &str, room_id: &str) -> Vec<InviteLink> {
+        let (now, clock) = (unix_timestamp(), Instant::now());
+        let url = |token: &str| link_url(base_url, room_id, token);
+        let shares = self
+            .share_links
+            .iter()
+            .filter(|l| l.is_live(now))
+            .map(|l| l.describe(url(&l.token)));
+        let reinvites = self
+            .reservations
+            .iter()
+            .filter(|r| r.peer_id.is_none() && r.expires_at > clock)
+            .map(|r| InviteLink {
+                token: r.token.clone(),
+                url: url(&r.token),
+                max_uses: Some(1),
+                uses: 0,
+                expires_at: Some(now + (r.expires_at - clock).as_secs()),
+                holds_slot: true,
+                links: None,
+            });
+        shares.chain(reinvites).collect()
+    }
+
+    /// Withdraw a share or re-invite link; returns false if there is none
+    pub fn revoke_invite(&mut self, token: &str) -> bool {
+        let before = self.share_links.len() + self.reservations.len();
+        self.share_links.retain(|l| !secret_eq(&l.token, token));
+        self.reservations
+            .retain(|r| r.peer_id.is_some() || !secret_eq(&r.token, token));
+        self.share_links.len() + self.reservations.len() != before
+    }
+}
+
+impl AppState {
+    /// Who may manage a room's links: an admin with at least the operator
+    /// role, or the room's host
+    ///
+    /// Returns the actor name for the audit trail.
+    async fn invite_manager(
+        &self,
+        room_id: &str,
+        headers: &HeaderMap,
+    ) -> Result<String, (StatusCode, &'static str)> {
+        let provided = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::to_string);
+        let admin = provided
+            .as_deref()
+            .and_then(|token| admin_principal(&self.config(), token));
+        if let Some(admin) = admin.filter(|a| a.role >= AdminRole::Operator) {
+            return Ok(admin.principal);
+        }
+        let is_host = self
+            .with_room(room_id, move |room| {
+                provided.is_some_and(|token| {
+                    room.peers
+                        .iter()
+                        .any(|p| p.role == PeerRole::Host && secret_eq(&p.resume_token, &token))
+                })
+            })
+            .await
+            .ok_or((StatusCode::NOT_FOUND, "Room not found"))?;
+        match is_host {
+            true => Ok("host".to_string()),
+            false => {
+                warn!("Rejected invite request for room {}", room_id);
+                Err((StatusCode::UNAUTHORIZED, "Host or admin token required"))
+            }
+        }
+    }
+}
+
+/// Create a share link into a room
+#[utoipa::path(
+    post,
+    path = "/api/room/{room_id}/invites",
+    tag = "Rooms",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room")
+    ),
+    request_body(content = Option<CreateInviteRequest>, description = "Optional limits"),
+    responses(
+        (status = 200, description = "Link created", body = InviteLink),
+        (status = 400, description = "Zero uses or lifetime requested"),
+        (status = 401, description = "Missing host resume token or admin token"),
+        (status = 403, description = "Room is invite-only"),
+        (status = 404, description = "Room not found")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn create_invite(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Option<Json<CreateInviteRequest>>,
+) -> Response {
+    let actor = match state.invite_manager(&room_id, &headers).await {
+        Ok(actor) => actor,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let Json(request) = body.unwrap_or_default();
+    if request.max_uses == Some(0) || request.expires_in_secs == Some(0) {
+        return (StatusCode::BAD_REQUEST, "Link would never be usable").into_response();
+    }
+    let link = ShareLink {
+        token: Uuid::new_v4().simple().to_string(),
+        max_uses: request.max_uses,
+        uses: 0,
+        expires_at: request.expires_in_secs.map(|s| unix_timestamp() + s),
+    };
+    let config = state.config();
+    let url = link_url(
+        config.public_url.trim_end_matches('/'),
+        &room_id,
+        &link.token,
+    );
+    let links = config.deep_links(&room_id, Some(&link.token));
+    let created = state.with_room(&room_id, move |room| {
+        // Invitees come back with their own room tokens
+        if room.invitees.is_some() {
+            return Err((StatusCode::FORBIDDEN, "This room is invite-only"));
+        }
+        let mut described = link.describe(url);
+        described.links = links;
+        room.share_links.push(link);
+        Ok(described)
+    });
+    match created.await {
+        Some(Ok(link)) => {
+            info!("Created share link for room {}", room_id);
+            let detail = format!(
+                "max_uses={:?} expires_at={:?}",
+                link.max_uses, link.expires_at
+            );
+            state
+                .record_audit(Some(&room_id), &actor, "invite.create", detail)
+                .await;
+            state.save_room_state(&room_id).await;
+            Json(link).into_response()
+        }
+        Some(Err(rejection)) => rejection.into_response(),
+        None => (StatusCode::NOT_FOUND, "Room not found").into_response(),
+    }
+}
+
+/// List a room's outstanding share and re-invite links
+#[utoipa::path(
+    get,
+    path = "/api/room/{room_id}/invites",
+    tag = "Rooms",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room")
+    ),
+    responses(
+        (status = 200, description = "Links that can still be used", body = Vec<InviteLink>),
+        (status = 401, description = "Missing host resume token or admin token"),
+        (status = 404, description = "Room not found")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_invites(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(rejection) = state.invite_manager(&room_id, &headers).await {
+        return rejection.into_response();
+    }
+    let config = state.config();
+    let base = config.public_url.trim_end_matches('/').to_string();
+    let rid = room_id.clone();
+    match state
+        .with_room(&room_id, move |room| room.invite_links(&base, &rid))
+        .await
+    {
+        Some(mut links) => {
+            for link in &mut links {
+                link.links = config.deep_links(&room_id, Some(&link.token));
+            }
+            Json(links).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "Room not found").into_response(),
+    }
+}
+
+/// Revoke a share or re-invite link
+#[utoipa::path(
+    delete,
+    path = "/api/room/{room_id}/invites/{token}",
+    tag = "Rooms",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room"),
+        ("token" = String, Path, description = "The link's token")
+    ),
+    responses(
+        (status = 204, description = "Link revoked"),
+        (status = 401, description = "Missing host resume token or admin token"),
+        (status = 404, description = "Room or link not found")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn revoke_invite(
+    Path((room_id, token)): Path<(String, String)>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    let actor = match state.invite_manager(&room_id, &headers).await {
+        Ok(actor) => actor,
+        Err(rejection) => return rejection.into_response(),
+    };
+    let revoked = state
+        .with_room(&room_id, move |room| room.revoke_invite(&token))
+        .await;
+    if revoked != Some(true) {
+        return (StatusCode::NOT_FOUND, "No such link").into_response();
+    }
+    info!("Revoked a link into room {}", room_id);
+    state.save_room_state(&room_id).await;
+    state
+        .record_audit(Some(&room_id), &actor, "invite.revoke", "")
+        .await;
+    StatusCode::NO_CONTENT.into_response()
+}
@@ -0,0 +1,205 @@
+//! Signed URLs
+//!
+//! With a `signed_urls` section, an operator can hand out a link to a
+//! recording file, an export bundle or a transcript that works without
+//! an API key until it expires. `POST /admin/signed-urls` signs a path
+//! with `?expires=<unix secs>&signature=<hex>`, where the signature is an
+//! HMAC-SHA256 keyed by `secret` over the method, path and expiry, so a
+//! link opens only the one artifact and cannot be extended. Rotating
+//! `secret` revokes every link.
+//!
+//! Only paths matching one of [`SIGNABLE_PATHS`] can be signed, and all of
+//! them are served by public listeners, so links work wherever the
+//! operator API is split off. A link past its expiry gets 403. With
+//! `signed_urls` set, transcripts are no longer public: they need an
+//! admin token or a signed URL, like recordings and exports.
+
+use axum::{
+    Json,
+    extract::{FromRequestParts, Query, State},
+    http::{StatusCode, Uri, request::Parts},
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use tracing::info;
+
+use crate::admin::OperatorAuth;
+use crate::models::{SignUrlRequest, SignedUrl};
+use crate::state::{AppState, unix_timestamp};
+
+/// Paths that can be signed; `*` stands for one path segment
+pub const SIGNABLE_PATHS: &[&str] = &[
+    "/api/recordings/*/files/*",
+    "/api/exports/*/download",
+    "/api/room/*/transcript",
+];
+
+/// The signing key and how long links last
+#[derive(Debug, Clone, Deserialize)]
+pub struct SignedUrlConfig {
+    pub secret: String,
+    /// Lifetime of a link when the request gives none, capped at
+    /// `max_ttl_secs`
+    #[serde(default = "default_ttl_secs")]
+    pub default_ttl_secs: u64,
+    /// Longest lifetime a link may be given
+    #[serde(default = "default_max_ttl_secs")]
+    pub max_ttl_secs: u64,
+}
+
+fn default_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_max_ttl_secs() -> u64 {
+    7 * 24 * 3600
+}
+
+/// Whether `path` matches one of [`SIGNABLE_PATHS`]
+pub fn is_signable(path: &str) -> bool {
+    SIGNABLE_PATHS.iter().any(|pattern| {
+        let (mut wanted, mut given) = (pattern.split('/'), path.split('/'));
+        loop {
+            match (wanted.next(), given.next()) {
+                (None, None) => return true,
+                (Some("*"), Some(segment)) if !segment.is_empty() => {}
+                (Some(w), Some(g)) if w == g => {}
+                _ => return false,
+            }
+        }
+    })
+}
+
+/// HMAC-SHA256 over a GET of `path` valid until `expires`
+fn url_mac(secret: &str, path: &str, expires: u64) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("GET\n{}\n{}", path, expires).as_bytes());
+    mac
+}
+
+/// Hex HMAC-SHA256 of a GET of `path` valid until `expires`
+pub fn url_signature(secret: &str, path: &str, expires: u64) -> String {
+    format!(
+        "{:x}",
+        url_mac(secret, path, expires).finalize().into_bytes()
+    )
+}
+
+/// Bytes of a hex string, if it is one
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Signature query parameters
+#[derive(Debug, Deserialize)]
+struct SignatureQuery {
+    expires: u64,
+    signature: String,
+}
+
+impl AppState {
+    /// Expiry of the genuine signature `uri` carries for its path, if any
+    fn signed_until(&self, uri: &Uri) -> Option<u64> {
+        let config = self.config();
+        let signing = config.signed_urls.as_ref()?;
+        let Query(query) = Query::<SignatureQuery>::try_from_uri(uri).ok()?;
+        let signature = from_hex(&query.signature)?;
+        (is_signable(uri.path())
+            && url_mac(&signing.secret, uri.path(), query.expires)
+                .verify_slice(&signature)
+                .is_ok())
+        .then_some(query.expires)
+    }
+
+    /// Whether `uri` carries a live signature for its path
+    pub fn is_signed(&self, uri: &Uri) -> bool {
+        self.signed_until(uri)
+            .is_some_and(|expires| expires > unix_timestamp())
+    }
+
+    /// Whether `uri` is a signed link that has expired
+    pub fn is_expired_link(&self, uri: &Uri) -> bool {
+        self.signed_until(uri)
+            .is_some_and(|expires| expires <= unix_timestamp())
+    }
+}
+
+/// Extractor admitting an operator, or anyone with a signed URL for the
+/// requested path; holds who is acting, for the audit trail
+pub struct OperatorOrSigned(pub String);
+
+impl FromRequestParts<AppState> for OperatorOrSigned {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if state.is_signed(&parts.uri) {
+            return Ok(OperatorOrSigned("signed-url".to_string()));
+        }
+        if state.is_expired_link(&parts.uri) {
+            return Err((StatusCode::FORBIDDEN, "This link has expired").into_response());
+        }
+        let OperatorAuth(auth) = OperatorAuth::from_request_parts(parts, state).await?;
+        Ok(OperatorOrSigned(auth.principal))
+    }
+}
+
+/// Sign a URL for a recording file, export bundle or transcript
+#[utoipa::path(
+    post,
+    path = "/admin/signed-urls",
+    tag = "Admin",
+    request_body = SignUrlRequest,
+    responses(
+        (status = 200, description = "The signed URL", body = SignedUrl),
+        (status = 400, description = "The path cannot be signed, or the lifetime is too long"),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "Signed URLs are not enabled")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn sign_url(
+    OperatorAuth(auth): OperatorAuth,
+    State(state): State<AppState>,
+    Json(request): Json<SignUrlRequest>,
+) -> Response {
+    let config = state.config();
+    let Some(signing) = config.signed_urls.as_ref() else {
+        return (StatusCode::NOT_FOUND, "Signed URLs are not enabled").into_response();
+    };
+    if !is_signable(&request.path) {
+        return (StatusCode::BAD_REQUEST, "This path cannot be signed").into_response();
+    }
+    let ttl = request
+        .ttl_secs
+        .unwrap_or(signing.default_ttl_secs.min(signing.max_ttl_secs));
+    if ttl == 0 || ttl > signing.max_ttl_secs {
+        return (StatusCode::BAD_REQUEST, "Lifetime is out of range").into_response();
+    }
+    let expires_at = unix_timestamp() + ttl;
+    let signature = url_signature(&signing.secret, &request.path, expires_at);
+    let url = format!(
+        "{}{}?expires={}&signature={}",
+        config.public_url.trim_end_matches('/'),
+        request.path,
+        expires_at,
+        signature
+    );
+    info!("Signed {} for {} seconds", request.path, ttl);
+    state
+        .record_audit(None, &auth.principal, "url.sign", &request.path)
+        .await;
+    Json(SignedUrl { url, expires_at }).into_response()
+}
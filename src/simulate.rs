@@ -0,0 +1,409 @@
+//! Load-testing against a running server
+//!
+//! `axi-vid simulate` opens synthetic WebSocket clients in pairs, one room
+//! per pair, and walks each pair through a realistic call: join, offer,
+//! answer, ICE candidates, a burst of chat, then hang up. Every relayed
+//! message carries its send time so the report can show relay latency
+//! percentiles alongside connection and delivery success rates.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::models::{LeaveReason, WsMessage};
+use crate::token::{self, Claims, TokenScope};
+
+/// Options for `axi-vid simulate`
+#[derive(Debug, Args)]
+pub struct SimulateArgs {
+    /// Base URL of the target server (defaults to `public_url`)
+    #[arg(long)]
+    pub url: Option<String>,
+    /// Number of synthetic clients, two per room
+    #[arg(long, default_value_t = 20)]
+    pub clients: usize,
+    /// Chat messages each client sends once the call is set up
+    #[arg(long, default_value_t = 20)]
+    pub messages: usize,
+    /// Delay between two messages from the same client, in milliseconds
+    #[arg(long, default_value_t = 50)]
+    pub interval_ms: u64,
+    /// Seconds to wait for any expected message before giving up on a room
+    #[arg(long, default_value_t = 10)]
+    pub timeout_secs: u64,
+}
+
+/// ICE candidates each side trickles after the offer/answer exchange
+const CANDIDATES_PER_CLIENT: usize = 2;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Write half of a client connection
+trait WsSink: Sink<Message, Error = WsError> + Unpin {}
+impl<T: Sink<Message, Error = WsError> + Unpin> WsSink for T {}
+
+/// Read half of a client connection
+trait WsStream: Stream<Item = Result<Message, WsError>> + Unpin {}
+impl<T: Stream<Item = Result<Message, WsError>> + Unpin> WsStream for T {}
+
+/// Outcome of one simulated room
+#[derive(Debug, Default)]
+struct RoomResult {
+    connected: usize,
+    negotiated: bool,
+    received: usize,
+    latencies: Vec<Duration>,
+    error: Option<String>,
+}
+
+/// Run the simulation and print a report
+pub async fn run(config: &Config, args: SimulateArgs) -> Result<(), String> {
+    let base = args
+        .url
+        .clone()
+        .unwrap_or_else(|| config.public_url.clone());
+    let ws_base = base
+        .trim_end_matches('/')
+        .replacen("https://", "wss://", 1)
+        .replacen("http://", "ws://", 1);
+    if !ws_base.starts_with("ws://") && !ws_base.starts_with("wss://") {
+        return Err(format!("Unsupported URL: {}", base));
+    }
+    let rooms = args.clients.div_ceil(2).max(1);
+    let clients = rooms * 2;
+    println!(
+        "Simulating {} clients in {} rooms against {}",
+        clients, rooms, ws_base
+    );
+
+    let started = Instant::now();
+    let mut tasks = JoinSet::new();
+    for _ in 0..rooms {
+        let room_id = Uuid::new_v4().to_string();
+        let url = match config.jwt_secret.as_deref() {
+            Some(secret) => {
+                let claims = Claims::new(TokenScope::Room, Some(room_id.clone()), 3600);
+                format!(
+                    "{}/ws/{}?token={}",
+                    ws_base,
+                    room_id,
+                    token::mint(secret, &claims)
+                )
+            }
+            None => format!("{}/ws/{}", ws_base, room_id),
+        };
+        let sim = Simulation {
+            url,
+            messages: args.messages,
+            interval: Duration::from_millis(args.interval_ms),
+            timeout: Duration::from_secs(args.timeout_secs),
+            epoch: started,
+        };
+        tasks.spawn(async move { sim.run_room().await });
+    }
+
+    let mut results = Vec::with_capacity(rooms);
+    while let Some(result) = tasks.join_next().await {
+        results.push(result.unwrap_or_else(|e| RoomResult {
+            error: Some(format!("task failed: {}", e)),
+            ..Default::default()
+        }));
+    }
+    report(&results, clients, args.messages, started.elapsed());
+    Ok(())
+}
+
+/// Shared parameters for one room's pair of clients
+struct Simulation {
+    url: String,
+    messages: usize,
+    interval: Duration,
+    timeout: Duration,
+    /// Reference point for the timestamps embedded in relayed messages
+    epoch: Instant,
+}
+
+impl Simulation {
+    async fn run_room(&self) -> RoomResult {
+        let mut result = RoomResult::default();
+        if let Err(e) = self.call(&mut result).await {
+            result.error = Some(e);
+        }
+        result
+    }
+
+    /// Drive a full call between two clients, filling in `result` as it goes
+    async fn call(&self, result: &mut RoomResult) -> Result<(), String> {
+        let mut caller = self.connect().await?;
+        result.connected += 1;
+        let mut callee = self.connect().await?;
+        result.connected += 1;
+
+        // Offer/answer
+        self.send(
+            &mut caller,
+            WsMessage::Offer {
+                sdp: self.sdp("offer"),
+                peer_id: None,
+            },
+        )
+        .await?;
+        let offer = self
+            .expect(&mut callee, |m| matches!(m, WsMessage::Offer { .. }))
+            .await?;
+        result.latencies.extend(self.latency(&offer));
+        self.send(
+            &mut callee,
+            WsMessage::Answer {
+                sdp: self.sdp("answer"),
+                peer_id: None,
+            },
+        )
+        .await?;
+        let answer = self
+            .expect(&mut caller, |m| matches!(m, WsMessage::Answer { .. }))
+            .await?;
+        result.latencies.extend(self.latency(&answer));
+        result.negotiated = true;
+
+        // Trickle ICE both ways
+        for socket in [&mut caller, &mut callee] {
+            for index in 0..CANDIDATES_PER_CLIENT {
+                self.send(socket, self.candidate(index)).await?;
+            }
+        }
+        for socket in [&mut caller, &mut callee] {
+            for _ in 0..CANDIDATES_PER_CLIENT {
+                let candidate = self
+                    .expect(socket, |m| matches!(m, WsMessage::IceCandidate { .. }))
+                    .await?;
+                result.latencies.extend(self.latency(&candidate));
+            }
+        }
+
+        // Chat in both directions at once, reading while writing
+        let (mut caller_tx, mut caller_rx) = caller.split();
+        let (mut callee_tx, mut callee_rx) = callee.split();
+        let (sent_a, sent_b, got_a, got_b) = tokio::join!(
+            self.chat(&mut caller_tx),
+            self.chat(&mut callee_tx),
+            self.receive_chat(&mut caller_rx),
+            self.receive_chat(&mut callee_rx),
+        );
+        for got in [got_a, got_b] {
+            let latencies = got?;
+            result.received += latencies.len();
+            result.latencies.extend(latencies);
+        }
+        sent_a?;
+        sent_b?;
+        let caller = caller_tx.reunite(caller_rx).map_err(|e| e.to_string())?;
+        let callee = callee_tx.reunite(callee_rx).map_err(|e| e.to_string())?;
+
+        // Hang up
+        for mut socket in [caller, callee] {
+            let leave = WsMessage::Leave {
+                peer_id: None,
+                reason: Some(LeaveReason::UserHangup),
+            };
+            let _ = self.send(&mut socket, leave).await;
+            let _ = socket.close(None).await;
+        }
+        Ok(())
+    }
+
+    /// Connect and wait for the server's `Welcome`
+    async fn connect(&self) -> Result<Socket, String> {
+        let connect = tokio_tungstenite::connect_async(self.url.as_str());
+        let (mut socket, _) = tokio::time::timeout(self.timeout, connect)
+            .await
+            .map_err(|_| "connect timed out".to_string())?
+            .map_err(|e| format!("connect failed: {}", e))?;
+        self.expect(&mut socket, |m| matches!(m, WsMessage::Welcome { .. }))
+            .await?;
+        Ok(socket)
+    }
+
+    /// Send `messages` chat messages spaced by `interval`
+    async fn chat(&self, sink: &mut (impl WsSink + ?Sized)) -> Result<(), String> {
+        for _ in 0..self.messages {
+            let message = self.stamp();
+            self.send(sink, WsMessage::Chat { message }).await?;
+            tokio::time::sleep(self.interval).await;
+        }
+        Ok(())
+    }
+
+    /// Collect the other side's chat messages, returning their latencies
+    async fn receive_chat(
+        &self,
+        stream: &mut (impl WsStream + ?Sized),
+    ) -> Result<Vec<Duration>, String> {
+        let mut latencies = Vec::with_capacity(self.messages);
+        for _ in 0..self.messages {
+            let chat = self
+                .expect(stream, |m| matches!(m, WsMessage::Chat { .. }))
+                .await?;
+            latencies.push(self.latency(&chat).unwrap_or_default());
+        }
+        Ok(latencies)
+    }
+
+    async fn send(&self, sink: &mut (impl WsSink + ?Sized), msg: WsMessage) -> Result<(), String> {
+        let text = serde_json::to_string(&msg).map_err(|e| e.to_string())?;
+        sink.send(Message::text(text))
+            .await
+            .map_err(|e| format!("send failed: {}", e))
+    }
+
+    /// Read until a message matching `wanted` arrives, skipping the rest
+    async fn expect(
+        &self,
+        stream: &mut (impl WsStream + ?Sized),
+        wanted: impl Fn(&WsMessage) -> bool,
+    ) -> Result<WsMessage, String> {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            let frame = tokio::time::timeout_at(deadline, stream.next())
+                .await
+                .map_err(|_| "timed out waiting for a relayed message".to_string())?;
+            let text = match frame {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(Message::Close(_))) | None => {
+                    return Err("server closed the connection".into());
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(format!("receive failed: {}", e)),
+            };
+            match serde_json::from_str::<WsMessage>(&text) {
+                Ok(WsMessage::Error { message }) => {
+                    return Err(format!("server error: {}", message));
+                }
+                Ok(msg) if wanted(&msg) => return Ok(msg),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Microseconds since the epoch, as embedded in outgoing payloads
+    fn stamp(&self) -> String {
+        format!("sim-{}", self.epoch.elapsed().as_micros())
+    }
+
+    /// Relay latency of a message carrying a `stamp`
+    fn latency(&self, msg: &WsMessage) -> Option<Duration> {
+        let text = match msg {
+            WsMessage::Offer { sdp, .. } | WsMessage::Answer { sdp, .. } => sdp,
+            WsMessage::IceCandidate { candidate, .. } => candidate,
+            WsMessage::Chat { message } => message,
+            _ => return None,
+        };
+        let start = text.find("sim-")? + "sim-".len();
+        let digits: String = text[start..]
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        let sent = Duration::from_micros(digits.parse().ok()?);
+        self.epoch.elapsed().checked_sub(sent)
+    }
+
+    /// Minimal SDP with the send time as the session name
+    fn sdp(&self, kind: &str) -> String {
+        format!(
+            "v=0\r\no=- {} 2 IN IP4 127.0.0.1\r\ns={}\r\nt=0 0\r\n\
+             m=audio 9 UDP/TLS/RTP/SAVPF 111\r\nc=IN IP4 0.0.0.0\r\n\
+             a=mid:0\r\na={}\r\na=rtpmap:111 opus/48000/2\r\n",
+            Uuid::new_v4().as_u128() as u64,
+            self.stamp(),
+            if kind == "offer" {
+                "setup:actpass"
+            } else {
+                "setup:active"
+            },
+        )
+    }
+
+    /// Host candidate with the send time as its foundation
+    fn candidate(&self, index: usize) -> WsMessage {
+        WsMessage::IceCandidate {
+            candidate: format!(
+                "candidate:{} 1 udp 2122260223 192.0.2.{} {} typ host",
+                self.stamp(),
+                index + 1,
+                50000 + index
+            ),
+            sdp_m_line_index: 0,
+            sdp_mid: Some("0".to_string()),
+            peer_id: None,
+        }
+    }
+}
+
+/// Print success rates and latency percentiles
+fn report(results: &[RoomResult], clients: usize, messages: usize, elapsed: Duration) {
+    let connected: usize = results.iter().map(|r| r.connected).sum();
+    let negotiated = results.iter().filter(|r| r.negotiated).count();
+    let received: usize = results.iter().map(|r| r.received).sum();
+    let expected = clients * messages;
+    let mut latencies: Vec<Duration> = results.iter().flat_map(|r| r.latencies.clone()).collect();
+    latencies.sort();
+
+    let percent = |n: usize, of: usize| match of {
+        0 => 100.0,
+        _ => n as f64 * 100.0 / of as f64,
+    };
+    println!("Finished in {:.1}s", elapsed.as_secs_f64());
+    println!(
+        "Connected:   {}/{} clients ({:.1}%)",
+        connected,
+        clients,
+        percent(connected, clients)
+    );
+    println!(
+        "Negotiated:  {}/{} rooms ({:.1}%)",
+        negotiated,
+        results.len(),
+        percent(negotiated, results.len())
+    );
+    println!(
+        "Chat:        {}/{} messages delivered ({:.1}%)",
+        received,
+        expected,
+        percent(received, expected)
+    );
+
+    if latencies.is_empty() {
+        println!("Latency:     no relayed messages");
+    } else {
+        let at = |p: f64| {
+            let index = ((latencies.len() - 1) as f64 * p).round() as usize;
+            latencies[index].as_secs_f64() * 1000.0
+        };
+        println!(
+            "Latency:     p50 {:.2}ms  p90 {:.2}ms  p99 {:.2}ms  max {:.2}ms ({} samples)",
+            at(0.50),
+            at(0.90),
+            at(0.99),
+            at(1.0),
+            latencies.len()
+        );
+    }
+
+    let mut errors: HashMap<&str, usize> = HashMap::new();
+    for error in results.iter().filter_map(|r| r.error.as_deref()) {
+        *errors.entry(error).or_default() += 1;
+    }
+    let mut errors: Vec<_> = errors.into_iter().collect();
+    errors.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    for (error, count) in errors {
+        println!("Error ({} rooms): {}", count, error);
+    }
+}
@@ -0,0 +1,657 @@
+//! SIP gateway bridge (feature `sip`)
+//!
+//! Registers with a SIP trunk over UDP and bridges incoming INVITEs into
+//! rooms as virtual peers. The user part of the Request-URI selects the room
+//! (`sip:<room-uuid>@gateway`). INVITEs are only taken from the trunk's
+//! address and only into running rooms, and pass the same admission checks
+//! as WebSocket joins; a room token goes in an `X-Room-Token` header. Only
+//! signaling is bridged: the phone side
+//! must speak WebRTC media (ICE + DTLS-SRTP), as PBXs with WebRTC endpoints
+//! do. Trickled ICE candidates from the web peer are folded into the SDP
+//! answer before it is sent back as `200 OK`.
+//!
+//! This is a minimal user agent: no TCP/TLS transport, no retransmission
+//! timers and no re-INVITE handling.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::lanes::{PeerReceiver, peer_channel};
+use crate::models::{LeaveReason, WsMessage};
+use crate::state::AppState;
+
+/// How long to keep collecting trickled ICE candidates after an answer
+const CANDIDATE_GATHER_WINDOW: Duration = Duration::from_secs(1);
+
+/// Digest challenges answered per registration before giving up
+const MAX_AUTH_ATTEMPTS: u32 = 3;
+
+/// SIP trunk settings
+#[derive(Debug, Clone, Deserialize)]
+pub struct SipConfig {
+    /// Local UDP address to listen on
+    #[serde(default = "default_bind")]
+    pub bind: SocketAddr,
+    /// Trunk registrar address (`host:port`)
+    pub trunk: String,
+    /// SIP domain used in From/To/Request-URI
+    pub domain: String,
+    pub username: String,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Registration lifetime in seconds
+    #[serde(default = "default_register_interval")]
+    pub register_interval_secs: u64,
+}
+
+fn default_bind() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 5060))
+}
+
+fn default_register_interval() -> u64 {
+    300
+}
+
+/// A parsed SIP request or response
+#[derive(Debug, Clone)]
+struct SipMessage {
+    start_line: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl SipMessage {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(data).ok()?;
+        let (head, body) = text.split_once("\r\n\r\n").unwrap_or((text, ""));
+        let mut lines = head.split("\r\n");
+        let start_line = lines.next()?.to_string();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (expand_compact(name.trim()), value.trim().to_string()))
+            .collect();
+        Some(Self {
+            start_line,
+            headers,
+            body: body.to_string(),
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn all_headers<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Method for requests, `None` for responses
+    fn method(&self) -> Option<&str> {
+        if self.start_line.starts_with("SIP/2.0") {
+            None
+        } else {
+            self.start_line.split(' ').next()
+        }
+    }
+
+    /// Status code for responses
+    fn status(&self) -> Option<u16> {
+        self.start_line
+            .strip_prefix("SIP/2.0 ")?
+            .get(..3)?
+            .parse()
+            .ok()
+    }
+
+    fn request_uri(&self) -> Option<&str> {
+        self.start_line.split(' ').nth(1)
+    }
+
+    /// Build a response to this request, echoing the dialog headers
+    fn response(&self, status: &str, to_tag: Option<&str>, body: Option<&str>) -> String {
+        self.response_with(status, to_tag, "", body)
+    }
+
+    /// Like `response`, with extra pre-formatted header lines
+    fn response_with(
+        &self,
+        status: &str,
+        to_tag: Option<&str>,
+        extra_headers: &str,
+        body: Option<&str>,
+    ) -> String {
+        let mut out = format!("SIP/2.0 {}\r\n", status);
+        for via in self.all_headers("Via") {
+            out.push_str(&format!("Via: {}\r\n", via));
+        }
+        for name in ["From", "Call-ID", "CSeq"] {
+            if let Some(value) = self.header(name) {
+                out.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        if let Some(to) = self.header("To") {
+            match to_tag {
+                Some(tag) if !to.contains(";tag=") => {
+                    out.push_str(&format!("To: {};tag={}\r\n", to, tag))
+                }
+                _ => out.push_str(&format!("To: {}\r\n", to)),
+            }
+        }
+        out.push_str(extra_headers);
+        finish(out, body)
+    }
+}
+
+/// Map compact header forms to their full names
+fn expand_compact(name: &str) -> String {
+    match name {
+        "v" => "Via",
+        "f" => "From",
+        "t" => "To",
+        "i" => "Call-ID",
+        "m" => "Contact",
+        "l" => "Content-Length",
+        "c" => "Content-Type",
+        other => other,
+    }
+    .to_string()
+}
+
+/// Append body headers and the body itself
+fn finish(mut out: String, body: Option<&str>) -> String {
+    match body {
+        Some(body) => {
+            out.push_str("Content-Type: application/sdp\r\n");
+            out.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+        }
+        None => out.push_str("Content-Length: 0\r\n\r\n"),
+    }
+    out
+}
+
+/// An active bridged call
+struct CallLeg {
+    room_id: String,
+    peer_id: String,
+    remote: SocketAddr,
+    invite: SipMessage,
+    to_tag: String,
+}
+
+/// Digest challenges answered since the last unauthenticated REGISTER
+#[derive(Debug, Default)]
+struct AuthAttempts {
+    count: u32,
+    nonce: Option<String>,
+}
+
+/// Shared gateway state
+struct Gateway {
+    config: SipConfig,
+    socket: UdpSocket,
+    local: SocketAddr,
+    state: AppState,
+    calls: Mutex<HashMap<String, CallLeg>>,
+    register_cseq: Mutex<u32>,
+    auth_attempts: Mutex<AuthAttempts>,
+}
+
+/// Start the SIP gateway in the background, returning its UDP address
+pub async fn spawn(config: SipConfig, state: AppState) -> std::io::Result<SocketAddr> {
+    let socket = UdpSocket::bind(config.bind).await?;
+    let local = socket.local_addr()?;
+    info!("SIP gateway listening on udp://{}", local);
+
+    let gateway = Arc::new(Gateway {
+        config,
+        socket,
+        local,
+        state,
+        calls: Mutex::new(HashMap::new()),
+        register_cseq: Mutex::new(0),
+        auth_attempts: Mutex::new(AuthAttempts::default()),
+    });
+
+    let registrar = gateway.clone();
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(registrar.config.register_interval_secs));
+        loop {
+            interval.tick().await;
+            if let Err(e) = registrar.register(None).await {
+                error!("SIP registration failed: {}", e);
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 65535];
+        loop {
+            let (len, from) = match gateway.socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("SIP socket error: {}", e);
+                    continue;
+                }
+            };
+            let Some(msg) = SipMessage::parse(&buf[..len]) else {
+                debug!("Ignoring unparseable SIP datagram from {}", from);
+                continue;
+            };
+            let gateway = gateway.clone();
+            tokio::spawn(async move { gateway.handle(msg, from).await });
+        }
+    });
+
+    Ok(local)
+}
+
+impl Gateway {
+    async fn send(&self, to: SocketAddr, data: &str) {
+        if let Err(e) = self.socket.send_to(data.as_bytes(), to).await {
+            warn!("Failed to send SIP message to {}: {}", to, e);
+        }
+    }
+
+    async fn trunk_addr(&self) -> std::io::Result<SocketAddr> {
+        tokio::net::lookup_host(&self.config.trunk)
+            .await?
+            .next()
+            .ok_or_else(|| std::io::Error::other("trunk did not resolve"))
+    }
+
+    /// Send a REGISTER, optionally answering a digest challenge
+    async fn register(&self, challenge: Option<&str>) -> std::io::Result<()> {
+        let trunk = self.trunk_addr().await?;
+        if challenge.is_none() {
+            *self.auth_attempts.lock().await = AuthAttempts::default();
+        }
+        let cseq = {
+            let mut cseq = self.register_cseq.lock().await;
+            *cseq += 1;
+            *cseq
+        };
+        let uri = format!("sip:{}", self.config.domain);
+        let aor = format!("sip:{}@{}", self.config.username, self.config.domain);
+        let mut out = format!(
+            "REGISTER {uri} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {local};branch=z9hG4bK{branch}\r\n\
+             Max-Forwards: 70\r\n\
+             From: <{aor}>;tag=axivid\r\n\
+             To: <{aor}>\r\n\
+             Call-ID: axivid-register@{local}\r\n\
+             CSeq: {cseq} REGISTER\r\n\
+             Contact: <sip:{user}@{local}>\r\n\
+             Expires: {expires}\r\n",
+            local = self.local,
+            branch = Uuid::new_v4().simple(),
+            user = self.config.username,
+            expires = self.config.register_interval_secs,
+        );
+        if let (Some(challenge), Some(password)) = (challenge, &self.config.password) {
+            out.push_str(&format!(
+                "Authorization: {}\r\n",
+                digest_authorization(challenge, &self.config.username, password, "REGISTER", &uri)
+            ));
+        }
+        self.send(trunk, &finish(out, None)).await;
+        Ok(())
+    }
+
+    async fn handle(&self, msg: SipMessage, from: SocketAddr) {
+        match msg.method() {
+            Some("INVITE") => self.handle_invite(msg, from).await,
+            Some("BYE") => self.handle_bye(msg, from).await,
+            Some("CANCEL") => self.handle_bye(msg, from).await,
+            Some("ACK") => {}
+            Some("OPTIONS") => self.send(from, &msg.response("200 OK", None, None)).await,
+            Some(other) => {
+                debug!("Unsupported SIP method {}", other);
+                self.send(from, &msg.response("501 Not Implemented", None, None))
+                    .await;
+            }
+            None => self.handle_response(msg).await,
+        }
+    }
+
+    async fn handle_response(&self, msg: SipMessage) {
+        let is_register = msg.header("CSeq").is_some_and(|c| c.ends_with("REGISTER"));
+        match (is_register, msg.status()) {
+            (true, Some(200)) => info!("Registered with SIP trunk {}", self.config.trunk),
+            (true, Some(401 | 407)) => {
+                let challenge = msg
+                    .header("WWW-Authenticate")
+                    .or_else(|| msg.header("Proxy-Authenticate"));
+                match challenge {
+                    Some(challenge) => {
+                        let challenge = challenge.to_string();
+                        let nonce = digest_param(&challenge, "nonce");
+                        {
+                            let mut attempts = self.auth_attempts.lock().await;
+                            // A second challenge with the same nonce means
+                            // the credentials were refused
+                            if attempts.count >= MAX_AUTH_ATTEMPTS
+                                || (attempts.nonce.is_some() && attempts.nonce == nonce)
+                            {
+                                warn!("SIP trunk refused our credentials; not retrying");
+                                return;
+                            }
+                            attempts.count += 1;
+                            attempts.nonce = nonce;
+                        }
+                        if let Err(e) = self.register(Some(&challenge)).await {
+                            error!("SIP registration failed: {}", e);
+                        }
+                    }
+                    None => warn!("SIP trunk demanded auth without a challenge"),
+                }
+            }
+            (true, status) => warn!("SIP registration rejected: {:?}", status),
+            (false, _) => debug!("Ignoring SIP response: {}", msg.start_line),
+        }
+    }
+
+    async fn handle_invite(&self, msg: SipMessage, from: SocketAddr) {
+        let Some(call_id) = msg.header("Call-ID").map(str::to_string) else {
+            return;
+        };
+        match self.trunk_addr().await {
+            Ok(trunk) if trunk.ip() == from.ip() => {}
+            Ok(_) => {
+                warn!("Ignoring SIP INVITE from {}, which is not the trunk", from);
+                return;
+            }
+            Err(e) => {
+                error!("Cannot resolve SIP trunk to check an INVITE: {}", e);
+                return;
+            }
+        }
+        if self.calls.lock().await.contains_key(&call_id) {
+            // Retransmission of an INVITE we are already handling
+            return;
+        }
+
+        let room_id = msg
+            .request_uri()
+            .and_then(|uri| uri.strip_prefix("sip:"))
+            .and_then(|uri| uri.split('@').next())
+            .unwrap_or_default()
+            .to_string();
+        if Uuid::parse_str(&room_id).is_err() || self.state.room(&room_id).await.is_none() {
+            self.send(from, &msg.response("404 Not Found", None, None))
+                .await;
+            return;
+        }
+        let token = msg.header("X-Room-Token");
+        if !self.state.may_join(&room_id, token).await
+            || !self.state.is_invited(&room_id, token).await
+        {
+            warn!("Rejected SIP call {} into room {}", call_id, room_id);
+            self.send(from, &msg.response("403 Forbidden", None, None))
+                .await;
+            return;
+        }
+        if msg.body.trim().is_empty() {
+            // Late-offer INVITEs would need us to originate an offer
+            self.send(from, &msg.response("488 Not Acceptable Here", None, None))
+                .await;
+            return;
+        }
+
+        self.send(from, &msg.response("100 Trying", None, None))
+            .await;
+
+        let peer_id = Uuid::new_v4().to_string();
+        let (tx, rx) = peer_channel();
+        let joined = match self
+            .state
+            .join_room(&room_id, peer_id.clone(), tx, None)
+            .await
+        {
+            Ok(joined) => joined,
+            Err(e) => {
+                info!("Rejecting SIP call into room {}: {}", room_id, e);
+                self.send(from, &msg.response("486 Busy Here", None, None))
+                    .await;
+                return;
+            }
+        };
+        info!(
+            "SIP call {} joined room {} as peer {}",
+            call_id, room_id, peer_id
+        );
+
+        let to_tag = Uuid::new_v4().simple().to_string();
+        self.send(from, &msg.response("180 Ringing", Some(&to_tag), None))
+            .await;
+
+        let offer = WsMessage::Offer {
+            sdp: msg.body.clone(),
+            peer_id: None,
+        };
+        self.calls.lock().await.insert(
+            call_id.clone(),
+            CallLeg {
+                room_id: room_id.clone(),
+                peer_id: peer_id.clone(),
+                remote: from,
+                invite: msg,
+                to_tag,
+            },
+        );
+        self.state
+            .announce_join(&room_id, &peer_id, joined.peer_count)
+            .await;
+        let _ = self.state.relay_message(&room_id, &peer_id, offer).await;
+
+        self.bridge_room_messages(call_id, rx).await;
+    }
+
+    /// Consume messages addressed to the virtual peer until the call ends
+    async fn bridge_room_messages(&self, call_id: String, mut rx: PeerReceiver) {
+        let mut answered = false;
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                WsMessage::Answer { sdp, .. } if !answered => {
+                    answered = true;
+                    let sdp = gather_candidates(sdp, &mut rx).await;
+                    let calls = self.calls.lock().await;
+                    if let Some(leg) = calls.get(&call_id) {
+                        let contact = format!("Contact: <sip:{}@{}>\r\n", leg.peer_id, self.local);
+                        let ok = leg.invite.response_with(
+                            "200 OK",
+                            Some(&leg.to_tag),
+                            &contact,
+                            Some(&sdp),
+                        );
+                        self.send(leg.remote, &ok).await;
+                    }
+                }
+                WsMessage::Offer { .. } => {
+                    debug!("Ignoring web-side offer on SIP call {}", call_id);
+                }
+                WsMessage::Leave { .. } => {
+                    let peers = match self.calls.lock().await.get(&call_id) {
+                        Some(leg) => self.state.get_room_summary(&leg.room_id).await.0,
+                        None => break,
+                    };
+                    if peers <= 1 {
+                        self.hang_up(&call_id).await;
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// End a call from our side with BYE (or 487 before answer)
+    async fn hang_up(&self, call_id: &str) {
+        let Some(leg) = self.calls.lock().await.remove(call_id) else {
+            return;
+        };
+        let invite = &leg.invite;
+        let (Some(from), Some(to)) = (invite.header("From"), invite.header("To")) else {
+            return;
+        };
+        let contact = invite
+            .header("Contact")
+            .map(|c| {
+                c.trim_start_matches('<')
+                    .split('>')
+                    .next()
+                    .unwrap_or(c)
+                    .to_string()
+            })
+            .unwrap_or_else(|| format!("sip:{}", leg.remote));
+        let bye = format!(
+            "BYE {contact} SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {local};branch=z9hG4bK{branch}\r\n\
+             Max-Forwards: 70\r\n\
+             From: {to};tag={tag}\r\n\
+             To: {from}\r\n\
+             Call-ID: {call_id}\r\n\
+             CSeq: 1 BYE\r\n",
+            local = self.local,
+            branch = Uuid::new_v4().simple(),
+            tag = leg.to_tag,
+        );
+        self.send(leg.remote, &finish(bye, None)).await;
+        self.state
+            .leave_room(&leg.room_id, &leg.peer_id, LeaveReason::UserHangup)
+            .await;
+        info!("Hung up SIP call {}", call_id);
+    }
+
+    async fn handle_bye(&self, msg: SipMessage, from: SocketAddr) {
+        self.send(from, &msg.response("200 OK", None, None)).await;
+        let Some(call_id) = msg.header("Call-ID") else {
+            return;
+        };
+        if let Some(leg) = self.calls.lock().await.remove(call_id) {
+            if msg.method() == Some("CANCEL") {
+                let terminated =
+                    leg.invite
+                        .response("487 Request Terminated", Some(&leg.to_tag), None);
+                self.send(from, &terminated).await;
+            }
+            self.state
+                .leave_room(&leg.room_id, &leg.peer_id, LeaveReason::UserHangup)
+                .await;
+            info!("SIP call {} ended by remote", call_id);
+        }
+    }
+}
+
+/// Collect trickled candidates for a short window and merge them into the SDP
+async fn gather_candidates(sdp: String, rx: &mut PeerReceiver) -> String {
+    let mut candidates = Vec::new();
+    let deadline = tokio::time::Instant::now() + CANDIDATE_GATHER_WINDOW;
+    while let Ok(Some(msg)) = tokio::time::timeout_at(deadline, rx.recv()).await {
+        if let WsMessage::IceCandidate {
+            candidate,
+            sdp_m_line_index,
+            ..
+        } = msg
+            && !candidate.is_empty()
+        {
+            candidates.push((sdp_m_line_index as usize, candidate));
+        }
+    }
+    merge_candidates(&sdp, &candidates)
+}
+
+/// Insert `a=candidate` lines at the end of their media sections
+fn merge_candidates(sdp: &str, candidates: &[(usize, String)]) -> String {
+    let mut out = String::with_capacity(sdp.len());
+    let mut section: Option<usize> = None;
+    let flush = |out: &mut String, section: Option<usize>| {
+        if let Some(index) = section {
+            for (_, candidate) in candidates.iter().filter(|(i, _)| *i == index) {
+                let line = candidate.strip_prefix("a=").unwrap_or(candidate);
+                out.push_str(&format!("a={}\r\n", line));
+            }
+        }
+    };
+    for line in sdp.lines() {
+        if line.starts_with("m=") {
+            flush(&mut out, section);
+            section = Some(section.map_or(0, |i| i + 1));
+        }
+        out.push_str(line);
+        out.push_str("\r\n");
+    }
+    flush(&mut out, section);
+    out
+}
+
+/// Compute a digest `Authorization` header value (RFC 2617, MD5)
+fn digest_authorization(
+    challenge: &str,
+    user: &str,
+    password: &str,
+    method: &str,
+    uri: &str,
+) -> String {
+    let param = |name: &str| digest_param(challenge, name);
+    let realm = param("realm").unwrap_or_default();
+    let nonce = param("nonce").unwrap_or_default();
+    let qop_auth = param("qop").is_some_and(|q| q.split(',').any(|q| q.trim() == "auth"));
+
+    let ha1 = md5_hex(&format!("{}:{}:{}", user, realm, password));
+    let ha2 = md5_hex(&format!("{}:{}", method, uri));
+    let mut header = format!(
+        "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm=MD5",
+        user, realm, nonce, uri
+    );
+    if qop_auth {
+        let cnonce = Uuid::new_v4().simple().to_string();
+        let response = md5_hex(&format!(
+            "{}:{}:00000001:{}:auth:{}",
+            ha1, nonce, cnonce, ha2
+        ));
+        header.push_str(&format!(
+            ", response=\"{}\", qop=auth, nc=00000001, cnonce=\"{}\"",
+            response, cnonce
+        ));
+    } else {
+        let response = md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2));
+        header.push_str(&format!(", response=\"{}\"", response));
+    }
+    if let Some(opaque) = param("opaque") {
+        header.push_str(&format!(", opaque=\"{}\"", opaque));
+    }
+    header
+}
+
+/// A parameter of a digest challenge, unquoted
+fn digest_param(challenge: &str, name: &str) -> Option<String> {
+    challenge
+        .trim_start_matches("Digest")
+        .split(',')
+        .filter_map(|p| p.trim().split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.trim().trim_matches('"').to_string())
+}
+
+fn md5_hex(input: &str) -> String {
+    Md5::digest(input.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
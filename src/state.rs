@@ -2,90 +2,498 @@
 //!
 //! Handles room lifecycle, peer connections, and message routing.
 
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::collections::{HashMap, VecDeque};
+use std::ops::ControlFlow;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::Mutex;
 use tracing::{debug, info, warn};
 
-use crate::models::WsMessage;
+use crate::alerts::Alerting;
+use crate::api_keys::{IssuedKeys, KeyStore};
+use crate::archive::ArchiveEntry;
+use crate::assets::Assets;
+use crate::audio_levels::AudioLevels;
+use crate::cache::EphemeralCache;
+use crate::call_history::CallHistory;
+use crate::canary::CanaryRuns;
+use crate::capture::RoomCapture;
+use crate::cluster::ClusterView;
+use crate::config::{Config, check_codec_overlap};
+use crate::consent::ConsentRound;
+use crate::contacts::AddressBooks;
+use crate::debug_bundle::SignalingDigests;
+use crate::diagnostics::MediaFlow;
+use crate::entitlements::Entitlements;
+use crate::event_rooms::EventRegistrations;
+use crate::export::{EXPORT_TTL, Exports, MAX_EXPORTS};
+use crate::ice_restart::Transport;
+use crate::jobs::JobQueue;
+use crate::join_queue::{Admission, Waiter};
+use crate::lanes::{Lane, RoomEvent, RoomEvents, room_events};
+use crate::media_relay::RelayLink;
+use crate::models::{
+    AuditEvent, CallRecord, CleanupStats, ClientCapabilities, DeviceInfo, FeatureFlags,
+    FeatureOverrides, LeaveReason, PeerQuality, PeerRole, Permission, RetentionStats, RoomMetadata,
+    RoomMode, RoomSettings, RoomTimeline, RoomTranscript, StageLayout, StoredClientError,
+    TimelineKind, TranscriptionSettings, WsMessage,
+};
+use crate::persistence::RecordStore;
+use crate::personal_rooms::PersonalRoom;
+use crate::presence::PresenceMap;
+use crate::preview::Preview;
+use crate::push::{PushCredentials, PushTokens};
+use crate::quotas::QuotaMeter;
+use crate::ratelimit::{MAX_TRACKED_CLIENTS, RATE_BUCKET_TTL, RateBuckets};
+use crate::reconnect::Reservation;
+use crate::recordings::Recordings;
+use crate::room_actor::{RoomHandle, RoomHandles};
+use crate::room_store::RoomStore;
+use crate::saml::{
+    MAX_PENDING_REQUESTS, MAX_SEEN_ASSERTIONS, PENDING_REQUEST_TTL, PendingRequests,
+    SEEN_ASSERTION_TTL, SeenAssertions,
+};
+use crate::scim::{ScimUsers, load_users};
+use crate::share_links::ShareLink;
+use crate::storage::{BlobStore, store_from_config};
+use crate::token::secret_eq;
+use crate::traffic::TrafficCounters;
+use crate::transcription::{SttBackend, backend_from_config};
+use crate::voicemail::Voicemails;
+use crate::webhooks::Outbox;
 
 /// Maximum peers allowed per room (1:1 video chat)
 pub const MAX_PEERS_PER_ROOM: usize = 2;
 
-/// Room inactivity timeout before cleanup
-pub const ROOM_TIMEOUT: Duration = Duration::from_secs(300); // 5 minutes
+/// Maximum viewers allowed in a broadcast room (excluding the presenter)
+pub const MAX_BROADCAST_VIEWERS: usize = 100;
 
-/// Sender half for broadcasting messages to a peer
-pub type PeerSender = mpsc::UnboundedSender<WsMessage>;
+/// Number of client error reports retained in memory
+pub const MAX_CLIENT_ERRORS: usize = 1000;
+
+/// Number of audit events retained in memory
+pub const MAX_AUDIT_EVENTS: usize = 5000;
+
+pub use crate::lanes::PeerSender;
 
 /// Represents a connected peer in a room
 #[derive(Debug)]
 pub struct Peer {
     pub id: String,
     pub sender: PeerSender,
+    pub role: PeerRole,
+    pub capabilities: Option<ClientCapabilities>,
+    /// Devices the client reported after joining
+    pub device: Option<DeviceInfo>,
+    /// Latest connection quality report
+    pub quality: Option<PeerQuality>,
+    /// Recent `AudioLevel` reports
+    pub audio: AudioLevels,
+    /// Media flow from the last `MediaStats` reports
+    pub media: MediaFlow,
+    /// Connections to other peers, by their ID, as the client reports them
+    pub transports: HashMap<String, Transport>,
+    /// Reason the client gave in its `Leave` message, if any
+    pub leave_reason: Option<LeaveReason>,
+    /// Token for reclaiming this peer's slot after a dropped connection
+    pub resume_token: String,
+    /// Inbound message counters
+    pub traffic: TrafficCounters,
+    /// Subject of the room token the peer joined with
+    pub identity: Option<String>,
 }
 
 impl Peer {
     pub fn new(id: String, sender: PeerSender) -> Self {
-        Self { id, sender }
+        Self {
+            id,
+            sender,
+            role: PeerRole::Participant,
+            capabilities: None,
+            device: None,
+            quality: None,
+            audio: AudioLevels::default(),
+            media: MediaFlow::default(),
+            transports: HashMap::new(),
+            leave_reason: None,
+            resume_token: uuid::Uuid::new_v4().simple().to_string(),
+            traffic: TrafficCounters::default(),
+            identity: None,
+        }
     }
 }
 
-/// A video chat room containing up to 2 peers
+/// Result of a successful room join
+#[derive(Debug, Clone)]
+pub struct JoinedRoom {
+    /// The joined peer's ID, which differs from the requested one on resume
+    pub peer_id: String,
+    pub resume_token: String,
+    pub peer_count: usize,
+    pub presenter: bool,
+    /// Peer currently sharing its screen
+    pub sharing: Option<String>,
+    pub layout: StageLayout,
+    pub pinned_peer: Option<String>,
+    pub role: PeerRole,
+    pub settings: RoomSettings,
+    /// Features offered in the room
+    pub features: FeatureFlags,
+}
+
+/// A video chat room: up to 2 peers, or a presenter plus viewers in broadcast mode
 #[derive(Debug)]
 pub struct Room {
     pub peers: Vec<Peer>,
     pub last_activity: Instant,
+    /// Set when the first peer joins; used for the duration cap
+    pub started_at: Option<Instant>,
+    /// Peer ID of the presenter (broadcast rooms only)
+    pub presenter: Option<String>,
+    /// Token that makes its holder the presenter, handed to the room's
+    /// creator (broadcast rooms only)
+    pub presenter_token: Option<String>,
+    /// Resume token of the presenter's latest session, so a reconnect
+    /// keeps the role
+    pub presenter_session: Option<String>,
+    pub settings: RoomSettings,
+    /// Numeric code for joining by phone keypad
+    pub dial_code: String,
+    /// Whether recording is currently active (after consent)
+    pub recording: bool,
+    /// Whether a low-quality alert has fired and not yet recovered
+    pub quality_alerted: bool,
+    /// Whether one-way audio was detected and has not cleared since
+    pub one_way_audio: bool,
+    /// Peer announced as the active speaker, and since when
+    pub active_speaker: Option<(String, Instant)>,
+    /// Pending recording consent round
+    pub consent: Option<ConsentRound>,
+    /// Slots held for reconnecting or re-invited peers
+    pub reservations: Vec<Reservation>,
+    /// Hidden recorder peers; they take no slot and only see addressed signaling
+    pub recorders: Vec<Peer>,
+    /// Control announcements to every peer, subscribed to on join
+    pub events: RoomEvents,
+    /// Peers waiting for a slot, first in line first
+    pub waiting: VecDeque<Waiter>,
+    /// Emails and token subjects allowed to join; anyone may when unset
+    pub invitees: Option<Vec<String>>,
+    /// Links minted by the host or an operator
+    pub share_links: Vec<ShareLink>,
+    /// Subject of the user whose personal room this is
+    pub owner: Option<String>,
+    /// Token subjects kept out of the room
+    pub bans: Vec<String>,
+    /// Peer holding the screen-share slot
+    pub sharing: Option<String>,
+    /// Peers waiting for the host to grant them the slot
+    pub present_requests: Vec<String>,
+    /// Stage arrangement set by the host
+    pub layout: StageLayout,
+    /// Peer the host pinned to everyone's stage
+    pub pinned_peer: Option<String>,
+    /// Tags set on room creation
+    pub metadata: RoomMetadata,
+    /// Latest preview image from a recorder, while the host allows it
+    pub preview: Option<Preview>,
+    /// Features offered to clients, from all flag layers
+    pub features: FeatureFlags,
+    /// Smallest `time_remaining` threshold announced so far
+    pub duration_warned: Option<u64>,
+}
+
+impl Default for Room {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Room {
     pub fn new() -> Self {
+        Self::with_settings(RoomSettings::default())
+    }
+
+    pub fn with_settings(settings: RoomSettings) -> Self {
+        let presenter_token = (settings.mode == RoomMode::Broadcast)
+            .then(|| uuid::Uuid::new_v4().simple().to_string());
         Self {
             peers: Vec::with_capacity(MAX_PEERS_PER_ROOM),
             last_activity: Instant::now(),
+            started_at: None,
+            presenter: None,
+            presenter_token,
+            presenter_session: None,
+            settings,
+            dial_code: String::new(),
+            recording: false,
+            quality_alerted: false,
+            one_way_audio: false,
+            active_speaker: None,
+            consent: None,
+            reservations: Vec::new(),
+            recorders: Vec::new(),
+            events: room_events(),
+            waiting: VecDeque::new(),
+            invitees: None,
+            share_links: Vec::new(),
+            owner: None,
+            bans: Vec::new(),
+            sharing: None,
+            present_requests: Vec::new(),
+            layout: StageLayout::default(),
+            pinned_peer: None,
+            metadata: RoomMetadata::new(),
+            preview: None,
+            features: FeatureFlags::default(),
+            duration_warned: None,
+        }
+    }
+
+    /// Room topology
+    pub fn mode(&self) -> RoomMode {
+        self.settings.mode
+    }
+
+    /// Room info with the current peer count
+    pub fn room_info(&self) -> WsMessage {
+        WsMessage::room_info(
+            self.peers.len(),
+            self.settings.codecs.as_deref(),
+            self.features,
+        )
+    }
+
+    /// Maximum number of peers this room accepts
+    pub fn capacity(&self) -> usize {
+        match self.mode() {
+            RoomMode::Interactive => MAX_PEERS_PER_ROOM,
+            RoomMode::Broadcast => self.settings.max_viewers.min(MAX_BROADCAST_VIEWERS) + 1,
         }
     }
 
     /// Check if room is full
+    ///
+    /// A broadcast without its presenter keeps the presenter's slot free
+    /// (unless a reconnect reservation already holds it), so viewers
+    /// arriving first cannot lock the presenter out.
     pub fn is_full(&self) -> bool {
-        self.peers.len() >= MAX_PEERS_PER_ROOM
+        let presenter_slot = self.mode() == RoomMode::Broadcast
+            && self.presenter.is_none()
+            && !self.reservations.iter().any(|r| {
+                r.expires_at > Instant::now()
+                    && self.presenter_session.as_deref() == Some(r.token.as_str())
+            });
+        self.slots_taken() + usize::from(presenter_slot) >= self.capacity()
+    }
+
+    /// Seated peers plus slots held for reconnecting ones
+    fn slots_taken(&self) -> usize {
+        self.peers.len() + self.reserved_slots()
+    }
+
+    /// Check if a peer is the broadcast presenter
+    pub fn is_presenter(&self, peer_id: &str) -> bool {
+        self.presenter.as_deref() == Some(peer_id)
+    }
+
+    /// Look up a peer's role
+    pub fn role_of(&self, peer_id: &str) -> Option<PeerRole> {
+        self.peers.iter().find(|p| p.id == peer_id).map(|p| p.role)
+    }
+
+    /// Check that a feature is switched on and the peer's role may use it
+    pub fn check_permitted(
+        &self,
+        peer_id: &str,
+        permission: Permission,
+    ) -> Result<(), &'static str> {
+        if !self.settings.controls.allows(permission) {
+            return Err("The host has turned that off in this room");
+        }
+        if !self.permits(peer_id, permission) {
+            return Err("Your role is not permitted to do that in this room");
+        }
+        Ok(())
+    }
+
+    /// Check whether a peer may perform an action under the room's matrix
+    pub fn permits(&self, peer_id: &str, permission: Permission) -> bool {
+        self.role_of(peer_id)
+            .is_some_and(|role| self.settings.permissions.for_role(role).allows(permission))
     }
 
     /// Add a peer to the room
-    pub fn add_peer(&mut self, peer: Peer) -> Result<(), &'static str> {
-        if self.is_full() {
+    ///
+    /// The first peer to arrive while nobody holds the host role becomes the
+    /// host. In broadcast rooms everyone joins as a viewer; the presenter
+    /// is made so by [`Room::make_presenter`].
+    pub fn add_peer(&mut self, mut peer: Peer) -> Result<(), &'static str> {
+        if self.slots_taken() >= self.capacity() {
             return Err("Room is full");
         }
+        let has_host = self.peers.iter().any(|p| p.role == PeerRole::Host);
+        peer.role = match (has_host, self.mode()) {
+            (_, RoomMode::Broadcast) => PeerRole::Viewer,
+            (false, RoomMode::Interactive) => PeerRole::Host,
+            (true, RoomMode::Interactive) => PeerRole::Participant,
+        };
+        self.started_at.get_or_insert_with(Instant::now);
+        peer.sender.attach_room(&self.events, &peer.id);
         self.peers.push(peer);
         self.last_activity = Instant::now();
         Ok(())
     }
 
+    /// Whether `token` is the presenter token of a broadcast room, or the
+    /// resume token of the presenter's latest session
+    pub fn is_presenter_token(&self, token: &str) -> bool {
+        [&self.presenter_token, &self.presenter_session]
+            .into_iter()
+            .flatten()
+            .any(|t| secret_eq(t, token))
+    }
+
+    /// Make a seated peer the broadcast's presenter and host
+    pub fn make_presenter(&mut self, peer_id: &str) {
+        let Some(peer) = self.peers.iter_mut().find(|p| p.id == peer_id) else {
+            return;
+        };
+        peer.role = PeerRole::Host;
+        self.presenter_session = Some(peer.resume_token.clone());
+        self.presenter = Some(peer_id.to_string());
+    }
+
     /// Remove a peer by ID
     pub fn remove_peer(&mut self, peer_id: &str) -> Option<Peer> {
         self.last_activity = Instant::now();
-        if let Some(pos) = self.peers.iter().position(|p| p.id == peer_id) {
-            Some(self.peers.remove(pos))
-        } else {
-            None
+        if self.is_presenter(peer_id) {
+            self.presenter = None;
+        }
+        let pos = self.peers.iter().position(|p| p.id == peer_id)?;
+        let peer = self.peers.remove(pos);
+        peer.sender.detach_room();
+        for other in &mut self.peers {
+            other.transports.remove(peer_id);
+        }
+        self.present_requests.retain(|id| id != peer_id);
+        if self.sharing.as_deref() == Some(peer_id) {
+            self.stop_sharing();
+        }
+        if self.pinned_peer.as_deref() == Some(peer_id) {
+            self.unpin();
+        }
+        Some(peer)
+    }
+
+    /// Remove a peer and notify the rest of the room; returns false if absent
+    pub fn leave(&mut self, peer_id: &str, reason: LeaveReason) -> bool {
+        if self.remove_peer(peer_id).is_none() {
+            return false;
+        }
+        let leave = WsMessage::Leave {
+            peer_id: Some(peer_id.to_string()),
+            reason: Some(reason),
+        };
+        for recorder in &self.recorders {
+            let _ = recorder.sender.send(leave.clone());
+        }
+        self.broadcast_to_all(&leave);
+        self.broadcast_to_all(&self.room_info());
+        self.ensure_host();
+        self.admit_waiting();
+        true
+    }
+
+    /// Send a message to a single peer
+    pub fn send_to(&self, peer_id: &str, msg: WsMessage) {
+        if let Some(peer) = self.peers.iter().find(|p| p.id == peer_id)
+            && let Err(e) = peer.sender.send(msg)
+        {
+            warn!("Failed to send to peer {}: {}", peer.id, e);
+        }
+    }
+
+    /// Route a message from `sender_id` according to the room mode
+    ///
+    /// Interactive rooms forward everything to the other peer. Broadcast rooms
+    /// only let the presenter offer; viewer signaling goes to the presenter and
+    /// presenter signaling goes to the addressed viewer (or all viewers).
+    pub fn route(&self, sender_id: &str, msg: WsMessage) -> Result<(), &'static str> {
+        let required = match &msg {
+            WsMessage::Chat { .. } => Some(Permission::Chat),
+            WsMessage::Reaction { .. } => Some(Permission::React),
+            WsMessage::Recording { .. } => Some(Permission::Record),
+            _ => None,
+        };
+        if let Some(permission) = required {
+            self.check_permitted(sender_id, permission)?;
+        }
+        let msg = self.rewrite_sdp(msg);
+        let Some(msg) = self.route_to_recorder(sender_id, msg) else {
+            return Ok(());
+        };
+
+        if self.mode() == RoomMode::Interactive {
+            self.broadcast_to_others(sender_id, &msg);
+            return Ok(());
+        }
+
+        let from_presenter = self.is_presenter(sender_id);
+        match msg {
+            WsMessage::Offer { .. } if !from_presenter => {
+                Err("Only the presenter may send offers in a broadcast room")
+            }
+            WsMessage::Offer { .. } | WsMessage::Answer { .. } | WsMessage::IceCandidate { .. } => {
+                let (msg, target) = stamp_sender(msg, sender_id);
+                match (from_presenter, target) {
+                    (true, Some(target)) => self.send_to(&target, msg),
+                    (true, None) => self.broadcast_to_others(sender_id, &msg),
+                    (false, _) => match &self.presenter {
+                        Some(presenter) => self.send_to(presenter, msg),
+                        None => return Err("Broadcast has no presenter"),
+                    },
+                }
+                Ok(())
+            }
+            WsMessage::MediaStatus { .. } if !from_presenter => {
+                if let Some(presenter) = &self.presenter {
+                    self.send_to(presenter, msg);
+                }
+                Ok(())
+            }
+            msg => {
+                self.broadcast_to_others(sender_id, &msg);
+                Ok(())
+            }
         }
     }
 
     /// Broadcast message to all peers except sender
+    ///
+    /// Control messages go out once on the room's event channel; anything
+    /// else is queued per peer so it keeps its lane priority.
     pub fn broadcast_to_others(&self, sender_id: &str, msg: &WsMessage) {
+        if Lane::of(msg) == Lane::Control {
+            self.announce(Some(sender_id), msg);
+            return;
+        }
         for peer in &self.peers {
-            if peer.id != sender_id {
-                if let Err(e) = peer.sender.send(msg.clone()) {
-                    warn!("Failed to send to peer {}: {}", peer.id, e);
-                }
+            if peer.id != sender_id
+                && let Err(e) = peer.sender.send(msg.clone())
+            {
+                warn!("Failed to send to peer {}: {}", peer.id, e);
             }
         }
     }
 
     /// Broadcast message to all peers
     pub fn broadcast_to_all(&self, msg: &WsMessage) {
+        if Lane::of(msg) == Lane::Control {
+            self.announce(None, msg);
+            return;
+        }
         for peer in &self.peers {
             if let Err(e) = peer.sender.send(msg.clone()) {
                 warn!("Failed to send to peer {}: {}", peer.id, e);
@@ -93,134 +501,997 @@ impl Room {
         }
     }
 
-    /// Check if room is inactive and should be cleaned up
-    pub fn is_inactive(&self) -> bool {
-        self.peers.is_empty() && self.last_activity.elapsed() > ROOM_TIMEOUT
+    /// Send a control message to every subscribed peer but `except`
+    fn announce(&self, except: Option<&str>, msg: &WsMessage) {
+        // Fails only when nobody is subscribed
+        let _ = self.events.send(RoomEvent {
+            msg: Arc::new(msg.clone()),
+            except: except.map(Arc::from),
+        });
+    }
+
+    /// Apply a host-only command (permission or role change)
+    pub fn apply_host_command(
+        &mut self,
+        sender_id: &str,
+        msg: WsMessage,
+    ) -> Result<(), &'static str> {
+        if self.role_of(sender_id) != Some(PeerRole::Host) {
+            return Err("Only the host may change room settings, permissions or roles");
+        }
+
+        match msg {
+            WsMessage::Permissions { permissions } => {
+                self.settings.permissions = permissions;
+                info!("Host {} updated room permissions", sender_id);
+                self.broadcast_to_all(&WsMessage::Permissions { permissions });
+                Ok(())
+            }
+            WsMessage::SetRole { peer_id, role } => {
+                if role == PeerRole::Host || peer_id == sender_id {
+                    return Err("The host role cannot be reassigned");
+                }
+                let peer = self
+                    .peers
+                    .iter_mut()
+                    .find(|p| p.id == peer_id)
+                    .ok_or("No such peer in this room")?;
+                peer.role = role;
+                info!("Host {} set role of {} to {:?}", sender_id, peer_id, role);
+                self.broadcast_to_all(&WsMessage::SetRole { peer_id, role });
+                Ok(())
+            }
+            WsMessage::RoomSettingsUpdate {
+                chat,
+                reactions,
+                screen_share,
+                recording,
+                preview,
+            } => {
+                let controls = &mut self.settings.controls;
+                controls.update(chat, reactions, screen_share, recording, preview);
+                let controls = *controls;
+                // Switching screen sharing off ends the current share
+                if !controls.screen_share {
+                    self.present_requests.clear();
+                    self.stop_sharing();
+                }
+                // Switching recording off ends it and any pending consent round
+                if !controls.recording {
+                    self.consent = None;
+                    if self.recording {
+                        self.recording = false;
+                        self.broadcast_to_all(&WsMessage::Recording { active: false });
+                    }
+                }
+                // Switching previews off drops the last one
+                if !controls.preview {
+                    self.preview = None;
+                }
+                info!("Host {} updated room controls: {:?}", sender_id, controls);
+                self.broadcast_to_all(&controls.message());
+                Ok(())
+            }
+            WsMessage::LayoutUpdate {
+                pinned_peer,
+                layout,
+            } => self.set_layout(sender_id, pinned_peer, layout),
+            WsMessage::PresentGrant { peer_id, granted } => {
+                self.grant_present(sender_id, &peer_id, granted)
+            }
+            _ => Err("Not a host command"),
+        }
+    }
+
+    /// Promote the longest-present peer to host if nobody holds the role
+    pub fn ensure_host(&mut self) {
+        if self.mode() == RoomMode::Broadcast || self.peers.iter().any(|p| p.role == PeerRole::Host)
+        {
+            return;
+        }
+        if let Some(peer) = self.peers.first_mut() {
+            peer.role = PeerRole::Host;
+            let msg = WsMessage::SetRole {
+                peer_id: peer.id.clone(),
+                role: PeerRole::Host,
+            };
+            info!("Promoted peer {} to host", peer.id);
+            self.broadcast_to_all(&msg);
+        }
+    }
+
+    /// Add a peer, claiming the reservation `token` names if any
+    ///
+    /// A reconnecting peer gets its old ID back; the flag is true then.
+    /// Without a reservation the peer may not pass anyone waiting in line.
+    pub fn join(
+        &mut self,
+        peer_id: String,
+        sender: PeerSender,
+        token: Option<&str>,
+    ) -> Result<(JoinedRoom, bool), &'static str> {
+        // Seat anyone a lapsed reservation left waiting
+        self.admit_waiting();
+        let reservation = token.and_then(|t| self.claim_reservation(t));
+        let resumed = reservation.as_ref().is_some_and(|r| r.peer_id.is_some());
+        let presenting = self.mode() == RoomMode::Broadcast
+            && self.presenter.is_none()
+            && token.is_some_and(|t| self.is_presenter_token(t));
+        // Only its owner gets this far into a personal room awaiting it, and
+        // the presenter goes straight to the slot kept for it
+        let queue_ahead = reservation.is_none()
+            && !presenting
+            && !self.waiting.is_empty()
+            && !self.awaits_owner();
+        let peer_id = reservation.and_then(|r| r.peer_id).unwrap_or(peer_id);
+
+        if (!presenting && self.is_full()) || queue_ahead {
+            return Err(match self.mode() {
+                RoomMode::Interactive => "Room is full (max 2 peers for 1:1 call)",
+                RoomMode::Broadcast => "Broadcast is full (viewer limit reached)",
+            });
+        }
+        let mut joined = self.seat(peer_id, sender)?;
+        if presenting {
+            self.make_presenter(&joined.peer_id);
+            joined = self.joined_as(joined.peer_id, joined.resume_token);
+        }
+        // The owner arriving lets its lobby in
+        self.admit_waiting();
+        Ok((joined, resumed))
+    }
+
+    /// Add a peer under `peer_id` and describe the result to it
+    pub fn seat(
+        &mut self,
+        peer_id: String,
+        sender: PeerSender,
+    ) -> Result<JoinedRoom, &'static str> {
+        let peer = Peer::new(peer_id.clone(), sender);
+        let resume_token = peer.resume_token.clone();
+        self.add_peer(peer)?;
+        Ok(self.joined_as(peer_id, resume_token))
+    }
+
+    /// Describe the room to a peer that has just taken its slot
+    pub fn joined_as(&self, peer_id: String, resume_token: String) -> JoinedRoom {
+        JoinedRoom {
+            resume_token,
+            peer_count: self.peers.len(),
+            presenter: self.is_presenter(&peer_id),
+            sharing: self.sharing.clone(),
+            layout: self.layout,
+            pinned_peer: self.pinned_peer.clone(),
+            role: self.role_of(&peer_id).unwrap_or_default(),
+            settings: self.settings.clone(),
+            features: self.features,
+            peer_id,
+        }
+    }
+
+    /// Check if the call has run past its configured maximum duration
+    pub fn is_expired(&self) -> bool {
+        match (self.started_at, self.settings.max_duration_secs) {
+            (Some(started), Some(max)) => started.elapsed() > Duration::from_secs(max),
+            _ => false,
+        }
     }
 }
 
 /// Shared application state
 #[derive(Debug, Clone)]
 pub struct AppState {
-    pub rooms: Arc<Mutex<HashMap<String, Room>>>,
+    /// Running rooms; each room's state lives in its own task
+    pub rooms: Arc<Mutex<RoomHandles>>,
+    /// Current configuration; replaced wholesale on reload
+    config: Arc<RwLock<Arc<Config>>>,
+    /// Shared HTTP client for outbound integrations
+    pub http: reqwest::Client,
+    /// Speech-to-text backend for live captions
+    pub stt: Option<Arc<dyn SttBackend>>,
+    /// Where recordings and export bundles are kept, if not locally
+    pub blobs: Option<Arc<dyn BlobStore>>,
+    /// Chat and caption history per room, kept after the room is reaped
+    pub transcripts: Arc<Mutex<HashMap<String, RoomTranscript>>>,
+    /// Reaped rooms, kept until purged by the archive policy
+    pub archive: Arc<Mutex<HashMap<String, ArchiveEntry>>>,
+    /// Rooms reaped by the cleanup task, by reason
+    pub cleanup_stats: Arc<Mutex<CleanupStats>>,
+    /// Registrations of event rooms, kept until the room is purged from
+    /// the archive
+    pub event_registrations: Arc<Mutex<EventRegistrations>>,
+    /// Entitlement codes minted by operators
+    pub entitlements: Arc<Mutex<Entitlements>>,
+    /// Tenant API keys issued by operators, while no key store is attached
+    pub api_keys: Arc<Mutex<IssuedKeys>>,
+    /// Participant-minutes used by each tenant with a quota
+    pub quota_meter: Arc<Mutex<QuotaMeter>>,
+    /// What the retention sweep deleted, by kind
+    pub retention_stats: Arc<Mutex<RetentionStats>>,
+    /// Event history per room, kept after the room is reaped
+    pub timelines: Arc<Mutex<HashMap<String, RoomTimeline>>>,
+    /// Call detail records, kept after the room is reaped
+    pub calls: Arc<Mutex<HashMap<String, CallRecord>>>,
+    /// Audit trail of privileged and consent-related actions, oldest first
+    pub audit: Arc<Mutex<VecDeque<AuditEvent>>>,
+    /// Most recent client error reports, oldest first
+    pub client_errors: Arc<Mutex<VecDeque<StoredClientError>>>,
+    /// Open media relay sockets per room
+    pub media_relays: Arc<Mutex<HashMap<String, Vec<RelayLink>>>>,
+    /// Room creation and join budgets per client address or /64
+    pub rate_limits: Arc<Mutex<RateBuckets>>,
+    /// Personal rooms by room ID, kept while they are not running
+    pub personal_rooms: Arc<Mutex<HashMap<String, PersonalRoom>>>,
+    /// Users with a presence socket open
+    pub presence: Arc<Mutex<PresenceMap>>,
+    /// Directed calls per user, kept after the room is reaped
+    pub call_history: Arc<Mutex<CallHistory>>,
+    /// Each user's contacts
+    pub contacts: Arc<Mutex<AddressBooks>>,
+    /// Each user's devices registered for push notifications
+    pub push_tokens: Arc<Mutex<PushTokens>>,
+    /// Push service credentials, reused until they near expiry
+    pub push_credentials: Arc<Mutex<PushCredentials>>,
+    /// Voice messages left for unanswered calls
+    pub voicemails: Arc<Mutex<Voicemails>>,
+    /// Other cluster members and the rooms they run
+    pub cluster: Arc<Mutex<ClusterView>>,
+    /// Where room records are kept across restarts, once attached
+    pub store: Arc<OnceLock<Arc<dyn RecordStore>>>,
+    /// Where running rooms are shared with other instances, once attached
+    pub room_store: Arc<OnceLock<Arc<dyn RoomStore>>>,
+    /// Where issued API keys are shared with other instances, once attached
+    pub key_store: Arc<OnceLock<Arc<dyn KeyStore>>>,
+    /// Room events queued for, or recently delivered to, the webhook
+    pub webhooks: Arc<Mutex<Outbox>>,
+    /// Finished room exports waiting to be downloaded
+    pub exports: Arc<Mutex<Exports>>,
+    /// Background jobs, queued, running and recently finished
+    pub jobs: Arc<Mutex<JobQueue>>,
+    /// Uploaded recordings; their files are under `recordings.dir`
+    pub recordings: Arc<Mutex<Recordings>>,
+    /// Feature flags an operator set at runtime, over the config's
+    pub feature_overrides: Arc<Mutex<FeatureOverrides>>,
+    /// Files under `static/`, read at startup
+    pub assets: Arc<Assets>,
+    /// Recent join outcomes and the state of each alert rule
+    pub alerting: Arc<Mutex<Alerting>>,
+    /// Recent synthetic signaling and TURN probes
+    pub canary_runs: Arc<Mutex<CanaryRuns>>,
+    /// Signaling captures in progress, by room
+    pub captures: Arc<Mutex<HashMap<String, RoomCapture>>>,
+    /// Last descriptions and candidate counts per room, kept after the
+    /// room is reaped
+    pub signaling_digests: Arc<Mutex<SignalingDigests>>,
+    /// SAML assertions already used to sign in
+    pub saml_assertions: Arc<Mutex<SeenAssertions>>,
+    /// SAML authentication requests awaiting an answer
+    pub saml_requests: Arc<Mutex<PendingRequests>>,
+    /// Users provisioned over SCIM; read without waiting whenever a user
+    /// token is checked
+    pub scim_users: Arc<RwLock<ScimUsers>>,
 }
 
 impl AppState {
-    pub fn new() -> Self {
+    pub fn new(config: Config) -> Self {
+        let http = reqwest::Client::new();
+        let scim_users = load_users(config.scim.as_ref());
         Self {
             rooms: Arc::new(Mutex::new(HashMap::new())),
+            stt: backend_from_config(config.transcription.as_ref(), &http),
+            blobs: store_from_config(config.storage.as_ref(), &http),
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            http,
+            transcripts: Arc::new(Mutex::new(HashMap::new())),
+            archive: Arc::new(Mutex::new(HashMap::new())),
+            cleanup_stats: Arc::new(Mutex::new(CleanupStats::default())),
+            event_registrations: Arc::new(Mutex::new(HashMap::new())),
+            entitlements: Arc::new(Mutex::new(HashMap::new())),
+            api_keys: Arc::new(Mutex::new(HashMap::new())),
+            quota_meter: Arc::new(Mutex::new(QuotaMeter::default())),
+            retention_stats: Arc::new(Mutex::new(RetentionStats::default())),
+            timelines: Arc::new(Mutex::new(HashMap::new())),
+            calls: Arc::new(Mutex::new(HashMap::new())),
+            audit: Arc::new(Mutex::new(VecDeque::new())),
+            client_errors: Arc::new(Mutex::new(VecDeque::with_capacity(MAX_CLIENT_ERRORS))),
+            media_relays: Arc::new(Mutex::new(HashMap::new())),
+            rate_limits: Arc::new(Mutex::new(
+                EphemeralCache::new("rate_limits", MAX_TRACKED_CLIENTS, RATE_BUCKET_TTL).sliding(),
+            )),
+            personal_rooms: Arc::new(Mutex::new(HashMap::new())),
+            presence: Arc::new(Mutex::new(HashMap::new())),
+            call_history: Arc::new(Mutex::new(HashMap::new())),
+            contacts: Arc::new(Mutex::new(HashMap::new())),
+            push_tokens: Arc::new(Mutex::new(HashMap::new())),
+            push_credentials: Arc::new(Mutex::new(PushCredentials::default())),
+            voicemails: Arc::new(Mutex::new(HashMap::new())),
+            cluster: Arc::new(Mutex::new(ClusterView::default())),
+            store: Arc::new(OnceLock::new()),
+            room_store: Arc::new(OnceLock::new()),
+            key_store: Arc::new(OnceLock::new()),
+            webhooks: Arc::new(Mutex::new(Outbox::default())),
+            exports: Arc::new(Mutex::new(EphemeralCache::new(
+                "exports",
+                MAX_EXPORTS,
+                EXPORT_TTL,
+            ))),
+            jobs: Arc::new(Mutex::new(JobQueue::default())),
+            recordings: Arc::new(Mutex::new(HashMap::new())),
+            feature_overrides: Arc::new(Mutex::new(FeatureOverrides::default())),
+            assets: Assets::bundled(),
+            alerting: Arc::new(Mutex::new(Alerting::default())),
+            canary_runs: Arc::new(Mutex::new(VecDeque::new())),
+            captures: Arc::new(Mutex::new(HashMap::new())),
+            signaling_digests: Arc::new(Mutex::new(HashMap::new())),
+            saml_assertions: Arc::new(Mutex::new(EphemeralCache::new(
+                "saml_assertions",
+                MAX_SEEN_ASSERTIONS,
+                SEEN_ASSERTION_TTL,
+            ))),
+            saml_requests: Arc::new(Mutex::new(EphemeralCache::new(
+                "saml_requests",
+                MAX_PENDING_REQUESTS,
+                PENDING_REQUEST_TTL,
+            ))),
+            scim_users: Arc::new(RwLock::new(scim_users)),
+        }
+    }
+
+    /// Snapshot of the current configuration
+    pub fn config(&self) -> Arc<Config> {
+        self.config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Re-read the config file and swap it in; active calls are unaffected
+    pub async fn reload_config(&self, actor: &str) -> Result<(), String> {
+        let config = Config::load_from(self.config().source.as_deref())?;
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+        self.refresh_features().await;
+        self.record_audit(None, actor, "config.reload", "").await;
+        Ok(())
+    }
+
+    /// Store a client error report, evicting the oldest when full
+    pub async fn record_client_error(&self, report: StoredClientError) {
+        let mut errors = self.client_errors.lock().await;
+        if errors.len() >= MAX_CLIENT_ERRORS {
+            errors.pop_front();
         }
+        errors.push_back(report);
     }
 
-    /// Create a new room with given ID
-    pub async fn create_room(&self, room_id: String) -> String {
-        let mut rooms = self.rooms.lock().await;
-        if !rooms.contains_key(&room_id) {
-            info!("Creating room: {}", room_id);
-            rooms.insert(room_id.clone(), Room::new());
+    /// Append an event to the audit trail
+    pub async fn record_audit(
+        &self,
+        room_id: Option<&str>,
+        actor: &str,
+        action: &str,
+        detail: impl Into<String>,
+    ) {
+        let event = AuditEvent {
+            timestamp: unix_timestamp(),
+            room_id: room_id.map(str::to_string),
+            actor: actor.to_string(),
+            action: action.to_string(),
+            detail: detail.into(),
+        };
+        info!(
+            "Audit: {} by {} ({})",
+            event.action, event.actor, event.detail
+        );
+
+        let mut audit = self.audit.lock().await;
+        if audit.len() >= MAX_AUDIT_EVENTS {
+            audit.pop_front();
         }
-        room_id
+        audit.push_back(event);
+    }
+
+    /// List audit events, optionally filtered by room, newest first
+    pub async fn list_audit(&self, room_id: Option<&str>) -> Vec<AuditEvent> {
+        let audit = self.audit.lock().await;
+        audit
+            .iter()
+            .rev()
+            .filter(|e| room_id.is_none() || e.room_id.as_deref() == room_id)
+            .cloned()
+            .collect()
+    }
+
+    /// List stored client errors, optionally filtered by room, newest first
+    pub async fn list_client_errors(&self, room_id: Option<&str>) -> Vec<StoredClientError> {
+        let errors = self.client_errors.lock().await;
+        errors
+            .iter()
+            .rev()
+            .filter(|e| room_id.is_none() || e.report.room_id.as_deref() == room_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Create a new room with given ID, returning its dial-in code
+    pub async fn create_room(&self, room_id: String, settings: RoomSettings) -> String {
+        self.create_tagged_room(room_id, settings, RoomMetadata::new(), None)
+            .await
+    }
+
+    /// Create a new room carrying `metadata`, on behalf of `tenant` if
+    /// given, returning its dial-in code
+    pub async fn create_tagged_room(
+        &self,
+        room_id: String,
+        settings: RoomSettings,
+        metadata: RoomMetadata,
+        tenant: Option<String>,
+    ) -> String {
+        let mode = settings.mode;
+        let tags = metadata.clone();
+        self.start_room(&room_id, mode, metadata, tenant, || Room {
+            metadata: tags,
+            ..Room::with_settings(settings)
+        })
+        .await
+    }
+
+    /// Start `make()` as `room_id` unless it is running, returning its
+    /// dial-in code
+    pub async fn start_room(
+        &self,
+        room_id: &str,
+        mode: RoomMode,
+        metadata: RoomMetadata,
+        tenant: Option<String>,
+        make: impl FnOnce() -> Room,
+    ) -> String {
+        let (handle, created) = self.room_or_start(room_id, make).await;
+        let dial_code = handle.dial_code.to_string();
+        if !created {
+            return dial_code;
+        }
+
+        info!("Created {:?} room: {}", mode, room_id);
+        self.open_call_record(room_id, mode, metadata, tenant).await;
+        self.record_timeline(room_id, TimelineKind::Created, None, format!("{:?}", mode))
+            .await;
+        self.claim_room(room_id).await;
+        self.save_room_state(room_id).await;
+        dial_code
+    }
+
+    /// Find a room by its dial-in code
+    pub async fn find_room_by_dial_code(&self, code: &str) -> Option<String> {
+        let rooms = self.rooms.lock().await;
+        rooms
+            .iter()
+            .find(|(_, h)| !h.is_closed() && *h.dial_code == *code)
+            .map(|(id, _)| id.clone())
     }
 
     /// Add a peer to a room, creating the room if needed
+    ///
+    /// A valid `token` claims a held slot; a reconnecting peer gets its old ID back.
     pub async fn join_room(
         &self,
         room_id: &str,
         peer_id: String,
         sender: PeerSender,
-    ) -> Result<usize, &'static str> {
-        let mut rooms = self.rooms.lock().await;
-
-        // Create room if it doesn't exist
-        let room = rooms
-            .entry(room_id.to_string())
-            .or_insert_with(Room::new);
-
-        if room.is_full() {
-            return Err("Room is full (max 2 peers for 1:1 call)");
+        token: Option<&str>,
+    ) -> Result<JoinedRoom, &'static str> {
+        match self
+            .enter_room(room_id, peer_id, sender, token, false)
+            .await?
+        {
+            Admission::Joined(joined) => Ok(joined),
+            Admission::Queued { .. } => Err("Room is full"),
         }
+    }
 
-        let peer = Peer::new(peer_id.clone(), sender);
-        room.add_peer(peer)?;
+    /// Join a room, or line up for it if it is full or awaits its owner
+    /// and the caller `can_wait`
+    pub async fn enter_room(
+        &self,
+        room_id: &str,
+        peer_id: String,
+        sender: PeerSender,
+        token: Option<&str>,
+        can_wait: bool,
+    ) -> Result<Admission, &'static str> {
+        let identity = self.identity_of(room_id, token);
+        let migrated = self.migrated_peer_id(room_id, token);
+        let peer_id = migrated.clone().unwrap_or(peer_id);
+        let config = self.config();
+        let duplicates = config.duplicate_sessions;
+        let queue = config.join_queue.clone().filter(|_| can_wait);
+        let lobby = (config.personal_rooms.as_ref())
+            .filter(|_| can_wait)
+            .map(|c| c.lobby());
+        let personal = self.personal_room(room_id, token).await;
+        // Out of time is out of time, but personal rooms recur
+        let expired = personal.is_none() && self.has_expired(room_id).await;
+        // Peers sent here by a draining instance bring their room along
+        let may_start =
+            !expired && (personal.is_some() || migrated.is_some() || self.may_start_rooms());
+        // A room stopped between the lookup and the join is started afresh,
+        // once; a room that stops again is presumably failing on the join
+        let mut attempt = None;
+        for _ in 0..2 {
+            let (handle, created) = if may_start {
+                self.room_or_start(room_id, || {
+                    personal.as_ref().map_or_else(Room::new, |p| p.start())
+                })
+                .await
+            } else {
+                let gone = match expired {
+                    true => "This call has reached its maximum duration",
+                    false => "Room not found",
+                };
+                (self.room(room_id).await.ok_or(gone)?, false)
+            };
+            let (peer_id, sender) = (peer_id.clone(), sender.clone());
+            let (token, queue, lobby) = (token.map(str::to_string), queue.clone(), lobby.clone());
+            let identity = identity.clone();
+            let joined = handle
+                .call(move |room| {
+                    let (token, who) = (token.as_deref(), identity.as_deref());
+                    if who.is_some_and(|who| room.is_banned(who)) {
+                        return Err("You are banned from this room");
+                    }
+                    if let Some(moved) = room.take_over(token, who, duplicates, sender.clone()) {
+                        return moved.map(|joined| (Admission::Joined(joined), true));
+                    }
+                    room.check_share_link(token)?;
+                    let entered = if room.awaits_owner() && !room.lets_in_early(who, token) {
+                        room.wait_for_owner(peer_id, sender, lobby.as_ref())
+                            .map(|admission| (admission, false))
+                    } else {
+                        match room.join(peer_id.clone(), sender.clone(), token) {
+                            Ok((joined, resumed)) => {
+                                room.set_identity(&joined.peer_id, identity);
+                                Ok((Admission::Joined(joined), resumed))
+                            }
+                            Err(e) => match queue {
+                                Some(queue) => room
+                                    .enqueue(peer_id, sender, &queue)
+                                    .map(|admission| (admission, false)),
+                                None => Err(e),
+                            },
+                        }
+                    };
+                    if entered.is_ok() {
+                        room.count_share_link_use(token);
+                    }
+                    entered
+                })
+                .await;
+            if let Some(result) = joined {
+                attempt = Some((result?, created));
+                break;
+            }
+            self.forget_room(room_id, &handle).await;
+        }
+        let Some(((admission, resumed), created)) = attempt else {
+            return Err("Room not found");
+        };
+        if let Admission::Joined(joined) = &admission {
+            self.record_join(room_id, joined, resumed, created).await;
+        }
+        Ok(admission)
+    }
 
-        let peer_count = room.peers.len();
+    /// Log and record a peer that has taken its slot
+    pub async fn record_join(
+        &self,
+        room_id: &str,
+        joined: &JoinedRoom,
+        resumed: bool,
+        created: bool,
+    ) {
+        let peer_id = &joined.peer_id;
+        let peer_count = joined.peer_count;
         info!(
             "Peer {} joined room {} ({} peers)",
             peer_id, room_id, peer_count
         );
 
-        Ok(peer_count)
+        // Rooms created implicitly by joining get their record here
+        if created {
+            self.open_call_record(room_id, joined.settings.mode, RoomMetadata::new(), None)
+                .await;
+            self.record_timeline(room_id, TimelineKind::Created, None, "on first join")
+                .await;
+        }
+        self.record_call_join(room_id, peer_id, peer_count).await;
+        self.record_attendance_join(room_id, peer_id).await;
+        let role = match resumed {
+            true => format!("{:?} (resumed)", joined.role),
+            false => format!("{:?}", joined.role),
+        };
+        self.record_timeline(room_id, TimelineKind::Joined, Some(peer_id), role)
+            .await;
+        // Joins count share link uses and may hand a personal room over
+        self.save_room_state(room_id).await;
     }
 
     /// Remove a peer from a room
-    pub async fn leave_room(&self, room_id: &str, peer_id: &str) {
-        let mut rooms = self.rooms.lock().await;
+    ///
+    /// A reason the client gave in its own `Leave` message takes precedence
+    /// over the one inferred from how the connection ended.
+    pub async fn leave_room(&self, room_id: &str, peer_id: &str, reason: LeaveReason) {
+        self.leave_session(room_id, peer_id, None, reason).await;
+    }
 
-        if let Some(room) = rooms.get_mut(room_id) {
-            if room.remove_peer(peer_id).is_some() {
-                info!("Peer {} left room {}", peer_id, room_id);
+    /// Remove a peer from a room unless it has moved off the connection
+    /// whose resume token is `session`
+    pub async fn leave_session(
+        &self,
+        room_id: &str,
+        peer_id: &str,
+        session: Option<&str>,
+        reason: LeaveReason,
+    ) {
+        let Some(room) = self.room(room_id).await else {
+            return;
+        };
+        let (id, token) = (peer_id.to_string(), session.map(str::to_string));
+        let Some(reason) = room
+            .call(move |room| {
+                room.peers
+                    .iter()
+                    .find(|p| p.id == id)
+                    .and_then(|p| p.leave_reason)
+                    .unwrap_or(reason)
+            })
+            .await
+        else {
+            return;
+        };
 
-                // Notify remaining peer
-                room.broadcast_to_all(&WsMessage::Leave);
-                room.broadcast_to_all(&WsMessage::room_info(room.peers.len()));
-            }
+        // Hold the slot of a peer whose connection dropped
+        if reason == LeaveReason::NetworkTimeout
+            && self.suspend_peer(room_id, peer_id, session).await
+        {
+            return;
+        }
+        let rid = room_id.to_string();
+        let id = peer_id.to_string();
+        let Some((left, outcome, empty)) = room
+            .call(move |room| {
+                let left = room.holds(&id, token.as_deref()) && room.leave(&id, reason);
+                // Clean up empty rooms after timeout
+                let empty = room.peers.is_empty();
+                if empty {
+                    debug!(
+                        "Room {} is now empty, will be cleaned up after timeout",
+                        rid
+                    );
+                }
+                // A departure can settle a pending consent round
+                (left, room.settle_consent(), empty)
+            })
+            .await
+        else {
+            return;
+        };
 
-            // Clean up empty rooms after timeout
-            if room.peers.is_empty() {
-                debug!("Room {} is now empty, will be cleaned up after timeout", room_id);
+        if left {
+            info!("Peer {} left room {} ({:?})", peer_id, room_id, reason);
+            self.close_media_relay(room_id, peer_id).await;
+            self.record_disconnect(room_id, peer_id, reason).await;
+            if empty {
+                self.end_directed_call(room_id).await;
             }
         }
+        if let Some(outcome) = outcome {
+            let detail = format!("{:?} after peer {} left", outcome, peer_id);
+            self.record_audit(Some(room_id), "server", "recording.consent", detail)
+                .await;
+        }
     }
 
-    /// Forward a message to the other peer in a room
-    pub async fn relay_message(&self, room_id: &str, sender_id: &str, msg: WsMessage) {
-        let rooms = self.rooms.lock().await;
+    /// Remember the reason a peer gave for leaving
+    pub async fn set_leave_reason(&self, room_id: &str, peer_id: &str, reason: LeaveReason) {
+        let peer_id = peer_id.to_string();
+        self.with_room(room_id, move |room| {
+            if let Some(peer) = room.peers.iter_mut().find(|p| p.id == peer_id) {
+                peer.leave_reason = Some(reason);
+            }
+        })
+        .await;
+    }
 
-        if let Some(room) = rooms.get(room_id) {
-            room.broadcast_to_others(sender_id, &msg);
-        }
+    /// Record a departure in the room timeline and call record
+    pub async fn record_disconnect(&self, room_id: &str, peer_id: &str, reason: LeaveReason) {
+        let detail = format!("{:?}", reason);
+        self.record_timeline(room_id, TimelineKind::Left, Some(peer_id), detail)
+            .await;
+        self.record_call_disconnect(room_id, peer_id, reason).await;
+        self.record_attendance_leave(room_id, peer_id).await;
     }
 
-    /// Get peer count for a room
-    pub async fn get_peer_count(&self, room_id: &str) -> usize {
-        let rooms = self.rooms.lock().await;
-        rooms.get(room_id).map(|r| r.peers.len()).unwrap_or(0)
+    /// Forward a message to the other peer(s) in a room
+    pub async fn relay_message(
+        &self,
+        room_id: &str,
+        sender_id: &str,
+        msg: WsMessage,
+    ) -> Result<(), &'static str> {
+        let policy = self.config().ice_policy;
+        let sender_id = sender_id.to_string();
+        let relayed = self.with_room(room_id, move |room| {
+            match policy.enforce(&room.settings, msg)? {
+                Some(msg) => room.route(&sender_id, msg),
+                None => {
+                    debug!("Dropped ICE candidate from peer {} by policy", sender_id);
+                    Ok(())
+                }
+            }
+        });
+        relayed.await.unwrap_or(Ok(()))
     }
 
-    /// Clean up inactive rooms
-    pub async fn cleanup_inactive_rooms(&self) {
-        let mut rooms = self.rooms.lock().await;
-        let before = rooms.len();
+    /// Record a peer's capabilities and relay them to the rest of the room
+    ///
+    /// The capabilities are checked against the compatibility gate and
+    /// against the codecs of the peer(s) this one will talk to. When the gate
+    /// is enforced a failing peer is removed from the room.
+    pub async fn register_capabilities(
+        &self,
+        room_id: &str,
+        peer_id: &str,
+        caps: ClientCapabilities,
+    ) {
+        let config = self.config();
+        let (id, rid) = (peer_id.to_string(), room_id.to_string());
+        let kicked = self.with_room(room_id, move |room| {
+            let compat = &config.compatibility;
+            let counterpart_ok = room
+                .peers
+                .iter()
+                .filter(|p| p.id != id)
+                .filter(|p| {
+                    room.mode() == RoomMode::Interactive
+                        || room.is_presenter(&p.id)
+                        || room.is_presenter(&id)
+                })
+                .filter_map(|p| p.capabilities.as_ref())
+                .try_for_each(|other| check_codec_overlap(&caps, other));
+            if let Err(reason) = compat.check(&caps).and(counterpart_ok) {
+                warn!("Peer {} in room {} is incompatible: {}", id, rid, reason);
+                if compat.enforce {
+                    room.send_to(&id, WsMessage::error(reason));
+                    room.leave(&id, LeaveReason::Kicked);
+                    return true;
+                }
+            }
 
-        rooms.retain(|id, room| {
-            if room.is_inactive() {
-                info!("Cleaning up inactive room: {}", id);
-                false
-            } else {
-                true
+            let msg = WsMessage::Capabilities {
+                codecs: caps.codecs.clone(),
+                browser: caps.browser.clone(),
+                version: caps.version.clone(),
+            };
+            if let Some(peer) = room.peers.iter_mut().find(|p| p.id == id) {
+                peer.capabilities = Some(caps);
             }
+            let _ = room.route(&id, msg);
+            false
         });
+        if kicked.await == Some(true) {
+            self.record_disconnect(room_id, peer_id, LeaveReason::Kicked)
+                .await;
+        }
+    }
 
-        let removed = before - rooms.len();
-        if removed > 0 {
-            info!("Cleaned up {} inactive rooms", removed);
+    /// Apply a host-only command to a room
+    pub async fn host_command(
+        &self,
+        room_id: &str,
+        sender_id: &str,
+        msg: WsMessage,
+    ) -> Result<(), &'static str> {
+        let sender_id = sender_id.to_string();
+        self.with_room(room_id, move |room| {
+            room.apply_host_command(&sender_id, msg)
+        })
+        .await
+        .unwrap_or(Err("Room not found"))?;
+        self.save_room_state(room_id).await;
+        Ok(())
+    }
+
+    /// Whether `token` makes its holder the presenter of broadcast room
+    /// `room_id`
+    pub async fn is_presenter_token(&self, room_id: &str, token: Option<&str>) -> bool {
+        let Some(token) = token.map(str::to_string) else {
+            return false;
+        };
+        self.with_room(room_id, move |room| room.is_presenter_token(&token))
+            .await
+            .unwrap_or(false)
+    }
+
+    /// A broadcast room's presenter token
+    pub async fn presenter_token(&self, room_id: &str) -> Option<String> {
+        self.with_room(room_id, |room| room.presenter_token.clone())
+            .await
+            .flatten()
+    }
+
+    /// Check whether a peer may perform an action in a room
+    pub async fn permits(&self, room_id: &str, peer_id: &str, permission: Permission) -> bool {
+        let peer_id = peer_id.to_string();
+        self.with_room(room_id, move |room| room.permits(&peer_id, permission))
+            .await
+            .unwrap_or(false)
+    }
+
+    /// Announce a newly joined peer to the rest of the room
+    ///
+    /// In broadcast rooms only the presenter needs to know about new viewers,
+    /// and a presenter arriving after its viewers learns about each of them.
+    pub async fn announce_join(&self, room_id: &str, peer_id: &str, peer_count: usize) {
+        let peer_id = peer_id.to_string();
+        self.with_room(room_id, move |room| {
+            let join = WsMessage::Join {
+                peer_id: Some(peer_id.clone()),
+            };
+            match (room.mode(), &room.presenter) {
+                (RoomMode::Broadcast, Some(presenter)) if *presenter != peer_id => {
+                    room.send_to(presenter, join);
+                }
+                (RoomMode::Broadcast, Some(_)) => {
+                    let viewers: Vec<String> = room
+                        .peers
+                        .iter()
+                        .filter(|p| p.id != peer_id)
+                        .map(|p| p.id.clone())
+                        .collect();
+                    for viewer in viewers {
+                        let join = WsMessage::Join {
+                            peer_id: Some(viewer),
+                        };
+                        room.send_to(&peer_id, join);
+                    }
+                }
+                (RoomMode::Broadcast, None) => {}
+                (RoomMode::Interactive, _) => room.broadcast_to_others(&peer_id, &join),
+            }
+            room.broadcast_to_others(
+                &peer_id,
+                &WsMessage::room_info(peer_count, room.settings.codecs.as_deref(), room.features),
+            );
+            // Recorders are only announced while recording was granted
+            if room.recording {
+                room.send_to(&peer_id, WsMessage::Recording { active: true });
+                for recorder in &room.recorders {
+                    room.send_to(
+                        &peer_id,
+                        WsMessage::RecorderJoined {
+                            peer_id: recorder.id.clone(),
+                        },
+                    );
+                }
+            }
+            room.send_devices_to(&peer_id);
+        })
+        .await;
+    }
+
+    /// Transcription settings for a peer's room, if the peer is present
+    /// with the resume token `session` and the room has captions enabled
+    pub async fn transcription_for(
+        &self,
+        room_id: &str,
+        peer_id: &str,
+        session: &str,
+    ) -> Option<TranscriptionSettings> {
+        let (peer_id, session) = (peer_id.to_string(), session.to_string());
+        self.with_room(room_id, move |room| {
+            room.peers
+                .iter()
+                .find(|p| p.id == peer_id && secret_eq(&p.resume_token, &session))?;
+            room.settings.transcription.clone()
+        })
+        .await?
+    }
+
+    /// Send a message to every peer in a room
+    pub async fn broadcast_to_room(&self, room_id: &str, msg: WsMessage) {
+        self.with_room(room_id, move |room| room.broadcast_to_all(&msg))
+            .await;
+    }
+
+    /// Send a message directly to one peer in a room
+    pub async fn send_to_peer(&self, room_id: &str, peer_id: &str, msg: WsMessage) {
+        let peer_id = peer_id.to_string();
+        self.with_room(room_id, move |room| room.send_to(&peer_id, msg))
+            .await;
+    }
+
+    /// Get peer count, capacity and mode for a room
+    pub async fn get_room_summary(&self, room_id: &str) -> (usize, usize, RoomMode) {
+        self.with_room(room_id, |room| {
+            (room.peers.len(), room.capacity(), room.mode())
+        })
+        .await
+        .unwrap_or((0, MAX_PEERS_PER_ROOM, RoomMode::Interactive))
+    }
+}
+
+impl AppState {
+    /// Notify every peer that the server is going away and close all rooms
+    pub async fn shutdown(&self) {
+        let rooms: Vec<(String, RoomHandle)> = self.rooms.lock().await.drain().collect();
+        info!("Shutting down {} rooms", rooms.len());
+
+        let ids: Vec<String> = rooms.iter().map(|(id, _)| id.clone()).collect();
+        for (room_id, room) in rooms {
+            if self.migrate_room(&room_id, &room).await {
+                continue;
+            }
+            let peer_ids = room
+                .call_until(|room| {
+                    let peer_ids: Vec<String> = room.peers.iter().map(|p| p.id.clone()).collect();
+                    for peer_id in &peer_ids {
+                        room.leave(peer_id, LeaveReason::ServerShutdown);
+                    }
+                    ControlFlow::Break(peer_ids)
+                })
+                .await
+                .unwrap_or_default();
+            for peer_id in peer_ids {
+                self.record_disconnect(&room_id, &peer_id, LeaveReason::ServerShutdown)
+                    .await;
+            }
+            self.record_timeline(&room_id, TimelineKind::Closed, None, "server shutdown")
+                .await;
         }
+        self.close_call_records(&ids).await;
+        // The rooms' snapshots stay, for whichever instance gets them next
+        self.release_room_claims().await;
     }
 }
 
 impl Default for AppState {
     fn default() -> Self {
-        Self::new()
+        Self::new(Config::default())
     }
 }
 
-/// Spawn a background task to periodically clean up inactive rooms
-pub fn spawn_cleanup_task(state: AppState) {
-    tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_secs(60));
-        loop {
-            interval.tick().await;
-            state.cleanup_inactive_rooms().await;
-        }
-    });
+/// Length of room dial-in codes
+pub const DIAL_CODE_DIGITS: usize = 6;
+
+/// Current Unix time in seconds
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Replace the `peer_id` of a signaling message with its sender, returning
+/// the original value (the addressed target)
+pub fn stamp_sender(msg: WsMessage, sender_id: &str) -> (WsMessage, Option<String>) {
+    let from = Some(sender_id.to_string());
+    match msg {
+        WsMessage::Offer { sdp, peer_id } => (WsMessage::Offer { sdp, peer_id: from }, peer_id),
+        WsMessage::Answer { sdp, peer_id } => (WsMessage::Answer { sdp, peer_id: from }, peer_id),
+        WsMessage::IceCandidate {
+            candidate,
+            sdp_m_line_index,
+            sdp_mid,
+            peer_id,
+        } => (
+            WsMessage::IceCandidate {
+                candidate,
+                sdp_m_line_index,
+                sdp_mid,
+                peer_id: from,
+            },
+            peer_id,
+        ),
+        other => (other, None),
+    }
 }
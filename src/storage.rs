@@ -0,0 +1,639 @@
+//! Blob storage for recordings and export bundles
+//!
+//! Without a `storage` section, recordings live under `recordings.dir` and
+//! finished exports are held in memory. With one, their files go to a
+//! [`BlobStore`] instead: a local directory, an S3 (or S3-compatible)
+//! bucket, or a Google Cloud Storage bucket. Objects are keyed
+//! `recordings/{id}/{name}` and `exports/{id}.zip`, under an optional
+//! `prefix`. The store is opened at startup; changing it needs a restart.
+
+use std::fmt::Debug;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Bytes;
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode, Url};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use crate::push::{AccessToken, JWT_BEARER_GRANT, ServiceAccount, rs256, sign_jwt};
+use crate::state::unix_timestamp;
+
+/// Timeout for one request to a cloud store
+const STORAGE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// OAuth scope for reading and writing GCS objects
+const GCS_SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+/// Somewhere to keep files
+pub trait BlobStore: Debug + Send + Sync {
+    /// Create or replace an object
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: &'a str,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<(), String>>;
+    /// An object's contents; None if there is no such object
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>, String>>;
+    /// Delete an object; deleting a missing one is not an error
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), String>>;
+    /// Keys of the objects starting with `prefix`
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>>;
+}
+
+/// Which store to use, by `backend`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum StorageConfig {
+    /// A directory on this machine
+    Local { dir: PathBuf },
+    /// An S3 bucket, or one of an S3-compatible service
+    S3(S3Config),
+    /// A Google Cloud Storage bucket
+    Gcs(GcsConfig),
+}
+
+/// S3 bucket and credentials
+#[derive(Debug, Clone, Deserialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Base URL of an S3-compatible service (MinIO, R2, ...), addressed
+    /// path-style; AWS when unset
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Prepended to every key, e.g. `axi-vid/`
+    #[serde(default)]
+    pub prefix: String,
+}
+
+/// GCS bucket and credentials
+#[derive(Debug, Clone, Deserialize)]
+pub struct GcsConfig {
+    pub bucket: String,
+    /// Service account key file (JSON) with access to the bucket
+    pub service_account: PathBuf,
+    /// GCS API base URL
+    #[serde(default = "default_gcs_url")]
+    pub url: String,
+    /// Prepended to every key, e.g. `axi-vid/`
+    #[serde(default)]
+    pub prefix: String,
+}
+
+fn default_gcs_url() -> String {
+    "https://storage.googleapis.com".to_string()
+}
+
+/// Open the configured store, if any
+pub fn store_from_config(
+    config: Option<&StorageConfig>,
+    http: &reqwest::Client,
+) -> Option<Arc<dyn BlobStore>> {
+    Some(match config? {
+        StorageConfig::Local { dir } => Arc::new(LocalStore { dir: dir.clone() }),
+        StorageConfig::S3(s3) => Arc::new(S3Store {
+            config: s3.clone(),
+            http: http.clone(),
+        }),
+        StorageConfig::Gcs(gcs) => Arc::new(GcsStore {
+            config: gcs.clone(),
+            http: http.clone(),
+            token: Mutex::new(None),
+        }),
+    })
+}
+
+/// Percent-encode all but unreserved characters, and `/` if `keep_slash`
+fn percent_encode(value: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// A store in a local directory; keys are relative paths
+#[derive(Debug)]
+pub struct LocalStore {
+    dir: PathBuf,
+}
+
+impl LocalStore {
+    /// Where `key` is kept, refusing keys that would leave the directory
+    fn path(&self, key: &str) -> Result<PathBuf, String> {
+        let relative = Path::new(key);
+        if key.is_empty()
+            || !relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(format!("Invalid key {:?}", key));
+        }
+        Ok(self.dir.join(relative))
+    }
+
+    /// Keys of the files under `dir`, relative to the store
+    async fn walk(&self, dir: PathBuf, keys: &mut Vec<String>) -> std::io::Result<()> {
+        let mut pending = vec![dir];
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    pending.push(path);
+                } else if let Ok(relative) = path.strip_prefix(&self.dir) {
+                    let parts: Vec<_> = relative
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy())
+                        .collect();
+                    keys.push(parts.join("/"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BlobStore for LocalStore {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        _content_type: &'a str,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            // Written aside and renamed, so readers never see half a file
+            let mut temp = path.clone().into_os_string();
+            temp.push(".tmp");
+            tokio::fs::write(&temp, &data)
+                .await
+                .map_err(|e| e.to_string())?;
+            tokio::fs::rename(&temp, &path)
+                .await
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>, String>> {
+        Box::pin(async move {
+            match tokio::fs::read(self.path(key)?).await {
+                Ok(data) => Ok(Some(Bytes::from(data))),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.to_string()),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.path(key)?).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        Box::pin(async move {
+            // Only the directory holding the prefix needs walking
+            let start = match prefix.rsplit_once('/') {
+                Some((dir, _)) => self.path(dir)?,
+                None => self.dir.clone(),
+            };
+            let mut keys = Vec::new();
+            self.walk(start, &mut keys)
+                .await
+                .map_err(|e| e.to_string())?;
+            keys.retain(|k| k.starts_with(prefix) && !k.ends_with(".tmp"));
+            keys.sort();
+            Ok(keys)
+        })
+    }
+}
+
+/// A store in an S3 bucket, with SigV4-signed requests
+#[derive(Debug)]
+pub struct S3Store {
+    config: S3Config,
+    http: reqwest::Client,
+}
+
+impl S3Store {
+    /// URL of an object, or of the bucket for an empty key
+    fn url(&self, key: &str) -> Result<Url, String> {
+        let key = match key {
+            "" => String::new(),
+            key => percent_encode(&format!("{}{}", self.config.prefix, key), true),
+        };
+        let url = match &self.config.endpoint {
+            Some(endpoint) => format!(
+                "{}/{}/{}",
+                endpoint.trim_end_matches('/'),
+                self.config.bucket,
+                key
+            ),
+            None => format!(
+                "https://{}.s3.{}.amazonaws.com/{}",
+                self.config.bucket, self.config.region, key
+            ),
+        };
+        Url::parse(&url).map_err(|e| format!("Invalid S3 URL {}: {}", url, e))
+    }
+
+    /// Send a request signed with AWS Signature Version 4
+    async fn send(
+        &self,
+        method: Method,
+        url: Url,
+        query: &[(&str, &str)],
+        content_type: Option<&str>,
+        body: Bytes,
+    ) -> Result<reqwest::Response, String> {
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = format!("{:x}", Sha256::digest(&body));
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let mut params: Vec<(String, String)> = query
+            .iter()
+            .map(|(k, v)| (percent_encode(k, false), percent_encode(v, false)))
+            .collect();
+        params.sort();
+        let canonical_query = params
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            url.path(),
+            canonical_query,
+            host,
+            payload_hash,
+            amz_date,
+            signed_headers,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            amz_date,
+            scope,
+            Sha256::digest(canonical_request.as_bytes())
+        );
+        let key = [
+            date.as_str(),
+            self.config.region.as_str(),
+            "s3",
+            "aws4_request",
+        ]
+        .iter()
+        .fold(
+            format!("AWS4{}", self.config.secret_access_key).into_bytes(),
+            |key, part| hmac_sha256(&key, part.as_bytes()),
+        );
+        let signature = hmac_sha256(&key, string_to_sign.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.config.access_key_id, scope, signed_headers, signature
+        );
+
+        let mut url = url;
+        if !canonical_query.is_empty() {
+            url.set_query(Some(&canonical_query));
+        }
+        let mut request = self
+            .http
+            .request(method, url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header(reqwest::header::AUTHORIZATION, authorization)
+            .timeout(STORAGE_TIMEOUT);
+        if let Some(content_type) = content_type {
+            request = request.header(reqwest::header::CONTENT_TYPE, content_type);
+        }
+        request
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| format!("S3 request failed: {}", e))
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The text of each `<tag>` element in an S3 XML response
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    xml.split(&open)
+        .skip(1)
+        .filter_map(|rest| rest.split_once(&close))
+        .map(|(value, _)| {
+            value
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&")
+        })
+        .collect()
+}
+
+impl BlobStore for S3Store {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: &'a str,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let response = self
+                .send(Method::PUT, self.url(key)?, &[], Some(content_type), data)
+                .await?;
+            if !response.status().is_success() {
+                return Err(format!("S3 answered {} to PUT {}", response.status(), key));
+            }
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>, String>> {
+        Box::pin(async move {
+            let response = self
+                .send(Method::GET, self.url(key)?, &[], None, Bytes::new())
+                .await?;
+            match response.status() {
+                StatusCode::NOT_FOUND => Ok(None),
+                status if status.is_success() => response
+                    .bytes()
+                    .await
+                    .map(Some)
+                    .map_err(|e| format!("S3 download failed: {}", e)),
+                status => Err(format!("S3 answered {} to GET {}", status, key)),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let response = self
+                .send(Method::DELETE, self.url(key)?, &[], None, Bytes::new())
+                .await?;
+            let status = response.status();
+            if !status.is_success() && status != StatusCode::NOT_FOUND {
+                return Err(format!("S3 answered {} to DELETE {}", status, key));
+            }
+            Ok(())
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        Box::pin(async move {
+            let full_prefix = format!("{}{}", self.config.prefix, prefix);
+            let mut keys = Vec::new();
+            let mut continuation = None;
+            loop {
+                let mut query = vec![("list-type", "2"), ("prefix", full_prefix.as_str())];
+                if let Some(token) = continuation.as_deref() {
+                    query.push(("continuation-token", token));
+                }
+                let response = self
+                    .send(Method::GET, self.url("")?, &query, None, Bytes::new())
+                    .await?;
+                if !response.status().is_success() {
+                    return Err(format!("S3 answered {} to a listing", response.status()));
+                }
+                let xml = response
+                    .text()
+                    .await
+                    .map_err(|e| format!("S3 listing failed: {}", e))?;
+                keys.extend(
+                    xml_values(&xml, "Key").into_iter().filter_map(|key| {
+                        key.strip_prefix(&self.config.prefix).map(str::to_string)
+                    }),
+                );
+                continuation = xml_values(&xml, "NextContinuationToken").pop();
+                if continuation.is_none() {
+                    return Ok(keys);
+                }
+            }
+        })
+    }
+}
+
+/// A store in a GCS bucket, authenticated as a service account
+#[derive(Debug)]
+pub struct GcsStore {
+    config: GcsConfig,
+    http: reqwest::Client,
+    /// Access token and when it expires
+    token: Mutex<Option<(String, u64)>>,
+}
+
+impl GcsStore {
+    /// A current access token for the service account
+    async fn access_token(&self) -> Result<String, String> {
+        let now = unix_timestamp();
+        let mut cached = self.token.lock().await;
+        if let Some((token, expires_at)) = cached.as_ref()
+            && now + 60 < *expires_at
+        {
+            return Ok(token.clone());
+        }
+        let path = &self.config.service_account;
+        let account: ServiceAccount = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let assertion = sign_jwt(
+            &json!({"alg": "RS256", "typ": "JWT"}),
+            &json!({
+                "iss": account.client_email,
+                "scope": GCS_SCOPE,
+                "aud": account.token_uri,
+                "iat": now,
+                "exp": now + 3600
+            }),
+            |input| rs256(&account.private_key, input),
+        )?;
+        let response = self
+            .http
+            .post(&account.token_uri)
+            .form(&[("grant_type", JWT_BEARER_GRANT), ("assertion", &assertion)])
+            .timeout(STORAGE_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("Token endpoint answered {}", response.status()));
+        }
+        let token: AccessToken = response.json().await.map_err(|e| e.to_string())?;
+        *cached = Some((token.access_token.clone(), now + token.expires_in));
+        Ok(token.access_token)
+    }
+
+    /// URL of an object in the JSON API
+    fn object_url(&self, key: &str) -> String {
+        format!(
+            "{}/storage/v1/b/{}/o/{}",
+            self.config.url.trim_end_matches('/'),
+            percent_encode(&self.config.bucket, false),
+            percent_encode(&format!("{}{}", self.config.prefix, key), false)
+        )
+    }
+
+    /// Send an authenticated request
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, String> {
+        request
+            .bearer_auth(self.access_token().await?)
+            .timeout(STORAGE_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| format!("GCS request failed: {}", e))
+    }
+}
+
+impl BlobStore for GcsStore {
+    fn put<'a>(
+        &'a self,
+        key: &'a str,
+        content_type: &'a str,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/upload/storage/v1/b/{}/o",
+                self.config.url.trim_end_matches('/'),
+                percent_encode(&self.config.bucket, false)
+            );
+            let name = format!("{}{}", self.config.prefix, key);
+            let request = self
+                .http
+                .post(url)
+                .query(&[("uploadType", "media"), ("name", &name)])
+                .header(reqwest::header::CONTENT_TYPE, content_type)
+                .body(data);
+            let response = self.send(request).await?;
+            if !response.status().is_success() {
+                return Err(format!(
+                    "GCS answered {} to uploading {}",
+                    response.status(),
+                    key
+                ));
+            }
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Bytes>, String>> {
+        Box::pin(async move {
+            let request = self
+                .http
+                .get(self.object_url(key))
+                .query(&[("alt", "media")]);
+            let response = self.send(request).await?;
+            match response.status() {
+                StatusCode::NOT_FOUND => Ok(None),
+                status if status.is_success() => response
+                    .bytes()
+                    .await
+                    .map(Some)
+                    .map_err(|e| format!("GCS download failed: {}", e)),
+                status => Err(format!("GCS answered {} to fetching {}", status, key)),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            let response = self.send(self.http.delete(self.object_url(key))).await?;
+            let status = response.status();
+            if !status.is_success() && status != StatusCode::NOT_FOUND {
+                return Err(format!("GCS answered {} to deleting {}", status, key));
+            }
+            Ok(())
+        })
+    }
+
+    fn list<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>, String>> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/storage/v1/b/{}/o",
+                self.config.url.trim_end_matches('/'),
+                percent_encode(&self.config.bucket, false)
+            );
+            let full_prefix = format!("{}{}", self.config.prefix, prefix);
+            let mut keys = Vec::new();
+            let mut page_token: Option<String> = None;
+            loop {
+                let mut query = vec![
+                    ("prefix", full_prefix.as_str()),
+                    ("fields", "items(name),nextPageToken"),
+                ];
+                if let Some(token) = page_token.as_deref() {
+                    query.push(("pageToken", token));
+                }
+                let response = self.send(self.http.get(&url).query(&query)).await?;
+                if !response.status().is_success() {
+                    return Err(format!("GCS answered {} to a listing", response.status()));
+                }
+                let page: Value = response
+                    .json()
+                    .await
+                    .map_err(|e| format!("GCS listing failed: {}", e))?;
+                keys.extend(
+                    page["items"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|item| item["name"].as_str())
+                        .filter_map(|name| name.strip_prefix(&self.config.prefix))
+                        .map(str::to_string),
+                );
+                page_token = page["nextPageToken"].as_str().map(str::to_string);
+                if page_token.is_none() {
+                    return Ok(keys);
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,216 @@
+//! STUN and TURN messages
+//!
+//! Just enough of the wire format (RFC 5389 and RFC 5766) for the server
+//! to check on its TURN servers: binding requests, allocations, permissions
+//! and the indications carrying relayed data. Messages are built and read
+//! as a type and a list of raw attributes; addresses are XOR-encoded.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use uuid::Uuid;
+
+/// Fixed value in every message's header
+pub const MAGIC_COOKIE: u32 = 0x2112_a442;
+
+// Methods
+pub const BINDING: u16 = 0x0001;
+pub const ALLOCATE: u16 = 0x0003;
+pub const REFRESH: u16 = 0x0004;
+pub const SEND: u16 = 0x0006;
+pub const DATA: u16 = 0x0007;
+pub const CREATE_PERMISSION: u16 = 0x0008;
+
+// Classes, combined with a method into a message type
+pub const REQUEST: u16 = 0x0000;
+pub const INDICATION: u16 = 0x0010;
+pub const SUCCESS: u16 = 0x0100;
+pub const ERROR: u16 = 0x0110;
+
+// Attributes
+pub const USERNAME: u16 = 0x0006;
+pub const MESSAGE_INTEGRITY: u16 = 0x0008;
+pub const ERROR_CODE: u16 = 0x0009;
+pub const LIFETIME: u16 = 0x000d;
+pub const XOR_PEER_ADDRESS: u16 = 0x0012;
+pub const DATA_ATTR: u16 = 0x0013;
+pub const REALM: u16 = 0x0014;
+pub const NONCE: u16 = 0x0015;
+pub const XOR_RELAYED_ADDRESS: u16 = 0x0016;
+pub const REQUESTED_TRANSPORT: u16 = 0x0019;
+pub const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// Length of the fixed header
+const HEADER_LEN: usize = 20;
+
+/// A STUN message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// Method and class, e.g. `ALLOCATE | SUCCESS`
+    pub kind: u16,
+    pub transaction: [u8; 12],
+    pub attributes: Vec<(u16, Vec<u8>)>,
+}
+
+impl Message {
+    /// A message with a fresh transaction ID
+    pub fn new(kind: u16) -> Self {
+        let mut transaction = [0u8; 12];
+        transaction.copy_from_slice(&Uuid::new_v4().into_bytes()[..12]);
+        Self {
+            kind,
+            transaction,
+            attributes: Vec::new(),
+        }
+    }
+
+    /// A response to this message, of class `class`
+    pub fn reply(&self, class: u16) -> Self {
+        Self {
+            kind: self.method() | class,
+            transaction: self.transaction,
+            attributes: Vec::new(),
+        }
+    }
+
+    pub fn method(&self) -> u16 {
+        self.kind & !ERROR
+    }
+
+    pub fn class(&self) -> u16 {
+        self.kind & ERROR
+    }
+
+    /// Add an attribute
+    pub fn with(mut self, attribute: u16, value: impl Into<Vec<u8>>) -> Self {
+        self.attributes.push((attribute, value.into()));
+        self
+    }
+
+    /// Add an XOR-encoded address attribute
+    pub fn with_address(self, attribute: u16, address: SocketAddr) -> Self {
+        let mut value = vec![0];
+        let port = address.port() ^ (MAGIC_COOKIE >> 16) as u16;
+        match address.ip() {
+            IpAddr::V4(ip) => {
+                value.push(0x01);
+                value.extend_from_slice(&port.to_be_bytes());
+                let ip = u32::from(ip) ^ MAGIC_COOKIE;
+                value.extend_from_slice(&ip.to_be_bytes());
+            }
+            IpAddr::V6(ip) => {
+                value.push(0x02);
+                value.extend_from_slice(&port.to_be_bytes());
+                let mask = self.xor_mask();
+                value.extend(ip.octets().iter().zip(mask).map(|(b, m)| b ^ m));
+            }
+        }
+        self.with(attribute, value)
+    }
+
+    /// The first value of an attribute
+    pub fn get(&self, attribute: u16) -> Option<&[u8]> {
+        self.attributes
+            .iter()
+            .find(|(a, _)| *a == attribute)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// An XOR-encoded address attribute
+    pub fn address(&self, attribute: u16) -> Option<SocketAddr> {
+        let value = self.get(attribute)?;
+        let port =
+            u16::from_be_bytes([*value.get(2)?, *value.get(3)?]) ^ (MAGIC_COOKIE >> 16) as u16;
+        let ip = match value.get(1)? {
+            0x01 => {
+                let bytes: [u8; 4] = value.get(4..8)?.try_into().ok()?;
+                IpAddr::V4(Ipv4Addr::from(u32::from_be_bytes(bytes) ^ MAGIC_COOKIE))
+            }
+            0x02 => {
+                let bytes = value.get(4..20)?;
+                let mut octets = [0u8; 16];
+                for (i, (b, m)) in bytes.iter().zip(self.xor_mask()).enumerate() {
+                    octets[i] = b ^ m;
+                }
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return None,
+        };
+        Some(SocketAddr::new(ip, port))
+    }
+
+    /// Code of an error response, e.g. 401
+    pub fn error_code(&self) -> Option<u16> {
+        let value = self.get(ERROR_CODE)?;
+        Some(u16::from(*value.get(2)? & 0x07) * 100 + u16::from(*value.get(3)?))
+    }
+
+    /// Cookie and transaction ID, which IPv6 addresses are XORed with
+    fn xor_mask(&self) -> [u8; 16] {
+        let mut mask = [0u8; 16];
+        mask[..4].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        mask[4..].copy_from_slice(&self.transaction);
+        mask
+    }
+
+    /// The message on the wire
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (attribute, value) in &self.attributes {
+            body.extend_from_slice(&attribute.to_be_bytes());
+            body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            body.extend_from_slice(value);
+            // Attributes are padded to four bytes
+            body.resize(body.len().next_multiple_of(4), 0);
+        }
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+        bytes.extend_from_slice(&self.kind.to_be_bytes());
+        bytes.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        bytes.extend_from_slice(&self.transaction);
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    /// The message on the wire, ending with a `MESSAGE-INTEGRITY` under
+    /// `key`
+    pub fn encode_signed(&self, key: &[u8]) -> Vec<u8> {
+        let mut bytes = self.encode();
+        // The length covers the integrity attribute about to be added
+        let length = (bytes.len() - HEADER_LEN + 24) as u16;
+        bytes[2..4].copy_from_slice(&length.to_be_bytes());
+        let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(&bytes);
+        bytes.extend_from_slice(&MESSAGE_INTEGRITY.to_be_bytes());
+        bytes.extend_from_slice(&20u16.to_be_bytes());
+        bytes.extend_from_slice(&mac.finalize().into_bytes());
+        bytes
+    }
+
+    /// Read a message; `None` if `bytes` is not one
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || bytes[4..8] != MAGIC_COOKIE.to_be_bytes() {
+            return None;
+        }
+        let kind = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let length = usize::from(u16::from_be_bytes([bytes[2], bytes[3]]));
+        let body = bytes.get(HEADER_LEN..HEADER_LEN + length)?;
+        let mut attributes = Vec::new();
+        let mut rest = body;
+        while rest.len() >= 4 {
+            let attribute = u16::from_be_bytes([rest[0], rest[1]]);
+            let len = usize::from(u16::from_be_bytes([rest[2], rest[3]]));
+            let value = rest.get(4..4 + len)?;
+            attributes.push((attribute, value.to_vec()));
+            rest = rest
+                .get((4 + len).next_multiple_of(4)..)
+                .unwrap_or_default();
+        }
+        Some(Self {
+            kind,
+            transaction: bytes[8..20].try_into().ok()?,
+            attributes,
+        })
+    }
+}
@@ -0,0 +1,83 @@
+//! Tenants
+//!
+//! Each entry under `tenants` is one customer sharing the server, with its
+//! own API keys and notification targets. A room created with
+//! `Authorization: Bearer <key>` for one of a tenant's keys belongs to that
+//! tenant: its events go to the tenant's `webhooks`, signed with the
+//! tenant's secret, and its "call started" announcement goes to the
+//! tenant's Slack and Discord webhooks and, with `email`, is mailed from
+//! the tenant's sender. None of it reaches the top-level `webhooks` or
+//! `integrations`, nor another tenant's targets; a tenant without webhooks
+//! gets no events. Rooms created without a key are routed as before. With
+//! tenants configured, a key that matches none is refused.
+//!
+//! A tenant can be given a usage quota, with warnings and downgrades as it
+//! runs out (see [`crate::quotas`]), and keys issued by operators besides
+//! those configured (see [`crate::api_keys`]).
+//!
+//! Webhook events carry their tenant, and keep their order per tenant: an
+//! endpoint that is down holds up only its own tenant's events.
+
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::integrations::IntegrationsConfig;
+use crate::token::secret_eq;
+use crate::webhooks::WebhookConfig;
+
+/// A tenant's keys and where its notifications go
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TenantConfig {
+    /// Keys creating rooms on behalf of the tenant
+    pub api_keys: Vec<String>,
+    /// Endpoint receiving the tenant's room events
+    pub webhooks: Option<WebhookConfig>,
+    /// Slack/Discord targets for the tenant's announcements
+    pub integrations: IntegrationsConfig,
+    /// Sender of the tenant's announcements by email
+    pub email: Option<TenantEmail>,
+    /// Participant-minutes allowed per period; unmetered when unset
+    pub quota: Option<crate::quotas::QuotaConfig>,
+}
+
+/// Mail through an SMTP relay (`host:port`), without TLS or login
+#[derive(Debug, Clone, Deserialize)]
+pub struct TenantEmail {
+    pub smtp: String,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+impl Config {
+    /// The tenant owning `api_key`
+    pub fn tenant_for_key(&self, api_key: &str) -> Option<&str> {
+        self.tenants
+            .iter()
+            .find(|(_, tenant)| tenant.api_keys.iter().any(|k| secret_eq(k, api_key)))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Where events of `tenant`'s rooms go; the top-level webhook for rooms
+    /// without one
+    pub fn webhook_for(&self, tenant: Option<&str>) -> Option<&WebhookConfig> {
+        match tenant {
+            Some(name) => self.tenants.get(name)?.webhooks.as_ref(),
+            None => self.webhooks.as_ref(),
+        }
+    }
+
+    /// Where `tenant`'s rooms are announced; the top-level integrations for
+    /// rooms without one
+    pub fn integrations_for(&self, tenant: Option<&str>) -> Option<&IntegrationsConfig> {
+        match tenant {
+            Some(name) => self.tenants.get(name).map(|t| &t.integrations),
+            None => Some(&self.integrations),
+        }
+    }
+
+    /// Whether any room's events go to a webhook
+    pub fn has_webhooks(&self) -> bool {
+        self.webhooks.is_some() || self.tenants.values().any(|t| t.webhooks.is_some())
+    }
+}
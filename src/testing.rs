@@ -0,0 +1,267 @@
+//! In-process server and signaling client for tests
+//!
+//! Enabled by the `testing` feature. [`TestServer`] runs the full router on
+//! an ephemeral localhost port and shuts down when dropped; [`SignalClient`]
+//! speaks the WebSocket protocol in terms of [`WsMessage`], so tests can
+//! assert on what each peer receives.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use uuid::Uuid;
+
+use crate::app::build_app;
+use crate::config::Config;
+use crate::listener::{RouteScope, bind_tcp};
+use crate::models::{LeaveReason, WsMessage};
+use crate::state::AppState;
+
+/// How long [`SignalClient::expect`] waits before failing the test
+pub const EXPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A running server bound to `127.0.0.1` on a random port
+pub struct TestServer {
+    pub addr: SocketAddr,
+    /// Shared state, for driving background jobs or inspecting rooms
+    pub state: AppState,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl TestServer {
+    /// Start a server with the default configuration
+    pub async fn start() -> Self {
+        Self::with_config(Config::default()).await
+    }
+
+    /// Start a server with `config`; listener settings are ignored
+    pub async fn with_config(config: Config) -> Self {
+        Self::bind(SocketAddr::from(([127, 0, 0, 1], 0)), config).await
+    }
+
+    /// Start a server serving only the routes in `scope`
+    pub async fn with_scope(config: Config, scope: RouteScope) -> Self {
+        Self::serve(SocketAddr::from(([127, 0, 0, 1], 0)), config, scope).await
+    }
+
+    /// Start a server on `addr`, e.g. `[::1]:0` for IPv6 clients or `[::]:0`
+    /// for both
+    pub async fn bind(addr: SocketAddr, config: Config) -> Self {
+        Self::serve(addr, config, RouteScope::All).await
+    }
+
+    async fn serve(addr: SocketAddr, config: Config, scope: RouteScope) -> Self {
+        let listener = bind_tcp(addr).expect("Failed to bind test listener");
+        let addr = listener.local_addr().expect("Test listener has no address");
+        let state = AppState::new(config);
+        let app =
+            build_app(state.clone(), scope).into_make_service_with_connect_info::<SocketAddr>();
+
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(async {
+                    let _ = stopped.await;
+                })
+                .await
+                .expect("Test server failed");
+        });
+        Self {
+            addr,
+            state,
+            shutdown: Some(shutdown),
+        }
+    }
+
+    /// Base HTTP URL, e.g. `http://127.0.0.1:41234`
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// WebSocket URL for a room, with an optional `?token=`
+    pub fn ws_url(&self, room_id: &str, token: Option<&str>) -> String {
+        match token {
+            Some(token) => format!("ws://{}/ws/{}?token={}", self.addr, room_id, token),
+            None => format!("ws://{}/ws/{}", self.addr, room_id),
+        }
+    }
+
+    /// Join `room_id` as a new peer
+    pub async fn join(&self, room_id: &str) -> SignalClient {
+        SignalClient::connect(&self.ws_url(room_id, None)).await
+    }
+
+    /// Join `room_id` presenting a resume, re-invite or room token
+    pub async fn join_with_token(&self, room_id: &str, token: &str) -> SignalClient {
+        SignalClient::connect(&self.ws_url(room_id, Some(token))).await
+    }
+
+    /// Open a presence socket with a user token
+    pub async fn go_online(&self, token: &str) -> SignalClient {
+        SignalClient::connect(&format!("ws://{}/ws/presence?token={}", self.addr, token)).await
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+/// A fresh random room ID
+pub fn room_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A signaling client connected to one room
+pub struct SignalClient {
+    socket: Socket,
+    /// The `Welcome` (or `Error`) the server sent on connect
+    pub welcome: WsMessage,
+}
+
+impl SignalClient {
+    /// Connect and read the server's first message
+    ///
+    /// Panics if the connection fails. A rejected join still returns a
+    /// client whose `welcome` is the server's `Error`.
+    pub async fn connect(url: &str) -> Self {
+        let (mut socket, _) = tokio_tungstenite::connect_async(url)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to connect to {}: {}", url, e));
+        let welcome = next_message(&mut socket)
+            .await
+            .expect("Server closed the connection before greeting");
+        Self { socket, welcome }
+    }
+
+    /// This peer's ID; panics if the join was rejected
+    pub fn peer_id(&self) -> &str {
+        match &self.welcome {
+            WsMessage::Welcome { peer_id, .. } => peer_id,
+            other => panic!("Join was rejected: {:?}", other),
+        }
+    }
+
+    /// Token for reclaiming this peer's slot; panics if the join was rejected
+    pub fn resume_token(&self) -> &str {
+        match &self.welcome {
+            WsMessage::Welcome { resume_token, .. } => resume_token,
+            other => panic!("Join was rejected: {:?}", other),
+        }
+    }
+
+    /// Send a message
+    pub async fn send(&mut self, msg: &WsMessage) {
+        let text = serde_json::to_string(msg).expect("WsMessage always serializes");
+        self.socket
+            .send(Message::text(text))
+            .await
+            .expect("Failed to send to server");
+    }
+
+    /// Send text as is, e.g. to check it is relayed byte for byte
+    pub async fn send_text(&mut self, text: &str) {
+        self.socket
+            .send(Message::text(text))
+            .await
+            .expect("Failed to send to server");
+    }
+
+    /// Next message from the server, or `None` once the connection closes
+    ///
+    /// Panics after [`EXPECT_TIMEOUT`] without a message.
+    pub async fn recv(&mut self) -> Option<WsMessage> {
+        next_message(&mut self.socket).await
+    }
+
+    /// Skip messages until one whose text contains `wanted`, returning the
+    /// text as received
+    ///
+    /// Panics if the connection closes or nothing matches in time.
+    pub async fn expect_text(&mut self, wanted: &str) -> String {
+        let deadline = tokio::time::Instant::now() + EXPECT_TIMEOUT;
+        loop {
+            let text = tokio::time::timeout_at(deadline, next_text(&mut self.socket))
+                .await
+                .unwrap_or_else(|_| panic!("No message containing {}", wanted));
+            match text {
+                Some(text) if text.contains(wanted) => return text,
+                Some(_) => continue,
+                None => panic!("Connection closed before a message containing {}", wanted),
+            }
+        }
+    }
+
+    /// Skip messages until one matches `wanted`, returning it
+    ///
+    /// Panics if the connection closes or nothing matches in time.
+    pub async fn expect(&mut self, wanted: impl Fn(&WsMessage) -> bool) -> WsMessage {
+        let mut skipped = Vec::new();
+        let deadline = tokio::time::Instant::now() + EXPECT_TIMEOUT;
+        loop {
+            let msg = tokio::time::timeout_at(deadline, self.recv())
+                .await
+                .unwrap_or_else(|_| panic!("No matching message; skipped {:?}", skipped));
+            match msg {
+                Some(msg) if wanted(&msg) => return msg,
+                Some(msg) => skipped.push(msg),
+                None => panic!("Connection closed; skipped {:?}", skipped),
+            }
+        }
+    }
+
+    /// Assert that nothing arrives within `wait`
+    pub async fn expect_silence(&mut self, wait: Duration) {
+        if let Ok(Some(Ok(Message::Text(text)))) =
+            tokio::time::timeout(wait, self.socket.next()).await
+        {
+            panic!("Expected no message, got {}", text);
+        }
+    }
+
+    /// Send `Leave` with a reason and close the socket cleanly
+    pub async fn hang_up(mut self) {
+        let leave = WsMessage::Leave {
+            peer_id: None,
+            reason: Some(LeaveReason::UserHangup),
+        };
+        self.send(&leave).await;
+        let _ = self.socket.close(None).await;
+    }
+
+    /// Drop the TCP connection without a WebSocket close, like a lost network
+    pub fn drop_connection(self) {
+        drop(self.socket);
+    }
+}
+
+/// Read the next text frame as a `WsMessage`, or `None` once closed
+async fn next_message(socket: &mut Socket) -> Option<WsMessage> {
+    let text = next_text(socket).await?;
+    let msg = serde_json::from_str(&text)
+        .unwrap_or_else(|e| panic!("Server sent invalid message {}: {}", text, e));
+    Some(msg)
+}
+
+/// Read the next text frame, or `None` once closed
+async fn next_text(socket: &mut Socket) -> Option<String> {
+    loop {
+        let frame = tokio::time::timeout(EXPECT_TIMEOUT, socket.next())
+            .await
+            .expect("Timed out waiting for a message");
+        match frame {
+            Some(Ok(Message::Text(text))) => return Some(text.to_string()),
+            Some(Ok(Message::Close(_))) | None | Some(Err(_)) => return None,
+            Some(Ok(_)) => continue,
+        }
+    }
+}
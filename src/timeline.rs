@@ -0,0 +1,140 @@
+//! Per-room event timeline
+//!
+//! Lifecycle, negotiation, error and quality events are appended to a
+//! timeline that outlives the room, so support staff can reconstruct what
+//! happened during a reported bad call.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use crate::admin::AdminAuth;
+use crate::models::{RoomTimeline, TimelineEvent, TimelineKind, WebhookPayload};
+use crate::state::AppState;
+
+/// Number of room timelines kept in memory
+pub const MAX_STORED_TIMELINES: usize = 1000;
+
+/// Events kept per room timeline
+pub const MAX_TIMELINE_EVENTS: usize = 2000;
+
+impl AppState {
+    /// Append an event to a room's timeline
+    pub async fn record_timeline(
+        &self,
+        room_id: &str,
+        kind: TimelineKind,
+        peer_id: Option<&str>,
+        detail: impl Into<String>,
+    ) {
+        let detail = detail.into();
+        let payload = match (kind, peer_id) {
+            (TimelineKind::Created, _) => Some(WebhookPayload::RoomCreated),
+            (TimelineKind::Joined, Some(peer_id)) => Some(WebhookPayload::PeerJoined {
+                peer_id: peer_id.to_string(),
+            }),
+            (TimelineKind::Left, Some(peer_id)) => Some(WebhookPayload::PeerLeft {
+                peer_id: peer_id.to_string(),
+                reason: detail.clone(),
+            }),
+            (TimelineKind::Closed, _) => Some(WebhookPayload::RoomClosed {
+                reason: detail.clone(),
+            }),
+            _ => None,
+        };
+        if let Some(payload) = payload {
+            self.queue_webhook(room_id, payload).await;
+        }
+        if kind == TimelineKind::Closed {
+            self.end_capture(room_id).await;
+        }
+
+        let mut timelines = self.timelines.lock().await;
+        if !timelines.contains_key(room_id) && timelines.len() >= MAX_STORED_TIMELINES {
+            // Evict the timeline that was least recently written
+            let oldest = timelines
+                .iter()
+                .min_by_key(|(_, t)| t.events.last().map_or(0, |e| e.timestamp_ms))
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                timelines.remove(&oldest);
+            }
+        }
+
+        let timeline = timelines
+            .entry(room_id.to_string())
+            .or_insert_with(|| RoomTimeline {
+                room_id: room_id.to_string(),
+                ..Default::default()
+            });
+        if timeline.events.len() >= MAX_TIMELINE_EVENTS {
+            return;
+        }
+
+        // An offer after the first one is a renegotiation
+        let kind = match kind {
+            TimelineKind::Offer
+                if timeline
+                    .events
+                    .iter()
+                    .any(|e| e.kind == TimelineKind::Offer) =>
+            {
+                TimelineKind::Renegotiation
+            }
+            kind => kind,
+        };
+        timeline.events.push(TimelineEvent {
+            timestamp_ms: unix_timestamp_ms(),
+            kind,
+            peer_id: peer_id.map(str::to_string),
+            detail,
+        });
+    }
+
+    /// Fetch a room's timeline
+    pub async fn get_timeline(&self, room_id: &str) -> Option<RoomTimeline> {
+        self.timelines.lock().await.get(room_id).cloned()
+    }
+}
+
+/// Current Unix time in milliseconds
+pub(crate) fn unix_timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Get the event timeline of a room
+///
+/// Events name peers and carry error details, so this is for support staff
+/// only.
+#[utoipa::path(
+    get,
+    path = "/api/room/{room_id}/timeline",
+    tag = "Analytics",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room")
+    ),
+    responses(
+        (status = 200, description = "Room events in order", body = RoomTimeline),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 404, description = "No timeline for this room")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn get_timeline(
+    _auth: AdminAuth,
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+) -> Response {
+    match state.get_timeline(&room_id).await {
+        Some(timeline) => Json(timeline).into_response(),
+        None => (StatusCode::NOT_FOUND, "No timeline for this room").into_response(),
+    }
+}
@@ -0,0 +1,226 @@
+//! Signed access tokens
+//!
+//! HS256 JWTs keyed by `jwt_secret`. Room tokens are passed as `?token=` on
+//! the room link and are required to join when `require_room_token` is set;
+//! admin tokens are accepted on `/admin` in place of `admin_token`; recorder
+//! tokens admit a hidden recorder peer; user tokens open their holder's
+//! personal room. Tokens are minted offline with `axi-vid generate-token`.
+
+use std::time::Instant;
+
+use axum::{
+    extract::FromRequestParts,
+    http::{StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use crate::admin::AdminRole;
+use crate::state::{AppState, unix_timestamp};
+
+/// Whether two secrets are equal, taking as long wherever they differ
+pub fn secret_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+/// What a token grants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    /// Join the room named in `room`
+    Room,
+    /// Use the admin API
+    Admin,
+    /// Join the room named in `room` as a hidden recorder
+    Recorder,
+    /// Own and open the personal room of `sub`
+    User,
+    /// Rejoin the room named in `room` as peer `sub` after its instance
+    /// shut down
+    Migrate,
+}
+
+/// JWT payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub scope: TokenScope,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    /// Free-form subject, e.g. who the token was issued to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    /// Email address of the holder, checked by invite-only rooms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    /// Role of an admin token; owner when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<AdminRole>,
+    pub iat: u64,
+    pub exp: u64,
+}
+
+impl Claims {
+    /// Claims valid from now for `ttl_secs`
+    pub fn new(scope: TokenScope, room: Option<String>, ttl_secs: u64) -> Self {
+        let now = unix_timestamp();
+        Self {
+            scope,
+            room,
+            sub: None,
+            email: None,
+            role: None,
+            iat: now,
+            exp: now + ttl_secs,
+        }
+    }
+}
+
+/// Fixed JOSE header: `{"alg":"HS256","typ":"JWT"}`
+const HEADER: &str = "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9";
+
+/// Encode and sign `claims`
+pub fn mint(secret: &str, claims: &Claims) -> String {
+    let payload = serde_json::to_vec(claims).unwrap_or_default();
+    let signing_input = format!("{}.{}", HEADER, URL_SAFE_NO_PAD.encode(payload));
+    let signature = URL_SAFE_NO_PAD.encode(sign(secret, &signing_input));
+    format!("{}.{}", signing_input, signature)
+}
+
+/// Check signature and expiry, returning the claims
+pub fn verify(secret: &str, token: &str) -> Result<Claims, &'static str> {
+    let (signing_input, signature) = token.rsplit_once('.').ok_or("Malformed token")?;
+    let (header, payload) = signing_input.split_once('.').ok_or("Malformed token")?;
+    if header != HEADER {
+        return Err("Unsupported token algorithm");
+    }
+
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| "Malformed token")?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).map_err(|_| "Bad secret")?;
+    mac.update(signing_input.as_bytes());
+    mac.verify_slice(&signature)
+        .map_err(|_| "Invalid token signature")?;
+
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|_| "Malformed token")?;
+    let claims: Claims = serde_json::from_slice(&payload).map_err(|_| "Malformed token")?;
+    if claims.exp <= unix_timestamp() {
+        return Err("Token has expired");
+    }
+    Ok(claims)
+}
+
+fn sign(secret: &str, input: &str) -> Vec<u8> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(input.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Extractor for the subject of a user token given as bearer
+pub struct UserAuth(pub String);
+
+impl FromRequestParts<AppState> for UserAuth {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let provided = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        state
+            .user_of(provided)
+            .map(UserAuth)
+            .ok_or_else(|| (StatusCode::UNAUTHORIZED, "User token required").into_response())
+    }
+}
+
+impl AppState {
+    /// The subject of a user token
+    pub fn user_of(&self, token: Option<&str>) -> Option<String> {
+        let config = self.config();
+        let claims = verify(config.jwt_secret.as_deref()?, token?).ok()?;
+        if claims.scope != TokenScope::User
+            || !self.is_provisioned(claims.sub.as_deref(), claims.email.as_deref())
+        {
+            return None;
+        }
+        claims.sub
+    }
+
+    /// Whether `token` lets its holder join `room_id`
+    ///
+    /// Always true unless `require_room_token` is set; then either a room JWT
+    /// or resume token for this room, a user token of the owner of a personal room or of an
+    /// invitee, a held-slot token (resume or re-invite) or a live share link
+    /// is needed.
+    pub async fn may_join(&self, room_id: &str, token: Option<&str>) -> bool {
+        let config = self.config();
+        if !config.require_room_token {
+            return true;
+        }
+        let Some(token) = token else {
+            return false;
+        };
+
+        if let Some(secret) = config.jwt_secret.as_deref()
+            && let Ok(claims) = verify(secret, token)
+        {
+            return match claims.scope {
+                TokenScope::Room | TokenScope::Migrate => claims.room.as_deref() == Some(room_id),
+                TokenScope::User
+                    if !self.is_provisioned(claims.sub.as_deref(), claims.email.as_deref()) =>
+                {
+                    false
+                }
+                TokenScope::User => {
+                    let personal = self.personal_room(room_id, Some(token)).await;
+                    personal.is_some_and(|p| claims.sub.as_ref() == Some(&p.owner))
+                        || self
+                            .with_room(room_id, move |room| {
+                                room.invitees.is_some()
+                                    && room.invites(claims.sub.as_deref(), claims.email.as_deref())
+                            })
+                            .await
+                            .unwrap_or(false)
+                }
+                _ => false,
+            };
+        }
+        let (now, unix_now) = (Instant::now(), unix_timestamp());
+        let token = token.to_string();
+        self.with_room(room_id, move |room| {
+            room.reservations
+                .iter()
+                .any(|r| secret_eq(&r.token, &token) && r.expires_at > now)
+                || room
+                    .share_links
+                    .iter()
+                    .any(|l| secret_eq(&l.token, &token) && l.is_live(unix_now))
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    /// Whether `token` admits a hidden recorder to `room_id`
+    pub fn is_recorder_token(&self, room_id: &str, token: Option<&str>) -> bool {
+        let config = self.config();
+        let (Some(secret), Some(token)) = (config.jwt_secret.as_deref(), token) else {
+            return false;
+        };
+        verify(secret, token).is_ok_and(|claims| {
+            claims.scope == TokenScope::Recorder && claims.room.as_deref() == Some(room_id)
+        })
+    }
+}
@@ -0,0 +1,224 @@
+//! Per-peer inbound message metrics and top-talker mitigation
+//!
+//! Every message a peer sends is counted, in total and within a fixed
+//! window. A peer that sends more than `max_messages` or `max_bytes` in one
+//! window (typically a broken client caught in an ICE storm) is flagged as a
+//! top talker, audited once, and handled according to `mitigation`: only
+//! logged, throttled until the window rolls over, or disconnected.
+
+use std::time::{Duration, Instant};
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::admin::AdminAuth;
+use crate::models::{PeerTraffic, WsMessage};
+use crate::state::AppState;
+
+/// What happens to a peer over its budget
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mitigation {
+    /// Flag and log only
+    Log,
+    /// Drop the peer's messages until the window rolls over
+    #[default]
+    Throttle,
+    /// Remove the peer from the room
+    Disconnect,
+}
+
+/// Expected signaling volume per peer
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TrafficConfig {
+    /// Length of the counting window
+    pub window_secs: u64,
+    /// Messages a peer may send per window
+    pub max_messages: u32,
+    /// Bytes a peer may send per window
+    pub max_bytes: u64,
+    pub mitigation: Mitigation,
+}
+
+impl Default for TrafficConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: 10,
+            max_messages: 300,
+            max_bytes: 512 * 1024,
+            mitigation: Mitigation::Throttle,
+        }
+    }
+}
+
+/// What to do with an inbound message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    Drop,
+    Disconnect,
+}
+
+/// Running counters for one peer
+#[derive(Debug)]
+pub struct TrafficCounters {
+    pub messages: u64,
+    pub bytes: u64,
+    pub dropped: u64,
+    pub flagged: bool,
+    window_started: Instant,
+    window_messages: u32,
+    window_bytes: u64,
+    /// Whether the peer went over budget in the current window
+    over_budget: bool,
+}
+
+impl Default for TrafficCounters {
+    fn default() -> Self {
+        Self {
+            messages: 0,
+            bytes: 0,
+            dropped: 0,
+            flagged: false,
+            window_started: Instant::now(),
+            window_messages: 0,
+            window_bytes: 0,
+            over_budget: false,
+        }
+    }
+}
+
+impl TrafficCounters {
+    /// Count one message of `len` bytes; the second value is true the first
+    /// time in a window that the peer goes over budget
+    fn record(&mut self, len: usize, config: &TrafficConfig) -> (Verdict, bool) {
+        if self.window_started.elapsed() >= Duration::from_secs(config.window_secs) {
+            self.window_started = Instant::now();
+            self.window_messages = 0;
+            self.window_bytes = 0;
+            self.over_budget = false;
+        }
+        self.messages += 1;
+        self.bytes += len as u64;
+        self.window_messages = self.window_messages.saturating_add(1);
+        self.window_bytes += len as u64;
+
+        let exceeded =
+            self.window_messages > config.max_messages || self.window_bytes > config.max_bytes;
+        let newly = exceeded && !self.over_budget;
+        self.over_budget |= exceeded;
+        self.flagged |= exceeded;
+        let verdict = match (exceeded, config.mitigation) {
+            (false, _) | (true, Mitigation::Log) => Verdict::Accept,
+            (true, Mitigation::Throttle) => Verdict::Drop,
+            (true, Mitigation::Disconnect) => Verdict::Disconnect,
+        };
+        if verdict == Verdict::Drop {
+            self.dropped += 1;
+        }
+        (verdict, newly)
+    }
+}
+
+impl AppState {
+    /// Count a message from a peer and decide whether to handle it
+    ///
+    /// On `Disconnect` the caller ends the connection as `Kicked`.
+    pub async fn record_inbound(&self, room_id: &str, peer_id: &str, len: usize) -> Verdict {
+        let config = self.config();
+        let (id, limits) = (peer_id.to_string(), config.traffic.clone());
+        let recorded = self.with_room(room_id, move |room| {
+            let peer = room.peers.iter_mut().find(|p| p.id == id)?;
+            let first_flag = !peer.traffic.flagged;
+            let (verdict, newly) = peer.traffic.record(len, &limits);
+            if newly {
+                let notice = match verdict {
+                    Verdict::Drop => Some("Sending too fast; messages are being dropped"),
+                    Verdict::Disconnect => Some("Sending too fast; disconnected"),
+                    Verdict::Accept => None,
+                };
+                if let Some(notice) = notice {
+                    let _ = peer.sender.send(WsMessage::error(notice));
+                }
+            }
+            Some((verdict, newly, first_flag, peer.traffic.window_messages))
+        });
+        let Some(Some((verdict, newly, first_flag, window_messages))) = recorded.await else {
+            return Verdict::Accept;
+        };
+        if !newly {
+            return verdict;
+        }
+
+        warn!(
+            "Peer {} in room {} is a top talker ({} messages this window): {:?}",
+            peer_id, room_id, window_messages, config.traffic.mitigation
+        );
+        if first_flag {
+            let detail = format!("{:?}", config.traffic.mitigation);
+            self.record_audit(Some(room_id), "server", "traffic.top_talker", detail)
+                .await;
+        }
+        verdict
+    }
+
+    /// Inbound volume of every connected peer, busiest first
+    pub async fn list_traffic(&self, flagged_only: bool) -> Vec<PeerTraffic> {
+        let mut list = Vec::new();
+        for (room_id, room) in self.room_handles().await {
+            let peers = room.call(move |room| {
+                room.peers
+                    .iter()
+                    .map(|peer| PeerTraffic {
+                        room_id: room_id.clone(),
+                        peer_id: peer.id.clone(),
+                        role: peer.role,
+                        messages: peer.traffic.messages,
+                        bytes: peer.traffic.bytes,
+                        dropped: peer.traffic.dropped,
+                        window_messages: peer.traffic.window_messages,
+                        flagged: peer.traffic.flagged,
+                    })
+                    .filter(|t| t.flagged || !flagged_only)
+                    .collect::<Vec<_>>()
+            });
+            list.extend(peers.await.unwrap_or_default());
+        }
+        list.sort_by_key(|t| std::cmp::Reverse(t.bytes));
+        list
+    }
+}
+
+/// Query parameters for the traffic listing
+#[derive(Debug, Deserialize)]
+pub struct TrafficQuery {
+    #[serde(default)]
+    pub flagged: bool,
+}
+
+/// Inbound message volume per connected peer
+#[utoipa::path(
+    get,
+    path = "/admin/traffic",
+    tag = "Admin",
+    params(
+        ("flagged" = Option<bool>, Query, description = "Only peers flagged as top talkers")
+    ),
+    responses(
+        (status = 200, description = "Peers by bytes sent, busiest first", body = Vec<PeerTraffic>),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_traffic(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+    Query(query): Query<TrafficQuery>,
+) -> Json<Vec<PeerTraffic>> {
+    Json(state.list_traffic(query.flagged).await)
+}
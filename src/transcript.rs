@@ -0,0 +1,237 @@
+//! Post-call transcript storage and summaries
+//!
+//! Chat messages and captions are appended to a per-room transcript that
+//! outlives the room itself. A summary can be generated on demand by an
+//! OpenAI-compatible chat completions API. Neither is public: reading a
+//! transcript takes an admin token, a room token for the room or a signed
+//! URL, and a summary an operator token or a room token.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::admin::{AdminRole, admin_principal};
+use crate::models::{RoomTranscript, SummaryResponse, TranscriptEntry, TranscriptKind};
+use crate::state::{AppState, unix_timestamp};
+use crate::token::{TokenScope, verify};
+
+/// Number of room transcripts kept in memory
+pub const MAX_STORED_TRANSCRIPTS: usize = 1000;
+
+/// Lines kept per room transcript
+pub const MAX_TRANSCRIPT_ENTRIES: usize = 10_000;
+
+/// LLM endpoint used for summaries
+#[derive(Debug, Clone, Deserialize)]
+pub struct SummaryConfig {
+    /// Chat completions URL, e.g. `https://api.openai.com/v1/chat/completions`
+    pub url: String,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    pub model: String,
+}
+
+impl AppState {
+    /// Append a line to a room's transcript
+    pub async fn record_transcript(
+        &self,
+        room_id: &str,
+        peer_id: &str,
+        kind: TranscriptKind,
+        text: &str,
+    ) {
+        let mut transcripts = self.transcripts.lock().await;
+        if !transcripts.contains_key(room_id) && transcripts.len() >= MAX_STORED_TRANSCRIPTS {
+            // Evict the transcript that was least recently written
+            let oldest = transcripts
+                .iter()
+                .min_by_key(|(_, t)| t.entries.last().map_or(0, |e| e.timestamp))
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                transcripts.remove(&oldest);
+            }
+        }
+
+        let transcript = transcripts
+            .entry(room_id.to_string())
+            .or_insert_with(|| RoomTranscript {
+                room_id: room_id.to_string(),
+                ..Default::default()
+            });
+        if transcript.entries.len() >= MAX_TRANSCRIPT_ENTRIES {
+            return;
+        }
+        let entry = TranscriptEntry {
+            timestamp: unix_timestamp(),
+            peer_id: peer_id.to_string(),
+            kind,
+            text: text.to_string(),
+        };
+        transcript.entries.push(entry.clone());
+        drop(transcripts);
+        if kind == TranscriptKind::Chat {
+            self.store_chat(room_id, entry).await;
+        }
+    }
+
+    /// Fetch a room's transcript
+    pub async fn get_transcript(&self, room_id: &str) -> Option<RoomTranscript> {
+        self.transcripts.lock().await.get(room_id).cloned()
+    }
+}
+
+/// Whether the request's bearer token is an admin token of at least
+/// `role`, or a room token for `room_id`
+fn may_access(state: &AppState, room_id: &str, headers: &HeaderMap, role: AdminRole) -> bool {
+    let Some(token) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    let config = state.config();
+    if admin_principal(&config, token).is_some_and(|a| a.role >= role) {
+        return true;
+    }
+    config
+        .jwt_secret
+        .as_deref()
+        .and_then(|secret| verify(secret, token).ok())
+        .is_some_and(|c| c.scope == TokenScope::Room && c.room.as_deref() == Some(room_id))
+}
+
+/// Get the stored transcript of a room
+#[utoipa::path(
+    get,
+    path = "/api/room/{room_id}/transcript",
+    tag = "Transcription",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room"),
+        ("expires" = Option<u64>, Query, description = "Expiry of a signed URL"),
+        ("signature" = Option<String>, Query, description = "Signature of a signed URL")
+    ),
+    responses(
+        (status = 200, description = "Chat and captions in order", body = RoomTranscript),
+        (status = 401, description = "Neither a signature, an admin token nor a room token was given"),
+        (status = 403, description = "The signed URL has expired"),
+        (status = 404, description = "No transcript for this room")
+    )
+)]
+pub async fn get_transcript(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    let signed = state.config().signed_urls.is_some() && state.is_signed(&uri);
+    if !signed && state.is_expired_link(&uri) {
+        return (StatusCode::FORBIDDEN, "This link has expired").into_response();
+    }
+    if !signed && !may_access(&state, &room_id, &headers, AdminRole::Viewer) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            "A signed URL, admin token or room token is required",
+        )
+            .into_response();
+    }
+    match state.get_transcript(&room_id).await {
+        Some(transcript) => Json(transcript).into_response(),
+        None => (StatusCode::NOT_FOUND, "No transcript for this room").into_response(),
+    }
+}
+
+/// Generate (or regenerate) a summary of a room's transcript
+#[utoipa::path(
+    post,
+    path = "/api/room/{room_id}/summary",
+    tag = "Transcription",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room")
+    ),
+    responses(
+        (status = 200, description = "Generated summary", body = SummaryResponse),
+        (status = 401, description = "Neither an operator token nor a room token was given"),
+        (status = 404, description = "No transcript, or summaries not configured"),
+        (status = 429, description = "Too many requests from this address"),
+        (status = 502, description = "Summary backend failed")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn create_summary(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    if !may_access(&state, &room_id, &headers, AdminRole::Operator) {
+        warn!("Rejected summary request for room {}", room_id);
+        return (
+            StatusCode::UNAUTHORIZED,
+            "An operator token or room token is required",
+        )
+            .into_response();
+    }
+    let Some(config) = state.config().summary.clone() else {
+        return (StatusCode::NOT_FOUND, "Summaries are not configured").into_response();
+    };
+    let Some(transcript) = state.get_transcript(&room_id).await else {
+        return (StatusCode::NOT_FOUND, "No transcript for this room").into_response();
+    };
+
+    let lines: Vec<String> = transcript
+        .entries
+        .iter()
+        .map(|e| {
+            let speaker = &e.peer_id[..8.min(e.peer_id.len())];
+            format!("[{}] {}: {}", e.timestamp, speaker, e.text)
+        })
+        .collect();
+    let body = json!({
+        "model": config.model,
+        "messages": [
+            {
+                "role": "system",
+                "content": "Summarize this meeting transcript in a few sentences, then list decisions and action items."
+            },
+            { "role": "user", "content": lines.join("\n") }
+        ]
+    });
+
+    let mut request = state.http.post(&config.url).json(&body);
+    if let Some(key) = &config.api_key {
+        request = request.bearer_auth(key);
+    }
+    let summary = match request.send().await {
+        Ok(resp) if resp.status().is_success() => {
+            resp.json::<serde_json::Value>().await.ok().and_then(|v| {
+                v["choices"][0]["message"]["content"]
+                    .as_str()
+                    .map(str::to_string)
+            })
+        }
+        Ok(resp) => {
+            warn!("Summary backend returned {}", resp.status());
+            None
+        }
+        Err(e) => {
+            warn!("Summary backend failed: {}", e);
+            None
+        }
+    };
+    let Some(summary) = summary else {
+        return (StatusCode::BAD_GATEWAY, "Summary backend failed").into_response();
+    };
+
+    if let Some(transcript) = state.transcripts.lock().await.get_mut(&room_id) {
+        transcript.summary = Some(summary.clone());
+    }
+    state.persist_rooms(std::slice::from_ref(&room_id)).await;
+    info!("Generated summary for room {}", room_id);
+    Json(SummaryResponse { room_id, summary }).into_response()
+}
@@ -0,0 +1,213 @@
+//! Live transcription pipeline
+//!
+//! Audio chunks for a peer are submitted to a pluggable speech-to-text
+//! backend and the resulting text is broadcast to the room as `Caption`
+//! messages. Rooms opt in through `RoomSettings::transcription`.
+//!
+//! Audio reaches the pipeline through `POST /api/room/{id}/audio`, which a
+//! client (e.g. a `MediaRecorder` timeslice) or a server-side media component
+//! can feed.
+
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, Request, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::models::{TranscriptKind, WsMessage};
+use crate::state::AppState;
+
+/// Largest audio chunk accepted per request (5 MiB)
+pub const MAX_AUDIO_CHUNK_BYTES: usize = 5 * 1024 * 1024;
+
+/// An encoded audio chunk to transcribe
+#[derive(Debug, Clone)]
+pub struct AudioChunk {
+    pub data: Bytes,
+    /// MIME type, e.g. `audio/webm`
+    pub content_type: String,
+    pub language: String,
+}
+
+/// A speech-to-text engine
+pub trait SttBackend: std::fmt::Debug + Send + Sync {
+    /// Transcribe one chunk, returning the recognised text
+    fn transcribe(&self, chunk: AudioChunk) -> BoxFuture<'_, Result<String, String>>;
+}
+
+/// HTTP speech-to-text backend settings
+///
+/// Works with OpenAI-compatible `/v1/audio/transcriptions` endpoints and the
+/// whisper.cpp server's `/inference` endpoint, which share the same
+/// multipart request and `{"text": ...}` response.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SttConfig {
+    pub url: String,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// Model name sent with each request (e.g. `whisper-1`)
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+/// Speech-to-text over HTTP multipart uploads
+#[derive(Debug)]
+pub struct HttpSttBackend {
+    config: SttConfig,
+    http: reqwest::Client,
+}
+
+impl HttpSttBackend {
+    pub fn new(config: SttConfig, http: reqwest::Client) -> Self {
+        Self { config, http }
+    }
+}
+
+#[derive(Deserialize)]
+struct SttResponse {
+    text: String,
+}
+
+impl SttBackend for HttpSttBackend {
+    fn transcribe(&self, chunk: AudioChunk) -> BoxFuture<'_, Result<String, String>> {
+        Box::pin(async move {
+            let extension = chunk
+                .content_type
+                .split(['/', ';'])
+                .nth(1)
+                .unwrap_or("webm")
+                .to_string();
+            let file = reqwest::multipart::Part::bytes(chunk.data.to_vec())
+                .file_name(format!("chunk.{}", extension))
+                .mime_str(&chunk.content_type)
+                .map_err(|e| e.to_string())?;
+            let mut form = reqwest::multipart::Form::new()
+                .part("file", file)
+                .text("language", chunk.language)
+                .text("response_format", "json");
+            if let Some(model) = &self.config.model {
+                form = form.text("model", model.clone());
+            }
+
+            let mut request = self.http.post(&self.config.url).multipart(form);
+            if let Some(key) = &self.config.api_key {
+                request = request.bearer_auth(key);
+            }
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            if !response.status().is_success() {
+                return Err(format!("STT backend returned {}", response.status()));
+            }
+            let body: SttResponse = response.json().await.map_err(|e| e.to_string())?;
+            Ok(body.text.trim().to_string())
+        })
+    }
+}
+
+/// Build the configured backend, if any
+pub fn backend_from_config(
+    config: Option<&SttConfig>,
+    http: &reqwest::Client,
+) -> Option<Arc<dyn SttBackend>> {
+    config.map(|c| Arc::new(HttpSttBackend::new(c.clone(), http.clone())) as Arc<dyn SttBackend>)
+}
+
+/// Query parameters for audio submission
+#[derive(Debug, Deserialize)]
+pub struct AudioParams {
+    /// Peer the audio belongs to (as assigned in `Welcome`)
+    pub peer_id: String,
+}
+
+/// Submit an audio chunk for live captioning
+///
+/// Authenticated with the peer's resume token from `welcome`, which only
+/// the peer itself holds.
+#[utoipa::path(
+    post,
+    path = "/api/room/{room_id}/audio",
+    tag = "Transcription",
+    params(
+        ("room_id" = String, Path, description = "The UUID of the room"),
+        ("peer_id" = String, Query, description = "Peer the audio belongs to")
+    ),
+    request_body(content = Vec<u8>, description = "Encoded audio chunk", content_type = "audio/webm"),
+    responses(
+        (status = 202, description = "Chunk queued for transcription"),
+        (status = 401, description = "No resume token given"),
+        (status = 404, description = "Room, peer or transcription not available, or the resume token is not the peer's"),
+        (status = 413, description = "Chunk too large")
+    )
+)]
+pub async fn submit_audio(
+    State(state): State<AppState>,
+    Path(room_id): Path<String>,
+    Query(params): Query<AudioParams>,
+    request: Request,
+) -> Response {
+    let Some(backend) = state.stt.clone() else {
+        return (StatusCode::NOT_FOUND, "Transcription is not configured").into_response();
+    };
+    let session = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::to_string);
+    let Some(session) = session else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            "The peer's resume token is required",
+        )
+            .into_response();
+    };
+    let transcription = state.transcription_for(&room_id, &params.peer_id, &session);
+    let Some(settings) = transcription.await else {
+        return (
+            StatusCode::NOT_FOUND,
+            "Transcription not enabled for this peer",
+        )
+            .into_response();
+    };
+
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("audio/webm")
+        .to_string();
+    let data = match axum::body::to_bytes(request.into_body(), MAX_AUDIO_CHUNK_BYTES).await {
+        Ok(data) => data,
+        Err(_) => return (StatusCode::PAYLOAD_TOO_LARGE, "Audio chunk too large").into_response(),
+    };
+
+    let chunk = AudioChunk {
+        data,
+        content_type,
+        language: settings.language,
+    };
+    tokio::spawn(async move {
+        match backend.transcribe(chunk).await {
+            Ok(text) if text.is_empty() => debug!("Empty transcription for {}", params.peer_id),
+            Ok(text) => {
+                state
+                    .record_transcript(&room_id, &params.peer_id, TranscriptKind::Caption, &text)
+                    .await;
+                let caption = WsMessage::Caption {
+                    peer_id: params.peer_id,
+                    text,
+                    is_final: true,
+                };
+                state.broadcast_to_room(&room_id, caption).await;
+            }
+            Err(e) => warn!("Transcription failed in room {}: {}", room_id, e),
+        }
+    });
+
+    StatusCode::ACCEPTED.into_response()
+}
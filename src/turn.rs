@@ -0,0 +1,62 @@
+//! Short-lived TURN credentials
+//!
+//! With `turn` configured, browsers are not given a fixed TURN password.
+//! Each ICE server list carries credentials minted for the TURN REST API
+//! scheme that coturn accepts with `use-auth-secret`: the username is the
+//! expiry time, followed by the peer's name if known, and the password is
+//! the base64 HMAC-SHA1 of the username under the secret shared with the
+//! TURN server. Nothing is stored; the TURN server checks them on its own.
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha1::Sha1;
+
+use crate::config::IceServer;
+use crate::state::{AppState, unix_timestamp};
+
+/// TURN servers sharing a credential secret with this server
+#[derive(Debug, Clone, Deserialize)]
+pub struct TurnConfig {
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// Coturn's `static-auth-secret`
+    pub secret: String,
+    /// Seconds minted credentials stay valid
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+}
+
+fn default_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+impl TurnConfig {
+    /// Credentials for `name`, valid for `ttl_secs` from now
+    pub fn credentials(&self, name: Option<&str>) -> IceServer {
+        let expires = unix_timestamp() + self.ttl_secs;
+        let username = match name {
+            Some(name) => format!("{}:{}", expires, name),
+            None => expires.to_string(),
+        };
+        let mut mac = Hmac::<Sha1>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(username.as_bytes());
+        IceServer {
+            urls: self.urls.clone(),
+            username: Some(username),
+            credential: Some(STANDARD.encode(mac.finalize().into_bytes())),
+        }
+    }
+}
+
+impl AppState {
+    /// The configured ICE servers, plus TURN with fresh credentials for
+    /// `name`
+    pub fn ice_servers(&self, name: Option<&str>) -> Vec<IceServer> {
+        let config = self.config();
+        let mut servers = config.ice_servers.clone();
+        servers.extend(config.turn.as_ref().map(|turn| turn.credentials(name)));
+        servers
+    }
+}
@@ -0,0 +1,364 @@
+//! Voice messages for unanswered calls
+//!
+//! With `voicemail` configured, the caller of a directed call that was
+//! missed or declined may leave one short recording for the callee with
+//! `POST /api/calls/{room_id}/voicemail`. The callee is told with
+//! `voicemail` on its presence socket if it is available, and the call
+//! shows a `voicemail_url` in both parties' call logs, where either of them
+//! can fetch it. Messages are kept in memory, the latest `max_messages` per
+//! callee, until the callee deletes them. With [`scanning`](crate::scanning)
+//! on, a message can only be fetched, and the callee is only told of it,
+//! once it was scanned clean.
+
+use std::collections::HashMap;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Request, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::models::{CallState, DirectedCall, JobKind, ScanStatus, WsMessage};
+use crate::scanning::{Verdict, scan};
+use crate::state::{AppState, unix_timestamp};
+use crate::token::UserAuth;
+
+/// Limits on voice messages
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct VoicemailConfig {
+    /// Largest recording accepted, in bytes
+    pub max_bytes: usize,
+    /// Messages kept per callee; the oldest are dropped first
+    pub max_messages: usize,
+}
+
+impl Default for VoicemailConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: 1024 * 1024,
+            max_messages: 50,
+        }
+    }
+}
+
+/// A recording left for the callee of an unanswered call
+#[derive(Debug, Clone)]
+pub struct Voicemail {
+    pub caller: String,
+    pub callee: String,
+    pub content_type: String,
+    pub audio: Bytes,
+    /// Unix timestamp (seconds) when it was left
+    pub left_at: u64,
+    /// How its malware scan went; None without scanning
+    pub scan: Option<ScanStatus>,
+}
+
+/// Voice messages by the room ID of their call
+pub type Voicemails = HashMap<String, Voicemail>;
+
+type Rejection = (StatusCode, &'static str);
+
+impl AppState {
+    /// A logged call of `user`'s
+    async fn logged_call(&self, user: &str, room_id: &str) -> Option<DirectedCall> {
+        let history = self.call_history.lock().await;
+        history
+            .get(user)?
+            .iter()
+            .rev()
+            .find(|c| c.room_id == room_id)
+            .cloned()
+    }
+
+    /// Note whether a call has a voice message, and how its scan went, in
+    /// its CDR and call logs
+    async fn mark_voicemail(
+        &self,
+        call: &DirectedCall,
+        left_at: Option<u64>,
+        scan: Option<ScanStatus>,
+    ) {
+        let mut calls = self.calls.lock().await;
+        if let Some(directed) = calls
+            .get_mut(&call.room_id)
+            .and_then(|c| c.directed.as_mut())
+        {
+            directed.voicemail_at = left_at;
+            directed.voicemail_scan = scan;
+        }
+        drop(calls);
+
+        let mut history = self.call_history.lock().await;
+        for user in [&call.caller, &call.callee] {
+            let logged = history
+                .get_mut(user)
+                .and_then(|log| log.iter_mut().rev().find(|c| c.room_id == call.room_id));
+            if let Some(logged) = logged {
+                logged.voicemail_at = left_at;
+                logged.voicemail_scan = scan;
+            }
+        }
+    }
+
+    /// Store `caller`'s recording for the callee of an unanswered call
+    pub async fn leave_voicemail(
+        &self,
+        caller: &str,
+        room_id: &str,
+        content_type: String,
+        audio: Bytes,
+    ) -> Result<(), Rejection> {
+        let config = self.config();
+        let limits = config
+            .voicemail
+            .as_ref()
+            .ok_or((StatusCode::NOT_FOUND, "Voicemail is not enabled"))?;
+        let call = self
+            .logged_call(caller, room_id)
+            .await
+            .filter(|c| c.caller == caller)
+            .ok_or((StatusCode::NOT_FOUND, "No such call"))?;
+        if !matches!(call.state, CallState::Missed | CallState::Declined) {
+            return Err((StatusCode::CONFLICT, "The call was not left unanswered"));
+        }
+        if audio.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "The recording is empty"));
+        }
+
+        let left_at = unix_timestamp();
+        let scan = config.scanning.as_ref().map(|_| ScanStatus::Pending);
+        let dropped = {
+            let mut voicemails = self.voicemails.lock().await;
+            if voicemails.contains_key(room_id) {
+                return Err((StatusCode::CONFLICT, "A message was already left"));
+            }
+            let mut theirs: Vec<_> = voicemails
+                .iter()
+                .filter(|(_, v)| v.callee == call.callee)
+                .map(|(id, v)| (v.left_at, id.clone()))
+                .collect();
+            theirs.sort();
+            let excess = (theirs.len() + 1).saturating_sub(limits.max_messages.max(1));
+            let dropped: Vec<_> = theirs.into_iter().take(excess).map(|(_, id)| id).collect();
+            for id in &dropped {
+                voicemails.remove(id);
+            }
+            voicemails.insert(
+                room_id.to_string(),
+                Voicemail {
+                    caller: call.caller.clone(),
+                    callee: call.callee.clone(),
+                    content_type,
+                    audio,
+                    left_at,
+                    scan,
+                },
+            );
+            dropped
+        };
+        for old_room in dropped {
+            if let Some(old) = self.logged_call(&call.callee, &old_room).await {
+                self.mark_voicemail(&old, None, None).await;
+            }
+        }
+        self.mark_voicemail(&call, Some(left_at), scan).await;
+        info!("{} left a voice message for {}", caller, call.callee);
+
+        match scan {
+            Some(_) => {
+                self.enqueue_job(JobKind::ScanVoicemail {
+                    room_id: room_id.to_string(),
+                })
+                .await;
+            }
+            None => self.announce_voicemail(room_id, &call).await,
+        }
+        Ok(())
+    }
+
+    /// Tell the callee a voice message is waiting
+    async fn announce_voicemail(&self, room_id: &str, call: &DirectedCall) {
+        let notice = WsMessage::Voicemail {
+            room_id: room_id.to_string(),
+            from: call.caller.clone(),
+        };
+        let _ = self.notify_user(&call.callee, notice).await;
+    }
+
+    /// Scan a call's voice message, then release or quarantine it
+    pub(crate) async fn scan_voicemail(&self, room_id: &str) -> Result<(), String> {
+        let Some(config) = self.config().scanning.clone() else {
+            return Err("Scanning is not configured".to_string());
+        };
+        // Deleted messages need no scan
+        let Some(message) = self.voicemails.lock().await.get(room_id).cloned() else {
+            return Ok(());
+        };
+        let verdict = scan(&config, &self.http, &message.audio).await?;
+        let status = match &verdict {
+            Verdict::Clean => ScanStatus::Clean,
+            Verdict::Infected(_) => ScanStatus::Quarantined,
+        };
+        match self.voicemails.lock().await.get_mut(room_id) {
+            Some(stored) => stored.scan = Some(status),
+            None => return Ok(()),
+        }
+        let Some(call) = self.logged_call(&message.callee, room_id).await else {
+            return Ok(());
+        };
+        self.mark_voicemail(&call, Some(message.left_at), Some(status))
+            .await;
+        match verdict {
+            Verdict::Clean => self.announce_voicemail(room_id, &call).await,
+            Verdict::Infected(finding) => {
+                warn!(
+                    "Quarantined the voice message {} left for {}: {}",
+                    message.caller, message.callee, finding
+                );
+                self.record_audit(Some(room_id), "scanner", "voicemail.quarantine", finding)
+                    .await;
+            }
+        }
+        Ok(())
+    }
+
+    /// The voice message of a call, if `user` took part in it
+    pub async fn voicemail(&self, room_id: &str, user: &str) -> Option<Voicemail> {
+        let voicemails = self.voicemails.lock().await;
+        voicemails
+            .get(room_id)
+            .filter(|v| v.caller == user || v.callee == user)
+            .cloned()
+    }
+
+    /// Delete the voice message left for `callee`; returns false if there
+    /// is none
+    pub async fn delete_voicemail(&self, room_id: &str, callee: &str) -> bool {
+        {
+            let mut voicemails = self.voicemails.lock().await;
+            if voicemails.get(room_id).is_none_or(|v| v.callee != callee) {
+                return false;
+            }
+            voicemails.remove(room_id);
+        }
+        if let Some(call) = self.logged_call(callee, room_id).await {
+            self.mark_voicemail(&call, None, None).await;
+        }
+        true
+    }
+}
+
+/// Leave a voice message for the callee of an unanswered call
+///
+/// Only the caller may leave one, once the call was missed or declined.
+#[utoipa::path(
+    post,
+    path = "/api/calls/{room_id}/voicemail",
+    tag = "Calls",
+    params(
+        ("room_id" = String, Path, description = "The call's room")
+    ),
+    request_body(content = Vec<u8>, description = "Encoded recording", content_type = "audio/webm"),
+    responses(
+        (status = 201, description = "Message stored for the callee"),
+        (status = 400, description = "Empty recording"),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 404, description = "Voicemail not enabled, or not a call the caller placed"),
+        (status = 409, description = "The call was answered or still rings, or has a message already"),
+        (status = 413, description = "Recording too large")
+    )
+)]
+pub async fn leave_voicemail(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+    UserAuth(caller): UserAuth,
+    request: Request,
+) -> Response {
+    let Some(max_bytes) = state.config().voicemail.as_ref().map(|v| v.max_bytes) else {
+        return (StatusCode::NOT_FOUND, "Voicemail is not enabled").into_response();
+    };
+    let content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("audio/webm")
+        .to_string();
+    let audio = match axum::body::to_bytes(request.into_body(), max_bytes).await {
+        Ok(audio) => audio,
+        Err(_) => return (StatusCode::PAYLOAD_TOO_LARGE, "Recording too large").into_response(),
+    };
+    match state
+        .leave_voicemail(&caller, &room_id, content_type, audio)
+        .await
+    {
+        Ok(()) => StatusCode::CREATED.into_response(),
+        Err(rejection) => rejection.into_response(),
+    }
+}
+
+/// Fetch the voice message of a call the caller took part in
+#[utoipa::path(
+    get,
+    path = "/api/calls/{room_id}/voicemail",
+    tag = "Calls",
+    params(
+        ("room_id" = String, Path, description = "The call's room")
+    ),
+    responses(
+        (status = 200, description = "The recording, in the format it was left in", body = Vec<u8>, content_type = "audio/webm"),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 403, description = "The message was quarantined by the malware scan"),
+        (status = 404, description = "No message, or not one of the caller's calls"),
+        (status = 409, description = "The message is still being scanned")
+    )
+)]
+pub async fn get_voicemail(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+    UserAuth(user): UserAuth,
+) -> Response {
+    match state.voicemail(&room_id, &user).await {
+        Some(message) if message.scan == Some(ScanStatus::Pending) => {
+            (StatusCode::CONFLICT, "The message is still being scanned").into_response()
+        }
+        Some(message) if message.scan == Some(ScanStatus::Quarantined) => {
+            (StatusCode::FORBIDDEN, "The message was quarantined").into_response()
+        }
+        Some(message) => (
+            [(header::CONTENT_TYPE, message.content_type)],
+            message.audio,
+        )
+            .into_response(),
+        None => (StatusCode::NOT_FOUND, "No such message").into_response(),
+    }
+}
+
+/// Delete a voice message left for the caller
+#[utoipa::path(
+    delete,
+    path = "/api/calls/{room_id}/voicemail",
+    tag = "Calls",
+    params(
+        ("room_id" = String, Path, description = "The call's room")
+    ),
+    responses(
+        (status = 204, description = "Message deleted"),
+        (status = 401, description = "Missing or invalid user token"),
+        (status = 404, description = "No message left for the caller")
+    )
+)]
+pub async fn delete_voicemail(
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+    UserAuth(callee): UserAuth,
+) -> Response {
+    match state.delete_voicemail(&room_id, &callee).await {
+        true => StatusCode::NO_CONTENT.into_response(),
+        false => (StatusCode::NOT_FOUND, "No such message").into_response(),
+    }
+}
@@ -0,0 +1,515 @@
+//! Room event webhooks
+//!
+//! With `webhooks` configured, room lifecycle events (created, peer joined
+//! and left, closed) and each finished call record are posted to `url` as
+//! JSON; rooms of a tenant post to the tenant's own endpoint instead (see
+//! [`crate::tenants`]). Events go through an outbox: each is queued before
+//! it is sent and retried with exponential backoff until the endpoint
+//! answers 2xx; later events for the same endpoint wait meanwhile, so they
+//! arrive in order. Delivery is at least
+//! once, so receivers should ignore an `id` they have seen.
+//! With a record store attached, the outbox is stored as well and queued
+//! events survive a restart. Delivered events are kept for a while so they
+//! can be sent again with `POST /admin/webhooks/replay`. An event still
+//! failing after `max_attempts` is set aside as failed, so it no longer
+//! holds up the rest; `GET /admin/webhooks?status=failed` lists these with
+//! their last error, and `POST /admin/webhooks/{id}/retry` queues one
+//! again. `POST /admin/webhooks/test` sends a sample event straight away
+//! and reports the outcome. When `secret` is
+//! set, every request is signed with `X-Axi-Vid-Signature: sha256=<hex>`,
+//! the HMAC-SHA256 of the body.
+
+use std::collections::{HashSet, VecDeque};
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::admin::{AdminAuth, OperatorAuth};
+use crate::models::{
+    DeliveryStatus, WebhookDelivery, WebhookEvent, WebhookPayload, WebhookReplayRequest,
+    WebhookReplayResponse, WebhookTestResult,
+};
+use crate::state::{AppState, unix_timestamp};
+
+/// Header carrying the body's signature
+pub const SIGNATURE_HEADER: &str = "x-axi-vid-signature";
+
+/// Delivered events kept for replay; the oldest are dropped first
+pub const MAX_DELIVERED_EVENTS: usize = 10_000;
+
+/// Failed events kept for inspection; the oldest are dropped first
+pub const MAX_FAILED_EVENTS: usize = 10_000;
+
+/// How long one delivery attempt may take
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where room events are posted
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Key signing each request body
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Seconds before the first retry; doubled after each further failure
+    #[serde(default = "default_retry_secs")]
+    pub retry_secs: u64,
+    /// Longest wait between retries
+    #[serde(default = "default_max_retry_secs")]
+    pub max_retry_secs: u64,
+    /// Attempts after which an event is set aside as failed
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+}
+
+fn default_retry_secs() -> u64 {
+    5
+}
+
+fn default_max_retry_secs() -> u64 {
+    3600
+}
+
+fn default_max_attempts() -> u32 {
+    10
+}
+
+impl WebhookConfig {
+    /// Wait before the next attempt, after `attempts` failed ones
+    fn backoff(&self, attempts: u32) -> u64 {
+        let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+        self.retry_secs
+            .saturating_mul(factor)
+            .min(self.max_retry_secs)
+    }
+}
+
+/// Queued, recently delivered and failed events, oldest first
+#[derive(Debug, Default)]
+pub struct Outbox {
+    deliveries: VecDeque<WebhookDelivery>,
+}
+
+impl Outbox {
+    fn get_mut(&mut self, id: &str) -> Option<&mut WebhookDelivery> {
+        self.deliveries.iter_mut().find(|d| d.event.id == id)
+    }
+
+    /// Add a delivery, dropping the oldest delivered and failed ones past
+    /// their limits
+    fn push(&mut self, delivery: WebhookDelivery) {
+        self.deliveries.push_back(delivery);
+        self.trim(DeliveryStatus::Delivered, MAX_DELIVERED_EVENTS);
+        self.trim(DeliveryStatus::Failed, MAX_FAILED_EVENTS);
+    }
+
+    /// Drop the oldest deliveries in `status` beyond `max`
+    fn trim(&mut self, status: DeliveryStatus, max: usize) {
+        let mut excess = self
+            .deliveries
+            .iter()
+            .filter(|d| d.status == status)
+            .count()
+            .saturating_sub(max);
+        self.deliveries.retain(|d| {
+            let drop = excess > 0 && d.status == status;
+            excess -= usize::from(drop);
+            !drop
+        });
+    }
+}
+
+/// Sign `body` with `secret`, as sent in [`SIGNATURE_HEADER`]
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+impl AppState {
+    /// Queue an event about `room_id` for its webhook, if one is
+    /// configured: its tenant's, or the top-level one
+    pub async fn queue_webhook(&self, room_id: &str, payload: WebhookPayload) {
+        let config = self.config();
+        if !config.has_webhooks() {
+            return;
+        }
+        let now = unix_timestamp();
+        let (metadata, tenant) = match &payload {
+            WebhookPayload::CallEnded { call } => (call.metadata.clone(), call.tenant.clone()),
+            _ => self
+                .calls
+                .lock()
+                .await
+                .get(room_id)
+                .map(|call| (call.metadata.clone(), call.tenant.clone()))
+                .unwrap_or_default(),
+        };
+        if config.webhook_for(tenant.as_deref()).is_none() {
+            return;
+        }
+        let delivery = WebhookDelivery {
+            event: WebhookEvent {
+                id: Uuid::new_v4().to_string(),
+                room_id: room_id.to_string(),
+                at: now,
+                metadata,
+                tenant,
+                payload,
+            },
+            status: DeliveryStatus::Pending,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            delivered_at: None,
+        };
+        self.webhooks.lock().await.push(delivery.clone());
+        self.store_delivery(delivery).await;
+    }
+
+    /// Put deliveries read back from the record store, in queue order, into
+    /// the outbox
+    pub(crate) async fn restore_deliveries(&self, deliveries: Vec<WebhookDelivery>) {
+        let mut outbox = self.webhooks.lock().await;
+        for delivery in deliveries {
+            if outbox.get_mut(&delivery.event.id).is_none() {
+                outbox.push(delivery);
+            }
+        }
+    }
+
+    /// Send due deliveries in order, each endpoint stopping at its first
+    /// failure so that events arrive in the order they happened
+    ///
+    /// A delivery out of attempts is marked failed and the rest go on.
+    pub async fn deliver_webhooks(&self) {
+        let config = self.config();
+        let now = unix_timestamp();
+        let pending: Vec<(WebhookEvent, u64)> = self
+            .webhooks
+            .lock()
+            .await
+            .deliveries
+            .iter()
+            .filter(|d| d.status == DeliveryStatus::Pending)
+            .map(|d| (d.event.clone(), d.next_attempt_at))
+            .collect();
+
+        // Tenants whose earlier events are still waiting
+        let mut blocked = HashSet::new();
+        for (event, next_attempt_at) in pending {
+            if blocked.contains(&event.tenant) {
+                continue;
+            }
+            if next_attempt_at > now {
+                blocked.insert(event.tenant);
+                continue;
+            }
+            let Some(webhook) = config.webhook_for(event.tenant.as_deref()) else {
+                continue;
+            };
+            let result = self.post_webhook(webhook, &event).await;
+            let mut outbox = self.webhooks.lock().await;
+            let Some(delivery) = outbox.get_mut(&event.id) else {
+                continue;
+            };
+            delivery.attempts += 1;
+            match result {
+                Ok(()) => {
+                    debug!("Delivered webhook event {}", event.id);
+                    delivery.status = DeliveryStatus::Delivered;
+                    delivery.delivered_at = Some(unix_timestamp());
+                    delivery.last_error = None;
+                }
+                Err(e) if delivery.attempts >= webhook.max_attempts => {
+                    warn!(
+                        "Giving up on webhook event {} after {} attempts: {}",
+                        event.id, delivery.attempts, e
+                    );
+                    delivery.status = DeliveryStatus::Failed;
+                    delivery.last_error = Some(e);
+                }
+                Err(e) => {
+                    let wait = webhook.backoff(delivery.attempts);
+                    warn!(
+                        "Webhook event {} failed (attempt {}), retrying in {}s: {}",
+                        event.id, delivery.attempts, wait, e
+                    );
+                    delivery.next_attempt_at = unix_timestamp() + wait;
+                    delivery.last_error = Some(e);
+                    blocked.insert(event.tenant.clone());
+                }
+            }
+            let delivery = delivery.clone();
+            drop(outbox);
+            self.store_delivery(delivery).await;
+        }
+    }
+
+    /// Post one event
+    async fn post_webhook(
+        &self,
+        webhook: &WebhookConfig,
+        event: &WebhookEvent,
+    ) -> Result<(), String> {
+        let body = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+        let mut request = self
+            .http
+            .post(&webhook.url)
+            .header("content-type", "application/json")
+            .timeout(DELIVERY_TIMEOUT);
+        if let Some(secret) = &webhook.secret {
+            request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+        }
+        let response = request.body(body).send().await.map_err(|e| e.to_string())?;
+        match response.status().is_success() {
+            true => Ok(()),
+            false => Err(format!("Endpoint answered {}", response.status())),
+        }
+    }
+
+    /// Deliveries in the outbox, newest first, optionally only those in
+    /// `status`
+    pub async fn list_webhook_deliveries(
+        &self,
+        status: Option<DeliveryStatus>,
+    ) -> Vec<WebhookDelivery> {
+        self.webhooks
+            .lock()
+            .await
+            .deliveries
+            .iter()
+            .rev()
+            .filter(|d| status.is_none_or(|s| d.status == s))
+            .cloned()
+            .collect()
+    }
+
+    /// Queue one delivery again, whatever its status, with a fresh set of
+    /// attempts
+    pub async fn retry_webhook(&self, id: &str) -> Option<WebhookDelivery> {
+        let mut outbox = self.webhooks.lock().await;
+        let delivery = outbox.get_mut(id)?;
+        delivery.status = DeliveryStatus::Pending;
+        delivery.attempts = 0;
+        delivery.next_attempt_at = unix_timestamp();
+        delivery.delivered_at = None;
+        let delivery = delivery.clone();
+        drop(outbox);
+        self.store_delivery(delivery.clone()).await;
+        Some(delivery)
+    }
+
+    /// Post a sample event to the webhook of `tenant`, or the top-level
+    /// one, right away, bypassing the outbox
+    pub async fn test_webhook(&self, tenant: Option<&str>) -> Option<WebhookTestResult> {
+        let config = self.config();
+        let webhook = config.webhook_for(tenant)?;
+        let event = WebhookEvent {
+            id: Uuid::new_v4().to_string(),
+            room_id: "test".to_string(),
+            at: unix_timestamp(),
+            metadata: Default::default(),
+            tenant: tenant.map(str::to_string),
+            payload: WebhookPayload::Test,
+        };
+        let started = std::time::Instant::now();
+        let result = self.post_webhook(webhook, &event).await;
+        Some(WebhookTestResult {
+            event,
+            delivered: result.is_ok(),
+            error: result.err(),
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        })
+    }
+
+    /// Queue delivered events again; returns how many
+    pub async fn replay_webhooks(&self, since: u64, room_id: Option<&str>) -> usize {
+        let now = unix_timestamp();
+        let replayed: Vec<WebhookDelivery> = self
+            .webhooks
+            .lock()
+            .await
+            .deliveries
+            .iter_mut()
+            .filter(|d| {
+                d.status == DeliveryStatus::Delivered
+                    && d.event.at >= since
+                    && room_id.is_none_or(|id| d.event.room_id == id)
+            })
+            .map(|d| {
+                d.status = DeliveryStatus::Pending;
+                d.attempts = 0;
+                d.next_attempt_at = now;
+                d.delivered_at = None;
+                d.clone()
+            })
+            .collect();
+        let count = replayed.len();
+        for delivery in replayed {
+            self.store_delivery(delivery).await;
+        }
+        count
+    }
+}
+
+/// Spawn the webhook delivery task if webhooks are configured
+pub fn spawn_webhook_delivery(state: AppState) {
+    if !state.config().has_webhooks() {
+        return;
+    }
+    info!("Delivering room events to the webhook");
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            state.deliver_webhooks().await;
+        }
+    });
+}
+
+/// Query parameters for the webhook outbox
+#[derive(Debug, Deserialize)]
+pub struct WebhookQuery {
+    pub status: Option<DeliveryStatus>,
+}
+
+/// Query parameters for a sample event
+#[derive(Debug, Deserialize)]
+pub struct WebhookTestQuery {
+    pub tenant: Option<String>,
+}
+
+/// List webhook deliveries
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    tag = "Admin",
+    params(
+        ("status" = Option<DeliveryStatus>, Query, description = "Only deliveries in this state, e.g. `failed`")
+    ),
+    responses(
+        (status = 200, description = "Deliveries with their attempts and last error, newest first", body = Vec<WebhookDelivery>),
+        (status = 401, description = "Missing or invalid admin token")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn list_webhooks(
+    _auth: AdminAuth,
+    State(state): State<AppState>,
+    Query(query): Query<WebhookQuery>,
+) -> Json<Vec<WebhookDelivery>> {
+    Json(state.list_webhook_deliveries(query.status).await)
+}
+
+/// Queue a webhook delivery again
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks/{id}/retry",
+    tag = "Admin",
+    params(
+        ("id" = String, Path, description = "Event ID")
+    ),
+    responses(
+        (status = 200, description = "Delivery queued again", body = WebhookDelivery),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "No such delivery")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn retry_webhook(
+    OperatorAuth(auth): OperatorAuth,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Response {
+    let Some(delivery) = state.retry_webhook(&id).await else {
+        return (StatusCode::NOT_FOUND, "No such webhook delivery").into_response();
+    };
+    info!("Retrying webhook event {}", id);
+    state
+        .record_audit(
+            Some(&delivery.event.room_id),
+            &auth.principal,
+            "webhooks.retry",
+            &id,
+        )
+        .await;
+    Json(delivery).into_response()
+}
+
+/// Send a sample event to the webhook
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks/test",
+    tag = "Admin",
+    params(
+        ("tenant" = Option<String>, Query, description = "Send to this tenant's webhook instead of the top-level one")
+    ),
+    responses(
+        (status = 200, description = "What happened to the sample event", body = WebhookTestResult),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low"),
+        (status = 404, description = "Webhooks are not enabled")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn test_webhook(
+    OperatorAuth(auth): OperatorAuth,
+    State(state): State<AppState>,
+    Query(query): Query<WebhookTestQuery>,
+) -> Response {
+    match state.test_webhook(query.tenant.as_deref()).await {
+        Some(result) => {
+            let detail = query.tenant.unwrap_or_default();
+            state
+                .record_audit(None, &auth.principal, "webhooks.test", detail)
+                .await;
+            Json(result).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "Webhooks are not enabled").into_response(),
+    }
+}
+
+/// Send delivered webhook events again
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks/replay",
+    tag = "Admin",
+    request_body = WebhookReplayRequest,
+    responses(
+        (status = 200, description = "Events queued again", body = WebhookReplayResponse),
+        (status = 401, description = "Missing or invalid admin token"),
+        (status = 403, description = "Admin role is too low")
+    ),
+    security(("admin_token" = []))
+)]
+pub async fn replay_webhooks(
+    OperatorAuth(auth): OperatorAuth,
+    State(state): State<AppState>,
+    Json(request): Json<WebhookReplayRequest>,
+) -> Json<WebhookReplayResponse> {
+    let requeued = state
+        .replay_webhooks(request.since, request.room_id.as_deref())
+        .await;
+    info!("Replaying {} webhook events", requeued);
+    state
+        .record_audit(
+            None,
+            &auth.principal,
+            "webhooks.replay",
+            format!("{} events", requeued),
+        )
+        .await;
+    Json(WebhookReplayResponse { requeued })
+}
@@ -0,0 +1,261 @@
+/**
+ * Operator dashboard
+ *
+ * Polls the admin API with the token the operator signs in with, kept for
+ * the browser tab only, and renders rooms, peers, quality, failed jobs and
+ * webhook deliveries.
+ */
+
+(function() {
+    'use strict';
+
+    const REFRESH_MS = 5000;
+    // MOS samples kept for the graph, one per refresh
+    const MOS_HISTORY = 60;
+
+    const elements = {
+        loginForm: document.getElementById('login-form'),
+        tokenInput: document.getElementById('admin-token'),
+        loginError: document.getElementById('login-error'),
+        logoutBtn: document.getElementById('logout-btn'),
+        dashboard: document.getElementById('dashboard'),
+        updated: document.getElementById('admin-updated'),
+        rooms: document.getElementById('rooms'),
+        noRooms: document.getElementById('no-rooms'),
+        roomPanel: document.getElementById('room-panel'),
+        roomTitle: document.getElementById('room-title'),
+        roomSummary: document.getElementById('room-summary'),
+        mosLine: document.getElementById('mos-line'),
+        peers: document.getElementById('peers'),
+        jobs: document.getElementById('jobs'),
+        noJobs: document.getElementById('no-jobs'),
+        webhooks: document.getElementById('webhooks'),
+        noWebhooks: document.getElementById('no-webhooks'),
+    };
+
+    let token = sessionStorage.getItem('adminToken');
+    let selectedRoom = null;
+    let mosHistory = [];
+    let timer = null;
+
+    async function api(path, options = {}) {
+        const response = await fetch(path, {
+            ...options,
+            headers: { 'Authorization': `Bearer ${token}`, ...(options.headers || {}) },
+        });
+        if (response.status === 401) {
+            signOut('That token was not accepted');
+            throw new Error('unauthorized');
+        }
+        if (!response.ok) {
+            throw new Error(`${path}: ${response.status}`);
+        }
+        return response.status === 204 ? null : response.json();
+    }
+
+    /** A table cell holding text; data never goes through innerHTML */
+    function cell(text) {
+        const td = document.createElement('td');
+        td.textContent = text === null || text === undefined ? '' : String(text);
+        return td;
+    }
+
+    function buttonCell(label, onClick, danger) {
+        const td = document.createElement('td');
+        const button = document.createElement('button');
+        button.className = danger ? 'btn btn-small btn-danger' : 'btn btn-small';
+        button.textContent = label;
+        button.addEventListener('click', onClick);
+        td.appendChild(button);
+        return td;
+    }
+
+    function fill(tbody, empty, rows) {
+        tbody.replaceChildren(...rows);
+        empty.classList.toggle('hidden', rows.length > 0);
+    }
+
+    async function refreshRooms() {
+        const rooms = await api('/admin/rooms');
+        fill(elements.rooms, elements.noRooms, rooms.map(room => {
+            const tr = document.createElement('tr');
+            const tags = Object.entries(room.metadata || {}).map(([k, v]) => `${k}=${v}`).join(', ');
+            tr.append(
+                cell(room.room_id),
+                cell(room.mode),
+                cell(room.peer_count),
+                cell(tags),
+            );
+            const actions = buttonCell('Details', () => selectRoom(room.room_id));
+            const close = buttonCell('Close', () => closeRoom(room.room_id), true);
+            actions.append(...close.childNodes);
+            tr.appendChild(actions);
+            return tr;
+        }));
+        if (selectedRoom && !rooms.some(r => r.room_id === selectedRoom)) {
+            selectRoom(null);
+        }
+    }
+
+    async function refreshRoom() {
+        if (!selectedRoom) {
+            return;
+        }
+        const room = await api(`/admin/rooms/${encodeURIComponent(selectedRoom)}`);
+        elements.roomTitle.textContent = room.room_id;
+        const flags = Object.entries(room.features).filter(([, on]) => on).map(([name]) => name);
+        elements.roomSummary.textContent = [
+            `${room.mode}`,
+            `dial-in ${room.dial_code}`,
+            room.recording ? 'recording' : null,
+            room.sharing ? `${room.sharing} is sharing` : null,
+            room.waiting ? `${room.waiting} waiting` : null,
+            `features: ${flags.join(', ') || 'none'}`,
+        ].filter(Boolean).join(' · ');
+
+        const scores = room.peers.map(p => p.quality && p.quality.mos).filter(m => m);
+        if (scores.length) {
+            mosHistory.push(Math.min(...scores));
+            mosHistory = mosHistory.slice(-MOS_HISTORY);
+        }
+        drawMos();
+
+        elements.peers.replaceChildren(...room.peers.map(peer => {
+            const tr = document.createElement('tr');
+            const device = peer.device
+                ? [peer.device.browser, peer.device.os, peer.device.camera_label].filter(Boolean).join(' / ')
+                : '';
+            const q = peer.quality;
+            tr.append(
+                cell(peer.peer_id),
+                cell(peer.role),
+                cell(peer.identity),
+                cell(device),
+                cell(q ? q.mos.toFixed(2) : ''),
+                cell(q ? `${Math.round(q.rtt_ms)} ms` : ''),
+                cell(q ? `${q.packet_loss.toFixed(1)}%` : ''),
+                buttonCell('Kick', () => kickPeer(room.room_id, peer.peer_id), true),
+            );
+            return tr;
+        }));
+    }
+
+    function drawMos() {
+        // 1.0 at the bottom of the graph, 4.5 at the top
+        const points = mosHistory.map((mos, i) => {
+            const x = (i / Math.max(MOS_HISTORY - 1, 1)) * 300;
+            const y = 60 - ((mos - 1) / 3.5) * 60;
+            return `${x.toFixed(1)},${y.toFixed(1)}`;
+        });
+        elements.mosLine.setAttribute('points', points.join(' '));
+    }
+
+    async function refreshJobs() {
+        const jobs = await api('/admin/jobs?status=failed');
+        fill(elements.jobs, elements.noJobs, jobs.map(job => {
+            const tr = document.createElement('tr');
+            tr.append(
+                cell(job.id),
+                cell(job.type),
+                cell(`${job.attempts}/${job.max_attempts}`),
+                cell(job.last_error),
+                buttonCell('Retry', () => act(`/admin/jobs/${encodeURIComponent(job.id)}/retry`, 'POST')),
+            );
+            return tr;
+        }));
+    }
+
+    async function refreshWebhooks() {
+        const deliveries = await api('/admin/webhooks');
+        fill(elements.webhooks, elements.noWebhooks, deliveries.slice(0, 20).map(d => {
+            const tr = document.createElement('tr');
+            tr.append(
+                cell(d.event.type),
+                cell(d.event.room_id),
+                cell(d.status),
+                cell(d.attempts),
+                cell(d.last_error),
+            );
+            return tr;
+        }));
+    }
+
+    async function refresh() {
+        try {
+            // Webhooks and jobs are optional features; their failures do not hide the rooms
+            await refreshRooms();
+            await refreshRoom();
+            await Promise.allSettled([refreshJobs(), refreshWebhooks()]);
+            elements.updated.textContent = `Updated ${new Date().toLocaleTimeString()}`;
+        } catch (e) {
+            if (e.message !== 'unauthorized') {
+                elements.updated.textContent = `Update failed: ${e.message}`;
+            }
+        }
+    }
+
+    async function act(path, method) {
+        try {
+            await api(path, { method });
+        } catch (e) {
+            if (e.message !== 'unauthorized') {
+                alert(`Failed: ${e.message}`);
+            }
+        }
+        refresh();
+    }
+
+    function selectRoom(roomId) {
+        selectedRoom = roomId;
+        mosHistory = [];
+        drawMos();
+        elements.roomPanel.classList.toggle('hidden', !roomId);
+        refresh();
+    }
+
+    function closeRoom(roomId) {
+        if (confirm(`Close room ${roomId} and disconnect everyone in it?`)) {
+            act(`/admin/rooms/${encodeURIComponent(roomId)}`, 'DELETE');
+        }
+    }
+
+    function kickPeer(roomId, peerId) {
+        if (confirm(`Remove ${peerId} from the room?`)) {
+            act(`/admin/rooms/${encodeURIComponent(roomId)}/peers/${encodeURIComponent(peerId)}`, 'DELETE');
+        }
+    }
+
+    function signIn() {
+        elements.loginForm.classList.add('hidden');
+        elements.dashboard.classList.remove('hidden');
+        elements.logoutBtn.classList.remove('hidden');
+        refresh();
+        timer = setInterval(refresh, REFRESH_MS);
+    }
+
+    function signOut(error) {
+        token = null;
+        sessionStorage.removeItem('adminToken');
+        clearInterval(timer);
+        elements.dashboard.classList.add('hidden');
+        elements.logoutBtn.classList.add('hidden');
+        elements.loginForm.classList.remove('hidden');
+        elements.loginError.textContent = error || '';
+        elements.loginError.classList.toggle('hidden', !error);
+    }
+
+    elements.loginForm.addEventListener('submit', (e) => {
+        e.preventDefault();
+        token = elements.tokenInput.value.trim();
+        sessionStorage.setItem('adminToken', token);
+        elements.tokenInput.value = '';
+        signIn();
+    });
+    elements.logoutBtn.addEventListener('click', () => signOut());
+
+    if (token) {
+        signIn();
+    } else {
+        signOut();
+    }
+})();
@@ -19,7 +19,12 @@
             audio: true
         },
         reconnectAttempts: 5,
-        reconnectDelay: 1000
+        reconnectDelay: 1000,
+        qualityReportInterval: 10000,
+        // Frequent enough for the server to follow who is speaking
+        audioLevelInterval: 500,
+        // How long a failed connection waits for an ICE restart before relaying
+        relayFallbackDelay: 15000
     };
 
     // State
@@ -32,6 +37,28 @@
     let reconnectAttempts = 0;
     let isCallActive = false;
     let isCaller = false;
+    let qualityTimer = null;
+    let audioLevelTimer = null;
+    let ownPeerId = null;
+    // Re-invite link token on first connect, then our own resume token
+    let joinToken = new URLSearchParams(window.location.search).get('token');
+    // Signaling URL handed over by a draining server
+    let redirectUrl = null;
+    let lastPacketCounts = null;
+    // Hidden recorder peers in the room, and our send-only connections to them
+    const recorders = new Set();
+    const recorderConnections = new Map();
+    // Whether the room agreed to be recorded; recorders get no media before
+    let recordingActive = false;
+    // Candidates the server will not relay; sent after welcome when restricted
+    let icePolicy = { drop_mdns: false, drop_host: false, relay_only: false };
+    // Codecs the room standardizes on, from `room_info`
+    let codecPolicy = null;
+    // The server's media relay offer, and the relay in use once ICE gives up
+    let relayOffer = null;
+    let mediaRelay = null;
+    // ICE restarts ordered since the connection last worked
+    let iceRestarts = 0;
 
     // DOM Elements
     const elements = {
@@ -40,6 +67,8 @@
         connectionStatus: document.getElementById('connection-status'),
         localVideo: document.getElementById('local-video'),
         remoteVideo: document.getElementById('remote-video'),
+        localVideoWrapper: document.getElementById('local-video-wrapper'),
+        remoteVideoWrapper: document.getElementById('remote-video-wrapper'),
         remoteStatus: document.getElementById('remote-status'),
         startCallBtn: document.getElementById('start-call-btn'),
         toggleAudioBtn: document.getElementById('toggle-audio-btn'),
@@ -50,6 +79,7 @@
         sendChatBtn: document.getElementById('send-chat-btn'),
         toggleChatBtn: document.getElementById('toggle-chat-btn'),
         chatContainer: document.getElementById('chat-container'),
+        chatSection: document.getElementById('chat-section'),
         waitingBanner: document.getElementById('waiting-banner'),
         permissionOverlay: document.getElementById('permission-overlay'),
         permissionErrorMsg: document.getElementById('permission-error-msg'),
@@ -66,9 +96,22 @@
 
         elements.roomIdDisplay.textContent = `Room: ${roomId.substring(0, 8)}...`;
         setupEventListeners();
+        loadIceServers();
         connectWebSocket(roomId);
     }
 
+    // Use the server's STUN/TURN list, keeping the defaults if it is unavailable
+    async function loadIceServers() {
+        try {
+            const response = await fetch('/api/ice-servers');
+            if (response.ok) {
+                CONFIG.iceServers = await response.json();
+            }
+        } catch (e) {
+            console.warn('Failed to load ICE servers, using defaults:', e);
+        }
+    }
+
     // Setup event listeners
     function setupEventListeners() {
         elements.copyLinkBtn.addEventListener('click', copyRoomLink);
@@ -90,7 +133,9 @@
     // WebSocket connection
     function connectWebSocket(roomId) {
         const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-        const wsUrl = `${protocol}//${window.location.host}/ws/${roomId}`;
+        const query = joinToken ? `?token=${encodeURIComponent(joinToken)}` : '';
+        const base = redirectUrl || `${protocol}//${window.location.host}/ws/${roomId}`;
+        const wsUrl = `${base}${query}`;
 
         setStatus('Connecting...', 'connecting');
         ws = new WebSocket(wsUrl);
@@ -135,6 +180,29 @@
         console.log('Received:', msg.type);
 
         switch (msg.type) {
+            case 'welcome':
+                joinToken = msg.resume_token;
+                ownPeerId = msg.peer_id;
+                sendDeviceInfo();
+                break;
+            case 'ice_policy':
+                icePolicy = msg.policy;
+                break;
+            case 'media_relay':
+                relayOffer = msg;
+                break;
+            case 'peer_reconnecting':
+                handlePeerReconnecting(msg);
+                break;
+            case 'redirect':
+                // The server is shutting down; the call carries on elsewhere
+                redirectUrl = msg.url;
+                joinToken = msg.resume_token;
+                reconnectAttempts = 0;
+                break;
+            case 'call_ended':
+                handleCallEnded(msg);
+                break;
             case 'room_info':
                 handleRoomInfo(msg);
                 break;
@@ -142,16 +210,36 @@
                 handlePeerJoined();
                 break;
             case 'leave':
-                handlePeerLeft();
+                handlePeerLeft(msg);
                 break;
             case 'offer':
                 handleOffer(msg);
                 break;
             case 'answer':
-                handleAnswer(msg);
+                if (recorderConnections.has(msg.peer_id)) {
+                    handleRecorderAnswer(msg);
+                } else {
+                    handleAnswer(msg);
+                }
                 break;
             case 'ice':
-                handleIceCandidate(msg);
+                if (recorderConnections.has(msg.peer_id)) {
+                    handleRecorderIceCandidate(msg);
+                } else {
+                    handleIceCandidate(msg);
+                }
+                break;
+            case 'consent_request':
+                handleConsentRequest();
+                break;
+            case 'recording':
+                handleRecording(msg);
+                break;
+            case 'recorder_joined':
+                handleRecorderJoined(msg);
+                break;
+            case 'recorder_left':
+                handleRecorderLeft(msg);
                 break;
             case 'chat':
                 handleChatMessage(msg);
@@ -159,13 +247,39 @@
             case 'media_status':
                 handleMediaStatus(msg);
                 break;
+            case 'device_info':
+                handleDeviceInfo(msg);
+                break;
+            case 'ice_restart':
+                handleIceRestart(msg);
+                break;
+            case 'active_speaker':
+                handleActiveSpeaker(msg);
+                break;
+            case 'diagnostic_hint':
+                addSystemMessage(msg.suggestion);
+                break;
             case 'error':
                 handleError(msg);
                 break;
         }
     }
 
+    // Outline whichever tile the server says is speaking
+    function handleActiveSpeaker(msg) {
+        const local = msg.peer_id === ownPeerId;
+        elements.localVideoWrapper.classList.toggle('speaking', local);
+        elements.remoteVideoWrapper.classList.toggle('speaking', !local);
+    }
+
     function handleRoomInfo(msg) {
+        if (msg.codecs) {
+            codecPolicy = msg.codecs;
+        }
+        // Feature flags decide which parts of the page the room offers
+        if (msg.features) {
+            elements.chatSection.classList.toggle('hidden', !msg.features.chat);
+        }
         const peerCount = msg.peer_count;
         if (peerCount === 1) {
             setStatus('Waiting for peer...', 'waiting');
@@ -189,15 +303,24 @@
         }
     }
 
-    function handlePeerLeft() {
+    const LEAVE_REASONS = {
+        user_hangup: 'Peer has left the room',
+        network_timeout: 'Peer lost their connection',
+        kicked: 'Peer was removed from the room',
+        server_shutdown: 'Server is shutting down',
+        room_expired: 'Call reached its maximum duration'
+    };
+
+    function handlePeerLeft(msg) {
         setStatus('Peer left', 'waiting');
-        addSystemMessage('Peer has left the room');
+        addSystemMessage(LEAVE_REASONS[msg.reason] || 'Peer has left the room');
         elements.remoteStatus.textContent = '';
 
         if (peerConnection) {
             peerConnection.close();
             peerConnection = null;
         }
+        stopMediaRelay();
 
         elements.remoteVideo.srcObject = null;
         remoteStream = null;
@@ -261,6 +384,96 @@
         }
     }
 
+    function handleConsentRequest() {
+        const granted = window.confirm('Someone wants to record this call. Do you agree?');
+        sendMessage({ type: 'consent_response', granted });
+    }
+
+    function handleRecording(msg) {
+        recordingActive = msg.active;
+        if (!recordingActive) {
+            recorders.clear();
+            recorderConnections.forEach((_, recorderId) => closeRecorder(recorderId));
+        }
+    }
+
+    // Recorders get our local media over a separate send-only connection,
+    // once the room consented to recording
+    function handleRecorderJoined(msg) {
+        if (!recordingActive) {
+            return;
+        }
+        recorders.add(msg.peer_id);
+        addSystemMessage('This call is being recorded');
+        if (localStream) {
+            connectRecorder(msg.peer_id);
+        }
+    }
+
+    function handleRecorderLeft(msg) {
+        recorders.delete(msg.peer_id);
+        closeRecorder(msg.peer_id);
+        addSystemMessage('Recording stopped');
+    }
+
+    async function connectRecorder(recorderId) {
+        closeRecorder(recorderId);
+        const pc = new RTCPeerConnection(rtcConfiguration());
+        recorderConnections.set(recorderId, pc);
+        localStream.getTracks().forEach(track => {
+            pc.addTransceiver(track, { direction: 'sendonly', streams: [localStream] });
+        });
+        pc.onicecandidate = (event) => {
+            if (event.candidate && candidateAllowed(event.candidate)) {
+                sendMessage({
+                    type: 'ice',
+                    candidate: event.candidate.candidate,
+                    sdpMLineIndex: event.candidate.sdpMLineIndex,
+                    sdpMid: event.candidate.sdpMid,
+                    peer_id: recorderId
+                });
+            }
+        };
+
+        try {
+            const offer = await pc.createOffer();
+            await pc.setLocalDescription(offer);
+            sendMessage({ type: 'offer', sdp: offer.sdp, peer_id: recorderId });
+        } catch (e) {
+            console.error('Error offering to recorder:', e);
+        }
+    }
+
+    function closeRecorder(recorderId) {
+        const pc = recorderConnections.get(recorderId);
+        if (pc) {
+            pc.close();
+            recorderConnections.delete(recorderId);
+        }
+    }
+
+    async function handleRecorderAnswer(msg) {
+        try {
+            await recorderConnections.get(msg.peer_id).setRemoteDescription(
+                new RTCSessionDescription({ type: 'answer', sdp: msg.sdp })
+            );
+        } catch (e) {
+            console.error('Error handling recorder answer:', e);
+        }
+    }
+
+    async function handleRecorderIceCandidate(msg) {
+        try {
+            await recorderConnections.get(msg.peer_id).addIceCandidate(new RTCIceCandidate({
+                candidate: msg.candidate,
+                sdpMLineIndex: msg.sdpMLineIndex,
+                sdpMid: msg.sdpMid
+            }));
+        } catch (e) {
+            console.error('Error adding recorder ICE candidate:', e);
+        }
+    }
+
     function handleChatMessage(msg) {
         addChatMessage(msg.message, false);
     }
@@ -272,6 +485,72 @@
         elements.remoteStatus.textContent = status.length ? status.join(', ') : '';
     }
 
+    function handleDeviceInfo(msg) {
+        if (/android|ios/i.test(msg.os || '')) {
+            addSystemMessage('Peer is on a mobile device');
+        }
+    }
+
+    // Tell the other side which camera, microphone and platform we use
+    function sendDeviceInfo() {
+        const label = (kind) => {
+            const track = localStream && localStream.getTracks().find(t => t.kind === kind);
+            return track ? track.label : undefined;
+        };
+        const ua = navigator.userAgent;
+        const os = /Android/.test(ua) ? 'Android'
+            : /iPhone|iPad|iPod/.test(ua) ? 'iOS'
+            : /Windows/.test(ua) ? 'Windows'
+            : /Mac OS X/.test(ua) ? 'macOS'
+            : /Linux/.test(ua) ? 'Linux' : undefined;
+        const browser = /Edg\//.test(ua) ? 'Edge'
+            : /Firefox\//.test(ua) ? 'Firefox'
+            : /Chrome\//.test(ua) ? 'Chrome'
+            : /Safari\//.test(ua) ? 'Safari' : undefined;
+        sendMessage({
+            type: 'device_info',
+            camera_label: label('video'),
+            mic_label: label('audio'),
+            os,
+            browser
+        });
+    }
+
+    function handlePeerReconnecting(msg) {
+        setStatus('Peer reconnecting...', 'waiting');
+        addSystemMessage(`Peer lost their connection, waiting ${msg.grace_secs}s for them to return`);
+
+        // The peer will rejoin and we re-offer from handlePeerJoined
+        if (peerConnection) {
+            peerConnection.close();
+            peerConnection = null;
+        }
+        remoteStream = null;
+        elements.remoteVideo.srcObject = null;
+    }
+
+    async function handleCallEnded(msg) {
+        setStatus('Call ended', 'disconnected');
+        if (msg.redirect) {
+            // Hand the page's own token on, so the rejoin button works
+            const token = new URLSearchParams(window.location.search).get('token');
+            const query = token ? `&token=${encodeURIComponent(token)}` : '';
+            window.location.href = msg.redirect + query;
+            return;
+        }
+        if (!msg.can_redial) return;
+
+        try {
+            const response = await fetch(`/api/room/${window.ROOM_ID}/reinvite`, { method: 'POST' });
+            if (response.ok) {
+                const invite = await response.json();
+                addSystemMessage(`Peer did not return. Send them this link to call back: ${invite.url}`);
+            }
+        } catch (e) {
+            console.error('Failed to create re-invite:', e);
+        }
+    }
+
     function handleError(msg) {
         console.error('Server error:', msg.message);
         setStatus(`Error: ${msg.message}`, 'error');
@@ -283,9 +562,13 @@
         try {
             localStream = await navigator.mediaDevices.getUserMedia(CONFIG.mediaConstraints);
             elements.localVideo.srcObject = localStream;
+            if (recordingActive) {
+                recorders.forEach(connectRecorder);
+            }
             return true;
         } catch (e) {
             console.error('Error getting media:', e);
+            reportClientError('get_user_media', `${e.name}: ${e.message}`);
             elements.permissionErrorMsg.textContent = getMediaErrorMessage(e);
             elements.permissionOverlay.classList.remove('hidden');
             return false;
@@ -305,13 +588,73 @@
         }
     }
 
+    // Report a call-setup failure to the server for debugging
+    function reportClientError(kind, message, details) {
+        fetch('/api/client-errors', {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({
+                kind,
+                message,
+                room_id: window.ROOM_ID,
+                browser: navigator.userAgent,
+                details: details || null
+            })
+        }).catch(() => {});
+    }
+
     // Create peer connection
+    // RTCPeerConnection settings honouring the server's ICE policy
+    function rtcConfiguration() {
+        return {
+            iceServers: CONFIG.iceServers,
+            iceTransportPolicy: icePolicy.relay_only ? 'relay' : 'all'
+        };
+    }
+
+    // Skip candidates the server would drop anyway
+    function candidateAllowed(candidate) {
+        if (candidate.type === 'relay') {
+            return true;
+        }
+        if (icePolicy.relay_only) {
+            return false;
+        }
+        if (candidate.type === 'host') {
+            return !icePolicy.drop_host &&
+                !(icePolicy.drop_mdns && (candidate.address || '').endsWith('.local'));
+        }
+        return true;
+    }
+
+    // Put the room's preferred codecs first where the browser allows it;
+    // the server reorders relayed SDP either way
+    function applyCodecPreferences(pc) {
+        const preferred = (codecPolicy && codecPolicy.preferred) || [];
+        if (!preferred.length || !window.RTCRtpReceiver || !RTCRtpReceiver.getCapabilities) {
+            return;
+        }
+        const rank = (codec) => {
+            const name = codec.mimeType.split('/')[1].toLowerCase();
+            const index = preferred.findIndex(p => p.toLowerCase() === name);
+            return index < 0 ? preferred.length : index;
+        };
+        pc.getTransceivers().forEach(transceiver => {
+            const capabilities = RTCRtpReceiver.getCapabilities(transceiver.receiver.track.kind);
+            if (!capabilities || !transceiver.setCodecPreferences) {
+                return;
+            }
+            const codecs = capabilities.codecs.slice().sort((a, b) => rank(a) - rank(b));
+            transceiver.setCodecPreferences(codecs);
+        });
+    }
+
     function createPeerConnection() {
         if (peerConnection) {
             peerConnection.close();
         }
 
-        peerConnection = new RTCPeerConnection({ iceServers: CONFIG.iceServers });
+        peerConnection = new RTCPeerConnection(rtcConfiguration());
 
         // Add local tracks
         if (localStream) {
@@ -319,10 +662,11 @@
                 peerConnection.addTrack(track, localStream);
             });
         }
+        applyCodecPreferences(peerConnection);
 
         // Handle ICE candidates
         peerConnection.onicecandidate = (event) => {
-            if (event.candidate) {
+            if (event.candidate && candidateAllowed(event.candidate)) {
                 sendMessage({
                     type: 'ice',
                     candidate: event.candidate.candidate,
@@ -345,27 +689,107 @@
         // Handle connection state changes
         peerConnection.onconnectionstatechange = () => {
             console.log('Connection state:', peerConnection.connectionState);
+            // The server orders an ICE restart if the connection stays down
+            sendMessage({ type: 'connection_state', state: peerConnection.connectionState });
             switch (peerConnection.connectionState) {
                 case 'connected':
                     setStatus('Call connected', 'connected');
+                    iceRestarts = 0;
+                    stopMediaRelay();
+                    startQualityReports();
                     break;
                 case 'disconnected':
                     setStatus('Call disconnected', 'disconnected');
                     break;
                 case 'failed':
                     setStatus('Connection failed', 'error');
-                    handleConnectionFailure();
+                    reportClientError('ice_failure', 'Peer connection failed', {
+                        iceConnectionState: peerConnection.iceConnectionState
+                    });
+                    // Once a restart has not helped either, carry the call over the server
+                    setTimeout(() => {
+                        if (peerConnection && peerConnection.connectionState === 'failed') {
+                            startMediaRelay();
+                        }
+                    }, iceRestarts > 0 ? 0 : CONFIG.relayFallbackDelay);
                     break;
             }
         };
+    }
 
-        // Handle ICE connection state
-        peerConnection.oniceconnectionstatechange = () => {
-            console.log('ICE state:', peerConnection.iceConnectionState);
-            if (peerConnection.iceConnectionState === 'failed') {
-                handleConnectionFailure();
+    // Periodically report RTT, jitter and loss for server-side MOS scoring,
+    // and the microphone level for active speaker detection
+    function startQualityReports() {
+        stopQualityReports();
+        qualityTimer = setInterval(reportQualityStats, CONFIG.qualityReportInterval);
+        audioLevelTimer = setInterval(reportAudioLevel, CONFIG.audioLevelInterval);
+    }
+
+    function stopQualityReports() {
+        clearInterval(qualityTimer);
+        clearInterval(audioLevelTimer);
+        qualityTimer = null;
+        audioLevelTimer = null;
+        lastPacketCounts = null;
+        elements.localVideoWrapper.classList.remove('speaking');
+        elements.remoteVideoWrapper.classList.remove('speaking');
+    }
+
+    async function reportAudioLevel() {
+        // A muted microphone is silent on purpose
+        if (!peerConnection || !isAudioEnabled) return;
+
+        const stats = await peerConnection.getStats();
+        stats.forEach(report => {
+            if (report.type === 'media-source' && report.kind === 'audio' &&
+                report.audioLevel !== undefined) {
+                sendMessage({ type: 'audio_level', level: report.audioLevel });
             }
-        };
+        });
+    }
+
+    async function reportQualityStats() {
+        if (!peerConnection) return;
+
+        let rttMs = null;
+        let jitterMs = 0;
+        let received = 0;
+        let lost = 0;
+        const mediaSent = { audio: 0, video: 0 };
+        const mediaReceived = { audio: 0, video: 0 };
+        const stats = await peerConnection.getStats();
+        stats.forEach(report => {
+            if (report.type === 'candidate-pair' && report.nominated &&
+                report.currentRoundTripTime !== undefined) {
+                rttMs = report.currentRoundTripTime * 1000;
+            } else if (report.type === 'inbound-rtp' && report.kind in mediaReceived) {
+                mediaReceived[report.kind] += report.bytesReceived || 0;
+                if (report.kind === 'audio') {
+                    jitterMs = (report.jitter || 0) * 1000;
+                    received += report.packetsReceived || 0;
+                    lost += report.packetsLost || 0;
+                }
+            } else if (report.type === 'outbound-rtp' && report.kind in mediaSent) {
+                mediaSent[report.kind] += report.bytesSent || 0;
+            }
+        });
+        sendMessage({ type: 'media_stats', sent: mediaSent, received: mediaReceived });
+        if (rttMs === null) return;
+
+        // Loss over the last interval rather than the whole call
+        const prev = lastPacketCounts || { received: 0, lost: 0 };
+        const deltaReceived = received - prev.received;
+        const deltaLost = lost - prev.lost;
+        lastPacketCounts = { received, lost };
+        const total = deltaReceived + deltaLost;
+        const packetLoss = total > 0 ? Math.max(0, deltaLost) / total * 100 : 0;
+
+        sendMessage({
+            type: 'quality_stats',
+            rtt_ms: rttMs,
+            jitter_ms: jitterMs,
+            packet_loss: packetLoss
+        });
     }
 
     // Create and send offer
@@ -386,18 +810,194 @@
         }
     }
 
-    // Handle connection failure
-    function handleConnectionFailure() {
-        if (isCallActive && peerConnection) {
-            console.log('Attempting to reconnect...');
-            // Trigger ICE restart
-            peerConnection.restartIce();
-            if (isCaller) {
-                createOffer();
+    // Restart ICE when the server finds the connection dead; only the
+    // designated offerer sends a new offer
+    function handleIceRestart(msg) {
+        if (!peerConnection) return;
+        console.log('Restarting ICE...');
+        setStatus('Reconnecting...', 'waiting');
+        CONFIG.iceServers = msg.ice_servers;
+        iceRestarts++;
+        peerConnection.setConfiguration(rtcConfiguration());
+        peerConnection.restartIce();
+        isCaller = msg.offerer;
+        if (msg.offerer) {
+            createOffer();
+        }
+    }
+
+    // Media relay fallback. Frames are one stream ID byte and a payload:
+    // stream 0 carries our ECDH public key, stream 1 pieces of an encrypted
+    // MediaRecorder chunk, the first payload byte marking the last piece.
+    const RELAY_KEY = 0;
+    const RELAY_MEDIA = 1;
+    const RELAY_MIME = 'video/webm;codecs=vp8,opus';
+    // MediaRecorder chunk length, in milliseconds
+    const RELAY_TIMESLICE = 200;
+
+    async function startMediaRelay() {
+        if (mediaRelay || !relayOffer || !window.MediaSource ||
+            !MediaRecorder.isTypeSupported(RELAY_MIME)) {
+            return;
+        }
+        console.log('Falling back to the media relay');
+        const url = new URL(relayOffer.url, redirectUrl || ws.url);
+        url.search = `?token=${encodeURIComponent(joinToken)}`;
+        const relay = {
+            socket: null,
+            keys: null,
+            publicKey: null,
+            key: null,
+            // Whether the other end's key arrived, and the chunk queue kept in order
+            keyed: false,
+            sealing: Promise.resolve(),
+            opening: Promise.resolve(),
+            recorder: null,
+            pieces: [],
+            source: null,
+            buffer: null,
+            pending: []
+        };
+        mediaRelay = relay;
+        relay.keys = await crypto.subtle.generateKey(
+            { name: 'ECDH', namedCurve: 'P-256' }, false, ['deriveKey']);
+        relay.publicKey = new Uint8Array(await crypto.subtle.exportKey('raw', relay.keys.publicKey));
+        if (mediaRelay !== relay) return;
+        relay.socket = new WebSocket(url);
+        relay.socket.binaryType = 'arraybuffer';
+        relay.socket.onopen = () => sendRelayFrame(relay, RELAY_KEY, relay.publicKey);
+        relay.socket.onmessage = (event) => handleRelayFrame(relay, new Uint8Array(event.data));
+        relay.socket.onclose = () => {
+            if (mediaRelay === relay) stopMediaRelay();
+        };
+        setStatus('Connected through the server', 'connected');
+        addSystemMessage('Direct connection failed; the call is relayed through the server');
+    }
+
+    function stopMediaRelay() {
+        const relay = mediaRelay;
+        if (!relay) return;
+        mediaRelay = null;
+        if (relay.recorder && relay.recorder.state !== 'inactive') {
+            relay.recorder.stop();
+        }
+        if (relay.socket) {
+            relay.socket.close();
+        }
+        if (relay.source) {
+            URL.revokeObjectURL(elements.remoteVideo.src);
+            elements.remoteVideo.removeAttribute('src');
+            elements.remoteVideo.srcObject = remoteStream;
+        }
+    }
+
+    function sendRelayFrame(relay, stream, payload) {
+        if (!relay.socket || relay.socket.readyState !== WebSocket.OPEN) return;
+        const frame = new Uint8Array(payload.length + 1);
+        frame[0] = stream;
+        frame.set(payload, 1);
+        relay.socket.send(frame);
+    }
+
+    async function handleRelayFrame(relay, frame) {
+        const payload = frame.subarray(1);
+        if (frame[0] === RELAY_KEY) {
+            // The first key from the other end; repeat ours in case it
+            // attached after we sent it
+            if (relay.keyed) return;
+            relay.keyed = true;
+            const theirs = await crypto.subtle.importKey(
+                'raw', payload, { name: 'ECDH', namedCurve: 'P-256' }, false, []);
+            relay.key = await crypto.subtle.deriveKey(
+                { name: 'ECDH', public: theirs }, relay.keys.privateKey,
+                { name: 'AES-GCM', length: 256 }, false, ['encrypt', 'decrypt']);
+            sendRelayFrame(relay, RELAY_KEY, relay.publicKey);
+            // Without a call of our own we only receive
+            if (localStream) {
+                startRelayRecorder(relay);
             }
+        } else if (frame[0] === RELAY_MEDIA && relay.key) {
+            relay.pieces.push(payload.slice(1));
+            if (payload[0] !== 1) return;
+            const sealed = concatBytes(relay.pieces);
+            relay.pieces = [];
+            relay.opening = relay.opening.then(async () => {
+                try {
+                    const chunk = await crypto.subtle.decrypt(
+                        { name: 'AES-GCM', iv: sealed.subarray(0, 12) }, relay.key, sealed.subarray(12));
+                    playRelayChunk(relay, chunk);
+                } catch (e) {
+                    // A piece was dropped on the way
+                    console.warn('Dropping damaged relay chunk');
+                }
+            });
         }
     }
 
+    // Record the local stream and send it in pieces the relay accepts
+    function startRelayRecorder(relay) {
+        // Leave headroom under the cap for the framing and the key exchange
+        const bitsPerSecond = relayOffer.max_bitrate_kbps * 1000 * 0.8;
+        relay.recorder = new MediaRecorder(localStream, {
+            mimeType: RELAY_MIME,
+            audioBitsPerSecond: Math.min(32000, bitsPerSecond / 4),
+            videoBitsPerSecond: bitsPerSecond - Math.min(32000, bitsPerSecond / 4)
+        });
+        // Stream ID and last-piece marker take two bytes of every frame
+        const pieceBytes = relayOffer.max_frame_bytes - 2;
+        relay.recorder.ondataavailable = (event) => {
+            if (!event.data.size) return;
+            relay.sealing = relay.sealing.then(async () => {
+                const iv = crypto.getRandomValues(new Uint8Array(12));
+                const ciphertext = new Uint8Array(await crypto.subtle.encrypt(
+                    { name: 'AES-GCM', iv }, relay.key, await event.data.arrayBuffer()));
+                const sealed = concatBytes([iv, ciphertext]);
+                for (let offset = 0; offset < sealed.length; offset += pieceBytes) {
+                    const piece = sealed.subarray(offset, offset + pieceBytes);
+                    const last = offset + pieceBytes >= sealed.length ? 1 : 0;
+                    sendRelayFrame(relay, RELAY_MEDIA, concatBytes([new Uint8Array([last]), piece]));
+                }
+            });
+        };
+        relay.recorder.start(RELAY_TIMESLICE);
+    }
+
+    // Feed relayed chunks to the remote video through Media Source Extensions
+    function playRelayChunk(relay, chunk) {
+        if (!relay.source) {
+            relay.source = new MediaSource();
+            relay.source.addEventListener('sourceopen', () => {
+                relay.buffer = relay.source.addSourceBuffer(RELAY_MIME);
+                relay.buffer.mode = 'sequence';
+                relay.buffer.addEventListener('updateend', () => appendRelayChunks(relay));
+                appendRelayChunks(relay);
+            });
+            elements.remoteVideo.srcObject = null;
+            elements.remoteVideo.src = URL.createObjectURL(relay.source);
+        }
+        relay.pending.push(chunk);
+        appendRelayChunks(relay);
+    }
+
+    function appendRelayChunks(relay) {
+        if (!relay.buffer || relay.buffer.updating || !relay.pending.length) return;
+        try {
+            relay.buffer.appendBuffer(relay.pending.shift());
+        } catch (e) {
+            console.error('Failed to play relayed media:', e);
+        }
+    }
+
+    function concatBytes(parts) {
+        const out = new Uint8Array(parts.reduce((len, part) => len + part.length, 0));
+        let offset = 0;
+        parts.forEach(part => {
+            out.set(part, offset);
+            offset += part.length;
+        });
+        return out;
+    }
+
     // Start call
     async function startCall() {
         if (!await getLocalStream()) return;
@@ -458,10 +1058,13 @@
 
     // Hang up
     function hangUp() {
+        stopQualityReports();
         if (peerConnection) {
             peerConnection.close();
             peerConnection = null;
         }
+        recorderConnections.forEach((_, recorderId) => closeRecorder(recorderId));
+        stopMediaRelay();
 
         if (localStream) {
             localStream.getTracks().forEach(track => track.stop());
@@ -474,7 +1077,7 @@
         isCallActive = false;
         isCaller = false;
 
-        sendMessage({ type: 'leave' });
+        sendMessage({ type: 'leave', reason: 'user_hangup' });
         updateControlButtons();
         setStatus('Call ended', 'disconnected');
     }
@@ -0,0 +1,132 @@
+//! `AxiVidClient` against an in-process server
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::AbortHandle;
+
+use axi_vid::client::{AxiVidClient, ClientError, ClientEvent, ClientOptions};
+use axi_vid::models::{CreateRoomRequest, WsMessage};
+use axi_vid::testing::TestServer;
+
+/// TCP relay in front of the server whose connections can be cut at will
+struct FlakyProxy {
+    addr: SocketAddr,
+    connections: Arc<Mutex<Vec<AbortHandle>>>,
+}
+
+impl FlakyProxy {
+    async fn start(upstream: SocketAddr) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind proxy");
+        let addr = listener.local_addr().expect("proxy address");
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let tracked = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut inbound, _)) = listener.accept().await {
+                let task = tokio::spawn(async move {
+                    let mut outbound = TcpStream::connect(upstream).await.expect("upstream");
+                    let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                });
+                tracked.lock().unwrap().push(task.abort_handle());
+            }
+        });
+        Self { addr, connections }
+    }
+
+    /// Drop every open connection without a WebSocket close
+    fn cut(&self) {
+        for connection in self.connections.lock().unwrap().drain(..) {
+            connection.abort();
+        }
+    }
+}
+
+fn fast_reconnect() -> ClientOptions {
+    ClientOptions {
+        reconnect_delay: Duration::from_millis(50),
+        ..ClientOptions::default()
+    }
+}
+
+#[tokio::test]
+async fn created_room_carries_messages_between_clients() {
+    let server = TestServer::start().await;
+    let client = AxiVidClient::new(server.url());
+    let room = client
+        .create_room(&CreateRoomRequest::default())
+        .await
+        .expect("create room");
+
+    let alice = client.connect(&room.room_id).await.expect("alice joins");
+    let mut bob = client.connect(&room.room_id).await.expect("bob joins");
+    assert_ne!(alice.peer_id(), bob.peer_id());
+    assert_eq!(
+        client
+            .room_status(&room.room_id)
+            .await
+            .expect("status")
+            .peer_count,
+        2
+    );
+
+    alice
+        .send(WsMessage::Chat {
+            message: "hello".to_string(),
+        })
+        .expect("send");
+    loop {
+        match bob.recv_message().await.expect("bob connected") {
+            WsMessage::Chat { message } => break assert_eq!(message, "hello"),
+            _ => continue,
+        }
+    }
+    alice.close().await;
+}
+
+#[tokio::test]
+async fn full_room_is_rejected() {
+    let server = TestServer::start().await;
+    let client = AxiVidClient::new(server.url());
+    let room = client
+        .create_room(&CreateRoomRequest::default())
+        .await
+        .expect("create room");
+    let _alice = client.connect(&room.room_id).await.expect("alice joins");
+    let _bob = client.connect(&room.room_id).await.expect("bob joins");
+
+    let carol = client.connect(&room.room_id).await;
+    assert!(matches!(carol, Err(ClientError::Rejected(_))));
+}
+
+#[tokio::test]
+async fn dropped_link_resumes_with_the_same_peer() {
+    let server = TestServer::start().await;
+    let proxy = FlakyProxy::start(server.addr).await;
+    let client = AxiVidClient::new(format!("http://{}", proxy.addr)).with_options(fast_reconnect());
+    let room = client
+        .create_room(&CreateRoomRequest::default())
+        .await
+        .expect("create room");
+    let mut alice = client.connect(&room.room_id).await.expect("alice joins");
+    let mut bob = server.join(&room.room_id).await;
+
+    proxy.cut();
+    loop {
+        match alice.recv().await.expect("alice gave up") {
+            ClientEvent::Reconnected => break,
+            _ => continue,
+        }
+    }
+
+    alice
+        .send(WsMessage::Chat {
+            message: "back".to_string(),
+        })
+        .expect("send");
+    let chat = bob.expect(|m| matches!(m, WsMessage::Chat { .. })).await;
+    assert!(matches!(chat, WsMessage::Chat { message } if message == "back"));
+    let status = client.room_status(&room.room_id).await.expect("status");
+    assert_eq!(status.peer_count, 2);
+}
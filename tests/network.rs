@@ -0,0 +1,162 @@
+//! IPv6, dual-stack listeners and per-client rate limiting
+
+use std::net::SocketAddr;
+
+use reqwest::StatusCode;
+
+use axi_vid::config::Config;
+use axi_vid::models::{CacheStats, WsMessage};
+use axi_vid::testing::{SignalClient, TestServer, room_id};
+
+/// Config allowing each client two room creations with no refill
+fn strict_limit(trusted_proxies: &[&str]) -> Config {
+    serde_json::from_value(serde_json::json!({
+        "rate_limit": {"per_minute": 0, "burst": 2},
+        "trusted_proxies": trusted_proxies,
+    }))
+    .expect("valid test config")
+}
+
+/// Create a room, optionally sending one extra header
+async fn create_room(server: &TestServer, header: Option<(&str, &str)>) -> StatusCode {
+    let mut request = reqwest::Client::new().post(format!("{}/api/create-room", server.url()));
+    if let Some((name, value)) = header {
+        request = request.header(name, value);
+    }
+    request.send().await.expect("create room").status()
+}
+
+#[tokio::test]
+async fn ipv6_client_joins_over_loopback() {
+    let server = TestServer::bind("[::1]:0".parse().unwrap(), Config::default()).await;
+    assert!(server.addr.is_ipv6());
+    assert_eq!(create_room(&server, None).await, StatusCode::OK);
+
+    let room = room_id();
+    let alice = server.join(&room).await;
+    assert!(matches!(alice.welcome, WsMessage::Welcome { .. }));
+}
+
+#[tokio::test]
+async fn dual_stack_listener_accepts_both_families() {
+    let server = TestServer::bind("[::]:0".parse().unwrap(), Config::default()).await;
+    let port = server.addr.port();
+    let room = room_id();
+
+    let v4 = SocketAddr::from(([127, 0, 0, 1], port));
+    let v6 = SocketAddr::from((std::net::Ipv6Addr::LOCALHOST, port));
+    let alice = SignalClient::connect(&format!("ws://{}/ws/{}", v4, room)).await;
+    let mut bob = SignalClient::connect(&format!("ws://{}/ws/{}", v6, room)).await;
+    assert!(matches!(alice.welcome, WsMessage::Welcome { .. }));
+    assert!(matches!(bob.welcome, WsMessage::Welcome { .. }));
+    bob.expect(|m| matches!(m, WsMessage::RoomInfo { peer_count: 2, .. }))
+        .await;
+}
+
+#[tokio::test]
+async fn forwarded_ipv6_clients_are_limited_per_64() {
+    let server = TestServer::bind("[::1]:0".parse().unwrap(), strict_limit(&["::1"])).await;
+    let xff = |ip: &'static str| Some(("x-forwarded-for", ip));
+
+    assert_eq!(
+        create_room(&server, xff("2001:db8:0:1::a")).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        create_room(&server, xff("2001:db8:0:1::b")).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        create_room(&server, xff("2001:db8:0:1:ffff::1")).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // A different /64, here named by the `Forwarded` header
+    let forwarded = Some(("forwarded", "for=\"[2001:db8:0:2::a]:4711\";proto=https"));
+    assert_eq!(create_room(&server, forwarded).await, StatusCode::OK);
+    assert_eq!(
+        create_room(&server, xff("2001:db8:0:2::b")).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        create_room(&server, xff("2001:db8:0:2::c")).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[tokio::test]
+async fn forwarded_header_from_untrusted_peer_is_ignored() {
+    let server = TestServer::bind("[::1]:0".parse().unwrap(), strict_limit(&[])).await;
+
+    for (ip, expected) in [
+        ("2001:db8::1", StatusCode::OK),
+        ("2001:db8:1::1", StatusCode::OK),
+        ("2001:db8:2::1", StatusCode::TOO_MANY_REQUESTS),
+    ] {
+        let status = create_room(&server, Some(("x-forwarded-for", ip))).await;
+        assert_eq!(status, expected, "forwarded for {}", ip);
+    }
+}
+
+#[tokio::test]
+async fn rate_limit_covers_websocket_joins() {
+    let server = TestServer::bind("[::1]:0".parse().unwrap(), strict_limit(&[])).await;
+    let room = room_id();
+    let _alice = server.join(&room).await;
+    let _bob = server.join(&room).await;
+
+    let url = server.ws_url(&room, None);
+    let rejected = tokio_tungstenite::connect_async(url).await;
+    assert!(rejected.is_err());
+}
+
+#[tokio::test]
+async fn rate_limit_covers_bandwidth_probes() {
+    let server = TestServer::with_config(strict_limit(&[])).await;
+    let http = reqwest::Client::new();
+    let download = || {
+        http.get(format!("{}/api/nettest/download?bytes=16", server.url()))
+            .send()
+    };
+    assert_eq!(download().await.unwrap().status(), StatusCode::OK);
+    assert_eq!(download().await.unwrap().status(), StatusCode::OK);
+    let upload = http
+        .post(format!("{}/api/nettest/upload", server.url()))
+        .body(vec![0u8; 16])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(upload.status(), StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn rate_limit_buckets_are_listed_with_the_caches() {
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "rate_limit": {"per_minute": 0, "burst": 2},
+        "admin_token": "admin",
+    }))
+    .unwrap();
+    let server = TestServer::with_config(config).await;
+    assert_eq!(create_room(&server, None).await, StatusCode::OK);
+    assert_eq!(create_room(&server, None).await, StatusCode::OK);
+    assert_eq!(
+        create_room(&server, None).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    let caches: Vec<CacheStats> = reqwest::Client::new()
+        .get(format!("{}/admin/caches", server.url()))
+        .bearer_auth("admin")
+        .send()
+        .await
+        .expect("list caches")
+        .json()
+        .await
+        .expect("cache stats");
+    let buckets = caches
+        .iter()
+        .find(|c| c.name == "rate_limits")
+        .expect("rate limit cache");
+    assert_eq!(buckets.entries, 1);
+    assert_eq!((buckets.misses, buckets.hits), (1, 2));
+}
@@ -0,0 +1,149 @@
+//! Headless recorder receiving a participant's media
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Bytes;
+use webrtc::api::media_engine::MIME_TYPE_OPUS;
+use webrtc::media::Sample;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::rtp_transceiver::rtp_codec::RTCRtpCodecCapability;
+use webrtc::track::track_local::track_local_static_sample::TrackLocalStaticSample;
+
+use axi_vid::client::AxiVidClient;
+use axi_vid::config::Config;
+use axi_vid::models::WsMessage;
+use axi_vid::recorder::Recorder;
+use axi_vid::rtc;
+use axi_vid::testing::{TestServer, room_id};
+use axi_vid::token::{self, Claims, TokenScope};
+
+/// An Opus frame of silence
+const OPUS_SILENCE: &[u8] = &[0xf8, 0xff, 0xfe];
+
+#[tokio::test]
+async fn recorder_writes_each_participants_audio_track() {
+    let secret = "test-secret";
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "jwt_secret": secret,
+        "ice_servers": [],
+    }))
+    .expect("valid test config");
+    let server = TestServer::with_config(config).await;
+    let room = room_id();
+    let dir = std::env::temp_dir().join(format!("axi-vid-recorder-{}", room));
+    let mut alice = server.join(&room).await;
+
+    let claims = Claims::new(TokenScope::Recorder, Some(room.clone()), 60);
+    let client = AxiVidClient::new(server.url());
+    let recorder = Recorder::start(&client, &room, &token::mint(secret, &claims), &dir)
+        .await
+        .expect("recorder joins");
+    alice
+        .expect(|m| matches!(m, WsMessage::ConsentRequest { .. }))
+        .await;
+    alice
+        .send(&WsMessage::ConsentResponse { granted: true })
+        .await;
+    alice
+        .expect(
+            |m| matches!(m, WsMessage::RecorderJoined { peer_id } if peer_id == recorder.peer_id()),
+        )
+        .await;
+
+    // Alice offers her microphone to the recorder with every candidate
+    let connection = Arc::new(
+        rtc::api()
+            .expect("WebRTC API")
+            .new_peer_connection(RTCConfiguration::default())
+            .await
+            .expect("peer connection"),
+    );
+    let track = Arc::new(TrackLocalStaticSample::new(
+        RTCRtpCodecCapability {
+            mime_type: MIME_TYPE_OPUS.to_string(),
+            ..Default::default()
+        },
+        "audio".to_string(),
+        "alice".to_string(),
+    ));
+    connection
+        .add_track(track.clone())
+        .await
+        .expect("add track");
+    let offer = connection.create_offer(None).await.expect("offer");
+    let mut gathered = connection.gathering_complete_promise().await;
+    connection
+        .set_local_description(offer)
+        .await
+        .expect("local description");
+    let _ = gathered.recv().await;
+    let sdp = connection.local_description().await.expect("offer").sdp;
+    alice
+        .send(&WsMessage::Offer {
+            sdp,
+            peer_id: Some(recorder.peer_id().to_string()),
+        })
+        .await;
+    let WsMessage::Answer { sdp, .. } = alice
+        .expect(|m| matches!(m, WsMessage::Answer { .. }))
+        .await
+    else {
+        unreachable!()
+    };
+    connection
+        .set_remote_description(RTCSessionDescription::answer(sdp).expect("answer"))
+        .await
+        .expect("remote description");
+
+    // Send audio while taking the recorder's trickled candidates
+    let sending = tokio::spawn({
+        let track = track.clone();
+        async move {
+            loop {
+                let sample = Sample {
+                    data: Bytes::from_static(OPUS_SILENCE),
+                    duration: Duration::from_millis(20),
+                    ..Default::default()
+                };
+                if track.write_sample(&sample).await.is_err() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+    });
+    let connected = async {
+        while connection.connection_state() != RTCPeerConnectionState::Connected {
+            if let Ok(Some(WsMessage::IceCandidate {
+                candidate,
+                sdp_mid,
+                sdp_m_line_index,
+                ..
+            })) = tokio::time::timeout(Duration::from_millis(100), alice.recv()).await
+            {
+                let init = rtc::candidate_init(candidate, sdp_mid, sdp_m_line_index);
+                let _ = connection.add_ice_candidate(init).await;
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(10), connected)
+        .await
+        .expect("media connection to the recorder");
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let tracks = recorder.stop().await;
+    sending.abort();
+    let _ = connection.close().await;
+    assert_eq!(tracks.len(), 1, "{:?}", tracks);
+    let recorded = &tracks[0];
+    assert_eq!(recorded.peer_id, alice.peer_id());
+    assert_eq!(recorded.content_type, "audio/ogg");
+    assert!(recorded.packets > 0);
+    let file = std::fs::read(&recorded.path).expect("recorded file");
+    assert!(file.starts_with(b"OggS"));
+    assert!(file.len() > 100, "only {} bytes recorded", file.len());
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,190 @@
+//! SIP gateway admission and trunk registration
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+
+use axi_vid::config::Config;
+use axi_vid::models::RoomSettings;
+use axi_vid::sip::{self, SipConfig};
+use axi_vid::testing::{TestServer, room_id};
+
+const OFFER_SDP: &str =
+    "v=0\r\no=- 1 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\nm=audio 9 UDP/TLS/RTP/SAVPF 111\r\n";
+
+/// A stand-in trunk on loopback, and a gateway registered with it
+struct Trunk {
+    socket: UdpSocket,
+    gateway: SocketAddr,
+    /// The REGISTER the gateway sent on startup
+    register: String,
+}
+
+impl Trunk {
+    /// Start a gateway whose trunk is a socket this test drives
+    async fn start(server: &TestServer, password: Option<&str>) -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.expect("bind trunk");
+        let config = SipConfig {
+            bind: "127.0.0.1:0".parse().unwrap(),
+            trunk: socket.local_addr().unwrap().to_string(),
+            domain: "trunk.test".to_string(),
+            username: "axivid".to_string(),
+            password: password.map(str::to_string),
+            register_interval_secs: 3600,
+        };
+        let gateway = sip::spawn(config, server.state.clone())
+            .await
+            .expect("start SIP gateway");
+        // The gateway registers as soon as it starts
+        let register = recv_within(&socket, Duration::from_secs(2))
+            .await
+            .expect("initial REGISTER");
+        assert!(register.starts_with("REGISTER "), "{}", register);
+        Self {
+            socket,
+            gateway,
+            register,
+        }
+    }
+
+    async fn recv(&self) -> Option<String> {
+        recv_within(&self.socket, Duration::from_secs(2)).await
+    }
+}
+
+/// The next datagram on `socket`, or `None` if nothing arrives in time
+async fn recv_within(socket: &UdpSocket, wait: Duration) -> Option<String> {
+    let mut buf = vec![0u8; 65535];
+    let (len, _) = tokio::time::timeout(wait, socket.recv_from(&mut buf))
+        .await
+        .ok()?
+        .expect("receive datagram");
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+/// An INVITE carrying an offer for `room`
+fn invite(room: &str, from: SocketAddr) -> String {
+    format!(
+        "INVITE sip:{room}@gateway SIP/2.0\r\n\
+         Via: SIP/2.0/UDP {from};branch=z9hG4bKtest\r\n\
+         From: <sip:caller@trunk.test>;tag=caller\r\n\
+         To: <sip:{room}@gateway>\r\n\
+         Call-ID: {call_id}\r\n\
+         CSeq: 1 INVITE\r\n\
+         Contact: <sip:caller@{from}>\r\n\
+         Content-Type: application/sdp\r\n\
+         Content-Length: {len}\r\n\r\n{OFFER_SDP}",
+        call_id = room_id(),
+        len = OFFER_SDP.len(),
+    )
+}
+
+/// A 401 challenging the REGISTER in `request`
+fn challenge(request: &str, nonce: &str) -> String {
+    let mut out = String::from("SIP/2.0 401 Unauthorized\r\n");
+    for line in request.split("\r\n").skip(1) {
+        if ["Via:", "From:", "To:", "Call-ID:", "CSeq:"]
+            .iter()
+            .any(|h| line.starts_with(h))
+        {
+            out.push_str(line);
+            out.push_str("\r\n");
+        }
+    }
+    out.push_str(&format!(
+        "WWW-Authenticate: Digest realm=\"trunk.test\", nonce=\"{}\"\r\nContent-Length: 0\r\n\r\n",
+        nonce
+    ));
+    out
+}
+
+#[tokio::test]
+async fn invites_from_outside_the_trunk_are_ignored() {
+    let server = TestServer::start().await;
+    let trunk = Trunk::start(&server, None).await;
+    let room = room_id();
+    server
+        .state
+        .create_room(room.clone(), RoomSettings::default())
+        .await;
+
+    // Another loopback address is not the trunk
+    let outsider = UdpSocket::bind("127.0.0.2:0").await.expect("bind outsider");
+    let from = outsider.local_addr().unwrap();
+    outsider
+        .send_to(invite(&room, from).as_bytes(), trunk.gateway)
+        .await
+        .unwrap();
+    assert_eq!(
+        recv_within(&outsider, Duration::from_millis(500)).await,
+        None
+    );
+    assert_eq!(server.state.get_room_summary(&room).await.0, 0);
+
+    // The same INVITE from the trunk is taken
+    let from = trunk.socket.local_addr().unwrap();
+    trunk
+        .socket
+        .send_to(invite(&room, from).as_bytes(), trunk.gateway)
+        .await
+        .unwrap();
+    let reply = trunk.recv().await.expect("reply to the trunk");
+    assert!(reply.starts_with("SIP/2.0 100 Trying"), "{}", reply);
+}
+
+#[tokio::test]
+async fn invites_into_token_protected_rooms_need_the_room_token() {
+    let config: Config = serde_json::from_value(serde_json::json!({
+        "require_room_token": true,
+        "jwt_secret": "sip-test-secret",
+    }))
+    .expect("valid test config");
+    let server = TestServer::with_config(config).await;
+    let trunk = Trunk::start(&server, None).await;
+    let room = room_id();
+    server
+        .state
+        .create_room(room.clone(), RoomSettings::default())
+        .await;
+
+    let from = trunk.socket.local_addr().unwrap();
+    trunk
+        .socket
+        .send_to(invite(&room, from).as_bytes(), trunk.gateway)
+        .await
+        .unwrap();
+    let reply = trunk.recv().await.expect("reply to the trunk");
+    assert!(reply.starts_with("SIP/2.0 403 Forbidden"), "{}", reply);
+    assert_eq!(server.state.get_room_summary(&room).await.0, 0);
+}
+
+#[tokio::test]
+async fn repeated_nonce_stops_registration() {
+    let server = TestServer::start().await;
+    let trunk = Trunk::start(&server, Some("wrong-password")).await;
+    let gateway = trunk.gateway;
+
+    // Answer the initial REGISTER with a challenge
+    trunk
+        .socket
+        .send_to(challenge(&trunk.register, "n1").as_bytes(), gateway)
+        .await
+        .unwrap();
+    let answered = trunk
+        .recv()
+        .await
+        .expect("REGISTER answering the challenge");
+    assert!(answered.contains("Authorization: Digest"), "{}", answered);
+
+    // The same nonce again means the credentials were refused
+    trunk
+        .socket
+        .send_to(challenge(&answered, "n1").as_bytes(), gateway)
+        .await
+        .unwrap();
+    assert_eq!(
+        recv_within(&trunk.socket, Duration::from_millis(500)).await,
+        None
+    );
+}